@@ -0,0 +1,41 @@
+//! Decrypts a stream written by an `EncryptedWriter`.
+//!
+//! Usage: `cargo run --example decrypt --features encryption -- <hex key> <input> <output>`
+//!
+//! The key can also be passed in the `CAPTURE_KEY` environment variable, use `-` as the key then.
+
+use bevy_capture::encryption::{decrypt, EncryptionKey};
+use std::{
+    env,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    process::ExitCode,
+};
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let [key, input, output] = &args[..] else {
+        eprintln!("Usage: decrypt <hex key | -> <input> <output>");
+        return ExitCode::FAILURE;
+    };
+
+    let key = match key.as_str() {
+        "-" => env::var("CAPTURE_KEY").unwrap_or_default(),
+        key => key.to_owned(),
+    };
+    let result = EncryptionKey::from_hex(&key).and_then(|key| {
+        let reader = BufReader::new(File::open(input)?);
+        let writer = BufWriter::new(File::create(output)?);
+        decrypt(reader, writer, &key)
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            // Don't leave partially decrypted output behind.
+            let _ = fs::remove_file(output);
+            eprintln!("Failed to decrypt {input}: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
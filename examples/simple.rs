@@ -33,7 +33,7 @@ fn main() -> AppExit {
             run_mode: RunMode::Loop { wait: None },
         },
         // Add the CapturePlugin
-        bevy_capture::CapturePlugin,
+        bevy_capture::CapturePlugin::default(),
     ));
 
     // Update the time at a fixed rate of 60 FPS
@@ -0,0 +1,37 @@
+/*
+ * C API of bevy_capture (feature `ffi`), see the `ffi` module.
+ *
+ * All functions return BEVY_CAPTURE_OK on success and a negative error code otherwise. The
+ * `capture` argument is the name of the capture entity, or NULL for all captures.
+ */
+
+#ifndef BEVY_CAPTURE_H
+#define BEVY_CAPTURE_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define BEVY_CAPTURE_OK 0
+#define BEVY_CAPTURE_INVALID_ARGUMENT -1
+#define BEVY_CAPTURE_NOT_INITIALIZED -2
+#define BEVY_CAPTURE_NO_OUTPUT_PATH -3
+
+/* Sets the output path of the captures started with bevy_capture_start. */
+int bevy_capture_set_output_path(const char *path);
+
+/* Starts capturing with the encoder registered under the given name, e.g. "mp4_ffmpeg_cli".
+ * `config` is the config of the encoder in JSON, or NULL for the defaults. */
+int bevy_capture_start(const char *capture, const char *encoder, const char *config);
+
+/* Stops capturing. */
+int bevy_capture_stop(const char *capture);
+
+/* Captures the next frame into a png at the given path. */
+int bevy_capture_screenshot(const char *capture, const char *path);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BEVY_CAPTURE_H */
@@ -0,0 +1,297 @@
+//! Lower the capture quality while the frame time is over budget, e.g. to record on weaker
+//! machines without tanking gameplay.
+//!
+//! Attach an [`AdaptiveQuality`] governor to a capture. While capturing, the governor steps down
+//! through its [quality levels](QualityLevel) whenever the smoothed frame time exceeds the budget,
+//! by rendering the source camera at a lower resolution and capturing only every n-th frame. Once
+//! the frame time has stayed well below the budget for a while, it steps back up. The original
+//! resolution is restored when the capture stops.
+//!
+//! Every captured frame is marked in the [frame metadata](crate::metadata) of the capture with the
+//! [`QUALITY_LEVEL_KEY`], [`QUALITY_SCALE_KEY`] and [`QUALITY_FRAME_INTERVAL_KEY`] keys. Note that
+//! frames passed to the encoders change their size when the resolution changes, so this should
+//! be used with encoders that support varying frame sizes.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::{prelude::*, utils::Duration};
+//! # use bevy_capture::{adaptive_quality::*, CaptureBundle};
+//! #
+//! app.add_plugins(AdaptiveQualityPlugin);
+//!
+//! fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+//!     commands.spawn((
+//!         Camera2dBundle::default().target_headless(1920, 1080, &mut images),
+//!         CaptureBundle::default(),
+//!         AdaptiveQuality::new(Duration::from_secs_f64(1.0 / 60.0)),
+//!     ));
+//! }
+//! ```
+
+use crate::{metadata::FrameMetadata, Capture, CaptureSource};
+use bevy::{
+    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+    render::{camera::RenderTarget, render_resource::Extent3d},
+    utils::Duration,
+};
+
+/// The metadata key of the index of the active quality level, `0` is full quality.
+pub const QUALITY_LEVEL_KEY: &str = "quality_level";
+
+/// The metadata key of the resolution scale of the active quality level.
+pub const QUALITY_SCALE_KEY: &str = "quality_scale";
+
+/// The metadata key of the frame interval of the active quality level.
+pub const QUALITY_FRAME_INTERVAL_KEY: &str = "quality_frame_interval";
+
+/// The default time between two steps down.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_millis(500);
+
+/// The default time the frame time must stay below the budget before stepping up.
+pub const DEFAULT_RECOVERY: Duration = Duration::from_secs(2);
+
+/// A Bevy plugin for adaptive capture quality. This adds the `FrameTimeDiagnosticsPlugin` if
+/// necessary.
+pub struct AdaptiveQualityPlugin;
+
+impl Plugin for AdaptiveQualityPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugins(FrameTimeDiagnosticsPlugin);
+        }
+
+        app.add_systems(PostUpdate, update_quality);
+    }
+}
+
+/// A quality level of the [`AdaptiveQuality`] governor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityLevel {
+    /// The resolution scale of the source camera, in `(0, 1]`.
+    pub scale: f32,
+    /// Only every n-th frame is captured.
+    pub frame_interval: u32,
+}
+
+impl QualityLevel {
+    /// Creates a new quality level.
+    pub fn new(scale: f32, frame_interval: u32) -> Self {
+        Self {
+            scale: scale.clamp(f32::EPSILON, 1.0),
+            frame_interval: frame_interval.max(1),
+        }
+    }
+}
+
+/// A component that governs the quality of the [`Capture`] of the same entity. The source camera
+/// must render to an image.
+#[derive(Debug, Component)]
+pub struct AdaptiveQuality {
+    budget: Duration,
+    levels: Vec<QualityLevel>,
+    cooldown: Duration,
+    recovery: Duration,
+    level: usize,
+    original_size: Option<Extent3d>,
+    last_step: Option<Duration>,
+    below_since: Option<Duration>,
+    frame: u64,
+    paused: bool,
+}
+
+impl AdaptiveQuality {
+    /// Creates a new governor with the given frame time budget and the default levels: full
+    /// quality, 75% and 50% resolution, and 50% resolution at half the frame rate.
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            levels: vec![
+                QualityLevel::new(1.0, 1),
+                QualityLevel::new(0.75, 1),
+                QualityLevel::new(0.5, 1),
+                QualityLevel::new(0.5, 2),
+            ],
+            cooldown: DEFAULT_COOLDOWN,
+            recovery: DEFAULT_RECOVERY,
+            level: 0,
+            original_size: None,
+            last_step: None,
+            below_since: None,
+            frame: 0,
+            paused: false,
+        }
+    }
+
+    /// Sets the quality levels the governor steps through, from best to worst. Full quality is
+    /// always the first level.
+    pub fn with_levels(mut self, levels: impl IntoIterator<Item = QualityLevel>) -> Self {
+        self.levels = std::iter::once(QualityLevel::new(1.0, 1))
+            .chain(levels)
+            .collect();
+        self.levels.dedup();
+        self
+    }
+
+    /// Sets the minimum time between two steps down, so the frame time can settle. Defaults to
+    /// [`DEFAULT_COOLDOWN`].
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Sets how long the frame time must stay below 80% of the budget before stepping up.
+    /// Defaults to [`DEFAULT_RECOVERY`].
+    pub fn with_recovery(mut self, recovery: Duration) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Returns the index of the active quality level, `0` is full quality.
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    /// Returns the active quality level.
+    pub fn current(&self) -> QualityLevel {
+        self.levels[self.level]
+    }
+
+    /// Returns `true` if the quality is currently reduced.
+    pub fn is_degraded(&self) -> bool {
+        self.level > 0
+    }
+
+    fn step(&mut self, frame_time: Duration, now: Duration) -> bool {
+        let cooled_down = match self.last_step {
+            Some(last_step) => now - last_step >= self.cooldown,
+            None => true,
+        };
+
+        if frame_time > self.budget {
+            self.below_since = None;
+            if cooled_down && self.level + 1 < self.levels.len() {
+                self.level += 1;
+                self.last_step = Some(now);
+                return true;
+            }
+        } else if frame_time.as_secs_f64() < self.budget.as_secs_f64() * 0.8 && self.level > 0 {
+            let below_since = *self.below_since.get_or_insert(now);
+            if now - below_since >= self.recovery {
+                self.level -= 1;
+                self.last_step = Some(now);
+                self.below_since = None;
+                return true;
+            }
+        } else {
+            self.below_since = None;
+        }
+
+        false
+    }
+
+    fn reset(&mut self) {
+        self.level = 0;
+        self.original_size = None;
+        self.last_step = None;
+        self.below_since = None;
+        self.frame = 0;
+        self.paused = false;
+    }
+}
+
+fn update_quality(
+    mut commands: Commands,
+    mut governors: Query<(
+        Entity,
+        &mut AdaptiveQuality,
+        &mut Capture,
+        &CaptureSource,
+        Option<&mut FrameMetadata>,
+    )>,
+    cameras: Query<&Camera>,
+    mut images: ResMut<Assets<Image>>,
+    diagnostics: Res<DiagnosticsStore>,
+    time: Res<Time<Real>>,
+) {
+    let frame_time = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FRAME_TIME)
+        .and_then(|diagnostic| diagnostic.smoothed())
+        .map(|ms| Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+
+    for (entity, mut governor, mut capture, source, metadata) in &mut governors {
+        let source = match source {
+            CaptureSource::ThisCamera => entity,
+            CaptureSource::Camera(camera) => *camera,
+        };
+        let target = match cameras.get(source).map(|camera| &camera.target) {
+            Ok(RenderTarget::Image(target)) => target,
+            _ => {
+                warn_once!("The source of an adaptive quality capture must render to an image");
+                continue;
+            }
+        };
+        let Some(current_size) = images
+            .get(target)
+            .map(|image| image.texture_descriptor.size)
+        else {
+            continue;
+        };
+
+        if !capture.is_capturing() {
+            if let Some(original_size) = governor.original_size {
+                if current_size != original_size {
+                    images.get_mut(target).unwrap().resize(original_size);
+                }
+                governor.reset();
+            }
+            continue;
+        }
+
+        let original_size = *governor.original_size.get_or_insert(current_size);
+
+        if let Some(frame_time) = frame_time {
+            if governor.step(frame_time, time.elapsed()) {
+                let level = governor.current();
+                info!(
+                    "Capture quality of {:?} changed to level {} ({:?})",
+                    entity, governor.level, level
+                );
+
+                let size = Extent3d {
+                    width: ((original_size.width as f32 * level.scale).round() as u32).max(1),
+                    height: ((original_size.height as f32 * level.scale).round() as u32).max(1),
+                    depth_or_array_layers: 1,
+                };
+                if current_size != size {
+                    images.get_mut(target).unwrap().resize(size);
+                }
+            }
+        }
+
+        // Skip frames by pausing the capture, unless it was paused by someone else.
+        let level = governor.current();
+        let skip = governor.frame % level.frame_interval as u64 != 0;
+        governor.frame += 1;
+        if skip && !capture.is_paused() {
+            capture.pause();
+            governor.paused = true;
+        } else if !skip && governor.paused {
+            capture.resume();
+            governor.paused = false;
+        }
+
+        if !skip && !capture.is_paused() {
+            let mut quality = FrameMetadata::default();
+            quality.insert(QUALITY_LEVEL_KEY, governor.level);
+            quality.insert(QUALITY_SCALE_KEY, level.scale);
+            quality.insert(QUALITY_FRAME_INTERVAL_KEY, level.frame_interval);
+            match metadata {
+                Some(mut metadata) => metadata.merge(&quality),
+                None => {
+                    commands.entity(entity).insert(quality);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Pause captures automatically while the window is minimized or unfocused.
+//!
+//! Swapchain-sourced captures record garbage or stall while the window is minimized, since the
+//! OS stops presenting it. Attach an [`AutoPause`] next to the [`Capture`] to pause it in that
+//! case, and optionally while the window is not focused. The capture is resumed once the window
+//! is restored.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{auto_pause::*, CaptureBundle};
+//! #
+//! app.add_plugins(AutoPausePlugin);
+//!
+//! fn setup(mut commands: Commands) {
+//!     commands.spawn((
+//!         Camera2dBundle::default(),
+//!         CaptureBundle::default(),
+//!         AutoPause::default().with_pause_on_focus_lost(true),
+//!     ));
+//! }
+//! ```
+
+use crate::Capture;
+use bevy::{
+    prelude::*,
+    window::{PrimaryWindow, WindowOccluded},
+};
+
+/// A Bevy plugin that pauses captures with an [`AutoPause`] component.
+pub struct AutoPausePlugin;
+
+impl Plugin for AutoPausePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<WindowOccluded>()
+            .add_systems(PostUpdate, auto_pause);
+    }
+}
+
+/// Pauses the capture while its window is minimized or unfocused. This is optional and can be
+/// attached next to the [`Capture`], see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct AutoPause {
+    window: Option<Entity>,
+    on_minimize: bool,
+    on_focus_lost: bool,
+    occluded: bool,
+    paused: bool,
+}
+
+impl Default for AutoPause {
+    fn default() -> Self {
+        Self {
+            window: None,
+            on_minimize: true,
+            on_focus_lost: false,
+            occluded: false,
+            paused: false,
+        }
+    }
+}
+
+impl AutoPause {
+    /// Watches the given window instead of the primary window.
+    pub fn with_window(mut self, window: Entity) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Sets whether the capture is paused while the window is minimized or fully occluded.
+    /// Defaults to `true`.
+    pub fn with_pause_on_minimize(mut self, on_minimize: bool) -> Self {
+        self.on_minimize = on_minimize;
+        self
+    }
+
+    /// Sets whether the capture is paused while the window is not focused. Defaults to `false`.
+    pub fn with_pause_on_focus_lost(mut self, on_focus_lost: bool) -> Self {
+        self.on_focus_lost = on_focus_lost;
+        self
+    }
+
+    /// Returns the watched window, or `None` for the primary window.
+    pub fn window(&self) -> Option<Entity> {
+        self.window
+    }
+
+    /// Returns `true` if the capture is currently paused by this component.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+fn auto_pause(
+    mut captures: Query<(&mut Capture, &mut AutoPause)>,
+    windows: Query<&Window>,
+    primary_window: Query<Entity, With<PrimaryWindow>>,
+    mut occluded_events: EventReader<WindowOccluded>,
+) {
+    let occluded_events = occluded_events.read().collect::<Vec<_>>();
+    for (mut capture, mut auto_pause) in &mut captures {
+        let Some(entity) = auto_pause
+            .window
+            .or_else(|| primary_window.get_single().ok())
+        else {
+            continue;
+        };
+        for event in occluded_events
+            .iter()
+            .filter(|event| event.window == entity)
+        {
+            auto_pause.occluded = event.occluded;
+        }
+        let Ok(window) = windows.get(entity) else {
+            continue;
+        };
+
+        // Minimized windows have a size of zero on most platforms, others only report occlusion.
+        let minimized =
+            auto_pause.occluded || window.physical_width() == 0 || window.physical_height() == 0;
+        let inactive =
+            (auto_pause.on_minimize && minimized) || (auto_pause.on_focus_lost && !window.focused);
+
+        if !capture.is_capturing() {
+            // The capture was stopped, a new one starts unpaused.
+            if auto_pause.paused {
+                auto_pause.paused = false;
+            }
+        } else if inactive && !auto_pause.paused {
+            // Captures paused by other means are left alone.
+            if !capture.is_paused() {
+                debug!("Pausing the capture while the window is inactive");
+                capture.pause();
+                auto_pause.paused = true;
+            }
+        } else if !inactive && auto_pause.paused {
+            debug!("Resuming the capture");
+            capture.resume();
+            auto_pause.paused = false;
+        }
+    }
+}
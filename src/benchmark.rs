@@ -0,0 +1,225 @@
+//! Record benchmark runs with a burned-in frame time graph and a JSON summary.
+//!
+//! The [`BenchmarkPlugin`] attaches the frame time of every frame (from Bevy's
+//! `FrameTimeDiagnosticsPlugin`) to the [frame metadata](crate::metadata). The [`BenchmarkEncoder`]
+//! wraps another encoder, draws a frame time graph into the bottom left corner of every frame and
+//! writes the results JSON when the capture finishes:
+//!
+//! ```json
+//! { "frames": 600, "mean_ms": 8.31, "min_ms": 7.9, "max_ms": 21.4, "p50_ms": 8.2, "p90_ms": 8.9, "p95_ms": 9.3, "p99_ms": 14.1, "fps": 120.3 }
+//! ```
+//!
+//! In the graph, every column is one frame. Bars are green at 60 fps or more, yellow at 30 fps or
+//! more and red otherwise. The white and magenta lines mark the median and the 99th percentile of
+//! the frames in the graph. The summary is computed from all frames once the capture finishes.
+
+use crate::{
+    encoder::{self, capabilities::EncoderCapabilities, Encoder},
+    metadata::{FrameMetadata, MetadataValue},
+};
+use bevy::{
+    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+    render::render_asset::RenderAssetUsages,
+};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::{fs, path::PathBuf};
+
+/// The metadata key of the frame time in milliseconds.
+pub const FRAME_TIME_KEY: &str = "frame_time_ms";
+
+/// A Bevy plugin that attaches frame times to the frame metadata. This adds the
+/// `FrameTimeDiagnosticsPlugin` if necessary.
+pub struct BenchmarkPlugin;
+
+impl Plugin for BenchmarkPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugins(FrameTimeDiagnosticsPlugin);
+        }
+
+        app.add_systems(PostUpdate, insert_frame_time);
+    }
+}
+
+fn insert_frame_time(diagnostics: Res<DiagnosticsStore>, mut metadata: ResMut<FrameMetadata>) {
+    let frame_time = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FRAME_TIME)
+        .and_then(|diagnostic| diagnostic.measurement());
+    if let Some(frame_time) = frame_time {
+        metadata.insert(FRAME_TIME_KEY, frame_time.value);
+    }
+}
+
+/// An encoder that wraps another encoder, burns a frame time graph into every frame and writes a
+/// JSON summary of the frame times when it finishes. Requires the [`BenchmarkPlugin`].
+pub struct BenchmarkEncoder<E> {
+    encoder: E,
+    results: PathBuf,
+    graph_size: UVec2,
+    frame_times: Vec<f64>,
+}
+
+impl<E: Encoder> BenchmarkEncoder<E> {
+    /// Creates a new benchmark encoder that writes the results to the given path.
+    pub fn new(encoder: E, results: impl Into<PathBuf>) -> Self {
+        Self {
+            encoder,
+            results: results.into(),
+            graph_size: UVec2::new(240, 80),
+            frame_times: Vec::new(),
+        }
+    }
+
+    /// Sets the size of the graph in pixels. Every column is one frame. Defaults to 240x80.
+    pub fn with_graph_size(mut self, width: u32, height: u32) -> Self {
+        self.graph_size = UVec2::new(width.max(1), height.max(1));
+        self
+    }
+
+    fn draw_graph(&self, image: &mut RgbaImage) {
+        // The graph shows up to 50 ms.
+        const MAX_MS: f64 = 50.0;
+
+        let width = self.graph_size.x.min(image.width());
+        let height = self.graph_size.y.min(image.height());
+        let top = image.height() - height;
+        let y = |ms: f64| top + height - 1 - ((ms / MAX_MS).min(1.0) * (height - 1) as f64) as u32;
+
+        for py in top..top + height {
+            for px in 0..width {
+                let pixel = image.get_pixel_mut(px, py);
+                pixel.0 = [pixel[0] / 4, pixel[1] / 4, pixel[2] / 4, 255];
+            }
+        }
+
+        let recent = &self.frame_times[self.frame_times.len().saturating_sub(width as usize)..];
+        for (px, &ms) in recent.iter().enumerate() {
+            let color = match ms {
+                ms if ms <= 1000.0 / 60.0 => Rgba([64, 200, 64, 255]),
+                ms if ms <= 1000.0 / 30.0 => Rgba([230, 200, 40, 255]),
+                _ => Rgba([220, 50, 50, 255]),
+            };
+            for py in y(ms)..top + height {
+                image.put_pixel(px as u32, py, color);
+            }
+        }
+
+        // Only the visible frames are sorted, so drawing doesn't slow down over long runs.
+        let stats = Stats::new(recent);
+        for (ms, color) in [
+            (stats.p50, Rgba([255, 255, 255, 255])),
+            (stats.p99, Rgba([255, 0, 255, 255])),
+        ] {
+            let py = y(ms);
+            for px in 0..width {
+                image.put_pixel(px, py, color);
+            }
+        }
+    }
+}
+
+impl<E: Encoder> Encoder for BenchmarkEncoder<E> {
+    fn encode(&mut self, image: &Image) -> encoder::Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(
+        &mut self,
+        image: &Image,
+        metadata: &FrameMetadata,
+    ) -> encoder::Result<()> {
+        match metadata.get(FRAME_TIME_KEY) {
+            Some(MetadataValue::Float(ms)) => self.frame_times.push(*ms),
+            _ => warn_once!("No frame time in the frame metadata, is the BenchmarkPlugin added?"),
+        }
+
+        let mut overlay = encoder::to_dynamic_image(image)?.to_rgba8();
+        if !self.frame_times.is_empty() {
+            self.draw_graph(&mut overlay);
+        }
+        let overlay = Image::from_dynamic(
+            DynamicImage::ImageRgba8(overlay),
+            true,
+            RenderAssetUsages::default(),
+        );
+        let overlay = self.encoder.capabilities().convert(&overlay)?;
+
+        self.encoder.encode_with_metadata(&overlay, metadata)
+    }
+
+    fn flush(&mut self) -> encoder::Result<()> {
+        self.encoder.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.encoder.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.encoder.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.encoder.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        let Self {
+            encoder,
+            results,
+            frame_times,
+            ..
+        } = *self;
+        Box::new(encoder).finish();
+
+        if let Err(err) = fs::write(&results, Stats::new(&frame_times).to_json()) {
+            bevy::log::error!("Failed to write benchmark results {:?}: {}", results, err);
+        }
+    }
+}
+
+struct Stats {
+    frames: usize,
+    mean: f64,
+    min: f64,
+    max: f64,
+    p50: f64,
+    p90: f64,
+    p95: f64,
+    p99: f64,
+}
+
+impl Stats {
+    fn new(frame_times: &[f64]) -> Self {
+        let mut sorted = frame_times.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let percentile = |p: f64| match sorted.len() {
+            0 => 0.0,
+            len => sorted[((len - 1) as f64 * p).round() as usize],
+        };
+
+        Self {
+            frames: sorted.len(),
+            mean: sorted.iter().sum::<f64>() / sorted.len().max(1) as f64,
+            min: sorted.first().copied().unwrap_or_default(),
+            max: sorted.last().copied().unwrap_or_default(),
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let fps = if self.mean > 0.0 {
+            1000.0 / self.mean
+        } else {
+            0.0
+        };
+        format!(
+            "{{ \"frames\": {}, \"mean_ms\": {:.3}, \"min_ms\": {:.3}, \"max_ms\": {:.3}, \"p50_ms\": {:.3}, \"p90_ms\": {:.3}, \"p95_ms\": {:.3}, \"p99_ms\": {:.3}, \"fps\": {:.3} }}\n",
+            self.frames, self.mean, self.min, self.max, self.p50, self.p90, self.p95, self.p99, fps,
+        )
+    }
+}
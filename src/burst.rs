@@ -0,0 +1,407 @@
+//! Burst and bracketed captures.
+//!
+//! A burst captures a group of frames into a directory, either consecutive frames or the same scene
+//! state at several exposure or tonemapping settings (a bracket). Virtual time is paused during a
+//! bracket, so all frames show the same moment. Use the [`BurstFinished`] event to pick up the saved
+//! frames, e.g. to let the user choose the best one or to merge exposures externally.
+//!
+//! Every frame of a burst is rendered in its own app update. Enable
+//! `synchronous_pipeline_compilation` on the `RenderPlugin`, otherwise a tonemapping bracket can
+//! contain frames rendered before the new pipeline was ready.
+//!
+//! With the `hdr` feature, an exposure bracket can additionally be merged into a single Radiance
+//! `.hdr` or OpenEXR image, see [`TakeBurst::with_hdr_merge`].
+
+use crate::{encoder, Capture, CaptureHandle, Encoder};
+use bevy::{core_pipeline::tonemapping::Tonemapping, prelude::*, render::camera::Exposure};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// A Bevy plugin for burst captures.
+pub struct BurstPlugin;
+
+impl Plugin for BurstPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActiveBursts>()
+            .add_event::<TakeBurst>()
+            .add_event::<BurstFinished>()
+            .add_systems(Update, update_bursts);
+    }
+}
+
+/// An event to start a burst capture with the given camera.
+///
+/// The camera must have a [`Capture`] component which is not capturing already.
+#[derive(Debug, Clone, Event)]
+pub struct TakeBurst {
+    camera: Entity,
+    directory: PathBuf,
+    kind: BurstKind,
+    #[cfg(feature = "hdr")]
+    hdr_merge: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+enum BurstKind {
+    Consecutive(usize),
+    Exposure(Vec<f32>),
+    Tonemapping(Vec<Tonemapping>),
+}
+
+impl BurstKind {
+    fn len(&self) -> usize {
+        match self {
+            Self::Consecutive(count) => *count,
+            Self::Exposure(stops) => stops.len(),
+            Self::Tonemapping(methods) => methods.len(),
+        }
+    }
+
+    fn is_bracket(&self) -> bool {
+        !matches!(self, Self::Consecutive(_))
+    }
+}
+
+impl TakeBurst {
+    /// Captures the given number of consecutive frames.
+    pub fn consecutive(camera: Entity, directory: impl Into<PathBuf>, count: usize) -> Self {
+        Self {
+            camera,
+            directory: directory.into(),
+            kind: BurstKind::Consecutive(count),
+            #[cfg(feature = "hdr")]
+            hdr_merge: None,
+        }
+    }
+
+    /// Captures the same frame once per exposure compensation, given in stops relative to the
+    /// current [`Exposure`] of the camera. Positive values are brighter, e.g. `[-2.0, 0.0, 2.0]`.
+    pub fn exposure_bracket(
+        camera: Entity,
+        directory: impl Into<PathBuf>,
+        stops: impl IntoIterator<Item = f32>,
+    ) -> Self {
+        Self {
+            camera,
+            directory: directory.into(),
+            kind: BurstKind::Exposure(stops.into_iter().collect()),
+            #[cfg(feature = "hdr")]
+            hdr_merge: None,
+        }
+    }
+
+    /// Captures the same frame once per tonemapping method.
+    pub fn tonemapping_bracket(
+        camera: Entity,
+        directory: impl Into<PathBuf>,
+        methods: impl IntoIterator<Item = Tonemapping>,
+    ) -> Self {
+        Self {
+            camera,
+            directory: directory.into(),
+            kind: BurstKind::Tonemapping(methods.into_iter().collect()),
+            #[cfg(feature = "hdr")]
+            hdr_merge: None,
+        }
+    }
+
+    /// Merges the frames of an exposure bracket into a single high dynamic range image, e.g. to
+    /// capture an environment map. The format is derived from the extension (`.hdr` or `.exr`).
+    ///
+    /// The frames are assumed to be sRGB encoded without a tone curve, so the camera should use
+    /// [`Tonemapping::None`]. This is ignored for other kinds of bursts.
+    #[cfg(feature = "hdr")]
+    pub fn with_hdr_merge(mut self, path: impl Into<PathBuf>) -> Self {
+        self.hdr_merge = Some(path.into());
+        self
+    }
+}
+
+/// An event sent when all frames of a burst were saved.
+#[derive(Debug, Clone, Event)]
+pub struct BurstFinished {
+    /// The camera the burst was taken with.
+    pub camera: Entity,
+    /// The paths of the saved frames, in capture order.
+    pub paths: Vec<PathBuf>,
+    /// The path of the merged high dynamic range image, if it was requested and the merge succeeded.
+    pub merged: Option<PathBuf>,
+}
+
+#[derive(Default, Resource)]
+struct ActiveBursts(Vec<ActiveBurst>);
+
+struct ActiveBurst {
+    camera: Entity,
+    kind: BurstKind,
+    frame: usize,
+    stopped: bool,
+    original_exposure: Option<Exposure>,
+    original_tonemapping: Option<Tonemapping>,
+    time_was_paused: bool,
+    handle: CaptureHandle,
+    output: Arc<Mutex<BurstOutput>>,
+}
+
+#[derive(Default)]
+struct BurstOutput {
+    paths: Vec<PathBuf>,
+    merged: Option<PathBuf>,
+}
+
+type BurstCamera<'a> = (
+    &'a mut Capture,
+    Option<&'a mut Exposure>,
+    Option<&'a mut Tonemapping>,
+);
+
+fn update_bursts(
+    mut bursts: ResMut<ActiveBursts>,
+    mut events: EventReader<TakeBurst>,
+    mut finished: EventWriter<BurstFinished>,
+    mut time: ResMut<Time<Virtual>>,
+    mut cameras: Query<BurstCamera<'_>>,
+) {
+    // Advance the active bursts by one frame.
+    bursts.0.retain_mut(|burst| {
+        if burst.stopped {
+            if !burst.handle.is_finished() {
+                return true;
+            }
+            let output = std::mem::take(&mut *burst.output.lock().unwrap());
+            finished.send(BurstFinished {
+                camera: burst.camera,
+                paths: output.paths,
+                merged: output.merged,
+            });
+            return false;
+        }
+
+        burst.frame += 1;
+        let Ok((mut capture, exposure, tonemapping)) = cameras.get_mut(burst.camera) else {
+            // The camera was despawned, which also dropped the encoder.
+            return false;
+        };
+
+        if burst.frame < burst.kind.len() {
+            burst.apply(exposure, tonemapping);
+        } else {
+            capture.stop();
+            burst.restore(exposure, tonemapping, &mut time);
+            burst.stopped = true;
+        }
+        true
+    });
+
+    // Start new bursts.
+    for event in events.read() {
+        if event.kind.len() == 0 {
+            continue;
+        }
+        if bursts.0.iter().any(|burst| burst.camera == event.camera) {
+            warn!("A burst is already active for {:?}", event.camera);
+            continue;
+        }
+        let Ok((mut capture, exposure, tonemapping)) = cameras.get_mut(event.camera) else {
+            warn!("The burst camera {:?} has no capture", event.camera);
+            continue;
+        };
+        if capture.is_capturing() {
+            warn!("The burst camera {:?} is already capturing", event.camera);
+            continue;
+        }
+
+        let output = Arc::<Mutex<BurstOutput>>::default();
+        let handle = capture.start(BurstEncoder {
+            directory: event.directory.clone(),
+            remaining: event.kind.len(),
+            output: Arc::clone(&output),
+            #[cfg(feature = "hdr")]
+            hdr_merge: match (&event.kind, &event.hdr_merge) {
+                (BurstKind::Exposure(stops), Some(path)) => Some(HdrMerge {
+                    path: path.clone(),
+                    stops: stops.clone(),
+                    frames: Vec::new(),
+                }),
+                _ => None,
+            },
+        });
+
+        let burst = ActiveBurst {
+            camera: event.camera,
+            kind: event.kind.clone(),
+            frame: 0,
+            stopped: false,
+            original_exposure: exposure.as_deref().copied(),
+            original_tonemapping: tonemapping.as_deref().copied(),
+            time_was_paused: time.is_paused(),
+            handle,
+            output,
+        };
+        if burst.kind.is_bracket() {
+            time.pause();
+        }
+        burst.apply(exposure, tonemapping);
+
+        bursts.0.push(burst);
+    }
+}
+
+impl ActiveBurst {
+    fn apply(
+        &self,
+        exposure: Option<Mut<'_, Exposure>>,
+        tonemapping: Option<Mut<'_, Tonemapping>>,
+    ) {
+        match &self.kind {
+            BurstKind::Consecutive(_) => {}
+            BurstKind::Exposure(stops) => match (exposure, self.original_exposure) {
+                (Some(mut exposure), Some(original)) => {
+                    // A higher EV100 means less light, so brighter stops lower it.
+                    exposure.ev100 = original.ev100 - stops[self.frame];
+                }
+                _ => warn_once!("Exposure brackets require an `Exposure` component on the camera"),
+            },
+            BurstKind::Tonemapping(methods) => match tonemapping {
+                Some(mut tonemapping) => *tonemapping = methods[self.frame],
+                None => {
+                    warn_once!(
+                        "Tonemapping brackets require a `Tonemapping` component on the camera"
+                    )
+                }
+            },
+        }
+    }
+
+    fn restore(
+        &self,
+        exposure: Option<Mut<'_, Exposure>>,
+        tonemapping: Option<Mut<'_, Tonemapping>>,
+        time: &mut Time<Virtual>,
+    ) {
+        if let (Some(mut exposure), Some(original)) = (exposure, self.original_exposure) {
+            exposure.ev100 = original.ev100;
+        }
+        if let (Some(mut tonemapping), Some(original)) = (tonemapping, self.original_tonemapping) {
+            *tonemapping = original;
+        }
+        if self.kind.is_bracket() && !self.time_was_paused {
+            time.unpause();
+        }
+    }
+}
+
+/// Saves the frames of a burst as individual pngs.
+struct BurstEncoder {
+    directory: PathBuf,
+    remaining: usize,
+    output: Arc<Mutex<BurstOutput>>,
+    #[cfg(feature = "hdr")]
+    hdr_merge: Option<HdrMerge>,
+}
+
+impl Encoder for BurstEncoder {
+    fn encode(&mut self, image: &Image) -> encoder::Result<()> {
+        if self.remaining == 0 {
+            return Ok(());
+        }
+        self.remaining -= 1;
+
+        let mut output = self.output.lock().unwrap();
+        let path = self
+            .directory
+            .join(format!("burst_{:03}.png", output.paths.len()));
+
+        let image = encoder::to_dynamic_image(image)?;
+        fs::create_dir_all(&self.directory)?;
+        image.save(&path)?;
+        output.paths.push(path);
+
+        #[cfg(feature = "hdr")]
+        if let Some(hdr_merge) = &mut self.hdr_merge {
+            hdr_merge.frames.push(image.into_rgba8());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "hdr")]
+    fn finish(self: Box<Self>) {
+        if let Some(hdr_merge) = self.hdr_merge {
+            let path = hdr_merge.path.clone();
+            match hdr_merge.merge() {
+                Ok(()) => self.output.lock().unwrap().merged = Some(path),
+                Err(err) => bevy::log::error!("Failed to merge exposures: {:?}", err),
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "burst"
+    }
+}
+
+/// Merges an exposure bracket into a single linear image.
+#[cfg(feature = "hdr")]
+struct HdrMerge {
+    path: PathBuf,
+    stops: Vec<f32>,
+    frames: Vec<image::RgbaImage>,
+}
+
+#[cfg(feature = "hdr")]
+impl HdrMerge {
+    fn merge(self) -> encoder::Result<()> {
+        if self.frames.len() != self.stops.len() {
+            return Err(format!(
+                "expected {} frames, got {}",
+                self.stops.len(),
+                self.frames.len()
+            )
+            .into());
+        }
+        let (width, height) = self.frames[0].dimensions();
+        if self
+            .frames
+            .iter()
+            .any(|frame| frame.dimensions() != (width, height))
+        {
+            return Err("the frames have different dimensions".into());
+        }
+
+        let mut merged = image::Rgb32FImage::new(width, height);
+        for (x, y, pixel) in merged.enumerate_pixels_mut() {
+            let mut radiance = [0.0; 3];
+            let mut weights = [0.0; 3];
+            for (frame, stops) in self.frames.iter().zip(&self.stops) {
+                // Undo the exposure compensation of this frame.
+                let scale = (-stops).exp2();
+                for (c, value) in frame.get_pixel(x, y).0[..3].iter().enumerate() {
+                    let value = *value as f32 / 255.0;
+
+                    // Mid-tones are the most reliable, clipped values barely contribute.
+                    let weight = (1.0 - (2.0 * value - 1.0).abs()).max(1e-4);
+                    radiance[c] += weight * srgb_to_linear(value) * scale;
+                    weights[c] += weight;
+                }
+            }
+            *pixel = image::Rgb(std::array::from_fn(|c| radiance[c] / weights[c]));
+        }
+
+        image::DynamicImage::ImageRgb32F(merged).save(&self.path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "hdr")]
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
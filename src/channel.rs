@@ -0,0 +1,330 @@
+//! Capture only the luminance or a single channel of frames, e.g. for scientific visualizations
+//! or depth-style outputs where color is irrelevant.
+//!
+//! With a [`CaptureChannel`] next to the [`Capture`](crate::Capture), a small render pass extracts
+//! the channel on the GPU before the frame is read back, so only a quarter (8 bit) or half (16 bit)
+//! of the bytes of an RGBA8 frame are copied from the GPU and passed to the encoders. The encoders
+//! get [`R8Unorm`](TextureFormat::R8Unorm) or [`R16Uint`](TextureFormat::R16Uint) frames, which
+//! encoders accepting RGBA8 only get converted to gray RGBA8, see
+//! [`capabilities`](crate::encoder::capabilities).
+//!
+//! The luminance is computed from the linear colors. Like the color channels, it is encoded like
+//! the source, i.e. with the sRGB transfer function for sRGB textures, so a channel of a frame has
+//! the same values as in the full frame. The alpha channel is always linear. Textures that can't be
+//! read as floats are captured in full color.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{
+//! #     channel::{CaptureChannel, Channel, ChannelFormat},
+//! #     CaptureBundle,
+//! # };
+//! #
+//! commands.spawn((
+//!     Camera2dBundle::default(),
+//!     CaptureBundle::default(),
+//!     CaptureChannel::new(Channel::Red).with_format(ChannelFormat::R16),
+//! ));
+//! ```
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{Texture, TextureFormat},
+        renderer::RenderDevice,
+    },
+    utils::HashMap,
+};
+
+/// The channel of the frames that is captured.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    /// The relative luminance of the colors, i.e. a grayscale frame.
+    #[default]
+    Luminance,
+    /// The red channel.
+    Red,
+    /// The green channel.
+    Green,
+    /// The blue channel.
+    Blue,
+    /// The alpha channel.
+    Alpha,
+}
+
+/// The format of the captured channel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelFormat {
+    /// 8 bits per pixel, [`TextureFormat::R8Unorm`], a quarter of the bytes of an RGBA8 frame.
+    #[default]
+    R8,
+    /// 16 bits per pixel, [`TextureFormat::R16Uint`] with the range `0.0..=1.0` mapped to
+    /// `0..=65535`, for more precision than 8 bits, e.g. of depth-style outputs rendered into float
+    /// textures.
+    R16,
+}
+
+impl ChannelFormat {
+    /// Returns the texture format of the frames passed to the encoders.
+    pub fn texture_format(&self) -> TextureFormat {
+        match self {
+            Self::R8 => TextureFormat::R8Unorm,
+            Self::R16 => TextureFormat::R16Uint,
+        }
+    }
+}
+
+/// Captures only a single channel of the frames. This is optional and can be attached next to the
+/// [`Capture`](crate::Capture), see the [module docs](self).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub struct CaptureChannel {
+    channel: Channel,
+    format: ChannelFormat,
+}
+
+impl CaptureChannel {
+    /// Captures the given channel with 8 bits per pixel.
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            channel,
+            format: ChannelFormat::default(),
+        }
+    }
+
+    /// Captures grayscale frames, i.e. the luminance, with 8 bits per pixel.
+    pub fn luminance() -> Self {
+        Self::new(Channel::Luminance)
+    }
+
+    /// Sets the format of the captured channel. Defaults to [`ChannelFormat::R8`].
+    pub fn with_format(mut self, format: ChannelFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Returns the captured channel.
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// Returns the format of the captured channel.
+    pub fn format(&self) -> ChannelFormat {
+        self.format
+    }
+}
+
+/// Identifies a pipeline, which is specialized for the channel, the format and the transfer
+/// function.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    config: CaptureChannel,
+    srgb: bool,
+}
+
+impl PipelineKey {
+    fn shader(&self) -> String {
+        let value = match self.config.channel {
+            Channel::Luminance => "dot(color.rgb, vec3(0.2126, 0.7152, 0.0722))",
+            Channel::Red => "color.r",
+            Channel::Green => "color.g",
+            Channel::Blue => "color.b",
+            Channel::Alpha => "color.a",
+        };
+        let (output, result) = match self.config.format {
+            ChannelFormat::R8 => ("vec4<f32>", "vec4(value, 0.0, 0.0, 1.0)"),
+            ChannelFormat::R16 => ("vec4<u32>", "vec4(u32(round(value * 65535.0)), 0u, 0u, 1u)"),
+        };
+        format!(
+            r#"
+@group(0) @binding(0) var source: texture_2d<f32>;
+
+const SRGB: bool = {srgb};
+
+@vertex
+fn vertex(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {{
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+}}
+
+@fragment
+fn fragment(@builtin(position) position: vec4<f32>) -> @location(0) {output} {{
+    let color = textureLoad(source, vec2<i32>(position.xy), 0);
+    var value = clamp({value}, 0.0, 1.0);
+    if SRGB {{
+        value = select(1.055 * pow(value, 1.0 / 2.4) - 0.055, value * 12.92, value <= 0.0031308);
+    }}
+    return {result};
+}}
+"#,
+            srgb = self.srgb,
+        )
+    }
+}
+
+/// The render pipelines extracting channels, shared by all captures.
+pub(crate) struct ChannelPipelines {
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+}
+
+impl ChannelPipelines {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bevy_capture_channel"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("bevy_capture_channel"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        Self {
+            layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Creates the pipeline of the channel state, if it doesn't exist yet.
+    pub fn prepare(&mut self, device: &wgpu::Device, state: &ChannelState) {
+        let key = state.key;
+        if self.pipelines.contains_key(&key) {
+            return;
+        }
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("bevy_capture_channel"),
+            source: wgpu::ShaderSource::Wgsl(key.shader().into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("bevy_capture_channel"),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vertex",
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: Default::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fragment",
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: key.config.format.texture_format(),
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        self.pipelines.insert(key, pipeline);
+    }
+}
+
+/// The texture the channel of a capture with a [`CaptureChannel`] is extracted into.
+pub(crate) struct ChannelState {
+    key: PipelineKey,
+    texture: Texture,
+}
+
+impl ChannelState {
+    /// Creates the texture for frames of the given size and format, or returns `None` if the
+    /// channels of the format can't be extracted.
+    pub fn new(
+        render_device: &RenderDevice,
+        size: UVec2,
+        format: TextureFormat,
+        config: CaptureChannel,
+    ) -> Option<Self> {
+        if !matches!(
+            format.sample_type(None, None),
+            Some(wgpu::TextureSampleType::Float { .. })
+        ) {
+            warn!(
+                "The channels of frames with format {format:?} can't be extracted, capturing all"
+            );
+            return None;
+        }
+
+        let texture = render_device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("bevy_capture_channel"),
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format.texture_format(),
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let key = PipelineKey {
+            config,
+            srgb: format.is_srgb() && config.channel != Channel::Alpha,
+        };
+        Some(Self { key, texture })
+    }
+
+    /// Returns the texture the channel is extracted into.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Records the extraction of the channel of the source into the texture.
+    pub fn record(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &ChannelPipelines,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::Texture,
+    ) {
+        let Some(pipeline) = pipelines.pipelines.get(&self.key) else {
+            return;
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bevy_capture_channel"),
+            layout: &pipelines.layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &source.create_view(&Default::default()),
+                ),
+            }],
+        });
+
+        let target = self.texture.create_view(&Default::default());
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("bevy_capture_channel"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,161 @@
+//! Record clips of a fixed duration when something happens in the app, e.g. an achievement or a
+//! detected bug.
+//!
+//! Attach a [`ClipRecorder`] to a capture camera and send [`RecordClip`], or forward any app event
+//! with [`ClipTriggerAppExt::add_clip_trigger`].
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{clip::*, encoder::frames::FramesEncoder, CaptureBundle};
+//! #
+//! app.add_plugins(ClipPlugin)
+//!     .add_clip_trigger(|achievement: &Achievement| format!("achievement_{}", achievement.id));
+//!
+//! fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+//!     commands.spawn((
+//!         Camera2dBundle::default().target_headless(512, 512, &mut images),
+//!         CaptureBundle::default(),
+//!         ClipRecorder::new(|name| FramesEncoder::new(format!("clips/{name}"))),
+//!     ));
+//! }
+//! ```
+
+use crate::{BoxedEncoder, Capture, IntoEncoders};
+use bevy::{prelude::*, utils::Duration};
+
+/// The default duration of a clip.
+pub const DEFAULT_CLIP_DURATION: Duration = Duration::from_secs(5);
+
+/// A Bevy plugin for recording clips.
+pub struct ClipPlugin;
+
+impl Plugin for ClipPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RecordClip>()
+            .add_systems(Update, update_clips);
+    }
+}
+
+/// Extension trait for the app to record clips when an event is sent.
+pub trait ClipTriggerAppExt {
+    /// Sends a [`RecordClip`] with the name returned by the given function whenever the event `E`
+    /// is sent.
+    fn add_clip_trigger<E: Event>(
+        &mut self,
+        name: impl Fn(&E) -> String + Send + Sync + 'static,
+    ) -> &mut Self;
+}
+
+impl ClipTriggerAppExt for App {
+    fn add_clip_trigger<E: Event>(
+        &mut self,
+        name: impl Fn(&E) -> String + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add_systems(
+            Update,
+            (move |mut events: EventReader<E>, mut clips: EventWriter<RecordClip>| {
+                for event in events.read() {
+                    clips.send(RecordClip::new(name(event)));
+                }
+            })
+            .before(update_clips),
+        )
+    }
+}
+
+/// An event to record a clip with every [`ClipRecorder`].
+#[derive(Debug, Clone, Event)]
+pub struct RecordClip {
+    name: String,
+    duration: Option<Duration>,
+}
+
+impl RecordClip {
+    /// Creates a new clip with the given name, which is passed to the encoder factory of the recorder.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            duration: None,
+        }
+    }
+
+    /// Overrides the duration of the recorder for this clip.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+}
+
+type EncoderFactory = Box<dyn Fn(&str) -> Vec<BoxedEncoder> + Send + Sync + 'static>;
+
+/// A component that records clips with the [`Capture`] of the same entity.
+///
+/// If a clip is requested while a clip is recording, the recording is extended instead. Clips are
+/// not started while the capture is used for something else.
+#[derive(Component)]
+pub struct ClipRecorder {
+    encoders: EncoderFactory,
+    duration: Duration,
+    recording: Option<Duration>,
+}
+
+impl ClipRecorder {
+    /// Creates a new clip recorder with the [default duration](DEFAULT_CLIP_DURATION). The given
+    /// function creates the encoders for a clip with the given name.
+    pub fn new<E: IntoEncoders>(encoders: impl Fn(&str) -> E + Send + Sync + 'static) -> Self {
+        Self {
+            encoders: Box::new(move |name| encoders(name).into_encoders()),
+            duration: DEFAULT_CLIP_DURATION,
+            recording: None,
+        }
+    }
+
+    /// Sets the duration of a clip, measured in real time.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Returns `true` if a clip is recording.
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+}
+
+fn update_clips(
+    mut events: EventReader<RecordClip>,
+    mut recorders: Query<(Entity, &mut ClipRecorder, &mut Capture)>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed();
+
+    for (_, mut recorder, mut capture) in &mut recorders {
+        match recorder.recording {
+            Some(ends_at) if now >= ends_at || !capture.is_capturing() => {
+                capture.stop();
+                recorder.recording = None;
+            }
+            _ => {}
+        }
+    }
+
+    for event in events.read() {
+        for (entity, mut recorder, mut capture) in &mut recorders {
+            let ends_at = now + event.duration.unwrap_or(recorder.duration);
+            match recorder.recording {
+                Some(ref mut recording) => *recording = (*recording).max(ends_at),
+                None if capture.is_capturing() => {
+                    warn!(
+                        "The capture of {:?} is busy, skipping clip {:?}",
+                        entity, event.name
+                    );
+                }
+                None => {
+                    capture.start((recorder.encoders)(&event.name));
+                    recorder.recording = Some(ends_at);
+                }
+            }
+        }
+    }
+}
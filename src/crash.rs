@@ -0,0 +1,68 @@
+//! Save the frames of a replay buffer when the app panics, to attach visual context to crash reports.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy::utils::Duration;
+//! # use bevy_capture::{crash, encoder::replay::ReplayLength, Capture};
+//! #
+//! fn setup(mut capture: Query<&mut Capture>) {
+//!     // Keep the last 5 seconds.
+//!     let mut capture = capture.single_mut();
+//!     capture.start_replay(ReplayLength::Duration(Duration::from_secs(5)));
+//!     crash::install_panic_hook(capture.replay_buffer().unwrap(), "crash_reports");
+//! }
+//! ```
+
+use crate::encoder::replay::ReplayBuffer;
+use std::{
+    panic,
+    path::PathBuf,
+    sync::{Mutex, Once, TryLockError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The replay buffer and the directory of the panic hook.
+static CRASH_DUMP: Mutex<Option<(ReplayBuffer, PathBuf)>> = Mutex::new(None);
+
+static INSTALL: Once = Once::new();
+
+/// Installs a panic hook that saves the frames of the given replay buffer as a png sequence into a
+/// new `crash_{unix timestamp}` directory inside the given directory.
+///
+/// The hook is only installed once. Calling this again replaces the replay buffer and the
+/// directory, so the frames are still saved once per panic.
+///
+/// The previously installed hook (e.g. the one printing the panic message) is called first.
+/// Frames can not be saved if the panic happened while the replay buffer was locked.
+pub fn install_panic_hook(buffer: ReplayBuffer, directory: impl Into<PathBuf>) {
+    *CRASH_DUMP.lock().unwrap_or_else(|err| err.into_inner()) = Some((buffer, directory.into()));
+
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            previous(info);
+
+            // The dump is skipped if another thread is panicking and saving the frames already.
+            let dump = match CRASH_DUMP.try_lock() {
+                Ok(dump) => dump,
+                Err(TryLockError::Poisoned(err)) => err.into_inner(),
+                Err(TryLockError::WouldBlock) => return,
+            };
+            let Some((buffer, directory)) = &*dump else {
+                return;
+            };
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default();
+            let directory = directory.join(format!("crash_{timestamp}"));
+            // Printed to stderr next to the panic message instead of logged, since the panic may
+            // happen while the logger is torn down or inside it, and the app exits right after.
+            match buffer.try_save_frames(&directory) {
+                Ok(count) => eprintln!("Saved the last {count} frames to {}", directory.display()),
+                Err(err) => eprintln!("Failed to save the last frames: {err}"),
+            }
+        }));
+    });
+}
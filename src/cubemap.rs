@@ -0,0 +1,389 @@
+//! Capture cubemaps from a point in the scene, e.g. to author environment maps for image based lighting.
+//!
+//! The six faces are rendered with HDR cameras (without tonemapping) into `Rgba16Float` images.
+//! The output format is derived from the extension:
+//!
+//! - `.ktx2`: A KTX2 cubemap (`R16G16B16A16_SFLOAT`), which can be loaded by Bevy and used directly
+//!   as the diffuse or specular map of an `EnvironmentMapLight` (after prefiltering, if needed).
+//! - `.hdr` or `.exr`: An equirectangular image (requires the `hdr` feature).
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::cubemap::*;
+//! #
+//! fn bake(mut cubemaps: EventWriter<CaptureCubemap>) {
+//!     cubemaps.send(CaptureCubemap::new(Vec3::new(0.0, 1.0, 0.0), "assets/env.ktx2").with_size(512));
+//! }
+//! ```
+
+use crate::{encoder, CaptureBundle, Encoder};
+use bevy::{
+    core_pipeline::tonemapping::{DebandDither, Tonemapping},
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+    },
+};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// The number of frames rendered before the faces are read back.
+const WARMUP_FRAMES: u32 = 1;
+
+/// The default size of a cubemap face in pixels.
+pub const DEFAULT_FACE_SIZE: u32 = 256;
+
+/// A Bevy plugin for cubemap captures.
+pub struct CubemapCapturePlugin;
+
+impl Plugin for CubemapCapturePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingCubemaps>()
+            .add_event::<CaptureCubemap>()
+            .add_event::<CubemapCaptured>()
+            .add_systems(Update, (finish_cubemaps, start_cubemaps).chain());
+    }
+}
+
+/// An event to capture a cubemap from the given position.
+#[derive(Debug, Clone, Event)]
+pub struct CaptureCubemap {
+    position: Vec3,
+    size: u32,
+    output: PathBuf,
+}
+
+impl CaptureCubemap {
+    /// Creates a new cubemap capture with the [default face size](DEFAULT_FACE_SIZE) that is saved
+    /// to the given path.
+    pub fn new(position: Vec3, output: impl Into<PathBuf>) -> Self {
+        Self {
+            position,
+            size: DEFAULT_FACE_SIZE,
+            output: output.into(),
+        }
+    }
+
+    /// Sets the size of a cubemap face in pixels.
+    pub fn with_size(mut self, size: u32) -> Self {
+        self.size = size.max(1);
+        self
+    }
+}
+
+/// An event sent when a cubemap capture is done.
+#[derive(Debug, Clone, Event)]
+pub struct CubemapCaptured {
+    /// The position the cubemap was captured from.
+    pub position: Vec3,
+    /// The path the cubemap was saved to.
+    pub output: PathBuf,
+    /// Whether the cubemap was saved successfully. Failures are logged.
+    pub success: bool,
+}
+
+#[derive(Default, Resource)]
+struct PendingCubemaps(Vec<PendingCubemap>);
+
+struct PendingCubemap {
+    request: CaptureCubemap,
+    cameras: Vec<Entity>,
+    images: Vec<Handle<Image>>,
+    faces: Arc<Mutex<[Option<Vec<u8>>; 6]>>,
+}
+
+/// The forward and up vector of every face, in the order of the cubemap layers
+/// (+X, -X, +Y, -Y, +Z, -Z). Bevy samples cubemaps with a flipped z axis, so the +Z face looks
+/// along -Z in world space.
+const FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::Y),
+    (Vec3::NEG_X, Vec3::Y),
+    (Vec3::Y, Vec3::Z),
+    (Vec3::NEG_Y, Vec3::NEG_Z),
+    (Vec3::NEG_Z, Vec3::Y),
+    (Vec3::Z, Vec3::Y),
+];
+
+fn start_cubemaps(
+    mut commands: Commands,
+    mut pending: ResMut<PendingCubemaps>,
+    mut events: EventReader<CaptureCubemap>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for request in events.read() {
+        let faces = Arc::<Mutex<[Option<Vec<u8>>; 6]>>::default();
+        let mut cameras = Vec::with_capacity(6);
+        let mut targets = Vec::with_capacity(6);
+
+        for (face, (forward, up)) in FACES.into_iter().enumerate() {
+            let target = images.add(face_image(request.size));
+            let mut capture = CaptureBundle::default();
+            capture.capture.start(FaceEncoder {
+                face,
+                warmup_frames: WARMUP_FRAMES,
+                faces: Arc::clone(&faces),
+            });
+
+            let camera = commands
+                .spawn((
+                    Camera3dBundle {
+                        camera: Camera {
+                            hdr: true,
+                            target: RenderTarget::Image(target.clone()),
+                            ..default()
+                        },
+                        projection: Projection::Perspective(PerspectiveProjection {
+                            fov: std::f32::consts::FRAC_PI_2,
+                            aspect_ratio: 1.0,
+                            ..default()
+                        }),
+                        tonemapping: Tonemapping::None,
+                        deband_dither: DebandDither::Disabled,
+                        transform: Transform::from_translation(request.position)
+                            .looking_to(forward, up),
+                        ..default()
+                    },
+                    capture,
+                ))
+                .id();
+
+            cameras.push(camera);
+            targets.push(target);
+        }
+
+        pending.0.push(PendingCubemap {
+            request: request.clone(),
+            cameras,
+            images: targets,
+            faces,
+        });
+    }
+}
+
+fn finish_cubemaps(
+    mut commands: Commands,
+    mut pending: ResMut<PendingCubemaps>,
+    mut images: ResMut<Assets<Image>>,
+    mut captured: EventWriter<CubemapCaptured>,
+) {
+    pending.0.retain(|cubemap| {
+        let faces = {
+            let mut faces = cubemap.faces.lock().unwrap();
+            if faces.iter().any(Option::is_none) {
+                return true;
+            }
+            std::mem::take(&mut *faces).map(Option::unwrap)
+        };
+
+        for &camera in &cubemap.cameras {
+            commands.entity(camera).despawn_recursive();
+        }
+        for image in &cubemap.images {
+            images.remove(image);
+        }
+
+        let request = &cubemap.request;
+        let result = write_cubemap(&request.output, request.size, &faces);
+        if let Err(err) = &result {
+            bevy::log::error!("Failed to write cubemap {:?}: {:?}", request.output, err);
+        }
+        captured.send(CubemapCaptured {
+            position: request.position,
+            output: request.output.clone(),
+            success: result.is_ok(),
+        });
+
+        false
+    });
+}
+
+fn face_image(size: u32) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 8],
+        TextureFormat::Rgba16Float,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage |=
+        TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING;
+    image
+}
+
+/// Stores the raw `Rgba16Float` data of a face after some warmup frames.
+struct FaceEncoder {
+    face: usize,
+    warmup_frames: u32,
+    faces: Arc<Mutex<[Option<Vec<u8>>; 6]>>,
+}
+
+impl Encoder for FaceEncoder {
+    fn encode(&mut self, image: &Image) -> encoder::Result<()> {
+        if self.warmup_frames > 0 {
+            self.warmup_frames -= 1;
+            return Ok(());
+        }
+
+        let mut faces = self.faces.lock().unwrap();
+        if faces[self.face].is_none() {
+            faces[self.face] = Some(image.data.clone());
+        }
+
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "cubemap"
+    }
+}
+
+fn write_cubemap(path: &Path, size: u32, faces: &[Vec<u8>; 6]) -> encoder::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("ktx2") => write_ktx2(&mut fs::File::create(path)?, size, faces),
+        #[cfg(feature = "hdr")]
+        Some("hdr" | "exr") => {
+            image::DynamicImage::ImageRgb32F(equirect(size, faces)).save(path)?;
+            Ok(())
+        }
+        _ => Err(format!("unsupported cubemap format: {:?}", path).into()),
+    }
+}
+
+/// Writes a single level KTX2 cubemap with `R16G16B16A16_SFLOAT` texels.
+fn write_ktx2(writer: &mut impl Write, size: u32, faces: &[Vec<u8>; 6]) -> encoder::Result<()> {
+    const IDENTIFIER: [u8; 12] = [
+        0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+    ];
+    const VK_FORMAT_R16G16B16A16_SFLOAT: u32 = 97;
+    const HEADER_LEN: u32 = 80;
+    const LEVEL_INDEX_LEN: u32 = 24;
+
+    // The basic data format descriptor: RGBA, BT.709 primaries, linear transfer, 16 bit floats.
+    let mut dfd = Vec::with_capacity(92);
+    dfd.extend_from_slice(&92u32.to_le_bytes());
+    dfd.extend_from_slice(&0u32.to_le_bytes());
+    dfd.extend_from_slice(&2u16.to_le_bytes());
+    dfd.extend_from_slice(&88u16.to_le_bytes());
+    dfd.extend_from_slice(&[1, 1, 1, 0]);
+    dfd.extend_from_slice(&[0; 4]);
+    dfd.extend_from_slice(&[8, 0, 0, 0, 0, 0, 0, 0]);
+    for (i, channel) in [0u8, 1, 2, 15].into_iter().enumerate() {
+        dfd.extend_from_slice(&(i as u16 * 16).to_le_bytes());
+        dfd.push(15);
+        // Float and signed qualifiers.
+        dfd.push(channel | 0x80 | 0x40);
+        dfd.extend_from_slice(&[0; 4]);
+        dfd.extend_from_slice(&(-1.0f32).to_bits().to_le_bytes());
+        dfd.extend_from_slice(&1.0f32.to_bits().to_le_bytes());
+    }
+
+    let dfd_offset = HEADER_LEN + LEVEL_INDEX_LEN;
+    // The level data is aligned to the texel size.
+    let level_offset = (dfd_offset + dfd.len() as u32).next_multiple_of(8);
+    let level_len = faces.iter().map(|face| face.len() as u64).sum::<u64>();
+
+    let mut header = Vec::with_capacity(level_offset as usize);
+    header.extend_from_slice(&IDENTIFIER);
+    for value in [VK_FORMAT_R16G16B16A16_SFLOAT, 2, size, size, 0, 0, 6, 1, 0] {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [dfd_offset, dfd.len() as u32, 0, 0] {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [0u64, 0, level_offset as u64, level_len, level_len] {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    header.extend_from_slice(&dfd);
+    header.resize(level_offset as usize, 0);
+
+    writer.write_all(&header)?;
+    for face in faces {
+        writer.write_all(face)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Resamples the faces into an equirectangular image that is twice as wide as high.
+#[cfg(feature = "hdr")]
+fn equirect(size: u32, faces: &[Vec<u8>; 6]) -> image::Rgb32FImage {
+    let (width, height) = (size * 4, size * 2);
+    image::Rgb32FImage::from_fn(width, height, |x, y| {
+        let longitude =
+            (x as f32 + 0.5) / width as f32 * std::f32::consts::TAU - std::f32::consts::PI;
+        let latitude =
+            std::f32::consts::FRAC_PI_2 - (y as f32 + 0.5) / height as f32 * std::f32::consts::PI;
+        // The center of the image looks along -Z.
+        let direction = Vec3::new(
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+            -latitude.cos() * longitude.cos(),
+        );
+        image::Rgb(sample_cubemap(size, faces, direction))
+    })
+}
+
+#[cfg(feature = "hdr")]
+fn sample_cubemap(size: u32, faces: &[Vec<u8>; 6], direction: Vec3) -> [f32; 3] {
+    // Pick the face the direction points at the most.
+    let (face, forward, up) = FACES
+        .into_iter()
+        .enumerate()
+        .map(|(face, (forward, up))| (face, forward, up))
+        .max_by(|a, b| direction.dot(a.1).total_cmp(&direction.dot(b.1)))
+        .unwrap();
+    let right = forward.cross(up);
+
+    // Project onto the face and sample bilinearly.
+    let depth = direction.dot(forward);
+    let u = (direction.dot(right) / depth + 1.0) / 2.0 * size as f32 - 0.5;
+    let v = (1.0 - direction.dot(up) / depth) / 2.0 * size as f32 - 0.5;
+    let (x0, y0) = (u.floor(), v.floor());
+    let (fx, fy) = (u - x0, v - y0);
+
+    let texel = |x: f32, y: f32| -> Vec3 {
+        let x = (x as i64).clamp(0, size as i64 - 1) as usize;
+        let y = (y as i64).clamp(0, size as i64 - 1) as usize;
+        let offset = (y * size as usize + x) * 8;
+        let channel = |c: usize| {
+            f16_to_f32(u16::from_le_bytes([
+                faces[face][offset + c * 2],
+                faces[face][offset + c * 2 + 1],
+            ]))
+        };
+        Vec3::new(channel(0), channel(1), channel(2))
+    };
+
+    let top = texel(x0, y0).lerp(texel(x0 + 1.0, y0), fx);
+    let bottom = texel(x0, y0 + 1.0).lerp(texel(x0 + 1.0, y0 + 1.0), fx);
+    top.lerp(bottom, fy).to_array()
+}
+
+#[cfg(feature = "hdr")]
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * (-24f32).exp2(),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * ((exponent - 15) as f32).exp2(),
+    }
+}
@@ -0,0 +1,182 @@
+//! Draw the cursor into the captured frames.
+//!
+//! The hardware cursor is drawn by the OS on top of the window, so it is not part of the rendered
+//! frames. The [`CursorOverlayPlugin`] records the cursor position of the primary window in the
+//! [frame metadata](crate::metadata) with the [`CURSOR_X_KEY`] and [`CURSOR_Y_KEY`] keys. The
+//! [`CursorOverlayEncoder`] draws a cursor at that position before passing the frames on.
+//!
+//! The position is relative to the size of the window, so it also matches frames of a different
+//! resolution. Apps without a window, e.g. with a virtual cursor, can set the keys themselves.
+
+use crate::{
+    encoder::{capabilities::EncoderCapabilities, to_rgba8, Encoder, Result},
+    metadata::{FrameMetadata, MetadataValue},
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+    window::PrimaryWindow,
+};
+
+/// The metadata key of the horizontal cursor position, from `0.0` (left) to `1.0` (right).
+pub const CURSOR_X_KEY: &str = "cursor_x";
+
+/// The metadata key of the vertical cursor position, from `0.0` (top) to `1.0` (bottom).
+pub const CURSOR_Y_KEY: &str = "cursor_y";
+
+/// A Bevy plugin that records the cursor position of the primary window in the frame metadata,
+/// while the cursor is inside the window and visible.
+pub struct CursorOverlayPlugin;
+
+impl Plugin for CursorOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, record_cursor);
+    }
+}
+
+fn record_cursor(
+    mut metadata: ResMut<FrameMetadata>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    if !window.cursor.visible {
+        return;
+    }
+    if let Some(position) = window.cursor_position() {
+        metadata.insert(CURSOR_X_KEY, position.x / window.width());
+        metadata.insert(CURSOR_Y_KEY, position.y / window.height());
+    }
+}
+
+/// An encoder that draws an arrow cursor at the position of the [`CURSOR_X_KEY`] and
+/// [`CURSOR_Y_KEY`] metadata before passing the frames to the inner encoder.
+///
+/// Frames without a cursor position are passed on unchanged, other frames are converted to RGBA8.
+pub struct CursorOverlayEncoder<E> {
+    inner: E,
+    scale: Option<u32>,
+}
+
+impl<E: Encoder> CursorOverlayEncoder<E> {
+    /// Creates a new cursor overlay encoder that passes the frames to the inner encoder.
+    pub fn new(inner: E) -> Self {
+        Self { inner, scale: None }
+    }
+
+    /// Sets the size of a cursor pixel in frame pixels. By default, this depends on the height of
+    /// the frame.
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = Some(scale.max(1));
+        self
+    }
+}
+
+impl<E: Encoder> Encoder for CursorOverlayEncoder<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let position = |key| match metadata.get(key) {
+            Some(MetadataValue::Float(value)) => Some(*value),
+            Some(MetadataValue::Int(value)) => Some(*value as f64),
+            _ => None,
+        };
+        let (Some(x), Some(y)) = (position(CURSOR_X_KEY), position(CURSOR_Y_KEY)) else {
+            return self.inner.encode_with_metadata(image, metadata);
+        };
+
+        let (width, height) = (image.width(), image.height());
+        let mut rgba = to_rgba8(image)?.into_owned();
+        let scale = self.scale.unwrap_or((height / 720).max(1));
+        draw_cursor(
+            &mut rgba,
+            width,
+            height,
+            (x * width as f64).round() as i64,
+            (y * height as f64).round() as i64,
+            scale,
+        );
+
+        let image = Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            rgba,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        let image = self.inner.capabilities().convert(&image)?;
+        self.inner.encode_with_metadata(&image, metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        Box::new(self.inner).finish();
+    }
+}
+
+/// An arrow cursor with its hot spot in the top left corner: `#` is the outline, `.` the fill.
+const CURSOR: [&str; 16] = [
+    "#",
+    "##",
+    "#.#",
+    "#..#",
+    "#...#",
+    "#....#",
+    "#.....#",
+    "#......#",
+    "#.......#",
+    "#........#",
+    "#.....#####",
+    "#..#..#",
+    "#.# #..#",
+    "##  #..#",
+    "     #..#",
+    "     ####",
+];
+
+fn draw_cursor(rgba: &mut [u8], width: u32, height: u32, x: i64, y: i64, scale: u32) {
+    for (row, line) in CURSOR.iter().enumerate() {
+        for (column, c) in line.chars().enumerate() {
+            let color = match c {
+                '#' => [0, 0, 0, 255],
+                '.' => [255, 255, 255, 255],
+                _ => continue,
+            };
+            for dy in 0..scale as i64 {
+                for dx in 0..scale as i64 {
+                    let px = x + column as i64 * scale as i64 + dx;
+                    let py = y + row as i64 * scale as i64 + dy;
+                    if (0..width as i64).contains(&px) && (0..height as i64).contains(&py) {
+                        let offset = (py as usize * width as usize + px as usize) * 4;
+                        rgba[offset..offset + 4].copy_from_slice(&color);
+                    }
+                }
+            }
+        }
+    }
+}
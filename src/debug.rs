@@ -0,0 +1,146 @@
+//! Inspect the active captures at runtime, e.g. for debug UIs or to find out where a recording is
+//! written to.
+//!
+//! The [`CaptureRegistry`] resource lists all active captures with their cameras and encoders. It
+//! is updated by the [`CapturePlugin`](crate::CapturePlugin) every frame, after captures were
+//! started or stopped. The names and outputs of the encoders are those of
+//! [`Encoder::name`](crate::Encoder::name) and
+//! [`Encoder::describe_output`](crate::Encoder::describe_output) when the encoders were attached.
+//!
+//! [`log_active_captures`] is a system that logs the registry, e.g. on a key press.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::{input::common_conditions::input_just_pressed, prelude::*};
+//! # use bevy_capture::debug::*;
+//! #
+//! app.add_systems(
+//!     Update,
+//!     log_active_captures.run_if(input_just_pressed(KeyCode::F9)),
+//! );
+//!
+//! fn recording_indicator(registry: Res<CaptureRegistry>) {
+//!     for capture in registry.captures() {
+//!         for encoder in &capture.encoders {
+//!             println!("{:?} -> {} ({})", capture.entity, encoder.output, encoder.name);
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::{Capture, CaptureSource, EncoderId};
+use bevy::{prelude::*, utils::Duration};
+
+/// All active captures, see the [module docs](self).
+#[derive(Debug, Default, Clone, Resource)]
+pub struct CaptureRegistry {
+    captures: Vec<ActiveCapture>,
+}
+
+impl CaptureRegistry {
+    /// Returns the active captures, in no particular order.
+    pub fn captures(&self) -> &[ActiveCapture] {
+        &self.captures
+    }
+
+    /// Returns the active capture of the given entity, if any.
+    pub fn get(&self, entity: Entity) -> Option<&ActiveCapture> {
+        self.captures
+            .iter()
+            .find(|capture| capture.entity == entity)
+    }
+
+    /// Returns the number of active captures.
+    pub fn len(&self) -> usize {
+        self.captures.len()
+    }
+
+    /// Returns `true` if no capture is active.
+    pub fn is_empty(&self) -> bool {
+        self.captures.is_empty()
+    }
+}
+
+/// An active capture in the [`CaptureRegistry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveCapture {
+    /// The entity of the [`Capture`].
+    pub entity: Entity,
+    /// The entity of the camera the frames are captured from, see [`CaptureSource`].
+    pub camera: Entity,
+    /// Whether the capture is paused.
+    pub paused: bool,
+    /// The number of frames captured so far.
+    pub frames_captured: u64,
+    /// The wall-clock time since the capture was started.
+    pub elapsed: Duration,
+    /// The encoders of the capture.
+    pub encoders: Vec<EncoderInfo>,
+}
+
+/// The id, name, output and progress of an encoder, see [`Capture::encoders`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EncoderInfo {
+    /// The id of the encoder.
+    pub id: EncoderId,
+    /// The name of the encoder, see [`Encoder::name`](crate::Encoder::name).
+    pub name: String,
+    /// Where the encoder writes to, see
+    /// [`Encoder::describe_output`](crate::Encoder::describe_output). Empty if unknown.
+    pub output: String,
+    /// The number of frames the encoder encoded successfully.
+    pub frames_encoded: u64,
+    /// The number of frames the encoder failed to encode.
+    pub errors: u64,
+}
+
+pub(crate) fn update_capture_registry(
+    mut registry: ResMut<CaptureRegistry>,
+    captures: Query<(Entity, &Capture, &CaptureSource)>,
+) {
+    registry.captures.clear();
+    for (entity, capture, source) in &captures {
+        let Some(elapsed) = capture.elapsed() else {
+            continue;
+        };
+        registry.captures.push(ActiveCapture {
+            entity,
+            camera: match source {
+                CaptureSource::ThisCamera => entity,
+                CaptureSource::Camera(camera) => *camera,
+            },
+            paused: capture.is_paused(),
+            frames_captured: capture.frames_captured(),
+            elapsed,
+            encoders: capture.encoders(),
+        });
+    }
+}
+
+/// A system that logs the active captures of the [`CaptureRegistry`], with one line per encoder.
+pub fn log_active_captures(registry: Res<CaptureRegistry>) {
+    if registry.is_empty() {
+        info!("No active captures");
+        return;
+    }
+    for capture in registry.captures() {
+        info!(
+            "Capture {:?} of camera {:?}: {} frames in {:.1?}{}",
+            capture.entity,
+            capture.camera,
+            capture.frames_captured,
+            capture.elapsed,
+            if capture.paused { " (paused)" } else { "" },
+        );
+        for encoder in &capture.encoders {
+            let output = match encoder.output.as_str() {
+                "" => "unknown output",
+                output => output,
+            };
+            info!(
+                "  {} {} -> {} ({} frames, {} errors)",
+                encoder.name, encoder.id, output, encoder.frames_encoded, encoder.errors
+            );
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Record debug views, e.g. wireframes and bounding boxes, with a capture camera only, so debug
+//! footage can be recorded alongside the beauty pass without affecting the player's view.
+//!
+//! Bevy's wireframe and bounding box rendering is global. The [`DebugViewPlugin`] instead renders
+//! them on the [`DEBUG_LAYER`], which only cameras with a [`DebugView`] see:
+//!
+//! - Wireframes are drawn by wireframe-only copies of all meshes on the debug layer. This requires
+//!   the `PbrPlugin`, the `WireframePlugin` is added if necessary.
+//! - Bounding boxes are drawn by the [`AabbGizmoConfigGroup`], which is moved to the debug layer
+//!   while any camera shows them. This requires the `GizmoPlugin`.
+//!
+//! Attach the [`DebugView`] to a separate capture camera, e.g. a pass camera of
+//! [`CapturePasses`](crate::multi_pass::CapturePasses), to record the beauty pass and the debug
+//! view of the same frames.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{debug_view::*, multi_pass::*};
+//! #
+//! app.add_plugins((DebugViewPlugin, MultiPassPlugin));
+//!
+//! let passes = CapturePasses::default().with_pass(
+//!     CapturePass::new("debug", |name| Ok(my_encoder(name)))
+//!         .with_setup(|pass| {
+//!             pass.insert(DebugView::default().with_wireframe(true).with_aabbs(true));
+//!         }),
+//! );
+//! ```
+
+use bevy::{
+    gizmos::{aabb::AabbGizmoConfigGroup, config::GizmoConfigStore},
+    pbr::{
+        wireframe::{Wireframe, WireframePlugin},
+        NotShadowCaster, PbrPlugin,
+    },
+    prelude::*,
+    render::view::{Layer, RenderLayers},
+};
+
+/// The render layer debug views are rendered on.
+pub const DEBUG_LAYER: Layer = 31;
+
+/// A Bevy plugin for [`DebugView`]s.
+pub struct DebugViewPlugin;
+
+impl Plugin for DebugViewPlugin {
+    fn build(&self, app: &mut App) {
+        if app.is_plugin_added::<PbrPlugin>() && !app.is_plugin_added::<WireframePlugin>() {
+            app.add_plugins(WireframePlugin);
+        }
+
+        app.init_resource::<DebugViewState>().add_systems(
+            PostUpdate,
+            (add_debug_layer, update_aabbs, update_wireframes),
+        );
+    }
+}
+
+/// The debug views rendered by a camera. The camera additionally renders the [`DEBUG_LAYER`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component)]
+pub struct DebugView {
+    wireframe: bool,
+    aabbs: bool,
+}
+
+impl DebugView {
+    /// Sets whether the wireframes of all meshes are shown.
+    pub fn with_wireframe(mut self, wireframe: bool) -> Self {
+        self.wireframe = wireframe;
+        self
+    }
+
+    /// Sets whether the bounding boxes of all entities are shown.
+    pub fn with_aabbs(mut self, aabbs: bool) -> Self {
+        self.aabbs = aabbs;
+        self
+    }
+
+    /// Returns `true` if the wireframes of all meshes are shown.
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    /// Returns `true` if the bounding boxes of all entities are shown.
+    pub fn aabbs(&self) -> bool {
+        self.aabbs
+    }
+}
+
+/// A wireframe-only copy of the mesh of its parent on the [`DEBUG_LAYER`].
+#[derive(Component)]
+pub struct WireframeProxy;
+
+/// The settings of the [`AabbGizmoConfigGroup`] before bounding boxes were moved to the debug
+/// layer, to restore them afterwards.
+#[derive(Default, Resource)]
+struct DebugViewState {
+    aabbs: Option<(RenderLayers, bool)>,
+}
+
+fn add_debug_layer(
+    mut commands: Commands,
+    cameras: Query<(Entity, Option<&RenderLayers>), Added<DebugView>>,
+) {
+    for (entity, layers) in &cameras {
+        let layers = layers.cloned().unwrap_or_default().with(DEBUG_LAYER);
+        commands.entity(entity).insert(layers);
+    }
+}
+
+fn update_aabbs(
+    mut state: ResMut<DebugViewState>,
+    config_store: Option<ResMut<GizmoConfigStore>>,
+    views: Query<&DebugView>,
+) {
+    let Some(mut config_store) = config_store else {
+        return;
+    };
+    let enabled = views.iter().any(|view| view.aabbs);
+    let (config, aabbs) = config_store.config_mut::<AabbGizmoConfigGroup>();
+
+    match (enabled, state.aabbs.take()) {
+        (true, None) => {
+            let render_layers =
+                std::mem::replace(&mut config.render_layers, RenderLayers::layer(DEBUG_LAYER));
+            state.aabbs = Some((render_layers, aabbs.draw_all));
+            aabbs.draw_all = true;
+        }
+        (false, Some((render_layers, draw_all))) => {
+            config.render_layers = render_layers;
+            aabbs.draw_all = draw_all;
+        }
+        (_, previous) => state.aabbs = previous,
+    }
+}
+
+type MeshesQuery<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static Handle<Mesh>, Option<&'static Children>),
+    Without<WireframeProxy>,
+>;
+
+fn update_wireframes(
+    mut commands: Commands,
+    views: Query<&DebugView>,
+    meshes: MeshesQuery<'_, '_>,
+    mut proxies: Query<&mut Handle<Mesh>, With<WireframeProxy>>,
+    proxy_entities: Query<Entity, With<WireframeProxy>>,
+) {
+    if !views.iter().any(|view| view.wireframe) {
+        for proxy in &proxy_entities {
+            commands.entity(proxy).despawn_recursive();
+        }
+        return;
+    }
+
+    for (entity, mesh, children) in &meshes {
+        let proxy = children
+            .into_iter()
+            .flatten()
+            .find(|child| proxies.contains(**child));
+        match proxy {
+            Some(proxy) => {
+                // Follow changes of the mesh.
+                let mut proxy_mesh = proxies.get_mut(*proxy).unwrap();
+                if *proxy_mesh != *mesh {
+                    *proxy_mesh = mesh.clone();
+                }
+            }
+            None => {
+                commands.entity(entity).with_children(|parent| {
+                    parent.spawn((
+                        WireframeProxy,
+                        mesh.clone(),
+                        SpatialBundle::default(),
+                        RenderLayers::layer(DEBUG_LAYER),
+                        Wireframe,
+                        NotShadowCaster,
+                    ));
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Configure capture once at startup, e.g. in large apps where captures are started from many
+//! places that shouldn't know about encoders, output directories or framerates.
+//!
+//! Insert a [`DefaultCaptureSettings`] resource and call [`Capture::start_default`] anywhere. The
+//! encoders are created by the factory of the settings before the frame is rendered.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::{defaults::DefaultCaptureSettings, encoder::mp4_ffmpeg_cli::Mp4FfmpegCliEncoder};
+//! #
+//! app.insert_resource(
+//!     DefaultCaptureSettings::new(|capture| {
+//!         Ok(Mp4FfmpegCliEncoder::new(capture.path("mp4"))?.with_framerate(capture.framerate))
+//!     })
+//!     .with_output_dir("captures")
+//!     .with_framerate(30),
+//! );
+//!
+//! // Anywhere else:
+//! capture.start_default();
+//! ```
+
+use crate::{encoder::Result, BoxedEncoder, Capture, CaptureState, IntoEncoders};
+use bevy::prelude::*;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// The framerate of the [`DefaultCaptureSettings`] if none is set.
+pub const DEFAULT_FRAMERATE: u32 = 60;
+
+type EncoderFactory =
+    Arc<dyn Fn(&DefaultCapture<'_>) -> Result<Vec<BoxedEncoder>> + Send + Sync + 'static>;
+
+/// The encoders, output directory and framerate of captures started with
+/// [`Capture::start_default`].
+#[derive(Clone, Resource)]
+pub struct DefaultCaptureSettings {
+    factory: EncoderFactory,
+    output_dir: PathBuf,
+    framerate: u32,
+    count: u32,
+}
+
+impl DefaultCaptureSettings {
+    /// Creates new settings with the given encoder factory. The factory is called for every capture
+    /// started with [`Capture::start_default`].
+    pub fn new<F, E>(factory: F) -> Self
+    where
+        F: Fn(&DefaultCapture<'_>) -> Result<E> + Send + Sync + 'static,
+        E: IntoEncoders,
+    {
+        Self {
+            factory: Arc::new(move |capture| factory(capture).map(E::into_encoders)),
+            output_dir: PathBuf::from("captures"),
+            framerate: DEFAULT_FRAMERATE,
+            count: 0,
+        }
+    }
+
+    /// Sets the directory outputs are written to. Defaults to `captures`. The directory is created
+    /// before the factory is called.
+    pub fn with_output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = output_dir.into();
+        self
+    }
+
+    /// Sets the framerate passed to the factory. Defaults to [`DEFAULT_FRAMERATE`].
+    pub fn with_framerate(mut self, framerate: u32) -> Self {
+        self.framerate = framerate.max(1);
+        self
+    }
+
+    /// Returns the directory outputs are written to.
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    /// Returns the framerate passed to the factory.
+    pub fn framerate(&self) -> u32 {
+        self.framerate
+    }
+}
+
+/// A capture started with [`Capture::start_default`], passed to the factory of the
+/// [`DefaultCaptureSettings`].
+#[derive(Debug, Clone, Copy)]
+pub struct DefaultCapture<'a> {
+    /// The entity of the [`Capture`].
+    pub entity: Entity,
+    /// The directory outputs should be written to.
+    pub output_dir: &'a Path,
+    /// The framerate outputs should use.
+    pub framerate: u32,
+    /// The number of captures started with the settings before this one.
+    pub index: u32,
+}
+
+impl DefaultCapture<'_> {
+    /// Returns a path in the output directory that is unique for this capture,
+    /// `capture_<index>.<extension>`.
+    pub fn path(&self, extension: &str) -> PathBuf {
+        self.output_dir
+            .join(format!("capture_{:04}.{extension}", self.index))
+    }
+}
+
+pub(crate) fn start_default_captures(
+    mut settings: Option<ResMut<DefaultCaptureSettings>>,
+    mut captures: Query<(Entity, &mut Capture), Changed<Capture>>,
+) {
+    for (entity, mut capture) in &mut captures {
+        let CaptureState::StartDefault { handle } = &capture.state else {
+            continue;
+        };
+        let handle = handle.clone();
+
+        let Some(settings) = settings.as_deref_mut() else {
+            warn!("Capture::start_default was called without DefaultCaptureSettings");
+            capture.stop();
+            continue;
+        };
+
+        let default_capture = DefaultCapture {
+            entity,
+            output_dir: &settings.output_dir,
+            framerate: settings.framerate,
+            index: settings.count,
+        };
+        let encoders = std::fs::create_dir_all(&settings.output_dir)
+            .map_err(Into::into)
+            .and_then(|()| (settings.factory)(&default_capture));
+        settings.count += 1;
+
+        match encoders {
+            Ok(encoders) => capture.start_with_handle(encoders, handle),
+            Err(err) => {
+                error!("Failed to create the default encoders: {}", err);
+                capture.stop();
+            }
+        }
+    }
+}
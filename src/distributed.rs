@@ -0,0 +1,339 @@
+//! Render long offline sequences in parallel on multiple processes or machines, farm style, and
+//! merge the outputs into one.
+//!
+//! A [`RenderJob`] splits a range of frames into [`RenderSlice`]s. Every process runs the app from
+//! the start, so deterministic simulations reach the same state, but only encodes the frames of
+//! its slice with a [`CaptureRange`] into its own output, see [`RenderSlice::output_path`]. Once
+//! all slices are rendered, the outputs are merged, and the result is the same as if a single
+//! process had rendered the whole range:
+//!
+//! - [`RenderJob::merge_frames`] moves the images of directories written by e.g. the
+//!   [`FramesEncoder`](crate::encoder::frames::FramesEncoder) into one directory and renumbers
+//!   them.
+//! - [`RenderJob::merge_videos`] concatenates videos with ffmpeg, without re-encoding. The slices
+//!   must be encoded with the same settings.
+//! - [`RenderJob::merge_streams`] concatenates raw and y4m streams.
+//!
+//! [`RenderJob::run_local`] renders the slices in child processes on this machine. The slice is
+//! passed in the [`SLICE_ENV`] and [`FRAMES_ENV`] environment variables and read by the child with
+//! [`RenderSlice::from_env`]. On other machines, set the same variables, e.g. with
+//! [`RenderSlice::apply`].
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{distributed::*, encoder::frames::FramesEncoder, CaptureBundle};
+//! # use std::process::Command;
+//! #
+//! // In the coordinator, render 6000 frames in 8 processes.
+//! let job = RenderJob::new(0..6000, 8);
+//! job.run_local(4, |_slice| Command::new(std::env::current_exe().unwrap()))?;
+//! job.merge_frames("captures/frames")?;
+//!
+//! // In the app, capture the slice of this process.
+//! let slice = RenderSlice::from_env().unwrap();
+//! commands.spawn((
+//!     Camera2dBundle::default(),
+//!     CaptureBundle::default(),
+//!     slice.capture_range(),
+//! ));
+//! capture.start(FramesEncoder::new(slice.output_path("captures/frames")));
+//! ```
+
+use crate::{
+    encoder::{Error, Result},
+    range::CaptureRange,
+};
+use bevy::prelude::*;
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+/// The environment variable with the index and the number of slices, e.g. `2/8`.
+pub const SLICE_ENV: &str = "BEVY_CAPTURE_SLICE";
+
+/// The environment variable with the frames of the slice, e.g. `1500..2250`.
+pub const FRAMES_ENV: &str = "BEVY_CAPTURE_FRAMES";
+
+/// A range of frames split into slices that are rendered independently, see the
+/// [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderJob {
+    frames: Range<u64>,
+    slices: u32,
+}
+
+impl RenderJob {
+    /// Creates a job that splits the frames into the given number of slices of (almost) equal
+    /// length. If there are fewer frames than slices, there is one slice per frame.
+    pub fn new(frames: Range<u64>, slices: u32) -> Self {
+        let len = frames.end.saturating_sub(frames.start);
+        Self {
+            slices: (slices as u64).clamp(1, len.max(1)) as u32,
+            frames,
+        }
+    }
+
+    /// Returns the frames of the job.
+    pub fn frames(&self) -> Range<u64> {
+        self.frames.clone()
+    }
+
+    /// Returns the slices of the job, in the order of their frames.
+    pub fn slices(&self) -> Vec<RenderSlice> {
+        let start = self.frames.start;
+        let len = self.frames.end.saturating_sub(start);
+        let count = self.slices as u64;
+        (0..count)
+            .map(|index| {
+                RenderSlice::new(
+                    index as u32,
+                    self.slices,
+                    // The lengths of the slices differ by at most one frame.
+                    start + index * len / count..start + (index + 1) * len / count,
+                )
+            })
+            .collect()
+    }
+
+    /// Renders every slice in a child process on this machine, at most `parallel` at a time, and
+    /// waits for all of them. The command of a slice is created with the given function, the slice
+    /// is added to its environment.
+    ///
+    /// Fails if a process can't be started or exits with an error. The remaining slices are still
+    /// rendered, so only the failed slices need to be rendered again.
+    pub fn run_local(
+        &self,
+        parallel: usize,
+        mut command: impl FnMut(&RenderSlice) -> Command,
+    ) -> Result<()> {
+        let mut pending = self.slices().into_iter();
+        let mut running = Vec::<(RenderSlice, Child)>::new();
+        let mut failed = Vec::new();
+
+        loop {
+            while running.len() < parallel.max(1) {
+                let Some(slice) = pending.next() else {
+                    break;
+                };
+                let mut command = command(&slice);
+                slice.apply(&mut command);
+                info!(
+                    "Rendering slice {} (frames {:?})",
+                    slice.label(),
+                    slice.frames
+                );
+                match command.stdin(Stdio::null()).spawn() {
+                    Ok(child) => running.push((slice, child)),
+                    Err(err) => failed.push(format!("{}: {}", slice.label(), err)),
+                }
+            }
+            if running.is_empty() {
+                break;
+            }
+
+            // Wait for the oldest process, the slices take about the same time.
+            let (slice, mut child) = running.remove(0);
+            match child.wait() {
+                Ok(status) if status.success() => {}
+                Ok(status) => failed.push(format!("{}: {}", slice.label(), status)),
+                Err(err) => failed.push(format!("{}: {}", slice.label(), err)),
+            }
+        }
+
+        match failed.is_empty() {
+            true => Ok(()),
+            false => Err(Error::external("render slice", failed.join(", "))),
+        }
+    }
+
+    /// Merges the frame directories of the slices into the directory at the given path. The
+    /// images and their sidecar files (`frame_{index}.*`) are moved and renumbered, so the index
+    /// is relative to the start of the job. The directories of the slices are removed if they are
+    /// empty afterwards.
+    pub fn merge_frames(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)?;
+        for slice in self.slices() {
+            let dir = slice.output_path(path);
+            let offset = slice.frames.start - self.frames.start;
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                let Some((index, extension)) = parse_frame_name(&name) else {
+                    continue;
+                };
+                let target = path.join(format!("frame_{:06}.{extension}", index + offset));
+                move_file(&entry.path(), &target)?;
+            }
+            // Other files are left in place.
+            let _ = fs::remove_dir(&dir);
+        }
+        Ok(())
+    }
+
+    /// Concatenates the videos of the slices into the video at the given path with ffmpeg,
+    /// without re-encoding. The videos of the slices are kept.
+    pub fn merge_videos(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut list = String::new();
+        for slice in self.slices() {
+            let input = fs::canonicalize(slice.output_path(path))?;
+            // Quotes are escaped for the concat demuxer.
+            let input = input.to_string_lossy().replace('\'', r"'\''");
+            list.push_str(&format!("file '{input}'\n"));
+        }
+        let list_path = slice_path(path, "slices", "txt");
+        fs::write(&list_path, list)?;
+
+        let output = Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error", "-y"])
+            .args(["-f", "concat", "-safe", "0", "-i"])
+            .arg(&list_path)
+            .args(["-c", "copy"])
+            .arg(path)
+            .stdin(Stdio::null())
+            .output();
+        let _ = fs::remove_file(&list_path);
+        let output = output.map_err(|err| Error::external("ffmpeg", err))?;
+        if !output.status.success() {
+            return Err(Error::external(
+                "ffmpeg",
+                format!(
+                    "{}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Concatenates the streams of the slices into the file at the given path, e.g. the output of
+    /// a [`RawEncoder`](crate::encoder::raw::RawEncoder) or a
+    /// [`Y4mEncoder`](crate::encoder::y4m::Y4mEncoder). The y4m header is only kept from the first
+    /// slice. The streams of the slices are kept.
+    pub fn merge_streams(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut output = BufWriter::new(File::create(path)?);
+        for (index, slice) in self.slices().into_iter().enumerate() {
+            let mut input = BufReader::new(File::open(slice.output_path(path))?);
+            if index > 0 && input.fill_buf()?.starts_with(b"YUV4MPEG2") {
+                input.read_until(b'\n', &mut Vec::new())?;
+            }
+            io::copy(&mut input, &mut output)?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+}
+
+/// The frames of a [`RenderJob`] rendered by one process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderSlice {
+    index: u32,
+    count: u32,
+    frames: Range<u64>,
+}
+
+impl RenderSlice {
+    pub(crate) fn new(index: u32, count: u32, frames: Range<u64>) -> Self {
+        Self {
+            index,
+            count,
+            frames,
+        }
+    }
+
+    /// Reads the slice of this process from the [`SLICE_ENV`] and [`FRAMES_ENV`] environment
+    /// variables. Returns `None` if they are not set or invalid, e.g. because the process was not
+    /// started by a [`RenderJob`].
+    pub fn from_env() -> Option<Self> {
+        let slice = env::var(SLICE_ENV).ok()?;
+        let frames = env::var(FRAMES_ENV).ok()?;
+        let (index, count) = slice.split_once('/')?;
+        let (start, end) = frames.split_once("..")?;
+        let slice = Self {
+            index: index.trim().parse().ok()?,
+            count: count.trim().parse().ok()?,
+            frames: start.trim().parse().ok()?..end.trim().parse().ok()?,
+        };
+        (slice.index < slice.count).then_some(slice)
+    }
+
+    /// Returns the index of the slice.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns the number of slices of the job.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Returns the frames of the slice, counted from the frame the capture was started in.
+    pub fn frames(&self) -> Range<u64> {
+        self.frames.clone()
+    }
+
+    /// Creates the [`CaptureRange`] that captures the frames of the slice. Attach it next to the
+    /// [`Capture`](crate::Capture).
+    pub fn capture_range(&self) -> CaptureRange {
+        CaptureRange::frames(self.frames.clone())
+    }
+
+    /// Returns the output path of the slice for the output path of the job, e.g.
+    /// `captures/video.slice_002.mp4` for `captures/video.mp4`. The merge functions of the
+    /// [`RenderJob`] expect the outputs at these paths.
+    pub fn output_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        slice_path(path, &format!("slice_{:03}", self.index), &extension)
+    }
+
+    /// Adds the slice to the environment of the command, to be read with
+    /// [`from_env`](Self::from_env).
+    pub fn apply(&self, command: &mut Command) {
+        command
+            .env(SLICE_ENV, format!("{}/{}", self.index, self.count))
+            .env(
+                FRAMES_ENV,
+                format!("{}..{}", self.frames.start, self.frames.end),
+            );
+    }
+
+    fn label(&self) -> String {
+        format!("{}/{}", self.index, self.count)
+    }
+}
+
+/// Returns the path with the suffix inserted before the extension.
+fn slice_path(path: &Path, suffix: &str, extension: &str) -> PathBuf {
+    let mut name = path.file_stem().map(OsString::from).unwrap_or_default();
+    name.push(format!(".{suffix}"));
+    if !extension.is_empty() {
+        name.push(format!(".{extension}"));
+    }
+    path.with_file_name(name)
+}
+
+/// Parses a file name like `frame_000042.png` into the index and the extension.
+fn parse_frame_name(name: &OsStr) -> Option<(u64, &str)> {
+    let (index, extension) = name.to_str()?.strip_prefix("frame_")?.split_once('.')?;
+    Some((index.parse().ok()?, extension))
+}
+
+/// Moves the file, or copies it if it is on another filesystem.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
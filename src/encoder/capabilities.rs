@@ -0,0 +1,270 @@
+//! Declare which frames an encoder accepts, so captures convert the frames up front or fail on the
+//! first frame with a clear error, instead of every encoder converting frames on its own or
+//! failing in the middle of a capture.
+//!
+//! Encoders return their [`EncoderCapabilities`] from
+//! [`Encoder::capabilities`](super::Encoder::capabilities). The encoders of a capture get frames
+//! that are converted to one of their formats and padded to even dimensions if required. Frames
+//! that can't be converted, exceed the maximum dimensions or change dimensions although the
+//! encoder doesn't support that are rejected with a [`Format`](Error::Format) error.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::{prelude::*, render::render_resource::TextureFormat};
+//! # use bevy_capture::encoder::{capabilities::EncoderCapabilities, Encoder, Result};
+//! #
+//! impl Encoder for MyBgraEncoder {
+//!     fn encode(&mut self, image: &Image) -> Result<()> {
+//!         // The frame is BGRA8 with even dimensions.
+//!         self.write(&image.data)
+//!     }
+//!
+//!     fn capabilities(&self) -> EncoderCapabilities {
+//!         EncoderCapabilities::new()
+//!             .with_formats([TextureFormat::Bgra8UnormSrgb])
+//!             .with_even_dimensions(true)
+//!     }
+//! }
+//! ```
+
+use super::{to_rgba8, Encoder, Error, OddDimensions, Result};
+use crate::{metadata::FrameMetadata, BoxedEncoder};
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use std::borrow::Cow;
+
+/// The 8 bit formats frames can be converted to, in the order of preference.
+const CONVERTIBLE_FORMATS: [TextureFormat; 4] = [
+    TextureFormat::Rgba8UnormSrgb,
+    TextureFormat::Rgba8Unorm,
+    TextureFormat::Bgra8UnormSrgb,
+    TextureFormat::Bgra8Unorm,
+];
+
+/// The frames an encoder accepts, see the [module docs](self). The default accepts all frames.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct EncoderCapabilities {
+    formats: Option<Vec<TextureFormat>>,
+    even_dimensions: bool,
+    fixed_dimensions: bool,
+    max_dimensions: Option<UVec2>,
+}
+
+impl EncoderCapabilities {
+    /// Creates capabilities that accept all frames.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates capabilities that accept RGBA8 frames only.
+    pub fn rgba8() -> Self {
+        Self::new().with_formats([TextureFormat::Rgba8UnormSrgb, TextureFormat::Rgba8Unorm])
+    }
+
+    /// Sets the texture formats the encoder accepts. Frames of other formats are converted to RGBA8
+    /// or BGRA8, if one of them is accepted.
+    pub fn with_formats(mut self, formats: impl IntoIterator<Item = TextureFormat>) -> Self {
+        self.formats = Some(formats.into_iter().collect());
+        self
+    }
+
+    /// Accepts frames of all formats again, e.g. for encoders that wrap other encoders and convert
+    /// the frames on their own.
+    pub fn with_all_formats(mut self) -> Self {
+        self.formats = None;
+        self
+    }
+
+    /// Sets whether the encoder requires even dimensions, e.g. for 4:2:0 chroma subsampling.
+    /// Frames with odd dimensions are padded by repeating the last column or row.
+    pub fn with_even_dimensions(mut self, even_dimensions: bool) -> Self {
+        self.even_dimensions = even_dimensions;
+        self
+    }
+
+    /// Sets whether all frames must have the dimensions of the first frame, e.g. because the
+    /// dimensions are written into a header.
+    pub fn with_fixed_dimensions(mut self, fixed_dimensions: bool) -> Self {
+        self.fixed_dimensions = fixed_dimensions;
+        self
+    }
+
+    /// Sets the maximum dimensions of the frames, e.g. the maximum resolution of a codec level.
+    pub fn with_max_dimensions(mut self, width: u32, height: u32) -> Self {
+        self.max_dimensions = Some(UVec2::new(width, height));
+        self
+    }
+
+    /// Returns the accepted texture formats, or `None` if all formats are accepted.
+    pub fn formats(&self) -> Option<&[TextureFormat]> {
+        self.formats.as_deref()
+    }
+
+    /// Returns `true` if the encoder requires even dimensions.
+    pub fn even_dimensions(&self) -> bool {
+        self.even_dimensions
+    }
+
+    /// Returns `true` if all frames must have the dimensions of the first frame.
+    pub fn fixed_dimensions(&self) -> bool {
+        self.fixed_dimensions
+    }
+
+    /// Returns the maximum dimensions of the frames, if any.
+    pub fn max_dimensions(&self) -> Option<UVec2> {
+        self.max_dimensions
+    }
+
+    /// Returns `true` if all frames are accepted as they are.
+    pub fn accepts_all(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns `true` if the format is accepted.
+    pub fn accepts_format(&self, format: TextureFormat) -> bool {
+        self.formats
+            .as_ref()
+            .is_none_or(|formats| formats.contains(&format))
+    }
+
+    /// Converts the frame to a format and dimensions the encoder accepts. Frames that are accepted
+    /// as they are are borrowed. Whether the dimensions changed since the first frame is not
+    /// checked, as that requires state.
+    ///
+    /// This can also be used by encoders that wrap other encoders, to pass frames the inner
+    /// encoder accepts.
+    pub fn convert<'a>(&self, image: &'a Image) -> Result<Cow<'a, Image>> {
+        let format = image.texture_descriptor.format;
+        let (width, height) = (image.width(), image.height());
+        let odd = self.even_dimensions && (width % 2 != 0 || height % 2 != 0);
+        let (even_width, even_height) = match odd {
+            true => OddDimensions::Pad.even(width, height),
+            false => (width, height),
+        };
+        if let Some(max) = self.max_dimensions {
+            if even_width > max.x || even_height > max.y {
+                return Err(Error::format(format!(
+                    "frames of {width}x{height} exceed the maximum dimensions of the encoder, {}x{}",
+                    max.x, max.y
+                )));
+            }
+        }
+
+        let accepted = self.accepts_format(format);
+        if accepted && !odd {
+            return Ok(Cow::Borrowed(image));
+        }
+
+        let unsupported = || {
+            Error::format(format!(
+                "frames of format {format:?} can't be converted to a format the encoder accepts \
+                 ({:?})",
+                self.formats.as_deref().unwrap_or_default()
+            ))
+        };
+        let target = if accepted && CONVERTIBLE_FORMATS.contains(&format) {
+            format
+        } else {
+            CONVERTIBLE_FORMATS
+                .into_iter()
+                .find(|&format| self.accepts_format(format))
+                .ok_or_else(unsupported)?
+        };
+        let rgba = to_rgba8(image).map_err(|_| unsupported())?;
+        let rgba = match odd {
+            true => OddDimensions::Pad.apply(rgba, width, height),
+            false => rgba,
+        };
+        let data = match target {
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => rgba
+                .chunks_exact(4)
+                .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+                .collect(),
+            _ => rgba.into_owned(),
+        };
+
+        Ok(Cow::Owned(Image::new(
+            Extent3d {
+                width: even_width,
+                height: even_height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            target,
+            RenderAssetUsages::default(),
+        )))
+    }
+}
+
+/// Wraps an encoder of a capture and passes it the frames converted according to its
+/// capabilities.
+pub(crate) struct CapabilityAdapter {
+    inner: BoxedEncoder,
+    capabilities: EncoderCapabilities,
+    dimensions: Option<(u32, u32)>,
+}
+
+impl CapabilityAdapter {
+    /// Wraps the encoder, unless it accepts all frames.
+    pub(crate) fn wrap(encoder: BoxedEncoder) -> BoxedEncoder {
+        let capabilities = encoder.capabilities();
+        if capabilities.accepts_all() {
+            return encoder;
+        }
+        Box::new(Self {
+            inner: encoder,
+            capabilities,
+            dimensions: None,
+        })
+    }
+}
+
+impl Encoder for CapabilityAdapter {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let dimensions = (image.width(), image.height());
+        if self.capabilities.fixed_dimensions {
+            match self.dimensions {
+                Some(first) if first != dimensions => {
+                    return Err(Error::format(format!(
+                        "the encoder doesn't support changing dimensions, from {}x{} to {}x{}",
+                        first.0, first.1, dimensions.0, dimensions.1
+                    )));
+                }
+                Some(_) => {}
+                None => self.dimensions = Some(dimensions),
+            }
+        }
+        let image = self.capabilities.convert(image)?;
+        self.inner.encode_with_metadata(&image, metadata)
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.capabilities.clone()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        self.inner.finish();
+    }
+}
@@ -0,0 +1,218 @@
+//! Replace the background of frames, e.g. to composite renders of characters or props.
+//!
+//! Render the scene in front of a key color (or with a transparent clear color) and wrap the
+//! encoder in a [`ChromaKeyEncoder`], which replaces the background with transparency, a color, an
+//! image or a sequence of images before passing the frames on.
+
+use super::{capabilities::EncoderCapabilities, to_rgba8, Encoder, Result};
+use crate::metadata::FrameMetadata;
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+/// How the background is detected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChromaKey {
+    /// Pixels close to the color are background. Pixels within `tolerance` (a distance in RGB
+    /// space, from `0.0` to `1.0`) are fully replaced, pixels within `tolerance + softness` are
+    /// blended, which smooths the edges.
+    Color {
+        /// The key color as sRGB.
+        color: [u8; 3],
+        /// The distance within which pixels are fully replaced.
+        tolerance: f32,
+        /// The distance over which the edges are blended.
+        softness: f32,
+    },
+    /// The alpha channel of the frame is used, e.g. with a transparent `ClearColor`.
+    Alpha,
+}
+
+impl ChromaKey {
+    /// A green screen key with a moderate tolerance.
+    pub const GREEN: Self = Self::Color {
+        color: [0, 255, 0],
+        tolerance: 0.3,
+        softness: 0.1,
+    };
+
+    /// A blue screen key with a moderate tolerance.
+    pub const BLUE: Self = Self::Color {
+        color: [0, 0, 255],
+        tolerance: 0.3,
+        softness: 0.1,
+    };
+
+    /// Returns the opacity of the foreground of the pixel, from `0.0` (background) to `1.0`.
+    fn opacity(&self, pixel: &[u8]) -> f32 {
+        match *self {
+            ChromaKey::Color {
+                color,
+                tolerance,
+                softness,
+            } => {
+                let distance = pixel
+                    .iter()
+                    .zip(color)
+                    .map(|(&a, b)| (a as f32 - b as f32) / 255.0)
+                    .map(|d| d * d)
+                    .sum::<f32>()
+                    .sqrt()
+                    / 3f32.sqrt();
+                if softness <= 0.0 {
+                    (distance > tolerance) as u8 as f32
+                } else {
+                    ((distance - tolerance) / softness).clamp(0.0, 1.0)
+                }
+            }
+            ChromaKey::Alpha => pixel[3] as f32 / 255.0,
+        }
+    }
+}
+
+/// What the background is replaced with.
+#[derive(Debug, Default, Clone)]
+pub enum ChromaBackground {
+    /// The background becomes transparent, for encoders that support alpha, e.g. PNG frames.
+    #[default]
+    Transparent,
+    /// A solid RGBA color.
+    Color([u8; 4]),
+    /// An image, scaled to the size of the frames.
+    Image(Image),
+    /// A sequence of images, e.g. the frames of a video, one per frame. The sequence is looped.
+    Frames(Vec<Image>),
+}
+
+/// An encoder that replaces the background of the frames before passing them to the inner
+/// encoder. The frames are converted to RGBA8.
+///
+/// # Example
+/// ```ignore
+/// # use bevy_capture::encoder::{chroma_key::*, frames::FramesEncoder};
+/// #
+/// let encoder = ChromaKeyEncoder::new(FramesEncoder::new("captures/character"), ChromaKey::GREEN)
+///     .with_background(ChromaBackground::Transparent);
+/// ```
+pub struct ChromaKeyEncoder<E> {
+    inner: E,
+    key: ChromaKey,
+    background: ChromaBackground,
+    frame: usize,
+}
+
+impl<E: Encoder> ChromaKeyEncoder<E> {
+    /// Creates a new chroma key encoder that passes the frames to the inner encoder.
+    pub fn new(inner: E, key: ChromaKey) -> Self {
+        Self {
+            inner,
+            key,
+            background: ChromaBackground::default(),
+            frame: 0,
+        }
+    }
+
+    /// Sets what the background is replaced with. Defaults to
+    /// [`ChromaBackground::Transparent`].
+    pub fn with_background(mut self, background: ChromaBackground) -> Self {
+        self.background = background;
+        self
+    }
+}
+
+impl<E: Encoder> Encoder for ChromaKeyEncoder<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let (width, height) = (image.width(), image.height());
+        let mut rgba = to_rgba8(image)?.into_owned();
+
+        let color = match self.background {
+            ChromaBackground::Color(color) => color,
+            _ => [0; 4],
+        };
+        let background = match &self.background {
+            ChromaBackground::Image(image) => Some(image),
+            ChromaBackground::Frames(frames) if !frames.is_empty() => {
+                Some(&frames[self.frame % frames.len()])
+            }
+            _ => None,
+        };
+        let background = background
+            .map(|image| to_rgba8(image).map(|pixels| (pixels, image.width(), image.height())))
+            .transpose()?;
+        self.frame += 1;
+
+        for (i, pixel) in rgba.chunks_exact_mut(4).enumerate() {
+            let opacity = self.key.opacity(pixel);
+            if opacity >= 1.0 {
+                continue;
+            }
+            let back: [u8; 4] = match &background {
+                Some((pixels, background_width, background_height)) => {
+                    // Nearest neighbor scaling to the size of the frame.
+                    let (x, y) = (i as u64 % width as u64, i as u64 / width as u64);
+                    let bx = x * *background_width as u64 / width as u64;
+                    let by = y * *background_height as u64 / height as u64;
+                    let offset = (by * *background_width as u64 + bx) as usize * 4;
+                    pixels[offset..offset + 4].try_into().unwrap()
+                }
+                None => color,
+            };
+
+            let back_alpha = back[3] as f32 / 255.0;
+            let alpha = opacity + back_alpha * (1.0 - opacity);
+            for channel in 0..3 {
+                let color = if alpha > 0.0 {
+                    (pixel[channel] as f32 * opacity
+                        + back[channel] as f32 * back_alpha * (1.0 - opacity))
+                        / alpha
+                } else {
+                    0.0
+                };
+                pixel[channel] = color.round() as u8;
+            }
+            pixel[3] = (alpha * 255.0).round() as u8;
+        }
+
+        let image = Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            rgba,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        let image = self.inner.capabilities().convert(&image)?;
+        self.inner.encode_with_metadata(&image, metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        Box::new(self.inner).finish();
+    }
+}
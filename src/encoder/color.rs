@@ -0,0 +1,432 @@
+//! Color spaces of YUV output and how they are signaled to players.
+//!
+//! Frames are converted from RGB to YUV by the video encoders. Players have to know which matrix
+//! and range were used to convert them back, otherwise the video looks washed out (full range
+//! interpreted as limited) or crushed (limited range interpreted as full).
+
+use super::{Error, Result};
+
+/// The matrix that is used to convert RGB to YUV.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorMatrix {
+    /// BT.601, the standard for SD video.
+    Bt601,
+    /// BT.709, the standard for HD video.
+    #[default]
+    Bt709,
+}
+
+/// The range of the YUV values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorRange {
+    /// Limited ("TV") range: 16-235 for luma and 16-240 for chroma. Supported by all players.
+    #[default]
+    Limited,
+    /// Full ("PC") range: 0-255.
+    Full,
+}
+
+/// The color space of YUV output, i.e. the matrix (and matching primaries and transfer
+/// characteristics) and the range.
+///
+/// Defaults to limited range BT.709.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ColorSpace {
+    /// The matrix.
+    pub matrix: ColorMatrix,
+    /// The range.
+    pub range: ColorRange,
+}
+
+impl ColorSpace {
+    /// Limited range BT.601.
+    pub const BT601: Self = Self {
+        matrix: ColorMatrix::Bt601,
+        range: ColorRange::Limited,
+    };
+
+    /// Limited range BT.709.
+    pub const BT709: Self = Self {
+        matrix: ColorMatrix::Bt709,
+        range: ColorRange::Limited,
+    };
+
+    /// Sets the range.
+    pub fn with_range(mut self, range: ColorRange) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Converts an RGB pixel to YUV.
+    pub fn rgb_to_yuv(&self, r: u8, g: u8, b: u8) -> [u8; 3] {
+        let (kr, kb) = match self.matrix {
+            ColorMatrix::Bt601 => (0.299, 0.114),
+            ColorMatrix::Bt709 => (0.2126, 0.0722),
+        };
+        let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+        let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+        let u = (b - y) / (2.0 * (1.0 - kb));
+        let v = (r - y) / (2.0 * (1.0 - kr));
+        let (y, u, v) = match self.range {
+            ColorRange::Limited => (16.0 + 219.0 * y, 128.0 + 224.0 * u, 128.0 + 224.0 * v),
+            ColorRange::Full => (255.0 * y, 128.0 + 255.0 * u, 128.0 + 255.0 * v),
+        };
+        [y.round() as u8, u.round() as u8, v.round() as u8]
+    }
+
+    /// The `colour_primaries`, `transfer_characteristics` and `matrix_coefficients` code points
+    /// (ITU-T H.273), as used in H.264/H.265 VUI parameters and MP4 `colr` boxes.
+    pub fn code_points(&self) -> [u8; 3] {
+        match self.matrix {
+            ColorMatrix::Bt601 => [6, 6, 6],
+            ColorMatrix::Bt709 => [1, 1, 1],
+        }
+    }
+
+    /// Writes the color space into the VUI parameters of an H.264 sequence parameter set (without
+    /// start code), adding the VUI parameters if necessary. Other VUI parameters are kept.
+    pub fn write_to_h264_sps(&self, sps: &[u8]) -> Result<Vec<u8>> {
+        self.write_to_h264_sps_with_sample_aspect_ratio(sps, None)
+    }
+
+    /// Like [`write_to_h264_sps`](Self::write_to_h264_sps), but also replaces the sample aspect
+    /// ratio (width and height of a pixel) if one is given.
+    pub fn write_to_h264_sps_with_sample_aspect_ratio(
+        &self,
+        sps: &[u8],
+        sample_aspect_ratio: Option<[u16; 2]>,
+    ) -> Result<Vec<u8>> {
+        let (&header, payload) = sps
+            .split_first()
+            .ok_or_else(|| Error::format("empty sps"))?;
+        if header & 0x1f != 7 {
+            return Err(Error::format("not an sps nal unit"));
+        }
+
+        let rbsp = remove_emulation_prevention(payload);
+        let mut reader = BitReader::new(&rbsp);
+        skip_sps_until_vui(&mut reader)?;
+
+        let mut writer = BitWriter::default();
+        writer.copy(&rbsp, 0, reader.position);
+        writer.write(1, 1);
+        if reader.read(1)? == 1 {
+            // aspect_ratio_info_present_flag
+            let start = reader.position;
+            if reader.read(1)? == 1 && reader.read(8)? == 255 {
+                reader.skip(32)?;
+            }
+            match sample_aspect_ratio {
+                Some(_) => write_aspect_ratio_info(&mut writer, sample_aspect_ratio),
+                None => writer.copy(&rbsp, start, reader.position),
+            }
+
+            // overscan_info_present_flag
+            let start = reader.position;
+            if reader.read(1)? == 1 {
+                reader.skip(1)?;
+            }
+            writer.copy(&rbsp, start, reader.position);
+
+            // Replace video_signal_type_present_flag and the video signal type.
+            if reader.read(1)? == 1 {
+                reader.skip(4)?;
+                if reader.read(1)? == 1 {
+                    reader.skip(24)?;
+                }
+            }
+            self.write_video_signal_type(&mut writer);
+
+            // Everything after the video signal type is kept, up to the rbsp trailing bits.
+            let end = rbsp_trailing_bits_position(&rbsp)?;
+            if end < reader.position {
+                return Err(Error::format("truncated sps"));
+            }
+            writer.copy(&rbsp, reader.position, end);
+        } else {
+            write_aspect_ratio_info(&mut writer, sample_aspect_ratio);
+            // overscan_info_present_flag
+            writer.write(0, 1);
+            self.write_video_signal_type(&mut writer);
+            // chroma_loc_info, timing_info, nal_hrd, vcl_hrd, pic_struct, bitstream_restriction
+            writer.write(0, 6);
+        }
+        writer.write(1, 1);
+        writer.align();
+
+        let mut output = vec![header];
+        output.extend(add_emulation_prevention(&writer.bytes));
+        Ok(output)
+    }
+
+    fn write_video_signal_type(&self, writer: &mut BitWriter) {
+        let [primaries, transfer, matrix] = self.code_points();
+        writer.write(1, 1);
+        // video_format: unspecified
+        writer.write(5, 3);
+        writer.write((self.range == ColorRange::Full) as u32, 1);
+        writer.write(1, 1);
+        writer.write(primaries as u32, 8);
+        writer.write(transfer as u32, 8);
+        writer.write(matrix as u32, 8);
+    }
+}
+
+fn write_aspect_ratio_info(writer: &mut BitWriter, sample_aspect_ratio: Option<[u16; 2]>) {
+    match sample_aspect_ratio {
+        Some([width, height]) => {
+            writer.write(1, 1);
+            // aspect_ratio_idc: Extended_SAR
+            writer.write(255, 8);
+            writer.write(width as u32, 16);
+            writer.write(height as u32, 16);
+        }
+        None => writer.write(0, 1),
+    }
+}
+
+/// Converts an RGB pixel to limited range BT.601 YUV.
+pub(crate) fn rgb_to_yuv(r: u8, g: u8, b: u8) -> [u8; 3] {
+    ColorSpace::BT601.rgb_to_yuv(r, g, b)
+}
+
+fn skip_sps_until_vui(reader: &mut BitReader) -> Result<()> {
+    let profile_idc = reader.read(8)?;
+    // constraint_set flags, reserved bits and level_idc
+    reader.skip(16)?;
+    // seq_parameter_set_id
+    reader.read_ue()?;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        let chroma_format_idc = reader.read_ue()?;
+        if chroma_format_idc == 3 {
+            reader.skip(1)?;
+        }
+        // bit_depth_luma_minus8, bit_depth_chroma_minus8
+        reader.read_ue()?;
+        reader.read_ue()?;
+        reader.skip(1)?;
+        if reader.read(1)? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if reader.read(1)? == 1 {
+                    skip_scaling_list(reader, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+    // log2_max_frame_num_minus4
+    reader.read_ue()?;
+    match reader.read_ue()? {
+        0 => {
+            reader.read_ue()?;
+        }
+        1 => {
+            reader.skip(1)?;
+            reader.read_ue()?;
+            reader.read_ue()?;
+            for _ in 0..reader.read_ue()? {
+                reader.read_ue()?;
+            }
+        }
+        _ => {}
+    }
+    // max_num_ref_frames, gaps_in_frame_num_value_allowed_flag
+    reader.read_ue()?;
+    reader.skip(1)?;
+    // pic_width_in_mbs_minus1, pic_height_in_map_units_minus1
+    reader.read_ue()?;
+    reader.read_ue()?;
+    if reader.read(1)? == 0 {
+        reader.skip(1)?;
+    }
+    reader.skip(1)?;
+    if reader.read(1)? == 1 {
+        for _ in 0..4 {
+            reader.read_ue()?;
+        }
+    }
+    Ok(())
+}
+
+fn skip_scaling_list(reader: &mut BitReader, size: usize) -> Result<()> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta_scale = reader.read_se()?;
+            next_scale = (last_scale + delta_scale + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Ok(())
+}
+
+fn rbsp_trailing_bits_position(rbsp: &[u8]) -> Result<usize> {
+    let (index, byte) = rbsp
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|(_, &byte)| byte != 0)
+        .ok_or_else(|| Error::format("missing rbsp trailing bits"))?;
+    Ok(index * 8 + 7 - byte.trailing_zeros() as usize)
+}
+
+fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+fn add_emulation_prevention(rbsp: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(rbsp.len() + rbsp.len() / 64);
+    let mut zeros = 0;
+    for &byte in rbsp {
+        if zeros >= 2 && byte <= 3 {
+            data.push(3);
+            zeros = 0;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        data.push(byte);
+    }
+    data
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read(&mut self, bits: usize) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self
+                .data
+                .get(self.position / 8)
+                .ok_or_else(|| Error::format("truncated sps"))?;
+            value = (value << 1) | ((byte >> (7 - self.position % 8)) & 1) as u32;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn skip(&mut self, bits: usize) -> Result<()> {
+        self.read(bits).map(|_| ())
+    }
+
+    fn read_ue(&mut self) -> Result<u32> {
+        let mut zeros = 0;
+        while self.read(1)? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return Err(Error::format("invalid exp-golomb code in sps"));
+            }
+        }
+        Ok((1 << zeros) - 1 + self.read(zeros)?)
+    }
+
+    fn read_se(&mut self) -> Result<i32> {
+        let value = self.read_ue()? as i32;
+        Ok(if value % 2 == 1 {
+            (value + 1) / 2
+        } else {
+            -(value / 2)
+        })
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.bits % 8);
+            self.bits += 1;
+        }
+    }
+
+    fn copy(&mut self, data: &[u8], start: usize, end: usize) {
+        for position in start..end {
+            self.write(((data[position / 8] >> (7 - position % 8)) & 1) as u32, 1);
+        }
+    }
+
+    fn align(&mut self) {
+        self.bits = self.bytes.len() * 8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signals_color_space_in_sps() {
+        fn bits(bits: &str) -> Vec<u8> {
+            let bits = bits.replace(' ', "");
+            let mut bytes = vec![0x67];
+            for chunk in bits.as_bytes().chunks(8) {
+                let byte = chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |byte, (i, bit)| byte | ((bit - b'0') << (7 - i)));
+                bytes.push(byte);
+            }
+            bytes
+        }
+
+        // Baseline profile, 64x32, no VUI.
+        let sps_without_vui = "01000010 00000000 00011110 1 1 011 010 0 00100 010 1 1 0";
+        let sps = bits(&format!("{sps_without_vui} 0 1"));
+
+        let bt709 = ColorSpace::BT709.write_to_h264_sps(&sps).unwrap();
+        let vui = "1 0 0 1 101 0 1 00000001 00000001 00000001 000000";
+        assert_eq!(bt709, bits(&format!("{sps_without_vui} {vui} 1")));
+
+        // Existing VUI parameters are replaced.
+        let full = ColorSpace::BT601.with_range(ColorRange::Full);
+        assert_eq!(
+            full.write_to_h264_sps(&bt709).unwrap(),
+            full.write_to_h264_sps(&sps).unwrap()
+        );
+
+        // The sample aspect ratio is added or replaced.
+        let sar = "1 11111111 0000000000000011 0000000000000010 0";
+        let vui = "1 101 0 1 00000001 00000001 00000001 000000";
+        let expected = bits(&format!("{sps_without_vui} 1 {sar} {vui} 1"));
+        for sps in [&sps, &bt709] {
+            let sps =
+                ColorSpace::BT709.write_to_h264_sps_with_sample_aspect_ratio(sps, Some([3, 2]));
+            assert_eq!(sps.unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Compute the regions that changed since the previous frame, so encoders of screen-capture style
+//! content, e.g. mostly static UIs, can encode only the changed regions.
+//!
+//! A [`DirtyRects`] compares every frame with the previous frame in tiles, merges the changed
+//! tiles into rectangles and passes them to the inner encoder in the [metadata](crate::metadata) of
+//! the frame, see [`DIRTY_RECTS_KEY`]. Encoders read them with [`from_metadata`]. The
+//! [`GifEncoder`](super::gif::GifEncoder) encodes only the bounding box of the changed regions as a
+//! sub-frame, which shrinks gifs of mostly static screens dramatically.
+//!
+//! The first frame, and frames whose dimensions or format changed, are dirty as a whole. A frame
+//! without changes has no dirty rectangles.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::{dirty::DirtyRects, gif::GifEncoder};
+//! # use std::fs::File;
+//! #
+//! let encoder = DirtyRects::new(GifEncoder::new(File::create("editor.gif")?)).with_tile_size(8);
+//!
+//! // In a custom encoder:
+//! if let Some(rects) = bevy_capture::encoder::dirty::from_metadata(metadata) {
+//!     for rect in rects {
+//!         self.write_region(image, rect)?;
+//!     }
+//! }
+//! ```
+
+use super::{capabilities::EncoderCapabilities, Encoder, Result};
+use crate::metadata::{FrameMetadata, MetadataValue};
+use bevy::{math::URect, prelude::*, render::texture::TextureFormatPixelInfo};
+use std::fmt::Write;
+
+/// The metadata key of the regions that changed since the previous frame, set by [`DirtyRects`].
+/// The value is a string of rectangles separated by `;`, each given as `min_x,min_y,max_x,max_y`
+/// in pixels with exclusive maximum, e.g. `"0,0,16,16;32,0,48,8"`. An empty string means that
+/// nothing changed.
+pub const DIRTY_RECTS_KEY: &str = "dirty_rects";
+
+/// The default size of the tiles frames are compared in, in pixels per side.
+pub const DEFAULT_TILE_SIZE: u32 = 16;
+
+/// An encoder that passes the regions that changed since the previous frame to the inner encoder,
+/// see the [module docs](self).
+pub struct DirtyRects<E> {
+    inner: E,
+    tile_size: u32,
+    previous: Option<Image>,
+}
+
+impl<E: Encoder> DirtyRects<E> {
+    /// Creates a new encoder that passes the frames to the inner encoder together with their dirty
+    /// rectangles.
+    pub fn new(inner: E) -> Self {
+        Self {
+            inner,
+            tile_size: DEFAULT_TILE_SIZE,
+            previous: None,
+        }
+    }
+
+    /// Sets the size of the tiles frames are compared in. Smaller tiles find smaller regions, but
+    /// result in more rectangles. Defaults to [`DEFAULT_TILE_SIZE`].
+    pub fn with_tile_size(mut self, tile_size: u32) -> Self {
+        self.tile_size = tile_size.max(1);
+        self
+    }
+
+    /// Returns the size of the tiles frames are compared in.
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+}
+
+impl<E: Encoder> Encoder for DirtyRects<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let rects = self
+            .previous
+            .as_ref()
+            .and_then(|previous| dirty_rects(previous, image, self.tile_size))
+            .unwrap_or_else(|| vec![URect::new(0, 0, image.width(), image.height())]);
+        self.previous = Some(image.clone());
+
+        let mut metadata = metadata.clone();
+        metadata.insert(DIRTY_RECTS_KEY, to_string(&rects));
+        self.inner.encode_with_metadata(image, &metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        Box::new(self.inner).finish();
+    }
+}
+
+/// Returns the regions of `current` that differ from `previous`, compared in tiles of the given
+/// size. Returns `None` if the images have different dimensions or formats, i.e. if the whole
+/// frame changed.
+pub fn dirty_rects(previous: &Image, current: &Image, tile_size: u32) -> Option<Vec<URect>> {
+    let format = current.texture_descriptor.format;
+    if previous.size() != current.size()
+        || previous.texture_descriptor.format != format
+        || previous.data.len() != current.data.len()
+    {
+        return None;
+    }
+
+    let (width, height) = (current.width(), current.height());
+    let tile_size = tile_size.max(1);
+    let pixel_size = format.pixel_size();
+    let row_bytes = width as usize * pixel_size;
+    let is_dirty = |column: u32, row: u32| {
+        let x = (column * tile_size) as usize * pixel_size;
+        let tile_bytes = (tile_size.min(width - column * tile_size)) as usize * pixel_size;
+        (row * tile_size..((row + 1) * tile_size).min(height)).any(|y| {
+            let offset = y as usize * row_bytes + x;
+            previous.data[offset..offset + tile_bytes] != current.data[offset..offset + tile_bytes]
+        })
+    };
+
+    // Runs of dirty tiles in a row are merged into rectangles, which are extended downwards while
+    // the next row has a run with the same columns.
+    let (columns, rows) = (width.div_ceil(tile_size), height.div_ceil(tile_size));
+    let mut rects = Vec::<URect>::new();
+    let mut open = Vec::new();
+    for row in 0..rows {
+        let mut next_open = Vec::new();
+        let mut column = 0;
+        while column < columns {
+            if !is_dirty(column, row) {
+                column += 1;
+                continue;
+            }
+            let start = column;
+            while column < columns && is_dirty(column, row) {
+                column += 1;
+            }
+            let extended = open
+                .iter()
+                .copied()
+                .find(|&i: &usize| rects[i].min.x == start && rects[i].max.x == column);
+            match extended {
+                Some(i) => {
+                    rects[i].max.y = row + 1;
+                    next_open.push(i);
+                }
+                None => {
+                    rects.push(URect::new(start, row, column, row + 1));
+                    next_open.push(rects.len() - 1);
+                }
+            }
+        }
+        open = next_open;
+    }
+
+    let rects = rects
+        .into_iter()
+        .map(|rect| {
+            URect::new(
+                rect.min.x * tile_size,
+                rect.min.y * tile_size,
+                (rect.max.x * tile_size).min(width),
+                (rect.max.y * tile_size).min(height),
+            )
+        })
+        .collect();
+    Some(rects)
+}
+
+/// Returns the dirty rectangles of the frame, set by a [`DirtyRects`], or `None` if the frame has
+/// none, i.e. if the whole frame must be encoded.
+pub fn from_metadata(metadata: &FrameMetadata) -> Option<Vec<URect>> {
+    let Some(MetadataValue::String(rects)) = metadata.get(DIRTY_RECTS_KEY) else {
+        return None;
+    };
+    rects
+        .split(';')
+        .filter(|rect| !rect.is_empty())
+        .map(|rect| {
+            let mut values = rect
+                .split(',')
+                .map(|value| value.trim().parse::<u32>().ok());
+            let mut next = || values.next().flatten();
+            Some(URect::new(next()?, next()?, next()?, next()?))
+        })
+        .collect()
+}
+
+/// Returns the bounding box of the rectangles, or `None` if there are none.
+pub fn bounds(rects: &[URect]) -> Option<URect> {
+    rects.iter().copied().reduce(|a, b| a.union(b))
+}
+
+fn to_string(rects: &[URect]) -> String {
+    let mut string = String::new();
+    for (i, rect) in rects.iter().enumerate() {
+        if i > 0 {
+            string.push(';');
+        }
+        write!(
+            string,
+            "{},{},{},{}",
+            rect.min.x, rect.min.y, rect.max.x, rect.max.y
+        )
+        .unwrap();
+    }
+    string
+}
@@ -0,0 +1,100 @@
+//! The error type of the encoders.
+
+use std::io;
+
+/// A boxed error, e.g. of a custom encoder.
+pub type BoxedError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// An error that occurred during encoding.
+///
+/// The variants are categories that callers can match on, e.g. to retry on [`Io`](Self::Io)
+/// errors but abort on [`Codec`](Self::Codec) errors.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing a file, pipe, socket or device failed.
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    /// Encoding the pixels failed, e.g. in the image or video codec.
+    #[error("codec error: {0}")]
+    Codec(#[source] BoxedError),
+    /// The frame has a format, size or layout the encoder doesn't support.
+    #[error("unsupported format: {0}")]
+    Format(String),
+    /// Writing the container around the encoded frames failed, e.g. the mp4 boxes.
+    #[error("mux error: {0}")]
+    Mux(#[source] BoxedError),
+    /// An external process or service failed.
+    #[error("{name} failed: {status}")]
+    External {
+        /// The name of the process or service.
+        name: String,
+        /// The exit status or response of the process or service.
+        status: String,
+    },
+    /// Any other error.
+    #[error("{0}")]
+    Other(String),
+    /// A custom error, e.g. of an encoder outside of this crate.
+    #[error(transparent)]
+    Custom(BoxedError),
+}
+
+impl Error {
+    /// Creates a [`Codec`](Self::Codec) error.
+    pub fn codec(err: impl Into<BoxedError>) -> Self {
+        Self::Codec(err.into())
+    }
+
+    /// Creates a [`Format`](Self::Format) error.
+    pub fn format(message: impl Into<String>) -> Self {
+        Self::Format(message.into())
+    }
+
+    /// Creates a [`Mux`](Self::Mux) error.
+    pub fn mux(err: impl Into<BoxedError>) -> Self {
+        Self::Mux(err.into())
+    }
+
+    /// Creates an [`External`](Self::External) error.
+    pub fn external(name: impl Into<String>, status: impl ToString) -> Self {
+        Self::External {
+            name: name.into(),
+            status: status.to_string(),
+        }
+    }
+
+    /// Creates a [`Custom`](Self::Custom) error.
+    pub fn custom(err: impl Into<BoxedError>) -> Self {
+        Self::Custom(err.into())
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Self::Other(message.to_string())
+    }
+}
+
+impl From<BoxedError> for Error {
+    fn from(err: BoxedError) -> Self {
+        Self::Custom(err)
+    }
+}
+
+#[cfg(feature = "image")]
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        match err {
+            image::ImageError::IoError(err) => Self::Io(err),
+            image::ImageError::Unsupported(err) => Self::Format(err.to_string()),
+            err => Self::Codec(err.into()),
+        }
+    }
+}
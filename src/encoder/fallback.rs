@@ -0,0 +1,140 @@
+//! Choose the first encoder that can be created from a chain of candidates, e.g. ffmpeg if it is
+//! installed and OpenH264 otherwise, so one binary works on machines with and without ffmpeg.
+
+use super::{capabilities::EncoderCapabilities, Encoder, Error, Result};
+use crate::metadata::FrameMetadata;
+use bevy::prelude::*;
+
+type Factory = Box<dyn FnOnce(u32, u32) -> Result<BoxedEncoder> + Send + Sync + 'static>;
+
+type BoxedEncoder = Box<dyn Encoder + Send + Sync + 'static>;
+
+/// An encoder that creates the first candidate encoder that can be created, once the dimensions of
+/// the frames are known, and passes all frames to it.
+///
+/// The candidates are created with the dimensions of the first frame, in the order they were
+/// added. A candidate that fails to be created, e.g. because a tool is missing, is skipped with a
+/// warning. If no candidate can be created, encoding the first frame fails.
+///
+/// # Example
+/// ```ignore
+/// # use bevy_capture::encoder::{
+/// #     fallback::FallbackEncoder, mp4_ffmpeg_cli::Mp4FfmpegCliEncoder,
+/// #     mp4_openh264::Mp4Openh264Encoder,
+/// # };
+/// # use std::fs::File;
+/// #
+/// let encoder = FallbackEncoder::new()
+///     .with_candidate("ffmpeg", |_, _| Mp4FfmpegCliEncoder::new("capture.mp4")?.checked())
+///     .with_candidate("openh264", |width, height| {
+///         Mp4Openh264Encoder::new(File::create("capture.mp4")?, width as u16, height as u16)
+///     });
+/// ```
+#[derive(Default)]
+pub struct FallbackEncoder {
+    candidates: Vec<(String, Factory)>,
+    selected: Option<(String, BoxedEncoder)>,
+}
+
+impl FallbackEncoder {
+    /// Creates a new fallback encoder without any candidates, see
+    /// [`with_candidate`](Self::with_candidate).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a candidate. The factory is called with the width and height of the first frame, if
+    /// all previous candidates failed to be created.
+    pub fn with_candidate<E: Encoder + Send + Sync + 'static>(
+        mut self,
+        name: impl Into<String>,
+        factory: impl FnOnce(u32, u32) -> Result<E> + Send + Sync + 'static,
+    ) -> Self {
+        self.candidates.push((
+            name.into(),
+            Box::new(move |width, height| {
+                factory(width, height).map(|encoder| Box::new(encoder) as BoxedEncoder)
+            }),
+        ));
+        self
+    }
+
+    /// Returns the name of the candidate that is used, once the first frame was encoded.
+    pub fn selected(&self) -> Option<&str> {
+        self.selected.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    fn select(&mut self, width: u32, height: u32) -> Result<&mut BoxedEncoder> {
+        if self.selected.is_none() {
+            let mut failures = Vec::new();
+            for (name, factory) in self.candidates.drain(..) {
+                match factory(width, height) {
+                    Ok(encoder) => {
+                        info!("Encoding with {}", name);
+                        self.selected = Some((name, encoder));
+                        break;
+                    }
+                    Err(err) => {
+                        warn!("Skipping the encoder {}: {}", name, err);
+                        failures.push(format!("{name}: {err}"));
+                    }
+                }
+            }
+            if self.selected.is_none() {
+                return Err(Error::Other(format!(
+                    "no encoder could be created ({})",
+                    failures.join(", ")
+                )));
+            }
+        }
+        Ok(&mut self.selected.as_mut().unwrap().1)
+    }
+}
+
+impl Encoder for FallbackEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        // The candidate is selected on the first frame, after the capabilities were queried, so
+        // the frames are converted for it here.
+        let encoder = self.select(image.width(), image.height())?;
+        let image = encoder.capabilities().convert(image)?;
+        encoder.encode_with_metadata(&image, metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.selected {
+            Some((_, encoder)) => encoder.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        match &self.selected {
+            Some((_, encoder)) => encoder.capabilities(),
+            None => EncoderCapabilities::default(),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match &self.selected {
+            Some((name, _)) => name,
+            None => "fallback",
+        }
+    }
+
+    fn describe_output(&self) -> String {
+        match &self.selected {
+            Some((_, encoder)) => encoder.describe_output(),
+            None => String::new(),
+        }
+    }
+
+    fn finish(self: Box<Self>) {
+        if let Some((_, encoder)) = self.selected {
+            encoder.finish();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Move the `moov` atom of an MP4 file to the front ("faststart").
+//!
+//! MP4 muxers usually write the sample data (`mdat`) first and the index (`moov`) last, since the
+//! index is only known once all samples are written. Players that stream the file from a web
+//! server need the index first, so they would have to download the whole file before playback can
+//! start. [`faststart`] rewrites a finished file so the index comes first.
+
+use super::{Error, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Moves the `moov` atom of the given MP4 file in front of the `mdat` atom and updates the chunk
+/// offsets (`stco`/`co64`) accordingly. Files that already have the `moov` atom in front are left
+/// unchanged.
+///
+/// The file is read into memory and rewritten in place, its length does not change.
+pub fn faststart<F: Read + Write + Seek>(file: &mut F) -> Result<()> {
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut data)?;
+
+    let atoms = atoms(&data)?;
+    let find = |name: &[u8; 4]| {
+        atoms
+            .iter()
+            .find(|atom| &atom.name == name)
+            .ok_or_else(|| Error::format(format!("missing {} atom", String::from_utf8_lossy(name))))
+    };
+    let moov = find(b"moov")?;
+    let mdat = find(b"mdat")?;
+    if moov.start < mdat.start {
+        return Ok(());
+    }
+
+    let mut moov_data = data[moov.start..moov.end].to_vec();
+    shift_chunk_offsets(
+        &mut moov_data[moov.header..],
+        (moov.end - moov.start) as u64,
+    )?;
+
+    // Everything between the start of the mdat atom and the moov atom moves back.
+    file.seek(SeekFrom::Start(mdat.start as u64))?;
+    file.write_all(&moov_data)?;
+    file.write_all(&data[mdat.start..moov.start])?;
+    file.write_all(&data[moov.end..])?;
+    file.flush()?;
+
+    Ok(())
+}
+
+struct Atom {
+    name: [u8; 4],
+    start: usize,
+    end: usize,
+    header: usize,
+}
+
+fn atoms(data: &[u8]) -> Result<Vec<Atom>> {
+    let mut atoms = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let header = data
+            .get(start..start + 8)
+            .ok_or_else(|| Error::format("truncated atom header"))?;
+        let name = header[4..8].try_into().unwrap();
+        let (size, header) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // The atom extends to the end of the file.
+            0 => (data.len() - start, 8),
+            1 => {
+                let size = data
+                    .get(start + 8..start + 16)
+                    .ok_or_else(|| Error::format("truncated atom header"))?;
+                (u64::from_be_bytes(size.try_into().unwrap()) as usize, 16)
+            }
+            size => (size as usize, 8),
+        };
+        if size < header || start + size > data.len() {
+            return Err(Error::format("invalid atom size"));
+        }
+        atoms.push(Atom {
+            name,
+            start,
+            end: start + size,
+            header,
+        });
+        start += size;
+    }
+    Ok(atoms)
+}
+
+fn shift_chunk_offsets(data: &mut [u8], shift: u64) -> Result<()> {
+    for atom in atoms(data)? {
+        let body = &mut data[atom.start + atom.header..atom.end];
+        match &atom.name {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => shift_chunk_offsets(body, shift)?,
+            b"stco" => {
+                for entry in entries(body, 4)? {
+                    let offset = u32::from_be_bytes(entry.try_into().unwrap()) as u64 + shift;
+                    let offset = u32::try_from(offset)
+                        .map_err(|_| Error::format("chunk offset does not fit into stco"))?;
+                    entry.copy_from_slice(&offset.to_be_bytes());
+                }
+            }
+            b"co64" => {
+                for entry in entries(body, 8)? {
+                    let offset = u64::from_be_bytes(entry.try_into().unwrap()) + shift;
+                    entry.copy_from_slice(&offset.to_be_bytes());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Returns the entries of a full atom with an entry count, e.g. `stco`.
+fn entries(body: &mut [u8], size: usize) -> Result<impl Iterator<Item = &mut [u8]>> {
+    let count = body
+        .get(4..8)
+        .map(|count| u32::from_be_bytes(count.try_into().unwrap()) as usize)
+        .ok_or_else(|| Error::format("truncated chunk offset atom"))?;
+    body.get_mut(8..8 + count * size)
+        .map(|entries| entries.chunks_exact_mut(size))
+        .ok_or_else(|| Error::format("truncated chunk offset atom"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_moov_to_front() {
+        fn atom(name: &[u8; 4], body: &[u8]) -> Vec<u8> {
+            let mut atom = (8 + body.len() as u32).to_be_bytes().to_vec();
+            atom.extend_from_slice(name);
+            atom.extend_from_slice(body);
+            atom
+        }
+
+        let ftyp = atom(b"ftyp", b"isom\0\0\x02\0");
+        let mdat = atom(b"mdat", b"samples");
+        let sample_offset = (ftyp.len() + 8) as u32;
+        let stco = atom(
+            b"stco",
+            &[
+                &[0; 4][..],
+                &1u32.to_be_bytes(),
+                &sample_offset.to_be_bytes(),
+            ]
+            .concat(),
+        );
+        let moov = atom(
+            b"moov",
+            &atom(
+                b"trak",
+                &atom(b"mdia", &atom(b"minf", &atom(b"stbl", &stco))),
+            ),
+        );
+
+        let mut file = std::io::Cursor::new([&ftyp[..], &mdat, &moov].concat());
+        faststart(&mut file).unwrap();
+        let file = file.into_inner();
+
+        assert_eq!(&file[ftyp.len() + 4..ftyp.len() + 8], b"moov");
+        let offset = sample_offset as usize + moov.len();
+        assert_eq!(&file[offset..offset + 7], b"samples");
+        let stco_entry = ftyp.len() + moov.len() - 4;
+        assert_eq!(
+            u32::from_be_bytes(file[stco_entry..stco_entry + 4].try_into().unwrap()) as usize,
+            offset
+        );
+    }
+}
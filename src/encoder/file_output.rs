@@ -0,0 +1,410 @@
+//! Tune how encoders that write a file per frame write their files, since at high frame rates the
+//! filesystem overhead limits the throughput more than the encoding.
+//!
+//! A [`FileOutput`] configures the size of the write buffer, direct I/O (`O_DIRECT`, Linux only),
+//! which bypasses the page cache so dumping frames doesn't evict everything else from it, and
+//! background writes, which move the writes to a dedicated thread so encoding the next frame
+//! doesn't wait for the disk.
+//!
+//! io_uring is not used. The writes of a frame are few and large, so submitting them
+//! asynchronously gains little over background writes, which take them off the encoding thread
+//! on every platform without another dependency.
+//!
+//! It is used by the [`FramesEncoder`](super::frames::FramesEncoder) and the
+//! [`UncompressedFramesEncoder`](super::uncompressed::UncompressedFramesEncoder).
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::{file_output::FileOutput, uncompressed::*};
+//! #
+//! let encoder = UncompressedFramesEncoder::new("captures/frames", UncompressedFormat::Tga)
+//!     .with_output(
+//!         FileOutput::new()
+//!             .with_buffer_size(16 << 20)
+//!             .with_direct_io(true)
+//!             .with_background_writes(8),
+//!     );
+//! ```
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+};
+
+/// The block size direct writes are aligned to.
+const BLOCK_SIZE: usize = 4096;
+
+/// How an encoder writes its files, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct FileOutput {
+    buffer_size: usize,
+    direct_io: bool,
+    background_queue: Option<usize>,
+}
+
+impl Default for FileOutput {
+    fn default() -> Self {
+        Self {
+            buffer_size: 1 << 20,
+            direct_io: false,
+            background_queue: None,
+        }
+    }
+}
+
+impl FileOutput {
+    /// Creates the default output, writing with a 1 MiB buffer on the encoding thread.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size of the write buffer in bytes. Defaults to 1 MiB. With direct I/O, it is
+    /// rounded up to a multiple of 4 KiB.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    /// Sets whether files are written with direct I/O (`O_DIRECT`), bypassing the page cache.
+    /// Only supported on Linux and by some filesystems, otherwise files are written as usual.
+    pub fn with_direct_io(mut self, direct_io: bool) -> Self {
+        self.direct_io = direct_io;
+        self
+    }
+
+    /// Writes the files on a dedicated thread. At most `queue` files wait to be written, further
+    /// writes block until there is space, so memory stays bounded if the disk is too slow.
+    pub fn with_background_writes(mut self, queue: usize) -> Self {
+        self.background_queue = Some(queue.max(1));
+        self
+    }
+
+    /// Returns the size of the write buffer in bytes.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Returns `true` if files are written with direct I/O, where supported.
+    pub fn direct_io(&self) -> bool {
+        self.direct_io
+    }
+
+    /// Returns the number of files that can wait to be written on the background thread, or
+    /// `None` if files are written on the encoding thread.
+    pub fn background_queue(&self) -> Option<usize> {
+        self.background_queue
+    }
+
+    /// Creates a writer with these settings. With background writes, this spawns the thread.
+    pub fn writer(&self) -> FileWriter {
+        let sink = FileSink {
+            output: self.clone(),
+            buffer: None,
+        };
+        let Some(queue) = self.background_queue else {
+            return FileWriter {
+                inner: WriterInner::Inline(sink),
+            };
+        };
+
+        let (sender, receiver) = crossbeam_channel::bounded::<FileJob>(queue);
+        let (errors_sender, errors) = crossbeam_channel::unbounded();
+        let thread = thread::Builder::new()
+            .name("capture file writer".to_owned())
+            .spawn(move || {
+                let mut sink = sink;
+                for job in receiver {
+                    match job {
+                        FileJob::Write(path, data) => {
+                            if let Err(err) = sink.write(&path, &[&data]) {
+                                let _ = errors_sender.send(err);
+                            }
+                        }
+                        FileJob::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })
+            .expect("Failed to spawn the file writer thread");
+        FileWriter {
+            inner: WriterInner::Background {
+                sender: Some(sender),
+                errors,
+                thread: Some(thread),
+            },
+        }
+    }
+}
+
+/// Writes files according to a [`FileOutput`].
+///
+/// With background writes, errors are returned by a later call to [`write`](Self::write) or by
+/// [`finish`](Self::finish). Dropping the writer waits for the queued files to be written.
+pub struct FileWriter {
+    inner: WriterInner,
+}
+
+enum WriterInner {
+    Inline(FileSink),
+    Background {
+        sender: Option<Sender<FileJob>>,
+        errors: Receiver<io::Error>,
+        thread: Option<JoinHandle<()>>,
+    },
+}
+
+/// A job for the background thread.
+enum FileJob {
+    Write(PathBuf, Vec<u8>),
+    /// Answered once the files queued before are written.
+    Flush(Sender<()>),
+}
+
+impl Default for FileWriter {
+    fn default() -> Self {
+        FileOutput::default().writer()
+    }
+}
+
+impl FileWriter {
+    /// Writes the parts, e.g. a header and the pixels, into a new file at the path.
+    pub fn write(&mut self, path: impl AsRef<Path>, parts: &[&[u8]]) -> io::Result<()> {
+        match &mut self.inner {
+            WriterInner::Inline(sink) => sink.write(path.as_ref(), parts),
+            WriterInner::Background { sender, errors, .. } => {
+                if let Ok(err) = errors.try_recv() {
+                    return Err(err);
+                }
+                let data = parts.concat();
+                sender
+                    .as_ref()
+                    .unwrap()
+                    .send(FileJob::Write(path.as_ref().to_path_buf(), data))
+                    .map_err(|_| io::Error::other("the file writer thread stopped"))
+            }
+        }
+    }
+
+    /// Waits for the queued files to be written and returns the first error of the background
+    /// thread, if any. Without background writes, the files are written by [`write`](Self::write).
+    pub fn flush(&mut self) -> io::Result<()> {
+        let WriterInner::Background { sender, errors, .. } = &mut self.inner else {
+            return Ok(());
+        };
+        let (done, wait) = crossbeam_channel::bounded(1);
+        sender
+            .as_ref()
+            .unwrap()
+            .send(FileJob::Flush(done))
+            .map_err(|_| io::Error::other("the file writer thread stopped"))?;
+        // Fails if the thread stopped, which is reported when it is joined.
+        let _ = wait.recv();
+        match errors.try_recv() {
+            Ok(err) => Err(err),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Waits for all files to be written and returns the first error of the background thread,
+    /// if any.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> io::Result<()> {
+        let WriterInner::Background {
+            sender,
+            errors,
+            thread,
+        } = &mut self.inner
+        else {
+            return Ok(());
+        };
+        // Closing the channel stops the thread once the queue is empty.
+        drop(sender.take());
+        if let Some(thread) = thread.take() {
+            if thread.join().is_err() {
+                return Err(io::Error::other("the file writer thread panicked"));
+            }
+        }
+        match errors.try_recv() {
+            Ok(err) => Err(err),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl Drop for FileWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.join() {
+            error!("Failed to write a file: {}", err);
+        }
+    }
+}
+
+/// Writes files on the current thread.
+struct FileSink {
+    output: FileOutput,
+    buffer: Option<AlignedBuffer>,
+}
+
+impl FileSink {
+    fn write(&mut self, path: &Path, parts: &[&[u8]]) -> io::Result<()> {
+        if self.output.direct_io {
+            if let Some(file) = open_direct(path)? {
+                let buffer = self
+                    .buffer
+                    .get_or_insert_with(|| AlignedBuffer::new(self.output.buffer_size));
+                return write_direct(file, parts, buffer);
+            }
+        }
+
+        let mut writer = BufWriter::with_capacity(self.output.buffer_size, File::create(path)?);
+        for part in parts {
+            writer.write_all(part)?;
+        }
+        writer.flush()
+    }
+}
+
+/// Opens the file for direct I/O, or returns `None` if that is not supported.
+#[cfg(target_os = "linux")]
+fn open_direct(path: &Path) -> io::Result<Option<File>> {
+    use std::{fs::OpenOptions, os::unix::fs::OpenOptionsExt};
+
+    match OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        // The filesystem doesn't support direct I/O, e.g. tmpfs.
+        Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {
+            warn_once!("Direct I/O is not supported for {}", path.display());
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_direct(_path: &Path) -> io::Result<Option<File>> {
+    warn_once!("Direct I/O is only supported on Linux");
+    Ok(None)
+}
+
+/// Writes the parts in aligned blocks. The tail, which is not a multiple of the block size, is
+/// written without direct I/O.
+fn write_direct(mut file: File, parts: &[&[u8]], buffer: &mut AlignedBuffer) -> io::Result<()> {
+    buffer.len = 0;
+    for part in parts {
+        let mut part = *part;
+        while !part.is_empty() {
+            let count = buffer.push(part);
+            part = &part[count..];
+            if buffer.is_full() {
+                file.write_all(buffer.as_slice())?;
+                buffer.len = 0;
+            }
+        }
+    }
+    if buffer.len > 0 {
+        disable_direct(&file)?;
+        file.write_all(buffer.as_slice())?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn disable_direct(file: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    // SAFETY: The file descriptor is valid as long as the file is.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_DIRECT) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn disable_direct(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// A block of an [`AlignedBuffer`], aligned to the [block size](BLOCK_SIZE).
+#[derive(Clone, Copy)]
+#[repr(C, align(4096))]
+struct Block([u8; BLOCK_SIZE]);
+
+/// A buffer aligned to the block size, as direct I/O requires.
+struct AlignedBuffer {
+    blocks: Vec<Block>,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            blocks: vec![Block([0; BLOCK_SIZE]); capacity.div_ceil(BLOCK_SIZE).max(1)],
+            len: 0,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.blocks.len() * BLOCK_SIZE
+    }
+
+    /// Appends as many bytes as fit and returns their count.
+    fn push(&mut self, bytes: &[u8]) -> usize {
+        let count = bytes.len().min(self.capacity() - self.len);
+        let mut bytes = &bytes[..count];
+        while !bytes.is_empty() {
+            let (block, offset) = (self.len / BLOCK_SIZE, self.len % BLOCK_SIZE);
+            let len = bytes.len().min(BLOCK_SIZE - offset);
+            self.blocks[block].0[offset..offset + len].copy_from_slice(&bytes[..len]);
+            bytes = &bytes[len..];
+            self.len += len;
+        }
+        count
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: The blocks are contiguous without padding, as their size equals their
+        // alignment, and the first `len` bytes are within them.
+        unsafe { std::slice::from_raw_parts(self.blocks.as_ptr().cast::<u8>(), self.len) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_aligned_blocks() {
+        let mut buffer = AlignedBuffer::new(BLOCK_SIZE + 1);
+        assert_eq!(buffer.capacity(), 2 * BLOCK_SIZE);
+        assert_eq!(buffer.as_slice().as_ptr() as usize % BLOCK_SIZE, 0);
+
+        // Bytes are pushed across the blocks until the buffer is full.
+        let bytes: Vec<u8> = (0..3 * BLOCK_SIZE).map(|i| i as u8).collect();
+        assert_eq!(buffer.push(&bytes[..BLOCK_SIZE - 1]), BLOCK_SIZE - 1);
+        assert_eq!(buffer.push(&bytes[BLOCK_SIZE - 1..]), BLOCK_SIZE + 1);
+        assert!(buffer.is_full());
+        assert_eq!(buffer.as_slice(), &bytes[..2 * BLOCK_SIZE]);
+    }
+}
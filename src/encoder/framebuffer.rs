@@ -0,0 +1,140 @@
+//! Shows the most recent frame on a Linux framebuffer device, e.g. `/dev/fb0`.
+
+use super::{to_rgba8, Encoder, Error, Result};
+use bevy::prelude::*;
+use std::{
+    fs::{self, File, OpenOptions},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+/// An encoder that draws every frame to the top left corner of a Linux framebuffer device,
+/// a live monitor for headless renders on machines without a window system.
+///
+/// 32-bit (BGRX) and 16-bit (RGB565) framebuffers are supported. Frames larger than the
+/// framebuffer are clipped.
+pub struct FramebufferEncoder {
+    path: PathBuf,
+    geometry: Option<FramebufferGeometry>,
+    device: Option<(File, FramebufferGeometry)>,
+}
+
+/// The geometry of a framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramebufferGeometry {
+    /// The width of the framebuffer in pixels.
+    pub width: u32,
+    /// The height of the framebuffer in pixels.
+    pub height: u32,
+    /// The bits per pixel, either `32` (BGRX) or `16` (RGB565).
+    pub bits_per_pixel: u32,
+    /// The number of bytes between the starts of two rows.
+    pub stride: u32,
+}
+
+impl FramebufferEncoder {
+    /// Creates a new framebuffer encoder that draws to the given device, e.g. `/dev/fb0`.
+    /// The device is opened when the first frame is encoded.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            geometry: None,
+            device: None,
+        }
+    }
+
+    /// Sets the geometry of the framebuffer instead of reading it from
+    /// `/sys/class/graphics/<name>`, e.g. if sysfs is not mounted in a container.
+    pub fn with_geometry(mut self, geometry: FramebufferGeometry) -> Self {
+        self.geometry = Some(geometry);
+        self
+    }
+
+    fn open(&self) -> Result<(File, FramebufferGeometry)> {
+        let geometry = match self.geometry {
+            Some(geometry) => geometry,
+            None => FramebufferGeometry::read(&self.path)?,
+        };
+        if geometry.bits_per_pixel != 32 && geometry.bits_per_pixel != 16 {
+            return Err(Error::format(format!(
+                "unsupported framebuffer depth: {} bits",
+                geometry.bits_per_pixel
+            )));
+        }
+
+        Ok((OpenOptions::new().write(true).open(&self.path)?, geometry))
+    }
+}
+
+impl FramebufferGeometry {
+    /// Reads the geometry of the given framebuffer device from `/sys/class/graphics/<name>`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let name = path.file_name().ok_or("invalid framebuffer path")?;
+        let sysfs = Path::new("/sys/class/graphics").join(name);
+        let read = |attribute: &str| -> Result<String> {
+            Ok(fs::read_to_string(sysfs.join(attribute))?
+                .trim()
+                .to_string())
+        };
+        let parse = |value: &str| -> Result<u32> {
+            value
+                .parse()
+                .map_err(|err| format!("invalid framebuffer attribute {value:?}: {err}").into())
+        };
+
+        let virtual_size = read("virtual_size")?;
+        let (width, height) = virtual_size
+            .split_once(',')
+            .ok_or("invalid framebuffer size")?;
+
+        Ok(Self {
+            width: parse(width)?,
+            height: parse(height)?,
+            bits_per_pixel: parse(&read("bits_per_pixel")?)?,
+            stride: parse(&read("stride")?)?,
+        })
+    }
+}
+
+impl Encoder for FramebufferEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        let (file, device) = match &mut self.device {
+            Some(device) => device,
+            None => {
+                let device = self.open()?;
+                self.device.insert(device)
+            }
+        };
+
+        let rgba = to_rgba8(image)?;
+        let width = image.width().min(device.width);
+        let height = image.height().min(device.height);
+
+        let mut row = Vec::with_capacity(width as usize * 4);
+        for y in 0..height {
+            row.clear();
+            let offset = (y * image.width()) as usize * 4;
+            for pixel in rgba[offset..offset + width as usize * 4].chunks_exact(4) {
+                let [r, g, b] = [pixel[0], pixel[1], pixel[2]];
+                if device.bits_per_pixel == 32 {
+                    row.extend_from_slice(&[b, g, r, 0xff]);
+                } else {
+                    let rgb565 = ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+                    row.extend_from_slice(&rgb565.to_le_bytes());
+                }
+            }
+            file.write_all_at(&row, y as u64 * device.stride as u64)?;
+        }
+
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "framebuffer"
+    }
+
+    fn describe_output(&self) -> String {
+        self.path.display().to_string()
+    }
+}
@@ -1,34 +1,154 @@
 //! Encode frames into individual images;
 
-use super::{Encoder, Result};
+use super::{
+    file_output::{FileOutput, FileWriter},
+    to_dynamic_image, Encoder, Result,
+};
+use crate::metadata::{FrameMetadata, FRAME_INDEX_KEY};
 use bevy::prelude::*;
-use std::{fs, path::PathBuf};
+use image::ImageFormat;
+use std::{
+    fs,
+    io::{Cursor, Write},
+    path::PathBuf,
+};
+
+type BoxedSink = Box<dyn FnMut(u32, &[u8]) -> std::io::Result<()> + Send + Sync + 'static>;
 
 /// An encoder that encodes a sequence of images into individual images.
+///
+/// When writing to a directory, the [metadata](crate::metadata) of a frame is written to a
+/// `frame_{index}.json` sidecar file next to the image, if there is any besides the
+/// [frame index](crate::metadata::FRAME_INDEX_KEY).
 pub struct FramesEncoder {
-    path: PathBuf,
+    sink: FramesSink,
+    writer: FileWriter,
     frame: u32,
 }
 
+enum FramesSink {
+    Directory(PathBuf),
+    Writer(BoxedSink),
+}
+
 impl FramesEncoder {
     /// Creates a new frames encoder that writes frames to the given directory.
     pub fn new(path: impl Into<PathBuf>) -> Self {
         Self {
-            path: path.into(),
+            sink: FramesSink::Directory(path.into()),
+            writer: FileWriter::default(),
             frame: 0,
         }
     }
+
+    /// Creates a new frames encoder that writes each frame as a png to the writer returned by the
+    /// given function, e.g. an in-memory buffer or a network socket. The function is called with
+    /// the frame index.
+    pub fn new_with_writer<W, F>(mut writer: F) -> Self
+    where
+        W: Write,
+        F: FnMut(u32) -> std::io::Result<W> + Send + Sync + 'static,
+    {
+        Self {
+            sink: FramesSink::Writer(Box::new(move |frame, bytes| {
+                writer(frame)?.write_all(bytes)
+            })),
+            writer: FileWriter::default(),
+            frame: 0,
+        }
+    }
+
+    /// Creates a new frames encoder that writes frames to the given directory, configured by the
+    /// given config.
+    pub fn from_config(path: impl Into<PathBuf>, config: &FramesConfig) -> Self {
+        Self::new(path).with_output(config.output.clone())
+    }
+
+    /// Sets how the files are written when writing to a directory, e.g. on a background thread.
+    pub fn with_output(mut self, output: FileOutput) -> Self {
+        self.writer = output.writer();
+        self
+    }
+}
+
+/// The configuration of a [`FramesEncoder`], which can be cloned and reused to create the encoders
+/// of many captures, see [`FramesEncoder::from_config`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct FramesConfig {
+    output: FileOutput,
+}
+
+impl FramesConfig {
+    /// Creates a new config with the defaults of the [`FramesEncoder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how the files are written, see [`FramesEncoder::with_output`].
+    pub fn with_output(mut self, output: FileOutput) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Returns how the files are written.
+    pub fn output(&self) -> &FileOutput {
+        &self.output
+    }
 }
 
 impl Encoder for FramesEncoder {
     fn encode(&mut self, image: &Image) -> Result<()> {
-        fs::create_dir_all(&self.path)?;
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
 
-        let image = image.clone().try_into_dynamic()?;
-        image.save(self.path.join(format!("frame_{:06}.png", self.frame)))?;
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let image = to_dynamic_image(image)?;
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+
+        match &mut self.sink {
+            FramesSink::Directory(path) => {
+                fs::create_dir_all(&*path)?;
+                self.writer
+                    .write(path.join(format!("frame_{:06}.png", self.frame)), &[&bytes])?;
+                if metadata.iter().any(|(key, _)| key != FRAME_INDEX_KEY) {
+                    self.writer.write(
+                        path.join(format!("frame_{:06}.json", self.frame)),
+                        &[metadata.to_json().as_bytes()],
+                    )?;
+                }
+            }
+            FramesSink::Writer(sink) => {
+                sink(self.frame, &bytes)?;
+            }
+        }
 
         self.frame += 1;
 
         Ok(())
     }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) {
+        if let Err(err) = self.writer.finish() {
+            error!("Failed to write frames: {}", err);
+        }
+    }
+
+    fn name(&self) -> &str {
+        "frames"
+    }
+
+    fn describe_output(&self) -> String {
+        match &self.sink {
+            FramesSink::Directory(path) => path.display().to_string(),
+            FramesSink::Writer(_) => String::new(),
+        }
+    }
 }
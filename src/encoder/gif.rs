@@ -1,41 +1,343 @@
 //! Encodes frames into a gif.
 
-use super::{Encoder, Result};
-use bevy::prelude::*;
-use image::{codecs::gif, Frame};
+use super::{dirty, quantize::QuantizeConfig, to_dynamic_image, Encoder, Error, Result};
+use crate::{live_settings::LiveEncoderSettings, metadata::FrameMetadata};
+use bevy::{math::URect, prelude::*, utils::Duration};
 use std::io::Write;
 
-pub use gif::Repeat;
+pub use image::codecs::gif::Repeat;
 
 /// An encoder that encodes a sequence of images into a gif.
-pub struct GifEncoder<W: Write>(gif::GifEncoder<W>);
+///
+/// The frame delay can be changed while capturing with [`LiveEncoderSettings`]. Frames with
+/// [dirty rectangles](dirty) are encoded as sub-frames of the bounding box of the rectangles, which
+/// assumes opaque frames. Frames are quantized by the gif crate, unless a [`QuantizeConfig`] is set
+/// with [`GifEncoder::with_quantization`].
+pub struct GifEncoder<W: Write> {
+    writer: Option<W>,
+    encoder: Option<::gif::Encoder<W>>,
+    speed: i32,
+    repeat: Option<Repeat>,
+    frame_delay: Duration,
+    quantization: Option<QuantizeConfig>,
+}
 
 impl<W: Write> GifEncoder<W> {
     /// Creates a new gif encoder that writes the gif to the given writer, e.g. a file.
     pub fn new(writer: W) -> Self {
-        Self(gif::GifEncoder::new(writer))
+        Self::new_with_speed(writer, 1)
     }
 
     /// Creates a new gif encoder that writes the gif to the given writer, e.g. a file,
     /// with the given speed.
     /// See [`Frame::from_rgba_speed`](https://docs.rs/gif/latest/gif/struct.Frame.html#method.from_rgba_speed)
     /// for more information on the speed parameter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the speed is not in `1..=30`.
     pub fn new_with_speed(writer: W, speed: i32) -> Self {
-        Self(gif::GifEncoder::new_with_speed(writer, speed))
+        assert!(
+            (1..=30).contains(&speed),
+            "speed needs to be in the range [1, 30]"
+        );
+        Self {
+            writer: Some(writer),
+            encoder: None,
+            speed,
+            repeat: None,
+            frame_delay: Duration::ZERO,
+            quantization: None,
+        }
     }
 
     /// Sets the repeat mode of the gif.
     pub fn with_repeat(mut self, repeat: Repeat) -> Self {
-        self.0.set_repeat(repeat).unwrap();
+        self.repeat = Some(repeat);
+        self
+    }
+
+    /// Sets the time each frame is shown, in steps of 10 ms. Defaults to zero, which most viewers
+    /// show as fast as they can.
+    pub fn with_frame_delay(mut self, frame_delay: Duration) -> Self {
+        self.frame_delay = frame_delay;
         self
     }
+
+    /// Sets the quantization of the frames, i.e. how their palettes are picked and dithered. The
+    /// speed of the encoder is ignored then.
+    pub fn with_quantization(mut self, quantization: QuantizeConfig) -> Self {
+        self.quantization = Some(quantization);
+        self
+    }
+
+    /// Creates a new gif encoder that writes the gif to the given writer, e.g. a file, configured
+    /// by the given config.
+    pub fn from_config(writer: W, config: &GifConfig) -> Self {
+        let encoder = match config.speed {
+            Some(speed) => Self::new_with_speed(writer, speed),
+            None => Self::new(writer),
+        };
+        let encoder = match config.repeat {
+            Some(repeat) => encoder.with_repeat(repeat),
+            None => encoder,
+        };
+        let encoder = match config.quantization {
+            Some(quantization) => encoder.with_quantization(quantization),
+            None => encoder,
+        };
+        encoder.with_frame_delay(config.frame_delay)
+    }
+}
+
+/// The configuration of a [`GifEncoder`], which can be cloned and reused to create the encoders of
+/// many captures, see [`GifEncoder::from_config`].
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct GifConfig {
+    speed: Option<i32>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_repeat"))]
+    repeat: Option<Repeat>,
+    frame_delay: Duration,
+    quantization: Option<QuantizeConfig>,
+}
+
+impl GifConfig {
+    /// Creates a new config with the defaults of the [`GifEncoder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the speed, see [`GifEncoder::new_with_speed`].
+    pub fn with_speed(mut self, speed: i32) -> Self {
+        self.speed = Some(speed);
+        self
+    }
+
+    /// Sets the repeat mode, see [`GifEncoder::with_repeat`].
+    pub fn with_repeat(mut self, repeat: Repeat) -> Self {
+        self.repeat = Some(repeat);
+        self
+    }
+
+    /// Sets the time each frame is shown, see [`GifEncoder::with_frame_delay`].
+    pub fn with_frame_delay(mut self, frame_delay: Duration) -> Self {
+        self.frame_delay = frame_delay;
+        self
+    }
+
+    /// Sets the quantization of the frames, see [`GifEncoder::with_quantization`].
+    pub fn with_quantization(mut self, quantization: QuantizeConfig) -> Self {
+        self.quantization = Some(quantization);
+        self
+    }
+
+    /// Returns the speed, if set.
+    pub fn speed(&self) -> Option<i32> {
+        self.speed
+    }
+
+    /// Returns the repeat mode, if set.
+    pub fn repeat(&self) -> Option<Repeat> {
+        self.repeat
+    }
+
+    /// Returns the time each frame is shown.
+    pub fn frame_delay(&self) -> Duration {
+        self.frame_delay
+    }
+
+    /// Returns the quantization of the frames, if set.
+    pub fn quantization(&self) -> Option<QuantizeConfig> {
+        self.quantization
+    }
+}
+
+/// (De)serializes the repeat mode of a [`GifConfig`], since [`Repeat`] does not implement the serde
+/// traits.
+#[cfg(feature = "serde")]
+mod serde_repeat {
+    use super::Repeat;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    enum RepeatDef {
+        Finite(u16),
+        Infinite,
+    }
+
+    pub fn serialize<S: Serializer>(
+        repeat: &Option<Repeat>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let repeat = repeat.map(|repeat| match repeat {
+            Repeat::Finite(count) => RepeatDef::Finite(count),
+            Repeat::Infinite => RepeatDef::Infinite,
+        });
+        repeat.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Repeat>, D::Error> {
+        let repeat = Option::<RepeatDef>::deserialize(deserializer)?;
+        Ok(repeat.map(|repeat| match repeat {
+            RepeatDef::Finite(count) => Repeat::Finite(count),
+            RepeatDef::Infinite => Repeat::Infinite,
+        }))
+    }
 }
 
 impl<W: Write> Encoder for GifEncoder<W> {
     fn encode(&mut self, image: &Image) -> Result<()> {
-        let image = image.clone().try_into_dynamic()?;
-        let buffer = image.to_rgba8();
-        self.0.encode_frame(Frame::new(buffer))?;
-        Ok(())
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let buffer = to_dynamic_image(image)?.to_rgba8();
+        let (width, height) = buffer.dimensions();
+        let frame_delay = LiveEncoderSettings::from_metadata(metadata)
+            .frame_delay()
+            .unwrap_or(self.frame_delay);
+
+        let encoder = match &mut self.encoder {
+            Some(encoder) => encoder,
+            None => {
+                let writer = self.writer.take().expect("the writer is taken once");
+                let mut encoder = ::gif::Encoder::new(writer, to_u16(width)?, to_u16(height)?, &[])
+                    .map_err(gif_error)?;
+                if let Some(repeat) = self.repeat {
+                    let repeat = match repeat {
+                        Repeat::Finite(count) => ::gif::Repeat::Finite(count),
+                        Repeat::Infinite => ::gif::Repeat::Infinite,
+                    };
+                    encoder.set_repeat(repeat).map_err(gif_error)?;
+                }
+                self.encoder.insert(encoder)
+            }
+        };
+
+        // Frames with dirty rectangles are drawn over the previous frame, which is kept. Frames
+        // without changes still need a frame for the delay, a single unchanged pixel.
+        let full = URect::new(0, 0, width, height);
+        let (rect, dispose) = match dirty::from_metadata(metadata) {
+            Some(rects) => (
+                dirty::bounds(&rects)
+                    .map(|bounds| bounds.intersect(full))
+                    .filter(|bounds| !bounds.is_empty())
+                    .unwrap_or(URect::new(0, 0, 1, 1)),
+                ::gif::DisposalMethod::Keep,
+            ),
+            None => (full, ::gif::DisposalMethod::Background),
+        };
+        let mut pixels = if rect == full {
+            buffer.into_raw()
+        } else {
+            let row_bytes = width as usize * 4;
+            (rect.min.y..rect.max.y)
+                .flat_map(|y| {
+                    let offset = y as usize * row_bytes;
+                    &buffer.as_raw()
+                        [offset + rect.min.x as usize * 4..offset + rect.max.x as usize * 4]
+                })
+                .copied()
+                .collect()
+        };
+
+        let (frame_width, frame_height) = (to_u16(rect.width())?, to_u16(rect.height())?);
+        let mut frame = match &self.quantization {
+            Some(quantization) => {
+                let quantized = quantization.quantize(&pixels, rect.width(), rect.height());
+                let palette = quantized.palette_bytes();
+                ::gif::Frame::from_palette_pixels(
+                    frame_width,
+                    frame_height,
+                    quantized.indices,
+                    palette,
+                    quantized.transparent,
+                )
+            }
+            None => {
+                ::gif::Frame::from_rgba_speed(frame_width, frame_height, &mut pixels, self.speed)
+            }
+        };
+        frame.left = to_u16(rect.min.x)?;
+        frame.top = to_u16(rect.min.y)?;
+        frame.delay = (frame_delay.as_millis() / 10)
+            .try_into()
+            .unwrap_or(u16::MAX);
+        frame.dispose = dispose;
+        encoder.write_frame(&frame).map_err(gif_error)
+    }
+
+    fn name(&self) -> &str {
+        "gif"
+    }
+}
+
+fn to_u16(size: u32) -> Result<u16> {
+    u16::try_from(size).map_err(|_| {
+        Error::format(format!(
+            "gifs can't be larger than 65535 pixels, got {size}"
+        ))
+    })
+}
+
+fn gif_error(err: ::gif::EncodingError) -> Error {
+    match err {
+        ::gif::EncodingError::Io(err) => Error::Io(err),
+        err => Error::codec(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+    use std::io;
+
+    #[test]
+    fn encodes_gif_with_quantization() {
+        use crate::encoder::quantize::Quantizer;
+        use image::{codecs::gif::GifDecoder, AnimationDecoder};
+
+        let mut image = Image::new_fill(
+            Extent3d {
+                width: 32,
+                height: 32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+            default(),
+        );
+        for (i, pixel) in image.data.chunks_exact_mut(4).enumerate() {
+            pixel[0] = (i % 32 * 8) as u8;
+            pixel[1] = (i / 32 * 8) as u8;
+        }
+
+        let mut gif = Vec::new();
+        let mut encoder = GifEncoder::new(&mut gif).with_quantization(
+            QuantizeConfig::new()
+                .with_quantizer(Quantizer::Octree)
+                .with_max_colors(8),
+        );
+        encoder.encode(&image).unwrap();
+        drop(encoder);
+
+        let frames = GifDecoder::new(io::Cursor::new(gif))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 1);
+        let colors = frames[0]
+            .buffer()
+            .pixels()
+            .map(|pixel| pixel.0)
+            .collect::<std::collections::HashSet<_>>();
+        assert!(colors.len() <= 8);
     }
 }
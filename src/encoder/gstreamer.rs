@@ -0,0 +1,115 @@
+//! Pushes frames into a GStreamer pipeline using the gst-launch-1.0 CLI (must be in PATH).
+//!
+//! The pipeline runs in a gst-launch-1.0 process that reads the raw frames from stdin, so the
+//! `gstreamer` feature needs no GStreamer development libraries at build time. If the pipeline
+//! fails, e.g. because of a typo in an element or a missing plugin, the error returned by the
+//! encoder contains the end of the output of gst-launch-1.0.
+
+use super::{capabilities::EncoderCapabilities, pipe::ChildPipe, to_rgba8, Encoder, Error, Result};
+use bevy::prelude::*;
+use std::{path::PathBuf, process::Command};
+
+/// An encoder that pushes frames into a GStreamer pipeline described by a launch string.
+/// gst-launch-1.0 must be in PATH.
+///
+/// The frames are passed as raw RGBA video to the given pipeline, so the pipeline usually
+/// starts with `videoconvert`.
+///
+/// # Example
+/// ```ignore
+/// # use bevy_capture::encoder::gstreamer::GstreamerEncoder;
+/// #
+/// let encoder = GstreamerEncoder::new("videoconvert ! x264enc ! mp4mux ! filesink location=out.mp4")
+///     .with_framerate(30);
+/// ```
+pub struct GstreamerEncoder {
+    pipeline: String,
+    framerate: u32,
+    program: PathBuf,
+    process: Option<(ChildPipe, u32, u32)>,
+}
+
+impl GstreamerEncoder {
+    /// Creates a new GStreamer encoder with the given pipeline description.
+    pub fn new(pipeline: impl Into<String>) -> Self {
+        Self {
+            pipeline: pipeline.into(),
+            framerate: 60,
+            program: PathBuf::from("gst-launch-1.0"),
+            process: None,
+        }
+    }
+
+    /// Sets the framerate of the video.
+    pub fn with_framerate(mut self, framerate: u32) -> Self {
+        self.framerate = framerate;
+        self
+    }
+
+    /// Sets the gst-launch-1.0 executable, e.g. of a GStreamer installation that is not in PATH.
+    pub fn with_program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Returns the full pipeline that is launched for frames of the given dimensions, including
+    /// the source reading the raw frames from stdin.
+    pub fn launch_line(&self, width: u32, height: u32) -> String {
+        format!(
+            "fdsrc fd=0 ! rawvideoparse width={} height={} format=rgba framerate={}/1 ! {}",
+            width, height, self.framerate, self.pipeline
+        )
+    }
+
+    fn spawn(&self, width: u32, height: u32) -> Result<ChildPipe> {
+        let mut command = Command::new(&self.program);
+        command.arg("-e").arg(self.launch_line(width, height));
+        ChildPipe::spawn(&self.program, command)
+    }
+}
+
+impl Encoder for GstreamerEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        let rgba = to_rgba8(image)?;
+
+        let (process, width, height) = match &mut self.process {
+            Some(process) => process,
+            None => {
+                let process = self.spawn(image.width(), image.height())?;
+                self.process
+                    .insert((process, image.width(), image.height()))
+            }
+        };
+        if (*width, *height) != (image.width(), image.height()) {
+            return Err(Error::format(
+                "gstreamer pipelines do not support changing dimensions",
+            ));
+        }
+
+        process.write_all(&rgba)
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        EncoderCapabilities::rgba8().with_fixed_dimensions(true)
+    }
+
+    fn finish(self: Box<Self>) {
+        if let Some((process, _, _)) = self.process {
+            process.finish();
+        }
+    }
+
+    fn name(&self) -> &str {
+        "gstreamer"
+    }
+
+    fn describe_output(&self) -> String {
+        self.pipeline.clone()
+    }
+}
+
+/// Quotes a property value for a pipeline description, e.g. a path or a URL with spaces or `!`,
+/// which would otherwise end the property or start a new element.
+pub fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,264 @@
+//! Skip frames while nothing changes, e.g. of mostly static dashboards or long-running monitoring
+//! simulations, so captures don't waste space on duplicates.
+//!
+//! An [`IdleSkip`] hashes every frame and compares it with the hash of the previous frame. Once
+//! more consecutive frames than the threshold are identical, the capture is idle and the frames are
+//! not passed to the inner encoder, until a frame differs again. Frames of different dimensions or
+//! formats are never identical.
+//!
+//! A [`ChangeTrigger`] records only frames that differ from the previous frame by more than a
+//! threshold, like a motion detection camera, so small changes like noise or a blinking cursor
+//! don't trigger recording. The first frame recorded after a gap carries the number of skipped
+//! frames and the timestamp of the first skipped frame in its [metadata](crate::metadata), see
+//! [`GAP_FRAMES_KEY`] and [`GAP_START_KEY`].
+//!
+//! Skipped frames are missing from the output, so videos with a fixed framerate play faster while
+//! nothing changes. Use image sequences, or [timestamp](crate::CapturePlugin::with_clock) the
+//! frames and pass the timestamps to encoders that support variable framerates.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::{frames::FramesEncoder, idle::*};
+//! #
+//! // Keep one second of identical frames at 60 fps, skip the rest.
+//! let encoder = IdleSkip::new(FramesEncoder::new("dashboard"), 60);
+//!
+//! // Record frames that differ by more than 1% from the previous one, and one second after that.
+//! let encoder = ChangeTrigger::new(FramesEncoder::new("simulation"), 0.01).with_hold(60);
+//! ```
+
+use super::{capabilities::EncoderCapabilities, Encoder, Result};
+use crate::metadata::{FrameMetadata, MetadataValue, TIMESTAMP_KEY};
+use bevy::prelude::*;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// The metadata key of the number of frames a [`ChangeTrigger`] skipped before the frame.
+pub const GAP_FRAMES_KEY: &str = "gap_frames";
+
+/// The metadata key of the [timestamp](TIMESTAMP_KEY) of the first frame a [`ChangeTrigger`]
+/// skipped before the frame, if the frames are timestamped.
+pub const GAP_START_KEY: &str = "gap_start";
+
+/// An encoder that skips identical frames, see the [module docs](self).
+pub struct IdleSkip<E> {
+    inner: E,
+    threshold: u32,
+    previous: Option<u64>,
+    identical: u32,
+    skipped: u64,
+}
+
+impl<E: Encoder> IdleSkip<E> {
+    /// Creates a new encoder that passes the frames to the inner encoder, but skips identical frames
+    /// once more than `threshold` consecutive frames were identical. A threshold of `0` skips every
+    /// frame that is identical to the previous one.
+    pub fn new(inner: E, threshold: u32) -> Self {
+        Self {
+            inner,
+            threshold,
+            previous: None,
+            identical: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Returns `true` if the last frame was skipped.
+    pub fn is_idle(&self) -> bool {
+        self.identical > self.threshold
+    }
+
+    /// Returns the number of skipped frames so far.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+impl<E: Encoder> Encoder for IdleSkip<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let hash = hash_image(image);
+        if self.previous == Some(hash) {
+            self.identical = self.identical.saturating_add(1);
+        } else {
+            if self.is_idle() {
+                debug!(
+                    "Frame changed after {} identical frames, resuming",
+                    self.identical
+                );
+            }
+            self.previous = Some(hash);
+            self.identical = 0;
+        }
+
+        if self.is_idle() {
+            self.skipped += 1;
+            return Ok(());
+        }
+        self.inner.encode_with_metadata(image, metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        if self.skipped > 0 {
+            debug!("Skipped {} identical frames", self.skipped);
+        }
+        Box::new(self.inner).finish();
+    }
+}
+
+/// An encoder that records only frames that changed, see the [module docs](self).
+pub struct ChangeTrigger<E> {
+    inner: E,
+    threshold: f32,
+    hold: u32,
+    previous: Option<Image>,
+    unchanged: u32,
+    gap: Option<Gap>,
+    skipped: u64,
+}
+
+struct Gap {
+    frames: u64,
+    start: Option<MetadataValue>,
+}
+
+impl<E: Encoder> ChangeTrigger<E> {
+    /// Creates a new encoder that passes the frames to the inner encoder if they differ from the
+    /// previous frame by more than the given threshold, i.e. the mean absolute difference of the
+    /// bytes of the frames, from `0.0` (identical) to `1.0` (inverted). The first frame is always
+    /// recorded.
+    pub fn new(inner: E, threshold: f32) -> Self {
+        Self {
+            inner,
+            threshold,
+            hold: 0,
+            previous: None,
+            unchanged: 0,
+            gap: None,
+            skipped: 0,
+        }
+    }
+
+    /// Sets the number of frames that are recorded after the last changed frame, e.g. to see how a
+    /// change settles. Defaults to `0`.
+    pub fn with_hold(mut self, hold: u32) -> Self {
+        self.hold = hold;
+        self
+    }
+
+    /// Returns the number of skipped frames so far.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+impl<E: Encoder> Encoder for ChangeTrigger<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let changed = match &self.previous {
+            Some(previous) => difference(previous, image) > self.threshold,
+            None => true,
+        };
+        self.previous = Some(image.clone());
+        self.unchanged = if changed {
+            0
+        } else {
+            self.unchanged.saturating_add(1)
+        };
+
+        if self.unchanged > self.hold {
+            self.skipped += 1;
+            let gap = self.gap.get_or_insert_with(|| Gap {
+                frames: 0,
+                start: metadata.get(TIMESTAMP_KEY).cloned(),
+            });
+            gap.frames += 1;
+            return Ok(());
+        }
+
+        match self.gap.take() {
+            Some(gap) => {
+                let mut metadata = metadata.clone();
+                metadata.insert(GAP_FRAMES_KEY, gap.frames);
+                if let Some(start) = gap.start {
+                    metadata.insert(GAP_START_KEY, start);
+                }
+                self.inner.encode_with_metadata(image, &metadata)
+            }
+            None => self.inner.encode_with_metadata(image, metadata),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        if self.skipped > 0 {
+            debug!("Skipped {} unchanged frames", self.skipped);
+        }
+        Box::new(self.inner).finish();
+    }
+}
+
+/// Returns the mean absolute difference of the bytes of the images, from `0.0` to `1.0`. Images
+/// of different dimensions or formats differ by `1.0`.
+fn difference(a: &Image, b: &Image) -> f32 {
+    if a.texture_descriptor.size != b.texture_descriptor.size
+        || a.texture_descriptor.format != b.texture_descriptor.format
+        || a.data.len() != b.data.len()
+    {
+        return 1.0;
+    }
+    if a.data.is_empty() {
+        return 0.0;
+    }
+    let sum = a
+        .data
+        .iter()
+        .zip(&b.data)
+        .map(|(&a, &b)| a.abs_diff(b) as u64)
+        .sum::<u64>();
+    sum as f32 / (a.data.len() as f32 * 255.0)
+}
+
+fn hash_image(image: &Image) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image.texture_descriptor.size.hash(&mut hasher);
+    image.texture_descriptor.format.hash(&mut hasher);
+    image.data.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,237 @@
+//! Publishes frames to other processes over a Unix domain socket or a Windows named pipe.
+//!
+//! Every frame is sent with a simple length-prefixed protocol (all integers little endian):
+//!
+//! | Field    | Type        | Description                          |
+//! | -------- | ----------- | ------------------------------------ |
+//! | magic    | `[u8; 4]`   | Always `b"BCAP"`.                    |
+//! | frame    | `u64`       | The index of the frame.              |
+//! | width    | `u32`       | The width of the frame.              |
+//! | height   | `u32`       | The height of the frame.             |
+//! | len      | `u32`       | The length of the data in bytes.     |
+//! | data     | `[u8; len]` | The RGBA8 pixels of the frame.       |
+//!
+//! [`IpcClient`] implements the reading side of the protocol.
+
+use super::{to_rgba8, Encoder, Result};
+use bevy::prelude::*;
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// The magic bytes at the start of every frame.
+pub const MAGIC: [u8; 4] = *b"BCAP";
+
+type BoxedWriter = Box<dyn Write + Send + Sync + 'static>;
+
+/// An encoder that publishes frames over a Unix domain socket or a Windows named pipe.
+pub struct IpcEncoder {
+    #[cfg(unix)]
+    listener: Option<(UnixListener, std::path::PathBuf)>,
+    clients: Vec<BoxedWriter>,
+    frame: u64,
+}
+
+impl IpcEncoder {
+    /// Creates a new IPC encoder that listens on a Unix domain socket at the given path.
+    /// Any number of clients can connect at any time, frames are sent to all connected clients.
+    ///
+    /// A stale socket at the path, e.g. of a previous run, is replaced. Fails if any other file
+    /// exists at the path.
+    #[cfg(unix)]
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        let path = path.as_ref();
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(format!("{} exists and is not a socket", path.display()).into());
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener: Some((listener, path.to_path_buf())),
+            clients: Vec::new(),
+            frame: 0,
+        })
+    }
+
+    /// Creates a new IPC encoder that connects to a listening Unix domain socket (Unix) or
+    /// an existing named pipe, e.g. `\\.\pipe\my_pipe` (Windows).
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self> {
+        #[cfg(unix)]
+        let client: BoxedWriter = Box::new(UnixStream::connect(path)?);
+        #[cfg(not(unix))]
+        let client: BoxedWriter = Box::new(std::fs::OpenOptions::new().write(true).open(path)?);
+
+        Ok(Self {
+            #[cfg(unix)]
+            listener: None,
+            clients: vec![client],
+            frame: 0,
+        })
+    }
+
+    #[cfg(unix)]
+    fn accept_clients(&mut self) -> Result<()> {
+        let Some((listener, _)) = &self.listener else {
+            return Ok(());
+        };
+
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    self.clients.push(Box::new(stream));
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Sends a frame of RGBA8 pixels to all connected clients.
+    pub(crate) fn send(&mut self, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+        #[cfg(unix)]
+        self.accept_clients()?;
+
+        if !self.clients.is_empty() {
+            let frame = IpcFrame {
+                frame: self.frame,
+                width,
+                height,
+                data: rgba.to_vec(),
+            };
+
+            // Disconnected clients are dropped.
+            self.clients
+                .retain_mut(|client| frame.write_to(client).is_ok());
+        }
+
+        self.frame += 1;
+
+        Ok(())
+    }
+}
+
+impl Encoder for IpcEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.send(image.width(), image.height(), &to_rgba8(image)?)
+    }
+
+    fn name(&self) -> &str {
+        "ipc"
+    }
+
+    fn describe_output(&self) -> String {
+        #[cfg(unix)]
+        if let Some((_, path)) = &self.listener {
+            return path.display().to_string();
+        }
+        String::new()
+    }
+}
+
+impl Drop for IpcEncoder {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some((_, path)) = &self.listener {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// A frame received by an [`IpcClient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpcFrame {
+    /// The index of the frame.
+    pub frame: u64,
+    /// The width of the frame.
+    pub width: u32,
+    /// The height of the frame.
+    pub height: u32,
+    /// The RGBA8 pixels of the frame.
+    pub data: Vec<u8>,
+}
+
+impl IpcFrame {
+    pub(crate) fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&self.frame.to_le_bytes());
+        header.extend_from_slice(&self.width.to_le_bytes());
+        header.extend_from_slice(&self.height.to_le_bytes());
+        header.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+
+        writer.write_all(&header)?;
+        writer.write_all(&self.data)?;
+        writer.flush()
+    }
+}
+
+/// A client that receives frames published by an [`IpcEncoder`].
+pub struct IpcClient<R: Read>(R);
+
+#[cfg(unix)]
+impl IpcClient<UnixStream> {
+    /// Connects to an [`IpcEncoder`] listening on the Unix domain socket at the given path.
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self(UnixStream::connect(path)?))
+    }
+}
+
+impl<R: Read> IpcClient<R> {
+    /// Creates a new client that reads frames from the given reader.
+    pub fn new(reader: R) -> Self {
+        Self(reader)
+    }
+
+    /// Reads the next frame. Returns `None` if the encoder has finished.
+    pub fn read_frame(&mut self) -> io::Result<Option<IpcFrame>> {
+        let mut header = [0; 24];
+        match self.0.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+
+        if header[0..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid magic bytes",
+            ));
+        }
+
+        let frame = u64::from_le_bytes(header[4..12].try_into().unwrap());
+        let width = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let height = u32::from_le_bytes(header[16..20].try_into().unwrap());
+        let len = u32::from_le_bytes(header[20..24].try_into().unwrap());
+
+        let mut data = vec![0; len as usize];
+        self.0.read_exact(&mut data)?;
+
+        Ok(Some(IpcFrame {
+            frame,
+            width,
+            height,
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for IpcClient<R> {
+    type Item = io::Result<IpcFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
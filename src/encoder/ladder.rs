@@ -0,0 +1,173 @@
+//! Encode one capture into multiple qualities at once, e.g. an ABR-ready set of videos for
+//! web publishing.
+
+use super::{to_rgba8, Encoder, Result};
+use crate::metadata::FrameMetadata;
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+/// The heights of the standard ladder, see [`LadderEncoder::standard`].
+pub const STANDARD_HEIGHTS: [u32; 3] = [1080, 720, 480];
+
+/// An encoder that scales every frame to the heights of a ladder (keeping the aspect ratio) and
+/// passes the scaled frames to one encoder per height.
+///
+/// The frame is converted to RGBA8 once and shared by all heights. Frames are never upscaled, so
+/// heights above the height of the frame get the unscaled frame. Scaled widths are rounded to even
+/// numbers, as most video codecs require.
+///
+/// # Example
+/// ```ignore
+/// # use bevy_capture::encoder::{ladder::LadderEncoder, mp4_ffmpeg_cli::Mp4FfmpegCliEncoder};
+/// #
+/// let encoder = LadderEncoder::standard(|height| {
+///     Mp4FfmpegCliEncoder::new(format!("captures/video_{height}p.mp4"))
+/// })?;
+/// ```
+#[derive(Default)]
+pub struct LadderEncoder {
+    rungs: Vec<Rung>,
+}
+
+struct Rung {
+    height: u32,
+    encoder: Box<dyn Encoder + Send + Sync>,
+}
+
+impl LadderEncoder {
+    /// Creates a new ladder encoder without any heights, see [`with_rung`](Self::with_rung).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new ladder encoder with the [standard heights](STANDARD_HEIGHTS) (1080p, 720p and
+    /// 480p), creating the encoder of every height with the given function.
+    pub fn standard<E: Encoder + Send + Sync + 'static>(
+        mut encoder: impl FnMut(u32) -> Result<E>,
+    ) -> Result<Self> {
+        STANDARD_HEIGHTS
+            .into_iter()
+            .try_fold(Self::new(), |ladder, height| {
+                Ok(ladder.with_rung(height, encoder(height)?))
+            })
+    }
+
+    /// Adds an encoder that gets the frames scaled to the given height.
+    pub fn with_rung(mut self, height: u32, encoder: impl Encoder + Send + Sync + 'static) -> Self {
+        self.rungs.push(Rung {
+            height: height.max(1),
+            encoder: Box::new(encoder),
+        });
+        self
+    }
+}
+
+impl Encoder for LadderEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let (width, height) = (image.width(), image.height());
+        let needs_scaling = self.rungs.iter().any(|rung| rung.height < height);
+        let rgba = if needs_scaling {
+            Some(to_rgba8(image)?)
+        } else {
+            None
+        };
+
+        // Every height is encoded, even if a previous one failed. The first error is returned.
+        let mut result = Ok(());
+        for rung in &mut self.rungs {
+            let rung_result = match &rgba {
+                Some(rgba) if rung.height < height => {
+                    let scaled_height = rung.height;
+                    let scaled_width =
+                        ((width as u64 * scaled_height as u64 / height as u64) as u32 & !1).max(2);
+                    let scaled = Image::new(
+                        Extent3d {
+                            width: scaled_width,
+                            height: scaled_height,
+                            depth_or_array_layers: 1,
+                        },
+                        TextureDimension::D2,
+                        downscale(rgba, width, height, scaled_width, scaled_height),
+                        TextureFormat::Rgba8UnormSrgb,
+                        RenderAssetUsages::default(),
+                    );
+                    rung.encoder.encode_with_metadata(&scaled, metadata)
+                }
+                _ => rung.encoder.encode_with_metadata(image, metadata),
+            };
+            if result.is_ok() {
+                result = rung_result;
+            }
+        }
+        result
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for rung in &mut self.rungs {
+            rung.encoder.flush()?;
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "ladder"
+    }
+
+    fn describe_output(&self) -> String {
+        self.rungs
+            .iter()
+            .map(|rung| format!("{}p: {}", rung.height, rung.encoder.describe_output()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn finish(self: Box<Self>) {
+        for rung in self.rungs {
+            rung.encoder.finish();
+        }
+    }
+}
+
+/// Downscales RGBA8 pixels by averaging the source pixels covered by every target pixel.
+fn downscale(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    target_width: u32,
+    target_height: u32,
+) -> Vec<u8> {
+    let span = |target: u32, size: u32, target_size: u32| {
+        let start = (target as u64 * size as u64 / target_size as u64) as usize;
+        let end = ((target as u64 + 1) * size as u64 / target_size as u64) as usize;
+        start..end.max(start + 1).min(size as usize)
+    };
+
+    let mut output = Vec::with_capacity((target_width * target_height * 4) as usize);
+    for target_y in 0..target_height {
+        let rows = span(target_y, height, target_height);
+        for target_x in 0..target_width {
+            let columns = span(target_x, width, target_width);
+            let mut sum = [0u32; 4];
+            for y in rows.clone() {
+                let row = &rgba[(y * width as usize + columns.start) * 4..][..columns.len() * 4];
+                for pixel in row.chunks_exact(4) {
+                    for (sum, &value) in sum.iter_mut().zip(pixel) {
+                        *sum += value as u32;
+                    }
+                }
+            }
+            let count = (rows.len() * columns.len()) as u32;
+            output.extend(sum.map(|sum| ((sum + count / 2) / count) as u8));
+        }
+    }
+    output
+}
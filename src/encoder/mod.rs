@@ -1,30 +1,336 @@
 //! Encoders for different formats.
 
+pub mod capabilities;
+pub mod chroma_key;
+pub mod color;
+pub mod dirty;
+pub mod fallback;
+pub mod faststart;
+pub mod file_output;
+pub mod idle;
+pub mod ipc;
+pub mod ladder;
+pub mod mpegts;
+pub mod quantize;
+pub mod rate_limit;
+pub mod raw;
+pub mod replay;
+pub mod retry;
+pub mod secondary_gpu;
+pub mod tcp;
+pub mod test;
+pub mod uncompressed;
+pub mod upload;
+pub mod virtual_camera;
+pub mod watermark;
+pub mod y4m;
+
+#[cfg(feature = "image")]
 pub mod frames;
 
+#[cfg(feature = "image")]
+pub mod terminal;
+
 #[cfg(feature = "gif")]
 pub mod gif;
 
-#[cfg(feature = "mp4_openh264")]
+#[cfg(any(feature = "mp4_openh264", feature = "mp4_openh264_libloading"))]
 pub mod mp4_openh264;
 
 #[cfg(feature = "mp4_ffmpeg_cli")]
 pub mod mp4_ffmpeg_cli;
 
-use bevy::prelude::*;
+#[cfg(feature = "zmq")]
+pub mod zmq;
+
+#[cfg(feature = "gstreamer")]
+pub mod gstreamer;
+
+#[cfg(feature = "gstreamer")]
+pub mod rtsp;
+
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+#[cfg(feature = "quic")]
+pub mod quic;
+
+#[cfg(target_os = "linux")]
+pub mod framebuffer;
+
+#[cfg(all(feature = "v4l2", target_os = "linux"))]
+pub mod v4l2;
+
+#[cfg(feature = "serde")]
+pub mod registry;
+
+#[cfg(feature = "gstreamer")]
+pub(crate) mod pipe;
+
+mod error;
 
-/// An error that occurred during encoding.
-pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+pub use error::{BoxedError, Error};
+
+use capabilities::EncoderCapabilities;
+
+use crate::metadata::FrameMetadata;
+use bevy::{prelude::*, render::render_resource::TextureFormat};
+use std::borrow::Cow;
 
 /// The result type for encoding operations.
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Returns the pixels of the image as tightly packed RGBA8, without going through the `image`
+/// crate. Fails with a [`Format`](Error::Format) error if the texture format is not supported.
+///
+/// RGBA8 images are borrowed, BGRA8 images are swizzled and R8/RG8 images are expanded the same
+/// way as by [`to_dynamic_image`] (as luma and luma + alpha).
+pub fn to_rgba8(image: &Image) -> Result<Cow<'_, [u8]>> {
+    let data = &image.data;
+    let rgba = match image.texture_descriptor.format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => Cow::Borrowed(&data[..]),
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => data
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+            .collect(),
+        TextureFormat::R8Unorm => data.iter().flat_map(|&l| [l, l, l, 0xff]).collect(),
+        TextureFormat::Rg8Unorm => data
+            .chunks_exact(2)
+            .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+            .collect(),
+        // The high byte of the little endian values.
+        TextureFormat::R16Uint => data
+            .chunks_exact(2)
+            .flat_map(|pixel| [pixel[1], pixel[1], pixel[1], 0xff])
+            .collect(),
+        format => {
+            return Err(Error::format(format!(
+                "unsupported texture format for rgba8 conversion: {format:?}"
+            )))
+        }
+    };
+    Ok(rgba)
+}
+
+/// How video encoders handle frames with odd dimensions, which can't be encoded with 4:2:0 chroma
+/// subsampling, e.g. by H.264 and H.265 encoders.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OddDimensions {
+    /// Pads the frames to even dimensions by repeating the last column or row. The sample aspect
+    /// ratio of the video is set, so the frames are displayed with the aspect ratio of the source.
+    ///
+    /// The padding can't be cropped in the video instead, since 4:2:0 video can only be cropped
+    /// by whole chroma samples, i.e. by 2 pixels.
+    #[default]
+    Pad,
+    /// Crops the last column or row of the frames. Frames that are 1 pixel wide or tall can't be
+    /// cropped.
+    Crop,
+}
+
+impl OddDimensions {
+    /// Returns the even dimensions the given dimensions are padded or cropped to.
+    pub fn even(self, width: u32, height: u32) -> (u32, u32) {
+        let even = |size: u32| match self {
+            Self::Pad => size.next_multiple_of(2),
+            Self::Crop => size & !1,
+        };
+        (even(width), even(height))
+    }
+
+    /// Checks that frames with the given dimensions can be made even, i.e. that they are not
+    /// cropped to nothing.
+    pub fn check(self, width: u32, height: u32) -> Result<()> {
+        if self == Self::Crop && (width == 1 || height == 1) {
+            return Err(Error::format(format!(
+                "frames with odd dimensions ({width}x{height}) can't be cropped to even dimensions"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the sample aspect ratio that displays frames padded to even dimensions with the
+    /// aspect ratio of the given dimensions, or `None` if the frames are not padded.
+    pub fn sample_aspect_ratio(self, width: u32, height: u32) -> Option<[u16; 2]> {
+        let (even_width, even_height) = self.even(width, height);
+        if self != Self::Pad || (even_width, even_height) == (width, height) {
+            return None;
+        }
+
+        let mut ratio = [
+            width as u64 * even_height as u64,
+            height as u64 * even_width as u64,
+        ];
+        let gcd = {
+            let [mut a, mut b] = ratio;
+            while b != 0 {
+                (a, b) = (b, a % b);
+            }
+            a
+        };
+        ratio = ratio.map(|value| value / gcd);
+        // Approximate ratios that don't fit into the 16-bit values of the video.
+        let scale = ratio[0].max(ratio[1]).div_ceil(u16::MAX as u64);
+        Some(ratio.map(|value| (value / scale).max(1) as u16))
+    }
+
+    /// Pads or crops tightly packed RGBA8 pixels with the given dimensions to even dimensions.
+    /// Pixels with even dimensions are returned unchanged.
+    pub fn apply<'a>(self, rgba: Cow<'a, [u8]>, width: u32, height: u32) -> Cow<'a, [u8]> {
+        let (even_width, even_height) = self.even(width, height);
+        if (even_width, even_height) == (width, height) {
+            return rgba;
+        }
+
+        let (width, even_width) = (width as usize * 4, even_width as usize * 4);
+        let mut output = Vec::with_capacity(even_width * even_height as usize);
+        for y in 0..even_height as usize {
+            let row = &rgba[y.min(height as usize - 1) * width..][..width];
+            let columns = even_width.min(width);
+            output.extend_from_slice(&row[..columns]);
+            if even_width > width {
+                output.extend_from_slice(&row[width - 4..]);
+            }
+        }
+        Cow::Owned(output)
+    }
+
+    /// Logs a warning if the given dimensions are odd, i.e. if the frames are padded or cropped.
+    /// Encoders call this once, for the first frame.
+    pub fn warn(self, width: u32, height: u32) {
+        let (even_width, even_height) = self.even(width, height);
+        if (even_width, even_height) != (width, height) {
+            let action = match self {
+                Self::Pad => "padded",
+                Self::Crop => "cropped",
+            };
+            warn!(
+                "Frames with odd dimensions ({width}x{height}) are {action} to \
+                 {even_width}x{even_height}, since the video codec requires even dimensions"
+            );
+        }
+    }
+}
+
+/// Converts the image into a `DynamicImage`, see `Image::try_into_dynamic`. Fails with a
+/// [`Format`](Error::Format) error if the texture format is not supported.
+#[cfg(feature = "image")]
+pub fn to_dynamic_image(image: &Image) -> Result<image::DynamicImage> {
+    image
+        .clone()
+        .try_into_dynamic()
+        .map_err(|err| Error::format(err.to_string()))
+}
+
 /// An encoder that encodes a sequence of images into a custom format.
 pub trait Encoder {
     /// Encodes the given image.
     fn encode(&mut self, image: &Image) -> Result<()>;
 
+    /// Encodes the given image together with the [metadata](crate::metadata) attached to the frame.
+    /// The default implementation ignores the metadata and calls [`encode`](Self::encode).
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let _ = metadata;
+        self.encode(image)
+    }
+
+    /// Writes the data the encoder buffered to its sink, e.g. the queued files or the buffered
+    /// bytes of a writer, without finishing, see [`Capture::flush`](crate::Capture::flush).
+    /// Encoders that only write their output when they finish keep it buffered. The default does
+    /// nothing.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     /// Finishes the encoding process.
     /// This method can be used to finalize the encoding process and write any remaining data, if necessary.
     fn finish(self: Box<Self>) {}
+
+    /// Returns the frames the encoder accepts. The frames of a capture are converted accordingly
+    /// before they are passed to the encoder, see [`capabilities`]. The default accepts all frames.
+    fn capabilities(&self) -> EncoderCapabilities {
+        EncoderCapabilities::default()
+    }
+
+    /// Returns the name of the encoder, which identifies it in logs, errors and events, e.g. when it
+    /// failed or finished, and in the [`CaptureRegistry`](crate::debug::CaptureRegistry). The
+    /// built-in encoders use short names like `"frames"`, encoders wrapping another encoder use
+    /// the name of the inner encoder. The default is the type name of the encoder.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Returns a description of where the encoder writes to, e.g. a path or a URL, which is logged
+    /// when the encoder finished. The default is an empty string, e.g. for encoders writing to an
+    /// arbitrary writer.
+    fn describe_output(&self) -> String {
+        String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::render_resource::{Extent3d, TextureDimension};
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn reports_unsupported_formats() {
+        let image = Image::new_fill(
+            default(),
+            TextureDimension::D2,
+            &[0; 4],
+            TextureFormat::R32Float,
+            default(),
+        );
+        let err = to_dynamic_image(&image).unwrap_err();
+        assert!(matches!(err, Error::Format(_)), "{err:?}");
+    }
+
+    #[test]
+    fn converts_bgra_to_rgba8() {
+        let image = Image::new(
+            Extent3d {
+                width: 2,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![1, 2, 3, 4, 5, 6, 7, 8],
+            TextureFormat::Bgra8UnormSrgb,
+            default(),
+        );
+        let rgba = to_rgba8(&image).unwrap();
+        assert_eq!(&rgba[..], &[3, 2, 1, 4, 7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn pads_and_crops_odd_dimensions() {
+        let rgba: Vec<u8> = (0..3).flat_map(|i| [i; 4]).collect();
+
+        let padded = OddDimensions::Pad.apply(Cow::Borrowed(&rgba), 3, 1);
+        let row = [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2];
+        assert_eq!(&padded[..], &[row, row].concat());
+
+        let cropped = OddDimensions::Crop.apply(Cow::Borrowed(&rgba), 3, 1);
+        assert!(cropped.is_empty());
+        assert_eq!(OddDimensions::Crop.even(511, 512), (510, 512));
+
+        // Frames that would be cropped to nothing are rejected.
+        assert!(OddDimensions::Pad.check(1, 1).is_ok());
+        let err = OddDimensions::Crop.check(3, 1).unwrap_err();
+        assert!(matches!(err, Error::Format(_)), "{err}");
+
+        // Padded frames are displayed with the aspect ratio of the source.
+        assert_eq!(OddDimensions::Pad.sample_aspect_ratio(3, 1), Some([3, 2]));
+        assert_eq!(OddDimensions::Pad.sample_aspect_ratio(4, 2), None);
+        assert_eq!(OddDimensions::Crop.sample_aspect_ratio(3, 1), None);
+        // Ratios that don't fit into 16 bits are approximated.
+        let [width, height] = OddDimensions::Pad
+            .sample_aspect_ratio(65535, 65533)
+            .unwrap();
+        let expected = (65535.0 * 65534.0) / (65533.0 * 65536.0);
+        assert!((width as f64 / height as f64 - expected).abs() < 1e-4);
+    }
 }
@@ -1,31 +1,179 @@
 //! MP4 encoder using ffmpeg CLI (ffmpeg must be in PATH).
+//!
+//! Whether ffmpeg is installed and supports a codec can be checked with
+//! [`Mp4FfmpegCliEncoder::probe`] and [`Mp4FfmpegCliEncoder::checked`] before the capture starts.
+//! Combined with a [`FallbackEncoder`](super::fallback::FallbackEncoder), one binary can use ffmpeg
+//! where it is installed and OpenH264 elsewhere.
+//!
+//! With [`Mp4FfmpegCliEncoder::with_container`], the encoder writes an MPEG transport stream
+//! instead, e.g. for streaming protocols and set-top pipelines. The stream is muxed by the
+//! [`mpegts`](super::mpegts) module, with timestamps and PCR taken from the capture clock.
 
-use super::{Encoder, Result};
+use super::{
+    capabilities::EncoderCapabilities,
+    color::{ColorMatrix, ColorRange, ColorSpace},
+    mpegts::{split_access_units, StreamType, TsMuxer},
+    to_rgba8, Encoder, Error, OddDimensions, Result,
+};
+use crate::{live_settings::LiveEncoderSettings, metadata::FrameMetadata};
 use bevy::prelude::*;
-use std::{path::PathBuf, process::Command};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::Duration,
+};
 use tempdir::TempDir;
 
 /// An encoder that encodes a sequence of images into an MP4 file using ffmpeg CLI.
 /// ffmpeg must be in PATH.
+///
+/// The frames are collected as raw RGBA8 video in a temporary directory and encoded when the
+/// capture finishes. All frames must have the same dimensions.
+///
+/// The CRF can be changed while capturing with [`LiveEncoderSettings`]. Every change starts a new
+/// segment, which is encoded with its own CRF, and the segments are joined without re-encoding.
+///
+/// With [`Container::MpegTs`], an MPEG transport stream is written instead, e.g. for streaming
+/// protocols or set-top pipelines. Its timestamps follow the clock of the
+/// [`CapturePlugin`](crate::CapturePlugin) if it has one, see
+/// [`with_container`](Self::with_container).
 pub struct Mp4FfmpegCliEncoder {
     dir: TempDir,
-    frame: u32,
-    path: PathBuf,
+    frames: Option<RawFrames>,
+    output: Output,
 
     framerate: u32,
+    codec: VideoCodec,
+    container: Container,
+    color_space: ColorSpace,
+    odd_dimensions: OddDimensions,
+    keyframe_interval: Option<u32>,
     crf: u32,
+    log_output: bool,
+    faststart: bool,
+}
+
+/// The video codec of the MP4.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VideoCodec {
+    /// H.264 (AVC), encoded with libx264. Plays almost everywhere.
+    #[default]
+    H264,
+    /// H.265 (HEVC), encoded with libx265. Needs about half the bitrate of H.264 for the same
+    /// quality, which makes a difference at 4K, but is not supported by all browsers.
+    H265,
+}
+
+impl VideoCodec {
+    /// Returns the name of the ffmpeg encoder of the codec.
+    fn ffmpeg_encoder(self) -> &'static str {
+        match self {
+            Self::H264 => "libx264",
+            Self::H265 => "libx265",
+        }
+    }
+}
+
+/// The container of the video.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Container {
+    /// MP4, or fragmented MP4 when writing to a writer.
+    #[default]
+    Mp4,
+    /// MPEG transport stream, muxed by the [`mpegts`](super::mpegts) module.
+    MpegTs,
+}
+
+/// The version and the encoders of the ffmpeg in PATH, see [`Mp4FfmpegCliEncoder::probe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FfmpegInfo {
+    version: String,
+    encoders: Vec<String>,
+}
+
+impl FfmpegInfo {
+    /// Returns the version as reported by ffmpeg, e.g. `6.1.1-3ubuntu5` or `N-113226-g9c4fd7b` for
+    /// development builds.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Returns the major and minor version, or `None` for development builds.
+    pub fn version_number(&self) -> Option<(u32, u32)> {
+        let mut parts = self.version.split(|c: char| !c.is_ascii_digit());
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts
+            .next()
+            .and_then(|minor| minor.parse().ok())
+            .unwrap_or(0);
+        Some((major, minor))
+    }
+
+    /// Returns `true` if ffmpeg has an encoder with the given name, e.g. `libx264`.
+    pub fn supports_encoder(&self, name: &str) -> bool {
+        self.encoders.iter().any(|encoder| encoder == name)
+    }
+
+    /// Returns `true` if ffmpeg can encode the codec.
+    pub fn supports_codec(&self, codec: VideoCodec) -> bool {
+        self.supports_encoder(codec.ffmpeg_encoder())
+    }
+}
+
+struct RawFrames {
+    width: u32,
+    height: u32,
+    segments: Vec<RawSegment>,
+    /// The capture clock timestamps of the frames in seconds.
+    timestamps: Vec<Option<f64>>,
+}
+
+/// Consecutive frames encoded with the same CRF.
+struct RawSegment {
+    file: BufWriter<File>,
+    crf: u32,
+    count: u64,
+    keyframes: Vec<u64>,
+}
+
+enum Output {
+    Path(PathBuf),
+    Writer(Box<dyn Write + Send + Sync + 'static>),
 }
 
 impl Mp4FfmpegCliEncoder {
     /// Creates a new MP4 encoder that writes the MP4 to the given path.
     pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::new_with_output(Output::Path(path.into()))
+    }
+
+    /// Creates a new MP4 encoder that writes the MP4 to the given writer, e.g. stdout or a socket.
+    /// Since the writer is not seekable, a fragmented MP4 is written, unless the container is
+    /// [`Container::MpegTs`].
+    pub fn new_with_writer(writer: impl Write + Send + Sync + 'static) -> Result<Self> {
+        Self::new_with_output(Output::Writer(Box::new(writer)))
+    }
+
+    fn new_with_output(output: Output) -> Result<Self> {
         Ok(Self {
             dir: TempDir::new("bevy_capture")?,
-            frame: 0,
-            path: path.into(),
+            frames: None,
+            output,
 
             framerate: 60,
+            codec: VideoCodec::H264,
+            container: Container::Mp4,
+            color_space: ColorSpace::default(),
+            odd_dimensions: OddDimensions::Pad,
+            keyframe_interval: None,
             crf: 23,
+            log_output: false,
+            faststart: false,
         })
     }
 
@@ -35,52 +183,693 @@ impl Mp4FfmpegCliEncoder {
         self
     }
 
-    /// Sets the CRF (Constant Rate Factor) of the video.
+    /// Sets the video codec. Defaults to [`VideoCodec::H264`].
+    pub fn with_codec(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Sets the container. Defaults to [`Container::Mp4`].
+    ///
+    /// For [`Container::MpegTs`], ffmpeg only encodes the video, without B-frames and with the
+    /// parameter sets at every keyframe, and the [`TsMuxer`] writes the transport stream. The
+    /// timestamps of the frames are taken from the [`TIMESTAMP_KEY`](crate::metadata::TIMESTAMP_KEY)
+    /// metadata, relative to the first frame, so the stream keeps the pace of the capture clock
+    /// even if frames were dropped or rendered at a varying rate. Without timestamps, the frames
+    /// follow the framerate.
+    pub fn with_container(mut self, container: Container) -> Self {
+        self.container = container;
+        self
+    }
+
+    /// Sets the color space the frames are converted to. It is also written into the video, so
+    /// players convert the frames back correctly. Defaults to limited range BT.709.
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Sets the maximum number of frames between keyframes (the GOP size). Defaults to the default
+    /// of the codec, 250 frames. Frames can also be forced to be keyframes with
+    /// [`FrameMetadata::force_keyframe`].
+    pub fn with_keyframe_interval(mut self, interval: u32) -> Self {
+        self.keyframe_interval = Some(interval.max(1));
+        self
+    }
+
+    /// Sets how frames with odd dimensions are made even, which H.264 and H.265 require.
+    /// Defaults to [`OddDimensions::Pad`]. The video has the padded or cropped dimensions, padded
+    /// frames are displayed with the aspect ratio of the source.
+    pub fn with_odd_dimensions(mut self, odd_dimensions: OddDimensions) -> Self {
+        self.odd_dimensions = odd_dimensions;
+        self
+    }
+
+    /// Sets the CRF (Constant Rate Factor) of the video. The scale depends on the codec, the
+    /// default of 23 is a good starting point for H.264, H.265 reaches similar quality at about 28.
     pub fn with_crf(mut self, crf: u32) -> Self {
         self.crf = crf;
         self
     }
+
+    /// Moves the moov atom to the front of the file, so the video can start playing before it is
+    /// fully downloaded, e.g. when streamed from a web server. This has no effect when writing to
+    /// a writer, since the fragmented MP4 already starts with the moov atom, or to MPEG-TS.
+    pub fn with_faststart(mut self, faststart: bool) -> Self {
+        self.faststart = faststart;
+        self
+    }
+
+    /// Forwards the output of ffmpeg to the log. By default, the output is only logged if ffmpeg
+    /// fails.
+    pub fn with_log_output(mut self, log_output: bool) -> Self {
+        self.log_output = log_output;
+        self
+    }
+
+    /// Creates a new MP4 encoder that writes the MP4 to the given path, configured by the given
+    /// config.
+    pub fn from_config(path: impl Into<PathBuf>, config: &Mp4FfmpegCliConfig) -> Result<Self> {
+        let mut encoder = Self::new(path)?
+            .with_framerate(config.framerate)
+            .with_codec(config.codec)
+            .with_container(config.container)
+            .with_color_space(config.color_space)
+            .with_odd_dimensions(config.odd_dimensions)
+            .with_crf(config.crf)
+            .with_faststart(config.faststart)
+            .with_log_output(config.log_output);
+        if let Some(interval) = config.keyframe_interval {
+            encoder = encoder.with_keyframe_interval(interval);
+        }
+        Ok(encoder)
+    }
 }
 
-impl Encoder for Mp4FfmpegCliEncoder {
-    fn encode(&mut self, image: &Image) -> Result<()> {
-        let image = image.clone().try_into_dynamic()?;
-        image.save(self.dir.path().join(format!("frame_{:06}.png", self.frame)))?;
+/// The configuration of an [`Mp4FfmpegCliEncoder`], which can be cloned and reused to create the
+/// encoders of many captures, see [`Mp4FfmpegCliEncoder::from_config`]. The builder methods and
+/// defaults are the same as those of the encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Mp4FfmpegCliConfig {
+    framerate: u32,
+    codec: VideoCodec,
+    container: Container,
+    color_space: ColorSpace,
+    keyframe_interval: Option<u32>,
+    odd_dimensions: OddDimensions,
+    crf: u32,
+    faststart: bool,
+    log_output: bool,
+}
+
+impl Default for Mp4FfmpegCliConfig {
+    fn default() -> Self {
+        Self {
+            framerate: 60,
+            codec: VideoCodec::H264,
+            container: Container::Mp4,
+            color_space: ColorSpace::default(),
+            keyframe_interval: None,
+            odd_dimensions: OddDimensions::Pad,
+            crf: 23,
+            faststart: false,
+            log_output: false,
+        }
+    }
+}
 
-        self.frame += 1;
+impl Mp4FfmpegCliConfig {
+    /// Creates a new config with the defaults of the [`Mp4FfmpegCliEncoder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        Ok(())
+    /// Sets the framerate, see [`Mp4FfmpegCliEncoder::with_framerate`].
+    pub fn with_framerate(mut self, framerate: u32) -> Self {
+        self.framerate = framerate;
+        self
+    }
+
+    /// Sets the video codec, see [`Mp4FfmpegCliEncoder::with_codec`].
+    pub fn with_codec(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Sets the container, see [`Mp4FfmpegCliEncoder::with_container`].
+    pub fn with_container(mut self, container: Container) -> Self {
+        self.container = container;
+        self
+    }
+
+    /// Sets the color space, see [`Mp4FfmpegCliEncoder::with_color_space`].
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Sets the keyframe interval, see [`Mp4FfmpegCliEncoder::with_keyframe_interval`].
+    pub fn with_keyframe_interval(mut self, interval: u32) -> Self {
+        self.keyframe_interval = Some(interval.max(1));
+        self
+    }
+
+    /// Sets how odd dimensions are made even, see [`Mp4FfmpegCliEncoder::with_odd_dimensions`].
+    pub fn with_odd_dimensions(mut self, odd_dimensions: OddDimensions) -> Self {
+        self.odd_dimensions = odd_dimensions;
+        self
+    }
+
+    /// Sets the CRF, see [`Mp4FfmpegCliEncoder::with_crf`].
+    pub fn with_crf(mut self, crf: u32) -> Self {
+        self.crf = crf;
+        self
+    }
+
+    /// Sets whether the moov atom is moved to the front, see
+    /// [`Mp4FfmpegCliEncoder::with_faststart`].
+    pub fn with_faststart(mut self, faststart: bool) -> Self {
+        self.faststart = faststart;
+        self
+    }
+
+    /// Sets whether the output of ffmpeg is logged, see [`Mp4FfmpegCliEncoder::with_log_output`].
+    pub fn with_log_output(mut self, log_output: bool) -> Self {
+        self.log_output = log_output;
+        self
+    }
+
+    /// Returns the framerate.
+    pub fn framerate(&self) -> u32 {
+        self.framerate
+    }
+
+    /// Returns the video codec.
+    pub fn codec(&self) -> VideoCodec {
+        self.codec
+    }
+
+    /// Returns the container.
+    pub fn container(&self) -> Container {
+        self.container
+    }
+
+    /// Returns the color space.
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Returns the keyframe interval, if set.
+    pub fn keyframe_interval(&self) -> Option<u32> {
+        self.keyframe_interval
     }
 
-    fn finish(self: Box<Self>) {
-        let mut command;
-        if cfg!(target_os = "windows") {
-            command = Command::new("cmd");
-            command.arg("/C");
-        } else {
-            command = Command::new("sh");
-            command.arg("-c");
+    /// Returns how odd dimensions are made even.
+    pub fn odd_dimensions(&self) -> OddDimensions {
+        self.odd_dimensions
+    }
+
+    /// Returns the CRF.
+    pub fn crf(&self) -> u32 {
+        self.crf
+    }
+
+    /// Returns whether the moov atom is moved to the front.
+    pub fn faststart(&self) -> bool {
+        self.faststart
+    }
+
+    /// Returns whether the output of ffmpeg is logged.
+    pub fn log_output(&self) -> bool {
+        self.log_output
+    }
+}
+
+impl Mp4FfmpegCliEncoder {
+    /// Runs ffmpeg to find out its version and encoders. Fails if ffmpeg is not in PATH.
+    pub fn probe() -> Result<FfmpegInfo> {
+        let run = |args: &[&str]| -> Result<String> {
+            let output = Command::new("ffmpeg")
+                .args(args)
+                .stdin(Stdio::null())
+                .output()
+                .map_err(|err| match err.kind() {
+                    io::ErrorKind::NotFound => Error::external("ffmpeg", "not found in PATH"),
+                    _ => Error::external("ffmpeg", err),
+                })?;
+            if !output.status.success() {
+                return Err(Error::external("ffmpeg", output.status));
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
         };
 
-        command.arg("ffmpeg");
+        // The first line is e.g. "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) ...".
+        let version = run(&["-hide_banner", "-version"])?
+            .split_whitespace()
+            .nth(2)
+            .ok_or_else(|| Error::external("ffmpeg", "no version reported"))?
+            .to_owned();
+        // The encoders are listed as e.g. " V....D libx264   libx264 H.264 / AVC ..." after a
+        // legend that ends with a line of dashes.
+        let encoders = run(&["-hide_banner", "-encoders"])?
+            .lines()
+            .skip_while(|line| !line.trim_start().starts_with("---"))
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().nth(1))
+            .map(str::to_owned)
+            .collect();
+        Ok(FfmpegInfo { version, encoders })
+    }
+
+    /// Returns `true` if ffmpeg is in PATH.
+    pub fn is_available() -> bool {
+        Self::probe().is_ok()
+    }
+
+    /// Checks that ffmpeg is in PATH and supports the codec of the encoder. The frames are only
+    /// encoded when the capture finishes, so without the check a missing ffmpeg is only noticed
+    /// after the whole capture was recorded.
+    ///
+    /// ```ignore
+    /// let encoder = Mp4FfmpegCliEncoder::new("capture.mp4")?
+    ///     .with_codec(VideoCodec::H265)
+    ///     .checked()?;
+    /// ```
+    pub fn checked(self) -> Result<Self> {
+        let info = Self::probe()?;
+        if !info.supports_codec(self.codec) {
+            return Err(Error::external(
+                "ffmpeg",
+                format!(
+                    "version {} has no {} encoder",
+                    info.version,
+                    self.codec.ffmpeg_encoder()
+                ),
+            ));
+        }
+        Ok(self)
+    }
+
+    /// Returns the ffmpeg command that encodes a segment of raw frames with the given (not yet
+    /// even) dimensions, without the output.
+    fn encode_command(
+        &self,
+        index: usize,
+        crf: u32,
+        keyframes: &[u64],
+        width: u32,
+        height: u32,
+    ) -> Command {
+        let (even_width, even_height) = self.odd_dimensions.even(width, height);
+        let mut command = Command::new("ffmpeg");
+        command.arg("-f").arg("rawvideo");
+        command.arg("-pix_fmt").arg("rgba");
+        command
+            .arg("-video_size")
+            .arg(format!("{even_width}x{even_height}"));
         command.arg("-framerate").arg(self.framerate.to_string());
         command
             .arg("-i")
-            .arg(self.dir.path().join("frame_%06d.png"));
-        command.arg("-c:v").arg("libx264");
+            .arg(segment_path(&self.dir, index, "rgba"));
+        command.arg("-c:v").arg(self.codec.ffmpeg_encoder());
+        match (self.container, self.codec) {
+            (Container::Mp4, VideoCodec::H264) => {}
+            (Container::Mp4, VideoCodec::H265) => {
+                // Tag as hvc1 (parameter sets in the hvcC box only), which Apple players require.
+                command.arg("-tag:v").arg("hvc1");
+            }
+            (Container::MpegTs, codec) => {
+                // The muxer splits the stream at the access unit delimiters and only writes
+                // presentation timestamps, so the frames must be in decoding order.
+                let params = match codec {
+                    VideoCodec::H264 => "-x264-params",
+                    VideoCodec::H265 => "-x265-params",
+                };
+                command.arg(params).arg("aud=1:repeat-headers=1:bframes=0");
+            }
+        }
         command.arg("-pix_fmt").arg("yuv420p");
-        command.arg("-crf").arg(self.crf.to_string());
-        command.arg(self.path);
+        let (matrix, color) = match self.color_space.matrix {
+            ColorMatrix::Bt601 => ("bt601", "smpte170m"),
+            ColorMatrix::Bt709 => ("bt709", "bt709"),
+        };
+        let range = match self.color_space.range {
+            ColorRange::Limited => "tv",
+            ColorRange::Full => "pc",
+        };
+        let mut filter = format!("scale=out_color_matrix={matrix}:out_range={range}");
+        if let Some([sar_width, sar_height]) =
+            self.odd_dimensions.sample_aspect_ratio(width, height)
+        {
+            filter.push_str(&format!(",setsar={sar_width}/{sar_height}"));
+        }
+        command.arg("-vf").arg(filter);
+        command.arg("-colorspace").arg(color);
+        command.arg("-color_primaries").arg(color);
+        command.arg("-color_trc").arg(color);
+        command.arg("-color_range").arg(range);
+        command.arg("-crf").arg(crf.to_string());
+        if let Some(interval) = self.keyframe_interval {
+            command.arg("-g").arg(interval.to_string());
+        }
+        if !keyframes.is_empty() {
+            let frames = keyframes
+                .iter()
+                .map(|frame| format!("eq(n,{frame})"))
+                .collect::<Vec<_>>();
+            command
+                .arg("-force_key_frames")
+                .arg(format!("expr:{}", frames.join("+")));
+        }
+        command
+    }
+}
+
+impl Encoder for Mp4FfmpegCliEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let rgba = to_rgba8(image)?;
+
+        let frames = match &mut self.frames {
+            Some(frames) => frames,
+            None => {
+                self.odd_dimensions.check(image.width(), image.height())?;
+                self.odd_dimensions.warn(image.width(), image.height());
+                self.frames.insert(RawFrames {
+                    width: image.width(),
+                    height: image.height(),
+                    segments: Vec::new(),
+                    timestamps: Vec::new(),
+                })
+            }
+        };
+        if (frames.width, frames.height) != (image.width(), image.height()) {
+            return Err(Error::format(
+                "ffmpeg cli output does not support changing dimensions",
+            ));
+        }
+
+        let crf = LiveEncoderSettings::from_metadata(metadata)
+            .crf()
+            .unwrap_or(self.crf);
+        if frames
+            .segments
+            .last()
+            .is_none_or(|segment| segment.crf != crf)
+        {
+            let path = segment_path(&self.dir, frames.segments.len(), "rgba");
+            frames.segments.push(RawSegment {
+                file: BufWriter::new(File::create(path)?),
+                crf,
+                count: 0,
+                keyframes: Vec::new(),
+            });
+        }
+        let segment = frames.segments.last_mut().unwrap();
+
+        let rgba = self
+            .odd_dimensions
+            .apply(rgba, image.width(), image.height());
+        segment.file.write_all(&rgba)?;
+
+        if metadata.is_keyframe_forced() {
+            segment.keyframes.push(segment.count);
+        }
+        segment.count += 1;
+        frames.timestamps.push(metadata.timestamp());
+
+        Ok(())
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        // Odd dimensions are handled by the encoder, see `with_odd_dimensions`.
+        EncoderCapabilities::rgba8().with_fixed_dimensions(true)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // The video is only encoded when the capture finishes, so only the raw frames are written.
+        if let Some(segment) = self
+            .frames
+            .as_mut()
+            .and_then(|frames| frames.segments.last_mut())
+        {
+            segment.file.flush()?;
+        }
+        Ok(())
+    }
 
-        match command.output() {
-            Ok(output) => {
-                if !output.status.success() {
-                    bevy::log::error!("ffmpeg failed: {:?}", output);
+    fn finish(mut self: Box<Self>) {
+        let Some(frames) = self.frames.take() else {
+            return;
+        };
+        let mut segments = Vec::with_capacity(frames.segments.len());
+        for segment in frames.segments {
+            let RawSegment {
+                mut file,
+                crf,
+                keyframes,
+                ..
+            } = segment;
+            if let Err(error) = file.flush() {
+                bevy::log::error!("Failed to write frames for ffmpeg: {}", error);
+                return;
+            }
+            segments.push((crf, keyframes));
+        }
+        let (width, height) = (frames.width, frames.height);
+
+        let mut command = match &segments[..] {
+            [(crf, keyframes)] => self.encode_command(0, *crf, keyframes, width, height),
+            segments => {
+                // Every segment is encoded on its own. MPEG-TS repeats the parameter sets at
+                // every keyframe, so the segments can be joined although their CRF differs.
+                let mut list = String::new();
+                for (index, (crf, keyframes)) in segments.iter().enumerate() {
+                    let mut command = self.encode_command(index, *crf, keyframes, width, height);
+                    command.arg("-f").arg("mpegts");
+                    command.arg(segment_path(&self.dir, index, "ts"));
+                    command.stdout(Stdio::null()).stderr(Stdio::piped());
+                    match command.output() {
+                        Ok(output) => {
+                            let stderr = String::from_utf8_lossy(&output.stderr);
+                            log_output(output.status, &stderr, self.log_output);
+                            if !output.status.success() {
+                                return;
+                            }
+                        }
+                        Err(error) => {
+                            bevy::log::error!("ffmpeg failed: {:?}", error);
+                            return;
+                        }
+                    }
+                    list.push_str(&format!("file 'segment_{index}.ts'\n"));
+                }
+
+                let list_path = self.dir.path().join("segments.txt");
+                if let Err(error) = fs::write(&list_path, list) {
+                    bevy::log::error!("Failed to write segments for ffmpeg: {}", error);
+                    return;
+                }
+                let mut command = Command::new("ffmpeg");
+                command.arg("-f").arg("concat").arg("-i").arg(list_path);
+                command.arg("-c").arg("copy");
+                if self.codec == VideoCodec::H265 && self.container == Container::Mp4 {
+                    command.arg("-tag:v").arg("hvc1");
                 }
+                command
             }
+        };
+
+        command.stdout(Stdio::null()).stderr(Stdio::piped());
+        if self.container == Container::MpegTs {
+            if let Err(error) = self.write_mpegts(command, &frames.timestamps) {
+                bevy::log::error!("Failed to write MPEG-TS: {}", error);
+            }
+            return;
+        }
+        let result = match self.output {
+            Output::Path(path) => {
+                if self.faststart {
+                    command.arg("-movflags").arg("+faststart");
+                }
+                command.arg(path).output().map(|output| {
+                    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+                    (output.status, stderr)
+                })
+            }
+            Output::Writer(mut writer) => {
+                command.arg("-movflags").arg("frag_keyframe+empty_moov");
+                command.arg("-f").arg("mp4").arg("pipe:1");
+                command.stdout(Stdio::piped());
+                command.spawn().and_then(|mut child| {
+                    // Read stderr on another thread, so ffmpeg doesn't block on a full pipe.
+                    let mut stderr = child.stderr.take().unwrap();
+                    let stderr = thread::spawn(move || {
+                        let mut output = String::new();
+                        let _ = stderr.read_to_string(&mut output);
+                        output
+                    });
+
+                    io::copy(child.stdout.as_mut().unwrap(), &mut writer)?;
+                    writer.flush()?;
+                    let status = child.wait()?;
+                    Ok((status, stderr.join().unwrap_or_default()))
+                })
+            }
+        };
+
+        match result {
+            Ok((status, stderr)) => log_output(status, &stderr, self.log_output),
             Err(error) => {
                 bevy::log::error!("ffmpeg failed: {:?}", error);
             }
         }
     }
+
+    fn name(&self) -> &str {
+        "mp4_ffmpeg_cli"
+    }
+
+    fn describe_output(&self) -> String {
+        match &self.output {
+            Output::Path(path) => path.display().to_string(),
+            Output::Writer(_) => String::new(),
+        }
+    }
+}
+
+impl Mp4FfmpegCliEncoder {
+    /// Runs the command to encode the video stream and muxes it into MPEG-TS with the timestamps
+    /// of the frames.
+    fn write_mpegts(self, mut command: Command, timestamps: &[Option<f64>]) -> Result<()> {
+        let (format, stream_type) = match self.codec {
+            VideoCodec::H264 => ("h264", StreamType::H264),
+            VideoCodec::H265 => ("hevc", StreamType::H265),
+        };
+        let path = self.dir.path().join(format!("stream.{format}"));
+        let output = command
+            .arg("-f")
+            .arg(format)
+            .arg(&path)
+            .output()
+            .map_err(|err| Error::external("ffmpeg", err))?;
+        log_output(
+            output.status,
+            &String::from_utf8_lossy(&output.stderr),
+            self.log_output,
+        );
+        if !output.status.success() {
+            return Ok(());
+        }
+        let stream = fs::read(&path)?;
+
+        let writer: Box<dyn Write> = match self.output {
+            Output::Path(path) => Box::new(BufWriter::new(File::create(path)?)),
+            Output::Writer(writer) => writer,
+        };
+        let mut muxer = TsMuxer::new(writer, stream_type);
+        let first = timestamps.first().copied().flatten();
+        for (index, (access_unit, keyframe)) in split_access_units(&stream, stream_type).enumerate()
+        {
+            let seconds = match (first, timestamps.get(index).copied().flatten()) {
+                (Some(first), Some(timestamp)) => (timestamp - first).max(0.0),
+                _ => index as f64 / self.framerate.max(1) as f64,
+            };
+            muxer.write_access_unit(access_unit, Duration::from_secs_f64(seconds), keyframe)?;
+        }
+        muxer.finish()?;
+        Ok(())
+    }
+}
+
+fn segment_path(dir: &TempDir, index: usize, extension: &str) -> PathBuf {
+    dir.path().join(format!("segment_{index}.{extension}"))
+}
+
+fn log_output(status: ExitStatus, stderr: &str, log_output: bool) {
+    if log_output {
+        for line in stderr.lines() {
+            bevy::log::info!(target: "ffmpeg", "{}", line);
+        }
+    }
+    if !status.success() {
+        // The last lines usually contain the error.
+        let lines = stderr.lines().collect::<Vec<_>>();
+        bevy::log::error!(
+            "ffmpeg failed with {}: {}",
+            status,
+            lines[lines.len().saturating_sub(5)..].join("\n")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(encoder: &Mp4FfmpegCliEncoder, width: u32, height: u32) -> Vec<String> {
+        encoder
+            .encode_command(0, encoder.crf, &[], width, height)
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn contains(args: &[String], expected: &[&str]) -> bool {
+        args.windows(expected.len())
+            .any(|window| window.iter().eq(expected.iter()))
+    }
+
+    #[test]
+    fn tags_hevc_as_hvc1() {
+        let path = std::env::temp_dir().join("bevy_capture_args.mp4");
+
+        let h264 = args(&Mp4FfmpegCliEncoder::new(&path).unwrap(), 4, 4);
+        assert!(contains(&h264, &["-c:v", "libx264"]), "{h264:?}");
+        assert!(!h264.iter().any(|arg| arg == "-tag:v"), "{h264:?}");
+
+        // Apple players require HEVC in MP4 to be tagged as hvc1.
+        let h265 = args(
+            &Mp4FfmpegCliEncoder::new(&path)
+                .unwrap()
+                .with_codec(VideoCodec::H265),
+            4,
+            4,
+        );
+        assert!(
+            contains(&h265, &["-c:v", "libx265", "-tag:v", "hvc1"]),
+            "{h265:?}"
+        );
+    }
+
+    #[test]
+    fn keeps_aspect_ratio_of_padded_frames() {
+        let path = std::env::temp_dir().join("bevy_capture_args.mp4");
+
+        // 3x4 padded to 4x4 is displayed as 3x4.
+        let padded = args(&Mp4FfmpegCliEncoder::new(&path).unwrap(), 3, 4);
+        assert!(contains(&padded, &["-video_size", "4x4"]), "{padded:?}");
+        assert!(
+            padded.iter().any(|arg| arg.ends_with(",setsar=3/4")),
+            "{padded:?}"
+        );
+
+        let cropped = args(
+            &Mp4FfmpegCliEncoder::new(&path)
+                .unwrap()
+                .with_odd_dimensions(OddDimensions::Crop),
+            3,
+            4,
+        );
+        assert!(contains(&cropped, &["-video_size", "2x4"]), "{cropped:?}");
+        assert!(
+            !cropped.iter().any(|arg| arg.contains("setsar")),
+            "{cropped:?}"
+        );
+    }
 }
@@ -1,18 +1,26 @@
 //! MP4 encoder using OpenH264.
+//!
+//! With the `mp4_openh264` feature, OpenH264 is compiled from source. With the
+//! `mp4_openh264_libloading` feature, a prebuilt libopenh264, e.g. the binary distributed by
+//! Cisco, is loaded at runtime instead, see [`Openh264Backend`].
 
-use super::{Encoder, Result};
+use super::{
+    color::ColorSpace, faststart::faststart, to_rgba8, Encoder, Error, OddDimensions, Result,
+};
+use crate::metadata::FrameMetadata;
 use bevy::prelude::*;
-use image::RgbaImage;
 use mp4::{
     AvcConfig, FourCC, MediaConfig, Mp4Config, Mp4Sample, Mp4Writer, TrackConfig, TrackType,
 };
 use openh264::{
     encoder::{EncoderConfig, FrameType},
-    formats::{RGBSource, YUVBuffer},
+    formats::YUVBuffer,
     OpenH264API, Timestamp,
 };
+#[cfg(feature = "mp4_openh264_libloading")]
+use std::path::PathBuf;
 use std::{
-    io::{Seek, Write},
+    io::{Read, Seek, Write},
     str::FromStr,
 };
 
@@ -20,19 +28,56 @@ type Openh264Encoder = openh264::encoder::Encoder;
 
 pub use openh264;
 
+/// Where the OpenH264 implementation comes from.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Openh264Backend {
+    /// OpenH264 compiled from source (feature `mp4_openh264`).
+    #[cfg(feature = "mp4_openh264")]
+    Source,
+    /// A prebuilt libopenh264 loaded from the given path at runtime
+    /// (feature `mp4_openh264_libloading`).
+    ///
+    /// Only the official binaries distributed by Cisco are accepted, since Cisco covers the
+    /// H.264 patent licensing only for those.
+    #[cfg(feature = "mp4_openh264_libloading")]
+    Library(PathBuf),
+}
+
+impl Openh264Backend {
+    fn load(&self) -> Result<OpenH264API> {
+        match self {
+            #[cfg(feature = "mp4_openh264")]
+            Self::Source => Ok(OpenH264API::from_source()),
+            #[cfg(feature = "mp4_openh264_libloading")]
+            Self::Library(path) => OpenH264API::from_blob_path(path).map_err(Error::codec),
+        }
+    }
+}
+
 /// An encoder that encodes a sequence of images into an MP4 file using OpenH264.
 pub struct Mp4Openh264Encoder<W> {
     mp4: Mp4Writer<W>,
     mp4_track_added: bool,
     openh264: Openh264Encoder,
     frame: u64,
+    framerate: u32,
+    first_timestamp: Option<f64>,
+    /// The last sample, which is written once the start of the next one, i.e. its duration, is
+    /// known.
+    pending: Option<Mp4Sample>,
     width: u16,
     height: u16,
+    color_space: ColorSpace,
+    odd_dimensions: OddDimensions,
+    keyframe_interval: Option<u64>,
+    faststart: Option<fn(&mut W) -> Result<()>>,
 }
 
 impl<W: Write + Seek> Mp4Openh264Encoder<W> {
     /// Creates a new MP4 encoder that writes the MP4 to the given writer, e.g. a file.
     /// The width and height of the video should match the dimensions of the images.
+    #[cfg(feature = "mp4_openh264")]
     pub fn new(writer: W, width: u16, height: u16) -> Result<Self> {
         Self::new_with_config(writer, width, height, EncoderConfig::new())
     }
@@ -40,12 +85,27 @@ impl<W: Write + Seek> Mp4Openh264Encoder<W> {
     /// Creates a new MP4 encoder that writes the MP4 to the given writer, e.g. a file.
     /// The width and height of the video should match the dimensions of the images.
     /// The encoder configuration can be used to set the desired quality and other parameters.
+    #[cfg(feature = "mp4_openh264")]
     pub fn new_with_config(
         writer: W,
         width: u16,
         height: u16,
         config: EncoderConfig,
     ) -> Result<Self> {
+        Self::new_with_backend(writer, width, height, Openh264Backend::Source, config)
+    }
+
+    /// Creates a new MP4 encoder that writes the MP4 to the given writer, e.g. a file, and uses
+    /// the given OpenH264 backend, e.g. to load a prebuilt libopenh264 at runtime.
+    /// The width and height of the video should match the dimensions of the images.
+    pub fn new_with_backend(
+        writer: W,
+        width: u16,
+        height: u16,
+        backend: Openh264Backend,
+        config: EncoderConfig,
+    ) -> Result<Self> {
+        let api = backend.load()?;
         let mp4 = Mp4Writer::write_start(
             writer,
             &Mp4Config {
@@ -59,90 +119,370 @@ impl<W: Write + Seek> Mp4Openh264Encoder<W> {
                 ],
                 timescale: 1000,
             },
-        )?;
+        )
+        .map_err(Error::mux)?;
 
         Ok(Self {
             mp4,
             mp4_track_added: false,
-            openh264: Openh264Encoder::with_api_config(OpenH264API::from_source(), config)?,
+            openh264: Openh264Encoder::with_api_config(api, config).map_err(Error::codec)?,
             frame: 0,
+            framerate: 60,
+            first_timestamp: None,
+            pending: None,
             width,
             height,
+            color_space: ColorSpace::default(),
+            odd_dimensions: OddDimensions::Pad,
+            keyframe_interval: None,
+            faststart: None,
         })
     }
+
+    /// Sets the framerate of frames without a [timestamp](crate::metadata::TIMESTAMP_KEY).
+    /// Timestamped frames are placed at their timestamps, relative to the first frame, so the
+    /// video plays at the pace of the capture clock. Defaults to 60.
+    pub fn with_framerate(mut self, framerate: u32) -> Self {
+        self.framerate = framerate.max(1);
+        self
+    }
+
+    /// Sets the color space the frames are converted to. It is also written into the video, so
+    /// players convert the frames back correctly. Defaults to limited range BT.709.
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Encodes every `interval`th frame as a keyframe. By default, only the first frame and the
+    /// frames forced with [`FrameMetadata::force_keyframe`] are keyframes.
+    pub fn with_keyframe_interval(mut self, interval: u64) -> Self {
+        self.keyframe_interval = Some(interval.max(1));
+        self
+    }
+
+    /// Sets how frames with odd dimensions are made even, which H.264 requires.
+    /// Defaults to [`OddDimensions::Pad`]. The video has the padded or cropped dimensions, padded
+    /// frames are displayed with the aspect ratio of the source.
+    pub fn with_odd_dimensions(mut self, odd_dimensions: OddDimensions) -> Self {
+        self.odd_dimensions = odd_dimensions;
+        self
+    }
+
+    /// Writes the pending sample, which lasts until the given start of the next sample, if any.
+    fn write_pending(&mut self, next_start_time: Option<u64>) -> Result<()> {
+        let Some(mut sample) = self.pending.take() else {
+            return Ok(());
+        };
+        if let Some(next_start_time) = next_start_time {
+            sample.duration = (next_start_time - sample.start_time)
+                .try_into()
+                .unwrap_or(u32::MAX);
+        }
+        self.mp4.write_sample(1, &sample).map_err(Error::mux)
+    }
+}
+
+impl<W: Read + Write + Seek> Mp4Openh264Encoder<W> {
+    /// Moves the moov atom to the front of the file when the capture finishes, so the video can
+    /// start playing before it is fully downloaded, e.g. when streamed from a web server.
+    /// See [`faststart`](super::faststart).
+    pub fn with_faststart(mut self, faststart: bool) -> Self {
+        self.faststart = faststart.then_some(faststart::<W>);
+        self
+    }
+
+    /// Creates a new MP4 encoder that writes the MP4 to the given writer, e.g. a file, configured
+    /// by the given config. The width and height of the video should match the dimensions of the
+    /// images.
+    pub fn from_config(
+        writer: W,
+        width: u16,
+        height: u16,
+        config: &Mp4Openh264Config,
+    ) -> Result<Self> {
+        let mut encoder = Self::new_with_backend(
+            writer,
+            width,
+            height,
+            config.backend.clone(),
+            EncoderConfig::new(),
+        )?
+        .with_framerate(config.framerate)
+        .with_color_space(config.color_space)
+        .with_odd_dimensions(config.odd_dimensions)
+        .with_faststart(config.faststart);
+        if let Some(interval) = config.keyframe_interval {
+            encoder = encoder.with_keyframe_interval(interval);
+        }
+        Ok(encoder)
+    }
+}
+
+/// The configuration of an [`Mp4Openh264Encoder`], which can be cloned and reused to create the
+/// encoders of many captures, see [`Mp4Openh264Encoder::from_config`]. The builder methods and
+/// defaults are the same as those of the encoder.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(all(feature = "serde", feature = "mp4_openh264"), serde(default))]
+pub struct Mp4Openh264Config {
+    backend: Openh264Backend,
+    framerate: u32,
+    color_space: ColorSpace,
+    odd_dimensions: OddDimensions,
+    keyframe_interval: Option<u64>,
+    faststart: bool,
+}
+
+#[cfg(feature = "mp4_openh264")]
+impl Default for Mp4Openh264Config {
+    fn default() -> Self {
+        Self::new_with_backend(Openh264Backend::Source)
+    }
+}
+
+impl Mp4Openh264Config {
+    /// Creates a new config with the defaults of the [`Mp4Openh264Encoder`], using OpenH264
+    /// compiled from source.
+    #[cfg(feature = "mp4_openh264")]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new config with the defaults of the [`Mp4Openh264Encoder`], using the given
+    /// OpenH264 backend.
+    pub fn new_with_backend(backend: Openh264Backend) -> Self {
+        Self {
+            backend,
+            framerate: 60,
+            color_space: ColorSpace::default(),
+            odd_dimensions: OddDimensions::Pad,
+            keyframe_interval: None,
+            faststart: false,
+        }
+    }
+
+    /// Sets the framerate, see [`Mp4Openh264Encoder::with_framerate`].
+    pub fn with_framerate(mut self, framerate: u32) -> Self {
+        self.framerate = framerate.max(1);
+        self
+    }
+
+    /// Sets the color space, see [`Mp4Openh264Encoder::with_color_space`].
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Sets the keyframe interval, see [`Mp4Openh264Encoder::with_keyframe_interval`].
+    pub fn with_keyframe_interval(mut self, interval: u64) -> Self {
+        self.keyframe_interval = Some(interval.max(1));
+        self
+    }
+
+    /// Sets how odd dimensions are made even, see [`Mp4Openh264Encoder::with_odd_dimensions`].
+    pub fn with_odd_dimensions(mut self, odd_dimensions: OddDimensions) -> Self {
+        self.odd_dimensions = odd_dimensions;
+        self
+    }
+
+    /// Sets whether the moov atom is moved to the front, see
+    /// [`Mp4Openh264Encoder::with_faststart`].
+    pub fn with_faststart(mut self, faststart: bool) -> Self {
+        self.faststart = faststart;
+        self
+    }
+
+    /// Returns the OpenH264 backend.
+    pub fn backend(&self) -> &Openh264Backend {
+        &self.backend
+    }
+
+    /// Returns the framerate of frames without a timestamp.
+    pub fn framerate(&self) -> u32 {
+        self.framerate
+    }
+
+    /// Returns the color space.
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Returns the keyframe interval, if set.
+    pub fn keyframe_interval(&self) -> Option<u64> {
+        self.keyframe_interval
+    }
+
+    /// Returns how odd dimensions are made even.
+    pub fn odd_dimensions(&self) -> OddDimensions {
+        self.odd_dimensions
+    }
+
+    /// Returns whether the moov atom is moved to the front.
+    pub fn faststart(&self) -> bool {
+        self.faststart
+    }
 }
 
 impl<W: Write + Seek> Encoder for Mp4Openh264Encoder<W> {
     fn encode(&mut self, image: &Image) -> Result<()> {
-        let image = image.clone().try_into_dynamic()?;
-        let buffer = image.to_rgba8();
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        if self.frame == 0 {
+            self.odd_dimensions.check(image.width(), image.height())?;
+            self.odd_dimensions.warn(image.width(), image.height());
+        }
+        let rgba = self
+            .odd_dimensions
+            .apply(to_rgba8(image)?, image.width(), image.height());
+        let (width, height) = self.odd_dimensions.even(image.width(), image.height());
+        let (width, height) = (width as usize, height as usize);
+        let yuv = to_i420(&rgba, width, height, self.color_space);
+
+        let interval_elapsed = self
+            .keyframe_interval
+            .is_some_and(|interval| self.frame > 0 && self.frame % interval == 0);
+        if metadata.is_keyframe_forced() || interval_elapsed {
+            self.openh264.force_intra_frame();
+        }
+
+        // The start of the frame in milliseconds, the timescale of the video.
+        let start_time = match metadata.timestamp() {
+            Some(timestamp) => {
+                let first = *self.first_timestamp.get_or_insert(timestamp);
+                ((timestamp - first).max(0.0) * 1000.0).round() as u64
+            }
+            None => self.frame * 1000 / self.framerate as u64,
+        };
+        let start_time = match &self.pending {
+            Some(pending) => start_time.max(pending.start_time + 1),
+            None => start_time,
+        };
+
+        let bitstream = self
+            .openh264
+            .encode_at(
+                &YUVBuffer::from_vec(yuv, width, height),
+                Timestamp::from_millis(start_time),
+            )
+            .map_err(Error::codec)?;
 
-        let bitstream = self.openh264.encode_at(
-            &YUVBuffer::from_rgb_source(ImageSource(buffer)),
-            Timestamp::from_millis(self.frame * 100),
-        )?;
+        // The rate control may skip frames, the previous sample then lasts until the next one.
+        if bitstream.num_layers() == 0 {
+            self.frame += 1;
+            return Ok(());
+        }
 
         if !self.mp4_track_added {
-            let layer_0 = bitstream.layer(0).unwrap();
-            self.mp4.add_track(&TrackConfig {
-                track_type: TrackType::Video,
-                timescale: 1000,
-                language: "und".to_string(),
-                media_conf: MediaConfig::AvcConfig(AvcConfig {
-                    width: self.width,
-                    height: self.height,
-                    seq_param_set: remove_nal_start_code(layer_0.nal_unit(0).unwrap()).to_vec(),
-                    pic_param_set: remove_nal_start_code(layer_0.nal_unit(1).unwrap()).to_vec(),
-                }),
-            })?;
+            let layer_0 = bitstream
+                .layer(0)
+                .ok_or_else(|| Error::codec("missing parameter sets"))?;
+            let nal_unit = |index| {
+                layer_0
+                    .nal_unit(index)
+                    .map(remove_nal_start_code)
+                    .ok_or_else(|| Error::codec("missing parameter sets"))
+            };
+            let sps = nal_unit(0)?;
+            let pps = nal_unit(1)?;
+            let (width, height) = (self.width as u32, self.height as u32);
+            let sample_aspect_ratio = self.odd_dimensions.sample_aspect_ratio(width, height);
+            let seq_param_set = self
+                .color_space
+                .write_to_h264_sps_with_sample_aspect_ratio(sps, sample_aspect_ratio)?;
+            let (width, height) = self.odd_dimensions.even(width, height);
+            self.mp4
+                .add_track(&TrackConfig {
+                    track_type: TrackType::Video,
+                    timescale: 1000,
+                    language: "und".to_string(),
+                    media_conf: MediaConfig::AvcConfig(AvcConfig {
+                        width: width as u16,
+                        height: height as u16,
+                        seq_param_set,
+                        pic_param_set: pps.to_vec(),
+                    }),
+                })
+                .map_err(Error::mux)?;
             self.mp4_track_added = true;
         }
 
         let mut bytes = Vec::new();
-        for l in 0..bitstream.num_layers() {
-            let layer = bitstream.layer(l).unwrap();
+        for layer in (0..bitstream.num_layers()).filter_map(|l| bitstream.layer(l)) {
             if layer.is_video() {
-                for n in 0..layer.nal_count() {
-                    let nal = remove_nal_start_code(layer.nal_unit(n).unwrap());
+                for nal in (0..layer.nal_count()).filter_map(|n| layer.nal_unit(n)) {
+                    let nal = remove_nal_start_code(nal);
                     bytes.extend_from_slice(&u32::to_be_bytes(nal.len() as u32));
                     bytes.extend_from_slice(nal);
                 }
             }
         }
 
-        self.mp4.write_sample(
-            1,
-            &Mp4Sample {
-                start_time: self.frame * 100,
-                duration: 100,
-                rendering_offset: 0,
-                is_sync: matches!(bitstream.frame_type(), FrameType::I | FrameType::IDR),
-                bytes: bytes.into(),
-            },
-        )?;
+        // The duration of the last frame is not known, so it is shown for its delta time or a
+        // frame of the framerate.
+        let duration = match metadata.delta_time() {
+            Some(delta_time) => (delta_time * 1000.0).round() as u32,
+            None => 1000 / self.framerate,
+        };
+        self.write_pending(Some(start_time))?;
+        self.pending = Some(Mp4Sample {
+            start_time,
+            duration: duration.max(1),
+            rendering_offset: 0,
+            is_sync: matches!(bitstream.frame_type(), FrameType::I | FrameType::IDR),
+            bytes: bytes.into(),
+        });
 
         self.frame += 1;
         Ok(())
     }
 
     fn finish(mut self: Box<Self>) {
+        if let Err(err) = self.write_pending(None) {
+            bevy::log::error!("Failed to write the last mp4 sample: {}", err);
+        }
         if let Err(err) = self.mp4.write_end() {
             bevy::log::error!("Failed to write mp4 end: {}", err);
+            return;
         }
+
+        if let Some(faststart) = self.faststart {
+            if let Err(err) = faststart(&mut self.mp4.into_writer()) {
+                bevy::log::error!("Failed to move the mp4 moov atom to the front: {}", err);
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "mp4_openh264"
     }
 }
 
-struct ImageSource(RgbaImage);
+/// Converts RGBA pixels to planar YUV 4:2:0, averaging the chroma of 2x2 blocks.
+fn to_i420(rgba: &[u8], width: usize, height: usize, color_space: ColorSpace) -> Vec<u8> {
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let mut yuv = vec![0; width * height + 2 * chroma_width * chroma_height];
+    let (y_plane, chroma) = yuv.split_at_mut(width * height);
+    let (u_plane, v_plane) = chroma.split_at_mut(chroma_width * chroma_height);
 
-impl RGBSource for ImageSource {
-    fn dimensions(&self) -> (usize, usize) {
-        (self.0.width() as usize, self.0.height() as usize)
+    let mut uv_sums = vec![[0u32; 3]; chroma_width * chroma_height];
+    for (i, pixel) in rgba.chunks_exact(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let [luma, u, v] = color_space.rgb_to_yuv(pixel[0], pixel[1], pixel[2]);
+        y_plane[i] = luma;
+        let sum = &mut uv_sums[(y / 2) * chroma_width + x / 2];
+        sum[0] += u as u32;
+        sum[1] += v as u32;
+        sum[2] += 1;
     }
-
-    fn pixel_f32(&self, x: usize, y: usize) -> (f32, f32, f32) {
-        let [r, g, b, _] = self.0.get_pixel(x as u32, y as u32).0;
-        (r as f32, g as f32, b as f32)
+    for (i, [u, v, count]) in uv_sums.into_iter().enumerate() {
+        u_plane[i] = ((u + count / 2) / count) as u8;
+        v_plane[i] = ((v + count / 2) / count) as u8;
     }
+
+    yuv
 }
 
 fn remove_nal_start_code(nal: &[u8]) -> &[u8] {
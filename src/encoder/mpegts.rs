@@ -0,0 +1,420 @@
+//! Mux H.264 or H.265 video into an MPEG transport stream (MPEG-TS), the container of many
+//! streaming protocols (HLS, SRT, UDP multicast) and broadcast or set-top pipelines.
+//!
+//! The [`TsMuxer`] writes a single program with one video stream. The presentation timestamps of
+//! the frames are passed in by the caller, e.g. the elapsed time of the
+//! [`CaptureClock`](crate::CaptureClock), and the program clock reference (PCR) is derived from
+//! them, so the stream plays at the pace the frames were rendered at, even if the framerate
+//! varied. The program tables are repeated before every keyframe, so players can join the stream
+//! at any keyframe.
+//!
+//! The frames must be in decoding order and without B-frames, as only presentation timestamps
+//! are written. The [`Mp4FfmpegCliEncoder`](super::mp4_ffmpeg_cli::Mp4FfmpegCliEncoder) writes
+//! MPEG-TS with this muxer, see its `with_container`.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::mpegts::*;
+//! # use std::{fs::File, time::Duration};
+//! #
+//! let mut muxer = TsMuxer::new(File::create("capture.ts")?, StreamType::H264);
+//! for (index, (access_unit, keyframe)) in split_access_units(&h264, StreamType::H264).enumerate() {
+//!     muxer.write_access_unit(access_unit, Duration::from_secs_f64(index as f64 / 60.0), keyframe)?;
+//! }
+//! muxer.finish()?;
+//! ```
+
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+/// The size of a transport stream packet.
+pub const PACKET_SIZE: usize = 188;
+
+/// The PID of the program map table.
+const PMT_PID: u16 = 0x1000;
+
+/// The PID of the video stream, which also carries the PCR.
+const VIDEO_PID: u16 = 0x100;
+
+/// The presentation timestamp of the first frame in 90 kHz ticks, which leaves room for the PCR
+/// to start before it.
+const PTS_OFFSET: u64 = 126_000;
+
+/// How far the PCR runs ahead of the presentation timestamps in 90 kHz ticks, i.e. how long
+/// decoders buffer frames.
+const PCR_DELAY: u64 = 63_000;
+
+/// The video codec of a transport stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StreamType {
+    /// H.264 (AVC) in Annex B format.
+    #[default]
+    H264,
+    /// H.265 (HEVC) in Annex B format.
+    H265,
+}
+
+impl StreamType {
+    /// Returns the stream type of the program map table.
+    fn id(self) -> u8 {
+        match self {
+            Self::H264 => 0x1b,
+            Self::H265 => 0x24,
+        }
+    }
+
+    /// Returns the type of a NAL unit from its first byte(s).
+    fn nal_type(self, nal: &[u8]) -> Option<u8> {
+        let header = *nal.first()?;
+        Some(match self {
+            Self::H264 => header & 0x1f,
+            Self::H265 => (header >> 1) & 0x3f,
+        })
+    }
+
+    fn is_access_unit_delimiter(self, nal_type: u8) -> bool {
+        match self {
+            Self::H264 => nal_type == 9,
+            Self::H265 => nal_type == 35,
+        }
+    }
+
+    fn is_keyframe(self, nal_type: u8) -> bool {
+        match self {
+            // IDR slice.
+            Self::H264 => nal_type == 5,
+            // IRAP pictures (BLA, IDR, CRA).
+            Self::H265 => (16..=21).contains(&nal_type),
+        }
+    }
+}
+
+/// Writes video access units into an MPEG transport stream, see the [module docs](self).
+pub struct TsMuxer<W: Write> {
+    writer: W,
+    stream_type: StreamType,
+    continuity: [u8; 3],
+    tables_written: bool,
+}
+
+impl<W: Write> TsMuxer<W> {
+    /// Creates a new muxer that writes the stream to the given writer.
+    pub fn new(writer: W, stream_type: StreamType) -> Self {
+        Self {
+            writer,
+            stream_type,
+            continuity: [0; 3],
+            tables_written: false,
+        }
+    }
+
+    /// Writes an access unit, i.e. all NAL units of a frame in Annex B format, with the given
+    /// presentation timestamp. Keyframes are marked as random access points.
+    pub fn write_access_unit(
+        &mut self,
+        data: &[u8],
+        timestamp: Duration,
+        keyframe: bool,
+    ) -> io::Result<()> {
+        if keyframe || !self.tables_written {
+            self.write_tables()?;
+        }
+
+        // 33 bit timestamps in 90 kHz ticks, wrapping after about 26.5 hours.
+        let pts = (PTS_OFFSET + (timestamp.as_nanos() * 9 / 100_000) as u64) & ((1 << 33) - 1);
+        let pcr = pts.wrapping_sub(PCR_DELAY) & ((1 << 33) - 1);
+
+        let mut pes = Vec::with_capacity(data.len() + 14);
+        // Video stream 0, unbounded length.
+        pes.extend_from_slice(&[0x00, 0x00, 0x01, 0xe0, 0x00, 0x00]);
+        // Data aligned, PTS only, 5 bytes of header data.
+        pes.extend_from_slice(&[0x84, 0x80, 0x05]);
+        pes.extend_from_slice(&encode_timestamp(0x20, pts));
+        pes.extend_from_slice(data);
+
+        let mut payload = &pes[..];
+        let mut first = true;
+        while !payload.is_empty() {
+            let mut adaptation = Vec::new();
+            if first {
+                let flags = if keyframe { 0x50 } else { 0x10 };
+                adaptation.push(flags);
+                adaptation.extend_from_slice(&encode_pcr(pcr));
+            }
+            let len = self.write_packet(VIDEO_PID, first, adaptation, payload)?;
+            payload = &payload[len..];
+            first = false;
+        }
+        Ok(())
+    }
+
+    /// Flushes the writer and returns it.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Writes the program association table and the program map table.
+    fn write_tables(&mut self) -> io::Result<()> {
+        // One program, number 1.
+        let mut pat = vec![0x00, 0x01, 0xc1, 0x00, 0x00, 0x00, 0x01];
+        pat.extend_from_slice(&(0xe000 | PMT_PID).to_be_bytes());
+        self.write_section(0, 0x00, &pat)?;
+
+        let mut pmt = vec![0x00, 0x01, 0xc1, 0x00, 0x00];
+        pmt.extend_from_slice(&(0xe000 | VIDEO_PID).to_be_bytes());
+        pmt.extend_from_slice(&[0xf0, 0x00, self.stream_type.id()]);
+        pmt.extend_from_slice(&(0xe000 | VIDEO_PID).to_be_bytes());
+        pmt.extend_from_slice(&[0xf0, 0x00]);
+        self.write_section(PMT_PID, 0x02, &pmt)?;
+
+        self.tables_written = true;
+        Ok(())
+    }
+
+    /// Writes a table section into a single packet. `data` is the section after the length.
+    fn write_section(&mut self, pid: u16, table_id: u8, data: &[u8]) -> io::Result<()> {
+        let length = data.len() + 4;
+        // The pointer field, then the section with the syntax indicator set.
+        let mut section = vec![0x00, table_id, 0xb0 | (length >> 8) as u8, length as u8];
+        section.extend_from_slice(data);
+        let crc = crc32(&section[1..]);
+        section.extend_from_slice(&crc.to_be_bytes());
+        section.resize(PACKET_SIZE - 4, 0xff);
+        self.write_packet(pid, true, Vec::new(), &section)?;
+        Ok(())
+    }
+
+    /// Writes a packet with as much of the payload as fits, stuffing the adaptation field if the
+    /// payload is shorter. Returns the length of the payload that was written.
+    fn write_packet(
+        &mut self,
+        pid: u16,
+        unit_start: bool,
+        mut adaptation: Vec<u8>,
+        payload: &[u8],
+    ) -> io::Result<usize> {
+        // The adaptation field has a length byte, if present.
+        let adaptation_len = |adaptation: &[u8], present: bool| match present {
+            true => 1 + adaptation.len(),
+            false => 0,
+        };
+        let mut has_adaptation = !adaptation.is_empty();
+        let mut available = PACKET_SIZE - 4 - adaptation_len(&adaptation, has_adaptation);
+        if payload.len() < available {
+            // Fill the rest of the packet with stuffing bytes.
+            if !has_adaptation {
+                has_adaptation = true;
+                available -= 1;
+                if payload.len() < available {
+                    adaptation.push(0x00);
+                }
+            }
+            while payload.len() < PACKET_SIZE - 4 - adaptation_len(&adaptation, true) {
+                adaptation.push(0xff);
+            }
+            available = payload.len();
+        }
+
+        let counter = match pid {
+            0 => &mut self.continuity[0],
+            PMT_PID => &mut self.continuity[1],
+            _ => &mut self.continuity[2],
+        };
+        let control = if has_adaptation { 0x30 } else { 0x10 };
+        let mut packet = Vec::with_capacity(PACKET_SIZE);
+        packet.push(0x47);
+        packet.push(((unit_start as u8) << 6) | (pid >> 8) as u8 & 0x1f);
+        packet.push(pid as u8);
+        packet.push(control | *counter);
+        *counter = (*counter + 1) & 0x0f;
+        if has_adaptation {
+            packet.push(adaptation.len() as u8);
+            packet.extend_from_slice(&adaptation);
+        }
+        packet.extend_from_slice(&payload[..available]);
+        debug_assert_eq!(packet.len(), PACKET_SIZE);
+
+        self.writer.write_all(&packet)?;
+        Ok(available)
+    }
+}
+
+/// Splits an H.264 or H.265 stream in Annex B format into access units, at the access unit
+/// delimiters, and returns them with whether they are keyframes. Data before the first delimiter
+/// belongs to the first access unit.
+pub fn split_access_units(
+    stream: &[u8],
+    stream_type: StreamType,
+) -> impl Iterator<Item = (&[u8], bool)> {
+    let mut starts = Vec::new();
+    let mut keyframes = Vec::new();
+    for nal in nal_units(stream) {
+        let Some(nal_type) = stream_type.nal_type(&stream[nal..]) else {
+            continue;
+        };
+        if stream_type.is_access_unit_delimiter(nal_type) || starts.is_empty() {
+            // The access unit starts at the start code before the NAL unit.
+            let start = if starts.is_empty() {
+                0
+            } else {
+                start_code_start(stream, nal)
+            };
+            starts.push(start);
+            keyframes.push(false);
+        }
+        if stream_type.is_keyframe(nal_type) {
+            *keyframes.last_mut().unwrap() = true;
+        }
+    }
+
+    let ends = starts
+        .iter()
+        .skip(1)
+        .copied()
+        .chain([stream.len()])
+        .collect::<Vec<_>>();
+    starts
+        .into_iter()
+        .zip(ends)
+        .zip(keyframes)
+        .map(|((start, end), keyframe)| (&stream[start..end], keyframe))
+}
+
+/// Returns the offsets of the NAL units after the start codes.
+fn nal_units(stream: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    stream
+        .windows(3)
+        .enumerate()
+        .filter(|(_, window)| *window == [0, 0, 1])
+        .map(|(offset, _)| offset + 3)
+}
+
+/// Returns the start of the start code before the NAL unit, including a leading zero byte.
+fn start_code_start(stream: &[u8], nal: usize) -> usize {
+    match nal >= 4 && stream[nal - 4] == 0 {
+        true => nal - 4,
+        false => nal - 3,
+    }
+}
+
+/// Encodes a 33 bit timestamp of a PES header with the given prefix.
+fn encode_timestamp(prefix: u8, timestamp: u64) -> [u8; 5] {
+    [
+        prefix | ((timestamp >> 29) as u8 & 0x0e) | 1,
+        (timestamp >> 22) as u8,
+        ((timestamp >> 14) as u8 & 0xfe) | 1,
+        (timestamp >> 7) as u8,
+        ((timestamp << 1) as u8 & 0xfe) | 1,
+    ]
+}
+
+/// Encodes a PCR with the given 90 kHz base and no extension.
+fn encode_pcr(base: u64) -> [u8; 6] {
+    [
+        (base >> 25) as u8,
+        (base >> 17) as u8,
+        (base >> 9) as u8,
+        (base >> 1) as u8,
+        ((base & 1) as u8) << 7 | 0x7e,
+        0x00,
+    ]
+}
+
+/// The CRC-32 of MPEG-2 table sections.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = match crc & 0x8000_0000 != 0 {
+                true => (crc << 1) ^ 0x04c1_1db7,
+                false => crc << 1,
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn muxes_mpeg_transport_stream() {
+        use std::time::Duration;
+
+        // Access unit delimiter, SPS and IDR slice, then a P slice, then another IDR slice.
+        let mut stream = Vec::new();
+        stream.extend_from_slice(&[
+            0, 0, 0, 1, 0x09, 0xf0, 0, 0, 0, 1, 0x67, 0x42, 0, 0, 1, 0x65,
+        ]);
+        stream.extend_from_slice(&[0xab; 400]);
+        stream.extend_from_slice(&[0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x41, 0xcd, 0xcd]);
+        stream.extend_from_slice(&[0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x65, 0xef]);
+        let access_units = split_access_units(&stream, StreamType::H264).collect::<Vec<_>>();
+        assert_eq!(
+            access_units.iter().map(|(_, key)| *key).collect::<Vec<_>>(),
+            [true, false, true]
+        );
+
+        // The timestamps of a capture clock with a dropped frame.
+        let timestamps = [0.0, 0.04, 0.1];
+        let mut muxer = TsMuxer::new(Vec::new(), StreamType::H264);
+        for ((access_unit, keyframe), seconds) in access_units.iter().zip(timestamps) {
+            muxer
+                .write_access_unit(access_unit, Duration::from_secs_f64(seconds), *keyframe)
+                .unwrap();
+        }
+        let ts = muxer.finish().unwrap();
+
+        assert_eq!(ts.len() % PACKET_SIZE, 0);
+        let mut tables = 0;
+        let mut pts = Vec::new();
+        let mut pcr = Vec::new();
+        let mut payload = Vec::new();
+        for packet in ts.chunks(PACKET_SIZE) {
+            assert_eq!(packet[0], 0x47);
+            let unit_start = packet[1] & 0x40 != 0;
+            let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+            let mut data = &packet[4..];
+            if packet[3] & 0x20 != 0 {
+                let adaptation = &data[1..1 + data[0] as usize];
+                if adaptation.first().is_some_and(|flags| flags & 0x10 != 0) {
+                    let base = adaptation[1..6]
+                        .iter()
+                        .fold(0u64, |pcr, byte| pcr << 8 | *byte as u64)
+                        >> 7;
+                    pcr.push(base);
+                }
+                data = &data[1 + data[0] as usize..];
+            }
+            match pid {
+                0 => tables += 1,
+                0x1000 => assert_eq!(data[13], 0x1b),
+                0x100 if unit_start => {
+                    assert_eq!(&data[..4], &[0, 0, 1, 0xe0]);
+                    let bytes = &data[9..14];
+                    pts.push(
+                        ((bytes[0] as u64 >> 1) & 0x07) << 30
+                            | (bytes[1] as u64) << 22
+                            | (bytes[2] as u64 >> 1) << 15
+                            | (bytes[3] as u64) << 7
+                            | bytes[4] as u64 >> 1,
+                    );
+                    payload.extend_from_slice(&data[14..]);
+                }
+                0x100 => payload.extend_from_slice(data),
+                pid => panic!("unexpected pid {pid}"),
+            }
+        }
+
+        // The tables are repeated before every keyframe.
+        assert_eq!(tables, 2);
+        assert_eq!(pts, [126_000, 129_600, 135_000]);
+        assert_eq!(pcr, [63_000, 66_600, 72_000]);
+        assert_eq!(payload, stream);
+    }
+}
@@ -0,0 +1,104 @@
+//! Helpers for encoders that pipe raw frames into an external process.
+
+use super::{Error, Result};
+use std::{
+    ffi::OsStr,
+    io::{Read, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    thread::{self, JoinHandle},
+};
+
+/// The number of bytes of the end of stderr that are kept for errors.
+const STDERR_TAIL: usize = 4096;
+
+/// A child process that receives frames on stdin.
+pub(crate) struct ChildPipe {
+    name: String,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stderr: Option<JoinHandle<String>>,
+}
+
+impl ChildPipe {
+    /// Spawns the command with a piped stdin. The output of the process is discarded, except for
+    /// the end of stderr, which is returned in the error if the process fails.
+    pub(crate) fn spawn(name: impl AsRef<OsStr>, mut command: Command) -> Result<Self> {
+        let name = name.as_ref().to_string_lossy().into_owned();
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| Error::external(&name, err))?;
+        let stdin = child.stdin.take();
+        let stderr = read_stderr(&mut child);
+
+        Ok(Self {
+            name,
+            child,
+            stdin,
+            stderr,
+        })
+    }
+
+    /// Writes the data to the stdin of the process. If the process exited, waits for it and
+    /// returns its exit status and stderr.
+    pub(crate) fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let stdin = self.stdin.as_mut().ok_or("stdin is closed")?;
+        if let Err(err) = stdin.write_all(data) {
+            return Err(match self.wait() {
+                Ok(()) => Error::external(&self.name, err),
+                Err(err) => err,
+            });
+        }
+        Ok(())
+    }
+
+    /// Closes stdin and waits for the process to exit.
+    pub(crate) fn finish(mut self) {
+        if let Err(err) = self.wait() {
+            bevy::log::error!("{}", err);
+        }
+    }
+
+    /// Closes stdin, waits for the process to exit and returns an error with the end of stderr if
+    /// it failed.
+    fn wait(&mut self) -> Result<()> {
+        drop(self.stdin.take());
+        wait(&self.name, &mut self.child, self.stderr.take())
+    }
+}
+
+/// Reads the end of the piped stderr of the child on a background thread, see [`wait`].
+pub(crate) fn read_stderr(child: &mut Child) -> Option<JoinHandle<String>> {
+    child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut tail = Vec::new();
+            let mut buffer = [0; 1024];
+            while let Ok(read @ 1..) = stderr.read(&mut buffer) {
+                tail.extend_from_slice(&buffer[..read]);
+                if tail.len() > STDERR_TAIL {
+                    tail.drain(..tail.len() - STDERR_TAIL);
+                }
+            }
+            String::from_utf8_lossy(&tail).trim().to_owned()
+        })
+    })
+}
+
+/// Waits for the child to exit and returns an error with the end of stderr if it failed.
+pub(crate) fn wait(
+    name: &str,
+    child: &mut Child,
+    stderr: Option<JoinHandle<String>>,
+) -> Result<()> {
+    let status = child.wait().map_err(|err| Error::external(name, err))?;
+    let stderr = stderr
+        .and_then(|stderr| stderr.join().ok())
+        .unwrap_or_default();
+    match status.success() {
+        true => Ok(()),
+        false if stderr.is_empty() => Err(Error::external(name, status)),
+        false => Err(Error::external(name, format!("{status}: {stderr}"))),
+    }
+}
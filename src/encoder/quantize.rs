@@ -0,0 +1,526 @@
+//! Reduce frames to a palette of at most 256 colors, for paletted formats like gifs.
+//!
+//! A [`QuantizeConfig`] selects the [`Quantizer`] that picks the colors of the palette, the maximum
+//! number of colors and the [`Dithering`] of the frames, so paletted outputs have a consistent and
+//! tunable quality instead of the defaults of every encoder. Encoders with paletted outputs take a
+//! config, e.g. [`GifEncoder::with_quantization`](super::gif::GifEncoder::with_quantization), and
+//! custom encoders can call [`QuantizeConfig::quantize`].
+//!
+//! Frames with at most the maximum number of colors, e.g. of UIs or pixel art, are not quantized,
+//! their colors are used as they are. Pixels with an alpha below 128 are transparent and get their
+//! own palette entry.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::{gif::GifEncoder, quantize::*};
+//! # use std::fs::File;
+//! #
+//! let encoder = GifEncoder::new(File::create("capture.gif")?).with_quantization(
+//!     QuantizeConfig::new()
+//!         .with_quantizer(Quantizer::Octree)
+//!         .with_max_colors(64)
+//!         .with_dithering(Dithering::FloydSteinberg),
+//! );
+//! ```
+
+use color_quant::NeuQuant;
+use std::collections::HashSet;
+
+/// The maximum number of colors of a palette.
+pub const MAX_COLORS: u16 = 256;
+
+/// The algorithm that picks the colors of the palette.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Quantizer {
+    /// A neural network (NeuQuant), with the best quality for photographic content. Palettes of
+    /// fewer than 64 colors, which NeuQuant doesn't handle well, use median cut instead.
+    #[default]
+    NeuQuant,
+    /// Splits the colors into boxes at the median of their widest channel. Fast, and good for
+    /// small palettes.
+    MedianCut,
+    /// Merges similar colors in an octree. Fast, and keeps rare but distinct colors, e.g. of UI
+    /// elements.
+    Octree,
+}
+
+/// How the colors that are not in the palette are approximated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dithering {
+    /// Every pixel gets the nearest color of the palette, which shows bands in gradients.
+    #[default]
+    None,
+    /// The error of every pixel is diffused to its neighbors (Floyd-Steinberg), which looks best
+    /// but changes between similar frames, so animations shimmer and compress worse.
+    FloydSteinberg,
+    /// The pixels are offset by a 4x4 Bayer matrix, which is stable between frames.
+    Ordered,
+}
+
+/// The configuration of the quantization of frames, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct QuantizeConfig {
+    quantizer: Quantizer,
+    max_colors: u16,
+    dithering: Dithering,
+    speed: i32,
+}
+
+impl Default for QuantizeConfig {
+    fn default() -> Self {
+        Self {
+            quantizer: Quantizer::default(),
+            max_colors: MAX_COLORS,
+            dithering: Dithering::default(),
+            speed: 10,
+        }
+    }
+}
+
+impl QuantizeConfig {
+    /// Creates a new config with NeuQuant, 256 colors, no dithering and a speed of 10.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the algorithm that picks the colors of the palette. Defaults to
+    /// [`Quantizer::NeuQuant`].
+    pub fn with_quantizer(mut self, quantizer: Quantizer) -> Self {
+        self.quantizer = quantizer;
+        self
+    }
+
+    /// Sets the maximum number of colors of the palette, including the transparent color, from 2
+    /// to [`MAX_COLORS`]. Defaults to [`MAX_COLORS`].
+    pub fn with_max_colors(mut self, max_colors: u16) -> Self {
+        self.max_colors = max_colors.clamp(2, MAX_COLORS);
+        self
+    }
+
+    /// Sets the dithering. Defaults to [`Dithering::None`].
+    pub fn with_dithering(mut self, dithering: Dithering) -> Self {
+        self.dithering = dithering;
+        self
+    }
+
+    /// Sets the speed from 1 to 30, i.e. every how many pixels one is sampled to pick the colors
+    /// of the palette. Higher is faster, but can miss small details. Defaults to 10.
+    pub fn with_speed(mut self, speed: i32) -> Self {
+        self.speed = speed.clamp(1, 30);
+        self
+    }
+
+    /// Returns the algorithm that picks the colors of the palette.
+    pub fn quantizer(&self) -> Quantizer {
+        self.quantizer
+    }
+
+    /// Returns the maximum number of colors of the palette.
+    pub fn max_colors(&self) -> u16 {
+        self.max_colors
+    }
+
+    /// Returns the dithering.
+    pub fn dithering(&self) -> Dithering {
+        self.dithering
+    }
+
+    /// Returns the speed.
+    pub fn speed(&self) -> i32 {
+        self.speed
+    }
+
+    /// Reduces tightly packed RGBA8 pixels with the given dimensions to a palette.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of pixels doesn't match the dimensions.
+    pub fn quantize(&self, rgba: &[u8], width: u32, height: u32) -> Quantized {
+        assert_eq!(
+            rgba.len(),
+            width as usize * height as usize * 4,
+            "the number of pixels doesn't match the dimensions"
+        );
+        let has_transparent = rgba.chunks_exact(4).any(is_transparent);
+        let max_colors = self.max_colors as usize - has_transparent as usize;
+        let opaque = || {
+            rgba.chunks_exact(4)
+                .filter(|pixel| !is_transparent(pixel))
+                .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        };
+
+        let mut palette = exact_palette(opaque(), max_colors).unwrap_or_else(|| {
+            let sampled = || opaque().step_by(self.speed as usize);
+            match self.quantizer {
+                Quantizer::NeuQuant if max_colors >= 64 => {
+                    let pixels = opaque()
+                        .flat_map(|[r, g, b]| [r, g, b, 255])
+                        .collect::<Vec<_>>();
+                    NeuQuant::new(self.speed, max_colors, &pixels)
+                        .color_map_rgb()
+                        .chunks_exact(3)
+                        .map(|color| [color[0], color[1], color[2]])
+                        .collect()
+                }
+                Quantizer::NeuQuant | Quantizer::MedianCut => {
+                    median_cut(sampled().collect(), max_colors)
+                }
+                Quantizer::Octree => octree(sampled(), max_colors),
+            }
+        });
+        if palette.is_empty() {
+            palette.push([0, 0, 0]);
+        }
+
+        let indices = map_pixels(rgba, width, &palette, has_transparent, self.dithering);
+        let transparent = has_transparent.then(|| {
+            palette.push([0, 0, 0]);
+            (palette.len() - 1) as u8
+        });
+        Quantized {
+            palette,
+            indices,
+            transparent,
+        }
+    }
+}
+
+/// A frame reduced to a palette, see [`QuantizeConfig::quantize`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quantized {
+    /// The colors of the palette.
+    pub palette: Vec<[u8; 3]>,
+    /// The index of the color of every pixel in the palette, row by row.
+    pub indices: Vec<u8>,
+    /// The index of the transparent color, if the frame has transparent pixels.
+    pub transparent: Option<u8>,
+}
+
+impl Quantized {
+    /// Returns the palette as consecutive RGB bytes, e.g. for the color table of a gif.
+    pub fn palette_bytes(&self) -> Vec<u8> {
+        self.palette.iter().flatten().copied().collect()
+    }
+}
+
+fn is_transparent(pixel: &[u8]) -> bool {
+    pixel[3] < 128
+}
+
+/// Returns the colors of the pixels, if there are at most `max_colors`.
+fn exact_palette(colors: impl Iterator<Item = [u8; 3]>, max_colors: usize) -> Option<Vec<[u8; 3]>> {
+    let mut unique = HashSet::new();
+    for color in colors {
+        if unique.insert(color) && unique.len() > max_colors {
+            return None;
+        }
+    }
+    let mut palette = unique.into_iter().collect::<Vec<_>>();
+    palette.sort_unstable();
+    Some(palette)
+}
+
+fn median_cut(colors: Vec<[u8; 3]>, max_colors: usize) -> Vec<[u8; 3]> {
+    // Returns the widest channel of the colors and its range.
+    let widest = |colors: &[[u8; 3]]| {
+        (0..3)
+            .map(|channel| {
+                let values = colors.iter().map(|color| color[channel]);
+                let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+                (channel, range)
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap()
+    };
+
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        let Some((index, channel)) = boxes
+            .iter()
+            .enumerate()
+            .map(|(index, colors)| (index, widest(colors), colors.len()))
+            .filter(|&(_, (_, range), _)| range > 0)
+            .max_by_key(|&(_, (_, range), len)| (range, len))
+            .map(|(index, (channel, _), _)| (index, channel))
+        else {
+            break;
+        };
+        let colors = &mut boxes[index];
+        colors.sort_unstable_by_key(|color| color[channel]);
+        let upper = colors.split_off(colors.len() / 2);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .filter(|colors| !colors.is_empty())
+        .map(|colors| {
+            average(
+                colors.iter().map(|color| color.map(u64::from)),
+                colors.len(),
+            )
+        })
+        .collect()
+}
+
+fn octree(colors: impl Iterator<Item = [u8; 3]>, max_colors: usize) -> Vec<[u8; 3]> {
+    #[derive(Default)]
+    struct Node {
+        // The sum and number of the colors in the subtree.
+        sum: [u64; 3],
+        count: u64,
+        // Indices of the children, `0` for none, since the root is no child.
+        children: [usize; 8],
+        leaf: bool,
+    }
+
+    let mut nodes = vec![Node::default()];
+    // The nodes that can be merged into leaves, by depth.
+    let mut reducible = vec![vec![0]];
+    reducible.resize_with(8, Vec::new);
+    let mut leaves = 0;
+    for color in colors {
+        let mut node = 0;
+        for depth in 0..=8 {
+            let current = &mut nodes[node];
+            for (sum, value) in current.sum.iter_mut().zip(color) {
+                *sum += value as u64;
+            }
+            current.count += 1;
+            if current.leaf {
+                break;
+            }
+
+            let shift = 7 - depth;
+            let child = (((color[0] >> shift) & 1) << 2
+                | ((color[1] >> shift) & 1) << 1
+                | ((color[2] >> shift) & 1)) as usize;
+            node = match current.children[child] {
+                0 => {
+                    let new = nodes.len();
+                    nodes[node].children[child] = new;
+                    nodes.push(Node {
+                        leaf: depth == 7,
+                        ..Default::default()
+                    });
+                    match depth {
+                        7 => leaves += 1,
+                        _ => reducible[depth + 1].push(new),
+                    }
+                    new
+                }
+                child => child,
+            };
+        }
+
+        // Merges the children of the least used node of the deepest level until the palette fits.
+        while leaves > max_colors {
+            let Some(level) = reducible.iter_mut().rev().find(|level| !level.is_empty()) else {
+                break;
+            };
+            let (position, _) = level
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, &node)| nodes[node].count)
+                .unwrap();
+            let node = level.swap_remove(position);
+            let children = nodes[node].children.iter().filter(|&&c| c != 0).count();
+            leaves = leaves + 1 - children;
+            nodes[node].children = [0; 8];
+            nodes[node].leaf = true;
+        }
+    }
+
+    let mut palette = Vec::new();
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        match node.leaf {
+            true => palette.push(average([node.sum].into_iter(), node.count as usize)),
+            false => stack.extend(node.children.iter().filter(|&&child| child != 0)),
+        }
+    }
+    palette
+}
+
+fn average(colors: impl Iterator<Item = [u64; 3]>, count: usize) -> [u8; 3] {
+    let sum = colors.fold([0; 3], |sum: [u64; 3], color| {
+        [sum[0] + color[0], sum[1] + color[1], sum[2] + color[2]]
+    });
+    let count = count.max(1) as u64;
+    sum.map(|sum| ((sum + count / 2) / count) as u8)
+}
+
+/// The 4x4 Bayer matrix of the ordered dithering.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Maps the pixels to the nearest colors of the palette, with the given dithering. Transparent
+/// pixels are mapped to the index after the palette.
+fn map_pixels(
+    rgba: &[u8],
+    width: u32,
+    palette: &[[u8; 3]],
+    has_transparent: bool,
+    dithering: Dithering,
+) -> Vec<u8> {
+    let width = width as usize;
+    let transparent = palette.len() as u8;
+    let mut nearest = Nearest::new(palette);
+    let spread = 255.0 / (palette.len() as f32).cbrt();
+    // The errors diffused to the current and the next row, with a column of padding on each side.
+    let mut errors = [vec![[0.0f32; 3]; width + 2], vec![[0.0f32; 3]; width + 2]];
+
+    let mut indices = Vec::with_capacity(rgba.len() / 4);
+    for (i, pixel) in rgba.chunks_exact(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        if x == 0 && i > 0 {
+            errors.swap(0, 1);
+            errors[1].fill([0.0; 3]);
+        }
+        if has_transparent && is_transparent(pixel) {
+            indices.push(transparent);
+            continue;
+        }
+
+        let offset = match dithering {
+            Dithering::None => [0.0; 3],
+            Dithering::FloydSteinberg => errors[0][x + 1],
+            Dithering::Ordered => {
+                let offset = ((BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5) * spread;
+                [offset; 3]
+            }
+        };
+        let color = [0, 1, 2].map(|c| (pixel[c] as f32 + offset[c]).clamp(0.0, 255.0));
+        let index = nearest.get(color.map(|value| value.round() as u8));
+        indices.push(index);
+
+        if dithering == Dithering::FloydSteinberg {
+            let error = [0, 1, 2].map(|c| color[c] - palette[index as usize][c] as f32);
+            for (row, column, weight) in [
+                (0, x + 2, 7.0),
+                (1, x, 3.0),
+                (1, x + 1, 5.0),
+                (1, x + 2, 1.0),
+            ] {
+                for c in 0..3 {
+                    errors[row][column][c] += error[c] * weight / 16.0;
+                }
+            }
+        }
+    }
+    indices
+}
+
+/// Finds the nearest colors of a palette, caching the results for colors with 6 bits per channel.
+struct Nearest<'a> {
+    palette: &'a [[u8; 3]],
+    cache: Vec<u8>,
+    cached: Vec<bool>,
+}
+
+impl<'a> Nearest<'a> {
+    fn new(palette: &'a [[u8; 3]]) -> Self {
+        Self {
+            palette,
+            cache: vec![0; 1 << 18],
+            cached: vec![false; 1 << 18],
+        }
+    }
+
+    fn get(&mut self, color: [u8; 3]) -> u8 {
+        let key =
+            (color[0] as usize >> 2) << 12 | (color[1] as usize >> 2) << 6 | color[2] as usize >> 2;
+        if !self.cached[key] {
+            let distance = |entry: &[u8; 3]| {
+                (0..3)
+                    .map(|c| (entry[c] as i32 - color[c] as i32).pow(2))
+                    .sum::<i32>()
+            };
+            let (index, _) = self
+                .palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| distance(entry))
+                .unwrap();
+            self.cache[key] = index as u8;
+            self.cached[key] = true;
+        }
+        self.cache[key]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantizes_frames() {
+        // A gradient with more colors than any palette, and a transparent corner.
+        let (width, height) = (64, 32);
+        let mut rgba = (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = (i % width, i / width);
+                [(x * 4) as u8, (y * 8) as u8, 128, 255]
+            })
+            .collect::<Vec<_>>();
+        rgba[3] = 0;
+
+        for quantizer in [Quantizer::NeuQuant, Quantizer::MedianCut, Quantizer::Octree] {
+            for dithering in [
+                Dithering::None,
+                Dithering::FloydSteinberg,
+                Dithering::Ordered,
+            ] {
+                for max_colors in [16, 256] {
+                    let config = QuantizeConfig::new()
+                        .with_quantizer(quantizer)
+                        .with_max_colors(max_colors)
+                        .with_dithering(dithering)
+                        .with_speed(1);
+                    let quantized = config.quantize(&rgba, width, height);
+                    assert!(quantized.palette.len() <= max_colors as usize);
+                    assert_eq!(quantized.indices.len(), (width * height) as usize);
+                    assert!(quantized
+                        .indices
+                        .iter()
+                        .all(|&index| (index as usize) < quantized.palette.len()));
+                    assert_eq!(quantized.transparent, Some(quantized.indices[0]));
+
+                    // Without dithering, the pixels are close to their colors in the palette.
+                    if dithering == Dithering::None && max_colors == 256 {
+                        let error = rgba
+                            .chunks_exact(4)
+                            .zip(&quantized.indices)
+                            .skip(1)
+                            .flat_map(|(pixel, &index)| {
+                                let color = quantized.palette[index as usize];
+                                (0..3).map(move |c| pixel[c].abs_diff(color[c]) as u32)
+                            })
+                            .sum::<u32>()
+                            / (width * height * 3);
+                        assert!(error <= 4, "{quantizer:?}: {error}");
+                    }
+                }
+            }
+        }
+
+        // Frames with few colors keep their colors.
+        let rgba = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+        ]
+        .concat();
+        let quantized = QuantizeConfig::new()
+            .with_max_colors(4)
+            .quantize(&rgba, 2, 2);
+        assert_eq!(quantized.palette, [[0, 0, 255], [0, 255, 0], [255, 0, 0]]);
+        assert_eq!(quantized.indices, [2, 1, 2, 0]);
+        assert_eq!(quantized.transparent, None);
+    }
+}
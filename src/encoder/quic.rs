@@ -0,0 +1,585 @@
+//! Stream frames to remote viewers over QUIC with low latency, e.g. to watch simulations running
+//! on cloud machines.
+//!
+//! The [`QuicEncoder`] listens for viewers on a UDP port. Every frame is compressed as JPEG once
+//! and sent to every viewer on its own unidirectional stream, so a lost packet only delays its own
+//! frame. Viewers always get the most recent frame: while a viewer has too many frames in flight,
+//! newer frames replace each other and only the latest is sent next. The JPEG quality adapts to
+//! the congestion, it is lowered while a viewer can't keep up and raised again once all viewers
+//! keep up.
+//!
+//! Every stream starts with a header (all integers little endian), followed by the JPEG data until
+//! the end of the stream:
+//!
+//! | Field   | Type      | Description                        |
+//! | ------- | --------- | ---------------------------------- |
+//! | magic   | `[u8; 4]` | Always `b"BCQF"`.                  |
+//! | frame   | `u64`     | The index of the frame.            |
+//! | width   | `u32`     | The width of the frame.            |
+//! | height  | `u32`     | The height of the frame.           |
+//! | quality | `u8`      | The JPEG quality of the frame.     |
+//!
+//! The connections are encrypted with TLS 1.3 and use the ALPN protocol `bcap`. By default, a
+//! self-signed [`QuicCertificate`] for `localhost` is generated, which viewers have to trust, see
+//! [`QuicClient`]. Browsers can't connect directly, as they only support QUIC through WebTransport,
+//! which is not implemented.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::quic::*;
+//! #
+//! // On the simulation server.
+//! let certificate = QuicCertificate::self_signed(["sim.example.com"])?;
+//! std::fs::write("sim.der", certificate.certificate_der())?;
+//! let encoder = QuicEncoder::bind_with_certificate("0.0.0.0:4433", certificate)?
+//!     .with_quality(40, 90);
+//!
+//! // On the viewer.
+//! let certificate = std::fs::read("sim.der")?;
+//! let mut client = QuicClient::connect(address, "sim.example.com", &certificate)?;
+//! while let Some(frame) = client.read_frame()? {
+//!     let image = frame.decode()?;
+//! }
+//! ```
+
+use super::{to_dynamic_image, BoxedError, Encoder, Error, Result};
+use bevy::prelude::*;
+use image::{codecs::jpeg::JpegEncoder, ImageFormat, RgbaImage};
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    rustls::{
+        self,
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    },
+    ConnectionError, Endpoint, Incoming, TransportConfig,
+};
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use tokio::{
+    runtime::Runtime,
+    sync::{watch, Semaphore},
+    task::JoinSet,
+};
+
+/// The magic bytes at the start of every stream.
+pub const MAGIC: [u8; 4] = *b"BCQF";
+
+/// The ALPN protocol of the connections.
+pub const ALPN: &[u8] = b"bcap";
+
+/// The length of the header of a stream.
+const HEADER_LEN: usize = 21;
+
+/// The number of frames that can be in flight to a viewer.
+const MAX_IN_FLIGHT: usize = 2;
+
+/// How long the frames in flight may take to be delivered when the encoder finishes.
+const FINISH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The maximum size of a stream a [`QuicClient`] reads.
+const MAX_FRAME_SIZE: usize = 64 << 20;
+
+/// The number of frames without congestion after which the quality is raised.
+const RAISE_QUALITY_AFTER: u32 = 30;
+
+type Latest = Option<Arc<Vec<u8>>>;
+
+/// A TLS certificate and its private key, see the [module docs](self).
+#[derive(Clone)]
+pub struct QuicCertificate {
+    certificate: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl QuicCertificate {
+    /// Generates a self-signed certificate for the given domain names or IP addresses.
+    pub fn self_signed(names: impl IntoIterator<Item = impl Into<String>>) -> Result<Self> {
+        let names = names.into_iter().map(Into::into).collect::<Vec<_>>();
+        let certified = rcgen::generate_simple_self_signed(names).map_err(Error::custom)?;
+        Ok(Self {
+            certificate: certified.cert.der().to_vec(),
+            key: certified.key_pair.serialize_der(),
+        })
+    }
+
+    /// Creates a certificate from the DER encoded certificate and PKCS #8 private key.
+    pub fn from_der(certificate: Vec<u8>, key: Vec<u8>) -> Self {
+        Self { certificate, key }
+    }
+
+    /// Returns the DER encoded certificate, which viewers of self-signed certificates need.
+    pub fn certificate_der(&self) -> &[u8] {
+        &self.certificate
+    }
+}
+
+/// An encoder that streams frames to remote viewers over QUIC, see the [module docs](self).
+pub struct QuicEncoder {
+    local_addr: SocketAddr,
+    certificate: QuicCertificate,
+    min_quality: u8,
+    max_quality: u8,
+    frames_without_congestion: u32,
+    stats: QuicStats,
+    sender: Option<watch::Sender<Latest>>,
+    thread: Option<JoinHandle<()>>,
+    frame: u64,
+}
+
+impl QuicEncoder {
+    /// Creates a new encoder that listens for viewers on the given address, e.g. `"0.0.0.0:4433"`,
+    /// with a self-signed certificate for `localhost`.
+    pub fn bind(address: impl ToSocketAddrs) -> Result<Self> {
+        Self::bind_with_certificate(address, QuicCertificate::self_signed(["localhost"])?)
+    }
+
+    /// Creates a new encoder that listens for viewers on the given address with the given
+    /// certificate.
+    pub fn bind_with_certificate(
+        address: impl ToSocketAddrs,
+        certificate: QuicCertificate,
+    ) -> Result<Self> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::Other("no address to bind to".to_owned()))?;
+        let config = server_config(&certificate)?;
+        let stats = QuicStats::default();
+        let (sender, receiver) = watch::channel::<Latest>(None);
+
+        // The endpoint runs on its own runtime, so encoding never waits for the network.
+        let (bound_sender, bound) = mpsc::sync_channel(1);
+        let shared = stats.inner.clone();
+        let thread = thread::Builder::new()
+            .name("capture quic endpoint".to_owned())
+            .spawn(move || {
+                let runtime = match current_thread_runtime() {
+                    Ok(runtime) => runtime,
+                    Err(err) => {
+                        let _ = bound_sender.send(Err(err));
+                        return;
+                    }
+                };
+                runtime.block_on(async move {
+                    let endpoint = match Endpoint::server(config, address) {
+                        Ok(endpoint) => endpoint,
+                        Err(err) => {
+                            let _ = bound_sender.send(Err(err));
+                            return;
+                        }
+                    };
+                    let _ = bound_sender.send(endpoint.local_addr());
+                    accept_viewers(&endpoint, receiver, shared).await;
+                    let _ = tokio::time::timeout(FINISH_TIMEOUT, endpoint.wait_idle()).await;
+                });
+            })?;
+        let local_addr = bound
+            .recv()
+            .map_err(|_| Error::Other("the quic endpoint thread stopped".to_owned()))??;
+
+        let encoder = Self {
+            local_addr,
+            certificate,
+            min_quality: 30,
+            max_quality: 85,
+            frames_without_congestion: 0,
+            stats,
+            sender: Some(sender),
+            thread: Some(thread),
+            frame: 0,
+        };
+        encoder.stats.inner.quality.store(85, Ordering::Relaxed);
+        Ok(encoder)
+    }
+
+    /// Sets the range of the JPEG quality (1-100) the encoder adapts within. The stream starts at
+    /// the maximum quality. Defaults to 30 to 85.
+    pub fn with_quality(mut self, min: u8, max: u8) -> Self {
+        self.max_quality = max.clamp(1, 100);
+        self.min_quality = min.clamp(1, self.max_quality);
+        self.stats
+            .inner
+            .quality
+            .store(self.max_quality, Ordering::Relaxed);
+        self
+    }
+
+    /// Returns the address the encoder listens on, e.g. to get the port if it was bound to port
+    /// `0`.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns the certificate of the encoder.
+    pub fn certificate(&self) -> &QuicCertificate {
+        &self.certificate
+    }
+
+    /// Returns the statistics of the stream. They can be read while the encoder is running.
+    pub fn stats(&self) -> QuicStats {
+        self.stats.clone()
+    }
+
+    /// Lowers the quality while a viewer can't keep up, and raises it after a while without
+    /// congestion.
+    fn adapt_quality(&mut self) -> u8 {
+        let shared = &self.stats.inner;
+        let quality = shared.quality.load(Ordering::Relaxed);
+        let quality = if shared.stalled.load(Ordering::Relaxed) > 0 {
+            self.frames_without_congestion = 0;
+            quality.saturating_sub(10).max(self.min_quality)
+        } else {
+            self.frames_without_congestion += 1;
+            if self.frames_without_congestion < RAISE_QUALITY_AFTER {
+                return quality;
+            }
+            self.frames_without_congestion = 0;
+            quality.saturating_add(5).min(self.max_quality)
+        };
+        shared.quality.store(quality, Ordering::Relaxed);
+        quality
+    }
+}
+
+impl Encoder for QuicEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        let frame = self.frame;
+        self.frame += 1;
+        // Frames are only compressed while someone is watching.
+        if self.stats.viewers() == 0 {
+            return Ok(());
+        }
+
+        let quality = self.adapt_quality();
+        let image = to_dynamic_image(image)?;
+        let mut data = Vec::with_capacity(HEADER_LEN);
+        data.extend_from_slice(&MAGIC);
+        data.extend_from_slice(&frame.to_le_bytes());
+        data.extend_from_slice(&image.width().to_le_bytes());
+        data.extend_from_slice(&image.height().to_le_bytes());
+        data.push(quality);
+        JpegEncoder::new_with_quality(&mut data, quality).encode_image(&image.to_rgb8())?;
+
+        if let Some(sender) = &self.sender {
+            sender.send_replace(Some(Arc::new(data)));
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) {
+        // Closing the channel stops the endpoint once the frames in flight are delivered.
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The quic endpoint thread panicked");
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "quic"
+    }
+
+    fn describe_output(&self) -> String {
+        format!("quic://{}", self.local_addr)
+    }
+}
+
+/// Statistics of a [`QuicEncoder`], shared with the encoder.
+#[derive(Debug, Clone, Default)]
+pub struct QuicStats {
+    inner: Arc<StatsInner>,
+}
+
+#[derive(Debug, Default)]
+struct StatsInner {
+    viewers: AtomicU64,
+    sent: AtomicU64,
+    skipped: AtomicU64,
+    quality: AtomicU8,
+    stalled: AtomicU64,
+}
+
+impl QuicStats {
+    /// Returns the number of connected viewers.
+    pub fn viewers(&self) -> u64 {
+        self.inner.viewers.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of frames that were delivered, summed over all viewers.
+    pub fn sent_frames(&self) -> u64 {
+        self.inner.sent.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of frames that viewers skipped, because their link was too slow.
+    pub fn skipped_frames(&self) -> u64 {
+        self.inner.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the current JPEG quality.
+    pub fn quality(&self) -> u8 {
+        self.inner.quality.load(Ordering::Relaxed)
+    }
+}
+
+fn current_thread_runtime() -> std::io::Result<Runtime> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+}
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut transport = TransportConfig::default();
+    // Viewers stay connected while the app doesn't capture.
+    transport.keep_alive_interval(Some(Duration::from_secs(5)));
+    Arc::new(transport)
+}
+
+fn server_config(certificate: &QuicCertificate) -> Result<quinn::ServerConfig> {
+    let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])
+    .map_err(Error::custom)?
+    .with_no_client_auth()
+    .with_single_cert(
+        vec![CertificateDer::from(certificate.certificate.clone())],
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certificate.key.clone())),
+    )
+    .map_err(Error::custom)?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicServerConfig::try_from(crypto).map_err(Error::custom)?;
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+/// Accepts viewers until the encoder finishes, then waits for them to be served.
+async fn accept_viewers(
+    endpoint: &Endpoint,
+    mut frames: watch::Receiver<Latest>,
+    shared: Arc<StatsInner>,
+) {
+    let mut viewers = JoinSet::new();
+    loop {
+        tokio::select! {
+            incoming = endpoint.accept() => match incoming {
+                Some(incoming) => {
+                    viewers.spawn(serve_viewer(incoming, frames.clone(), shared.clone()));
+                }
+                None => break,
+            },
+            changed = frames.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    while viewers.join_next().await.is_some() {}
+}
+
+/// Sends the latest frames to a viewer, at most [`MAX_IN_FLIGHT`] at a time.
+async fn serve_viewer(
+    incoming: Incoming,
+    mut frames: watch::Receiver<Latest>,
+    shared: Arc<StatsInner>,
+) {
+    let connection = match incoming.await {
+        Ok(connection) => connection,
+        Err(err) => {
+            warn!("Failed to accept a viewer: {}", err);
+            return;
+        }
+    };
+    info!("Viewer {} connected", connection.remote_address());
+    shared.viewers.fetch_add(1, Ordering::Relaxed);
+
+    let permits = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let mut last_frame = None;
+    loop {
+        tokio::select! {
+            changed = frames.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+            _ = connection.closed() => break,
+        }
+        let permit = match permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                // The viewer can't keep up, newer frames replace this one while it waits.
+                shared.stalled.fetch_add(1, Ordering::Relaxed);
+                let permit = tokio::select! {
+                    permit = permits.clone().acquire_owned() => Some(permit.unwrap()),
+                    _ = connection.closed() => None,
+                };
+                shared.stalled.fetch_sub(1, Ordering::Relaxed);
+                match permit {
+                    Some(permit) => permit,
+                    None => break,
+                }
+            }
+        };
+        let Some(data) = frames.borrow_and_update().clone() else {
+            continue;
+        };
+
+        let frame = u64::from_le_bytes(data[4..12].try_into().unwrap());
+        if let Some(last_frame) = last_frame.filter(|&last_frame| frame > last_frame + 1) {
+            shared
+                .skipped
+                .fetch_add(frame - last_frame - 1, Ordering::Relaxed);
+        }
+        last_frame = Some(frame);
+
+        let connection = connection.clone();
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            let result = async {
+                let mut stream = connection.open_uni().await?;
+                stream.write_all(&data).await?;
+                stream.finish()?;
+                // Resolves once the viewer read the whole frame.
+                stream.stopped().await?;
+                Ok::<_, BoxedError>(())
+            };
+            match result.await {
+                Ok(()) => {
+                    shared.sent.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(err) => debug!("Failed to send a frame: {}", err),
+            }
+            drop(permit);
+        });
+    }
+
+    // The frames in flight are delivered before the connection is closed.
+    let _ = tokio::time::timeout(FINISH_TIMEOUT, permits.acquire_many(MAX_IN_FLIGHT as u32)).await;
+    connection.close(0u32.into(), b"finished");
+    shared.viewers.fetch_sub(1, Ordering::Relaxed);
+    info!("Viewer {} disconnected", connection.remote_address());
+}
+
+/// A frame received by a [`QuicClient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuicFrame {
+    /// The index of the frame.
+    pub frame: u64,
+    /// The width of the frame.
+    pub width: u32,
+    /// The height of the frame.
+    pub height: u32,
+    /// The JPEG quality of the frame.
+    pub quality: u8,
+    /// The JPEG data of the frame.
+    pub data: Vec<u8>,
+}
+
+impl QuicFrame {
+    /// Decodes the JPEG data into RGBA8 pixels.
+    pub fn decode(&self) -> Result<RgbaImage> {
+        Ok(image::load_from_memory_with_format(&self.data, ImageFormat::Jpeg)?.to_rgba8())
+    }
+}
+
+/// A viewer that receives frames streamed by a [`QuicEncoder`].
+///
+/// Frames are received in the order they were sent, but frames can be skipped. The client blocks
+/// on its own runtime, so it can be used from any thread.
+pub struct QuicClient {
+    runtime: Runtime,
+    endpoint: Endpoint,
+    connection: quinn::Connection,
+}
+
+impl QuicClient {
+    /// Connects to a [`QuicEncoder`] at the given address. The server name must match the
+    /// certificate of the encoder, which is trusted in addition to the system's root
+    /// certificates, e.g. a self-signed [`QuicCertificate`].
+    pub fn connect(address: SocketAddr, server_name: &str, certificate: &[u8]) -> Result<Self> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(CertificateDer::from(certificate.to_vec()))
+            .map_err(Error::custom)?;
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(Error::custom)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let crypto = QuicClientConfig::try_from(crypto).map_err(Error::custom)?;
+        let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+        config.transport_config(transport_config());
+
+        let runtime = current_thread_runtime()?;
+        let (endpoint, connection) = runtime.block_on(async {
+            let bind: SocketAddr = match address {
+                SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+                SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+            };
+            let mut endpoint = Endpoint::client(bind)?;
+            endpoint.set_default_client_config(config);
+            let connection = endpoint
+                .connect(address, server_name)
+                .map_err(Error::custom)?
+                .await
+                .map_err(Error::custom)?;
+            Ok::<_, Error>((endpoint, connection))
+        })?;
+
+        Ok(Self {
+            runtime,
+            endpoint,
+            connection,
+        })
+    }
+
+    /// Reads the next frame. Returns `None` if the encoder has finished.
+    pub fn read_frame(&mut self) -> Result<Option<QuicFrame>> {
+        self.runtime.block_on(async {
+            let mut stream = match self.connection.accept_uni().await {
+                Ok(stream) => stream,
+                Err(ConnectionError::ApplicationClosed(_) | ConnectionError::LocallyClosed) => {
+                    return Ok(None);
+                }
+                Err(err) => return Err(Error::custom(err)),
+            };
+            let data = stream
+                .read_to_end(MAX_FRAME_SIZE)
+                .await
+                .map_err(Error::custom)?;
+            if data.len() < HEADER_LEN || data[0..4] != MAGIC {
+                return Err(Error::format("invalid quic frame"));
+            }
+            Ok(Some(QuicFrame {
+                frame: u64::from_le_bytes(data[4..12].try_into().unwrap()),
+                width: u32::from_le_bytes(data[12..16].try_into().unwrap()),
+                height: u32::from_le_bytes(data[16..20].try_into().unwrap()),
+                quality: data[20],
+                data: data[HEADER_LEN..].to_vec(),
+            }))
+        })
+    }
+}
+
+impl Drop for QuicClient {
+    fn drop(&mut self) {
+        self.connection.close(0u32.into(), b"closed");
+        // The timer needs the runtime, so it is created in the future.
+        self.runtime.block_on(async {
+            let _ = tokio::time::timeout(FINISH_TIMEOUT, self.endpoint.wait_idle()).await;
+        });
+    }
+}
@@ -0,0 +1,311 @@
+//! Limit the bandwidth of the output of encoders, so captures on render nodes don't saturate the
+//! uplink or shared storage.
+//!
+//! A [`RateLimit`] is a token bucket of bytes that refills at the maximum bandwidth. Writers
+//! created with [`RateLimit::writer`] take the bytes they write from the bucket and are passed to
+//! writer-based encoders, e.g. the [`Y4mEncoder`](super::y4m::Y4mEncoder). What happens if the
+//! bucket is empty depends on the [`RateLimitPolicy`]:
+//!
+//! - [`Wait`](RateLimitPolicy::Wait): the writer blocks until the bucket refilled, so all frames
+//!   are written, but later. Run the encoder on [worker threads](crate::CaptureWorkerSettings), so
+//!   the render thread doesn't wait.
+//! - [`DropFrames`](RateLimitPolicy::DropFrames): the writer never blocks and the bucket goes into
+//!   debt. A [`RateLimitEncoder`] around the encoder drops whole frames until the debt is repaid,
+//!   so streams are never cut in the middle of a frame.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::{rate_limit::*, y4m::Y4mEncoder};
+//! # use std::net::TcpStream;
+//! #
+//! // At most 4 MB/s to the viewer.
+//! let limit = RateLimit::new(4_000_000).with_policy(RateLimitPolicy::DropFrames);
+//! let stream = TcpStream::connect("viewer:7000")?;
+//! let encoder = RateLimitEncoder::new(Y4mEncoder::new(limit.writer(stream)), limit.clone());
+//! ```
+
+use super::{capabilities::EncoderCapabilities, Encoder, Result};
+use crate::metadata::FrameMetadata;
+use bevy::prelude::*;
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// What happens if the output exceeds the bandwidth of a [`RateLimit`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitPolicy {
+    /// The writers block until the bandwidth is available again.
+    #[default]
+    Wait,
+    /// Frames are dropped by the [`RateLimitEncoder`] until the bandwidth is available again.
+    DropFrames,
+}
+
+/// A maximum bandwidth shared by writers and encoders, see the [module docs](self). Clones share
+/// the bandwidth.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    inner: Arc<RateLimitInner>,
+}
+
+#[derive(Debug)]
+struct RateLimitInner {
+    bytes_per_second: f64,
+    burst: f64,
+    policy: RateLimitPolicy,
+    bucket: Mutex<Bucket>,
+    written: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// The available bytes, negative if in debt.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimit {
+    /// Creates a limit of the given number of bytes per second. Up to one second of bandwidth can
+    /// be used at once, see [`with_burst`](Self::with_burst).
+    pub fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1);
+        Self::build(bytes_per_second, bytes_per_second, RateLimitPolicy::Wait)
+    }
+
+    fn build(bytes_per_second: u64, burst: u64, policy: RateLimitPolicy) -> Self {
+        Self {
+            inner: Arc::new(RateLimitInner {
+                bytes_per_second: bytes_per_second as f64,
+                burst: burst as f64,
+                policy,
+                bucket: Mutex::new(Bucket {
+                    tokens: burst as f64,
+                    refilled_at: Instant::now(),
+                }),
+                written: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Sets the number of bytes that can be written at once after the output was idle, e.g. a
+    /// whole frame. Defaults to the bytes of one second.
+    pub fn with_burst(self, burst: u64) -> Self {
+        Self::build(self.bytes_per_second(), burst.max(1), self.inner.policy)
+    }
+
+    /// Sets what happens if the output exceeds the bandwidth. Defaults to
+    /// [`RateLimitPolicy::Wait`].
+    pub fn with_policy(self, policy: RateLimitPolicy) -> Self {
+        Self::build(self.bytes_per_second(), self.inner.burst as u64, policy)
+    }
+
+    /// Returns the maximum number of bytes per second.
+    pub fn bytes_per_second(&self) -> u64 {
+        self.inner.bytes_per_second as u64
+    }
+
+    /// Returns the policy.
+    pub fn policy(&self) -> RateLimitPolicy {
+        self.inner.policy
+    }
+
+    /// Returns the number of bytes written by the writers so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.inner.written.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of frames dropped by the encoders so far.
+    pub fn dropped_frames(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Creates a writer whose output counts against the limit.
+    pub fn writer<W: Write>(&self, writer: W) -> RateLimitedWriter<W> {
+        RateLimitedWriter {
+            inner: writer,
+            limit: self.clone(),
+        }
+    }
+
+    /// Takes up to `len` bytes from the bucket and returns how many were taken. With
+    /// [`RateLimitPolicy::Wait`], this waits until at least one byte is available.
+    fn take(&self, len: usize) -> usize {
+        loop {
+            let mut bucket = self.inner.bucket.lock().unwrap();
+            self.refill(&mut bucket);
+            let taken = match self.inner.policy {
+                RateLimitPolicy::DropFrames => len,
+                RateLimitPolicy::Wait if bucket.tokens >= 1.0 => len.min(bucket.tokens as usize),
+                RateLimitPolicy::Wait => {
+                    let wait = (1.0 - bucket.tokens) / self.inner.bytes_per_second;
+                    drop(bucket);
+                    thread::sleep(Duration::from_secs_f64(wait));
+                    continue;
+                }
+            };
+            bucket.tokens -= taken as f64;
+            return taken;
+        }
+    }
+
+    /// Returns bytes that were taken but not written.
+    fn give_back(&self, len: usize) {
+        let mut bucket = self.inner.bucket.lock().unwrap();
+        bucket.tokens = (bucket.tokens + len as f64).min(self.inner.burst);
+    }
+
+    /// Returns `true` if more bytes were written than the bandwidth allows.
+    fn is_in_debt(&self) -> bool {
+        let mut bucket = self.inner.bucket.lock().unwrap();
+        self.refill(&mut bucket);
+        bucket.tokens < 0.0
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens =
+            (bucket.tokens + elapsed * self.inner.bytes_per_second).min(self.inner.burst);
+        bucket.refilled_at = now;
+    }
+}
+
+/// A writer whose output counts against a [`RateLimit`], see [`RateLimit::writer`].
+pub struct RateLimitedWriter<W> {
+    inner: W,
+    limit: RateLimit,
+}
+
+impl<W> RateLimitedWriter<W> {
+    /// Returns the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for RateLimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let taken = self.limit.take(buf.len());
+        let written = match self.inner.write(&buf[..taken]) {
+            Ok(written) => written,
+            Err(err) => {
+                self.limit.give_back(taken);
+                return Err(err);
+            }
+        };
+        self.limit.give_back(taken - written);
+        self.limit
+            .inner
+            .written
+            .fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An encoder that drops frames while the output of the inner encoder exceeds a [`RateLimit`]
+/// with [`RateLimitPolicy::DropFrames`]. With [`RateLimitPolicy::Wait`], all frames are passed
+/// on.
+pub struct RateLimitEncoder<E> {
+    inner: E,
+    limit: RateLimit,
+}
+
+impl<E: Encoder> RateLimitEncoder<E> {
+    /// Creates a new rate limited encoder. The inner encoder should write to a writer of the limit,
+    /// see [`RateLimit::writer`].
+    pub fn new(inner: E, limit: RateLimit) -> Self {
+        Self { inner, limit }
+    }
+}
+
+impl<E: Encoder> Encoder for RateLimitEncoder<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        if self.limit.policy() == RateLimitPolicy::DropFrames && self.limit.is_in_debt() {
+            self.limit.inner.dropped.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        self.inner.encode_with_metadata(image, metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        Box::new(self.inner).finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    #[test]
+    fn limits_output_bandwidth() {
+        use crate::encoder::raw::RawEncoder;
+
+        // Writers wait once the burst is used up.
+        let limit = RateLimit::new(10_000).with_burst(1_000);
+        let mut writer = limit.writer(Vec::new());
+        let start = Instant::now();
+        writer.write_all(&[0; 3_000]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(writer.into_inner().len(), 3_000);
+        assert_eq!(limit.bytes_written(), 3_000);
+
+        // Frames are dropped while the output exceeds the bandwidth.
+        let image = Image::new_fill(
+            Extent3d {
+                width: 16,
+                height: 8,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 255, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+            default(),
+        );
+        let limit = RateLimit::new(100)
+            .with_burst(1_000)
+            .with_policy(RateLimitPolicy::DropFrames);
+        let mut encoder =
+            RateLimitEncoder::new(RawEncoder::new(limit.writer(Vec::new())), limit.clone());
+        for _ in 0..5 {
+            encoder.encode(&image).unwrap();
+        }
+        assert_eq!(limit.bytes_written(), 2 * 16 * 8 * 4);
+        assert_eq!(limit.dropped_frames(), 3);
+    }
+}
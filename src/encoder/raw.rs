@@ -0,0 +1,73 @@
+//! Encodes frames into a stream of raw RGBA pixels, e.g. for piping into external tools.
+
+use super::{capabilities::EncoderCapabilities, to_rgba8, Encoder, Result};
+use bevy::prelude::*;
+use std::io::{self, Stdout, Write};
+
+/// An encoder that writes the raw RGBA8 pixels of each frame to a writer, without any header.
+///
+/// Writing to stdout allows composing with external tools, e.g.
+/// `my_app | ffmpeg -f rawvideo -pix_fmt rgba -s 512x512 -i - out.mp4`.
+/// Bevy's `LogPlugin` already logs to stderr, so stdout only contains the frames.
+pub struct RawEncoder<W: Write>(W);
+
+impl RawEncoder<Stdout> {
+    /// Creates a new raw encoder that writes the frames to stdout.
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write> RawEncoder<W> {
+    /// Creates a new raw encoder that writes the frames to the given writer.
+    pub fn new(writer: W) -> Self {
+        Self(writer)
+    }
+}
+
+impl<W: Write> Encoder for RawEncoder<W> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.0.write_all(&to_rgba8(image)?)?;
+        self.0.flush()?;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        EncoderCapabilities::rgba8()
+    }
+
+    fn name(&self) -> &str {
+        "raw"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    #[test]
+    fn encodes_raw_stream() {
+        let image = Image::new(
+            Extent3d {
+                width: 2,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![1, 2, 3, 4, 5, 6, 7, 8],
+            TextureFormat::Bgra8UnormSrgb,
+            default(),
+        );
+
+        let mut stream = Vec::new();
+        {
+            let mut encoder = RawEncoder::new(&mut stream);
+            encoder.encode(&image).unwrap();
+            encoder.encode(&image).unwrap();
+        }
+
+        // The RGBA8 pixels of every frame, without a header.
+        assert_eq!(stream, [[3, 2, 1, 4, 7, 6, 5, 8]; 2].concat());
+    }
+}
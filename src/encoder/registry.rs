@@ -0,0 +1,161 @@
+//! Create encoders by name at runtime, e.g. from a console command, a scripting layer or a settings
+//! file (feature `serde`).
+//!
+//! The [`EncoderRegistry`] resource maps names to factories that deserialize the config of an
+//! encoder, e.g. a [`Y4mConfig`], and create it for an output path. The built-in encoders are
+//! registered under their [names](crate::Encoder::name), e.g. `"frames"`, `"gif"` or
+//! `"mp4_ffmpeg_cli"`, custom encoders can be registered with [`EncoderRegistry::register`].
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::registry::EncoderRegistry;
+//! #
+//! fn start_capture(registry: Res<EncoderRegistry>, mut capture: Query<&mut Capture>) {
+//!     let encoders = registry
+//!         .create_from_str("mp4_ffmpeg_cli", "out.mp4", r#"{ "framerate": 30 }"#)
+//!         .unwrap();
+//!     capture.single_mut().start(encoders);
+//! }
+//! ```
+
+use super::{
+    uncompressed::{UncompressedFramesConfig, UncompressedFramesEncoder},
+    y4m::{Y4mConfig, Y4mEncoder},
+    Error, Result,
+};
+use crate::{BoxedEncoder, IntoEncoders};
+use bevy::{prelude::*, utils::HashMap};
+use serde::de::DeserializeOwned;
+use std::{fs::File, path::Path, sync::Arc};
+
+type EncoderFactory =
+    Arc<dyn Fn(&Path, serde_json::Value) -> Result<Vec<BoxedEncoder>> + Send + Sync + 'static>;
+
+/// Maps names to factories that create encoders from their config, see the
+/// [module docs](self).
+///
+/// The resource is inserted by the [`CapturePlugin`](crate::CapturePlugin), with the built-in
+/// encoders of the enabled features registered.
+#[derive(Clone, Resource)]
+pub struct EncoderRegistry {
+    factories: HashMap<String, EncoderFactory>,
+}
+
+impl Default for EncoderRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+
+        #[cfg(feature = "image")]
+        registry.register("frames", |path, config: super::frames::FramesConfig| {
+            Ok(super::frames::FramesEncoder::from_config(path, &config))
+        });
+        registry.register(
+            "uncompressed_frames",
+            |path, config: UncompressedFramesConfig| {
+                Ok(UncompressedFramesEncoder::from_config(path, &config))
+            },
+        );
+        registry.register("y4m", |path, config: Y4mConfig| {
+            Ok(Y4mEncoder::from_config(File::create(path)?, &config))
+        });
+        #[cfg(feature = "gif")]
+        registry.register("gif", |path, config: super::gif::GifConfig| {
+            Ok(super::gif::GifEncoder::from_config(
+                File::create(path)?,
+                &config,
+            ))
+        });
+        #[cfg(feature = "mp4_ffmpeg_cli")]
+        registry.register(
+            "mp4_ffmpeg_cli",
+            |path, config: super::mp4_ffmpeg_cli::Mp4FfmpegCliConfig| {
+                super::mp4_ffmpeg_cli::Mp4FfmpegCliEncoder::from_config(path, &config)
+            },
+        );
+
+        registry
+    }
+}
+
+impl EncoderRegistry {
+    /// Creates a new registry with the built-in encoders of the enabled features.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new registry without any encoders.
+    pub fn empty() -> Self {
+        Self {
+            factories: HashMap::default(),
+        }
+    }
+
+    /// Registers a factory under the given name, replacing the factory registered under the same
+    /// name, if any. The factory is called with the output path and the deserialized config.
+    pub fn register<C, E, F>(&mut self, name: impl Into<String>, factory: F) -> &mut Self
+    where
+        C: DeserializeOwned,
+        E: IntoEncoders,
+        F: Fn(&Path, C) -> Result<E> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        let factory_name = name.clone();
+        self.factories.insert(
+            name,
+            Arc::new(move |path, config| {
+                let config = serde_json::from_value(config).map_err(|err| {
+                    Error::Other(format!(
+                        "invalid config for the {factory_name} encoder: {err}"
+                    ))
+                })?;
+                factory(path, config).map(E::into_encoders)
+            }),
+        );
+        self
+    }
+
+    /// Creates the encoders registered under the given name, writing to the given path and
+    /// configured by the given config. Fields missing from the config fall back to the defaults of
+    /// the encoder.
+    pub fn create(
+        &self,
+        name: &str,
+        path: impl AsRef<Path>,
+        config: serde_json::Value,
+    ) -> Result<Vec<BoxedEncoder>> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| Error::Other(format!("unknown encoder: {name}")))?;
+        factory(path.as_ref(), config)
+    }
+
+    /// Creates the encoders registered under the given name, configured by the given config in
+    /// JSON, see [`create`](Self::create).
+    pub fn create_from_str(
+        &self,
+        name: &str,
+        path: impl AsRef<Path>,
+        config: &str,
+    ) -> Result<Vec<BoxedEncoder>> {
+        let config = serde_json::from_str(config)
+            .map_err(|err| Error::Other(format!("invalid config for the {name} encoder: {err}")))?;
+        self.create(name, path, config)
+    }
+
+    /// Returns whether an encoder is registered under the given name.
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// Returns the names of the registered encoders, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self
+            .factories
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+}
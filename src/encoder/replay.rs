@@ -0,0 +1,237 @@
+//! Keep the most recent frames in memory, e.g. to save them when something goes wrong.
+//!
+//! A [`ReplayBufferEncoder`] keeps the last frames, or the frames of the last seconds, in a ring
+//! buffer instead of encoding them. [`ReplayBuffer::dump`] encodes the buffered frames with other
+//! encoders on a background thread, e.g. to save a clip of the last 30 seconds when the player
+//! asks for it, without recording to disk all the time.
+//! [`Capture::start_replay`](crate::Capture::start_replay) and
+//! [`Capture::dump_replay`](crate::Capture::dump_replay) do the same for a capture.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{encoder::{frames::FramesEncoder, replay::ReplayLength}, Capture};
+//! # use std::time::Duration;
+//! #
+//! fn setup(mut capture: Query<&mut Capture>) {
+//!     capture
+//!         .single_mut()
+//!         .start_replay(ReplayLength::Duration(Duration::from_secs(30)));
+//! }
+//!
+//! fn clip_that(keys: Res<ButtonInput<KeyCode>>, capture: Query<&Capture>) {
+//!     if keys.just_pressed(KeyCode::F9) {
+//!         capture.single().dump_replay(FramesEncoder::new("clips/latest"));
+//!     }
+//! }
+//! ```
+
+use super::{Encoder, Result};
+use crate::{metadata::FrameMetadata, CaptureHandle, Encoders, IntoEncoders};
+use bevy::{
+    prelude::*,
+    utils::{Duration, Instant},
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+};
+#[cfg(feature = "image")]
+use {
+    super::to_dynamic_image,
+    std::{fs, path::Path, sync::TryLockError},
+};
+
+/// How many frames a [`ReplayBufferEncoder`] keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayLength {
+    /// Keeps at most the given number of frames.
+    Frames(usize),
+    /// Keeps the frames of the given wall-clock duration, however many that are.
+    Duration(Duration),
+}
+
+/// An encoder that keeps the last frames in a ring buffer in memory instead of encoding them.
+/// Use [`buffer`](Self::buffer) to access the frames after the encoder was moved into a capture.
+pub struct ReplayBufferEncoder {
+    buffer: ReplayBuffer,
+}
+
+impl ReplayBufferEncoder {
+    /// Creates a new replay buffer encoder that keeps at most `max_frames` frames.
+    pub fn new(max_frames: usize) -> Self {
+        Self::new_with_length(ReplayLength::Frames(max_frames))
+    }
+
+    /// Creates a new replay buffer encoder that keeps the frames of the given length.
+    pub fn new_with_length(length: ReplayLength) -> Self {
+        let length = match length {
+            ReplayLength::Frames(max_frames) => ReplayLength::Frames(max_frames.max(1)),
+            length => length,
+        };
+        let capacity = match length {
+            ReplayLength::Frames(max_frames) => max_frames,
+            ReplayLength::Duration(_) => 0,
+        };
+        Self {
+            buffer: ReplayBuffer(Arc::new(Mutex::new(ReplayBufferState {
+                frames: VecDeque::with_capacity(capacity),
+                length,
+            }))),
+        }
+    }
+
+    /// Returns a handle to the buffered frames.
+    pub fn buffer(&self) -> ReplayBuffer {
+        self.buffer.clone()
+    }
+}
+
+impl Encoder for ReplayBufferEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let now = Instant::now();
+        let mut state = self.buffer.state();
+        match state.length {
+            ReplayLength::Frames(max_frames) => {
+                if state.frames.len() == max_frames {
+                    state.frames.pop_front();
+                }
+            }
+            ReplayLength::Duration(duration) => {
+                while state
+                    .frames
+                    .front()
+                    .is_some_and(|frame| now - frame.at > duration)
+                {
+                    state.frames.pop_front();
+                }
+            }
+        }
+        state.frames.push_back(BufferedFrame {
+            image: Arc::new(image.clone()),
+            metadata: metadata.clone(),
+            at: now,
+        });
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "replay_buffer"
+    }
+}
+
+/// A handle to the frames of a [`ReplayBufferEncoder`]. The frames stay available after the
+/// capture was stopped.
+#[derive(Clone)]
+pub struct ReplayBuffer(Arc<Mutex<ReplayBufferState>>);
+
+struct ReplayBufferState {
+    frames: VecDeque<BufferedFrame>,
+    length: ReplayLength,
+}
+
+/// A buffered frame. The image is shared, so dumps don't copy the frames.
+#[derive(Clone)]
+struct BufferedFrame {
+    image: Arc<Image>,
+    metadata: FrameMetadata,
+    at: Instant,
+}
+
+impl ReplayBuffer {
+    /// Returns the number of buffered frames.
+    pub fn len(&self) -> usize {
+        self.state().frames.len()
+    }
+
+    /// Returns `true` if no frames are buffered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many frames the buffer keeps.
+    pub fn length(&self) -> ReplayLength {
+        self.state().length
+    }
+
+    /// Returns a copy of the buffered frames, oldest first.
+    pub fn frames(&self) -> Vec<Image> {
+        self.state()
+            .frames
+            .iter()
+            .map(|frame| Image::clone(&frame.image))
+            .collect()
+    }
+
+    /// Encodes the currently buffered frames, oldest first and with the metadata they were
+    /// captured with, with the given encoders, e.g. to save a clip of the last seconds. The
+    /// encoders run on a background thread and are finished after the last frame, the returned
+    /// handle is finished once they are. The frames stay in the buffer.
+    pub fn dump(&self, encoders: impl IntoEncoders) -> CaptureHandle {
+        let frames = self.state().frames.clone();
+        let handle = CaptureHandle::default();
+        let mut encoders = Encoders::new(encoders.into_encoders(), handle.clone());
+        let spawned = thread::Builder::new()
+            .name("replay dump".to_string())
+            .spawn(move || {
+                for frame in frames {
+                    for encoder in &mut encoders.encoders {
+                        if let Err(err) = encoder.encode(&frame.image, &frame.metadata) {
+                            error!("Failed to encode the replay buffer: {:?}", err.error);
+                        }
+                    }
+                }
+            });
+        if let Err(err) = spawned {
+            // The encoders are finished when the closure is dropped.
+            error!("Failed to spawn the replay dump thread: {err}");
+        }
+        handle
+    }
+
+    /// Removes all buffered frames.
+    pub fn clear(&self) {
+        self.state().frames.clear();
+    }
+
+    /// Saves the buffered frames as `frame_{index}.png` into the given directory, oldest first.
+    /// Returns the number of saved frames.
+    #[cfg(feature = "image")]
+    pub fn save_frames(&self, directory: impl AsRef<Path>) -> Result<usize> {
+        save_frames(self.state().frames.iter(), directory.as_ref())
+    }
+
+    /// Like [`save_frames`](Self::save_frames), but fails instead of blocking if the buffer is
+    /// currently locked. This is used from panic hooks, where the panicking thread might hold the lock.
+    #[cfg(feature = "image")]
+    pub(crate) fn try_save_frames(&self, directory: &Path) -> Result<usize> {
+        let state = match self.0.try_lock() {
+            Ok(state) => state,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return Err("the replay buffer is locked".into()),
+        };
+        save_frames(state.frames.iter(), directory)
+    }
+
+    fn state(&self) -> MutexGuard<'_, ReplayBufferState> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(feature = "image")]
+fn save_frames<'a>(
+    frames: impl ExactSizeIterator<Item = &'a BufferedFrame>,
+    directory: &Path,
+) -> Result<usize> {
+    fs::create_dir_all(directory)?;
+    let len = frames.len();
+    for (i, frame) in frames.enumerate() {
+        to_dynamic_image(&frame.image)?.save(directory.join(format!("frame_{:06}.png", i)))?;
+    }
+    Ok(len)
+}
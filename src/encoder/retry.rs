@@ -0,0 +1,189 @@
+//! Retry transient failures of an encoder, e.g. a network sink that drops the connection for a
+//! moment or a file that is briefly locked by another process.
+//!
+//! A [`Retry`] passes the frames to the inner encoder. If encoding fails with an error that is
+//! [transient](is_transient), the frame is encoded again after a backoff, until it succeeds or the
+//! maximum number of attempts is reached. Only then is the error returned to the capture.
+//!
+//! The backoff blocks the thread the encoder runs on, so run retrying encoders on
+//! [worker threads](crate::CaptureWorkerSettings) to keep the render thread responsive.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::{ipc::IpcEncoder, retry::Retry};
+//! # use std::time::Duration;
+//! #
+//! let encoder = Retry::new(IpcEncoder::connect("/tmp/viewer.sock")?)
+//!     .with_max_attempts(5)
+//!     .with_backoff(Duration::from_millis(20), Duration::from_millis(500));
+//! ```
+
+use super::{capabilities::EncoderCapabilities, Encoder, Error, Result};
+use crate::metadata::FrameMetadata;
+use bevy::prelude::*;
+use std::{io, thread, time::Duration};
+
+/// An encoder that retries transient failures of the inner encoder with backoff, see the
+/// [module docs](self).
+///
+/// The inner encoder must be able to encode a frame again after encoding it failed.
+pub struct Retry<E> {
+    inner: E,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    retry_if: fn(&Error) -> bool,
+    retries: u64,
+}
+
+impl<E: Encoder> Retry<E> {
+    /// Creates a new retrying encoder that passes the frames to the inner encoder.
+    pub fn new(inner: E) -> Self {
+        Self {
+            inner,
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            retry_if: is_transient,
+            retries: 0,
+        }
+    }
+
+    /// Sets how often a frame is encoded at most, including the first attempt. Defaults to `3`.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry and the maximum delay. The delay doubles with every
+    /// retry of a frame. Defaults to 10 ms and 1 s.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Sets which errors are retried. Defaults to [`is_transient`].
+    pub fn with_retry_if(mut self, retry_if: fn(&Error) -> bool) -> Self {
+        self.retry_if = retry_if;
+        self
+    }
+
+    /// Returns the number of retries so far, over all frames.
+    pub fn retries(&self) -> u64 {
+        self.retries
+    }
+}
+
+impl<E: Encoder> Encoder for Retry<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            let err = match self.inner.encode_with_metadata(image, metadata) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            if attempt >= self.max_attempts || !(self.retry_if)(&err) {
+                return Err(err);
+            }
+
+            debug!(
+                "Encoding failed (attempt {}/{}), retrying in {:?}: {}",
+                attempt, self.max_attempts, backoff, err
+            );
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(self.max_backoff);
+            attempt += 1;
+            self.retries += 1;
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        Box::new(self.inner).finish();
+    }
+}
+
+/// Returns `true` if the error is likely to go away when trying again: I/O errors of interrupted,
+/// timed out or lost connections and busy resources, and failures of external processes or
+/// services.
+pub fn is_transient(err: &Error) -> bool {
+    match err {
+        Error::Io(err) => matches!(
+            err.kind(),
+            io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ResourceBusy
+        ),
+        Error::External { .. } => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_transient_failures() {
+        // Fails with the given errors before it succeeds.
+        struct FlakyEncoder(Vec<Error>);
+
+        impl Encoder for FlakyEncoder {
+            fn encode(&mut self, _image: &Image) -> Result<()> {
+                match self.0.pop() {
+                    Some(err) => Err(err),
+                    None => Ok(()),
+                }
+            }
+        }
+
+        let image = Image::default();
+        let timed_out = || io::Error::from(io::ErrorKind::TimedOut).into();
+        let backoff = Duration::from_millis(1);
+
+        let mut encoder = Retry::new(FlakyEncoder(vec![timed_out(), timed_out()]))
+            .with_backoff(backoff, backoff * 4);
+        encoder.encode(&image).unwrap();
+        assert_eq!(encoder.retries(), 2);
+
+        // The error is returned after the last attempt.
+        let mut encoder = Retry::new(FlakyEncoder(vec![timed_out(), timed_out(), timed_out()]))
+            .with_max_attempts(2)
+            .with_backoff(backoff, backoff);
+        assert!(matches!(encoder.encode(&image), Err(Error::Io(_))));
+        assert_eq!(encoder.retries(), 1);
+        encoder.encode(&image).unwrap();
+
+        // Errors that are not transient are returned right away.
+        let mut encoder = Retry::new(FlakyEncoder(vec![Error::format("odd size")]));
+        assert!(matches!(encoder.encode(&image), Err(Error::Format(_))));
+        assert_eq!(encoder.retries(), 0);
+    }
+}
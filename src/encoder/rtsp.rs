@@ -0,0 +1,152 @@
+//! Pushes frames as an H.264 stream to an RTSP server using GStreamer (gst-launch-1.0 must be in
+//! PATH).
+//!
+//! This crate doesn't serve RTSP itself: the encoder is a client that publishes (`ANNOUNCE`/
+//! `RECORD`) the stream to an RTSP server like MediaMTX, which serves it to viewers.
+
+use super::{
+    gstreamer::{self, GstreamerEncoder},
+    Encoder, Result,
+};
+use crate::{live_settings::LiveEncoderSettings, metadata::FrameMetadata};
+use bevy::prelude::*;
+
+/// An encoder that pushes frames as an H.264 stream to a running RTSP server, e.g. MediaMTX,
+/// which serves the stream to standard RTSP clients (NVRs, VLC, ...). Clients can't connect to the
+/// app directly. gst-launch-1.0 with the `rtspclientsink` element must be in PATH.
+///
+/// Use a separate mount for every capture to expose multiple captures from one app, e.g.
+/// `rtsp://localhost:8554/front` and `rtsp://localhost:8554/back`.
+///
+/// The bitrate can be changed while streaming with [`LiveEncoderSettings`]. The stream is
+/// restarted with the new bitrate at the next keyframe, where clients can resume decoding.
+pub struct RtspPushEncoder {
+    url: String,
+    framerate: u32,
+    bitrate: u32,
+    keyframe_interval: Option<u32>,
+    encoder: Option<GstreamerEncoder>,
+    frames: u64,
+}
+
+impl RtspPushEncoder {
+    /// Creates a new RTSP encoder that pushes to the given server and mount.
+    pub fn new(server: &str, mount: &str) -> Self {
+        Self {
+            url: format!(
+                "{}/{}",
+                server.trim_end_matches('/'),
+                mount.trim_start_matches('/')
+            ),
+            framerate: 60,
+            bitrate: 4000,
+            keyframe_interval: None,
+            encoder: None,
+            frames: 0,
+        }
+    }
+
+    /// Sets the framerate of the stream.
+    pub fn with_framerate(mut self, framerate: u32) -> Self {
+        self.framerate = framerate;
+        self
+    }
+
+    /// Sets the bitrate of the stream in kbit/s.
+    pub fn with_bitrate(mut self, bitrate: u32) -> Self {
+        self.bitrate = bitrate;
+        self
+    }
+
+    /// Sets the maximum number of frames between keyframes. Clients can only start decoding the
+    /// stream at a keyframe. Defaults to the framerate, i.e. one keyframe per second.
+    pub fn with_keyframe_interval(mut self, interval: u32) -> Self {
+        self.keyframe_interval = Some(interval.max(1));
+        self
+    }
+
+    /// Returns the URL the stream is pushed to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns the pipeline that encodes and pushes the raw frames, with the URL quoted.
+    pub fn pipeline(&self) -> String {
+        format!(
+            "videoconvert ! video/x-raw,format=I420 ! x264enc tune=zerolatency bitrate={} \
+             key-int-max={} ! rtspclientsink location={}",
+            self.bitrate,
+            self.keyframe_interval(),
+            gstreamer::quote(&self.url)
+        )
+    }
+
+    fn keyframe_interval(&self) -> u32 {
+        self.keyframe_interval.unwrap_or(self.framerate).max(1)
+    }
+}
+
+impl Encoder for RtspPushEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let keyframe_interval = self.keyframe_interval();
+        if let Some(bitrate) = LiveEncoderSettings::from_metadata(metadata).bitrate() {
+            // x264enc can't change the bitrate of a running pipeline, so it is restarted where
+            // the stream has a keyframe anyway.
+            let keyframe = self.frames.is_multiple_of(keyframe_interval as u64);
+            if bitrate != self.bitrate && (keyframe || metadata.is_keyframe_forced()) {
+                self.bitrate = bitrate;
+                if let Some(encoder) = self.encoder.take() {
+                    Box::new(encoder).finish();
+                }
+                self.frames = 0;
+            }
+        }
+        self.frames += 1;
+
+        if self.encoder.is_none() {
+            self.encoder =
+                Some(GstreamerEncoder::new(self.pipeline()).with_framerate(self.framerate));
+        }
+        self.encoder.as_mut().unwrap().encode(image)
+    }
+
+    fn finish(self: Box<Self>) {
+        if let Some(encoder) = self.encoder {
+            Box::new(encoder).finish();
+        }
+    }
+
+    fn name(&self) -> &str {
+        "rtsp"
+    }
+
+    fn describe_output(&self) -> String {
+        self.url.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_rtsp_push_url() {
+        use crate::encoder::gstreamer;
+
+        // Spaces and `!` stay in the location instead of starting new elements.
+        let encoder = RtspPushEncoder::new("rtsp://localhost:8554/", "/my cam ! fakesink")
+            .with_bitrate(2000)
+            .with_framerate(30);
+        assert_eq!(encoder.url(), "rtsp://localhost:8554/my cam ! fakesink");
+        assert_eq!(
+            encoder.pipeline(),
+            "videoconvert ! video/x-raw,format=I420 ! x264enc tune=zerolatency bitrate=2000 \
+             key-int-max=30 ! rtspclientsink location=\"rtsp://localhost:8554/my cam ! fakesink\""
+        );
+        assert_eq!(gstreamer::quote(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+    }
+}
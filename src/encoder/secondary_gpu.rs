@@ -0,0 +1,381 @@
+//! Converts frames on a secondary GPU, so encode related GPU work doesn't contend with the
+//! rendering on the primary GPU.
+//!
+//! wgpu can't share textures between devices, so the captured frame is still read back from the
+//! primary GPU. The [`SecondaryGpuEncoder`] uploads it to its own device on another adapter,
+//! converts it to RGBA8 there and passes the result to the wrapped encoder. sRGB frames (e.g.
+//! `Bgra8UnormSrgb`) and linear float frames (e.g. HDR `Rgba16Float`) are encoded to
+//! `Rgba8UnormSrgb`, other normalized frames (e.g. `Bgra8Unorm`) keep their values and become
+//! `Rgba8Unorm`.
+//!
+//! The whole frame is converted in a single dispatch, so it must fit into a storage buffer of the
+//! secondary adapter (`width * height * 4` bytes), otherwise the frame is rejected.
+
+use super::{capabilities::EncoderCapabilities, Encoder, Error, Result};
+use crate::metadata::FrameMetadata;
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+    tasks::block_on,
+};
+
+const SHADER: &str = r#"
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var<storage, read_write> output: array<u32>;
+
+fn to_srgb(value: f32) -> f32 {
+    let c = clamp(value, 0.0, 1.0);
+    return select(1.055 * pow(c, 1.0 / 2.4) - 0.055, c * 12.92, c <= 0.0031308);
+}
+
+@compute @workgroup_size(8, 8)
+fn encode_srgb(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(source);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let color = textureLoad(source, vec2<i32>(id.xy), 0);
+    let srgb = vec4<f32>(to_srgb(color.r), to_srgb(color.g), to_srgb(color.b), clamp(color.a, 0.0, 1.0));
+    output[id.y * size.x + id.x] = pack4x8unorm(srgb);
+}
+
+@compute @workgroup_size(8, 8)
+fn copy(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(source);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    let color = textureLoad(source, vec2<i32>(id.xy), 0);
+    output[id.y * size.x + id.x] = pack4x8unorm(clamp(color, vec4<f32>(0.0), vec4<f32>(1.0)));
+}
+"#;
+
+/// The adapter used by the [`SecondaryGpuEncoder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecondaryAdapter {
+    /// The low power adapter, usually an integrated GPU when the game runs on a dedicated one.
+    /// Fails if it is also the high performance adapter Bevy renders on by default, e.g. on
+    /// machines with a single GPU.
+    LowPower,
+    /// The adapter with the given index, see [`SecondaryGpuEncoder::adapters`].
+    Index(usize),
+    /// The first adapter whose name contains the given string.
+    Name(String),
+}
+
+/// An encoder that wraps another encoder and converts every frame to RGBA8 on a secondary GPU.
+pub struct SecondaryGpuEncoder<E> {
+    encoder: E,
+    adapter_info: wgpu::AdapterInfo,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    /// Encodes linear values to sRGB.
+    encode_pipeline: wgpu::ComputePipeline,
+    /// Keeps the values of normalized formats.
+    copy_pipeline: wgpu::ComputePipeline,
+    frame: Option<FrameResources>,
+}
+
+struct FrameResources {
+    size: UVec2,
+    format: TextureFormat,
+    encode_srgb: bool,
+    texture: wgpu::Texture,
+    output: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl<E: Encoder> SecondaryGpuEncoder<E> {
+    /// Returns the info of all available adapters, in the order used by [`SecondaryAdapter::Index`].
+    pub fn adapters() -> Vec<wgpu::AdapterInfo> {
+        wgpu::Instance::default()
+            .enumerate_adapters(wgpu::Backends::all())
+            .iter()
+            .map(|adapter| adapter.get_info())
+            .collect()
+    }
+
+    /// Creates a new secondary GPU encoder on the given adapter.
+    pub fn new(encoder: E, adapter: SecondaryAdapter) -> Result<Self> {
+        let instance = wgpu::Instance::default();
+        let adapter = match &adapter {
+            SecondaryAdapter::LowPower => {
+                let request = |power_preference| {
+                    block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                        power_preference,
+                        force_fallback_adapter: false,
+                        compatible_surface: None,
+                    }))
+                };
+                let primary = request(wgpu::PowerPreference::HighPerformance)
+                    .map(|adapter| adapter.get_info());
+                request(wgpu::PowerPreference::LowPower)
+                    .filter(|adapter| Some(adapter.get_info()) != primary)
+            }
+            SecondaryAdapter::Index(index) => instance
+                .enumerate_adapters(wgpu::Backends::all())
+                .into_iter()
+                .nth(*index),
+            SecondaryAdapter::Name(name) => instance
+                .enumerate_adapters(wgpu::Backends::all())
+                .into_iter()
+                .find(|adapter| adapter.get_info().name.contains(name.as_str())),
+        }
+        .ok_or_else(|| format!("no adapter found for {adapter:?}"))?;
+
+        let (device, queue) = block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("bevy_capture_secondary_gpu"),
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits {
+                    max_storage_buffer_binding_size:
+                        adapter.limits().max_storage_buffer_binding_size,
+                    max_buffer_size: adapter.limits().max_buffer_size,
+                    ..wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits())
+                },
+            },
+            None,
+        ))
+        .map_err(Error::custom)?;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("bevy_capture_secondary_gpu"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let create_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("bevy_capture_secondary_gpu"),
+                layout: None,
+                module: &shader,
+                entry_point,
+                compilation_options: Default::default(),
+            })
+        };
+        let encode_pipeline = create_pipeline("encode_srgb");
+        let copy_pipeline = create_pipeline("copy");
+
+        Ok(Self {
+            encoder,
+            adapter_info: adapter.get_info(),
+            device,
+            queue,
+            encode_pipeline,
+            copy_pipeline,
+            frame: None,
+        })
+    }
+
+    /// Returns the info of the adapter the frames are converted on.
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    fn pipeline(&self, encode_srgb: bool) -> &wgpu::ComputePipeline {
+        if encode_srgb {
+            &self.encode_pipeline
+        } else {
+            &self.copy_pipeline
+        }
+    }
+
+    fn prepare_frame(&mut self, image: &Image) {
+        let size = image.size();
+        let format = image.texture_descriptor.format;
+        if let Some(frame) = &self.frame {
+            if frame.size == size && frame.format == format {
+                return;
+            }
+        }
+
+        // Linear float values are encoded to sRGB, sRGB formats are decoded when loaded and
+        // encoded again. Other normalized formats already hold the final values.
+        let encode_srgb = format.is_srgb() || is_float(format);
+
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("bevy_capture_secondary_gpu_source"),
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let output_size = size.x as u64 * size.y as u64 * 4;
+        let output = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("bevy_capture_secondary_gpu_output"),
+            size: output_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("bevy_capture_secondary_gpu_readback"),
+            size: output_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bevy_capture_secondary_gpu"),
+            layout: &self.pipeline(encode_srgb).get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &texture.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+        });
+
+        self.frame = Some(FrameResources {
+            size,
+            format,
+            encode_srgb,
+            texture,
+            output,
+            readback,
+            bind_group,
+        });
+    }
+
+    fn convert(&mut self, image: &Image) -> Result<Image> {
+        if !matches!(
+            image.texture_descriptor.format.sample_type(None, None),
+            Some(wgpu::TextureSampleType::Float { .. })
+        ) {
+            return Err(Error::format(format!(
+                "unsupported format for the secondary gpu: {:?}",
+                image.texture_descriptor.format
+            )));
+        }
+
+        let size = image.size();
+        let limits = self.device.limits();
+        let output_size = size.x as u64 * size.y as u64 * 4;
+        if size.max_element() > limits.max_texture_dimension_2d
+            || output_size > limits.max_storage_buffer_binding_size as u64
+        {
+            return Err(Error::format(format!(
+                "a frame of {}x{} exceeds the limits of the secondary gpu {}",
+                size.x, size.y, self.adapter_info.name
+            )));
+        }
+
+        self.prepare_frame(image);
+        let frame = self.frame.as_ref().unwrap();
+        let size = frame.size;
+        let block_size = frame.format.block_copy_size(None).unwrap_or(4);
+
+        self.queue.write_texture(
+            frame.texture.as_image_copy(),
+            &image.data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(size.x * block_size),
+                rows_per_image: None,
+            },
+            frame.texture.size(),
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(self.pipeline(frame.encode_srgb));
+            pass.set_bind_group(0, &frame.bind_group, &[]);
+            pass.dispatch_workgroups(size.x.div_ceil(8), size.y.div_ceil(8), 1);
+        }
+        encoder.copy_buffer_to_buffer(&frame.output, 0, &frame.readback, 0, frame.output.size());
+        self.queue.submit([encoder.finish()]);
+
+        let slice = frame.readback.slice(..);
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .map_err(Error::custom)?
+            .map_err(Error::custom)?;
+
+        let data = slice.get_mapped_range().to_vec();
+        frame.readback.unmap();
+
+        Ok(Image::new(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            if frame.encode_srgb {
+                TextureFormat::Rgba8UnormSrgb
+            } else {
+                TextureFormat::Rgba8Unorm
+            },
+            RenderAssetUsages::default(),
+        ))
+    }
+}
+
+impl<E: Encoder> Encoder for SecondaryGpuEncoder<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let image = self.convert(image)?;
+        let image = self.encoder.capabilities().convert(&image)?;
+        self.encoder.encode_with_metadata(&image, metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.encoder.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        // Frames are converted to RGBA8 before they are passed on.
+        self.encoder.capabilities().with_all_formats()
+    }
+
+    fn name(&self) -> &str {
+        self.encoder.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.encoder.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        Box::new(self.encoder).finish();
+    }
+}
+
+fn is_float(format: TextureFormat) -> bool {
+    matches!(
+        format,
+        TextureFormat::R16Float
+            | TextureFormat::Rg16Float
+            | TextureFormat::Rgba16Float
+            | TextureFormat::R32Float
+            | TextureFormat::Rg32Float
+            | TextureFormat::Rgba32Float
+            | TextureFormat::Rg11b10Float
+            | TextureFormat::Rgb9e5Ufloat
+    )
+}
@@ -0,0 +1,310 @@
+//! Stream frames over TCP, e.g. telemetry or previews from headless simulation nodes to a viewer
+//! on another machine.
+//!
+//! The frames are sent with the protocol of the [`ipc`](super::ipc) module, so a viewer reads them
+//! with an [`IpcClient`](super::ipc::IpcClient) over a [`TcpStream`]. A background thread sends the
+//! frames, so a slow link never stalls the app. If the connection fails or is lost, the thread
+//! reconnects with exponential backoff.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::{ipc::IpcClient, tcp::TcpEncoder};
+//! # use std::{net::TcpListener, time::Duration};
+//! #
+//! // On the simulation node.
+//! let encoder = TcpEncoder::new("viewer.local:7878")
+//!     .with_backoff(Duration::from_millis(100), Duration::from_secs(5));
+//!
+//! // On the viewer.
+//! let listener = TcpListener::bind("0.0.0.0:7878")?;
+//! for stream in listener.incoming() {
+//!     for frame in IpcClient::new(stream?) {
+//!         let frame = frame?;
+//!     }
+//! }
+//! ```
+
+use super::{ipc::IpcFrame, to_rgba8, Encoder, Result};
+use bevy::prelude::*;
+use crossbeam_channel::{Sender, TrySendError};
+use std::{
+    io,
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How long connecting to an address may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long writing a frame may block before the connection is considered lost.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An encoder that streams frames to a TCP server, see the [module docs](self).
+///
+/// By default, frames are dropped if the link is too slow or down, so the viewer always gets the
+/// most recent frames. Without frame dropping, no frame is lost while the encoder is running, but
+/// the capture waits while the queue is full. The frames that are still queued when the capture
+/// finishes are dropped if the server is not reachable.
+pub struct TcpEncoder {
+    address: String,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    queue: usize,
+    drop_frames: bool,
+    stats: TcpStats,
+    finishing: Arc<AtomicBool>,
+    sender: Option<Sender<IpcFrame>>,
+    thread: Option<JoinHandle<()>>,
+    frame: u64,
+}
+
+impl TcpEncoder {
+    /// Creates a new encoder that streams frames to the server at the given address, e.g.
+    /// `"127.0.0.1:7878"`. The address is resolved and connected to on a background thread once
+    /// the first frame is encoded.
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            queue: 2,
+            drop_frames: true,
+            stats: TcpStats::default(),
+            finishing: Arc::default(),
+            sender: None,
+            thread: None,
+            frame: 0,
+        }
+    }
+
+    /// Sets the delay before the first reconnect attempt and the maximum delay. The delay doubles
+    /// with every failed attempt. Defaults to 100 ms and 10 s.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Sets the number of frames that can wait to be sent. Defaults to `2`, to keep the latency
+    /// low.
+    pub fn with_queue(mut self, queue: usize) -> Self {
+        self.queue = queue.max(1);
+        self
+    }
+
+    /// Sets whether frames are dropped if the queue is full or the server is not reachable.
+    /// Defaults to `true`.
+    pub fn with_drop_frames(mut self, drop_frames: bool) -> Self {
+        self.drop_frames = drop_frames;
+        self
+    }
+
+    /// Returns the statistics of the stream. They can be read while the encoder is running.
+    pub fn stats(&self) -> TcpStats {
+        self.stats.clone()
+    }
+
+    fn spawn(&mut self) -> Result<&Sender<IpcFrame>> {
+        if self.sender.is_none() {
+            let (sender, receiver) = crossbeam_channel::bounded::<IpcFrame>(self.queue);
+            let mut connection = Connection {
+                address: self.address.clone(),
+                initial_backoff: self.initial_backoff,
+                max_backoff: self.max_backoff,
+                drop_frames: self.drop_frames,
+                stats: self.stats.clone(),
+                finishing: self.finishing.clone(),
+                stream: None,
+                backoff: self.initial_backoff,
+                retry_at: Instant::now(),
+            };
+            let thread = thread::Builder::new()
+                .name("capture tcp sender".to_owned())
+                .spawn(move || {
+                    for frame in receiver {
+                        connection.send(&frame);
+                    }
+                })?;
+            self.sender = Some(sender);
+            self.thread = Some(thread);
+        }
+        Ok(self.sender.as_ref().unwrap())
+    }
+}
+
+impl Encoder for TcpEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        let frame = IpcFrame {
+            frame: self.frame,
+            width: image.width(),
+            height: image.height(),
+            data: to_rgba8(image)?.into_owned(),
+        };
+        self.frame += 1;
+
+        let drop_frames = self.drop_frames;
+        let sender = self.spawn()?;
+        let result = match drop_frames {
+            true => sender.try_send(frame),
+            false => sender
+                .send(frame)
+                .map_err(|err| TrySendError::Disconnected(err.0)),
+        };
+        match result {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.stats.inner.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(io::Error::other("the tcp sender thread stopped").into())
+            }
+        }
+    }
+
+    fn finish(mut self: Box<Self>) {
+        // Closing the channel stops the thread once the queue is empty.
+        self.finishing.store(true, Ordering::Relaxed);
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            if thread.join().is_err() {
+                error!("The tcp sender thread panicked");
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        "tcp"
+    }
+
+    fn describe_output(&self) -> String {
+        format!("tcp://{}", self.address)
+    }
+}
+
+/// Statistics of a [`TcpEncoder`], shared with the encoder.
+#[derive(Debug, Clone, Default)]
+pub struct TcpStats {
+    inner: Arc<StatsInner>,
+}
+
+#[derive(Debug, Default)]
+struct StatsInner {
+    connected: AtomicBool,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    connections: AtomicU64,
+}
+
+impl TcpStats {
+    /// Returns `true` if the encoder is connected to the server.
+    pub fn is_connected(&self) -> bool {
+        self.inner.connected.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of frames that were sent.
+    pub fn sent_frames(&self) -> u64 {
+        self.inner.sent.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of frames that were dropped, because the link was too slow or down.
+    pub fn dropped_frames(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of successful connections, including reconnects.
+    pub fn connections(&self) -> u64 {
+        self.inner.connections.load(Ordering::Relaxed)
+    }
+}
+
+/// The connection of the sender thread.
+struct Connection {
+    address: String,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    drop_frames: bool,
+    stats: TcpStats,
+    finishing: Arc<AtomicBool>,
+    stream: Option<TcpStream>,
+    backoff: Duration,
+    retry_at: Instant,
+}
+
+impl Connection {
+    /// Sends the frame, reconnecting if needed.
+    fn send(&mut self, frame: &IpcFrame) {
+        loop {
+            if self.stream.is_none() {
+                let now = Instant::now();
+                if now < self.retry_at {
+                    if self.drop_frames || self.finishing.load(Ordering::Relaxed) {
+                        self.stats.inner.dropped.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    // Unparked early when the encoder finishes.
+                    thread::park_timeout(self.retry_at - now);
+                    continue;
+                }
+                if let Err(err) = self.connect() {
+                    warn!(
+                        "Failed to connect to {}, retrying in {:?}: {}",
+                        self.address, self.backoff, err
+                    );
+                    self.retry_at = Instant::now() + self.backoff;
+                    self.backoff = (self.backoff * 2).min(self.max_backoff);
+                    continue;
+                }
+            }
+
+            match frame.write_to(self.stream.as_mut().unwrap()) {
+                Ok(()) => {
+                    self.stats.inner.sent.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                Err(err) => {
+                    warn!("Lost the connection to {}: {}", self.address, err);
+                    self.stream = None;
+                    self.stats.inner.connected.store(false, Ordering::Relaxed);
+                    if self.drop_frames {
+                        self.stats.inner.dropped.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn connect(&mut self) -> io::Result<()> {
+        let addresses = self.address.to_socket_addrs()?.collect::<Vec<SocketAddr>>();
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no address");
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                    info!("Connected to {}", self.address);
+                    self.stream = Some(stream);
+                    self.backoff = self.initial_backoff;
+                    self.stats.inner.connected.store(true, Ordering::Relaxed);
+                    self.stats.inner.connections.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.stats.inner.connected.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,221 @@
+//! Renders a live, downscaled preview of the frames into the terminal.
+
+use super::{to_dynamic_image, Encoder, Result};
+use bevy::prelude::*;
+use image::{imageops::FilterType, RgbaImage};
+use std::{
+    fmt::Write as _,
+    io::{self, Stderr, Write},
+};
+
+/// How the preview is drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalMode {
+    /// Unicode half blocks with 24-bit colors. Works in most terminals.
+    #[default]
+    Blocks,
+    /// Sixel graphics, e.g. supported by xterm, foot, WezTerm, and Windows Terminal.
+    Sixel,
+    /// The kitty graphics protocol, e.g. supported by kitty, WezTerm, and Ghostty.
+    Kitty,
+}
+
+/// An encoder that renders a live, downscaled preview of the frames into the terminal,
+/// e.g. to sanity-check a headless render over SSH.
+///
+/// By default, the preview is written to stderr, so stdout can still be used for piping.
+pub struct TerminalEncoder<W: Write> {
+    writer: W,
+    mode: TerminalMode,
+    width: u32,
+    every: u32,
+    frame: u32,
+}
+
+impl TerminalEncoder<Stderr> {
+    /// Creates a new terminal encoder that draws to stderr.
+    pub fn new() -> Self {
+        Self::new_with_writer(io::stderr())
+    }
+}
+
+impl Default for TerminalEncoder<Stderr> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Write> TerminalEncoder<W> {
+    /// Creates a new terminal encoder that draws to the given writer.
+    pub fn new_with_writer(writer: W) -> Self {
+        Self {
+            writer,
+            mode: TerminalMode::Blocks,
+            width: 80,
+            every: 1,
+            frame: 0,
+        }
+    }
+
+    /// Sets how the preview is drawn.
+    pub fn with_mode(mut self, mode: TerminalMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the width of the preview, in columns for [`TerminalMode::Blocks`] and in pixels
+    /// otherwise.
+    pub fn with_width(mut self, width: u32) -> Self {
+        self.width = width.max(1);
+        self
+    }
+
+    /// Only draws every n-th frame, to keep the terminal responsive.
+    pub fn with_every(mut self, every: u32) -> Self {
+        self.every = every.max(1);
+        self
+    }
+}
+
+impl<W: Write> Encoder for TerminalEncoder<W> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        let frame = self.frame;
+        self.frame += 1;
+        if !frame.is_multiple_of(self.every) {
+            return Ok(());
+        }
+
+        let image = to_dynamic_image(image)?;
+        let width = self.width.min(image.width()).max(1);
+        let height = (image.height() as u64 * width as u64 / image.width() as u64).max(1) as u32;
+        let image = image
+            .resize_exact(width, height, FilterType::Triangle)
+            .to_rgba8();
+
+        // Move the cursor to the top left corner to redraw the preview in place.
+        let mut out = String::from("\x1b[H");
+        match self.mode {
+            TerminalMode::Blocks => draw_blocks(&mut out, &image),
+            TerminalMode::Sixel => draw_sixel(&mut out, &image),
+            TerminalMode::Kitty => draw_kitty(&mut out, &image),
+        }
+
+        self.writer.write_all(out.as_bytes())?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "terminal"
+    }
+}
+
+fn draw_blocks(out: &mut String, image: &RgbaImage) {
+    for y in (0..image.height()).step_by(2) {
+        for x in 0..image.width() {
+            let [r, g, b, _] = image.get_pixel(x, y).0;
+            let [r2, g2, b2, _] = if y + 1 < image.height() {
+                image.get_pixel(x, y + 1).0
+            } else {
+                [0; 4]
+            };
+            // Upper half block: foreground is the upper pixel, background the lower pixel.
+            let _ = write!(
+                out,
+                "\x1b[38;2;{r};{g};{b}m\x1b[48;2;{r2};{g2};{b2}m\u{2580}"
+            );
+        }
+        out.push_str("\x1b[0m\n");
+    }
+}
+
+fn draw_sixel(out: &mut String, image: &RgbaImage) {
+    // Quantize to a 6x6x6 color cube.
+    let index = |pixel: [u8; 4]| {
+        let level = |c: u8| (c as u32 * 5 + 127) / 255;
+        level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2])
+    };
+
+    out.push_str("\x1bPq");
+    for i in 0..216 {
+        let percent = |level: u32| level * 100 / 5;
+        let _ = write!(
+            out,
+            "#{i};2;{};{};{}",
+            percent(i / 36),
+            percent(i / 6 % 6),
+            percent(i % 6)
+        );
+    }
+
+    for band in (0..image.height()).step_by(6) {
+        let rows = (image.height() - band).min(6);
+        let mut colors = [false; 216];
+        for y in band..band + rows {
+            for x in 0..image.width() {
+                colors[index(image.get_pixel(x, y).0) as usize] = true;
+            }
+        }
+
+        for color in (0..216).filter(|&color| colors[color as usize]) {
+            let _ = write!(out, "#{color}");
+            for x in 0..image.width() {
+                let mut bits = 0;
+                for row in 0..rows {
+                    if index(image.get_pixel(x, band + row).0) == color {
+                        bits |= 1 << row;
+                    }
+                }
+                out.push(char::from(63 + bits));
+            }
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\\n");
+}
+
+fn draw_kitty(out: &mut String, image: &RgbaImage) {
+    let data = base64(image.as_raw());
+    let chunks = data.as_bytes().chunks(4096).collect::<Vec<_>>();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        if i == 0 {
+            // Delete previous images, then transmit and display the new one.
+            let _ = write!(
+                out,
+                "\x1b_Ga=d\x1b\\\x1b_Ga=T,f=32,s={},v={},m={more};",
+                image.width(),
+                image.height()
+            );
+        } else {
+            let _ = write!(out, "\x1b_Gm={more};");
+        }
+        out.push_str(std::str::from_utf8(chunk).unwrap());
+        out.push_str("\x1b\\");
+    }
+    out.push('\n');
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,129 @@
+//! An encoder that records how it is called, for asserting capture behavior in unit tests.
+
+use super::{Encoder, Result};
+use crate::metadata::FrameMetadata;
+use bevy::{prelude::*, render::render_resource::TextureFormat};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// An encoder that does not encode anything, but records every call.
+/// Use [`handle`](Self::handle) to inspect the recorded calls after the encoder was moved into a capture.
+#[derive(Default)]
+pub struct TestEncoder {
+    state: Arc<Mutex<TestEncoderState>>,
+    keep_images: bool,
+}
+
+impl TestEncoder {
+    /// Creates a new test encoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps a copy of every encoded image, see [`TestEncoderHandle::images`].
+    pub fn with_images(mut self) -> Self {
+        self.keep_images = true;
+        self
+    }
+
+    /// Returns a handle to the recorded calls.
+    pub fn handle(&self) -> TestEncoderHandle {
+        TestEncoderHandle(Arc::clone(&self.state))
+    }
+}
+
+impl Encoder for TestEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.frames.push(RecordedFrame {
+            width: image.width(),
+            height: image.height(),
+            format: image.texture_descriptor.format,
+        });
+        state.metadata.push(metadata.clone());
+        if self.keep_images {
+            state.images.push(image.clone());
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let frames = state.frames.len();
+        state.flushes.push(frames);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) {
+        self.state.lock().unwrap().finished = true;
+    }
+
+    fn name(&self) -> &str {
+        "test"
+    }
+}
+
+/// A handle to the calls recorded by a [`TestEncoder`].
+#[derive(Clone)]
+pub struct TestEncoderHandle(Arc<Mutex<TestEncoderState>>);
+
+impl TestEncoderHandle {
+    /// Returns the number of times [`encode`](Encoder::encode) was called.
+    pub fn encode_count(&self) -> usize {
+        self.state().frames.len()
+    }
+
+    /// Returns `true` if [`finish`](Encoder::finish) was called.
+    pub fn is_finished(&self) -> bool {
+        self.state().finished
+    }
+
+    /// Returns the number of frames that were encoded before each call to
+    /// [`flush`](Encoder::flush).
+    pub fn flushes(&self) -> Vec<usize> {
+        self.state().flushes.clone()
+    }
+
+    /// Returns the size and format of every encoded frame.
+    pub fn frames(&self) -> Vec<RecordedFrame> {
+        self.state().frames.clone()
+    }
+
+    /// Returns a copy of every encoded image. This is empty unless
+    /// [`with_images`](TestEncoder::with_images) was used.
+    pub fn images(&self) -> Vec<Image> {
+        self.state().images.clone()
+    }
+
+    /// Returns the metadata of every encoded frame.
+    pub fn metadata(&self) -> Vec<FrameMetadata> {
+        self.state().metadata.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, TestEncoderState> {
+        self.0.lock().unwrap()
+    }
+}
+
+/// The size and format of an encoded frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedFrame {
+    /// The width of the frame.
+    pub width: u32,
+    /// The height of the frame.
+    pub height: u32,
+    /// The texture format of the frame.
+    pub format: TextureFormat,
+}
+
+#[derive(Default)]
+struct TestEncoderState {
+    frames: Vec<RecordedFrame>,
+    images: Vec<Image>,
+    metadata: Vec<FrameMetadata>,
+    flushes: Vec<usize>,
+    finished: bool,
+}
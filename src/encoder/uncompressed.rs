@@ -0,0 +1,228 @@
+//! Encode frames into individual uncompressed images (PPM, TGA or BMP), for captures where PNG
+//! encoding is the bottleneck and disk space is cheap, e.g. offline 4K captures at interactive
+//! speeds.
+
+use super::{
+    capabilities::EncoderCapabilities,
+    file_output::{FileOutput, FileWriter},
+    to_rgba8, Encoder, Result,
+};
+use crate::metadata::FrameMetadata;
+use bevy::{prelude::*, render::render_resource::TextureFormat};
+use std::{borrow::Cow, fs, path::PathBuf};
+
+/// The file format of an [`UncompressedFramesEncoder`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UncompressedFormat {
+    /// Binary PPM (`P6`), RGB without alpha. The simplest format, read by most tools.
+    Ppm,
+    /// Uncompressed 32 bit TGA, BGRA with alpha, stored top to bottom.
+    #[default]
+    Tga,
+    /// Uncompressed 32 bit BMP, BGRA, stored top to bottom. Most tools ignore the alpha channel.
+    Bmp,
+}
+
+impl UncompressedFormat {
+    /// Returns the file extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Ppm => "ppm",
+            Self::Tga => "tga",
+            Self::Bmp => "bmp",
+        }
+    }
+
+    /// Returns the header of an image with the given dimensions.
+    fn header(self, width: u32, height: u32) -> Vec<u8> {
+        match self {
+            Self::Ppm => format!("P6\n{width} {height}\n255\n").into_bytes(),
+            Self::Tga => {
+                let mut header = vec![0; 18];
+                // Uncompressed true-color image.
+                header[2] = 2;
+                header[12..14].copy_from_slice(&(width as u16).to_le_bytes());
+                header[14..16].copy_from_slice(&(height as u16).to_le_bytes());
+                header[16] = 32;
+                // 8 alpha bits, top-left origin.
+                header[17] = 0x28;
+                header
+            }
+            Self::Bmp => {
+                let data_size = width * height * 4;
+                let mut header = Vec::with_capacity(54);
+                // BITMAPFILEHEADER
+                header.extend(b"BM");
+                header.extend((54 + data_size).to_le_bytes());
+                header.extend([0; 4]);
+                header.extend(54u32.to_le_bytes());
+                // BITMAPINFOHEADER, a negative height stores the rows top to bottom.
+                header.extend(40u32.to_le_bytes());
+                header.extend((width as i32).to_le_bytes());
+                header.extend((-(height as i32)).to_le_bytes());
+                header.extend(1u16.to_le_bytes());
+                header.extend(32u16.to_le_bytes());
+                header.extend(0u32.to_le_bytes());
+                header.extend(data_size.to_le_bytes());
+                header.extend([0; 16]);
+                header
+            }
+        }
+    }
+}
+
+/// An encoder that writes every frame as an uncompressed image into a directory.
+///
+/// The pixels are written as they are after the header. TGA and BMP store BGRA pixels, so BGRA
+/// frames are written without any conversion. The dimensions of TGA images are limited to
+/// 65535x65535. How the files are written can be tuned with [`with_output`](Self::with_output).
+///
+/// Like the [`FramesEncoder`](super::frames::FramesEncoder), the [metadata](crate::metadata) of a
+/// frame is written to a `frame_{index}.json` sidecar file next to the image, if there is any.
+pub struct UncompressedFramesEncoder {
+    path: PathBuf,
+    format: UncompressedFormat,
+    writer: FileWriter,
+    frame: u32,
+}
+
+impl UncompressedFramesEncoder {
+    /// Creates a new encoder that writes frames to the given directory in the given format.
+    pub fn new(path: impl Into<PathBuf>, format: UncompressedFormat) -> Self {
+        Self {
+            path: path.into(),
+            format,
+            writer: FileWriter::default(),
+            frame: 0,
+        }
+    }
+
+    /// Creates a new encoder that writes frames to the given directory, configured by the given
+    /// config.
+    pub fn from_config(path: impl Into<PathBuf>, config: &UncompressedFramesConfig) -> Self {
+        Self::new(path, config.format).with_output(config.output.clone())
+    }
+
+    /// Sets how the files are written, e.g. with direct I/O or on a background thread.
+    pub fn with_output(mut self, output: FileOutput) -> Self {
+        self.writer = output.writer();
+        self
+    }
+}
+
+/// The configuration of an [`UncompressedFramesEncoder`], which can be cloned and reused to create
+/// the encoders of many captures, see [`UncompressedFramesEncoder::from_config`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct UncompressedFramesConfig {
+    format: UncompressedFormat,
+    output: FileOutput,
+}
+
+impl UncompressedFramesConfig {
+    /// Creates a new config with the given format.
+    pub fn new(format: UncompressedFormat) -> Self {
+        Self {
+            format,
+            output: FileOutput::default(),
+        }
+    }
+
+    /// Sets how the files are written, see [`UncompressedFramesEncoder::with_output`].
+    pub fn with_output(mut self, output: FileOutput) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Returns the format.
+    pub fn format(&self) -> UncompressedFormat {
+        self.format
+    }
+
+    /// Returns how the files are written.
+    pub fn output(&self) -> &FileOutput {
+        &self.output
+    }
+}
+
+impl Encoder for UncompressedFramesEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let bgra = matches!(
+            image.texture_descriptor.format,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+        );
+        let pixels = match bgra {
+            true => Cow::Borrowed(&image.data[..]),
+            false => to_rgba8(image)?,
+        };
+        // The channel indices of red and blue in the pixels.
+        let (r, b) = if bgra { (2, 0) } else { (0, 2) };
+        let data = match self.format {
+            UncompressedFormat::Ppm => pixels
+                .chunks_exact(4)
+                .flat_map(|pixel| [pixel[r], pixel[1], pixel[b]])
+                .collect(),
+            UncompressedFormat::Tga | UncompressedFormat::Bmp if bgra => pixels,
+            UncompressedFormat::Tga | UncompressedFormat::Bmp => pixels
+                .chunks_exact(4)
+                .flat_map(|pixel| [pixel[b], pixel[1], pixel[r], pixel[3]])
+                .collect(),
+        };
+
+        fs::create_dir_all(&self.path)?;
+        let name = format!("frame_{:06}", self.frame);
+        self.writer.write(
+            self.path
+                .join(format!("{name}.{}", self.format.extension())),
+            &[&self.format.header(image.width(), image.height()), &data],
+        )?;
+        if !metadata.is_empty() {
+            self.writer.write(
+                self.path.join(format!("{name}.json")),
+                &[metadata.to_json().as_bytes()],
+            )?;
+        }
+
+        self.frame += 1;
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) {
+        if let Err(err) = self.writer.finish() {
+            error!("Failed to write frames: {}", err);
+        }
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        let capabilities = EncoderCapabilities::new().with_formats([
+            TextureFormat::Rgba8UnormSrgb,
+            TextureFormat::Rgba8Unorm,
+            TextureFormat::Bgra8UnormSrgb,
+            TextureFormat::Bgra8Unorm,
+        ]);
+        match self.format {
+            UncompressedFormat::Tga => capabilities.with_max_dimensions(65535, 65535),
+            _ => capabilities,
+        }
+    }
+
+    fn name(&self) -> &str {
+        "uncompressed_frames"
+    }
+
+    fn describe_output(&self) -> String {
+        self.path.display().to_string()
+    }
+}
@@ -0,0 +1,370 @@
+//! Stream encoded output to object storage (S3, GCS, ...) via multipart uploads.
+//!
+//! No storage client is bundled, so the crate doesn't depend on a particular cloud SDK. Implement
+//! [`MultipartUpload`] with the client of your choice, e.g. `aws-sdk-s3` or the S3-compatible XML
+//! API of GCS, and wrap any writer-based encoder, e.g. the [`GifEncoder`](super::gif::GifEncoder),
+//! in an [`UploadEncoder`]. The output then never touches the local disk.
+//!
+//! The upload is only completed when the encoder finished without errors. Otherwise, e.g. if the
+//! capture panicked or an encoder was dropped without finishing, it is aborted, so no truncated
+//! object is published.
+
+use super::{capabilities::EncoderCapabilities, Encoder, Result};
+use crate::metadata::FrameMetadata;
+use bevy::prelude::*;
+use std::{
+    io, mem,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// The minimum part size of S3 multipart uploads (except for the last part).
+pub const DEFAULT_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// A multipart upload to an object storage.
+///
+/// The upload should already be initiated (e.g. `CreateMultipartUpload` for S3) when it is passed
+/// to the [`UploadWriter`]. The methods are called from the thread the encoder runs on, so
+/// implementations should block until the request is done.
+pub trait MultipartUpload {
+    /// Uploads a part. Part numbers start at `1`.
+    fn upload_part(&mut self, part_number: u32, data: Vec<u8>) -> Result<()>;
+
+    /// Completes the upload after all parts were uploaded.
+    fn complete(&mut self) -> Result<()>;
+
+    /// Aborts the upload. This is called instead of [`complete`](Self::complete) if a part failed
+    /// or the upload was not [finished](UploadWriter::finish).
+    fn abort(&mut self) {}
+}
+
+/// A writer that buffers data into parts and uploads them with a [`MultipartUpload`].
+///
+/// The upload is completed by [`finish`](Self::finish). If the writer is dropped without
+/// finishing, the upload is aborted. To write the output of an encoder, which drops its writer
+/// when it finishes, use an [`UploadEncoder`].
+pub struct UploadWriter<U: MultipartUpload>(Arc<Mutex<Upload<U>>>);
+
+struct Upload<U: MultipartUpload> {
+    upload: U,
+    part_size: usize,
+    part_number: u32,
+    buffer: Vec<u8>,
+    failed: bool,
+    done: bool,
+}
+
+impl<U: MultipartUpload> UploadWriter<U> {
+    /// Creates a new upload writer with the [default part size](DEFAULT_PART_SIZE).
+    pub fn new(upload: U) -> Self {
+        Self::new_with_part_size(upload, DEFAULT_PART_SIZE)
+    }
+
+    /// Creates a new upload writer with the given part size.
+    pub fn new_with_part_size(upload: U, part_size: usize) -> Self {
+        Self(Arc::new(Mutex::new(Upload {
+            upload,
+            part_size: part_size.max(1),
+            part_number: 1,
+            buffer: Vec::with_capacity(part_size),
+            failed: false,
+            done: false,
+        })))
+    }
+
+    /// Uploads the last part and completes the upload.
+    pub fn finish(self) -> Result<()> {
+        let mut upload = self.lock();
+        upload.done = true;
+        if upload.failed {
+            upload.upload.abort();
+            return Err("a previous part failed to upload".into());
+        }
+
+        // At least one part is required, even if it is empty.
+        if !upload.buffer.is_empty() || upload.part_number == 1 {
+            if let Err(err) = upload.upload_part() {
+                upload.upload.abort();
+                return Err(err.into());
+            }
+        }
+        upload.upload.complete()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Upload<U>> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl<U: MultipartUpload> Upload<U> {
+    fn upload_part(&mut self) -> io::Result<()> {
+        let data = mem::replace(&mut self.buffer, Vec::with_capacity(self.part_size));
+        match self.upload.upload_part(self.part_number, data) {
+            Ok(()) => {
+                self.part_number += 1;
+                Ok(())
+            }
+            Err(err) => {
+                self.failed = true;
+                Err(io::Error::other(err))
+            }
+        }
+    }
+}
+
+impl<U: MultipartUpload> io::Write for UploadWriter<U> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut upload = self.lock();
+        if upload.failed {
+            return Err(io::Error::other("a previous part failed to upload"));
+        }
+
+        let len = buf.len().min(upload.part_size - upload.buffer.len());
+        upload.buffer.extend_from_slice(&buf[..len]);
+        if upload.buffer.len() == upload.part_size {
+            upload.upload_part()?;
+        }
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Parts can not be flushed early, as all but the last part must have the minimum size.
+        Ok(())
+    }
+}
+
+impl<U: MultipartUpload> Drop for Upload<U> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.upload.abort();
+        }
+    }
+}
+
+/// An encoder that writes the output of a writer-based encoder to an [`UploadWriter`] and
+/// completes the upload when the encoder finished.
+///
+/// If encoding a frame failed, the upload is aborted instead, since the output may be incomplete.
+///
+/// # Example
+/// ```ignore
+/// # use bevy_capture::encoder::{gif::GifEncoder, upload::{UploadEncoder, UploadWriter}};
+/// #
+/// let encoder = UploadEncoder::new(UploadWriter::new(my_s3_upload), GifEncoder::new);
+/// ```
+pub struct UploadEncoder<E, U: MultipartUpload> {
+    encoder: E,
+    writer: UploadWriter<U>,
+    failed: bool,
+}
+
+impl<E: Encoder, U: MultipartUpload> UploadEncoder<E, U> {
+    /// Creates the encoder with the given function, which gets the writer to write to.
+    pub fn new(writer: UploadWriter<U>, encoder: impl FnOnce(UploadWriter<U>) -> E) -> Self {
+        let handle = UploadWriter(Arc::clone(&writer.0));
+        Self {
+            encoder: encoder(writer),
+            writer: handle,
+            failed: false,
+        }
+    }
+
+    /// Creates the encoder with the given fallible function, which gets the writer to write to.
+    /// The upload is aborted if creating the encoder fails.
+    pub fn try_new(
+        writer: UploadWriter<U>,
+        encoder: impl FnOnce(UploadWriter<U>) -> Result<E>,
+    ) -> Result<Self> {
+        let handle = UploadWriter(Arc::clone(&writer.0));
+        Ok(Self {
+            encoder: encoder(writer)?,
+            writer: handle,
+            failed: false,
+        })
+    }
+}
+
+impl<E: Encoder, U: MultipartUpload> Encoder for UploadEncoder<E, U> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let result = self.encoder.encode_with_metadata(image, metadata);
+        self.failed |= result.is_err();
+        result
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.encoder.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.encoder.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.encoder.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.encoder.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        let Self {
+            encoder,
+            writer,
+            failed,
+        } = *self;
+        Box::new(encoder).finish();
+
+        if failed {
+            bevy::log::warn!("Aborting upload, as encoding a frame failed");
+            let mut upload = writer.lock();
+            upload.done = true;
+            upload.upload.abort();
+        } else if let Err(err) = writer.finish() {
+            bevy::log::error!("Failed to complete upload: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Records the calls, fails to upload the given part.
+    #[derive(Clone, Default)]
+    struct FakeUpload {
+        calls: Arc<Mutex<Vec<String>>>,
+        fail_part: Option<u32>,
+    }
+
+    impl FakeUpload {
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl MultipartUpload for FakeUpload {
+        fn upload_part(&mut self, part_number: u32, data: Vec<u8>) -> Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("part {part_number}: {data:?}"));
+            match self.fail_part == Some(part_number) {
+                true => Err(io::Error::other("rejected").into()),
+                false => Ok(()),
+            }
+        }
+
+        fn complete(&mut self) -> Result<()> {
+            self.calls.lock().unwrap().push("complete".to_string());
+            Ok(())
+        }
+
+        fn abort(&mut self) {
+            self.calls.lock().unwrap().push("abort".to_string());
+        }
+    }
+
+    /// Writes the first byte of every frame, fails on frames starting with `0`.
+    struct FirstByteEncoder<W>(W);
+
+    impl<W: Write> Encoder for FirstByteEncoder<W> {
+        fn encode(&mut self, image: &Image) -> Result<()> {
+            match image.data[0] {
+                0 => Err(io::Error::other("invalid frame").into()),
+                byte => Ok(self.0.write_all(&[byte])?),
+            }
+        }
+    }
+
+    fn frame(byte: u8) -> Image {
+        Image {
+            data: vec![byte; 4],
+            ..default()
+        }
+    }
+
+    #[test]
+    fn uploads_in_parts() {
+        // Full parts are uploaded while writing, the rest when the upload is finished.
+        let upload = FakeUpload::default();
+        let mut writer = UploadWriter::new_with_part_size(upload.clone(), 4);
+        writer.write_all(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            upload.calls(),
+            ["part 1: [0, 1, 2, 3]", "part 2: [4, 5, 6, 7]"]
+        );
+        writer.finish().unwrap();
+        assert_eq!(
+            upload.calls(),
+            [
+                "part 1: [0, 1, 2, 3]",
+                "part 2: [4, 5, 6, 7]",
+                "part 3: [8, 9]",
+                "complete"
+            ]
+        );
+
+        // An empty upload still has one part.
+        let upload = FakeUpload::default();
+        UploadWriter::new(upload.clone()).finish().unwrap();
+        assert_eq!(upload.calls(), ["part 1: []", "complete"]);
+    }
+
+    #[test]
+    fn aborts_unfinished_and_failed_uploads() {
+        // Dropping the writer without finishing, e.g. during a panic, aborts the upload.
+        let upload = FakeUpload::default();
+        let mut writer = UploadWriter::new_with_part_size(upload.clone(), 4);
+        writer.write_all(&[1, 2]).unwrap();
+        drop(writer);
+        assert_eq!(upload.calls(), ["abort"]);
+
+        // A failed part fails all further writes and aborts the upload.
+        let upload = FakeUpload {
+            fail_part: Some(2),
+            ..default()
+        };
+        let mut writer = UploadWriter::new_with_part_size(upload.clone(), 2);
+        assert!(writer.write_all(&[1, 2, 3, 4, 5]).is_err());
+        assert!(writer.write_all(&[6]).is_err());
+        assert!(writer.finish().is_err());
+        assert_eq!(
+            upload.calls(),
+            ["part 1: [1, 2]", "part 2: [3, 4]", "abort"]
+        );
+    }
+
+    #[test]
+    fn completes_upload_when_encoder_finished() {
+        let upload = FakeUpload::default();
+        let mut encoder = UploadEncoder::new(UploadWriter::new(upload.clone()), FirstByteEncoder);
+        encoder.encode(&frame(1)).unwrap();
+        encoder.encode(&frame(2)).unwrap();
+        assert!(upload.calls().is_empty());
+        Box::new(encoder).finish();
+        assert_eq!(upload.calls(), ["part 1: [1, 2]", "complete"]);
+
+        // The output of an encoder that failed may be incomplete.
+        let upload = FakeUpload::default();
+        let mut encoder = UploadEncoder::new(UploadWriter::new(upload.clone()), FirstByteEncoder);
+        encoder.encode(&frame(1)).unwrap();
+        assert!(encoder.encode(&frame(0)).is_err());
+        Box::new(encoder).finish();
+        assert_eq!(upload.calls(), ["abort"]);
+
+        // An encoder dropped without finishing aborts the upload.
+        let upload = FakeUpload::default();
+        drop(UploadEncoder::new(
+            UploadWriter::new(upload.clone()),
+            FirstByteEncoder,
+        ));
+        assert_eq!(upload.calls(), ["abort"]);
+    }
+}
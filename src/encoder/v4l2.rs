@@ -0,0 +1,158 @@
+//! Writes frames to a v4l2loopback device, so the app appears as a webcam (Linux only).
+
+use super::{color::rgb_to_yuv, to_rgba8, Encoder, Error, Result};
+use bevy::prelude::*;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    mem,
+    os::fd::AsRawFd,
+    path::PathBuf,
+};
+
+const V4L2_BUF_TYPE_VIDEO_OUTPUT: u32 = 2;
+const V4L2_FIELD_NONE: u32 = 1;
+const V4L2_COLORSPACE_SMPTE170M: u32 = 1;
+const V4L2_PIX_FMT_YUYV: u32 = u32::from_le_bytes(*b"YUYV");
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct V4l2PixFormat {
+    width: u32,
+    height: u32,
+    pixelformat: u32,
+    field: u32,
+    bytesperline: u32,
+    sizeimage: u32,
+    colorspace: u32,
+    priv_: u32,
+    flags: u32,
+    ycbcr_enc: u32,
+    quantization: u32,
+    xfer_func: u32,
+}
+
+#[repr(C)]
+union V4l2FormatUnion {
+    pix: V4l2PixFormat,
+    raw_data: [u8; 200],
+    // Some members of the union contain pointers.
+    _align: [usize; 200 / mem::size_of::<usize>()],
+}
+
+#[repr(C)]
+struct V4l2Format {
+    type_: u32,
+    fmt: V4l2FormatUnion,
+}
+
+/// `_IOWR('V', 5, struct v4l2_format)`
+const VIDIOC_S_FMT: u32 =
+    (3 << 30) | ((mem::size_of::<V4l2Format>() as u32) << 16) | ((b'V' as u32) << 8) | 5;
+
+/// An encoder that writes frames to a v4l2loopback device, e.g. `/dev/video10`,
+/// so video conferencing tools, OBS, or browsers can use the app as a webcam.
+///
+/// The frames are written as YUYV, which is supported by most consumers. The width of the
+/// frames must be even.
+pub struct V4l2Encoder {
+    path: PathBuf,
+    device: Option<(File, u32, u32)>,
+}
+
+impl V4l2Encoder {
+    /// Creates a new v4l2 encoder that writes to the given v4l2loopback device.
+    /// The device is opened when the first frame is encoded.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            device: None,
+        }
+    }
+
+    fn open(&self, width: u32, height: u32) -> Result<File> {
+        if !width.is_multiple_of(2) {
+            return Err(Error::format("v4l2 output requires an even width"));
+        }
+
+        let device = OpenOptions::new().write(true).open(&self.path)?;
+
+        let mut format = V4l2Format {
+            type_: V4L2_BUF_TYPE_VIDEO_OUTPUT,
+            fmt: V4l2FormatUnion { raw_data: [0; 200] },
+        };
+        format.fmt.pix = V4l2PixFormat {
+            width,
+            height,
+            pixelformat: V4L2_PIX_FMT_YUYV,
+            field: V4L2_FIELD_NONE,
+            bytesperline: width * 2,
+            sizeimage: width * height * 2,
+            colorspace: V4L2_COLORSPACE_SMPTE170M,
+            priv_: 0,
+            flags: 0,
+            ycbcr_enc: 0,
+            quantization: 0,
+            xfer_func: 0,
+        };
+
+        // SAFETY: The format matches the layout of `struct v4l2_format` and outlives the call.
+        let result = unsafe {
+            libc::ioctl(
+                device.as_raw_fd(),
+                VIDIOC_S_FMT as _,
+                &mut format as *mut V4l2Format,
+            )
+        };
+        if result < 0 {
+            return Err(Error::format(format!(
+                "failed to set the format of {}: {}",
+                self.path.display(),
+                io::Error::last_os_error()
+            )));
+        }
+
+        Ok(device)
+    }
+}
+
+impl Encoder for V4l2Encoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        let rgba = to_rgba8(image)?;
+        let (width, height) = (image.width(), image.height());
+
+        let (device, device_width, device_height) = match &mut self.device {
+            Some(device) => device,
+            None => {
+                let device = self.open(width, height)?;
+                self.device.insert((device, width, height))
+            }
+        };
+        if (*device_width, *device_height) != (width, height) {
+            return Err(Error::format(
+                "v4l2 output does not support changing dimensions",
+            ));
+        }
+
+        let mut yuyv = Vec::with_capacity((width * height * 2) as usize);
+        for pixels in rgba.chunks_exact(8) {
+            let [y0, u0, v0] = rgb_to_yuv(pixels[0], pixels[1], pixels[2]);
+            let [y1, u1, v1] = rgb_to_yuv(pixels[4], pixels[5], pixels[6]);
+            let u = ((u0 as u16 + u1 as u16) / 2) as u8;
+            let v = ((v0 as u16 + v1 as u16) / 2) as u8;
+            yuyv.extend_from_slice(&[y0, u, y1, v]);
+        }
+
+        device.write_all(&yuyv)?;
+
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "v4l2"
+    }
+
+    fn describe_output(&self) -> String {
+        self.path.display().to_string()
+    }
+}
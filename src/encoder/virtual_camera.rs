@@ -0,0 +1,237 @@
+//! Sends frames to a virtual camera that is installed separately, so the app can be used as a
+//! webcam.
+//!
+//! This crate doesn't ship a camera driver or extension. The platform specific part is
+//! implemented by a [`VirtualCameraBackend`], which only talks to an existing camera:
+//!
+//! - Windows: [`SoftcamBackend`] (feature `softcam`) loads the
+//!   [softcam](https://github.com/tshino/softcam) DirectShow filter at runtime. The DLL has to be
+//!   built and registered separately.
+//! - macOS: [`CameraExtensionBackend`] (feature `camera_extension`) only serves the frames on a
+//!   Unix domain socket with the [`IpcEncoder`](super::ipc::IpcEncoder) protocol. The CoreMediaIO
+//!   camera extension that reads them and exposes the camera has to be written, signed and bundled
+//!   with the app, e.g. listening in a shared app group container.
+//! - Linux: Use the `V4l2Encoder` (feature `v4l2`) with a v4l2loopback device instead.
+
+use super::{to_rgba8, Encoder, Error, Result};
+use bevy::prelude::*;
+
+/// A platform specific virtual camera.
+pub trait VirtualCameraBackend {
+    /// Creates the camera. This is called once, before the first frame.
+    fn start(&mut self, width: u32, height: u32, framerate: f32) -> Result<()>;
+
+    /// Sends a frame of RGBA8 pixels to the camera.
+    fn send_frame(&mut self, rgba: &[u8]) -> Result<()>;
+
+    /// Destroys the camera.
+    fn stop(&mut self) {}
+}
+
+/// An encoder that sends frames to a virtual camera.
+pub struct VirtualCameraEncoder<B: VirtualCameraBackend> {
+    backend: B,
+    framerate: f32,
+    dimensions: Option<(u32, u32)>,
+}
+
+impl<B: VirtualCameraBackend> VirtualCameraEncoder<B> {
+    /// Creates a new virtual camera encoder with the given backend.
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            framerate: 60.0,
+            dimensions: None,
+        }
+    }
+
+    /// Sets the framerate the camera announces to consumers.
+    pub fn with_framerate(mut self, framerate: f32) -> Self {
+        self.framerate = framerate;
+        self
+    }
+}
+
+impl<B: VirtualCameraBackend> Encoder for VirtualCameraEncoder<B> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        let rgba = to_rgba8(image)?;
+        let (width, height) = (image.width(), image.height());
+
+        match self.dimensions {
+            None => {
+                self.backend.start(width, height, self.framerate)?;
+                self.dimensions = Some((width, height));
+            }
+            Some(dimensions) if dimensions != (width, height) => {
+                return Err(Error::format(
+                    "virtual cameras do not support changing dimensions",
+                ));
+            }
+            Some(_) => {}
+        }
+
+        self.backend.send_frame(&rgba)
+    }
+
+    fn finish(mut self: Box<Self>) {
+        if self.dimensions.is_some() {
+            self.backend.stop();
+        }
+    }
+
+    fn name(&self) -> &str {
+        "virtual_camera"
+    }
+}
+
+#[cfg(feature = "softcam")]
+pub use softcam::SoftcamBackend;
+
+#[cfg(feature = "softcam")]
+mod softcam {
+    use super::{Error, Result, VirtualCameraBackend};
+    use libloading::{Library, Symbol};
+    use std::{ffi::c_void, path::PathBuf};
+
+    type CreateCamera = unsafe extern "C" fn(i32, i32, f32) -> *mut c_void;
+    type DeleteCamera = unsafe extern "C" fn(*mut c_void);
+    type SendFrame = unsafe extern "C" fn(*mut c_void, *const c_void);
+
+    /// A backend that uses the [softcam](https://github.com/tshino/softcam) library (Windows).
+    ///
+    /// `softcam.dll` is loaded at runtime and must be registered as a DirectShow filter
+    /// (`regsvr32 softcam.dll`) to be visible to other applications.
+    pub struct SoftcamBackend {
+        path: PathBuf,
+        library: Option<Library>,
+        camera: *mut c_void,
+        buffer: Vec<u8>,
+    }
+
+    // SAFETY: The camera handle is only used by the thread that owns the backend.
+    unsafe impl Send for SoftcamBackend {}
+    // SAFETY: The camera handle is never accessed through a shared reference.
+    unsafe impl Sync for SoftcamBackend {}
+
+    impl SoftcamBackend {
+        /// Creates a new backend that loads `softcam.dll` from the default search path.
+        pub fn new() -> Self {
+            Self::new_with_path("softcam.dll")
+        }
+
+        /// Creates a new backend that loads the softcam library from the given path.
+        pub fn new_with_path(path: impl Into<PathBuf>) -> Self {
+            Self {
+                path: path.into(),
+                library: None,
+                camera: std::ptr::null_mut(),
+                buffer: Vec::new(),
+            }
+        }
+
+        fn symbol<T>(&self, name: &[u8]) -> Result<Symbol<'_, T>> {
+            let library = self.library.as_ref().ok_or("softcam is not loaded")?;
+            // SAFETY: The signatures match the softcam API.
+            Ok(unsafe { library.get(name).map_err(Error::custom)? })
+        }
+    }
+
+    impl Default for SoftcamBackend {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl VirtualCameraBackend for SoftcamBackend {
+        fn start(&mut self, width: u32, height: u32, framerate: f32) -> Result<()> {
+            // SAFETY: Loading softcam runs no initialization code with preconditions.
+            self.library = Some(unsafe { Library::new(&self.path).map_err(Error::custom)? });
+
+            let create = self.symbol::<CreateCamera>(b"scCreateCamera")?;
+            // SAFETY: The arguments are valid, a null pointer is returned on failure.
+            let camera = unsafe { create(width as i32, height as i32, framerate) };
+            if camera.is_null() {
+                return Err("failed to create softcam camera".into());
+            }
+            self.camera = camera;
+
+            Ok(())
+        }
+
+        fn send_frame(&mut self, rgba: &[u8]) -> Result<()> {
+            // softcam expects 24-bit BGR pixels.
+            self.buffer.clear();
+            for pixel in rgba.chunks_exact(4) {
+                self.buffer
+                    .extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+
+            let send = self.symbol::<SendFrame>(b"scSendFrame")?;
+            // SAFETY: The camera is valid and the buffer has the size of a frame.
+            unsafe { send(self.camera, self.buffer.as_ptr().cast()) };
+
+            Ok(())
+        }
+
+        fn stop(&mut self) {
+            if let Ok(delete) = self.symbol::<DeleteCamera>(b"scDeleteCamera") {
+                // SAFETY: The camera is valid and not used afterwards.
+                unsafe { delete(self.camera) };
+            }
+            self.camera = std::ptr::null_mut();
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "camera_extension"))]
+pub use camera_extension::CameraExtensionBackend;
+
+#[cfg(all(unix, feature = "camera_extension"))]
+mod camera_extension {
+    use super::{Result, VirtualCameraBackend};
+    use crate::encoder::ipc::IpcEncoder;
+    use std::path::PathBuf;
+
+    /// A backend that publishes frames to a CoreMediaIO camera extension (macOS). The extension
+    /// is not part of this crate, see the [module docs](super).
+    ///
+    /// The backend listens on a Unix domain socket and sends every frame to the connected
+    /// extensions with the [`IpcEncoder`] protocol, e.g. read with an
+    /// [`IpcClient`](crate::encoder::ipc::IpcClient) port. Extensions can connect at any time, the
+    /// framerate is announced by the extension itself.
+    pub struct CameraExtensionBackend {
+        path: PathBuf,
+        encoder: Option<IpcEncoder>,
+        size: (u32, u32),
+    }
+
+    impl CameraExtensionBackend {
+        /// Creates a new backend that listens on the Unix domain socket at the given path once
+        /// the capture starts. A stale socket at the path is replaced.
+        pub fn new(path: impl Into<PathBuf>) -> Self {
+            Self {
+                path: path.into(),
+                encoder: None,
+                size: (0, 0),
+            }
+        }
+    }
+
+    impl VirtualCameraBackend for CameraExtensionBackend {
+        fn start(&mut self, width: u32, height: u32, _framerate: f32) -> Result<()> {
+            self.encoder = Some(IpcEncoder::bind(&self.path)?);
+            self.size = (width, height);
+            Ok(())
+        }
+
+        fn send_frame(&mut self, rgba: &[u8]) -> Result<()> {
+            let encoder = self.encoder.as_mut().ok_or("the camera is not started")?;
+            encoder.send(self.size.0, self.size.1, rgba)
+        }
+
+        fn stop(&mut self) {
+            // Removes the socket, connected extensions see the end of the stream.
+            self.encoder = None;
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Embed an invisible watermark, e.g. a build hash or a user id, into the frames, so leaked footage
+//! can be traced back to its source.
+//!
+//! The payload is written into the least significant bits of the color channels and repeated
+//! across the whole frame, so it can still be read if parts of the frame were painted over. Use
+//! [`decode_watermark`] to read it back. The watermark only survives lossless outputs, e.g. PNG
+//! frames, raw or y4m streams. Lossy video codecs discard the least significant bits.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::encoder::{frames::FramesEncoder, watermark::*};
+//! #
+//! let encoder = WatermarkEncoder::new(
+//!     FramesEncoder::new("captures/playtest"),
+//!     format!("{}:{}", env!("CARGO_PKG_VERSION"), tester_id),
+//! );
+//! ```
+
+use super::{capabilities::EncoderCapabilities, to_rgba8, Encoder, Error, Result};
+use crate::metadata::FrameMetadata;
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+/// Marks the start of a watermark.
+const MAGIC: [u8; 4] = *b"BCWM";
+
+/// The size of the magic, the payload length (u16) and the checksum (u32) in bytes.
+const OVERHEAD: usize = MAGIC.len() + 2 + 4;
+
+/// The maximum length of a payload in bytes.
+pub const MAX_PAYLOAD_LEN: usize = u16::MAX as usize;
+
+/// An encoder that embeds a watermark into the frames before passing them to the inner encoder.
+/// The frames are converted to RGBA8.
+pub struct WatermarkEncoder<E> {
+    inner: E,
+    message: Vec<u8>,
+}
+
+impl<E: Encoder> WatermarkEncoder<E> {
+    /// Creates a new watermark encoder that passes the frames to the inner encoder. Payloads
+    /// longer than [`MAX_PAYLOAD_LEN`] are truncated.
+    pub fn new(inner: E, payload: impl AsRef<[u8]>) -> Self {
+        let payload = payload.as_ref();
+        let payload = &payload[..payload.len().min(MAX_PAYLOAD_LEN)];
+
+        let mut message = Vec::with_capacity(OVERHEAD + payload.len());
+        message.extend(MAGIC);
+        message.extend((payload.len() as u16).to_le_bytes());
+        message.extend(payload);
+        message.extend(checksum(payload).to_le_bytes());
+
+        Self { inner, message }
+    }
+}
+
+impl<E: Encoder> Encoder for WatermarkEncoder<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let (width, height) = (image.width(), image.height());
+        let mut rgba = to_rgba8(image)?.into_owned();
+
+        // Three bits per pixel, alpha is left untouched.
+        let capacity = rgba.len() / 4 * 3;
+        let bits = self.message.len() * 8;
+        if capacity < bits {
+            return Err(Error::format(format!(
+                "a {width}x{height} frame is too small for a watermark of {} bytes",
+                self.message.len()
+            )));
+        }
+
+        let channels = rgba
+            .chunks_exact_mut(4)
+            .flat_map(|pixel| pixel[..3].iter_mut());
+        for (i, channel) in channels.take(capacity / bits * bits).enumerate() {
+            let bit = i % bits;
+            let value = (self.message[bit / 8] >> (bit % 8)) & 1;
+            *channel = (*channel & !1) | value;
+        }
+
+        let image = Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            rgba,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        let image = self.inner.capabilities().convert(&image)?;
+        self.inner.encode_with_metadata(&image, metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        Box::new(self.inner).finish();
+    }
+}
+
+/// Reads the watermark of a frame written by a [`WatermarkEncoder`]. Returns `None` if the frame
+/// does not contain an intact copy of a watermark.
+pub fn decode_watermark(image: &Image) -> Result<Option<Vec<u8>>> {
+    let rgba = to_rgba8(image)?;
+    let bits = rgba
+        .chunks_exact(4)
+        .flat_map(|pixel| pixel[..3].iter().map(|channel| channel & 1))
+        .collect::<Vec<_>>();
+    let bytes = bits
+        .chunks_exact(8)
+        .map(|bits| {
+            bits.iter()
+                .enumerate()
+                .fold(0, |byte, (i, bit)| byte | (bit << i))
+        })
+        .collect::<Vec<u8>>();
+
+    // The copies are aligned to whole bytes, so a copy starts at every occurrence of the magic.
+    let mut offset = 0;
+    while let Some(start) = bytes[offset..]
+        .windows(MAGIC.len())
+        .position(|window| window == MAGIC)
+    {
+        let start = offset + start;
+        if let Some(payload) = parse_message(&bytes[start..]) {
+            return Ok(Some(payload.to_vec()));
+        }
+        offset = start + 1;
+    }
+    Ok(None)
+}
+
+fn parse_message(bytes: &[u8]) -> Option<&[u8]> {
+    let len = u16::from_le_bytes(bytes.get(4..6)?.try_into().unwrap()) as usize;
+    let payload = bytes.get(6..6 + len)?;
+    let expected = u32::from_le_bytes(bytes.get(6 + len..10 + len)?.try_into().unwrap());
+    (checksum(payload) == expected).then_some(payload)
+}
+
+/// The 32 bit FNV-1a hash of the payload.
+fn checksum(payload: &[u8]) -> u32 {
+    payload.iter().fold(0x811c9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
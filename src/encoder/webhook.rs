@@ -0,0 +1,208 @@
+//! Posts a message to a Discord or Slack webhook when a capture finishes or fails.
+
+use super::{capabilities::EncoderCapabilities, to_dynamic_image, Encoder, Error, Result};
+use crate::metadata::FrameMetadata;
+use bevy::{prelude::*, utils::Instant};
+use image::ImageFormat;
+use serde_json::json;
+use std::io::Cursor;
+
+/// The kind of webhook, which determines the payload format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookKind {
+    /// A Discord webhook. Supports thumbnails.
+    Discord,
+    /// A Slack incoming webhook.
+    Slack,
+}
+
+/// An encoder that wraps another encoder and posts a message to a webhook when the capture
+/// finishes, including the number of frames, the duration, and the first error, if any.
+///
+/// The message is posted from [`finish`](Encoder::finish) after the wrapped encoder has finished,
+/// which blocks until the request is done.
+pub struct WebhookNotifier<E> {
+    encoder: E,
+    report: Report,
+}
+
+struct Report {
+    kind: WebhookKind,
+    url: String,
+    title: String,
+    output: Option<String>,
+    thumbnail: Option<Vec<u8>>,
+    frames: u64,
+    started_at: Option<Instant>,
+    error: Option<String>,
+}
+
+impl<E: Encoder> WebhookNotifier<E> {
+    /// Creates a new notifier that posts to the given Discord webhook.
+    pub fn discord(url: impl Into<String>, encoder: E) -> Self {
+        Self::new(WebhookKind::Discord, url, encoder)
+    }
+
+    /// Creates a new notifier that posts to the given Slack webhook.
+    pub fn slack(url: impl Into<String>, encoder: E) -> Self {
+        Self::new(WebhookKind::Slack, url, encoder)
+    }
+
+    /// Creates a new notifier that posts to the given webhook.
+    pub fn new(kind: WebhookKind, url: impl Into<String>, encoder: E) -> Self {
+        Self {
+            encoder,
+            report: Report {
+                kind,
+                url: url.into(),
+                title: "capture".to_string(),
+                output: None,
+                thumbnail: None,
+                frames: 0,
+                started_at: None,
+                error: None,
+            },
+        }
+    }
+
+    /// Sets the title of the capture used in the message.
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.report.title = title.into();
+        self
+    }
+
+    /// Sets a description of the output, e.g. its file path or URL, that is included in the message.
+    pub fn with_output(mut self, output: impl Into<String>) -> Self {
+        self.report.output = Some(output.into());
+        self
+    }
+
+    /// Attaches a thumbnail of the first frame to the message (Discord only).
+    pub fn with_thumbnail(mut self) -> Self {
+        self.report.thumbnail = Some(Vec::new());
+        self
+    }
+}
+
+impl<E: Encoder> Encoder for WebhookNotifier<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let report = &mut self.report;
+        report.started_at.get_or_insert_with(Instant::now);
+
+        if let Some(thumbnail) = &mut report.thumbnail {
+            if thumbnail.is_empty() {
+                let image = to_dynamic_image(image)?.thumbnail(320, 320);
+                image.write_to(&mut Cursor::new(thumbnail), ImageFormat::Png)?;
+            }
+        }
+
+        let result = self.encoder.encode_with_metadata(image, metadata);
+        match &result {
+            Ok(()) => report.frames += 1,
+            Err(err) => {
+                report.error.get_or_insert_with(|| err.to_string());
+            }
+        }
+        result
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.encoder.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.encoder.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.encoder.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.encoder.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        let Self { encoder, report } = *self;
+        Box::new(encoder).finish();
+
+        if let Err(err) = report.post() {
+            bevy::log::error!("Failed to post webhook: {}", err);
+        }
+    }
+}
+
+impl Report {
+    fn message(&self) -> String {
+        let duration = self
+            .started_at
+            .map(|started_at| started_at.elapsed().as_secs_f32())
+            .unwrap_or_default();
+        let mut message = match &self.error {
+            None => format!(
+                "Capture `{}` finished: {} frames in {:.1}s.",
+                self.title, self.frames, duration
+            ),
+            Some(error) => format!(
+                "Capture `{}` failed after {} frames in {:.1}s: {}",
+                self.title, self.frames, duration, error
+            ),
+        };
+        if let Some(output) = &self.output {
+            message.push_str(&format!("\nOutput: {output}"));
+        }
+        message
+    }
+
+    fn post(&self) -> Result<()> {
+        let message = self.message();
+        match (self.kind, &self.thumbnail) {
+            (WebhookKind::Discord, Some(thumbnail)) if !thumbnail.is_empty() => {
+                let boundary = "bevy-capture-boundary";
+                let mut body = Vec::new();
+                body.extend_from_slice(
+                    format!(
+                        "--{boundary}\r\nContent-Disposition: form-data; name=\"payload_json\"\r\n\
+                         Content-Type: application/json\r\n\r\n{}\r\n",
+                        json!({ "content": message })
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(
+                    format!(
+                        "--{boundary}\r\nContent-Disposition: form-data; name=\"files[0]\"; \
+                         filename=\"thumbnail.png\"\r\nContent-Type: image/png\r\n\r\n"
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(thumbnail);
+                body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+                ureq::post(&self.url)
+                    .set(
+                        "Content-Type",
+                        &format!("multipart/form-data; boundary={boundary}"),
+                    )
+                    .send_bytes(&body)
+                    .map_err(|err| Error::external("webhook", err))?;
+            }
+            (WebhookKind::Discord, _) => {
+                ureq::post(&self.url)
+                    .set("Content-Type", "application/json")
+                    .send_string(&json!({ "content": message }).to_string())
+                    .map_err(|err| Error::external("webhook", err))?;
+            }
+            (WebhookKind::Slack, _) => {
+                ureq::post(&self.url)
+                    .set("Content-Type", "application/json")
+                    .send_string(&json!({ "text": message }).to_string())
+                    .map_err(|err| Error::external("webhook", err))?;
+            }
+        }
+        Ok(())
+    }
+}
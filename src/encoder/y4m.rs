@@ -0,0 +1,160 @@
+//! Encodes frames into a YUV4MPEG2 (y4m) stream, e.g. for piping into external tools.
+
+use super::{color::rgb_to_yuv, to_rgba8, Encoder, Error, Result};
+use bevy::prelude::*;
+use std::io::{self, Stdout, Write};
+
+/// An encoder that encodes a sequence of images into an uncompressed y4m stream (4:4:4 chroma).
+///
+/// Writing to stdout allows composing with external tools, e.g. `my_app | ffmpeg -i - out.mp4`.
+/// Bevy's `LogPlugin` already logs to stderr, so stdout only contains the stream.
+pub struct Y4mEncoder<W: Write> {
+    writer: W,
+    framerate: (u32, u32),
+    dimensions: Option<(u32, u32)>,
+}
+
+impl Y4mEncoder<Stdout> {
+    /// Creates a new y4m encoder that writes the stream to stdout.
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write> Y4mEncoder<W> {
+    /// Creates a new y4m encoder that writes the stream to the given writer.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            framerate: (60, 1),
+            dimensions: None,
+        }
+    }
+
+    /// Creates a new y4m encoder that writes the stream to the given writer, configured by the
+    /// given config.
+    pub fn from_config(writer: W, config: &Y4mConfig) -> Self {
+        Self::new(writer).with_framerate(config.framerate)
+    }
+
+    /// Sets the framerate of the stream.
+    pub fn with_framerate(mut self, framerate: u32) -> Self {
+        self.framerate = (framerate, 1);
+        self
+    }
+}
+
+/// The configuration of a [`Y4mEncoder`], which can be cloned and reused to create the encoders of
+/// many captures, see [`Y4mEncoder::from_config`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Y4mConfig {
+    framerate: u32,
+}
+
+impl Default for Y4mConfig {
+    fn default() -> Self {
+        Self { framerate: 60 }
+    }
+}
+
+impl Y4mConfig {
+    /// Creates a new config with the defaults of the [`Y4mEncoder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the framerate of the stream. Defaults to 60.
+    pub fn with_framerate(mut self, framerate: u32) -> Self {
+        self.framerate = framerate;
+        self
+    }
+
+    /// Returns the framerate of the stream.
+    pub fn framerate(&self) -> u32 {
+        self.framerate
+    }
+}
+
+impl<W: Write> Encoder for Y4mEncoder<W> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        let rgba = to_rgba8(image)?;
+        let (width, height) = (image.width(), image.height());
+
+        match self.dimensions {
+            None => {
+                writeln!(
+                    self.writer,
+                    "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                    width, height, self.framerate.0, self.framerate.1
+                )?;
+                self.dimensions = Some((width, height));
+            }
+            Some(dimensions) if dimensions != (width, height) => {
+                return Err(Error::format(
+                    "y4m streams do not support changing dimensions",
+                ));
+            }
+            Some(_) => {}
+        }
+
+        let pixels = (width * height) as usize;
+        let mut planes = vec![0; pixels * 3];
+        for (i, pixel) in rgba.chunks_exact(4).enumerate() {
+            // Limited range BT.601, the default color space of y4m.
+            let [y, u, v] = rgb_to_yuv(pixel[0], pixel[1], pixel[2]);
+            planes[i] = y;
+            planes[pixels + i] = u;
+            planes[2 * pixels + i] = v;
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&planes)?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "y4m"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    #[test]
+    fn encodes_y4m_stream() {
+        let frame = |width: u32, data: Vec<u8>| {
+            Image::new(
+                Extent3d {
+                    width,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                data,
+                TextureFormat::Rgba8UnormSrgb,
+                default(),
+            )
+        };
+        let image = frame(2, vec![255, 255, 255, 255, 0, 0, 0, 255]);
+
+        let mut stream = Vec::new();
+        {
+            let mut encoder = Y4mEncoder::new(&mut stream).with_framerate(30);
+            encoder.encode(&image).unwrap();
+            encoder.encode(&image).unwrap();
+            let err = encoder.encode(&frame(1, vec![0; 4])).unwrap_err();
+            assert!(matches!(err, Error::Format(_)), "{err:?}");
+        }
+
+        // A single header, then the planar limited range Y, U and V of every frame.
+        let header = b"YUV4MPEG2 W2 H1 F30:1 Ip A1:1 C444\n";
+        let planes = [b"FRAME\n".as_slice(), &[235, 16], &[128, 128], &[128, 128]].concat();
+        assert_eq!(stream, [header.as_slice(), &planes, &planes].concat());
+    }
+}
@@ -0,0 +1,95 @@
+//! Publishes frames on a ZeroMQ PUB socket.
+//!
+//! Every frame is sent as a multipart message:
+//!
+//! 1. The topic.
+//! 2. A header (all integers little endian): frame index (`u64`), width (`u32`), height (`u32`),
+//!    and the encoding of the data (`u8`, see [`FrameEncoding`]).
+//! 3. The data.
+
+use super::{to_dynamic_image, Encoder, Result};
+use bevy::prelude::*;
+use image::codecs::jpeg::JpegEncoder;
+use std::io;
+
+pub use zmq;
+
+/// The encoding of the published frame data.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameEncoding {
+    /// Raw RGBA8 pixels.
+    #[default]
+    Rgba8 = 0,
+    /// A JPEG image.
+    Jpeg = 1,
+}
+
+/// An encoder that publishes frames on a ZeroMQ PUB socket.
+pub struct ZmqEncoder {
+    socket: zmq::Socket,
+    topic: Vec<u8>,
+    jpeg_quality: Option<u8>,
+    frame: u64,
+}
+
+impl ZmqEncoder {
+    /// Creates a new ZeroMQ encoder that binds a PUB socket to the given endpoint,
+    /// e.g. `tcp://*:5555`, and publishes frames with the given topic, e.g. the name of the capture.
+    pub fn bind(endpoint: &str, topic: impl Into<Vec<u8>>) -> Result<Self> {
+        let socket = zmq::Context::new()
+            .socket(zmq::PUB)
+            .map_err(io::Error::from)?;
+        socket.bind(endpoint).map_err(io::Error::from)?;
+        Ok(Self::new(socket, topic))
+    }
+
+    /// Creates a new ZeroMQ encoder that publishes frames on the given (PUB) socket.
+    pub fn new(socket: zmq::Socket, topic: impl Into<Vec<u8>>) -> Self {
+        Self {
+            socket,
+            topic: topic.into(),
+            jpeg_quality: None,
+            frame: 0,
+        }
+    }
+
+    /// Compresses the frames as JPEG with the given quality (1-100).
+    pub fn with_jpeg(mut self, quality: u8) -> Self {
+        self.jpeg_quality = Some(quality.clamp(1, 100));
+        self
+    }
+}
+
+impl Encoder for ZmqEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        let image = to_dynamic_image(image)?;
+
+        let (encoding, data) = match self.jpeg_quality {
+            None => (FrameEncoding::Rgba8, image.to_rgba8().into_raw()),
+            Some(quality) => {
+                let mut data = Vec::new();
+                JpegEncoder::new_with_quality(&mut data, quality).encode_image(&image.to_rgb8())?;
+                (FrameEncoding::Jpeg, data)
+            }
+        };
+
+        let mut header = Vec::with_capacity(17);
+        header.extend_from_slice(&self.frame.to_le_bytes());
+        header.extend_from_slice(&image.width().to_le_bytes());
+        header.extend_from_slice(&image.height().to_le_bytes());
+        header.push(encoding as u8);
+
+        self.socket
+            .send_multipart([self.topic.as_slice(), &header, &data], 0)
+            .map_err(io::Error::from)?;
+
+        self.frame += 1;
+
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "zmq"
+    }
+}
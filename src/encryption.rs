@@ -0,0 +1,300 @@
+//! Encrypt output streams with AES-256-GCM, e.g. when capturing confidential builds on shared
+//! render infrastructure.
+//!
+//! An [`EncryptedWriter`] wraps the writer of an encoder, e.g. of the [`Y4mEncoder`] or
+//! [`RawEncoder`], so no plaintext ever reaches the disk or the network. Use [`decrypt`] or the
+//! `decrypt` example to restore the stream:
+//!
+//! ```sh
+//! cargo run --example decrypt --features encryption -- <hex key> capture.y4m.enc capture.y4m
+//! ```
+//!
+//! The stream is split into chunks that are encrypted and authenticated individually, so it can be
+//! written incrementally. Reordered, modified or truncated streams are detected on decryption.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::{encoder::y4m::Y4mEncoder, encryption::*};
+//! #
+//! let key = EncryptionKey::from_hex(&std::env::var("CAPTURE_KEY")?)?;
+//! let writer = EncryptedWriter::new(File::create("capture.y4m.enc")?, &key)?;
+//! capture.start(Y4mEncoder::new(writer));
+//! ```
+//!
+//! [`Y4mEncoder`]: crate::encoder::y4m::Y4mEncoder
+//! [`RawEncoder`]: crate::encoder::raw::RawEncoder
+
+use crate::encoder::{Error, Result};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+/// Marks the start of an encrypted stream and its version.
+const MAGIC: [u8; 8] = *b"BCAPENC1";
+
+/// The size of the random nonce prefix. The rest of the nonce is the chunk counter (u32).
+const PREFIX_LEN: usize = NONCE_LEN - 4;
+
+/// The size of the header, i.e. the magic and the nonce prefix.
+const HEADER_LEN: usize = MAGIC.len() + PREFIX_LEN;
+
+/// The maximum size of the plaintext of a chunk.
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// The size of the authentication tag of a chunk.
+const TAG_LEN: usize = 16;
+
+/// Marks the last chunk of a stream.
+const FINAL_CHUNK: u8 = 1;
+
+/// A 256 bit AES key.
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// Creates a key from raw bytes.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Parses a key from 64 hexadecimal characters.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let hex = hex.trim();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err("an encryption key must be 64 hexadecimal characters".into());
+        }
+        let mut bytes = [0; 32];
+        for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(digits).unwrap(), 16)
+                .map_err(|_| "an encryption key must be 64 hexadecimal characters")?;
+        }
+        Ok(Self(bytes))
+    }
+
+    /// Generates a random key with the system random number generator.
+    pub fn generate() -> Result<Self> {
+        let mut bytes = [0; 32];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| "failed to generate a random key")?;
+        Ok(Self(bytes))
+    }
+
+    /// Returns the key as 64 hexadecimal characters, e.g. to store it in a secret manager.
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn aead_key(&self) -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &self.0).unwrap())
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never leak the key into logs.
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// A writer that encrypts everything written to it before passing it to the inner writer.
+///
+/// The stream is completed by [`finish`](Self::finish) or when the writer is dropped. Streams that
+/// were not completed are rejected on decryption, as they could have been truncated.
+pub struct EncryptedWriter<W: Write> {
+    inner: Option<W>,
+    key: LessSafeKey,
+    header: [u8; HEADER_LEN],
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptedWriter<W> {
+    /// Creates a new encrypted writer and writes the header of the stream.
+    pub fn new(mut inner: W, key: &EncryptionKey) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(&MAGIC);
+        SystemRandom::new()
+            .fill(&mut header[MAGIC.len()..])
+            .map_err(|_| io::Error::other("failed to generate a random nonce"))?;
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner: Some(inner),
+            key: key.aead_key(),
+            header,
+            counter: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    /// Writes the remaining data and completes the stream. Returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk(FINAL_CHUNK)?;
+        let mut inner = self.inner.take().unwrap();
+        inner.flush()?;
+        Ok(inner)
+    }
+
+    fn write_chunk(&mut self, flags: u8) -> io::Result<()> {
+        let nonce = nonce(&self.header, self.counter);
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("the encrypted stream is too long"))?;
+
+        let mut chunk = std::mem::take(&mut self.buffer);
+        self.key
+            .seal_in_place_append_tag(nonce, aad(&self.header, flags), &mut chunk)
+            .map_err(|_| io::Error::other("failed to encrypt a chunk"))?;
+
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(&[flags])?;
+        inner.write_all(&(chunk.len() as u32).to_le_bytes())?;
+        inner.write_all(&chunk)?;
+
+        chunk.clear();
+        self.buffer = chunk;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == CHUNK_SIZE {
+            self.write_chunk(0)?;
+        }
+        Ok(len)
+    }
+
+    /// Encrypts the buffered data as a (shorter) chunk and flushes the inner writer.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_chunk(0)?;
+        }
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for EncryptedWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            if let Err(err) = self.write_chunk(FINAL_CHUNK) {
+                bevy::log::error!("Failed to complete the encrypted stream: {:?}", err);
+            }
+        }
+    }
+}
+
+/// Decrypts a stream written by an [`EncryptedWriter`]. Fails if the key is wrong or the stream was
+/// modified or truncated, in which case the output must be discarded.
+pub fn decrypt(mut reader: impl Read, mut writer: impl Write, key: &EncryptionKey) -> Result<()> {
+    let mut header = [0; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    if header[..MAGIC.len()] != MAGIC {
+        return Err(Error::format("not an encrypted stream"));
+    }
+
+    let key = key.aead_key();
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE + TAG_LEN);
+    for counter in 0..=u32::MAX {
+        let mut chunk_header = [0; 5];
+        match reader.read_exact(&mut chunk_header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(Error::format("the encrypted stream is truncated"));
+            }
+            Err(err) => return Err(err.into()),
+        }
+        let flags = chunk_header[0];
+        let len = u32::from_le_bytes(chunk_header[1..].try_into().unwrap()) as usize;
+        if !(TAG_LEN..=CHUNK_SIZE + TAG_LEN).contains(&len) {
+            return Err(Error::format("the encrypted stream is corrupted"));
+        }
+
+        chunk.resize(len, 0);
+        reader.read_exact(&mut chunk)?;
+        let plaintext = key
+            .open_in_place(nonce(&header, counter), aad(&header, flags), &mut chunk)
+            .map_err(|_| Error::format("the key is wrong or the encrypted stream is corrupted"))?;
+        writer.write_all(plaintext)?;
+
+        if flags == FINAL_CHUNK {
+            writer.flush()?;
+            return Ok(());
+        }
+    }
+
+    Err(Error::format("the encrypted stream is too long"))
+}
+
+fn nonce(header: &[u8; HEADER_LEN], counter: u32) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..PREFIX_LEN].copy_from_slice(&header[MAGIC.len()..]);
+    nonce[PREFIX_LEN..].copy_from_slice(&counter.to_le_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// The header and the flags are authenticated with every chunk, so neither can be swapped.
+fn aad(header: &[u8; HEADER_LEN], flags: u8) -> Aad<[u8; HEADER_LEN + 1]> {
+    let mut aad = [0; HEADER_LEN + 1];
+    aad[..HEADER_LEN].copy_from_slice(header);
+    aad[HEADER_LEN] = flags;
+    Aad::from(aad)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypts_output_stream() {
+        // Spans several chunks, the last one is partial.
+        let plaintext = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let key = EncryptionKey::generate().unwrap();
+        assert_eq!(
+            EncryptionKey::from_hex(&key.to_hex()).unwrap().to_hex(),
+            key.to_hex()
+        );
+
+        let mut writer = EncryptedWriter::new(Vec::new(), &key).unwrap();
+        writer.write_all(&plaintext[..1000]).unwrap();
+        writer.flush().unwrap();
+        writer.write_all(&plaintext[1000..]).unwrap();
+        let encrypted = writer.finish().unwrap();
+        assert!(!encrypted
+            .windows(64)
+            .any(|window| window == &plaintext[..64]));
+
+        let mut decrypted = Vec::new();
+        decrypt(&encrypted[..], &mut decrypted, &key).unwrap();
+        assert_eq!(decrypted, plaintext);
+
+        // A wrong key, a modified or a truncated stream are rejected.
+        let wrong_key = EncryptionKey::generate().unwrap();
+        assert!(decrypt(&encrypted[..], &mut Vec::new(), &wrong_key).is_err());
+        let mut modified = encrypted.clone();
+        modified[100] ^= 1;
+        assert!(decrypt(&modified[..], &mut Vec::new(), &key).is_err());
+        let truncated = &encrypted[..encrypted.len() - 20];
+        assert!(decrypt(truncated, &mut Vec::new(), &key).is_err());
+        assert!(EncryptionKey::from_hex("not a key").is_err());
+
+        // Dropping the writer completes the stream as well.
+        let mut encrypted = Vec::new();
+        {
+            let mut writer = EncryptedWriter::new(&mut encrypted, &key).unwrap();
+            writer.write_all(b"dropped").unwrap();
+        }
+        let mut decrypted = Vec::new();
+        decrypt(&encrypted[..], &mut decrypted, &key).unwrap();
+        assert_eq!(decrypted, b"dropped");
+    }
+}
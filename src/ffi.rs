@@ -0,0 +1,161 @@
+//! Control captures from C, C++ or C# hosts embedding a Bevy app as a library (feature `ffi`).
+//!
+//! Add the [`CaptureFfiPlugin`] to the app, the host then calls the `extern "C"` functions of this
+//! module, declared in `include/bevy_capture.h`. The functions queue
+//! [script commands](crate::scripting::ScriptCommand), which are applied to the captures in
+//! `PreUpdate`, so they can be called from any thread.
+//!
+//! All functions return [`BEVY_CAPTURE_OK`] on success and a negative error code otherwise. The
+//! `capture` argument is the [`Name`](bevy::core::Name) of the capture entity, or null for all
+//! captures.
+//!
+//! # Example
+//! ```c
+//! bevy_capture_set_output_path("captures/boss_fight.mp4");
+//! bevy_capture_start(NULL, "mp4_ffmpeg_cli", "{ \"framerate\": 30 }");
+//! // ...
+//! bevy_capture_stop(NULL);
+//! bevy_capture_screenshot("overview_camera", "captures/arena.png");
+//! ```
+
+use crate::scripting::{CaptureScriptQueue, CaptureScriptingPlugin};
+use bevy::prelude::*;
+use std::{
+    ffi::{c_char, c_int, CStr},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// The function succeeded.
+pub const BEVY_CAPTURE_OK: c_int = 0;
+
+/// An argument was null or not valid UTF-8.
+pub const BEVY_CAPTURE_INVALID_ARGUMENT: c_int = -1;
+
+/// The [`CaptureFfiPlugin`] was not added to the app.
+pub const BEVY_CAPTURE_NOT_INITIALIZED: c_int = -2;
+
+/// No output path was set with [`bevy_capture_set_output_path`].
+pub const BEVY_CAPTURE_NO_OUTPUT_PATH: c_int = -3;
+
+static STATE: Mutex<Option<FfiState>> = Mutex::new(None);
+
+struct FfiState {
+    queue: CaptureScriptQueue,
+    output_path: Option<PathBuf>,
+}
+
+/// A Bevy plugin that lets the host control the captures of the app through the C functions, see
+/// the [module docs](self). Adds the [`CaptureScriptingPlugin`] if it was not added yet.
+///
+/// Only one app per process can be controlled, the last app the plugin was added to.
+pub struct CaptureFfiPlugin;
+
+impl Plugin for CaptureFfiPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<CaptureScriptingPlugin>() {
+            app.add_plugins(CaptureScriptingPlugin);
+        }
+        let queue = app.world().resource::<CaptureScriptQueue>().clone();
+        *STATE.lock().unwrap() = Some(FfiState {
+            queue,
+            output_path: None,
+        });
+    }
+}
+
+/// Sets the output path of the captures started with [`bevy_capture_start`].
+///
+/// # Safety
+///
+/// `path` must be null or a valid pointer to a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bevy_capture_set_output_path(path: *const c_char) -> c_int {
+    let Some(Some(path)) = to_str(path) else {
+        return BEVY_CAPTURE_INVALID_ARGUMENT;
+    };
+    with_state(|state| {
+        state.output_path = Some(PathBuf::from(path));
+        BEVY_CAPTURE_OK
+    })
+}
+
+/// Starts capturing with the encoder registered under the given name in the
+/// [`EncoderRegistry`](crate::encoder::registry::EncoderRegistry), writing to the output path set
+/// with [`bevy_capture_set_output_path`]. `config` is the config of the encoder in JSON, or null
+/// for the defaults.
+///
+/// # Safety
+///
+/// All arguments must be null or valid pointers to nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn bevy_capture_start(
+    capture: *const c_char,
+    encoder: *const c_char,
+    config: *const c_char,
+) -> c_int {
+    let (Some(capture), Some(Some(encoder)), Some(config)) =
+        (to_str(capture), to_str(encoder), to_str(config))
+    else {
+        return BEVY_CAPTURE_INVALID_ARGUMENT;
+    };
+    with_state(|state| {
+        let Some(path) = &state.output_path else {
+            return BEVY_CAPTURE_NO_OUTPUT_PATH;
+        };
+        state
+            .queue
+            .start(capture, encoder, path.clone(), config.unwrap_or("{}"));
+        BEVY_CAPTURE_OK
+    })
+}
+
+/// Stops capturing.
+///
+/// # Safety
+///
+/// `capture` must be null or a valid pointer to a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bevy_capture_stop(capture: *const c_char) -> c_int {
+    let Some(capture) = to_str(capture) else {
+        return BEVY_CAPTURE_INVALID_ARGUMENT;
+    };
+    with_state(|state| {
+        state.queue.stop(capture);
+        BEVY_CAPTURE_OK
+    })
+}
+
+/// Captures the next frame into a png at the given path.
+///
+/// # Safety
+///
+/// All arguments must be null or valid pointers to nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn bevy_capture_screenshot(
+    capture: *const c_char,
+    path: *const c_char,
+) -> c_int {
+    let (Some(capture), Some(Some(path))) = (to_str(capture), to_str(path)) else {
+        return BEVY_CAPTURE_INVALID_ARGUMENT;
+    };
+    with_state(|state| {
+        state.queue.screenshot(capture, path);
+        BEVY_CAPTURE_OK
+    })
+}
+
+/// Converts a nullable C string, returns `None` if it is not valid UTF-8.
+unsafe fn to_str<'a>(string: *const c_char) -> Option<Option<&'a str>> {
+    if string.is_null() {
+        return Some(None);
+    }
+    CStr::from_ptr(string).to_str().ok().map(Some)
+}
+
+fn with_state(f: impl FnOnce(&mut FfiState) -> c_int) -> c_int {
+    match &mut *STATE.lock().unwrap() {
+        Some(state) => f(state),
+        None => BEVY_CAPTURE_NOT_INITIALIZED,
+    }
+}
@@ -0,0 +1,102 @@
+//! Choose per capture whether gizmos are part of the recorded frames, e.g. no debug gizmos in
+//! trailer footage, but all of them in debug footage.
+//!
+//! Gizmos are rendered by every camera on their render layers, so by default they end up in all
+//! captures. The [`CaptureGizmosPlugin`] moves gizmo config groups on the default render layer to
+//! the [`GIZMO_LAYER`] instead, and adds that layer to every camera that rendered the default layer
+//! before, so nothing changes for the player's view. Attach [`CaptureGizmos::Exclude`] to a capture
+//! camera to remove the layer from it, or [`CaptureGizmos::Include`] to record gizmos with a camera
+//! that doesn't render the default layer.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{gizmos::*, CaptureBundle};
+//! #
+//! app.add_plugins(CaptureGizmosPlugin);
+//!
+//! commands.spawn((
+//!     Camera3dBundle::default().target_headless(1920, 1080, &mut images),
+//!     CaptureBundle::default(),
+//!     CaptureGizmos::Exclude,
+//! ));
+//! ```
+
+use bevy::{
+    gizmos::config::GizmoConfigStore,
+    prelude::*,
+    render::view::{Layer, RenderLayers},
+};
+
+/// The render layer gizmos on the default render layer are moved to.
+pub const GIZMO_LAYER: Layer = 30;
+
+/// A Bevy plugin that manages the [`GIZMO_LAYER`] of cameras, see the [module docs](self).
+/// This requires the `GizmoPlugin`.
+pub struct CaptureGizmosPlugin;
+
+impl Plugin for CaptureGizmosPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, (move_gizmo_configs, update_camera_layers));
+    }
+}
+
+/// Whether a camera records gizmos. This is optional and can be attached next to the
+/// [`Capture`](crate::Capture). Cameras without it render gizmos if they render the default
+/// render layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub enum CaptureGizmos {
+    /// Gizmos are rendered by the camera, regardless of its render layers.
+    Include,
+    /// Gizmos are not rendered by the camera.
+    Exclude,
+}
+
+fn move_gizmo_configs(config_store: Option<ResMut<GizmoConfigStore>>) {
+    let Some(mut config_store) = config_store else {
+        return;
+    };
+    let default_layers = RenderLayers::default();
+    // Config groups can be added at any time, so this is checked every frame.
+    if config_store
+        .iter()
+        .all(|(_, config, _)| config.render_layers != default_layers)
+    {
+        return;
+    }
+    for (_, config, _) in config_store.iter_mut() {
+        if config.render_layers == default_layers {
+            config.render_layers = RenderLayers::layer(GIZMO_LAYER);
+        }
+    }
+}
+
+type CamerasQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        Option<&'static RenderLayers>,
+        Option<&'static CaptureGizmos>,
+    ),
+    With<Camera>,
+>;
+
+fn update_camera_layers(mut commands: Commands, cameras: CamerasQuery<'_, '_>) {
+    for (entity, layers, gizmos) in &cameras {
+        let layers = layers.cloned().unwrap_or_default();
+        let include = match gizmos {
+            Some(CaptureGizmos::Include) => true,
+            Some(CaptureGizmos::Exclude) => false,
+            None => layers.intersects(&RenderLayers::default()),
+        };
+        let new_layers = if include {
+            layers.clone().with(GIZMO_LAYER)
+        } else {
+            layers.clone().without(GIZMO_LAYER)
+        };
+        if new_layers != layers {
+            commands.entity(entity).insert(new_layers);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Compare frames against golden images within a tolerance, e.g. to assert renders across GPUs and
+//! platforms that never match exactly.
+//!
+//! The difference of two pixels is measured perceptually in the YIQ color space, which weights
+//! brightness more than hue, as in pixelmatch. A [`DiffTolerance`] allows a threshold per pixel and
+//! a ratio of pixels above it. The [`ImageDiff`] contains a diff image with the differing pixels in
+//! red on a faded copy of the expected image.
+//!
+//! Set the `BEVY_CAPTURE_UPDATE_GOLDEN` environment variable to write missing or mismatching golden
+//! images instead of failing, e.g. after an intended change or for a new platform. Keep separate
+//! golden directories per platform if the differences exceed any sensible tolerance.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::golden::*;
+//! #
+//! let tolerance = DiffTolerance::new(0.1).with_max_diff_ratio(0.001);
+//! assert_golden(&frame, "tests/golden/sprite.png", &tolerance);
+//! ```
+
+use crate::encoder::{self, to_rgba8, Result};
+use bevy::{prelude::*, render::render_asset::RenderAssetUsages};
+use image::RgbaImage;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// The environment variable that makes golden comparisons write the actual image as the new golden
+/// image instead of failing.
+pub const UPDATE_GOLDEN_ENV: &str = "BEVY_CAPTURE_UPDATE_GOLDEN";
+
+/// The maximum YIQ difference of two pixels, i.e. between black and white.
+const MAX_YIQ_DELTA: f32 = 35215.0;
+
+/// How much two images may differ.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffTolerance {
+    threshold: f32,
+    max_diff_ratio: f32,
+}
+
+impl Default for DiffTolerance {
+    fn default() -> Self {
+        Self::new(0.1)
+    }
+}
+
+impl DiffTolerance {
+    /// Only identical images match.
+    pub fn exact() -> Self {
+        Self::new(0.0)
+    }
+
+    /// Creates a tolerance where pixels differ if their perceptual difference is above the
+    /// threshold, from `0.0` (any change) to `1.0` (black to white). No differing pixels are
+    /// allowed, see [`with_max_diff_ratio`](Self::with_max_diff_ratio).
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold: threshold.clamp(0.0, 1.0),
+            max_diff_ratio: 0.0,
+        }
+    }
+
+    /// Sets the ratio of pixels that may differ, from `0.0` to `1.0`, e.g. to allow small
+    /// differences at the edges of anti-aliased geometry.
+    pub fn with_max_diff_ratio(mut self, max_diff_ratio: f32) -> Self {
+        self.max_diff_ratio = max_diff_ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns the threshold per pixel.
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    /// Returns the ratio of pixels that may differ.
+    pub fn max_diff_ratio(&self) -> f32 {
+        self.max_diff_ratio
+    }
+}
+
+/// The difference of two images.
+#[derive(Debug, Clone)]
+pub struct ImageDiff {
+    /// The number of pixels above the threshold of the tolerance.
+    pub diff_pixels: usize,
+    /// The total number of pixels.
+    pub total_pixels: usize,
+    /// The largest perceptual difference of a pixel, from `0.0` to `1.0`.
+    pub max_delta: f32,
+    /// The differing pixels in red on a faded copy of the expected image.
+    pub diff_image: RgbaImage,
+    tolerance: DiffTolerance,
+}
+
+impl ImageDiff {
+    /// Returns the ratio of pixels above the threshold.
+    pub fn diff_ratio(&self) -> f32 {
+        if self.total_pixels == 0 {
+            0.0
+        } else {
+            self.diff_pixels as f32 / self.total_pixels as f32
+        }
+    }
+
+    /// Returns `true` if the images match within the tolerance.
+    pub fn is_match(&self) -> bool {
+        self.diff_ratio() <= self.tolerance.max_diff_ratio
+    }
+}
+
+/// Compares two images. Fails if the dimensions differ or a format is not supported, see
+/// [`to_rgba8`].
+pub fn diff(actual: &Image, expected: &Image, tolerance: &DiffTolerance) -> Result<ImageDiff> {
+    let (width, height) = (actual.width(), actual.height());
+    if expected.size() != actual.size() {
+        return Err(encoder::Error::format(format!(
+            "expected an image of {}x{}, got {width}x{height}",
+            expected.width(),
+            expected.height()
+        )));
+    }
+    let actual = to_rgba8(actual)?;
+    let expected = to_rgba8(expected)?;
+
+    let mut diff = ImageDiff {
+        diff_pixels: 0,
+        total_pixels: (width * height) as usize,
+        max_delta: 0.0,
+        diff_image: RgbaImage::new(width, height),
+        tolerance: *tolerance,
+    };
+    for ((actual, expected), target) in actual
+        .chunks_exact(4)
+        .zip(expected.chunks_exact(4))
+        .zip(diff.diff_image.pixels_mut())
+    {
+        let delta = yiq_delta(actual, expected) / MAX_YIQ_DELTA;
+        diff.max_delta = diff.max_delta.max(delta);
+        target.0 = if delta > tolerance.threshold {
+            diff.diff_pixels += 1;
+            [255, 0, 0, 255]
+        } else {
+            // Fade the expected image, so the differences stand out.
+            let luma = luma(&[expected[0], expected[1], expected[2]].map(f32::from));
+            let faded = (255.0 - 0.1 * (255.0 - luma)) as u8;
+            [faded, faded, faded, 255]
+        };
+    }
+
+    Ok(diff)
+}
+
+/// Compares the image with the golden image at the given path.
+///
+/// If the golden image does not exist or does not match, and the [`UPDATE_GOLDEN_ENV`]
+/// environment variable is set, the image is written as the new golden image and a match is
+/// returned.
+pub fn diff_golden(
+    actual: &Image,
+    golden: impl AsRef<Path>,
+    tolerance: &DiffTolerance,
+) -> Result<ImageDiff> {
+    let golden = golden.as_ref();
+    let update = env::var_os(UPDATE_GOLDEN_ENV).is_some();
+
+    match read_golden(golden).and_then(|expected| diff(actual, &expected, tolerance)) {
+        Ok(diff) if diff.is_match() || !update => Ok(diff),
+        Err(err) if !update => Err(err),
+        _ => {
+            if let Some(directory) = golden.parent() {
+                fs::create_dir_all(directory)?;
+            }
+            encoder::to_dynamic_image(actual)?.to_rgba8().save(golden)?;
+            diff(actual, actual, tolerance)
+        }
+    }
+}
+
+/// Compares the image with the golden image at the given path and panics if they don't match. The
+/// diff image is saved next to the golden image as `<name>.diff.png`.
+#[track_caller]
+pub fn assert_golden(actual: &Image, golden: impl AsRef<Path>, tolerance: &DiffTolerance) {
+    let golden = golden.as_ref();
+    let diff = match diff_golden(actual, golden, tolerance) {
+        Ok(diff) => diff,
+        Err(err) => panic!("Failed to compare with {}: {err}", golden.display()),
+    };
+    if diff.is_match() {
+        return;
+    }
+
+    let diff_path = diff_path(golden);
+    let saved = diff.diff_image.save(&diff_path).is_ok();
+    panic!(
+        "{} of {} pixels differ from {} (max delta {:.3}){}",
+        diff.diff_pixels,
+        diff.total_pixels,
+        golden.display(),
+        diff.max_delta,
+        if saved {
+            format!(", see {}", diff_path.display())
+        } else {
+            String::new()
+        },
+    );
+}
+
+/// Returns the path of the diff image of a golden image, `<name>.diff.png` in the same directory.
+pub fn diff_path(golden: impl AsRef<Path>) -> PathBuf {
+    let golden = golden.as_ref();
+    let stem = golden.file_stem().unwrap_or_default().to_string_lossy();
+    golden.with_file_name(format!("{stem}.diff.png"))
+}
+
+fn read_golden(path: &Path) -> Result<Image> {
+    let golden = image::open(path)?.into_rgba8();
+    Ok(Image::from_dynamic(
+        golden.into(),
+        true,
+        RenderAssetUsages::default(),
+    ))
+}
+
+fn luma(pixel: &[f32]) -> f32 {
+    0.298_895_3 * pixel[0] + 0.586_622_5 * pixel[1] + 0.114_482_2 * pixel[2]
+}
+
+/// The squared YIQ difference of two pixels, blended with white by their alpha.
+fn yiq_delta(a: &[u8], b: &[u8]) -> f32 {
+    let blend = |pixel: &[u8]| {
+        let alpha = pixel[3] as f32 / 255.0;
+        [0, 1, 2].map(|c| 255.0 + (pixel[c] as f32 - 255.0) * alpha)
+    };
+    let (a, b) = (blend(a), blend(b));
+    let i = |p: [f32; 3]| 0.595_978 * p[0] - 0.274_176_1 * p[1] - 0.321_801_9 * p[2];
+    let q = |p: [f32; 3]| 0.211_470_2 * p[0] - 0.522_617_1 * p[1] + 0.311_146_9 * p[2];
+
+    let dy = luma(&a) - luma(&b);
+    let di = i(a) - i(b);
+    let dq = q(a) - q(b);
+    0.5053 * dy * dy + 0.299 * di * di + 0.1957 * dq * dq
+}
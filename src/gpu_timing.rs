@@ -0,0 +1,131 @@
+//! Record render pass timings alongside the captured frames.
+//!
+//! The [`GpuTimingPlugin`] enables Bevy's render diagnostics, which measure the CPU and GPU
+//! (via wgpu timestamp queries) time of every render pass, and attaches the measurements to the
+//! [frame metadata](crate::metadata). The [`GpuTimingEncoder`] writes them to a CSV file:
+//!
+//! ```text
+//! frame,pass,measurement,ms
+//! 0,main_opaque_pass_3d,elapsed_gpu,1.204
+//! 0,main_opaque_pass_3d,elapsed_cpu,0.081
+//! ...
+//! ```
+//!
+//! The frame is the [index of the captured frame](crate::metadata::FRAME_INDEX_KEY) the
+//! measurements were attached to, so the rows line up with the frames of other encoders of the
+//! capture. Timestamp queries are resolved asynchronously and Bevy doesn't report which frame a
+//! measurement belongs to, so the measurements attached to a frame are the most recent ones
+//! available and usually lag one or two frames behind. GPU timings are only available on Vulkan
+//! and DX12 adapters that support timestamp queries, otherwise only CPU timings are recorded.
+
+use crate::{
+    encoder::{self, Encoder},
+    metadata::{FrameMetadata, MetadataValue},
+};
+use bevy::{
+    diagnostic::DiagnosticsStore, prelude::*, render::diagnostic::RenderDiagnosticsPlugin,
+    utils::Instant,
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// The prefix of the metadata keys of render pass timings.
+pub const RENDER_TIMING_PREFIX: &str = "render/";
+
+/// A Bevy plugin that attaches render pass timings to the frame metadata. This adds the
+/// `RenderDiagnosticsPlugin` if necessary.
+pub struct GpuTimingPlugin;
+
+impl Plugin for GpuTimingPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<RenderDiagnosticsPlugin>() {
+            app.add_plugins(RenderDiagnosticsPlugin);
+        }
+
+        app.init_resource::<DiagnosticsStore>()
+            .add_systems(PostUpdate, insert_render_timings);
+    }
+}
+
+fn insert_render_timings(
+    diagnostics: Res<DiagnosticsStore>,
+    mut metadata: ResMut<FrameMetadata>,
+    mut last_update: Local<Option<Instant>>,
+) {
+    let mut latest = *last_update;
+    for diagnostic in diagnostics.iter() {
+        let path = diagnostic.path().as_str();
+        if !path.starts_with(RENDER_TIMING_PREFIX) {
+            continue;
+        }
+        let Some(measurement) = diagnostic.measurement() else {
+            continue;
+        };
+
+        // Only measurements that were not attached to a previous frame.
+        if Some(measurement.time) > *last_update {
+            metadata.insert(path, measurement.value);
+            latest = latest.max(Some(measurement.time));
+        }
+    }
+    *last_update = latest;
+}
+
+/// An encoder that writes the render pass timings of every frame to a CSV file. The pixels are
+/// ignored, so this is usually combined with other encoders. Requires the [`GpuTimingPlugin`].
+pub struct GpuTimingEncoder {
+    writer: BufWriter<File>,
+    /// The index of the next frame, for frames without a frame index in their metadata.
+    frame: u64,
+}
+
+impl GpuTimingEncoder {
+    /// Creates a new GPU timing encoder that writes to the given path.
+    pub fn new(path: impl AsRef<Path>) -> encoder::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "frame,pass,measurement,ms")?;
+
+        Ok(Self { writer, frame: 0 })
+    }
+}
+
+impl Encoder for GpuTimingEncoder {
+    fn encode(&mut self, image: &Image) -> encoder::Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, _: &Image, metadata: &FrameMetadata) -> encoder::Result<()> {
+        let frame = metadata.frame_index().unwrap_or(self.frame);
+        for (key, value) in metadata.iter() {
+            let (Some(path), MetadataValue::Float(ms)) =
+                (key.strip_prefix(RENDER_TIMING_PREFIX), value)
+            else {
+                continue;
+            };
+            // The last path component is the measurement, the rest is the (possibly nested) pass.
+            let (pass, measurement) = path.rsplit_once('/').unwrap_or((path, ""));
+            writeln!(self.writer, "{},{},{},{:.3}", frame, pass, measurement, ms)?;
+        }
+        self.frame = frame + 1;
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> encoder::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) {
+        if let Err(err) = self.writer.flush() {
+            bevy::log::error!("Failed to write gpu timings: {}", err);
+        }
+    }
+
+    fn name(&self) -> &str {
+        "gpu_timing"
+    }
+}
@@ -0,0 +1,396 @@
+//! Show the pressed keys and buttons in the captured frames, e.g. for tutorial-style footage.
+//!
+//! The [`InputOverlayPlugin`] records the pressed keyboard keys, mouse buttons and gamepad buttons
+//! of every frame in the [frame metadata](crate::metadata) with the [`INPUT_KEY`] key. The
+//! [`InputOverlayEncoder`] draws them as key caps into the frames before passing them on, so the
+//! overlay is only part of the capture and not of the game window.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{encoder::mp4_ffmpeg_cli::Mp4FfmpegCliEncoder, input_overlay::*, Capture};
+//! #
+//! app.add_plugins(InputOverlayPlugin);
+//!
+//! fn start(mut capture: Query<&mut Capture>) {
+//!     capture.single_mut().start(InputOverlayEncoder::new(
+//!         Mp4FfmpegCliEncoder::new("tutorial.mp4").unwrap(),
+//!     ));
+//! }
+//! ```
+
+use crate::{
+    encoder::{capabilities::EncoderCapabilities, to_rgba8, Encoder, Result},
+    metadata::{FrameMetadata, MetadataValue},
+};
+use bevy::{
+    input::gamepad::{GamepadButton, GamepadButtonType},
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+/// The metadata key of the pressed inputs, as labels separated by spaces, e.g. `"CTRL C"`.
+pub const INPUT_KEY: &str = "input";
+
+/// A Bevy plugin that records the pressed inputs in the frame metadata.
+pub struct InputOverlayPlugin;
+
+impl Plugin for InputOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, record_input);
+    }
+}
+
+fn record_input(
+    mut metadata: ResMut<FrameMetadata>,
+    keys: Option<Res<ButtonInput<KeyCode>>>,
+    mouse_buttons: Option<Res<ButtonInput<MouseButton>>>,
+    gamepad_buttons: Option<Res<ButtonInput<GamepadButton>>>,
+) {
+    let mut labels = Vec::new();
+    if let Some(keys) = &keys {
+        labels.extend(keys.get_pressed().map(|&key| key_label(key)));
+    }
+    if let Some(mouse_buttons) = &mouse_buttons {
+        labels.extend(
+            mouse_buttons
+                .get_pressed()
+                .map(|&button| mouse_button_label(button)),
+        );
+    }
+    if let Some(gamepad_buttons) = &gamepad_buttons {
+        labels.extend(
+            gamepad_buttons
+                .get_pressed()
+                .map(|button| gamepad_button_label(button.button_type)),
+        );
+    }
+    if labels.is_empty() {
+        return;
+    }
+
+    // Modifiers first, the rest sorted, so the order is stable between frames.
+    labels.sort_by_key(|label| {
+        (
+            !matches!(label.as_str(), "CTRL" | "SHIFT" | "ALT" | "SUPER"),
+            label.clone(),
+        )
+    });
+    labels.dedup();
+    metadata.insert(INPUT_KEY, labels.join(" "));
+}
+
+fn key_label(key: KeyCode) -> String {
+    let label = match key {
+        KeyCode::ControlLeft | KeyCode::ControlRight => "CTRL",
+        KeyCode::ShiftLeft | KeyCode::ShiftRight => "SHIFT",
+        KeyCode::AltLeft | KeyCode::AltRight => "ALT",
+        KeyCode::SuperLeft | KeyCode::SuperRight => "SUPER",
+        KeyCode::Escape => "ESC",
+        KeyCode::Backspace => "BKSP",
+        KeyCode::ArrowUp => "UP",
+        KeyCode::ArrowDown => "DOWN",
+        KeyCode::ArrowLeft => "LEFT",
+        KeyCode::ArrowRight => "RIGHT",
+        key => {
+            // E.g. `KeyW`, `Digit1` or `Space`.
+            let name = format!("{key:?}");
+            let name = name
+                .strip_prefix("Key")
+                .or_else(|| name.strip_prefix("Digit"))
+                .unwrap_or(&name);
+            return name.to_uppercase();
+        }
+    };
+    label.to_owned()
+}
+
+fn mouse_button_label(button: MouseButton) -> String {
+    match button {
+        MouseButton::Left => "LMB".to_owned(),
+        MouseButton::Right => "RMB".to_owned(),
+        MouseButton::Middle => "MMB".to_owned(),
+        MouseButton::Back => "MB4".to_owned(),
+        MouseButton::Forward => "MB5".to_owned(),
+        MouseButton::Other(button) => format!("MB{button}"),
+    }
+}
+
+fn gamepad_button_label(button: GamepadButtonType) -> String {
+    let label = match button {
+        GamepadButtonType::South => "A",
+        GamepadButtonType::East => "B",
+        GamepadButtonType::West => "X",
+        GamepadButtonType::North => "Y",
+        GamepadButtonType::LeftTrigger => "LB",
+        GamepadButtonType::LeftTrigger2 => "LT",
+        GamepadButtonType::RightTrigger => "RB",
+        GamepadButtonType::RightTrigger2 => "RT",
+        GamepadButtonType::LeftThumb => "LS",
+        GamepadButtonType::RightThumb => "RS",
+        GamepadButtonType::DPadUp => "UP",
+        GamepadButtonType::DPadDown => "DOWN",
+        GamepadButtonType::DPadLeft => "LEFT",
+        GamepadButtonType::DPadRight => "RIGHT",
+        button => return format!("{button:?}").to_uppercase(),
+    };
+    label.to_owned()
+}
+
+/// The corner of the frame the [`InputOverlayEncoder`] draws the key caps in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OverlayCorner {
+    /// The top left corner.
+    TopLeft,
+    /// The top right corner.
+    TopRight,
+    /// The bottom left corner.
+    #[default]
+    BottomLeft,
+    /// The bottom right corner.
+    BottomRight,
+}
+
+/// An encoder that draws the pressed inputs of the [`INPUT_KEY`] metadata as key caps into the
+/// frames before passing them to the inner encoder.
+///
+/// Frames without pressed inputs are passed on unchanged, other frames are converted to RGBA8.
+pub struct InputOverlayEncoder<E> {
+    inner: E,
+    corner: OverlayCorner,
+    scale: Option<u32>,
+}
+
+impl<E: Encoder> InputOverlayEncoder<E> {
+    /// Creates a new input overlay encoder that passes the frames to the inner encoder.
+    pub fn new(inner: E) -> Self {
+        Self {
+            inner,
+            corner: OverlayCorner::default(),
+            scale: None,
+        }
+    }
+
+    /// Sets the corner the key caps are drawn in. Defaults to [`OverlayCorner::BottomLeft`].
+    pub fn with_corner(mut self, corner: OverlayCorner) -> Self {
+        self.corner = corner;
+        self
+    }
+
+    /// Sets the size of a font pixel in frame pixels. By default, this depends on the height of
+    /// the frame.
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = Some(scale.max(1));
+        self
+    }
+}
+
+impl<E: Encoder> Encoder for InputOverlayEncoder<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let labels = match metadata.get(INPUT_KEY) {
+            Some(MetadataValue::String(labels)) if !labels.trim().is_empty() => labels,
+            _ => return self.inner.encode_with_metadata(image, metadata),
+        };
+
+        let (width, height) = (image.width(), image.height());
+        let mut canvas = Canvas {
+            rgba: to_rgba8(image)?.into_owned(),
+            width,
+            height,
+        };
+        let scale = self.scale.unwrap_or((height / 240).max(1));
+        canvas.draw_key_caps(labels.split_whitespace(), self.corner, scale);
+
+        let image = Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            canvas.rgba,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        let image = self.inner.capabilities().convert(&image)?;
+        self.inner.encode_with_metadata(&image, metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        Box::new(self.inner).finish();
+    }
+}
+
+/// An RGBA8 image to draw overlays into.
+pub(crate) struct Canvas {
+    pub(crate) rgba: Vec<u8>,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl Canvas {
+    fn draw_key_caps<'a>(
+        &mut self,
+        labels: impl Iterator<Item = &'a str>,
+        corner: OverlayCorner,
+        scale: u32,
+    ) {
+        let (padding, gap) = (2 * scale, 2 * scale);
+        let cap_height = GLYPH_HEIGHT * scale + 2 * padding;
+        let caps = labels
+            .map(|label| {
+                let chars = label.chars().count() as u32;
+                (
+                    label,
+                    chars * (GLYPH_WIDTH + 1) * scale - scale + 2 * padding,
+                )
+            })
+            .collect::<Vec<_>>();
+        let total_width = caps.iter().map(|(_, width)| width + gap).sum::<u32>() - gap;
+
+        let margin = 4 * scale;
+        let x = match corner {
+            OverlayCorner::TopLeft | OverlayCorner::BottomLeft => margin as i64,
+            OverlayCorner::TopRight | OverlayCorner::BottomRight => {
+                self.width as i64 - margin as i64 - total_width as i64
+            }
+        };
+        let y = match corner {
+            OverlayCorner::TopLeft | OverlayCorner::TopRight => margin as i64,
+            OverlayCorner::BottomLeft | OverlayCorner::BottomRight => {
+                self.height as i64 - margin as i64 - cap_height as i64
+            }
+        };
+
+        let mut cap_x = x;
+        for (label, cap_width) in caps {
+            self.blend_rect(cap_x, y, cap_width, cap_height, [0, 0, 0], 0.7);
+            let mut glyph_x = cap_x + padding as i64;
+            for c in label.chars() {
+                self.draw_glyph(glyph(c), glyph_x, y + padding as i64, scale);
+                glyph_x += ((GLYPH_WIDTH + 1) * scale) as i64;
+            }
+            cap_x += (cap_width + gap) as i64;
+        }
+    }
+
+    pub(crate) fn draw_glyph(&mut self, glyph: [u8; 5], x: i64, y: i64, scale: u32) {
+        for (row, bits) in glyph.into_iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                    self.blend_rect(
+                        x + (column * scale) as i64,
+                        y + (row as u32 * scale) as i64,
+                        scale,
+                        scale,
+                        [255, 255, 255],
+                        1.0,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Blends a rectangle into the canvas, clipped to its bounds.
+    pub(crate) fn blend_rect(
+        &mut self,
+        x: i64,
+        y: i64,
+        width: u32,
+        height: u32,
+        color: [u8; 3],
+        alpha: f32,
+    ) {
+        let clip = |start: i64, size: u32, max: u32| {
+            start.clamp(0, max as i64) as usize..(start + size as i64).clamp(0, max as i64) as usize
+        };
+        let columns = clip(x, width, self.width);
+        for y in clip(y, height, self.height) {
+            let row = y * self.width as usize;
+            for pixel in
+                self.rgba[(row + columns.start) * 4..(row + columns.end) * 4].chunks_exact_mut(4)
+            {
+                for (value, color) in pixel.iter_mut().zip(color) {
+                    *value = (*value as f32 * (1.0 - alpha) + color as f32 * alpha).round() as u8;
+                }
+            }
+        }
+    }
+}
+
+pub(crate) const GLYPH_WIDTH: u32 = 3;
+pub(crate) const GLYPH_HEIGHT: u32 = 5;
+
+/// Returns the rows of a 3x5 glyph, the most significant of the three bits is the left column.
+pub(crate) fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010],
+    }
+}
@@ -0,0 +1,210 @@
+//! Isolate the encoders of a capture from each other, so one failing encoder doesn't take the
+//! others down with it.
+//!
+//! By default, an encoder that fails keeps receiving frames and every failure is logged, e.g.
+//! once per frame for a frames encoder on a full disk. With an [`EncoderIsolation`] next to the
+//! [`Capture`], an encoder that fails for a number of frames in a row is finished and detached
+//! from the capture, while the other encoders keep going. An [`EncoderDetached`] event is sent for
+//! every detached encoder.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{encoder::frames::FramesEncoder, isolation::*, CaptureBundle};
+//! #
+//! commands.spawn((
+//!     Camera2dBundle::default(),
+//!     CaptureBundle::default(),
+//!     EncoderIsolation::new(10),
+//! ));
+//!
+//! fn on_detached(mut events: EventReader<EncoderDetached>) {
+//!     for event in events.read() {
+//!         warn!("Encoder {} stopped: {}", event.name, event.error);
+//!     }
+//! }
+//! ```
+
+use crate::{
+    encoder::{capabilities::EncoderCapabilities, Encoder, Error, Result},
+    metadata::FrameMetadata,
+    AttachedEncoder, BoxedEncoder, Capture, CaptureState, CaptureStats, EncoderId,
+};
+use bevy::prelude::*;
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
+
+/// Detaches encoders of a capture that fail repeatedly, see the [module docs](self). This is
+/// optional and can be attached next to the [`Capture`]. It is applied when the capture starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct EncoderIsolation {
+    max_errors: u32,
+}
+
+impl Default for EncoderIsolation {
+    fn default() -> Self {
+        Self::new(5)
+    }
+}
+
+impl EncoderIsolation {
+    /// Creates an isolation that detaches an encoder after it failed for the given number of
+    /// frames in a row. Defaults to `5`.
+    pub fn new(max_errors: u32) -> Self {
+        Self {
+            max_errors: max_errors.max(1),
+        }
+    }
+
+    /// Returns the number of frames in a row an encoder may fail before it is detached.
+    pub fn max_errors(&self) -> u32 {
+        self.max_errors
+    }
+
+    /// Wraps the encoders, so they are detached once they failed too often.
+    pub(crate) fn wrap(&self, encoders: &mut Vec<AttachedEncoder>, stats: &Arc<CaptureStats>) {
+        *encoders = mem::take(encoders)
+            .into_iter()
+            .map(|encoder| {
+                encoder.map(|id, inner| {
+                    Box::new(IsolatedEncoder {
+                        inner: Some(inner),
+                        id,
+                        max_errors: self.max_errors,
+                        errors: 0,
+                        detached: Arc::clone(&stats.detached),
+                    })
+                })
+            })
+            .collect();
+    }
+}
+
+/// Sent when an encoder of a capture was detached because it failed too often.
+#[derive(Debug, Clone, PartialEq, Eq, Event)]
+pub struct EncoderDetached {
+    /// The entity of the [`Capture`].
+    pub capture: Entity,
+    /// The id of the encoder, see [`CaptureHandle::encoders`](crate::CaptureHandle::encoders).
+    pub encoder: EncoderId,
+    /// The [name](Encoder::name) of the encoder.
+    pub name: String,
+    /// The last error of the encoder.
+    pub error: String,
+}
+
+/// An encoder that was detached, reported from the render world.
+pub(crate) struct Detached {
+    encoder: EncoderId,
+    name: String,
+    error: String,
+}
+
+/// An encoder that stops passing frames to the inner encoder once it failed too often.
+struct IsolatedEncoder {
+    inner: Option<BoxedEncoder>,
+    id: EncoderId,
+    max_errors: u32,
+    errors: u32,
+    detached: Arc<Mutex<Vec<Detached>>>,
+}
+
+impl IsolatedEncoder {
+    fn check(&mut self, result: Result<()>) -> Result<()> {
+        let err = match result {
+            Ok(()) => {
+                self.errors = 0;
+                return Ok(());
+            }
+            Err(err) => err,
+        };
+        self.errors += 1;
+        if self.errors < self.max_errors {
+            return Err(err);
+        }
+
+        // The encoder still finishes, e.g. so the frames written so far are complete.
+        let name = self.name().to_string();
+        if let Some(inner) = self.inner.take() {
+            inner.finish();
+        }
+        self.detached.lock().unwrap().push(Detached {
+            encoder: self.id,
+            name: name.clone(),
+            error: err.to_string(),
+        });
+        Err(Error::Other(format!(
+            "detached the {} encoder {} after {} errors in a row: {}",
+            name, self.id, self.errors, err
+        )))
+    }
+}
+
+impl Encoder for IsolatedEncoder {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.encode_with_metadata(image, &FrameMetadata::default())
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        let Some(inner) = &mut self.inner else {
+            return Ok(());
+        };
+        let result = inner.encode_with_metadata(image, metadata);
+        self.check(result)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.inner {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        match &self.inner {
+            Some(inner) => inner.capabilities(),
+            None => EncoderCapabilities::default(),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match &self.inner {
+            Some(inner) => inner.name(),
+            None => std::any::type_name::<Self>(),
+        }
+    }
+
+    fn describe_output(&self) -> String {
+        match &self.inner {
+            Some(inner) => inner.describe_output(),
+            None => String::new(),
+        }
+    }
+
+    fn finish(self: Box<Self>) {
+        if let Some(inner) = self.inner {
+            inner.finish();
+        }
+    }
+}
+
+pub(crate) fn send_detached_events(
+    captures: Query<(Entity, &Capture)>,
+    mut events: EventWriter<EncoderDetached>,
+) {
+    for (entity, capture) in &captures {
+        let CaptureState::Capturing { stats, .. } = &capture.state else {
+            continue;
+        };
+        for detached in stats.detached.lock().unwrap().drain(..) {
+            events.send(EncoderDetached {
+                capture: entity,
+                encoder: detached.encoder,
+                name: detached.name,
+                error: detached.error,
+            });
+        }
+    }
+}
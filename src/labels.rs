@@ -0,0 +1,212 @@
+//! Draw the names of entities at their screen positions into the captured frames, e.g. for
+//! annotated debugging footage of AI or physics behavior. The game window is not affected.
+//!
+//! Mark entities as [`Labeled`] and attach [`CaptureLabels`] next to the
+//! [`Capture`](crate::Capture) of every capture that should show the labels. The
+//! [`EntityLabelPlugin`] projects the marked entities to the screen of the camera of the capture
+//! every frame, and the labels are drawn into the frames before they are passed to the encoders.
+//! A label shows the [`Name`] of the entity, or its index if it has no name, unless a text is set.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{labels::*, CaptureBundle};
+//! #
+//! app.add_plugins(EntityLabelPlugin);
+//!
+//! fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+//!     commands.spawn((
+//!         Camera2dBundle::default().target_headless(1920, 1080, &mut images),
+//!         CaptureBundle::default(),
+//!         CaptureLabels::default(),
+//!     ));
+//!     commands.spawn((
+//!         SpriteBundle::default(),
+//!         Name::new("Enemy"),
+//!         Labeled::default().with_offset(Vec3::Y * 32.0),
+//!     ));
+//! }
+//! ```
+
+use crate::{
+    input_overlay::{glyph, Canvas, GLYPH_HEIGHT, GLYPH_WIDTH},
+    Capture, CaptureSource,
+};
+use bevy::{
+    prelude::*,
+    render::{camera::CameraUpdateSystem, render_resource::TextureFormat},
+    transform::TransformSystem,
+};
+use std::mem;
+
+/// A Bevy plugin that tracks the screen positions of [`Labeled`] entities in every capture with
+/// [`CaptureLabels`].
+pub struct EntityLabelPlugin;
+
+impl Plugin for EntityLabelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            track_labeled_entities
+                .after(TransformSystem::TransformPropagate)
+                .after(CameraUpdateSystem),
+        );
+    }
+}
+
+/// Marks an entity that is labeled in all captures with [`CaptureLabels`].
+#[derive(Debug, Default, Clone, PartialEq, Component)]
+pub struct Labeled {
+    text: Option<String>,
+    offset: Vec3,
+}
+
+impl Labeled {
+    /// Labels the entity with the given text instead of its name.
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: Some(text.into()),
+            ..default()
+        }
+    }
+
+    /// Moves the label by the given offset in world space, e.g. above the head of a character.
+    pub fn with_offset(mut self, offset: Vec3) -> Self {
+        self.offset = offset;
+        self
+    }
+}
+
+/// A label drawn into the frames of a capture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityLabel {
+    entity: Entity,
+    text: String,
+    position: Vec2,
+}
+
+impl EntityLabel {
+    /// Returns the labeled entity.
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    /// Returns the text of the label.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the position of the entity in the viewport, the label is centered on it.
+    pub fn position(&self) -> Vec2 {
+        self.position
+    }
+}
+
+/// Draws the labels of [`Labeled`] entities into the frames of a capture. This is optional and can
+/// be attached next to the [`Capture`].
+#[derive(Debug, Default, Clone, PartialEq, Component)]
+pub struct CaptureLabels {
+    scale: Option<u32>,
+    tracked: Vec<EntityLabel>,
+}
+
+impl CaptureLabels {
+    /// Sets the size of a font pixel in frame pixels. By default, this depends on the height of
+    /// the frame.
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = Some(scale.max(1));
+        self
+    }
+
+    /// Returns the labels of the [`Labeled`] entities in the last frame.
+    pub fn labels(&self) -> &[EntityLabel] {
+        &self.tracked
+    }
+}
+
+fn track_labeled_entities(
+    mut captures: Query<(Entity, &Capture, &CaptureSource, &mut CaptureLabels)>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    labeled: Query<(Entity, &Labeled, &GlobalTransform, Option<&Name>)>,
+) {
+    for (entity, capture, source, mut labels) in &mut captures {
+        if !capture.is_capturing() {
+            continue;
+        }
+        let camera_entity = match source {
+            CaptureSource::ThisCamera => entity,
+            CaptureSource::Camera(entity) => *entity,
+        };
+        let Ok((camera, camera_transform)) = cameras.get(camera_entity) else {
+            continue;
+        };
+
+        let tracked = labeled
+            .iter()
+            .filter_map(|(entity, labeled, transform, name)| {
+                let world = transform.translation() + labeled.offset;
+                let position = camera.world_to_viewport(camera_transform, world)?;
+                let text = match (&labeled.text, name) {
+                    (Some(text), _) => text.clone(),
+                    (None, Some(name)) => name.as_str().to_owned(),
+                    (None, None) => format!("#{}", entity.index()),
+                };
+                Some(EntityLabel {
+                    entity,
+                    text,
+                    position,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Only changed labels are written, so change detection stays meaningful.
+        if labels.tracked != tracked {
+            labels.tracked = tracked;
+        }
+    }
+}
+
+/// Draws the labels into the image in place. Only images with 8 bit RGBA or BGRA pixels are
+/// supported, the labels are white on black so the channel order doesn't matter.
+pub(crate) fn draw(image: &mut Image, labels: &CaptureLabels) {
+    if !matches!(
+        image.texture_descriptor.format,
+        TextureFormat::Rgba8Unorm
+            | TextureFormat::Rgba8UnormSrgb
+            | TextureFormat::Bgra8Unorm
+            | TextureFormat::Bgra8UnormSrgb
+    ) {
+        warn_once!(
+            "Entity labels are not supported for the format {:?}",
+            image.texture_descriptor.format
+        );
+        return;
+    }
+
+    let (width, height) = (image.width(), image.height());
+    let mut canvas = Canvas {
+        rgba: mem::take(&mut image.data),
+        width,
+        height,
+    };
+    let scale = labels.scale.unwrap_or((height / 240).max(1));
+    let padding = scale;
+    for label in &labels.tracked {
+        let chars = label.text.chars().count() as u32;
+        if chars == 0 {
+            continue;
+        }
+        let box_width = chars * (GLYPH_WIDTH + 1) * scale - scale + 2 * padding;
+        let box_height = GLYPH_HEIGHT * scale + 2 * padding;
+        let x = label.position.x.round() as i64 - box_width as i64 / 2;
+        let y = label.position.y.round() as i64 - box_height as i64 / 2;
+
+        canvas.blend_rect(x, y, box_width, box_height, [0, 0, 0], 0.7);
+        let mut glyph_x = x + padding as i64;
+        for c in label.text.chars() {
+            canvas.draw_glyph(glyph(c), glyph_x, y + padding as i64, scale);
+            glyph_x += ((GLYPH_WIDTH + 1) * scale) as i64;
+        }
+    }
+    image.data = canvas.rgba;
+}
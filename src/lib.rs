@@ -4,20 +4,95 @@
 #![doc = include_str!("../README.md")]
 
 mod render_world;
+mod worker;
 
+pub mod adaptive_quality;
+pub mod auto_pause;
+#[cfg(feature = "image")]
+pub mod benchmark;
+#[cfg(feature = "image")]
+pub mod burst;
+pub mod channel;
+pub mod clip;
+#[cfg(feature = "image")]
+pub mod crash;
+pub mod cubemap;
+pub mod cursor;
+pub mod debug;
+#[cfg(feature = "debug_view")]
+pub mod debug_view;
+pub mod defaults;
+pub mod distributed;
 pub mod encoder;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "gizmos")]
+pub mod gizmos;
+#[cfg(feature = "golden")]
+pub mod golden;
+pub mod gpu_timing;
+pub mod input_overlay;
+pub mod isolation;
+pub mod labels;
+pub mod limits;
+pub mod live_settings;
+pub mod memory;
+pub mod metadata;
+pub mod multi_pass;
+#[cfg(feature = "obs")]
+pub mod obs;
+#[cfg(feature = "image")]
+pub mod photo_mode;
+pub mod preview;
+pub mod privacy;
+#[cfg(feature = "probe_grid")]
+pub mod probe_grid;
+pub mod range;
+#[cfg(feature = "render_farm")]
+pub mod render_farm;
+pub mod scene_change;
+pub mod screen;
+#[cfg(feature = "image")]
+pub mod screenshot_matrix;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+#[cfg(feature = "sidecar")]
+pub mod sidecar;
+#[cfg(feature = "state_snapshot")]
+pub mod state_snapshot;
+pub mod testing;
+pub mod time_remap;
+#[cfg(feature = "ui")]
+pub mod ui;
+pub mod verify;
 
 use bevy::{
+    app::{AppLabel, InternedAppLabel},
     prelude::*,
     render::{
         camera::RenderTarget,
+        graph::CameraDriverLabel,
         render_asset::RenderAssetUsages,
+        render_graph::{InternedRenderLabel, RenderLabel},
         render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
         texture::BevyDefault,
+        RenderApp,
     },
-    utils::all_tuples,
+    utils::{all_tuples, Duration, Instant},
+};
+use std::{
+    fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
 };
-use std::sync::Mutex;
 
 #[doc(inline)]
 pub use encoder::Encoder;
@@ -25,14 +100,174 @@ pub use encoder::Encoder;
 type BoxedEncoder = Box<dyn Encoder + Send + Sync + 'static>;
 
 /// A Bevy plugin for capturing frames.
-pub struct CapturePlugin;
+///
+/// The defaults work for most apps, the builder methods configure how frames are read back and
+/// encoded across all captures:
+///
+/// ```ignore
+/// app.add_plugins(
+///     CapturePlugin::default()
+///         .with_readback(ReadbackMode::Async)
+///         .with_worker_settings(CaptureWorkerSettings::default().with_threads(2))
+///         .with_clock(CaptureClock::Virtual),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct CapturePlugin {
+    readback: ReadbackMode,
+    worker_settings: Option<CaptureWorkerSettings>,
+    memory_budget: Option<memory::CaptureMemoryBudget>,
+    clock: Option<CaptureClock>,
+    graph_node: InternedRenderLabel,
+    render_app: InternedAppLabel,
+}
+
+impl Default for CapturePlugin {
+    fn default() -> Self {
+        Self {
+            readback: ReadbackMode::default(),
+            worker_settings: None,
+            memory_budget: None,
+            clock: None,
+            graph_node: CameraDriverLabel.intern(),
+            render_app: RenderApp.intern(),
+        }
+    }
+}
+
+impl CapturePlugin {
+    /// Sets how frames are read back from the gpu. Defaults to [`ReadbackMode::Sync`].
+    pub fn with_readback(mut self, readback: ReadbackMode) -> Self {
+        self.readback = readback;
+        self
+    }
+
+    /// Runs the encoders of captures without a [`CaptureWorkerSettings`] component on worker
+    /// threads with these settings. By default, they run on the render thread.
+    pub fn with_worker_settings(mut self, worker_settings: CaptureWorkerSettings) -> Self {
+        self.worker_settings = Some(worker_settings);
+        self
+    }
+
+    /// Inserts the memory budget for queued frames, unless the app already has one.
+    pub fn with_memory_budget(mut self, memory_budget: memory::CaptureMemoryBudget) -> Self {
+        self.memory_budget = Some(memory_budget);
+        self
+    }
+
+    /// Attaches the elapsed time of the clock to every frame, see
+    /// [`TIMESTAMP_KEY`](metadata::TIMESTAMP_KEY). By default, frames have no timestamp.
+    pub fn with_clock(mut self, clock: CaptureClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Copies the frames after the given node of the render graph, e.g. a custom node that
+    /// renders to the target after the cameras. Defaults to the camera driver.
+    ///
+    /// The node is connected once all plugins are built. If the node doesn't exist then, the
+    /// frames are copied after the camera driver and a warning is logged.
+    pub fn with_graph_node(mut self, label: impl RenderLabel) -> Self {
+        self.graph_node = label.intern();
+        self
+    }
+
+    /// Captures frames of the given render sub-app instead of the [`RenderApp`], e.g. in apps with
+    /// a custom render sub-app setup. The sub-app must have the schedules and the render graph of
+    /// the `RenderPlugin`.
+    ///
+    /// If the sub-app doesn't exist, e.g. in dedicated server builds, capturing is disabled and a
+    /// warning is logged. Captures can still be started and stopped, but never receive frames.
+    pub fn with_render_app(mut self, label: impl AppLabel) -> Self {
+        self.render_app = label.intern();
+        self
+    }
+
+    /// Returns the readback mode.
+    pub fn readback(&self) -> ReadbackMode {
+        self.readback
+    }
+
+    /// Returns the default worker settings, if set.
+    pub fn worker_settings(&self) -> Option<&CaptureWorkerSettings> {
+        self.worker_settings.as_ref()
+    }
+
+    /// Returns the clock frames are timestamped with, if set.
+    pub fn clock(&self) -> Option<CaptureClock> {
+        self.clock
+    }
+}
 
 impl Plugin for CapturePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(render_world::CaptureRenderWorldPlugin);
+        app.add_plugins(render_world::CaptureRenderWorldPlugin {
+            readback: self.readback,
+            worker_settings: self.worker_settings.clone(),
+            graph_node: self.graph_node,
+            render_app: self.render_app,
+        })
+        .init_resource::<metadata::FrameMetadata>()
+        .init_resource::<debug::CaptureRegistry>()
+        .add_event::<isolation::EncoderDetached>()
+        .add_event::<limits::CaptureLimitExceeded>()
+        .add_systems(First, metadata::clear_metadata)
+        .add_systems(
+            PostUpdate,
+            (
+                defaults::start_default_captures,
+                range::update_capture_ranges.after(defaults::start_default_captures),
+                limits::enforce_capture_limits.after(range::update_capture_ranges),
+                debug::update_capture_registry.after(range::update_capture_ranges),
+            ),
+        )
+        .add_systems(
+            PreUpdate,
+            (
+                preview::update_preview.run_if(resource_exists::<preview::CapturePreview>),
+                isolation::send_detached_events,
+            ),
+        );
+
+        if let Some(memory_budget) = &self.memory_budget {
+            if !app
+                .world()
+                .contains_resource::<memory::CaptureMemoryBudget>()
+            {
+                app.insert_resource(memory_budget.clone());
+            }
+        }
+        #[cfg(feature = "serde")]
+        app.init_resource::<encoder::registry::EncoderRegistry>();
+        if let Some(clock) = self.clock {
+            app.insert_resource(metadata::TimestampClock(clock))
+                .add_systems(Last, metadata::insert_timestamp);
+        }
     }
 }
 
+/// How frames are read back from the gpu, see [`CapturePlugin::with_readback`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReadbackMode {
+    /// The render thread waits for the copy of the frame and encodes it in the same frame.
+    #[default]
+    Sync,
+    /// The frame is copied into a second staging buffer and encoded in the next frame, so the
+    /// render thread doesn't wait for the gpu. The last frame is encoded when the capture stops.
+    /// This doubles the memory of the staging buffers.
+    Async,
+}
+
+/// A clock frames can be timestamped with, see [`CapturePlugin::with_clock`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CaptureClock {
+    /// The wall-clock time since startup, `Time<Real>`.
+    #[default]
+    Real,
+    /// The game time, `Time<Virtual>`, which can be paused and scaled.
+    Virtual,
+}
+
 /// Bundle for the capture plugin. This is usually attached to a camera.
 #[derive(Default, Bundle)]
 pub struct CaptureBundle {
@@ -50,13 +285,110 @@ pub struct Capture {
 
 impl Capture {
     /// Starts capturing frames with the given encoders.
-    pub fn start(&mut self, encoders: impl IntoEncoders) {
+    ///
+    /// The returned handle can be used to wait until the encoders have finished after the capture
+    /// was stopped.
+    pub fn start(&mut self, encoders: impl IntoEncoders) -> CaptureHandle {
+        let handle = CaptureHandle::default();
+        self.start_with_handle(encoders.into_encoders(), handle.clone());
+        handle
+    }
+
+    /// Starts capturing frames with the encoders of the
+    /// [`DefaultCaptureSettings`](defaults::DefaultCaptureSettings) resource.
+    ///
+    /// The encoders are created before the next frame is rendered. If the resource is missing or
+    /// the encoders can't be created, the capture is stopped again and the error is logged.
+    pub fn start_default(&mut self) -> CaptureHandle {
+        let handle = CaptureHandle::default();
+        self.state = CaptureState::StartDefault {
+            handle: handle.clone(),
+        };
+        handle
+    }
+
+    fn start_with_handle(&mut self, encoders: Vec<BoxedEncoder>, handle: CaptureHandle) {
+        let encoders = encoders
+            .into_iter()
+            .map(|encoder| AttachedEncoder::new(encoder, &handle))
+            .collect::<Vec<_>>();
         self.state = CaptureState::Capturing {
-            encoders: Mutex::new(Some(Encoders(encoders.into_encoders()))),
+            encoder_infos: encoders.iter().map(AttachedEncoder::info).collect(),
+            encoders: Mutex::new(Some(Encoders {
+                encoders,
+                handle: handle.clone(),
+            })),
+            handle: handle.clone(),
             paused: false,
+            started_at: Instant::now(),
+            stats: Arc::default(),
+            replay: None,
         };
     }
 
+    /// Starts keeping the frames of the given length in a ring buffer in memory instead of
+    /// encoding them, see [`dump_replay`](Self::dump_replay) and the
+    /// [`replay`](encoder::replay) module. More encoders can be added with
+    /// [`add_encoder`](Self::add_encoder), e.g. to record to disk at the same time.
+    pub fn start_replay(&mut self, length: encoder::replay::ReplayLength) -> CaptureHandle {
+        let encoder = encoder::replay::ReplayBufferEncoder::new_with_length(length);
+        let buffer = encoder.buffer();
+        let handle = self.start(encoder);
+        if let CaptureState::Capturing { replay, .. } = &mut self.state {
+            *replay = Some(buffer);
+        }
+        handle
+    }
+
+    /// Encodes the frames in the replay buffer of [`start_replay`](Self::start_replay) with the
+    /// given encoders on a background thread, e.g. for a "clip that" button. The capture keeps
+    /// buffering. Returns the handle of the encoders, or `None` if the capture is not a replay
+    /// capture, see [`ReplayBuffer::dump`](encoder::replay::ReplayBuffer::dump).
+    pub fn dump_replay(&self, encoders: impl IntoEncoders) -> Option<CaptureHandle> {
+        self.replay_buffer().map(|buffer| buffer.dump(encoders))
+    }
+
+    /// Returns the replay buffer of the capture, if it was started with
+    /// [`start_replay`](Self::start_replay).
+    pub fn replay_buffer(&self) -> Option<encoder::replay::ReplayBuffer> {
+        match &self.state {
+            CaptureState::Capturing { replay, .. } => replay.clone(),
+            _ => None,
+        }
+    }
+
+    /// Adds an encoder to the active capture, which gets the frames from the next captured frame
+    /// on. Returns the id of the encoder, or `None` if the capture is not capturing or still waiting
+    /// for the encoders of [`start_default`](Self::start_default).
+    ///
+    /// Encoders added to a running capture are run on the render thread, even if the capture uses
+    /// [worker threads](CaptureWorkerSettings).
+    pub fn add_encoder(
+        &mut self,
+        encoder: impl Encoder + Send + Sync + 'static,
+    ) -> Option<EncoderId> {
+        let CaptureState::Capturing {
+            encoders,
+            encoder_infos,
+            handle,
+            stats,
+            ..
+        } = &mut self.state
+        else {
+            return None;
+        };
+
+        let encoder = AttachedEncoder::new(Box::new(encoder), handle);
+        let id = encoder.id;
+        encoder_infos.push(encoder.info());
+        match encoders.get_mut().unwrap() {
+            // Not extracted yet, so the encoder can be started with the others.
+            Some(encoders) => encoders.encoders.push(encoder),
+            None => stats.added.0.lock().unwrap().push(encoder),
+        }
+        Some(id)
+    }
+
     /// Pauses the capture.
     pub fn pause(&mut self) {
         if let CaptureState::Capturing { paused, .. } = &mut self.state {
@@ -71,40 +403,347 @@ impl Capture {
         }
     }
 
+    /// Flushes the encoders without stopping the capture, so the frames encoded so far survive a
+    /// crash, e.g. at checkpoints of a long recording. The encoders are flushed before the next
+    /// frame is encoded, after the frames that were already passed to them, see
+    /// [`Encoder::flush`]. Does nothing if the capture is not capturing.
+    pub fn flush(&mut self) {
+        if let CaptureState::Capturing { stats, .. } = &self.state {
+            stats.flush_requested.store(true, Ordering::Relaxed);
+        }
+    }
+
     /// Stops the capture. This will drop the active encoders, which will call [`finish`](Encoder::finish)
     /// on them.
     pub fn stop(&mut self) {
-        self.state = CaptureState::Idle;
+        if let CaptureState::StartDefault { handle } = mem::take(&mut self.state) {
+            // No encoders were created, so there is nothing to wait for.
+            handle.set_finished();
+        }
     }
 
-    /// Returns `true` if the capture is currently capturing frames.
+    /// Returns `true` if the capture is currently capturing frames, or about to with
+    /// [`start_default`](Self::start_default).
     pub fn is_capturing(&self) -> bool {
-        matches!(&self.state, CaptureState::Capturing { .. })
+        matches!(
+            &self.state,
+            CaptureState::Capturing { .. } | CaptureState::StartDefault { .. }
+        )
     }
 
     /// Returns `true` if the capture is currently paused.
     pub fn is_paused(&self) -> bool {
         matches!(&self.state, CaptureState::Capturing { paused: true, .. })
     }
+
+    /// Returns the number of frames captured so far, or `0` if the capture is not capturing.
+    pub fn frames_captured(&self) -> u64 {
+        match &self.state {
+            CaptureState::Idle | CaptureState::StartDefault { .. } => 0,
+            CaptureState::Capturing { stats, .. } => stats.frames_captured.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the handle of the active capture, or `None` if the capture is not capturing.
+    pub fn handle(&self) -> Option<CaptureHandle> {
+        match &self.state {
+            CaptureState::Idle => None,
+            CaptureState::StartDefault { handle } | CaptureState::Capturing { handle, .. } => {
+                Some(handle.clone())
+            }
+        }
+    }
+
+    /// Returns `true` if at least one frame has been passed to the encoders.
+    pub fn has_captured_frame(&self) -> bool {
+        self.frames_captured() > 0
+    }
+
+    /// Returns the number of encoders of the active capture, or `0` if the capture is not capturing.
+    pub fn encoder_count(&self) -> usize {
+        self.encoders().len()
+    }
+
+    /// Returns the ids, names, outputs and progress of the encoders of the active capture, or an
+    /// empty vector if the capture is not capturing. The names and outputs are those of when the
+    /// encoders were attached.
+    pub fn encoders(&self) -> Vec<debug::EncoderInfo> {
+        match &self.state {
+            CaptureState::Idle | CaptureState::StartDefault { .. } => Vec::new(),
+            CaptureState::Capturing { encoder_infos, .. } => encoder_infos
+                .iter()
+                .map(|(info, stats)| debug::EncoderInfo {
+                    frames_encoded: stats.frames_encoded.load(Ordering::Relaxed),
+                    errors: stats.errors.load(Ordering::Relaxed),
+                    ..info.clone()
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the instant the capture was started, or `None` if the capture is not capturing.
+    pub fn started_at(&self) -> Option<Instant> {
+        match &self.state {
+            CaptureState::Idle | CaptureState::StartDefault { .. } => None,
+            CaptureState::Capturing { started_at, .. } => Some(*started_at),
+        }
+    }
+
+    /// Returns the wall-clock time since the capture was started, including paused periods,
+    /// or `None` if the capture is not capturing.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.started_at().map(|started_at| started_at.elapsed())
+    }
 }
 
 #[derive(Default)]
 enum CaptureState {
     #[default]
     Idle,
+    /// Waiting for the encoders of the default settings.
+    StartDefault { handle: CaptureHandle },
     Capturing {
         encoders: Mutex<Option<Encoders>>,
+        encoder_infos: Vec<(debug::EncoderInfo, Arc<EncoderStats>)>,
+        handle: CaptureHandle,
         paused: bool,
+        started_at: Instant,
+        stats: Arc<CaptureStats>,
+        /// The buffer of [`Capture::start_replay`].
+        replay: Option<encoder::replay::ReplayBuffer>,
     },
 }
 
-struct Encoders(Vec<BoxedEncoder>);
+/// Statistics shared between the main world and the render world.
+#[derive(Default)]
+struct CaptureStats {
+    frames_captured: AtomicU64,
+    /// Set by [`Capture::flush`] and reset once the encoders were flushed.
+    flush_requested: AtomicBool,
+    /// The encoders detached by an [`EncoderIsolation`](isolation::EncoderIsolation).
+    detached: Arc<Mutex<Vec<isolation::Detached>>>,
+    /// The encoders added by [`Capture::add_encoder`] after the encoders were extracted.
+    added: AddedEncoders,
+}
+
+/// Encoders waiting to be passed to the render world. Encoders that never were, e.g. because the
+/// capture was stopped in the same frame, are finished when dropped.
+#[derive(Default)]
+struct AddedEncoders(Mutex<Vec<AttachedEncoder>>);
+
+impl Drop for AddedEncoders {
+    fn drop(&mut self) {
+        for encoder in self.0.get_mut().unwrap().drain(..) {
+            encoder.finish();
+        }
+    }
+}
+
+struct Encoders {
+    encoders: Vec<AttachedEncoder>,
+    handle: CaptureHandle,
+}
+
+impl Encoders {
+    fn new(encoders: Vec<BoxedEncoder>, handle: CaptureHandle) -> Self {
+        Self {
+            encoders: encoders
+                .into_iter()
+                .map(|encoder| AttachedEncoder::new(encoder, &handle))
+                .collect(),
+            handle,
+        }
+    }
+}
 
 impl Drop for Encoders {
     fn drop(&mut self) {
-        for encoder in self.0.drain(..) {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("capture_finish").entered();
+
+        for encoder in self.encoders.drain(..) {
             encoder.finish();
         }
+        self.handle.set_finished();
+    }
+}
+
+/// Identifies an encoder of a capture, see [`CaptureHandle::encoders`] and
+/// [`Capture::add_encoder`]. The ids are unique across all captures of the app and are used in
+/// logs, errors, events and the [`CaptureRegistry`](debug::CaptureRegistry).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EncoderId(u64);
+
+impl EncoderId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Returns the id as a number.
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for EncoderId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// The progress of an encoder, shared between the main world and the render world.
+#[derive(Default)]
+struct EncoderStats {
+    frames_encoded: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// An encoder of a capture with its id.
+struct AttachedEncoder {
+    id: EncoderId,
+    encoder: BoxedEncoder,
+    stats: Arc<EncoderStats>,
+}
+
+impl AttachedEncoder {
+    /// Assigns an id to the encoder and adds it to the handle.
+    fn new(encoder: BoxedEncoder, handle: &CaptureHandle) -> Self {
+        let id = EncoderId::next();
+        handle.0.encoders.lock().unwrap().push(id);
+        Self {
+            id,
+            encoder: encoder::capabilities::CapabilityAdapter::wrap(encoder),
+            stats: Arc::default(),
+        }
+    }
+
+    fn info(&self) -> (debug::EncoderInfo, Arc<EncoderStats>) {
+        let info = debug::EncoderInfo {
+            id: self.id,
+            name: self.encoder.name().to_string(),
+            output: self.encoder.describe_output(),
+            frames_encoded: 0,
+            errors: 0,
+        };
+        (info, Arc::clone(&self.stats))
+    }
+
+    /// Replaces the encoder, e.g. with a wrapper around it.
+    fn map(self, f: impl FnOnce(EncoderId, BoxedEncoder) -> BoxedEncoder) -> Self {
+        Self {
+            encoder: f(self.id, self.encoder),
+            ..self
+        }
+    }
+
+    fn encode(
+        &mut self,
+        image: &Image,
+        metadata: &metadata::FrameMetadata,
+    ) -> Result<(), EncodeError> {
+        let result = self.encoder.encode_with_metadata(image, metadata);
+        let counter = match result {
+            Ok(()) => &self.stats.frames_encoded,
+            Err(_) => &self.stats.errors,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result.map_err(|err| self.error(err))
+    }
+
+    fn flush(&mut self) -> Result<(), EncodeError> {
+        self.encoder.flush().map_err(|err| self.error(err))
+    }
+
+    fn error(&self, error: encoder::Error) -> EncodeError {
+        EncodeError {
+            encoder: Some((self.id, self.encoder.name().to_string())),
+            error,
+        }
+    }
+
+    /// Finishes the encoder and logs where it wrote to.
+    fn finish(self) {
+        let name = self.encoder.name().to_string();
+        let output = self.encoder.describe_output();
+        self.encoder.finish();
+        match output.as_str() {
+            "" => debug!("Finished the {} encoder {}", name, self.id),
+            output => debug!(
+                "Finished the {} encoder {}, written to {}",
+                name, self.id, output
+            ),
+        }
+    }
+}
+
+/// An error of a capture, with the id and name of the encoder that failed, if any.
+struct EncodeError {
+    encoder: Option<(EncoderId, String)>,
+    error: encoder::Error,
+}
+
+impl From<encoder::Error> for EncodeError {
+    fn from(error: encoder::Error) -> Self {
+        Self {
+            encoder: None,
+            error,
+        }
+    }
+}
+
+/// A handle to a capture, returned by [`Capture::start`].
+///
+/// The handle is finished once the capture was stopped and all encoders have finished.
+/// It can be polled with [`is_finished`](Self::is_finished) or awaited, e.g. in a task on one of
+/// Bevy's task pools.
+#[derive(Default, Clone)]
+pub struct CaptureHandle(Arc<CaptureHandleInner>);
+
+#[derive(Default)]
+struct CaptureHandleInner {
+    finished: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+    encoders: Mutex<Vec<EncoderId>>,
+}
+
+impl CaptureHandle {
+    /// Returns the ids of the encoders of the capture, in the order they were passed to
+    /// [`Capture::start`], followed by the encoders added with [`Capture::add_encoder`]. Empty
+    /// until the encoders of [`Capture::start_default`] were created.
+    pub fn encoders(&self) -> Vec<EncoderId> {
+        self.0.encoders.lock().unwrap().clone()
+    }
+
+    /// Returns `true` if all encoders of the capture have finished.
+    pub fn is_finished(&self) -> bool {
+        self.0.finished.load(Ordering::Acquire)
+    }
+
+    fn set_finished(&self) {
+        self.0.finished.store(true, Ordering::Release);
+        for waker in self.0.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for CaptureHandle {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.is_finished() {
+            return Poll::Ready(());
+        }
+
+        self.0.wakers.lock().unwrap().push(cx.waker().clone());
+
+        // Check again, the capture might have finished while registering the waker.
+        if self.is_finished() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
     }
 }
 
@@ -119,6 +758,241 @@ pub enum CaptureSource {
     Camera(Entity),
 }
 
+/// Configures the staging buffers the frames are copied into before they are read back. This is
+/// optional and can be attached next to the [`Capture`].
+///
+/// Frames that don't fit into a single buffer, e.g. 8K+ captures exceeding the `max_buffer_size`
+/// of the device, are split into multiple copies of consecutive rows and reassembled on the CPU.
+/// If a single row doesn't fit either, the rows are split into columns as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub struct CaptureBufferSettings {
+    row_alignment: u32,
+    max_buffer_size: Option<u64>,
+    max_rows_per_copy: Option<u32>,
+}
+
+impl Default for CaptureBufferSettings {
+    fn default() -> Self {
+        Self {
+            row_alignment: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT,
+            max_buffer_size: None,
+            max_rows_per_copy: None,
+        }
+    }
+}
+
+impl CaptureBufferSettings {
+    /// Sets the alignment of the rows in the staging buffers in bytes. This is rounded up to a
+    /// multiple of `wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`, which is the default.
+    pub fn with_row_alignment(mut self, row_alignment: u32) -> Self {
+        self.row_alignment = row_alignment
+            .max(1)
+            .next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        self
+    }
+
+    /// Sets the maximum size of a staging buffer in bytes. The `max_buffer_size` limit of the
+    /// device is always respected.
+    pub fn with_max_buffer_size(mut self, max_buffer_size: u64) -> Self {
+        self.max_buffer_size = Some(max_buffer_size);
+        self
+    }
+
+    /// Sets the maximum number of rows copied into a single staging buffer, e.g. to read back
+    /// very tall images in chunks.
+    pub fn with_max_rows_per_copy(mut self, max_rows_per_copy: u32) -> Self {
+        self.max_rows_per_copy = Some(max_rows_per_copy.max(1));
+        self
+    }
+
+    /// Returns the alignment of the rows in the staging buffers in bytes.
+    pub fn row_alignment(&self) -> u32 {
+        self.row_alignment
+    }
+
+    /// Returns the maximum size of a staging buffer in bytes, if set.
+    pub fn max_buffer_size(&self) -> Option<u64> {
+        self.max_buffer_size
+    }
+
+    /// Returns the maximum number of rows copied into a single staging buffer, if set.
+    pub fn max_rows_per_copy(&self) -> Option<u32> {
+        self.max_rows_per_copy
+    }
+}
+
+/// Controls what a capture logs, e.g. so long automated renders don't flood the logs. This is
+/// optional and can be attached next to the [`Capture`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component)]
+pub struct CaptureLogPolicy {
+    verbosity: CaptureVerbosity,
+    aggregate_errors: Option<Duration>,
+}
+
+/// The verbosity of a [`CaptureLogPolicy`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CaptureVerbosity {
+    /// Nothing is logged.
+    Quiet,
+    /// Encoding errors are logged.
+    #[default]
+    Errors,
+    /// Encoding errors are logged, as well as when the capture starts and finishes.
+    Verbose,
+}
+
+impl CaptureLogPolicy {
+    /// Creates a policy that logs nothing.
+    pub fn quiet() -> Self {
+        Self::default().with_verbosity(CaptureVerbosity::Quiet)
+    }
+
+    /// Creates a policy that logs errors and when the capture starts and finishes.
+    pub fn verbose() -> Self {
+        Self::default().with_verbosity(CaptureVerbosity::Verbose)
+    }
+
+    /// Sets the verbosity. Defaults to [`CaptureVerbosity::Errors`].
+    pub fn with_verbosity(mut self, verbosity: CaptureVerbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Aggregates encoding errors: at most one error is logged per interval, together with the
+    /// number of errors since the last one. Remaining errors are logged when the capture stops.
+    pub fn with_aggregated_errors(mut self, interval: Duration) -> Self {
+        self.aggregate_errors = Some(interval);
+        self
+    }
+
+    /// Returns the verbosity.
+    pub fn verbosity(&self) -> CaptureVerbosity {
+        self.verbosity
+    }
+
+    /// Returns the interval errors are aggregated over, if set.
+    pub fn aggregate_errors(&self) -> Option<Duration> {
+        self.aggregate_errors
+    }
+}
+
+/// Runs the encoders of a capture on background worker threads instead of the render thread, so
+/// slow encoders don't stall rendering. This is optional and can be attached next to the
+/// [`Capture`]. The settings are applied when the capture starts.
+///
+/// The encoders are distributed over the threads. Every encoder always runs on the same thread, so
+/// it gets the frames in order. If the workers fall behind by more than the
+/// [queue capacity](Self::with_queue_capacity), the render thread waits for them or the frames are
+/// dropped, depending on the [`WorkerBackpressure`]. The total size of the queued frames can be
+/// limited with a [`CaptureMemoryBudget`](memory::CaptureMemoryBudget).
+///
+/// By default, a single thread with [`WorkerPriority::Low`] is used, so encoding doesn't steal time
+/// from rendering or the simulation on constrained machines. Priorities and core pinning are
+/// supported on Linux and Windows; on other platforms, they are ignored with a warning.
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+pub struct CaptureWorkerSettings {
+    threads: usize,
+    priority: WorkerPriority,
+    core_affinity: Option<Vec<usize>>,
+    queue_capacity: usize,
+    backpressure: WorkerBackpressure,
+}
+
+/// What happens if the workers of [`CaptureWorkerSettings`] fall behind, i.e. if the queue of a
+/// worker is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WorkerBackpressure {
+    /// The render thread waits until the worker has room for the frame, so all frames are encoded.
+    #[default]
+    Block,
+    /// The frame is dropped for the encoders of the worker, so rendering is never slowed down by
+    /// encoding. The number of dropped frames is logged when the capture stops.
+    DropFrames,
+}
+
+/// The priority of the threads of [`CaptureWorkerSettings`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WorkerPriority {
+    /// The priority of the app is kept.
+    Normal,
+    /// Below normal priority (nice 10 on Linux, `THREAD_PRIORITY_BELOW_NORMAL` on Windows).
+    #[default]
+    Low,
+    /// The lowest priority (nice 19 on Linux, `THREAD_PRIORITY_LOWEST` on Windows).
+    Lowest,
+}
+
+impl Default for CaptureWorkerSettings {
+    fn default() -> Self {
+        Self {
+            threads: 1,
+            priority: WorkerPriority::default(),
+            core_affinity: None,
+            queue_capacity: 2,
+            backpressure: WorkerBackpressure::default(),
+        }
+    }
+}
+
+impl CaptureWorkerSettings {
+    /// Sets the number of worker threads. At most one thread per encoder is used. Defaults to `1`.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Sets the priority of the worker threads. Defaults to [`WorkerPriority::Low`].
+    pub fn with_priority(mut self, priority: WorkerPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Pins the worker threads to the given CPU cores, e.g. to keep them away from the cores the
+    /// simulation runs on. Cores that don't exist are ignored.
+    pub fn with_core_affinity(mut self, cores: impl IntoIterator<Item = usize>) -> Self {
+        self.core_affinity = Some(cores.into_iter().collect());
+        self
+    }
+
+    /// Sets how many frames can be queued per worker before the [backpressure](Self::with_backpressure)
+    /// kicks in. Defaults to `2`.
+    pub fn with_queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.queue_capacity = queue_capacity.max(1);
+        self
+    }
+
+    /// Sets what happens if a worker falls behind. Defaults to [`WorkerBackpressure::Block`].
+    pub fn with_backpressure(mut self, backpressure: WorkerBackpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Returns the number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Returns the priority of the worker threads.
+    pub fn priority(&self) -> WorkerPriority {
+        self.priority
+    }
+
+    /// Returns the CPU cores the worker threads are pinned to, if set.
+    pub fn core_affinity(&self) -> Option<&[usize]> {
+        self.core_affinity.as_deref()
+    }
+
+    /// Returns how many frames can be queued per worker.
+    pub fn queue_capacity(&self) -> usize {
+        self.queue_capacity
+    }
+
+    /// Returns what happens if a worker falls behind.
+    pub fn backpressure(&self) -> WorkerBackpressure {
+        self.backpressure
+    }
+}
+
 /// Extension trait for the camera to set the target to a headless image.
 /// This is implemented for `Camera`, `Camera2dBundle`, and `Camera3dBundle`.
 ///
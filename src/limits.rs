@@ -0,0 +1,213 @@
+//! Stop captures automatically when they exceed a limit, e.g. to protect long-running servers from
+//! forgotten recordings filling the disk.
+//!
+//! Attach [`CaptureLimits`] next to the [`Capture`]. The capture is stopped as soon as it exceeds
+//! its maximum duration, number of frames or output size, and a [`CaptureLimitExceeded`] event is
+//! sent.
+//!
+//! The output size is the size of the files and directories the encoders
+//! [write to](crate::Encoder::describe_output), checked once per
+//! [interval](CaptureLimits::with_check_interval). Encoders writing to an arbitrary writer, e.g. a
+//! socket, are not counted.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{limits::*, CaptureBundle};
+//! # use std::time::Duration;
+//! #
+//! commands.spawn((
+//!     Camera2dBundle::default(),
+//!     CaptureBundle::default(),
+//!     CaptureLimits::new()
+//!         .with_max_duration(Duration::from_secs(60 * 60))
+//!         .with_max_output_bytes(50_000_000_000),
+//! ));
+//!
+//! fn on_limit(mut events: EventReader<CaptureLimitExceeded>) {
+//!     for event in events.read() {
+//!         warn!("Capture {} stopped: {}", event.capture, event.limit);
+//!     }
+//! }
+//! ```
+
+use crate::Capture;
+use bevy::{prelude::*, utils::Duration};
+use std::{fmt, fs, path::Path, time::Instant};
+
+/// The default interval of the output size checks of [`CaptureLimits`].
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The limits of a capture. This is optional and can be attached next to the [`Capture`], see the
+/// [module docs](self).
+#[derive(Debug, Clone, PartialEq, Component)]
+pub struct CaptureLimits {
+    max_duration: Option<Duration>,
+    max_frames: Option<u64>,
+    max_output_bytes: Option<u64>,
+    check_interval: Duration,
+    checked_at: Option<Instant>,
+}
+
+impl Default for CaptureLimits {
+    fn default() -> Self {
+        Self {
+            max_duration: None,
+            max_frames: None,
+            max_output_bytes: None,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            checked_at: None,
+        }
+    }
+}
+
+impl CaptureLimits {
+    /// Creates new limits without any limit set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum wall-clock duration of a capture, see [`Capture::elapsed`].
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    /// Sets the maximum number of frames passed to the encoders.
+    pub fn with_max_frames(mut self, max_frames: u64) -> Self {
+        self.max_frames = Some(max_frames);
+        self
+    }
+
+    /// Sets the maximum size of the output of the encoders in bytes, see the
+    /// [module docs](self).
+    pub fn with_max_output_bytes(mut self, max_output_bytes: u64) -> Self {
+        self.max_output_bytes = Some(max_output_bytes);
+        self
+    }
+
+    /// Sets how often the output size is checked. Defaults to [`DEFAULT_CHECK_INTERVAL`].
+    pub fn with_check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+        self
+    }
+
+    /// Returns the maximum wall-clock duration of a capture.
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.max_duration
+    }
+
+    /// Returns the maximum number of frames passed to the encoders.
+    pub fn max_frames(&self) -> Option<u64> {
+        self.max_frames
+    }
+
+    /// Returns the maximum size of the output of the encoders in bytes.
+    pub fn max_output_bytes(&self) -> Option<u64> {
+        self.max_output_bytes
+    }
+
+    /// Returns how often the output size is checked.
+    pub fn check_interval(&self) -> Duration {
+        self.check_interval
+    }
+
+    fn exceeded(&mut self, capture: &Capture) -> Option<CaptureLimit> {
+        if let (Some(max), Some(elapsed)) = (self.max_duration, capture.elapsed()) {
+            if elapsed >= max {
+                return Some(CaptureLimit::Duration(max));
+            }
+        }
+        if let Some(max) = self.max_frames {
+            if capture.frames_captured() >= max {
+                return Some(CaptureLimit::Frames(max));
+            }
+        }
+        if let Some(max) = self.max_output_bytes {
+            let now = Instant::now();
+            if self
+                .checked_at
+                .is_none_or(|checked_at| now - checked_at >= self.check_interval)
+            {
+                self.checked_at = Some(now);
+                let bytes = capture
+                    .encoders()
+                    .iter()
+                    .map(|encoder| output_size(Path::new(&encoder.output)))
+                    .sum::<u64>();
+                if bytes >= max {
+                    return Some(CaptureLimit::OutputBytes(max));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A limit of [`CaptureLimits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureLimit {
+    /// The maximum wall-clock duration.
+    Duration(Duration),
+    /// The maximum number of frames.
+    Frames(u64),
+    /// The maximum size of the output in bytes.
+    OutputBytes(u64),
+}
+
+impl fmt::Display for CaptureLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duration(max) => write!(f, "exceeded the maximum duration of {max:?}"),
+            Self::Frames(max) => write!(f, "exceeded the maximum of {max} frames"),
+            Self::OutputBytes(max) => write!(f, "exceeded the maximum output size of {max} bytes"),
+        }
+    }
+}
+
+/// Sent when a capture was stopped because it exceeded one of its [`CaptureLimits`].
+#[derive(Debug, Clone, PartialEq, Eq, Event)]
+pub struct CaptureLimitExceeded {
+    /// The entity of the [`Capture`].
+    pub capture: Entity,
+    /// The limit that was exceeded.
+    pub limit: CaptureLimit,
+}
+
+/// Returns the size of the file, or of the files in the directory, at the given path. Paths that
+/// don't exist, e.g. descriptions of outputs that aren't files, have a size of zero.
+fn output_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+pub(crate) fn enforce_capture_limits(
+    mut captures: Query<(Entity, &mut Capture, &mut CaptureLimits)>,
+    mut events: EventWriter<CaptureLimitExceeded>,
+) {
+    for (entity, mut capture, mut limits) in &mut captures {
+        if !capture.is_capturing() {
+            continue;
+        }
+        if let Some(limit) = limits.exceeded(&capture) {
+            warn!("Stopping capture {entity}, which {limit}");
+            capture.stop();
+            events.send(CaptureLimitExceeded {
+                capture: entity,
+                limit,
+            });
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Adjust encoder settings of a running capture, e.g. to live-tune the quality of a stream.
+//!
+//! Attach [`LiveEncoderSettings`] next to the [`Capture`](crate::Capture) and change them at any
+//! time. The settings are passed to the encoders of the capture with every frame in the
+//! [frame metadata](crate::metadata), and applied where the codec allows:
+//!
+//! - `bitrate`: The [`RtspPushEncoder`](crate::encoder::rtsp::RtspPushEncoder) restarts the
+//!   stream with the new bitrate at the next keyframe.
+//! - `crf`: The [`Mp4FfmpegCliEncoder`](crate::encoder::mp4_ffmpeg_cli::Mp4FfmpegCliEncoder) starts
+//!   a new segment with the new CRF at the next frame. The segments are joined when it finishes.
+//! - `frame_delay`: The [`GifEncoder`](crate::encoder::gif::GifEncoder) shows the next frame for the
+//!   new delay.
+//!
+//! Encoders ignore settings they don't support.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::live_settings::LiveEncoderSettings;
+//! #
+//! fn lower_quality(mut settings: Query<&mut LiveEncoderSettings>) {
+//!     for mut settings in &mut settings {
+//!         settings.set_bitrate(Some(1500));
+//!     }
+//! }
+//! ```
+
+use crate::metadata::{FrameMetadata, MetadataValue};
+use bevy::{prelude::*, utils::Duration};
+
+/// The metadata key of the bitrate in kbit/s, see [`LiveEncoderSettings::with_bitrate`].
+pub const BITRATE_KEY: &str = "bitrate";
+
+/// The metadata key of the CRF, see [`LiveEncoderSettings::with_crf`].
+pub const CRF_KEY: &str = "crf";
+
+/// The metadata key of the frame delay in milliseconds, see
+/// [`LiveEncoderSettings::with_frame_delay`].
+pub const FRAME_DELAY_KEY: &str = "frame_delay";
+
+/// Encoder settings that can be changed while capturing. This is optional and can be attached next
+/// to the [`Capture`](crate::Capture). Settings that are `None` keep the value the encoder was
+/// created with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Component)]
+pub struct LiveEncoderSettings {
+    bitrate: Option<u32>,
+    crf: Option<u32>,
+    frame_delay: Option<Duration>,
+}
+
+impl LiveEncoderSettings {
+    /// Creates new settings that keep all values of the encoders.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the bitrate of streaming encoders in kbit/s.
+    pub fn with_bitrate(mut self, bitrate: u32) -> Self {
+        self.bitrate = Some(bitrate);
+        self
+    }
+
+    /// Sets the CRF (Constant Rate Factor) of video encoders.
+    pub fn with_crf(mut self, crf: u32) -> Self {
+        self.crf = Some(crf);
+        self
+    }
+
+    /// Sets the time each frame of a gif is shown.
+    pub fn with_frame_delay(mut self, frame_delay: Duration) -> Self {
+        self.frame_delay = Some(frame_delay);
+        self
+    }
+
+    /// Changes the bitrate of streaming encoders in kbit/s.
+    pub fn set_bitrate(&mut self, bitrate: Option<u32>) {
+        self.bitrate = bitrate;
+    }
+
+    /// Changes the CRF (Constant Rate Factor) of video encoders.
+    pub fn set_crf(&mut self, crf: Option<u32>) {
+        self.crf = crf;
+    }
+
+    /// Changes the time each frame of a gif is shown.
+    pub fn set_frame_delay(&mut self, frame_delay: Option<Duration>) {
+        self.frame_delay = frame_delay;
+    }
+
+    /// Returns the bitrate of streaming encoders in kbit/s.
+    pub fn bitrate(&self) -> Option<u32> {
+        self.bitrate
+    }
+
+    /// Returns the CRF (Constant Rate Factor) of video encoders.
+    pub fn crf(&self) -> Option<u32> {
+        self.crf
+    }
+
+    /// Returns the time each frame of a gif is shown.
+    pub fn frame_delay(&self) -> Option<Duration> {
+        self.frame_delay
+    }
+
+    /// Reads the settings from the metadata of a frame, e.g. in a custom encoder.
+    pub fn from_metadata(metadata: &FrameMetadata) -> Self {
+        Self {
+            bitrate: get_u32(metadata, BITRATE_KEY),
+            crf: get_u32(metadata, CRF_KEY),
+            frame_delay: get_u32(metadata, FRAME_DELAY_KEY)
+                .map(|millis| Duration::from_millis(millis as u64)),
+        }
+    }
+
+    /// Inserts the settings into the metadata of a frame.
+    pub(crate) fn apply(&self, metadata: &mut FrameMetadata) {
+        if let Some(bitrate) = self.bitrate {
+            metadata.insert(BITRATE_KEY, bitrate);
+        }
+        if let Some(crf) = self.crf {
+            metadata.insert(CRF_KEY, crf);
+        }
+        if let Some(frame_delay) = self.frame_delay {
+            metadata.insert(
+                FRAME_DELAY_KEY,
+                frame_delay.as_millis().min(u32::MAX as u128) as u32,
+            );
+        }
+    }
+}
+
+fn get_u32(metadata: &FrameMetadata, key: &str) -> Option<u32> {
+    match metadata.get(key)? {
+        MetadataValue::Int(value) => u32::try_from(*value).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,219 @@
+//! A global budget for the memory of frames that are queued for the encoders.
+//!
+//! Frames are queued when the encoders run on worker threads (see
+//! [`CaptureWorkerSettings`](crate::CaptureWorkerSettings)). If the encoders fall behind, e.g.
+//! during long 4K captures, the queued frames can add up quickly.
+
+use bevy::prelude::*;
+use std::{
+    fs, io,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+};
+
+/// A resource limiting the total size of the frames that are queued for the encoders, across all
+/// captures. Without this resource, the queues are only limited by their length.
+///
+/// ```ignore
+/// app.insert_resource(
+///     CaptureMemoryBudget::new(2 * 1024 * 1024 * 1024).with_policy(MemoryBudgetPolicy::Spill),
+/// );
+/// ```
+///
+/// A frame larger than the whole budget is still queued if nothing else is queued.
+#[derive(Debug, Clone, Resource)]
+pub struct CaptureMemoryBudget {
+    max_bytes: u64,
+    policy: MemoryBudgetPolicy,
+    spill_dir: PathBuf,
+    state: Arc<BudgetState>,
+}
+
+/// What happens to a frame that doesn't fit into the [`CaptureMemoryBudget`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryBudgetPolicy {
+    /// Rendering waits until enough queued frames are encoded.
+    #[default]
+    Block,
+    /// The frame is not encoded.
+    Drop,
+    /// The frame is written to a temporary file and read back when it is encoded.
+    Spill,
+}
+
+/// Statistics of a [`CaptureMemoryBudget`], see [`CaptureMemoryBudget::stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudgetStats {
+    /// The size of the frames that are currently queued, in bytes.
+    pub in_flight_bytes: u64,
+    /// The largest size of the queued frames so far, in bytes.
+    pub peak_bytes: u64,
+    /// The number of frames rendering had to wait for.
+    pub frames_blocked: u64,
+    /// The number of frames that were dropped.
+    pub frames_dropped: u64,
+    /// The number of frames that were spilled to temporary files.
+    pub frames_spilled: u64,
+}
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    in_flight: Mutex<u64>,
+    released: Condvar,
+    peak: AtomicU64,
+    blocked: AtomicU64,
+    dropped: AtomicU64,
+    spilled: AtomicU64,
+}
+
+impl CaptureMemoryBudget {
+    /// Creates a new budget of the given number of bytes with [`MemoryBudgetPolicy::Block`].
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            policy: MemoryBudgetPolicy::default(),
+            spill_dir: std::env::temp_dir(),
+            state: Arc::default(),
+        }
+    }
+
+    /// Sets what happens to frames that don't fit into the budget.
+    pub fn with_policy(mut self, policy: MemoryBudgetPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the directory frames are spilled to with [`MemoryBudgetPolicy::Spill`]. Defaults to
+    /// the temporary directory of the system.
+    pub fn with_spill_dir(mut self, spill_dir: impl Into<PathBuf>) -> Self {
+        self.spill_dir = spill_dir.into();
+        self
+    }
+
+    /// Returns the maximum size of the queued frames in bytes.
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Returns the policy for frames that don't fit into the budget.
+    pub fn policy(&self) -> MemoryBudgetPolicy {
+        self.policy
+    }
+
+    /// Returns the current statistics.
+    pub fn stats(&self) -> MemoryBudgetStats {
+        MemoryBudgetStats {
+            in_flight_bytes: *self.state.in_flight.lock().unwrap(),
+            peak_bytes: self.state.peak.load(Ordering::Relaxed),
+            frames_blocked: self.state.blocked.load(Ordering::Relaxed),
+            frames_dropped: self.state.dropped.load(Ordering::Relaxed),
+            frames_spilled: self.state.spilled.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Decides what to do with a frame of the given size, waiting for queued frames with
+    /// [`MemoryBudgetPolicy::Block`].
+    pub(crate) fn admit(&self, bytes: u64) -> Admission {
+        let mut in_flight = self.state.in_flight.lock().unwrap();
+        let fits = |in_flight: u64| in_flight == 0 || in_flight + bytes <= self.max_bytes;
+        if !fits(*in_flight) {
+            match self.policy {
+                MemoryBudgetPolicy::Block => {
+                    self.state.blocked.fetch_add(1, Ordering::Relaxed);
+                    in_flight = self
+                        .state
+                        .released
+                        .wait_while(in_flight, |in_flight| !fits(*in_flight))
+                        .unwrap();
+                }
+                MemoryBudgetPolicy::Drop => {
+                    self.state.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Admission::Drop;
+                }
+                MemoryBudgetPolicy::Spill => {
+                    self.state.spilled.fetch_add(1, Ordering::Relaxed);
+                    return Admission::Spill;
+                }
+            }
+        }
+
+        *in_flight += bytes;
+        self.state.peak.fetch_max(*in_flight, Ordering::Relaxed);
+        Admission::Memory(Reservation {
+            state: Arc::clone(&self.state),
+            bytes,
+        })
+    }
+
+    /// Writes the data of the image to a temporary file.
+    pub(crate) fn spill(&self, image: &Image) -> io::Result<SpilledImage> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        fs::create_dir_all(&self.spill_dir)?;
+        let path = self.spill_dir.join(format!(
+            "bevy_capture_spill_{}_{}.raw",
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, &image.data)?;
+
+        // Everything but the data is kept in memory.
+        let image = Image {
+            data: Vec::new(),
+            texture_descriptor: image.texture_descriptor.clone(),
+            sampler: image.sampler.clone(),
+            texture_view_descriptor: image.texture_view_descriptor.clone(),
+            asset_usage: image.asset_usage,
+        };
+        Ok(SpilledImage { path, image })
+    }
+}
+
+/// The decision of [`CaptureMemoryBudget::admit`].
+pub(crate) enum Admission {
+    /// The frame fits into the budget, which is released when the reservation is dropped.
+    Memory(Reservation),
+    /// The frame has to be spilled.
+    Spill,
+    /// The frame has to be dropped.
+    Drop,
+}
+
+/// A part of the budget that is in use by a queued frame.
+pub(crate) struct Reservation {
+    state: Arc<BudgetState>,
+    bytes: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        *self.state.in_flight.lock().unwrap() -= self.bytes;
+        self.state.released.notify_all();
+    }
+}
+
+/// An image whose data was written to a temporary file. The file is removed once the image is
+/// dropped.
+pub(crate) struct SpilledImage {
+    path: PathBuf,
+    image: Image,
+}
+
+impl SpilledImage {
+    /// Reads the image back from the temporary file.
+    pub fn load(&self) -> io::Result<Image> {
+        let mut image = self.image.clone();
+        image.data = fs::read(&self.path)?;
+        Ok(image)
+    }
+}
+
+impl Drop for SpilledImage {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,301 @@
+//! Custom key/value metadata attached to the current frame by game systems.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::metadata::FrameMetadata;
+//! #
+//! fn telemetry(mut metadata: ResMut<FrameMetadata>, score: Res<Score>) {
+//!     metadata.insert("score", score.0);
+//!     metadata.insert("level", "forest");
+//! }
+//! ```
+//!
+//! The metadata is cleared at the start of every frame and passed to the encoders together with the
+//! frame, see [`Encoder::encode_with_metadata`](crate::Encoder::encode_with_metadata).
+//!
+//! Metadata that only belongs to a single capture goes into a [`FrameMetadata`] component on the
+//! entity of the [`Capture`](crate::Capture) instead. It is also cleared at the start of every
+//! frame and overrides the values of the resource for the encoders of that capture only.
+//!
+//! Captures add the [index](FRAME_INDEX_KEY) of the frame, and the [timestamp](TIMESTAMP_KEY) and
+//! [delta time](DELTA_TIME_KEY) of the clock if the [`CapturePlugin`](crate::CapturePlugin) has
+//! one, so encoders can encode frames at the pace they were rendered at, see
+//! [`FrameMetadata::frame_index`], [`FrameMetadata::timestamp`] and [`FrameMetadata::delta_time`].
+
+use crate::CaptureClock;
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+
+#[cfg(not(feature = "serde"))]
+use std::fmt::Write;
+
+/// The key that forces video encoders to encode the frame as a keyframe, e.g. at a marker or a
+/// segment boundary, if set to `true`. See [`FrameMetadata::force_keyframe`].
+pub const KEYFRAME_KEY: &str = "keyframe";
+
+/// The key of the elapsed seconds of the [`CaptureClock`] the frame was rendered at, if the
+/// [`CapturePlugin`](crate::CapturePlugin) has a clock.
+pub const TIMESTAMP_KEY: &str = "timestamp";
+
+/// The key of the seconds of the [`CaptureClock`] that elapsed since the previous frame of the app,
+/// if the [`CapturePlugin`](crate::CapturePlugin) has a clock. Frames that were not captured, e.g.
+/// while the capture was paused, are not taken into account, use the [`TIMESTAMP_KEY`] for the
+/// time between captured frames.
+pub const DELTA_TIME_KEY: &str = "delta_time";
+
+/// The key of the index of the frame in its capture, starting at `0` with the first frame passed
+/// to the encoders. Set for every captured frame.
+pub const FRAME_INDEX_KEY: &str = "frame_index";
+
+/// A resource holding the metadata of the current frame, or a component holding the metadata of
+/// the current frame of a single capture.
+#[derive(Debug, Default, Clone, PartialEq, Resource, Component)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameMetadata(BTreeMap<String, MetadataValue>);
+
+impl FrameMetadata {
+    /// Inserts a value, replacing the previous value of the key.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<MetadataValue>) {
+        self.0.insert(key.into(), value.into());
+    }
+
+    /// Returns the value of the key.
+    pub fn get(&self, key: &str) -> Option<&MetadataValue> {
+        self.0.get(key)
+    }
+
+    /// Forces video encoders to encode the current frame as a keyframe, so the video can be cut or
+    /// seeked exactly at this frame.
+    pub fn force_keyframe(&mut self) {
+        self.insert(KEYFRAME_KEY, true);
+    }
+
+    /// Returns `true` if the current frame is forced to be a keyframe.
+    pub fn is_keyframe_forced(&self) -> bool {
+        matches!(self.get(KEYFRAME_KEY), Some(MetadataValue::Bool(true)))
+    }
+
+    /// Returns the index of the frame in its capture, see [`FRAME_INDEX_KEY`].
+    pub fn frame_index(&self) -> Option<u64> {
+        match self.get(FRAME_INDEX_KEY) {
+            Some(MetadataValue::Int(index)) => u64::try_from(*index).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the timestamp of the frame in seconds, see [`TIMESTAMP_KEY`].
+    pub fn timestamp(&self) -> Option<f64> {
+        self.seconds(TIMESTAMP_KEY)
+    }
+
+    /// Returns the seconds since the previous frame of the app, see [`DELTA_TIME_KEY`].
+    pub fn delta_time(&self) -> Option<f64> {
+        self.seconds(DELTA_TIME_KEY)
+    }
+
+    fn seconds(&self, key: &str) -> Option<f64> {
+        match self.get(key) {
+            Some(MetadataValue::Float(seconds)) => Some(*seconds),
+            Some(MetadataValue::Int(seconds)) => Some(*seconds as f64),
+            _ => None,
+        }
+    }
+
+    /// Inserts all values of the other metadata, replacing the values of the same keys.
+    pub fn merge(&mut self, other: &FrameMetadata) {
+        for (key, value) in other.iter() {
+            self.insert(key, value.clone());
+        }
+    }
+
+    /// Removes all values.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Returns `true` if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns an iterator over the values, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &MetadataValue)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Serializes the metadata as a compact JSON object. Non-finite floats are written as `null`.
+    pub fn to_json(&self) -> String {
+        #[cfg(feature = "serde")]
+        return serde_json::to_string(self).expect("metadata serializes to JSON");
+
+        #[cfg(not(feature = "serde"))]
+        write_json(self)
+    }
+}
+
+/// A metadata value.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum MetadataValue {
+    /// A boolean.
+    Bool(bool),
+    /// An integer.
+    Int(i64),
+    /// A floating point number.
+    Float(f64),
+    /// A string.
+    String(String),
+}
+
+impl From<bool> for MetadataValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+macro_rules! impl_from_int {
+    ($($ty:ty),*) => {
+        $(impl From<$ty> for MetadataValue {
+            fn from(value: $ty) -> Self {
+                Self::Int(value as i64)
+            }
+        })*
+    };
+}
+
+impl_from_int!(i8, i16, i32, i64, u8, u16, u32, u64, isize, usize);
+
+impl From<f32> for MetadataValue {
+    fn from(value: f32) -> Self {
+        Self::Float(value as f64)
+    }
+}
+
+impl From<f64> for MetadataValue {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<&str> for MetadataValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for MetadataValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+/// Serializes the metadata like serde_json, without the `serde` feature.
+#[cfg(not(feature = "serde"))]
+fn write_json(metadata: &FrameMetadata) -> String {
+    let mut json = String::from("{");
+    for (i, (key, value)) in metadata.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write_json_string(&mut json, key);
+        json.push(':');
+        match value {
+            MetadataValue::Bool(value) => write!(json, "{value}").unwrap(),
+            MetadataValue::Int(value) => write!(json, "{value}").unwrap(),
+            MetadataValue::Float(value) if value.is_finite() => write!(json, "{value:?}").unwrap(),
+            MetadataValue::Float(_) => json.push_str("null"),
+            MetadataValue::String(value) => write_json_string(&mut json, value),
+        }
+    }
+    json.push('}');
+    json
+}
+
+#[cfg(not(feature = "serde"))]
+fn write_json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+pub(crate) fn clear_metadata(
+    mut metadata: ResMut<FrameMetadata>,
+    mut captures: Query<&mut FrameMetadata>,
+) {
+    metadata.clear();
+    for mut metadata in &mut captures {
+        metadata.clear();
+    }
+}
+
+/// The clock of the [`CapturePlugin`](crate::CapturePlugin) frames are timestamped with.
+#[derive(Resource)]
+pub(crate) struct TimestampClock(pub CaptureClock);
+
+pub(crate) fn insert_timestamp(
+    mut metadata: ResMut<FrameMetadata>,
+    clock: Res<TimestampClock>,
+    real: Option<Res<Time<Real>>>,
+    virt: Option<Res<Time<Virtual>>>,
+) {
+    let times = match clock.0 {
+        CaptureClock::Real => {
+            real.map(|time| (time.elapsed_seconds_f64(), time.delta_seconds_f64()))
+        }
+        CaptureClock::Virtual => {
+            virt.map(|time| (time.elapsed_seconds_f64(), time.delta_seconds_f64()))
+        }
+    };
+    if let Some((elapsed, delta)) = times {
+        metadata.insert(TIMESTAMP_KEY, elapsed);
+        metadata.insert(DELTA_TIME_KEY, delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_json() {
+        let mut metadata = FrameMetadata::default();
+        metadata.insert("int", -3);
+        metadata.insert("float", 0.5);
+        metadata.insert("nan", f64::NAN);
+        metadata.insert("bool", true);
+        metadata.insert("string", "\"quoted\"\\\n\u{1}");
+        assert_eq!(
+            metadata.to_json(),
+            r#"{"bool":true,"float":0.5,"int":-3,"nan":null,"string":"\"quoted\"\\\n\u0001"}"#
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserializes_json() {
+        let metadata: FrameMetadata =
+            serde_json::from_str(r#"{"bool":true,"float":0.5,"int":-3,"string":"a"}"#).unwrap();
+        let values: Vec<_> = metadata.iter().map(|(_, value)| value.clone()).collect();
+        assert_eq!(
+            values,
+            [
+                MetadataValue::Bool(true),
+                MetadataValue::Float(0.5),
+                MetadataValue::Int(-3),
+                MetadataValue::String("a".to_string())
+            ]
+        );
+    }
+}
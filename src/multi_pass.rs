@@ -0,0 +1,290 @@
+//! Capture the same frames multiple times with different camera or post-processing settings, e.g.
+//! a beauty, a wireframe and an AO-only pass, like render layers / AOVs in DCC tools.
+//!
+//! Attach [`CapturePasses`] to a capturing camera that renders to a headless image. The
+//! [`MultiPassPlugin`] spawns a pass camera for every [`CapturePass`] as a child of the camera. A
+//! pass camera starts as a copy of the camera with its own target, and is then modified by the
+//! setup callback of the pass, e.g. to change its render layers, tonemapping or clear color.
+//!
+//! The pass cameras follow the camera and capture the same frames: their captures are started,
+//! paused and stopped together with the capture of the camera, each with the encoders of its pass.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::{prelude::*, render::view::RenderLayers};
+//! # use bevy_capture::{encoder::frames::FramesEncoder, multi_pass::*, CaptureBundle};
+//! #
+//! app.add_plugins(MultiPassPlugin);
+//!
+//! commands.spawn((
+//!     Camera3dBundle::default().target_headless(1920, 1080, &mut images),
+//!     CaptureBundle::default(),
+//!     CapturePasses::default().with_pass(
+//!         CapturePass::new("wireframe", |name| Ok(FramesEncoder::new(format!("captures/{name}"))))
+//!             .with_setup(|pass| {
+//!                 pass.insert(RenderLayers::layer(1));
+//!             }),
+//!     ),
+//! ));
+//! ```
+
+use crate::{
+    encoder::Result, BoxedEncoder, CameraTargetHeadless, Capture, CaptureBundle, IntoEncoders,
+};
+use bevy::{
+    core_pipeline::{
+        core_2d::Camera2d,
+        core_3d::Camera3d,
+        tonemapping::{DebandDither, Tonemapping},
+    },
+    ecs::world::EntityWorldMut,
+    prelude::*,
+    render::{
+        camera::{CameraMainTextureUsages, CameraRenderGraph, Exposure, RenderTarget},
+        primitives::Frustum,
+        view::{ColorGrading, RenderLayers, VisibleEntities},
+    },
+};
+use std::{mem, sync::Arc, time::Instant};
+
+/// A Bevy plugin that spawns and drives the pass cameras of [`CapturePasses`].
+pub struct MultiPassPlugin;
+
+impl Plugin for MultiPassPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            update_passes.after(crate::defaults::start_default_captures),
+        );
+    }
+}
+
+type EncoderFactory = Arc<dyn Fn(&str) -> Result<Vec<BoxedEncoder>> + Send + Sync + 'static>;
+
+type SetupFn = Arc<dyn Fn(&mut EntityWorldMut<'_>) + Send + Sync + 'static>;
+
+/// The passes of a camera, see the [module docs](self).
+#[derive(Default, Component)]
+pub struct CapturePasses {
+    passes: Vec<CapturePass>,
+}
+
+impl CapturePasses {
+    /// Adds a pass.
+    pub fn with_pass(mut self, pass: CapturePass) -> Self {
+        self.passes.push(pass);
+        self
+    }
+
+    /// Returns the pass camera of the pass with the given name, once it was spawned.
+    pub fn camera(&self, name: &str) -> Option<Entity> {
+        self.passes
+            .iter()
+            .find(|pass| pass.name == name)
+            .and_then(|pass| pass.camera)
+    }
+
+    /// Returns the names of the passes.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.passes.iter().map(|pass| pass.name.as_str())
+    }
+}
+
+/// A pass of [`CapturePasses`], with its own encoders and camera settings.
+pub struct CapturePass {
+    name: String,
+    factory: EncoderFactory,
+    setup: Option<SetupFn>,
+    camera: Option<Entity>,
+    started_at: Option<Instant>,
+}
+
+impl CapturePass {
+    /// Creates a new pass. The factory is called with the name of the pass whenever the capture of
+    /// the camera is started, and returns the encoders of the pass.
+    pub fn new<F, E>(name: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(&str) -> Result<E> + Send + Sync + 'static,
+        E: IntoEncoders,
+    {
+        Self {
+            name: name.into(),
+            factory: Arc::new(move |name| factory(name).map(E::into_encoders)),
+            setup: None,
+            camera: None,
+            started_at: None,
+        }
+    }
+
+    /// Sets a callback that modifies the pass camera after it was spawned, e.g. to change its
+    /// render layers or post-processing settings.
+    pub fn with_setup(
+        mut self,
+        setup: impl Fn(&mut EntityWorldMut<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.setup = Some(Arc::new(setup));
+        self
+    }
+
+    /// Returns the name of the pass.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+fn update_passes(world: &mut World) {
+    let cameras = world
+        .query_filtered::<Entity, With<CapturePasses>>()
+        .iter(world)
+        .collect::<Vec<_>>();
+
+    for camera in cameras {
+        let mut passes = mem::take(
+            &mut world
+                .get_mut::<CapturePasses>(camera)
+                .unwrap()
+                .bypass_change_detection()
+                .passes,
+        );
+
+        for (index, pass) in passes.iter_mut().enumerate() {
+            if pass
+                .camera
+                .is_none_or(|entity| world.get_entity(entity).is_none())
+            {
+                pass.camera = spawn_pass_camera(world, camera, index, pass);
+                pass.started_at = None;
+            }
+            if let Some(pass_camera) = pass.camera {
+                sync_pass_camera(world, camera, pass_camera);
+                sync_pass_capture(world, camera, pass_camera, pass);
+            }
+        }
+
+        world
+            .get_mut::<CapturePasses>(camera)
+            .unwrap()
+            .bypass_change_detection()
+            .passes = passes;
+    }
+}
+
+fn spawn_pass_camera(
+    world: &mut World,
+    camera: Entity,
+    index: usize,
+    pass: &CapturePass,
+) -> Option<Entity> {
+    let main_camera = world.get::<Camera>(camera)?.clone();
+    let size = match &main_camera.target {
+        RenderTarget::Image(image) => world.resource::<Assets<Image>>().get(image)?.size(),
+        _ => {
+            warn_once!("Capture passes require a camera that renders to a headless image");
+            return None;
+        }
+    };
+
+    let mut pass_camera = Camera {
+        order: main_camera.order + 1 + index as isize,
+        ..main_camera
+    };
+    pass_camera =
+        pass_camera.target_headless(size.x, size.y, &mut world.resource_mut::<Assets<Image>>());
+
+    let global_transform = world
+        .get::<GlobalTransform>(camera)
+        .copied()
+        .unwrap_or_default();
+    let entity = world
+        .spawn((
+            Name::new(format!("Capture pass {}", pass.name)),
+            pass_camera,
+            TransformBundle::from_transform(Transform::IDENTITY),
+            CaptureBundle::default(),
+        ))
+        .insert(global_transform)
+        .set_parent(camera)
+        .id();
+
+    // The pass starts as a copy of the camera.
+    copy_component::<Projection>(world, camera, entity);
+    copy_component::<OrthographicProjection>(world, camera, entity);
+    copy_component::<CameraRenderGraph>(world, camera, entity);
+    copy_component::<Camera2d>(world, camera, entity);
+    copy_component::<Camera3d>(world, camera, entity);
+    copy_component::<Frustum>(world, camera, entity);
+    copy_component::<VisibleEntities>(world, camera, entity);
+    copy_component::<CameraMainTextureUsages>(world, camera, entity);
+    copy_component::<Tonemapping>(world, camera, entity);
+    copy_component::<DebandDither>(world, camera, entity);
+    copy_component::<ColorGrading>(world, camera, entity);
+    copy_component::<Exposure>(world, camera, entity);
+    copy_component::<RenderLayers>(world, camera, entity);
+
+    if let Some(setup) = &pass.setup {
+        setup(&mut world.entity_mut(entity));
+    }
+    Some(entity)
+}
+
+fn copy_component<T: Component + Clone>(world: &mut World, from: Entity, to: Entity) {
+    if let Some(component) = world.get::<T>(from).cloned() {
+        world.entity_mut(to).insert(component);
+    }
+}
+
+/// Keeps the projection of the pass camera in sync, e.g. when the camera zooms.
+fn sync_pass_camera(world: &mut World, camera: Entity, pass_camera: Entity) {
+    if let Some(projection) = world.get::<Projection>(camera).cloned() {
+        if let Some(mut pass_projection) = world.get_mut::<Projection>(pass_camera) {
+            *pass_projection = projection;
+        }
+    }
+    if let Some(projection) = world.get::<OrthographicProjection>(camera).cloned() {
+        if let Some(mut pass_projection) = world.get_mut::<OrthographicProjection>(pass_camera) {
+            *pass_projection = projection;
+        }
+    }
+}
+
+/// Starts, pauses and stops the capture of the pass together with the capture of the camera.
+fn sync_pass_capture(
+    world: &mut World,
+    camera: Entity,
+    pass_camera: Entity,
+    pass: &mut CapturePass,
+) {
+    let Some(capture) = world.get::<Capture>(camera) else {
+        return;
+    };
+    let (started_at, paused) = (capture.started_at(), capture.is_paused());
+    let Some(mut pass_capture) = world.get_mut::<Capture>(pass_camera) else {
+        return;
+    };
+
+    if started_at != pass.started_at {
+        pass.started_at = started_at;
+        if started_at.is_none() {
+            pass_capture.stop();
+            return;
+        }
+        // The capture of the camera was (re)started.
+        match (pass.factory)(&pass.name) {
+            Ok(encoders) => {
+                pass_capture.start(encoders);
+            }
+            Err(err) => {
+                error!(
+                    "Failed to create the encoders of the pass {}: {}",
+                    pass.name, err
+                );
+                pass_capture.stop();
+            }
+        }
+    }
+    if paused && !pass_capture.is_paused() {
+        pass_capture.pause();
+    } else if !paused && pass_capture.is_paused() {
+        pass_capture.resume();
+    }
+}
@@ -0,0 +1,203 @@
+//! Controls OBS through obs-websocket (v5), e.g. to start and stop OBS recordings in sync with
+//! captures.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::obs::{ObsClient, ObsPlugin, SyncObsRecording};
+//! #
+//! app.add_plugins(ObsPlugin)
+//!     .insert_resource(ObsClient::connect("ws://localhost:4455", Some("password")).unwrap());
+//!
+//! // OBS starts and stops recording whenever the capture of this camera starts and stops.
+//! commands.spawn((camera, CaptureBundle::default(), SyncObsRecording::default()));
+//! ```
+
+use crate::{
+    encoder::{Error, Result},
+    Capture,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bevy::prelude::*;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    thread,
+};
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// A plugin that syncs OBS recordings with captures that have a [`SyncObsRecording`] component.
+/// The requests are [queued](ObsClient::queue), so they don't block the app.
+pub struct ObsPlugin;
+
+impl Plugin for ObsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            sync_obs_recording.run_if(resource_exists::<ObsClient>),
+        );
+    }
+}
+
+/// Starts and stops the OBS recording whenever the capture on this entity starts and stops.
+#[derive(Default, Component)]
+pub struct SyncObsRecording {
+    recording: bool,
+}
+
+fn sync_obs_recording(obs: Res<ObsClient>, mut captures: Query<(&Capture, &mut SyncObsRecording)>) {
+    for (capture, mut sync) in &mut captures {
+        if capture.is_capturing() == sync.recording {
+            continue;
+        }
+        sync.recording = capture.is_capturing();
+
+        let request_type = if sync.recording {
+            "StartRecord"
+        } else {
+            "StopRecord"
+        };
+        obs.queue(request_type, Value::Null);
+    }
+}
+
+/// An obs-websocket client.
+///
+/// Requests are either sent directly, blocking until OBS responds, or [queued](Self::queue) and
+/// sent on a background thread of the client.
+#[derive(Resource)]
+pub struct ObsClient {
+    socket: Arc<Mutex<Socket>>,
+    queue: crossbeam_channel::Sender<(String, Value)>,
+}
+
+impl ObsClient {
+    /// Connects to obs-websocket, e.g. at `ws://localhost:4455`. The password is required if
+    /// authentication is enabled in OBS.
+    pub fn connect(url: &str, password: Option<&str>) -> Result<Self> {
+        let (mut socket, _) = tungstenite::connect(url).map_err(Error::custom)?;
+
+        let hello = read_op(&mut socket, 0)?;
+        let mut identify = json!({ "rpcVersion": 1 });
+        if let Some(auth) = hello.get("authentication") {
+            let password = password.ok_or("obs-websocket requires a password")?;
+            let salt = auth["salt"].as_str().ok_or("missing salt")?;
+            let challenge = auth["challenge"].as_str().ok_or("missing challenge")?;
+
+            let secret = STANDARD.encode(Sha256::digest(format!("{password}{salt}")));
+            let auth = STANDARD.encode(Sha256::digest(format!("{secret}{challenge}")));
+            identify["authentication"] = auth.into();
+        }
+
+        send_op(&mut socket, 1, identify)?;
+        read_op(&mut socket, 2)?;
+
+        // The queued requests are sent until the client is dropped.
+        let socket = Arc::new(Mutex::new(socket));
+        let (queue, requests) = crossbeam_channel::unbounded::<(String, Value)>();
+        let worker_socket = Arc::clone(&socket);
+        thread::Builder::new()
+            .name("capture obs client".to_owned())
+            .spawn(move || {
+                for (request_type, request_data) in requests {
+                    if let Err(err) = request(&worker_socket, &request_type, request_data) {
+                        bevy::log::error!("Failed to send OBS request: {}", err);
+                    }
+                }
+            })?;
+
+        Ok(Self { socket, queue })
+    }
+
+    /// Sends a request and returns the response data, see the
+    /// [protocol](https://github.com/obsproject/obs-websocket/blob/master/docs/generated/protocol.md#requests)
+    /// for all requests. This blocks until OBS responds, and while a queued request is sent.
+    pub fn request(&self, request_type: &str, request_data: Value) -> Result<Value> {
+        request(&self.socket, request_type, request_data)
+    }
+
+    /// Queues a request without waiting for the response. Queued requests are sent in order on a
+    /// background thread, failures are logged.
+    pub fn queue(&self, request_type: &str, request_data: Value) {
+        // The thread only stops once the client is dropped.
+        let _ = self.queue.send((request_type.to_owned(), request_data));
+    }
+
+    /// Starts the OBS recording.
+    pub fn start_record(&self) -> Result<()> {
+        self.request("StartRecord", Value::Null).map(|_| ())
+    }
+
+    /// Stops the OBS recording.
+    pub fn stop_record(&self) -> Result<()> {
+        self.request("StopRecord", Value::Null).map(|_| ())
+    }
+
+    /// Sets the text of a text source, e.g. to show the name of the current capture.
+    pub fn set_text(&self, input_name: &str, text: &str) -> Result<()> {
+        self.request(
+            "SetInputSettings",
+            json!({ "inputName": input_name, "inputSettings": { "text": text } }),
+        )
+        .map(|_| ())
+    }
+}
+
+fn request(socket: &Mutex<Socket>, request_type: &str, request_data: Value) -> Result<Value> {
+    let mut socket = socket.lock().unwrap();
+
+    let request_id = format!("bevy_capture_{request_type}");
+    send_op(
+        &mut socket,
+        6,
+        json!({
+            "requestType": request_type,
+            "requestId": request_id,
+            "requestData": request_data,
+        }),
+    )?;
+
+    loop {
+        let response = read_op(&mut socket, 7)?;
+        if response["requestId"] != request_id.as_str() {
+            continue;
+        }
+
+        let status = &response["requestStatus"];
+        if status["result"] != true {
+            return Err(format!(
+                "{request_type} failed with code {}: {}",
+                status["code"],
+                status["comment"].as_str().unwrap_or_default()
+            )
+            .into());
+        }
+
+        return Ok(response["responseData"].clone());
+    }
+}
+
+fn send_op(socket: &mut Socket, op: u8, d: Value) -> Result<()> {
+    socket
+        .send(Message::Text(json!({ "op": op, "d": d }).to_string()))
+        .map_err(Error::custom)?;
+    Ok(())
+}
+
+/// Reads messages until a message with the given op code is received and returns its data.
+fn read_op(socket: &mut Socket, op: u8) -> Result<Value> {
+    loop {
+        let message = match socket.read().map_err(Error::custom)? {
+            Message::Text(text) => serde_json::from_str::<Value>(&text).map_err(Error::custom)?,
+            Message::Close(_) => return Err("obs-websocket closed the connection".into()),
+            _ => continue,
+        };
+        if message["op"] == op {
+            return Ok(message["d"].clone());
+        }
+    }
+}
@@ -0,0 +1,431 @@
+//! An in-game photo mode built on top of the capture.
+//!
+//! The [`PhotoModePlugin`] provides the pieces most games re-implement around a capture:
+//!
+//! - [`PhotoMode`] freezes virtual time while it is active.
+//! - [`PhotoCamera`] is a free camera with FOV, roll and depth of field settings. Hook your own UI
+//!   up to its fields, they are applied to the camera while the photo mode is active.
+//! - [`TakePhoto`] saves a single frame, optionally rendered at a multiple of the target resolution.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{photo_mode::*, CameraTargetHeadless, CaptureBundle};
+//! #
+//! fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+//!     commands.spawn((
+//!         Camera3dBundle::default().target_headless(1920, 1080, &mut images),
+//!         CaptureBundle::default(),
+//!         PhotoCamera::default(),
+//!     ));
+//! }
+//!
+//! fn shutter(keys: Res<ButtonInput<KeyCode>>, mut mode: ResMut<PhotoMode>, mut photos: EventWriter<TakePhoto>) {
+//!     if keys.just_pressed(KeyCode::KeyP) {
+//!         mode.toggle();
+//!     }
+//!     if mode.is_active() && keys.just_pressed(KeyCode::Enter) {
+//!         photos.send(TakePhoto::new("photo.png").with_scale(4));
+//!     }
+//! }
+//! ```
+
+use crate::{encoder, Capture, Encoder};
+use bevy::{
+    core_pipeline::dof::DepthOfFieldSettings,
+    ecs::event::ManualEventReader,
+    input::mouse::MouseMotion,
+    prelude::*,
+    render::{camera::RenderTarget, render_resource::Extent3d, renderer::RenderDevice},
+};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// The number of frames rendered at the photo resolution before the photo is taken. The first
+/// frame after a resize can still use the camera settings of the previous resolution.
+const WARMUP_FRAMES: u32 = 1;
+
+/// A Bevy plugin for the photo mode.
+pub struct PhotoModePlugin;
+
+impl Plugin for PhotoModePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PhotoMode>()
+            .add_event::<TakePhoto>()
+            .add_systems(
+                Update,
+                (
+                    update_time.run_if(resource_changed::<PhotoMode>),
+                    (control_camera, apply_camera).chain().run_if(is_active),
+                    (finish_photo, take_photo).chain(),
+                ),
+            );
+    }
+}
+
+/// The state of the photo mode.
+#[derive(Default, Resource)]
+pub struct PhotoMode {
+    active: bool,
+    applied: bool,
+    time_was_paused: bool,
+    photo: Option<PendingPhoto>,
+}
+
+impl PhotoMode {
+    /// Enters the photo mode. Virtual time is paused until the photo mode is exited.
+    pub fn enter(&mut self) {
+        self.active = true;
+    }
+
+    /// Exits the photo mode.
+    pub fn exit(&mut self) {
+        self.active = false;
+    }
+
+    /// Enters the photo mode if it is not active, exits it otherwise.
+    pub fn toggle(&mut self) {
+        self.active = !self.active;
+    }
+
+    /// Returns `true` if the photo mode is active.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Returns `true` if a photo is being taken.
+    pub fn is_taking_photo(&self) -> bool {
+        self.photo.is_some()
+    }
+}
+
+/// A free camera for the photo mode. The camera is controlled with WASD (move), Q/E (down/up),
+/// Shift (faster) and the right mouse button (look) while the photo mode is active.
+///
+/// The fields can be changed by the app at any time, e.g. from sliders in a photo mode UI.
+/// When the photo mode is entered, the orientation and FOV are initialized from the camera.
+#[derive(Debug, Clone, Component)]
+pub struct PhotoCamera {
+    /// The rotation around the vertical axis in radians.
+    pub yaw: f32,
+    /// The rotation around the horizontal axis in radians.
+    pub pitch: f32,
+    /// The rotation around the view axis in radians.
+    pub roll: f32,
+    /// The vertical field of view in radians. Only used with a perspective projection.
+    pub fov: f32,
+    /// The distance in meters to the location in focus. Only used if the camera has
+    /// [`DepthOfFieldSettings`].
+    pub focal_distance: f32,
+    /// The aperture in f-stops. Only used if the camera has [`DepthOfFieldSettings`].
+    pub aperture_f_stops: f32,
+    /// The movement speed in meters per second.
+    pub move_speed: f32,
+    /// The rotation in radians per pixel of mouse movement.
+    pub look_sensitivity: f32,
+}
+
+impl Default for PhotoCamera {
+    fn default() -> Self {
+        let dof = DepthOfFieldSettings::default();
+        Self {
+            yaw: 0.0,
+            pitch: 0.0,
+            roll: 0.0,
+            fov: PerspectiveProjection::default().fov,
+            focal_distance: dof.focal_distance,
+            aperture_f_stops: dof.aperture_f_stops,
+            move_speed: 5.0,
+            look_sensitivity: 0.003,
+        }
+    }
+}
+
+/// An event to take a photo with the [`PhotoCamera`].
+///
+/// The camera must render to a headless image and must not be capturing already.
+#[derive(Debug, Clone, Event)]
+pub struct TakePhoto {
+    path: PathBuf,
+    scale: u32,
+}
+
+impl TakePhoto {
+    /// Creates a new photo event that saves the photo to the given path. The format is derived
+    /// from the extension.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            scale: 1,
+        }
+    }
+
+    /// Renders the photo at a multiple of the target resolution. Photos exceeding the maximum
+    /// texture dimension of the GPU are not taken, an error is logged instead.
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale.max(1);
+        self
+    }
+}
+
+struct PendingPhoto {
+    camera: Entity,
+    target: Handle<Image>,
+    original_size: Extent3d,
+    taken: Arc<AtomicBool>,
+}
+
+fn is_active(mode: Res<PhotoMode>) -> bool {
+    mode.active
+}
+
+fn update_time(
+    mut mode: ResMut<PhotoMode>,
+    mut time: ResMut<Time<Virtual>>,
+    mut cameras: Query<(&mut PhotoCamera, &Transform, &Projection)>,
+) {
+    let mode = mode.bypass_change_detection();
+    if mode.active == mode.applied {
+        return;
+    }
+    mode.applied = mode.active;
+
+    match (mode.active, time.is_paused()) {
+        (true, paused) => {
+            mode.time_was_paused = paused;
+            time.pause();
+
+            for (mut camera, transform, projection) in &mut cameras {
+                let (yaw, pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
+                camera.yaw = yaw;
+                camera.pitch = pitch;
+                camera.roll = roll;
+                if let Projection::Perspective(perspective) = projection {
+                    camera.fov = perspective.fov;
+                }
+            }
+        }
+        (false, true) if !mode.time_was_paused => time.unpause(),
+        (false, _) => {}
+    }
+}
+
+fn control_camera(
+    keys: Option<Res<ButtonInput<KeyCode>>>,
+    mouse: Option<Res<ButtonInput<MouseButton>>>,
+    motion: Option<Res<Events<MouseMotion>>>,
+    mut motion_reader: Local<ManualEventReader<MouseMotion>>,
+    time: Res<Time<Real>>,
+    mut cameras: Query<(&mut PhotoCamera, &mut Transform)>,
+) {
+    let look = match (&mouse, &motion) {
+        (Some(mouse), Some(motion)) => {
+            let delta = motion_reader
+                .read(motion)
+                .map(|motion| motion.delta)
+                .sum::<Vec2>();
+            if mouse.pressed(MouseButton::Right) {
+                delta
+            } else {
+                Vec2::ZERO
+            }
+        }
+        _ => Vec2::ZERO,
+    };
+
+    let mut movement = Vec3::ZERO;
+    let mut speed = 1.0;
+    if let Some(keys) = &keys {
+        for (key, direction) in [
+            (KeyCode::KeyW, Vec3::NEG_Z),
+            (KeyCode::KeyS, Vec3::Z),
+            (KeyCode::KeyA, Vec3::NEG_X),
+            (KeyCode::KeyD, Vec3::X),
+            (KeyCode::KeyQ, Vec3::NEG_Y),
+            (KeyCode::KeyE, Vec3::Y),
+        ] {
+            if keys.pressed(key) {
+                movement += direction;
+            }
+        }
+        if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+            speed = 4.0;
+        }
+    }
+
+    for (mut camera, mut transform) in &mut cameras {
+        if look != Vec2::ZERO {
+            camera.yaw -= look.x * camera.look_sensitivity;
+            camera.pitch = (camera.pitch - look.y * camera.look_sensitivity)
+                .clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
+        }
+        if movement != Vec3::ZERO {
+            let rotation = Quat::from_euler(EulerRot::YXZ, camera.yaw, camera.pitch, 0.0);
+            transform.translation +=
+                rotation * movement.normalize() * camera.move_speed * speed * time.delta_seconds();
+        }
+    }
+}
+
+fn apply_camera(
+    mut cameras: Query<
+        (
+            &PhotoCamera,
+            &mut Transform,
+            &mut Projection,
+            Option<&mut DepthOfFieldSettings>,
+        ),
+        Changed<PhotoCamera>,
+    >,
+) {
+    for (camera, mut transform, mut projection, dof) in &mut cameras {
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, camera.yaw, camera.pitch, camera.roll);
+        if let Projection::Perspective(perspective) = &mut *projection {
+            perspective.fov = camera.fov;
+        }
+        if let Some(mut dof) = dof {
+            dof.focal_distance = camera.focal_distance;
+            dof.aperture_f_stops = camera.aperture_f_stops;
+        }
+    }
+}
+
+fn take_photo(
+    mut mode: ResMut<PhotoMode>,
+    mut events: EventReader<TakePhoto>,
+    mut cameras: Query<(Entity, &Camera, &mut Capture), With<PhotoCamera>>,
+    mut images: ResMut<Assets<Image>>,
+    render_device: Option<Res<RenderDevice>>,
+) {
+    let max_dimension =
+        render_device.map_or(u32::MAX, |device| device.limits().max_texture_dimension_2d);
+    for event in events.read() {
+        if mode.photo.is_some() {
+            warn!("A photo is already being taken, ignoring {:?}", event.path);
+            continue;
+        }
+
+        let Ok((camera, camera_settings, mut capture)) = cameras.get_single_mut() else {
+            warn!(
+                "Expected exactly one photo camera, ignoring {:?}",
+                event.path
+            );
+            continue;
+        };
+        let RenderTarget::Image(target) = &camera_settings.target else {
+            warn!(
+                "The photo camera must render to an image, ignoring {:?}",
+                event.path
+            );
+            continue;
+        };
+        if capture.is_capturing() {
+            warn!(
+                "The photo camera is already capturing, ignoring {:?}",
+                event.path
+            );
+            continue;
+        }
+        let Some(image) = images.get_mut(target) else {
+            continue;
+        };
+
+        let original_size = image.texture_descriptor.size;
+        let (Some(width), Some(height)) = (
+            original_size.width.checked_mul(event.scale),
+            original_size.height.checked_mul(event.scale),
+        ) else {
+            error!("The photo size overflows, ignoring {:?}", event.path);
+            continue;
+        };
+        if width > max_dimension || height > max_dimension {
+            error!(
+                "A photo of {}x{} exceeds the maximum texture dimension of {}, ignoring {:?}",
+                width, height, max_dimension, event.path
+            );
+            continue;
+        }
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        if event.scale != 1 {
+            image.resize(size);
+        }
+
+        let taken = Arc::new(AtomicBool::new(false));
+        capture.start(PhotoEncoder {
+            path: event.path.clone(),
+            size: UVec2::new(size.width, size.height),
+            warmup_frames: WARMUP_FRAMES,
+            taken: Arc::clone(&taken),
+        });
+        mode.photo = Some(PendingPhoto {
+            camera,
+            target: target.clone(),
+            original_size,
+            taken,
+        });
+    }
+}
+
+fn finish_photo(
+    mut mode: ResMut<PhotoMode>,
+    mut captures: Query<&mut Capture>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let taken = match &mode.photo {
+        Some(photo) => photo.taken.load(Ordering::Acquire),
+        None => return,
+    };
+    if !taken {
+        return;
+    }
+
+    let photo = mode.photo.take().unwrap();
+    if let Ok(mut capture) = captures.get_mut(photo.camera) {
+        capture.stop();
+    }
+    if let Some(image) = images.get_mut(&photo.target) {
+        if image.texture_descriptor.size != photo.original_size {
+            image.resize(photo.original_size);
+        }
+    }
+}
+
+/// Saves the first frame with the expected size after some warmup frames.
+struct PhotoEncoder {
+    path: PathBuf,
+    size: UVec2,
+    warmup_frames: u32,
+    taken: Arc<AtomicBool>,
+}
+
+impl Encoder for PhotoEncoder {
+    fn encode(&mut self, image: &Image) -> encoder::Result<()> {
+        if self.taken.load(Ordering::Acquire) || image.size() != self.size {
+            return Ok(());
+        }
+        if self.warmup_frames > 0 {
+            self.warmup_frames -= 1;
+            return Ok(());
+        }
+
+        let result = encoder::to_dynamic_image(image).and_then(|image| Ok(image.save(&self.path)?));
+
+        // The photo is done even if saving failed, the error is logged by the capture.
+        self.taken.store(true, Ordering::Release);
+
+        result
+    }
+
+    fn name(&self) -> &str {
+        "photo_mode"
+    }
+}
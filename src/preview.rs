@@ -0,0 +1,62 @@
+//! A preview of the most recent captured frame, available in the main world.
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::BevyDefault,
+    },
+};
+use std::sync::{Arc, Mutex};
+
+/// A resource holding an image that is updated with the most recent captured frame, e.g. to show
+/// a "recording preview" as a UI image or on a quad inside the app itself.
+///
+/// The preview is disabled by default, as it copies every captured frame. Enable it by initializing
+/// the resource:
+///
+/// ```ignore
+/// app.init_resource::<CapturePreview>();
+/// ```
+///
+/// If multiple captures are active, the image shows the frame of whichever capture was encoded last.
+#[derive(Resource)]
+pub struct CapturePreview {
+    image: Handle<Image>,
+    pub(crate) latest: Arc<Mutex<Option<Image>>>,
+}
+
+impl CapturePreview {
+    /// Returns the handle of the preview image.
+    pub fn image(&self) -> &Handle<Image> {
+        &self.image
+    }
+}
+
+impl FromWorld for CapturePreview {
+    fn from_world(world: &mut World) -> Self {
+        let image = Image::new_fill(
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0; 4],
+            TextureFormat::bevy_default(),
+            RenderAssetUsages::default(),
+        );
+
+        Self {
+            image: world.resource_mut::<Assets<Image>>().add(image),
+            latest: Arc::default(),
+        }
+    }
+}
+
+pub(crate) fn update_preview(preview: Res<CapturePreview>, mut images: ResMut<Assets<Image>>) {
+    if let Some(image) = preview.latest.lock().unwrap().take() {
+        images.insert(&preview.image, image);
+    }
+}
@@ -0,0 +1,330 @@
+//! Black out or blur regions of the captured frames, e.g. so personal data or secrets in dev HUDs
+//! never end up in shared recordings. The game window is not affected.
+//!
+//! Regions are either fixed rectangles of a [`CaptureMask`] attached next to the
+//! [`Capture`](crate::Capture), or follow entities marked as [`PrivacyMasked`]. The screen bounds
+//! of marked entities are tracked by the [`PrivacyMaskPlugin`] and masked in every capture.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{privacy::*, CaptureBundle};
+//! #
+//! app.add_plugins(PrivacyMaskPlugin);
+//!
+//! fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+//!     commands.spawn((
+//!         Camera2dBundle::default().target_headless(1920, 1080, &mut images),
+//!         CaptureBundle::default(),
+//!         // The debug console in the top left corner.
+//!         CaptureMask::default().with_region(MaskRegion::blackout(Rect::new(0.0, 0.0, 400.0, 200.0))),
+//!     ));
+//!     commands.spawn((SpriteBundle::default(), PrivacyMasked::blur(16)));
+//! }
+//! ```
+
+use crate::{Capture, CaptureSource};
+use bevy::{
+    prelude::*,
+    render::{
+        camera::CameraUpdateSystem, primitives::Aabb, render_resource::TextureFormat,
+        texture::TextureFormatPixelInfo, view::VisibilitySystems,
+    },
+    transform::TransformSystem,
+};
+
+/// A Bevy plugin that tracks the screen bounds of [`PrivacyMasked`] entities in every capture.
+pub struct PrivacyMaskPlugin;
+
+impl Plugin for PrivacyMaskPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            track_masked_entities
+                .after(TransformSystem::TransformPropagate)
+                .after(CameraUpdateSystem)
+                .after(VisibilitySystems::CalculateBounds),
+        );
+    }
+}
+
+/// How a region is masked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MaskStyle {
+    /// The region is filled with black.
+    #[default]
+    Blackout,
+    /// The region is blurred with the given radius in pixels.
+    Blur(u32),
+}
+
+/// A rectangle of a captured frame that is masked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaskRegion {
+    /// The rectangle in pixels of the captured frame, the origin is the top left corner.
+    pub rect: Rect,
+    /// How the region is masked.
+    pub style: MaskStyle,
+}
+
+impl MaskRegion {
+    /// Creates a region that is filled with black.
+    pub fn blackout(rect: Rect) -> Self {
+        Self {
+            rect,
+            style: MaskStyle::Blackout,
+        }
+    }
+
+    /// Creates a region that is blurred with the given radius in pixels.
+    pub fn blur(rect: Rect, radius: u32) -> Self {
+        Self {
+            rect,
+            style: MaskStyle::Blur(radius),
+        }
+    }
+}
+
+/// The regions masked in the frames of a capture. This is optional and can be attached next to
+/// the [`Capture`].
+#[derive(Debug, Default, Clone, PartialEq, Component)]
+pub struct CaptureMask {
+    regions: Vec<MaskRegion>,
+    tracked: Vec<MaskRegion>,
+}
+
+impl CaptureMask {
+    /// Adds a fixed region.
+    pub fn with_region(mut self, region: MaskRegion) -> Self {
+        self.regions.push(region);
+        self
+    }
+
+    /// Returns the fixed regions.
+    pub fn regions(&self) -> &[MaskRegion] {
+        &self.regions
+    }
+
+    /// Returns the regions of the [`PrivacyMasked`] entities in the last frame.
+    pub fn tracked_regions(&self) -> &[MaskRegion] {
+        &self.tracked
+    }
+
+    /// Returns all regions that are masked.
+    pub(crate) fn all_regions(&self) -> impl Iterator<Item = &MaskRegion> {
+        self.regions.iter().chain(&self.tracked)
+    }
+}
+
+/// Marks an entity whose screen bounds are masked in all captures, see [`PrivacyMaskPlugin`].
+/// The bounds are computed from the [`Aabb`] of the entity, e.g. of a mesh or a sprite.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Component)]
+pub struct PrivacyMasked {
+    style: MaskStyle,
+    padding: f32,
+}
+
+impl PrivacyMasked {
+    /// Fills the bounds of the entity with black.
+    pub fn blackout() -> Self {
+        Self::default()
+    }
+
+    /// Blurs the bounds of the entity with the given radius in pixels.
+    pub fn blur(radius: u32) -> Self {
+        Self {
+            style: MaskStyle::Blur(radius),
+            ..default()
+        }
+    }
+
+    /// Grows the masked bounds by the given number of pixels on every side.
+    pub fn with_padding(mut self, padding: f32) -> Self {
+        self.padding = padding;
+        self
+    }
+}
+
+fn track_masked_entities(
+    mut commands: Commands,
+    mut captures: Query<(Entity, &Capture, &CaptureSource, Option<&mut CaptureMask>)>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    masked: Query<(&PrivacyMasked, &Aabb, &GlobalTransform)>,
+) {
+    for (entity, capture, source, mask) in &mut captures {
+        if !capture.is_capturing() {
+            continue;
+        }
+        let camera_entity = match source {
+            CaptureSource::ThisCamera => entity,
+            CaptureSource::Camera(entity) => *entity,
+        };
+        let Ok((camera, camera_transform)) = cameras.get(camera_entity) else {
+            continue;
+        };
+
+        let tracked = masked
+            .iter()
+            .filter_map(|(masked, aabb, transform)| {
+                let rect = screen_bounds(camera, camera_transform, aabb, transform)?;
+                Some(MaskRegion {
+                    rect: rect.inflate(masked.padding),
+                    style: masked.style,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Only changed masks are written, so change detection stays meaningful.
+        match mask {
+            Some(mut mask) if mask.tracked != tracked => mask.tracked = tracked,
+            None if !tracked.is_empty() => {
+                commands.entity(entity).insert(CaptureMask {
+                    regions: Vec::new(),
+                    tracked,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Projects the corners of the bounding box to the viewport of the camera. Corners behind the
+/// camera are ignored.
+fn screen_bounds(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    aabb: &Aabb,
+    transform: &GlobalTransform,
+) -> Option<Rect> {
+    let (center, half_extents) = (Vec3::from(aabb.center), Vec3::from(aabb.half_extents));
+    (0..8)
+        .filter_map(|i| {
+            let corner = Vec3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+            );
+            let world = transform.transform_point(center + corner * half_extents);
+            camera.world_to_viewport(camera_transform, world)
+        })
+        .fold(None, |rect: Option<Rect>, point| {
+            Some(match rect {
+                Some(rect) => rect.union_point(point),
+                None => Rect::from_corners(point, point),
+            })
+        })
+}
+
+/// Masks the regions of the image in place. Images with 8 bits per channel are blurred, for other
+/// formats blurred regions are blacked out instead.
+pub(crate) fn apply<'a>(image: &mut Image, regions: impl IntoIterator<Item = &'a MaskRegion>) {
+    let format = image.texture_descriptor.format;
+    let rgba8 = matches!(
+        format,
+        TextureFormat::Rgba8Unorm
+            | TextureFormat::Rgba8UnormSrgb
+            | TextureFormat::Bgra8Unorm
+            | TextureFormat::Bgra8UnormSrgb
+    );
+    let pixel_size = format.pixel_size();
+    let (width, height) = (image.width(), image.height());
+
+    for region in regions {
+        let rect = region.rect;
+        let x0 = (rect.min.x.floor().max(0.0) as u32).min(width) as usize;
+        let y0 = (rect.min.y.floor().max(0.0) as u32).min(height) as usize;
+        let x1 = (rect.max.x.ceil().max(0.0) as u32).min(width) as usize;
+        let y1 = (rect.max.y.ceil().max(0.0) as u32).min(height) as usize;
+        if x0 >= x1 || y0 >= y1 {
+            continue;
+        }
+
+        let row_bytes = width as usize * pixel_size;
+        match region.style {
+            MaskStyle::Blur(radius) if rgba8 && radius > 0 => {
+                blur(
+                    &mut image.data,
+                    row_bytes,
+                    (x0, y0, x1, y1),
+                    radius as usize,
+                );
+            }
+            _ => {
+                for y in y0..y1 {
+                    let row = &mut image.data[y * row_bytes..][x0 * pixel_size..x1 * pixel_size];
+                    if rgba8 {
+                        for pixel in row.chunks_exact_mut(4) {
+                            pixel.copy_from_slice(&[0, 0, 0, 255]);
+                        }
+                    } else {
+                        row.fill(0);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Blurs a rectangle of 4 byte pixels with three passes of a box blur in both directions, which
+/// approximates a gaussian blur.
+fn blur(
+    data: &mut [u8],
+    row_bytes: usize,
+    (x0, y0, x1, y1): (usize, usize, usize, usize),
+    radius: usize,
+) {
+    let (width, height) = (x1 - x0, y1 - y0);
+    let mut pixels = (y0..y1)
+        .flat_map(|y| {
+            data[y * row_bytes + x0 * 4..y * row_bytes + x1 * 4]
+                .iter()
+                .map(|&v| v as u32)
+        })
+        .collect::<Vec<_>>();
+
+    let mut sums = Vec::new();
+    for _ in 0..3 {
+        for y in 0..height {
+            box_blur(&mut pixels, &mut sums, y * width, 1, width, radius);
+        }
+        for x in 0..width {
+            box_blur(&mut pixels, &mut sums, x, width, height, radius);
+        }
+    }
+
+    for (y, row) in pixels.chunks_exact(width * 4).enumerate() {
+        let target = &mut data[(y0 + y) * row_bytes + x0 * 4..][..width * 4];
+        for (target, &value) in target.iter_mut().zip(row) {
+            *target = value as u8;
+        }
+    }
+}
+
+/// Blurs a line of `len` pixels, starting at pixel `start` with a distance of `stride` pixels.
+fn box_blur(
+    pixels: &mut [u32],
+    sums: &mut Vec<u32>,
+    start: usize,
+    stride: usize,
+    len: usize,
+    radius: usize,
+) {
+    // Prefix sums of the channels, so every pixel is averaged in constant time.
+    sums.clear();
+    sums.extend([0; 4]);
+    for i in 0..len {
+        let offset = (start + i * stride) * 4;
+        for channel in 0..4 {
+            sums.push(sums[i * 4 + channel] + pixels[offset + channel]);
+        }
+    }
+
+    for i in 0..len {
+        let (from, to) = (i.saturating_sub(radius), (i + radius + 1).min(len));
+        let offset = (start + i * stride) * 4;
+        for channel in 0..4 {
+            let sum = sums[to * 4 + channel] - sums[from * 4 + channel];
+            pixels[offset + channel] = sum / (to - from) as u32;
+        }
+    }
+}
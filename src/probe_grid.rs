@@ -0,0 +1,222 @@
+//! Bake a grid of light probes, i.e. small environment maps captured at regular positions.
+//!
+//! Every probe is captured with the [cubemap capture](crate::cubemap) and saved as
+//! `probe_{x}_{y}_{z}.ktx2` into the output directory. A `probes.json` placement file is written
+//! next to them once all probes are done (requires the `probe_grid` feature):
+//!
+//! ```json
+//! {
+//!   "size": 32,
+//!   "counts": [2, 1, 2],
+//!   "probes": [
+//!     { "index": [0, 0, 0], "position": [-4.0, 1.0, -4.0], "file": "probe_0_0_0.ktx2" },
+//!     ...
+//!   ]
+//! }
+//! ```
+
+use crate::cubemap::{CaptureCubemap, CubemapCapturePlugin, CubemapCaptured};
+use bevy::{prelude::*, utils::HashMap};
+use serde_json::json;
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The default size of a probe face in pixels.
+pub const DEFAULT_PROBE_SIZE: u32 = 32;
+
+/// The default number of probes captured at the same time.
+pub const DEFAULT_BATCH_SIZE: usize = 8;
+
+/// A Bevy plugin for baking probe grids. This adds the [`CubemapCapturePlugin`] if necessary.
+pub struct ProbeGridPlugin;
+
+impl Plugin for ProbeGridPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<CubemapCapturePlugin>() {
+            app.add_plugins(CubemapCapturePlugin);
+        }
+
+        app.init_resource::<PendingGrids>()
+            .add_event::<BakeProbeGrid>()
+            .add_event::<ProbeGridBaked>()
+            .add_systems(Update, update_grids);
+    }
+}
+
+/// An event to bake a grid of probes within the given bounds.
+#[derive(Debug, Clone, Event)]
+pub struct BakeProbeGrid {
+    min: Vec3,
+    max: Vec3,
+    counts: UVec3,
+    size: u32,
+    batch_size: usize,
+    directory: PathBuf,
+}
+
+impl BakeProbeGrid {
+    /// Creates a new probe grid with the given number of probes along each axis, evenly spread
+    /// from `min` to `max` (inclusive). An axis with a single probe is placed in the center.
+    pub fn new(min: Vec3, max: Vec3, counts: UVec3, directory: impl Into<PathBuf>) -> Self {
+        Self {
+            min,
+            max,
+            counts: counts.max(UVec3::ONE),
+            size: DEFAULT_PROBE_SIZE,
+            batch_size: DEFAULT_BATCH_SIZE,
+            directory: directory.into(),
+        }
+    }
+
+    /// Sets the size of a probe face in pixels.
+    pub fn with_size(mut self, size: u32) -> Self {
+        self.size = size.max(1);
+        self
+    }
+
+    /// Sets the number of probes captured at the same time. Every probe renders six cameras.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    fn probes(&self) -> Vec<Probe> {
+        let position = |i: u32, count: u32, min: f32, max: f32| {
+            if count == 1 {
+                (min + max) / 2.0
+            } else {
+                min + (max - min) * i as f32 / (count - 1) as f32
+            }
+        };
+
+        let mut probes = Vec::new();
+        for z in 0..self.counts.z {
+            for y in 0..self.counts.y {
+                for x in 0..self.counts.x {
+                    probes.push(Probe {
+                        index: UVec3::new(x, y, z),
+                        position: Vec3::new(
+                            position(x, self.counts.x, self.min.x, self.max.x),
+                            position(y, self.counts.y, self.min.y, self.max.y),
+                            position(z, self.counts.z, self.min.z, self.max.z),
+                        ),
+                        file: format!("probe_{x}_{y}_{z}.ktx2"),
+                    });
+                }
+            }
+        }
+        probes
+    }
+}
+
+/// An event sent when all probes of a grid are done.
+#[derive(Debug, Clone, Event)]
+pub struct ProbeGridBaked {
+    /// The directory the probes were saved to.
+    pub directory: PathBuf,
+    /// The path of the placement file, or `None` if a probe or the placement file failed.
+    pub placement: Option<PathBuf>,
+}
+
+#[derive(Default, Resource)]
+struct PendingGrids(Vec<PendingGrid>);
+
+struct PendingGrid {
+    request: BakeProbeGrid,
+    probes: Vec<Probe>,
+    queue: VecDeque<usize>,
+    in_flight: HashMap<PathBuf, usize>,
+    failed: bool,
+}
+
+struct Probe {
+    index: UVec3,
+    position: Vec3,
+    file: String,
+}
+
+fn update_grids(
+    mut grids: ResMut<PendingGrids>,
+    mut events: EventReader<BakeProbeGrid>,
+    mut captured: EventReader<CubemapCaptured>,
+    mut cubemaps: EventWriter<CaptureCubemap>,
+    mut baked: EventWriter<ProbeGridBaked>,
+) {
+    for request in events.read() {
+        let probes = request.probes();
+        grids.0.push(PendingGrid {
+            request: request.clone(),
+            queue: (0..probes.len()).collect(),
+            probes,
+            in_flight: HashMap::default(),
+            failed: false,
+        });
+    }
+
+    for event in captured.read() {
+        for grid in &mut grids.0 {
+            if grid.in_flight.remove(&event.output).is_some() {
+                grid.failed |= !event.success;
+            }
+        }
+    }
+
+    grids.0.retain_mut(|grid| {
+        while grid.in_flight.len() < grid.request.batch_size {
+            let Some(probe) = grid.queue.pop_front() else {
+                break;
+            };
+            let output = grid.request.directory.join(&grid.probes[probe].file);
+            cubemaps.send(
+                CaptureCubemap::new(grid.probes[probe].position, &output)
+                    .with_size(grid.request.size),
+            );
+            grid.in_flight.insert(output, probe);
+        }
+
+        if !grid.in_flight.is_empty() {
+            return true;
+        }
+
+        let placement = grid.request.directory.join("probes.json");
+        let placement = match (grid.failed, write_placement(&placement, grid)) {
+            (false, Ok(())) => Some(placement),
+            (true, _) => None,
+            (false, Err(err)) => {
+                bevy::log::error!("Failed to write probe placement {:?}: {:?}", placement, err);
+                None
+            }
+        };
+        baked.send(ProbeGridBaked {
+            directory: grid.request.directory.clone(),
+            placement,
+        });
+
+        false
+    });
+}
+
+fn write_placement(path: &Path, grid: &PendingGrid) -> std::io::Result<()> {
+    let probes = grid
+        .probes
+        .iter()
+        .map(|probe| {
+            json!({
+                "index": probe.index.to_array(),
+                "position": probe.position.to_array(),
+                "file": probe.file,
+            })
+        })
+        .collect::<Vec<_>>();
+    let placement = json!({
+        "size": grid.request.size,
+        "counts": grid.request.counts.to_array(),
+        "probes": probes,
+    });
+
+    fs::create_dir_all(&grid.request.directory)?;
+    fs::write(path, serde_json::to_string_pretty(&placement)?)
+}
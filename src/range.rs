@@ -0,0 +1,144 @@
+//! Capture only the frames in a range, e.g. to re-render a damaged segment of a long offline
+//! render and splice it into the original.
+//!
+//! Attach a [`CaptureRange`] next to the [`Capture`]. The app runs as usual from the start of the
+//! capture, so deterministic simulations reach the same state, but only the frames in the range are
+//! passed to the encoders. The range is either a range of frame indices, counted from the frame the
+//! capture was started in, or a range of game time (`Time<Virtual>`) since the start. The capture
+//! is stopped after the last frame of the range.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{range::CaptureRange, CaptureBundle};
+//! #
+//! // Re-render frames 1200 to 1499 of the sequence.
+//! commands.spawn((
+//!     Camera2dBundle::default(),
+//!     CaptureBundle::default(),
+//!     CaptureRange::frames(1200..1500),
+//! ));
+//! ```
+
+use crate::Capture;
+use bevy::{prelude::*, utils::Duration};
+use std::{ops::Range, time::Instant};
+
+/// The frames of a capture passed to the encoders. This is optional and can be attached next to
+/// the [`Capture`], see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Component)]
+pub struct CaptureRange {
+    bounds: RangeBounds,
+    stop_at_end: bool,
+    position: Option<RangePosition>,
+}
+
+/// The bounds of a [`CaptureRange`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeBounds {
+    /// A range of frame indices, `0` is the frame the capture was started in.
+    Frames(Range<u64>),
+    /// A range of game time (`Time<Virtual>`) since the capture was started.
+    Time(Range<Duration>),
+}
+
+/// The position of the running capture in the range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RangePosition {
+    started_at: Instant,
+    frame: u64,
+    start_time: Duration,
+    in_range: bool,
+}
+
+impl CaptureRange {
+    /// Creates a range of frame indices, `0` is the frame the capture was started in.
+    pub fn frames(frames: Range<u64>) -> Self {
+        Self::new(RangeBounds::Frames(frames))
+    }
+
+    /// Creates a range of game time (`Time<Virtual>`) since the capture was started.
+    pub fn time(time: Range<Duration>) -> Self {
+        Self::new(RangeBounds::Time(time))
+    }
+
+    fn new(bounds: RangeBounds) -> Self {
+        Self {
+            bounds,
+            stop_at_end: true,
+            position: None,
+        }
+    }
+
+    /// Sets whether the capture is stopped after the last frame of the range. Defaults to `true`.
+    pub fn with_stop_at_end(mut self, stop_at_end: bool) -> Self {
+        self.stop_at_end = stop_at_end;
+        self
+    }
+
+    /// Returns the bounds of the range.
+    pub fn bounds(&self) -> &RangeBounds {
+        &self.bounds
+    }
+
+    /// Returns the index of the current frame of the running capture, or `None` if the capture is
+    /// not capturing.
+    pub fn frame(&self) -> Option<u64> {
+        self.position.map(|position| position.frame)
+    }
+
+    /// Returns `true` if the current frame is passed to the encoders.
+    pub fn is_in_range(&self) -> bool {
+        self.position.is_some_and(|position| position.in_range)
+    }
+}
+
+pub(crate) fn update_capture_ranges(
+    mut captures: Query<(&mut Capture, &mut CaptureRange)>,
+    time: Option<Res<Time<Virtual>>>,
+) {
+    let elapsed = time.map(|time| time.elapsed()).unwrap_or_default();
+    for (mut capture, mut range) in &mut captures {
+        let Some(started_at) = capture.started_at() else {
+            if range.position.is_some() {
+                range.position = None;
+            }
+            continue;
+        };
+
+        let position = match range.position {
+            Some(position) if position.started_at == started_at => RangePosition {
+                frame: position.frame + 1,
+                ..position
+            },
+            // The capture was (re)started in this frame.
+            _ => RangePosition {
+                started_at,
+                frame: 0,
+                start_time: elapsed,
+                in_range: false,
+            },
+        };
+        let (in_range, past_end) = match &range.bounds {
+            RangeBounds::Frames(frames) => (
+                frames.contains(&position.frame),
+                position.frame >= frames.end,
+            ),
+            RangeBounds::Time(time) => {
+                let time_since_start = elapsed.saturating_sub(position.start_time);
+                (
+                    time.contains(&time_since_start),
+                    time_since_start >= time.end,
+                )
+            }
+        };
+        range.position = Some(RangePosition {
+            in_range,
+            ..position
+        });
+
+        if past_end && range.stop_at_end {
+            capture.stop();
+        }
+    }
+}
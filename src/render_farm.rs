@@ -0,0 +1,254 @@
+//! Orchestrate capture jobs with existing render farm schedulers, e.g. Deadline, without custom
+//! glue.
+//!
+//! A [`JobManifest`] describes a [`RenderJob`] in a machine-readable JSON file: the frames, the
+//! command line and the expected outputs of every task, so a scheduler can submit one task per
+//! slice and check the outputs afterwards. The commands pass the frames with the
+//! `--frame-range <first>-<last>` and `--slice <index>/<count>` arguments, which the app reads with
+//! [`slice_from_args`].
+//!
+//! ```json
+//! {
+//!   "name": "intro",
+//!   "frames": { "first": 0, "last": 5999 },
+//!   "outputs": ["captures/intro.mp4"],
+//!   "tasks": [
+//!     {
+//!       "index": 0,
+//!       "frames": { "first": 0, "last": 749 },
+//!       "command": ["./game", "--render", "--frame-range", "0-749", "--slice", "0/8"],
+//!       "outputs": ["captures/intro.slice_000.mp4"]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Frame ranges are inclusive, like the frame lists of most schedulers, so
+//! `--frame-range <STARTFRAME>-<ENDFRAME>` can be used as it is in a scheduler's command line.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{distributed::RenderJob, render_farm::*, CaptureBundle};
+//! #
+//! // When submitting the job.
+//! JobManifest::new("intro", RenderJob::new(0..6000, 8))
+//!     .with_args(["--render"])
+//!     .with_output("captures/intro.mp4")
+//!     .write("intro.json")?;
+//!
+//! // In the app, capture the frames of the task.
+//! if let Some(slice) = slice_from_args(std::env::args())? {
+//!     commands.spawn((
+//!         Camera2dBundle::default(),
+//!         CaptureBundle::default(),
+//!         slice.capture_range(),
+//!     ));
+//! }
+//! ```
+
+use crate::{
+    distributed::{RenderJob, RenderSlice},
+    encoder::{Error, Result},
+};
+use serde_json::{json, Value};
+use std::{
+    env, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// The argument with the inclusive range of frames of a task, e.g. `--frame-range 0-749`.
+pub const FRAME_RANGE_ARG: &str = "--frame-range";
+
+/// The argument with the index and the number of slices of a task, e.g. `--slice 0/8`.
+pub const SLICE_ARG: &str = "--slice";
+
+/// A machine-readable description of a [`RenderJob`] for render farm schedulers, see the
+/// [module docs](self).
+#[derive(Debug, Clone)]
+pub struct JobManifest {
+    name: String,
+    job: RenderJob,
+    program: Option<PathBuf>,
+    args: Vec<String>,
+    outputs: Vec<PathBuf>,
+}
+
+impl JobManifest {
+    /// Creates a manifest of the job with the given name. The tasks run the current executable by
+    /// default.
+    pub fn new(name: impl Into<String>, job: RenderJob) -> Self {
+        Self {
+            name: name.into(),
+            job,
+            program: None,
+            args: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Sets the program the tasks run, e.g. the path of the game on the render nodes.
+    pub fn with_program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = Some(program.into());
+        self
+    }
+
+    /// Adds arguments that are passed to every task before the frames, e.g. a flag that starts
+    /// the app in render mode.
+    pub fn with_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds an output of the job. Every task writes it to the
+    /// [output path of its slice](RenderSlice::output_path).
+    pub fn with_output(mut self, output: impl Into<PathBuf>) -> Self {
+        self.outputs.push(output.into());
+        self
+    }
+
+    /// Returns the name of the job.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the job.
+    pub fn job(&self) -> &RenderJob {
+        &self.job
+    }
+
+    /// Returns the command line of the task that renders the slice, starting with the program.
+    pub fn command(&self, slice: &RenderSlice) -> Result<Vec<String>> {
+        let program = match &self.program {
+            Some(program) => program.clone(),
+            None => env::current_exe()?,
+        };
+        let frames = slice.frames();
+        let mut command = vec![program.to_string_lossy().into_owned()];
+        command.extend(self.args.iter().cloned());
+        command.extend([
+            FRAME_RANGE_ARG.to_owned(),
+            format!("{}-{}", frames.start, frames.end - 1),
+            SLICE_ARG.to_owned(),
+            format!("{}/{}", slice.index(), slice.count()),
+        ]);
+        Ok(command)
+    }
+
+    /// Returns the manifest as JSON. Slices without frames are omitted.
+    pub fn to_json(&self) -> Result<Value> {
+        let tasks = self
+            .job
+            .slices()
+            .into_iter()
+            .filter(|slice| !slice.frames().is_empty())
+            .map(|slice| {
+                Ok(json!({
+                    "index": slice.index(),
+                    "frames": frames_json(slice.frames()),
+                    "command": self.command(&slice)?,
+                    "outputs": self
+                        .outputs
+                        .iter()
+                        .map(|output| path_json(&slice.output_path(output)))
+                        .collect::<Vec<_>>(),
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(json!({
+            "name": self.name,
+            "frames": frames_json(self.job.frames()),
+            "outputs": self.outputs.iter().map(|output| path_json(output)).collect::<Vec<_>>(),
+            "tasks": tasks,
+        }))
+    }
+
+    /// Writes the manifest as JSON to the file at the given path.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.to_json()?).map_err(Error::custom)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+fn frames_json(frames: Range<u64>) -> Value {
+    json!({ "first": frames.start, "last": frames.end.saturating_sub(1) })
+}
+
+fn path_json(path: &Path) -> Value {
+    Value::String(path.to_string_lossy().into_owned())
+}
+
+/// Reads the slice of this task from the command line arguments, e.g. [`std::env::args`].
+///
+/// The frames are read from `--frame-range <range>` or `--frame-range=<range>`, see
+/// [`parse_frame_range`], and the slice from `--slice <index>/<count>`. Without `--slice`, the task
+/// is the only slice of its job. Other arguments are ignored. Returns `None` if there is no
+/// `--frame-range`.
+pub fn slice_from_args(
+    args: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<Option<RenderSlice>> {
+    let mut frames = None;
+    let mut slice = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.as_ref();
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (arg, None),
+        };
+        if name != FRAME_RANGE_ARG && name != SLICE_ARG {
+            continue;
+        }
+        let value = match value {
+            Some(value) => value,
+            None => args
+                .next()
+                .map(|value| value.as_ref().to_owned())
+                .ok_or_else(|| format!("{name} has no value"))?,
+        };
+        match name {
+            FRAME_RANGE_ARG => frames = Some(parse_frame_range(&value)?),
+            _ => slice = Some(parse_slice(&value)?),
+        }
+    }
+
+    let Some(frames) = frames else {
+        return Ok(None);
+    };
+    let (index, count) = slice.unwrap_or((0, 1));
+    Ok(Some(RenderSlice::new(index, count, frames)))
+}
+
+/// Parses a range of frames: `<first>-<last>` and `<first>:<last>` are inclusive, like the frame
+/// lists of render farm schedulers, `<start>..<end>` is exclusive and `<frame>` is a single frame.
+pub fn parse_frame_range(range: &str) -> Result<Range<u64>> {
+    let invalid = || Error::Other(format!("invalid frame range: {range}"));
+    let parse = |frame: &str| frame.trim().parse::<u64>().map_err(|_| invalid());
+
+    let frames = if let Some((start, end)) = range.split_once("..") {
+        parse(start)?..parse(end)?
+    } else if let Some((first, last)) = range.split_once(['-', ':']) {
+        parse(first)?..parse(last)? + 1
+    } else {
+        let frame = parse(range)?;
+        frame..frame + 1
+    };
+    match frames.is_empty() {
+        true => Err(invalid()),
+        false => Ok(frames),
+    }
+}
+
+fn parse_slice(slice: &str) -> Result<(u32, u32)> {
+    let invalid = || Error::Other(format!("invalid slice: {slice}"));
+    let (index, count) = slice.split_once('/').ok_or_else(invalid)?;
+    let index = index.trim().parse::<u32>().map_err(|_| invalid())?;
+    let count = count.trim().parse::<u32>().map_err(|_| invalid())?;
+    match index < count {
+        true => Ok((index, count)),
+        false => Err(invalid()),
+    }
+}
@@ -1,147 +1,636 @@
-use crate::*;
+use crate::{
+    channel::{CaptureChannel, ChannelPipelines, ChannelState},
+    isolation::EncoderIsolation,
+    labels::{self, CaptureLabels},
+    live_settings::LiveEncoderSettings,
+    memory::CaptureMemoryBudget,
+    metadata::{FrameMetadata, FRAME_INDEX_KEY},
+    preview::CapturePreview,
+    privacy::{self, CaptureMask, MaskRegion},
+    range::CaptureRange,
+    scene_change::{ChangePipeline, ChangeState, GpuChangeTrigger},
+    worker::Workers,
+    *,
+};
 use bevy::{
+    app::InternedAppLabel,
+    ecs::system::SystemParam,
     prelude::*,
     render::{
+        diagnostic::RecordDiagnostics,
         graph::CameraDriverLabel,
         render_asset::RenderAssets,
-        render_graph::{self, NodeRunError, RenderGraph, RenderGraphContext, RenderLabel},
+        render_graph::{
+            self, InternedRenderLabel, NodeRunError, RenderGraph, RenderGraphContext, RenderLabel,
+        },
         render_resource::{
-            Buffer, BufferDescriptor, BufferUsages, ImageCopyBuffer, ImageDataLayout, Maintain,
-            MapMode,
+            Buffer, BufferDescriptor, BufferUsages, CommandEncoder, CommandEncoderDescriptor,
+            ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, Maintain, MapMode, Origin3d,
+            Texture, TextureAspect,
         },
-        renderer::{RenderContext, RenderDevice},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
         texture::{GpuImage, TextureFormatPixelInfo},
-        Extract, Render, RenderApp, RenderSet,
+        Extract, Render, RenderSet,
     },
     utils::EntityHashMap,
 };
+use std::mem;
 
-pub struct CaptureRenderWorldPlugin;
+pub struct CaptureRenderWorldPlugin {
+    pub readback: ReadbackMode,
+    pub worker_settings: Option<CaptureWorkerSettings>,
+    pub graph_node: InternedRenderLabel,
+    pub render_app: InternedAppLabel,
+}
 
 impl Plugin for CaptureRenderWorldPlugin {
     fn build(&self, app: &mut App) {
-        let render_app = app.sub_app_mut(RenderApp);
+        // Dedicated servers and other apps without rendering have no render app. The capture
+        // components still work, they just never receive frames.
+        let Some(render_app) = app.get_sub_app_mut(self.render_app) else {
+            warn!(
+                "No {:?} sub-app, capturing is disabled. Add the CapturePlugin after the \
+                 RenderPlugin to capture frames.",
+                self.render_app
+            );
+            return;
+        };
+        if !render_app.world().contains_resource::<RenderGraph>() {
+            warn!(
+                "The {:?} sub-app has no render graph, capturing is disabled.",
+                self.render_app
+            );
+            return;
+        }
 
         render_app
+            .insert_resource(RenderCaptureConfig {
+                readback: self.readback,
+                worker_settings: self.worker_settings.clone(),
+            })
             .init_resource::<Captures>()
-            .add_systems(ExtractSchedule, extract_captures);
+            .init_resource::<PreviewSlot>()
+            .init_resource::<MemoryBudgetSlot>()
+            .init_resource::<FrameMetadata>()
+            .add_systems(
+                ExtractSchedule,
+                (
+                    extract_captures,
+                    extract_preview,
+                    extract_memory_budget,
+                    extract_metadata,
+                ),
+            );
 
         let mut graph = render_app.world_mut().resource_mut::<RenderGraph>();
         graph.add_node(ImageCopy, ImageCopyDriver);
-        graph.add_node_edge(CameraDriverLabel, ImageCopy);
 
         render_app.add_systems(Render, encode.after(RenderSet::Render));
     }
+
+    fn finish(&self, app: &mut App) {
+        // The node is connected once all plugins are built, so it can run after nodes of plugins
+        // that were added after this one.
+        let Some(render_app) = app.get_sub_app_mut(self.render_app) else {
+            return;
+        };
+        let Some(mut graph) = render_app.world_mut().get_resource_mut::<RenderGraph>() else {
+            return;
+        };
+        if let Err(err) = graph.try_add_node_edge(self.graph_node, ImageCopy) {
+            warn!(
+                "Failed to run the capture after {:?}, running it after the camera driver: {}",
+                self.graph_node, err
+            );
+            graph.add_node_edge(CameraDriverLabel, ImageCopy);
+        }
+    }
+}
+
+/// The configuration of the [`CapturePlugin`].
+#[derive(Resource)]
+struct RenderCaptureConfig {
+    readback: ReadbackMode,
+    worker_settings: Option<CaptureWorkerSettings>,
 }
 
 #[derive(Default, Resource)]
 struct Captures {
     captures: EntityHashMap<Entity, ExtractedCapture>,
+    /// Created once the first capture with a [`GpuChangeTrigger`] is extracted.
+    change_pipeline: Option<ChangePipeline>,
+    /// Created once the first capture with a [`CaptureChannel`] is extracted.
+    channel_pipelines: Option<ChannelPipelines>,
 }
 
+/// The slot the most recent frame is written to, if the [`CapturePreview`] is enabled.
+#[derive(Default, Resource)]
+struct PreviewSlot(Option<Arc<Mutex<Option<Image>>>>);
+
+/// The [`CaptureMemoryBudget`] of the main world, if any.
+#[derive(Default, Resource)]
+struct MemoryBudgetSlot(Option<CaptureMemoryBudget>);
+
 struct ExtractedCapture {
+    // Dropped before the encoders, so the capture only finishes once the workers finished.
+    workers: Option<Workers>,
     encoders: Encoders,
+    // Dropped after the encoders, so it can report once they finished.
+    log: CaptureLog,
+    masks: Vec<MaskRegion>,
+    labels: Option<CaptureLabels>,
+    metadata: Option<FrameMetadata>,
+    live_settings: Option<LiveEncoderSettings>,
+    change_trigger: Option<GpuChangeTrigger>,
     paused: bool,
+    stats: Arc<CaptureStats>,
     state: Option<ExtractedCaptureState>,
 }
 
 struct ExtractedCaptureState {
     source: Handle<Image>,
-    target_buffer: Buffer,
+    settings: CaptureBufferSettings,
+    channel_config: Option<CaptureChannel>,
+    tiles: Vec<CopyTile>,
     target_image: Image,
+    /// The staging buffers of the tiles the frame is copied into.
+    write: usize,
+    /// The frame that is read back asynchronously, see [`ReadbackMode::Async`].
+    pending: Option<PendingReadback>,
+    /// The comparison with the previous frame, if the capture has a [`GpuChangeTrigger`].
+    change: Option<ChangeState>,
+    /// The texture the channel is extracted into, if the capture has a [`CaptureChannel`].
+    channel: Option<ChannelState>,
+}
+
+/// A region of the source that is copied into its own staging buffers, one per frame in flight.
+struct CopyTile {
+    origin: UVec2,
+    size: UVec2,
+    padded_bytes_per_row: usize,
+    buffers: Vec<Buffer>,
+}
+
+struct PendingReadback {
+    buffers: usize,
+    mapped: crossbeam_channel::Receiver<()>,
+    metadata: FrameMetadata,
 }
 
 impl ExtractedCaptureState {
-    fn init(source: Handle<Image>, images: &Assets<Image>, render_device: &RenderDevice) -> Self {
+    fn init(
+        source: Handle<Image>,
+        settings: CaptureBufferSettings,
+        channel_config: Option<CaptureChannel>,
+        readback: ReadbackMode,
+        images: &Assets<Image>,
+        render_device: &RenderDevice,
+    ) -> Self {
         let source_image = images.get(&source).unwrap();
         let size = source_image.texture_descriptor.size;
-
-        let padded_bytes_per_row =
-            RenderDevice::align_copy_bytes_per_row((size.width) as usize) * 4;
-        let target_buffer = render_device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: padded_bytes_per_row as u64 * size.height as u64,
-            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
+        let channel = channel_config.and_then(|config| {
+            ChannelState::new(
+                render_device,
+                source_image.size(),
+                source_image.texture_descriptor.format,
+                config,
+            )
         });
+        // Only the channel is copied, if it can be extracted.
+        let format = match &channel {
+            Some(channel) => channel.texture().format(),
+            None => source_image.texture_descriptor.format,
+        };
+        let pixel_size = format.pixel_size();
+
+        let row_alignment = settings.row_alignment() as usize;
+        let max_buffer_size = settings
+            .max_buffer_size()
+            .unwrap_or(u64::MAX)
+            .min(render_device.limits().max_buffer_size);
+
+        // The frame is split into tiles if it doesn't fit into a single staging buffer: into
+        // chunks of rows first, and into columns as well if a single row doesn't fit.
+        let max_row_bytes =
+            (max_buffer_size as usize / row_alignment * row_alignment).max(row_alignment);
+        let tile_width = (max_row_bytes / pixel_size).clamp(1, size.width.max(1) as usize) as u32;
+
+        // Rows in the staging buffers must be aligned to wgpu::COPY_BYTES_PER_ROW_ALIGNMENT, so
+        // they can be a little bit wider than the tile. This is taken into account when copying
+        // from the buffers to the image.
+        let padded_bytes_per_row =
+            (tile_width as usize * pixel_size).next_multiple_of(row_alignment);
+        let tile_height = (max_buffer_size / padded_bytes_per_row as u64)
+            .min(settings.max_rows_per_copy().unwrap_or(u32::MAX) as u64)
+            .clamp(1, size.height.max(1) as u64) as u32;
+
+        let mut tiles = Vec::new();
+        for y in (0..size.height).step_by(tile_height as usize) {
+            for x in (0..size.width).step_by(tile_width as usize) {
+                let tile_size = UVec2::new(
+                    tile_width.min(size.width - x),
+                    tile_height.min(size.height - y),
+                );
+                let frames_in_flight = match readback {
+                    ReadbackMode::Sync => 1,
+                    ReadbackMode::Async => 2,
+                };
+                let buffers = (0..frames_in_flight)
+                    .map(|_| {
+                        render_device.create_buffer(&BufferDescriptor {
+                            label: None,
+                            size: padded_bytes_per_row as u64 * tile_size.y as u64,
+                            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        })
+                    })
+                    .collect();
+                tiles.push(CopyTile {
+                    origin: UVec2::new(x, y),
+                    size: tile_size,
+                    padded_bytes_per_row,
+                    buffers,
+                });
+            }
+        }
 
         let target_image = Image::new_fill(
             size,
             TextureDimension::D2,
-            &[0; 4],
-            source_image.texture_descriptor.format,
+            &vec![0; pixel_size],
+            format,
             RenderAssetUsages::default(),
         );
 
         Self {
             source,
-            target_buffer,
+            settings,
+            channel_config,
+            tiles,
             target_image,
+            write: 0,
+            pending: None,
+            change: None,
+            channel,
+        }
+    }
+
+    /// Copies the frame into the staging buffers of this frame, extracting the channel first if
+    /// the capture has a [`CaptureChannel`].
+    fn copy_frame(
+        &self,
+        render_device: &RenderDevice,
+        channel_pipelines: Option<&ChannelPipelines>,
+        encoder: &mut CommandEncoder,
+        source: &Texture,
+    ) {
+        match (&self.channel, channel_pipelines) {
+            (Some(channel), Some(pipelines)) => {
+                channel.record(render_device.wgpu_device(), pipelines, encoder, source);
+                self.copy_tiles(encoder, channel.texture());
+            }
+            _ => self.copy_tiles(encoder, source),
+        }
+    }
+
+    /// Copies the tiles of the source into the staging buffers of this frame.
+    fn copy_tiles(&self, encoder: &mut CommandEncoder, source: &Texture) {
+        for tile in &self.tiles {
+            encoder.copy_texture_to_buffer(
+                ImageCopyTexture {
+                    texture: source,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: tile.origin.x,
+                        y: tile.origin.y,
+                        z: 0,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyBuffer {
+                    buffer: &tile.buffers[self.write],
+                    layout: ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(tile.padded_bytes_per_row as u32),
+                        rows_per_image: None,
+                    },
+                },
+                Extent3d {
+                    width: tile.size.x,
+                    height: tile.size.y,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    /// Maps the staging buffers the frame was copied into. The frame can be read once all
+    /// messages were received.
+    fn map(&mut self) -> crossbeam_channel::Receiver<()> {
+        let (s, r) = crossbeam_channel::bounded(self.tiles.len());
+        for tile in &self.tiles {
+            let s = s.clone();
+            tile.buffers[self.write]
+                .slice(..)
+                .map_async(MapMode::Read, move |r| match r {
+                    Ok(r) => s.send(r).expect("Failed to send map update"),
+                    Err(err) => panic!("Failed to map buffer {err}"),
+                });
+        }
+        r
+    }
+
+    /// Reassembles the image from the mapped staging buffers, removing the padding of the rows.
+    fn read(&mut self, buffers: usize) {
+        #[cfg(feature = "trace")]
+        let _span = info_span!("capture_repack").entered();
+
+        let pixel_size = self.target_image.texture_descriptor.format.pixel_size();
+        let row_bytes = self.target_image.width() as usize * pixel_size;
+        for tile in &self.tiles {
+            let buffer = &tile.buffers[buffers];
+            let buffer_bytes = buffer.slice(..).get_mapped_range();
+            let tile_row_bytes = tile.size.x as usize * pixel_size;
+            for (y, row) in buffer_bytes
+                .chunks(tile.padded_bytes_per_row)
+                .take(tile.size.y as usize)
+                .enumerate()
+            {
+                let offset =
+                    (tile.origin.y as usize + y) * row_bytes + tile.origin.x as usize * pixel_size;
+                self.target_image.data[offset..offset + tile_row_bytes]
+                    .copy_from_slice(&row[..tile_row_bytes]);
+            }
+            drop(buffer_bytes);
+            buffer.unmap();
         }
     }
 }
 
+type CapturesQuery = Query<
+    'static,
+    'static,
+    (
+        Entity,
+        &'static Capture,
+        &'static CaptureSource,
+        Option<&'static CaptureWorkerSettings>,
+        Option<&'static CaptureMask>,
+        Option<&'static CaptureLabels>,
+        Option<&'static LiveEncoderSettings>,
+        Option<&'static CaptureRange>,
+        Option<&'static EncoderIsolation>,
+        Option<&'static GpuChangeTrigger>,
+        Option<&'static CaptureChannel>,
+        Option<&'static FrameMetadata>,
+    ),
+>;
+
+/// The render world resources needed to read back frames.
+#[derive(SystemParam)]
+struct Readback<'w> {
+    config: Res<'w, RenderCaptureConfig>,
+    memory_budget: Res<'w, MemoryBudgetSlot>,
+    preview: Res<'w, PreviewSlot>,
+    render_device: Res<'w, RenderDevice>,
+}
+
 fn extract_captures(
     mut captures: ResMut<Captures>,
-    captures_query: Extract<Query<(Entity, &Capture, &CaptureSource)>>,
+    captures_query: Extract<CapturesQuery>,
+    settings_query: Extract<Query<&CaptureBufferSettings>>,
+    log_policy_query: Extract<Query<&CaptureLogPolicy>>,
     cameras_query: Extract<Query<&Camera>>,
     images: Extract<Res<Assets<Image>>>,
-    render_device: Res<RenderDevice>,
+    readback: Readback,
 ) {
-    captures.captures = captures_query
+    let Readback {
+        config,
+        memory_budget,
+        preview,
+        render_device,
+    } = readback;
+    let extracted: EntityHashMap<_, _> = captures_query
         .iter()
-        .filter_map(|(entity, capture, capture_source)| match &capture.state {
-            CaptureState::Idle => None,
-            CaptureState::Capturing { encoders, paused } => {
-                let (prev_encoder, prev_state) = match captures.captures.remove(&entity) {
-                    Some(extracted) => (Some(extracted.encoders), extracted.state),
-                    None => (None, None),
-                };
-
-                let encoders =
-                    prev_encoder.unwrap_or_else(|| encoders.lock().unwrap().take().unwrap());
-
-                let camera_entity = match capture_source {
-                    CaptureSource::ThisCamera => entity,
-                    CaptureSource::Camera(entity) => *entity,
-                };
-                let source = cameras_query
-                    .get(camera_entity)
-                    .ok()
-                    .and_then(|camera| match &camera.target {
-                        RenderTarget::Image(image) => Some(image.clone()),
-                        _ => None,
-                    });
-                let source = match source {
-                    Some(source) => source,
-                    None => {
-                        return Some((
+        .filter_map(
+            |(
+                entity,
+                capture,
+                capture_source,
+                worker_settings,
+                mask,
+                labels,
+                live_settings,
+                range,
+                isolation,
+                change_trigger,
+                channel,
+                capture_metadata,
+            )| {
+                match &capture.state {
+                    CaptureState::Idle | CaptureState::StartDefault { .. } => None,
+                    CaptureState::Capturing {
+                        encoders,
+                        paused,
+                        stats,
+                        ..
+                    } => {
+                        #[cfg(feature = "trace")]
+                        let _span = info_span!("capture_extract", ?entity).entered();
+
+                        let camera_entity = match capture_source {
+                            CaptureSource::ThisCamera => entity,
+                            CaptureSource::Camera(entity) => *entity,
+                        };
+                        let source =
+                            cameras_query
+                                .get(camera_entity)
+                                .ok()
+                                .and_then(|camera| match &camera.target {
+                                    RenderTarget::Image(image) => Some(image.clone()),
+                                    _ => None,
+                                });
+                        let settings = settings_query.get(entity).copied().unwrap_or_default();
+                        // The state is reused unless the source, the settings or the channel
+                        // changed, or the source was resized.
+                        let reusable = |state: &ExtractedCaptureState| {
+                            source.as_ref() == Some(&state.source)
+                                && state.settings == settings
+                                && state.channel_config.as_ref() == channel
+                                && images.get(&state.source).map(|image| image.size())
+                                    == Some(state.target_image.size())
+                        };
+
+                        let mut prev = captures.captures.remove(&entity);
+                        if let Some(prev) = &mut prev {
+                            let restarted = !Arc::ptr_eq(&prev.stats, stats);
+                            if restarted || !prev.state.as_ref().is_some_and(reusable) {
+                                // The frame in flight belongs to the previous encoders or buffers.
+                                prev.finish_readback(&render_device, &memory_budget, &preview);
+                            }
+                        }
+                        let (prev_encoders, prev_state) = match prev {
+                            // The capture was restarted, the previous encoders are dropped and the
+                            // first frame is not compared with the frames of the previous capture.
+                            Some(extracted) if !Arc::ptr_eq(&extracted.stats, stats) => (
+                                None,
+                                extracted.state.map(|state| ExtractedCaptureState {
+                                    change: None,
+                                    ..state
+                                }),
+                            ),
+                            Some(extracted) => (
+                                Some((extracted.workers, extracted.encoders, extracted.log)),
+                                extracted.state,
+                            ),
+                            None => (None, None),
+                        };
+
+                        let log_policy = log_policy_query.get(entity).copied().unwrap_or_default();
+                        let (workers, mut encoders, mut log) = prev_encoders.unwrap_or_else(|| {
+                            let mut encoders = encoders.lock().unwrap().take().unwrap();
+                            if let Some(isolation) = isolation {
+                                isolation.wrap(&mut encoders.encoders, stats);
+                            }
+                            let mut log =
+                                CaptureLog::start(entity, log_policy, stats, &encoders.encoders);
+                            let worker_settings =
+                                worker_settings.or(config.worker_settings.as_ref());
+                            let workers = worker_settings.map(|settings| {
+                                let (errors, receiver) = crossbeam_channel::unbounded();
+                                log.worker_errors = Some(receiver);
+                                Workers::spawn(mem::take(&mut encoders.encoders), settings, errors)
+                            });
+                            (workers, encoders, log)
+                        });
+                        log.policy = log_policy;
+                        let mut added = mem::take(&mut *stats.added.0.lock().unwrap());
+                        if let Some(isolation) = isolation {
+                            isolation.wrap(&mut added, stats);
+                        }
+                        encoders.encoders.extend(added);
+                        // Frames outside of the range are skipped like paused frames.
+                        let paused = *paused || range.is_some_and(|range| !range.is_in_range());
+
+                        let source = match source.clone() {
+                            Some(source) => source,
+                            None => {
+                                return Some((
+                                    entity,
+                                    ExtractedCapture {
+                                        workers,
+                                        encoders,
+                                        log,
+                                        masks: Vec::new(),
+                                        labels: None,
+                                        metadata: None,
+                                        live_settings: live_settings.copied(),
+                                        change_trigger: change_trigger.copied(),
+                                        paused,
+                                        stats: Arc::clone(stats),
+                                        state: None,
+                                    },
+                                ))
+                            }
+                        };
+
+                        let mut state = match prev_state {
+                            Some(prev_state) if reusable(&prev_state) => prev_state,
+                            _ => ExtractedCaptureState::init(
+                                source,
+                                settings,
+                                channel.copied(),
+                                config.readback,
+                                &images,
+                                &render_device,
+                            ),
+                        };
+                        if change_trigger.is_none() {
+                            state.change = None;
+                        } else if state.change.is_none() {
+                            // The frames are compared in full color, before the channel is
+                            // extracted.
+                            let format =
+                                images.get(&state.source).unwrap().texture_descriptor.format;
+                            state.change = ChangeState::new(
+                                render_device.wgpu_device(),
+                                state.target_image.size(),
+                                format,
+                            );
+                        }
+
+                        Some((
                             entity,
                             ExtractedCapture {
+                                workers,
                                 encoders,
-                                paused: *paused,
-                                state: None,
+                                log,
+                                masks: mask
+                                    .map(|mask| mask.all_regions().copied().collect())
+                                    .unwrap_or_default(),
+                                labels: labels
+                                    .filter(|labels| !labels.labels().is_empty())
+                                    .cloned(),
+                                metadata: capture_metadata
+                                    .filter(|metadata| !metadata.is_empty())
+                                    .cloned(),
+                                live_settings: live_settings.copied(),
+                                change_trigger: change_trigger.copied(),
+                                paused,
+                                stats: Arc::clone(stats),
+                                state: Some(state),
                             },
                         ))
                     }
-                };
+                }
+            },
+        )
+        .collect();
 
-                let state = match prev_state {
-                    Some(prev_state) if prev_state.source == source => prev_state,
-                    _ => ExtractedCaptureState::init(source, &images, &render_device),
-                };
+    let has_change_state = |capture: &ExtractedCapture| {
+        capture
+            .state
+            .as_ref()
+            .is_some_and(|state| state.change.is_some())
+    };
+    if captures.change_pipeline.is_none() && extracted.values().any(has_change_state) {
+        captures.change_pipeline = Some(ChangePipeline::new(render_device.wgpu_device()));
+    }
+    for channel in extracted
+        .values()
+        .filter_map(|capture| capture.state.as_ref()?.channel.as_ref())
+    {
+        captures
+            .channel_pipelines
+            .get_or_insert_with(|| ChannelPipelines::new(render_device.wgpu_device()))
+            .prepare(render_device.wgpu_device(), channel);
+    }
 
-                Some((
-                    entity,
-                    ExtractedCapture {
-                        encoders,
-                        paused: *paused,
-                        state: Some(state),
-                    },
-                ))
-            }
-        })
-        .collect();
+    // The frames in flight of stopped captures are encoded before their encoders finish.
+    for (_, mut stopped) in mem::replace(&mut captures.captures, extracted) {
+        stopped.finish_readback(&render_device, &memory_budget, &preview);
+    }
+}
+
+fn extract_preview(mut slot: ResMut<PreviewSlot>, preview: Extract<Option<Res<CapturePreview>>>) {
+    slot.0 = preview.as_ref().map(|preview| Arc::clone(&preview.latest));
+}
+
+fn extract_memory_budget(
+    mut slot: ResMut<MemoryBudgetSlot>,
+    budget: Extract<Option<Res<CaptureMemoryBudget>>>,
+) {
+    slot.0 = budget.as_deref().cloned();
+}
+
+fn extract_metadata(
+    mut metadata: ResMut<FrameMetadata>,
+    main_metadata: Extract<Option<Res<FrameMetadata>>>,
+) {
+    match &*main_metadata {
+        Some(main_metadata) => metadata.clone_from(main_metadata),
+        None => metadata.clear(),
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash, RenderLabel)]
@@ -160,103 +649,360 @@ impl render_graph::Node for ImageCopyDriver {
         let captures = world.get_resource::<Captures>().unwrap();
         let gpu_images = world.get_resource::<RenderAssets<GpuImage>>().unwrap();
 
-        for capture in captures.captures.values() {
+        let diagnostics = render_context.diagnostic_recorder();
+        let time_span = diagnostics.time_span(render_context.command_encoder(), "capture_copy");
+
+        #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+        for (entity, capture) in captures.captures.iter() {
             let capture_state = match &capture.state {
                 Some(state) if !capture.paused => state,
                 _ => continue,
             };
 
-            let src_image = gpu_images.get(&capture_state.source).unwrap();
-
-            let encoder = render_context.command_encoder();
+            #[cfg(feature = "trace")]
+            let _span = info_span!("capture_copy", ?entity).entered();
 
-            let block_dimensions = src_image.texture_format.block_dimensions();
-            let block_size = src_image.texture_format.block_copy_size(None).unwrap();
-
-            // Calculating correct size of image row because
-            // copy_texture_to_buffer can copy image only by rows aligned wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
-            // That's why image in buffer can be little bit wider
-            // This should be taken into account at copy from buffer stage
-            let padded_bytes_per_row = RenderDevice::align_copy_bytes_per_row(
-                (src_image.size.x as usize / block_dimensions.0 as usize) * block_size as usize,
-            );
+            let src_image = gpu_images.get(&capture_state.source).unwrap();
+            if src_image.size != capture_state.target_image.size() {
+                // The source was resized and the gpu image is not updated yet.
+                continue;
+            }
 
-            let texture_extent = Extent3d {
-                width: src_image.size.x,
-                height: src_image.size.y,
-                depth_or_array_layers: 1,
-            };
+            // Frames compared on the gpu are only copied in `encode`, once they are known to have
+            // changed.
+            if let (Some(change), Some(pipeline)) =
+                (&capture_state.change, &captures.change_pipeline)
+            {
+                let render_device = render_context.render_device().clone();
+                change.record(
+                    render_device.wgpu_device(),
+                    pipeline,
+                    render_context.command_encoder(),
+                    &src_image.texture,
+                );
+                continue;
+            }
 
-            encoder.copy_texture_to_buffer(
-                src_image.texture.as_image_copy(),
-                ImageCopyBuffer {
-                    buffer: &capture_state.target_buffer,
-                    layout: ImageDataLayout {
-                        offset: 0,
-                        bytes_per_row: Some(
-                            std::num::NonZeroU32::new(padded_bytes_per_row as u32)
-                                .unwrap()
-                                .into(),
-                        ),
-                        rows_per_image: None,
-                    },
-                },
-                texture_extent,
+            let render_device = render_context.render_device().clone();
+            capture_state.copy_frame(
+                &render_device,
+                captures.channel_pipelines.as_ref(),
+                render_context.command_encoder(),
+                &src_image.texture,
             );
         }
 
+        time_span.end(render_context.command_encoder());
+
         Ok(())
     }
 }
 
-fn encode(mut captures: ResMut<Captures>, render_device: Res<RenderDevice>) {
-    for capture in captures.captures.values_mut() {
+fn encode(
+    mut captures: ResMut<Captures>,
+    metadata: Res<FrameMetadata>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    render_queue: Res<RenderQueue>,
+    readback: Readback,
+) {
+    let Readback {
+        config,
+        memory_budget,
+        preview,
+        render_device,
+    } = readback;
+    let captures = &mut *captures;
+    let has_change_pipeline = captures.change_pipeline.is_some();
+    #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+    for (entity, capture) in captures.captures.iter_mut() {
+        capture.log.receive_worker_errors();
+
+        #[cfg(feature = "trace")]
+        let _span = info_span!("capture_encode", ?entity).entered();
+
+        // The previous frame is encoded first, so the frames stay in order.
+        capture.finish_readback(&render_device, &memory_budget, &preview);
+        if capture.stats.flush_requested.swap(false, Ordering::Relaxed) {
+            capture.flush();
+        }
+
         let capture_state = match &mut capture.state {
             Some(state) if !capture.paused => state,
             _ => continue,
         };
+        let src_image = match gpu_images.get(&capture_state.source) {
+            Some(src_image) if src_image.size == capture_state.target_image.size() => src_image,
+            _ => continue,
+        };
+
+        // The metadata of the capture and the live settings only apply to the encoders of this
+        // capture.
+        let mut capture_metadata;
+        let mut metadata = match (&capture.metadata, &capture.live_settings) {
+            (None, None) => &*metadata,
+            (own, live_settings) => {
+                capture_metadata = metadata.clone();
+                if let Some(own) = own {
+                    capture_metadata.merge(own);
+                }
+                if let Some(live_settings) = live_settings {
+                    live_settings.apply(&mut capture_metadata);
+                }
+                &capture_metadata
+            }
+        };
+
+        // Frames that didn't change are skipped before they are read back.
+        let mut change_metadata;
+        if let (Some(change), Some(trigger), true) = (
+            &mut capture_state.change,
+            &capture.change_trigger,
+            has_change_pipeline,
+        ) {
+            change_metadata = metadata.clone();
+            if !change.resolve(render_device.wgpu_device(), trigger, &mut change_metadata) {
+                continue;
+            }
+            metadata = &change_metadata;
+
+            let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("bevy_capture_copy"),
+            });
+            capture_state.copy_frame(
+                &render_device,
+                captures.channel_pipelines.as_ref(),
+                &mut encoder,
+                &src_image.texture,
+            );
+            render_queue.submit([encoder.finish()]);
+        }
 
         // Get the data back from the gpu
-        let buffer_slice = capture_state.target_buffer.slice(..);
+        #[cfg(feature = "trace")]
+        let map_span = info_span!("capture_map").entered();
+        let mapped = capture_state.map();
+        match config.readback {
+            ReadbackMode::Sync => {
+                render_device.poll(Maintain::wait()).panic_on_timeout();
+                for _ in &capture_state.tiles {
+                    mapped
+                        .recv()
+                        .expect("Failed to receive the map_async message");
+                }
+                capture_state.read(capture_state.write);
+                #[cfg(feature = "trace")]
+                drop(map_span);
+
+                capture.encode_frame(metadata, &memory_budget, &preview);
+            }
+            ReadbackMode::Async => {
+                render_device.poll(Maintain::Poll);
+                capture_state.pending = Some(PendingReadback {
+                    buffers: capture_state.write,
+                    mapped,
+                    metadata: metadata.clone(),
+                });
+                capture_state.write =
+                    (capture_state.write + 1) % capture_state.tiles[0].buffers.len();
+            }
+        }
+    }
+}
 
-        let (s, r) = crossbeam_channel::bounded(1);
-        buffer_slice.map_async(MapMode::Read, move |r| match r {
-            Ok(r) => s.send(r).expect("Failed to send map update"),
-            Err(err) => panic!("Failed to map buffer {err}"),
-        });
-        render_device.poll(Maintain::wait()).panic_on_timeout();
-        r.recv().expect("Failed to receive the map_async message");
-
-        let buffer_bytes = buffer_slice.get_mapped_range().to_vec();
-        capture_state.target_buffer.unmap();
-
-        // We need to ensure that this works regardless of the image dimensions
-        // If the image became wider when copying from the texture to the buffer,
-        // then the data is reduced to its original size when copying from the buffer to the image.
-        let row_bytes = capture_state.target_image.width() as usize
-            * capture_state
-                .target_image
-                .texture_descriptor
-                .format
-                .pixel_size();
-        let aligned_row_bytes = RenderDevice::align_copy_bytes_per_row(row_bytes);
-        if row_bytes == aligned_row_bytes {
-            capture_state.target_image.data.clone_from(&buffer_bytes);
-        } else {
-            // shrink data to original image size
-            capture_state.target_image.data = buffer_bytes
-                .chunks(aligned_row_bytes)
-                .take(capture_state.target_image.height() as usize)
-                .flat_map(|row| &row[..row_bytes.min(row.len())])
-                .cloned()
-                .collect();
+impl ExtractedCapture {
+    /// Waits for the frame that is read back asynchronously, if any, and encodes it.
+    fn finish_readback(
+        &mut self,
+        render_device: &RenderDevice,
+        memory_budget: &MemoryBudgetSlot,
+        preview: &PreviewSlot,
+    ) {
+        let Some(capture_state) = &mut self.state else {
+            return;
+        };
+        let Some(pending) = capture_state.pending.take() else {
+            return;
+        };
+
+        #[cfg(feature = "trace")]
+        let map_span = info_span!("capture_map").entered();
+        // Usually the copy is done by now, so this doesn't block.
+        if pending.mapped.len() < capture_state.tiles.len() {
+            render_device.poll(Maintain::wait()).panic_on_timeout();
+        }
+        for _ in &capture_state.tiles {
+            pending
+                .mapped
+                .recv()
+                .expect("Failed to receive the map_async message");
+        }
+        capture_state.read(pending.buffers);
+        #[cfg(feature = "trace")]
+        drop(map_span);
+
+        self.encode_frame(&pending.metadata, memory_budget, preview);
+    }
+
+    /// Flushes the encoders. The workers flush theirs once they encoded the queued frames.
+    fn flush(&mut self) {
+        if let Some(workers) = &self.workers {
+            workers.flush();
+        }
+        for encoder in &mut self.encoders.encoders {
+            if let Err(err) = encoder.flush() {
+                self.log.encode_error(err);
+            }
+        }
+    }
+
+    /// Passes the frame in the target image to the encoders.
+    fn encode_frame(
+        &mut self,
+        metadata: &FrameMetadata,
+        memory_budget: &MemoryBudgetSlot,
+        preview: &PreviewSlot,
+    ) {
+        let Some(capture_state) = &mut self.state else {
+            return;
+        };
+        let mut metadata = metadata.clone();
+        let index = self.stats.frames_captured.load(Ordering::Relaxed);
+        metadata.insert(FRAME_INDEX_KEY, index);
+
+        // Mask the regions of the frame that must not be captured.
+        if !self.masks.is_empty() {
+            privacy::apply(&mut capture_state.target_image, &self.masks);
+        }
+        if let Some(labels) = &self.labels {
+            labels::draw(&mut capture_state.target_image, labels);
         }
 
         // Call the encoder
-        for encoder in &mut capture.encoders.0 {
-            if let Err(err) = encoder.encode(&capture_state.target_image) {
-                bevy::log::error!("Failed to encode: {:?}", err);
+        if let Some(workers) = &self.workers {
+            let budget = memory_budget.0.as_ref();
+            if let Err(err) = workers.send(&capture_state.target_image, &metadata, budget) {
+                self.log.encode_error(err.into());
+            }
+        }
+        for encoder in &mut self.encoders.encoders {
+            #[cfg(feature = "trace")]
+            let _span = info_span!("capture_encoder", encoder = encoder.encoder.name()).entered();
+
+            if let Err(err) = encoder.encode(&capture_state.target_image, &metadata) {
+                self.log.encode_error(err);
+            }
+        }
+        self.stats.frames_captured.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(latest) = &preview.0 {
+            *latest.lock().unwrap() = Some(capture_state.target_image.clone());
+        }
+    }
+}
+
+/// Logs the events of a capture according to its [`CaptureLogPolicy`].
+struct CaptureLog {
+    entity: Entity,
+    policy: CaptureLogPolicy,
+    stats: Arc<CaptureStats>,
+    errors: u64,
+    last_error: Option<EncodeError>,
+    last_report: Option<Instant>,
+    worker_errors: Option<crossbeam_channel::Receiver<EncodeError>>,
+}
+
+impl CaptureLog {
+    fn start(
+        entity: Entity,
+        policy: CaptureLogPolicy,
+        stats: &Arc<CaptureStats>,
+        encoders: &[AttachedEncoder],
+    ) -> Self {
+        if policy.verbosity() == CaptureVerbosity::Verbose {
+            let names = encoders
+                .iter()
+                .map(|encoder| format!("{} {}", encoder.encoder.name(), encoder.id));
+            bevy::log::info!(
+                "Capture of {:?} started with {} encoders ({})",
+                entity,
+                encoders.len(),
+                names.collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        Self {
+            entity,
+            policy,
+            stats: Arc::clone(stats),
+            errors: 0,
+            last_error: None,
+            last_report: None,
+            worker_errors: None,
+        }
+    }
+
+    /// Logs the errors the workers reported since the last call.
+    fn receive_worker_errors(&mut self) {
+        while let Some(err) = self.worker_errors.as_ref().and_then(|r| r.try_recv().ok()) {
+            self.encode_error(err);
+        }
+    }
+
+    fn encode_error(&mut self, err: EncodeError) {
+        if self.policy.verbosity() == CaptureVerbosity::Quiet {
+            return;
+        }
+        let Some(interval) = self.policy.aggregate_errors() else {
+            match &err.encoder {
+                Some((id, name)) => {
+                    bevy::log::error!("Failed to encode with {} {}: {:?}", name, id, err.error)
+                }
+                None => bevy::log::error!("Failed to encode: {:?}", err.error),
             }
+            return;
+        };
+
+        self.errors += 1;
+        self.last_error = Some(err);
+        if !matches!(self.last_report, Some(last_report) if last_report.elapsed() < interval) {
+            self.report_errors();
+        }
+    }
+
+    fn report_errors(&mut self) {
+        let Some(err) = self.last_error.take() else {
+            return;
+        };
+        let encoder = match &err.encoder {
+            Some((id, name)) => format!(" (of {name} {id})"),
+            None => String::new(),
+        };
+        bevy::log::error!(
+            "Failed to encode {} times for {:?}, last error{}: {:?}",
+            self.errors,
+            self.entity,
+            encoder,
+            err.error
+        );
+        self.errors = 0;
+        self.last_report = Some(Instant::now());
+    }
+}
+
+impl Drop for CaptureLog {
+    fn drop(&mut self) {
+        // The workers are done by now, so these are the remaining errors.
+        self.receive_worker_errors();
+        if self.policy.verbosity() == CaptureVerbosity::Quiet {
+            return;
+        }
+        self.report_errors();
+        if self.policy.verbosity() == CaptureVerbosity::Verbose {
+            bevy::log::info!(
+                "Capture of {:?} finished after {} frames",
+                self.entity,
+                self.stats.frames_captured.load(Ordering::Relaxed)
+            );
         }
     }
 }
@@ -0,0 +1,387 @@
+//! Skip unchanged frames on the GPU, before they are read back, and detect scene cuts.
+//!
+//! The [`ChangeTrigger`](crate::encoder::idle::ChangeTrigger) encoder compares frames after they
+//! were read back, so a capture of a mostly static scene still copies every frame from the GPU.
+//! With a [`GpuChangeTrigger`] next to the [`Capture`](crate::Capture), a small compute pass
+//! compares the frame with the previous frame on the GPU instead. Only a few bytes of metrics are
+//! read back: the sum of the color differences and a luma histogram. The frame itself is only read
+//! back and passed to the encoders if it changed by more than the threshold.
+//!
+//! Like the `ChangeTrigger`, the first frame recorded after a gap carries the number of skipped
+//! frames and the timestamp of the first skipped frame in its [metadata](crate::metadata), see
+//! [`GAP_FRAMES_KEY`] and [`GAP_START_KEY`]. Optionally, frames whose histogram differs a lot from
+//! the previous frame are marked as scene cuts and [forced](FrameMetadata::force_keyframe) to be
+//! keyframes, so videos can be seeked and cut exactly at the cut.
+//!
+//! The color differences are computed on the values the shader reads from the texture, i.e. on
+//! linear values for sRGB textures. Textures that can't be read as floats are never skipped.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{scene_change::GpuChangeTrigger, CaptureBundle};
+//! #
+//! commands.spawn((
+//!     Camera2dBundle::default(),
+//!     CaptureBundle::default(),
+//!     GpuChangeTrigger::new(0.01)
+//!         .with_hold(60)
+//!         .with_scene_cut_threshold(0.5),
+//! ));
+//! ```
+
+use crate::{
+    encoder::idle::{GAP_FRAMES_KEY, GAP_START_KEY},
+    metadata::{FrameMetadata, MetadataValue, TIMESTAMP_KEY},
+};
+use bevy::{prelude::*, render::render_resource::TextureFormat};
+
+/// The number of bins of the luma histogram.
+const BINS: usize = 16;
+
+/// The size of the workgroups, in pixels per side.
+const WORKGROUP_SIZE: u32 = 16;
+
+const SHADER: &str = r#"
+@group(0) @binding(0) var current: texture_2d<f32>;
+@group(0) @binding(1) var previous: texture_2d<f32>;
+@group(0) @binding(2) var<storage, read_write> metrics: array<atomic<u32>>;
+
+const BINS: u32 = 16u;
+
+var<workgroup> difference: atomic<u32>;
+
+@compute @workgroup_size(16, 16)
+fn main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    let size = textureDimensions(current);
+    if id.x < size.x && id.y < size.y {
+        let a = clamp(textureLoad(current, vec2<i32>(id.xy), 0).rgb, vec3(0.0), vec3(1.0));
+        let b = clamp(textureLoad(previous, vec2<i32>(id.xy), 0).rgb, vec3(0.0), vec3(1.0));
+        let d = abs(a - b);
+        atomicAdd(&difference, u32(round((d.r + d.g + d.b) * 255.0)));
+        let luma = dot(a, vec3(0.2126, 0.7152, 0.0722));
+        atomicAdd(&metrics[min(u32(luma * f32(BINS)), BINS - 1u)], 1u);
+    }
+
+    // One partial sum per workgroup, so the sum can't overflow for large frames.
+    workgroupBarrier();
+    if index == 0u {
+        atomicStore(&metrics[BINS + group.y * groups.x + group.x], atomicLoad(&difference));
+    }
+}
+"#;
+
+/// Skips frames that didn't change before they are read back from the GPU. This is optional and
+/// can be attached next to the [`Capture`](crate::Capture), see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub struct GpuChangeTrigger {
+    threshold: f32,
+    hold: u32,
+    scene_cut_threshold: Option<f32>,
+}
+
+impl GpuChangeTrigger {
+    /// Creates a new trigger that passes frames to the encoders if they differ from the previous
+    /// frame by more than the given threshold, i.e. the mean absolute difference of the RGB values
+    /// of the frames, from `0.0` (identical) to `1.0` (inverted). The first frame is always
+    /// recorded.
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold,
+            hold: 0,
+            scene_cut_threshold: None,
+        }
+    }
+
+    /// Sets the number of frames that are recorded after the last changed frame, e.g. to see how a
+    /// change settles. Defaults to `0`.
+    pub fn with_hold(mut self, hold: u32) -> Self {
+        self.hold = hold;
+        self
+    }
+
+    /// Forces recorded frames to be keyframes if their luma histogram differs from the one of the
+    /// previous frame by more than the given threshold, from `0.0` (same histogram) to `1.0` (no
+    /// overlap). Disabled by default.
+    pub fn with_scene_cut_threshold(mut self, threshold: f32) -> Self {
+        self.scene_cut_threshold = Some(threshold);
+        self
+    }
+
+    /// Returns the threshold of the mean absolute difference.
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    /// Returns the number of frames that are recorded after the last changed frame.
+    pub fn hold(&self) -> u32 {
+        self.hold
+    }
+
+    /// Returns the threshold of the histogram difference of scene cuts, if enabled.
+    pub fn scene_cut_threshold(&self) -> Option<f32> {
+        self.scene_cut_threshold
+    }
+}
+
+/// The compute pipeline comparing frames, shared by all captures.
+pub(crate) struct ChangePipeline {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl ChangePipeline {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bevy_capture_change_trigger"),
+            entries: &[
+                texture(0),
+                texture(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("bevy_capture_change_trigger"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("bevy_capture_change_trigger"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("bevy_capture_change_trigger"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+            compilation_options: Default::default(),
+        });
+        Self { layout, pipeline }
+    }
+}
+
+/// The GPU resources and the state of the [`GpuChangeTrigger`] of a capture.
+pub(crate) struct ChangeState {
+    previous: wgpu::Texture,
+    metrics: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    workgroups: UVec2,
+    pixels: u64,
+    histogram: Option<[u32; BINS]>,
+    unchanged: u32,
+    gap: Option<Gap>,
+}
+
+struct Gap {
+    frames: u64,
+    start: Option<MetadataValue>,
+}
+
+impl ChangeState {
+    /// Creates the resources for frames of the given size and format, or returns `None` if the
+    /// format can't be compared.
+    pub fn new(device: &wgpu::Device, size: UVec2, format: TextureFormat) -> Option<Self> {
+        if !matches!(
+            format.sample_type(None, None),
+            Some(wgpu::TextureSampleType::Float { .. })
+        ) {
+            warn!("Frames with format {format:?} can't be compared on the gpu, none are skipped");
+            return None;
+        }
+
+        let previous = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("bevy_capture_change_trigger_previous"),
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let workgroups = UVec2::new(
+            size.x.div_ceil(WORKGROUP_SIZE),
+            size.y.div_ceil(WORKGROUP_SIZE),
+        );
+        let metrics_size = (BINS as u64 + workgroups.x as u64 * workgroups.y as u64) * 4;
+        let metrics = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("bevy_capture_change_trigger_metrics"),
+            size: metrics_size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("bevy_capture_change_trigger_readback"),
+            size: metrics_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            previous,
+            metrics,
+            readback,
+            workgroups,
+            pixels: size.x as u64 * size.y as u64,
+            histogram: None,
+            unchanged: 0,
+            gap: None,
+        })
+    }
+
+    /// Records the comparison of the source with the previous frame, and copies the source into
+    /// the previous frame for the next comparison.
+    pub fn record(
+        &self,
+        device: &wgpu::Device,
+        pipeline: &ChangePipeline,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::Texture,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bevy_capture_change_trigger"),
+            layout: &pipeline.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &source.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &self.previous.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.metrics.as_entire_binding(),
+                },
+            ],
+        });
+
+        encoder.clear_buffer(&self.metrics, 0, Some(BINS as u64 * 4));
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("bevy_capture_change_trigger"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&pipeline.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(self.workgroups.x, self.workgroups.y, 1);
+        }
+        encoder.copy_texture_to_texture(
+            source.as_image_copy(),
+            self.previous.as_image_copy(),
+            self.previous.size(),
+        );
+        encoder.copy_buffer_to_buffer(&self.metrics, 0, &self.readback, 0, self.metrics.size());
+    }
+
+    /// Reads the metrics of the frame recorded in this frame and decides whether the frame is read
+    /// back. Returns `false` if the frame is skipped, otherwise the metadata is updated with the
+    /// gap and the scene cut.
+    pub fn resolve(
+        &mut self,
+        device: &wgpu::Device,
+        trigger: &GpuChangeTrigger,
+        metadata: &mut FrameMetadata,
+    ) -> bool {
+        let slice = self.readback.slice(..);
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        if !matches!(receiver.recv(), Ok(Ok(()))) {
+            error!("Failed to read the frame metrics, the frame is not skipped");
+            return true;
+        }
+        let (histogram, difference) = {
+            let bytes = slice.get_mapped_range();
+            let mut values = bytes
+                .chunks_exact(4)
+                .map(|value| u32::from_le_bytes(value.try_into().unwrap()));
+            let mut histogram = [0; BINS];
+            for (bin, value) in histogram.iter_mut().zip(&mut values) {
+                *bin = value;
+            }
+            let difference = values.map(u64::from).sum::<u64>();
+            (histogram, difference)
+        };
+        self.readback.unmap();
+
+        // The first frame has nothing to compare with.
+        let previous_histogram = self.histogram.replace(histogram);
+        let changed = match previous_histogram {
+            Some(_) => {
+                difference as f64 / (self.pixels as f64 * 3.0 * 255.0) > trigger.threshold as f64
+            }
+            None => true,
+        };
+        self.unchanged = if changed {
+            0
+        } else {
+            self.unchanged.saturating_add(1)
+        };
+
+        if self.unchanged > trigger.hold {
+            let gap = self.gap.get_or_insert_with(|| Gap {
+                frames: 0,
+                start: metadata.get(TIMESTAMP_KEY).cloned(),
+            });
+            gap.frames += 1;
+            return false;
+        }
+
+        if let Some(gap) = self.gap.take() {
+            metadata.insert(GAP_FRAMES_KEY, gap.frames);
+            if let Some(start) = gap.start {
+                metadata.insert(GAP_START_KEY, start);
+            }
+        }
+        if let (Some(threshold), Some(previous)) = (trigger.scene_cut_threshold, previous_histogram)
+        {
+            let distance = histogram
+                .iter()
+                .zip(&previous)
+                .map(|(&a, &b)| a.abs_diff(b) as u64)
+                .sum::<u64>();
+            if distance as f64 / (2.0 * self.pixels as f64) > threshold as f64 {
+                metadata.force_keyframe();
+            }
+        }
+        true
+    }
+}
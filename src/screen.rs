@@ -0,0 +1,303 @@
+//! Capture screens and windows outside of Bevy with the same encoders.
+//!
+//! A [`ScreenCapture`] reads frames from a [`ScreenSource`] on a background thread and passes
+//! them to the encoders, e.g. to record the app together with other tooling. The platform specific
+//! part is implemented by the source:
+//!
+//! - [`GstreamerScreenSource`] (feature `gstreamer`) captures PipeWire streams (Wayland), X11
+//!   displays, or DXGI desktop duplication (Windows). There is no native backend for these APIs,
+//!   they are only reachable through GStreamer source elements run by the gst-launch-1.0 CLI,
+//!   which must be installed with the matching plugins.
+//! - Implement [`ScreenSource`] for anything else.
+//!
+//! The frames get the same [metadata](crate::metadata) as frames of a [`Capture`](crate::Capture):
+//! their index, and the wall-clock timestamp and delta time since the capture started, as the
+//! frames are read at the rate of the source.
+
+use crate::{
+    encoder::Result,
+    metadata::{FrameMetadata, DELTA_TIME_KEY, FRAME_INDEX_KEY, TIMESTAMP_KEY},
+    CaptureHandle, Encoders, IntoEncoders,
+};
+use bevy::prelude::*;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Instant,
+};
+
+/// A source of frames outside of Bevy, e.g. a screen or a window.
+pub trait ScreenSource {
+    /// Returns the next frame, blocking until it is available, or `None` if the source ended.
+    fn next_frame(&mut self) -> Result<Option<Image>>;
+
+    /// Stops the source. This is called once after the last frame.
+    fn stop(&mut self) {}
+}
+
+/// A component capturing frames from a [`ScreenSource`]. This mirrors [`Capture`](crate::Capture),
+/// but does not need a camera and encodes on its own thread, at the rate of the source.
+///
+/// # Example
+/// ```ignore
+/// # use bevy_capture::{encoder::mp4_ffmpeg_cli::Mp4FfmpegCliEncoder, screen::*};
+/// #
+/// let mut capture = ScreenCapture::default();
+/// capture.start(
+///     GstreamerScreenSource::x11(1920, 1080),
+///     Mp4FfmpegCliEncoder::new("desktop.mp4")?,
+/// );
+/// ```
+#[derive(Default, Component)]
+pub struct ScreenCapture {
+    state: Option<ScreenCaptureState>,
+}
+
+struct ScreenCaptureState {
+    stop: Arc<AtomicBool>,
+    frames_captured: Arc<AtomicU64>,
+    handle: CaptureHandle,
+}
+
+impl ScreenCapture {
+    /// Starts capturing frames from the source with the given encoders. An active capture is
+    /// stopped first.
+    ///
+    /// The returned handle can be used to wait until the encoders have finished after the capture
+    /// was stopped.
+    pub fn start(
+        &mut self,
+        source: impl ScreenSource + Send + 'static,
+        encoders: impl IntoEncoders,
+    ) -> CaptureHandle {
+        self.stop();
+
+        let handle = CaptureHandle::default();
+        let state = ScreenCaptureState {
+            stop: Arc::default(),
+            frames_captured: Arc::default(),
+            handle: handle.clone(),
+        };
+        let encoders = Encoders::new(encoders.into_encoders(), handle.clone());
+        let stop = Arc::clone(&state.stop);
+        let frames_captured = Arc::clone(&state.frames_captured);
+        thread::Builder::new()
+            .name("screen capture".to_owned())
+            .spawn(move || run(source, encoders, &stop, &frames_captured))
+            .expect("Failed to spawn screen capture");
+
+        self.state = Some(state);
+        handle
+    }
+
+    /// Stops the capture. The encoders are finished on the capture thread once the source returned
+    /// its current frame.
+    pub fn stop(&mut self) {
+        if let Some(state) = self.state.take() {
+            state.stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns `true` if the capture is currently capturing frames.
+    pub fn is_capturing(&self) -> bool {
+        self.state.is_some()
+    }
+
+    /// Returns the number of frames captured so far, or `0` if the capture is not capturing.
+    pub fn frames_captured(&self) -> u64 {
+        self.state
+            .as_ref()
+            .map_or(0, |state| state.frames_captured.load(Ordering::Relaxed))
+    }
+
+    /// Returns the handle of the active capture, or `None` if the capture is not capturing.
+    pub fn handle(&self) -> Option<CaptureHandle> {
+        self.state.as_ref().map(|state| state.handle.clone())
+    }
+}
+
+impl Drop for ScreenCapture {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run(
+    mut source: impl ScreenSource,
+    mut encoders: Encoders,
+    stop: &AtomicBool,
+    frames_captured: &AtomicU64,
+) {
+    let start = Instant::now();
+    let mut previous = 0.0;
+    while !stop.load(Ordering::Relaxed) {
+        let image = match source.next_frame() {
+            Ok(Some(image)) => image,
+            Ok(None) => break,
+            Err(err) => {
+                bevy::log::error!("Failed to capture the screen: {:?}", err);
+                break;
+            }
+        };
+        let timestamp = start.elapsed().as_secs_f64();
+        let mut metadata = FrameMetadata::default();
+        metadata.insert(FRAME_INDEX_KEY, frames_captured.load(Ordering::Relaxed));
+        metadata.insert(TIMESTAMP_KEY, timestamp);
+        metadata.insert(DELTA_TIME_KEY, timestamp - previous);
+        previous = timestamp;
+
+        for encoder in &mut encoders.encoders {
+            if let Err(err) = encoder.encode(&image, &metadata) {
+                bevy::log::error!(
+                    "Failed to encode with {} {}: {:?}",
+                    encoder.encoder.name(),
+                    encoder.id,
+                    err.error
+                );
+            }
+        }
+        frames_captured.fetch_add(1, Ordering::Relaxed);
+    }
+
+    source.stop();
+    // Dropping the encoders finishes them.
+}
+
+#[cfg(feature = "gstreamer")]
+pub use gstreamer::GstreamerScreenSource;
+
+#[cfg(feature = "gstreamer")]
+mod gstreamer {
+    use super::ScreenSource;
+    use crate::encoder::{pipe, Error, Result};
+    use bevy::{
+        prelude::*,
+        render::{
+            render_asset::RenderAssetUsages,
+            render_resource::{Extent3d, TextureDimension, TextureFormat},
+        },
+    };
+    use std::{
+        io::{self, Read},
+        process::{Child, ChildStdout, Command, Stdio},
+        thread::JoinHandle,
+    };
+
+    /// A screen source that captures with a GStreamer source element using the gst-launch-1.0 CLI
+    /// (must be in PATH). The frames are scaled to the given dimensions.
+    ///
+    /// If gst-launch-1.0 fails, e.g. because the element or a plugin is missing, the error contains
+    /// the end of its output.
+    pub struct GstreamerScreenSource {
+        element: String,
+        width: u32,
+        height: u32,
+        framerate: u32,
+        process: Option<Process>,
+    }
+
+    struct Process {
+        child: Child,
+        stdout: ChildStdout,
+        stderr: Option<JoinHandle<String>>,
+    }
+
+    impl GstreamerScreenSource {
+        /// Creates a new source with the given source element, e.g. `ximagesrc display-name=:1`.
+        pub fn new(element: impl Into<String>, width: u32, height: u32) -> Self {
+            Self {
+                element: element.into(),
+                width,
+                height,
+                framerate: 30,
+                process: None,
+            }
+        }
+
+        /// Captures a PipeWire stream, e.g. a screen or window shared through the
+        /// xdg-desktop-portal on Wayland.
+        pub fn pipewire(node_id: u32, width: u32, height: u32) -> Self {
+            Self::new(format!("pipewiresrc path={node_id}"), width, height)
+        }
+
+        /// Captures the default X11 display.
+        pub fn x11(width: u32, height: u32) -> Self {
+            Self::new("ximagesrc use-damage=false", width, height)
+        }
+
+        /// Captures the primary monitor with DXGI desktop duplication (Windows).
+        pub fn dxgi(width: u32, height: u32) -> Self {
+            Self::new("d3d11screencapturesrc", width, height)
+        }
+
+        /// Sets the framerate the source is captured at.
+        pub fn with_framerate(mut self, framerate: u32) -> Self {
+            self.framerate = framerate;
+            self
+        }
+
+        fn spawn(&self) -> Result<Process> {
+            let mut command = Command::new("gst-launch-1.0");
+            command.arg("-q").arg(format!(
+                "{} ! videoconvert ! videoscale ! videorate ! \
+                 video/x-raw,format=RGBA,width={},height={},framerate={}/1 ! fdsink fd=1",
+                self.element, self.width, self.height, self.framerate
+            ));
+            let mut child = command
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|err| Error::external("gst-launch-1.0", err))?;
+            let stdout = child.stdout.take().ok_or("stdout is closed")?;
+            let stderr = pipe::read_stderr(&mut child);
+            Ok(Process {
+                child,
+                stdout,
+                stderr,
+            })
+        }
+    }
+
+    impl ScreenSource for GstreamerScreenSource {
+        fn next_frame(&mut self) -> Result<Option<Image>> {
+            let process = match &mut self.process {
+                Some(process) => process,
+                None => self.process.insert(self.spawn()?),
+            };
+
+            let mut data = vec![0; (self.width * self.height * 4) as usize];
+            if let Err(err) = process.stdout.read_exact(&mut data) {
+                // The process exited, return its error if it failed.
+                let mut process = self.process.take().unwrap();
+                pipe::wait("gst-launch-1.0", &mut process.child, process.stderr)?;
+                return match err.kind() {
+                    io::ErrorKind::UnexpectedEof => Ok(None),
+                    _ => Err(Error::external("gst-launch-1.0", err)),
+                };
+            }
+
+            Ok(Some(Image::new(
+                Extent3d {
+                    width: self.width,
+                    height: self.height,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                data,
+                TextureFormat::Rgba8UnormSrgb,
+                RenderAssetUsages::default(),
+            )))
+        }
+
+        fn stop(&mut self) {
+            if let Some(mut process) = self.process.take() {
+                let _ = process.child.kill();
+                let _ = process.child.wait();
+            }
+        }
+    }
+}
@@ -0,0 +1,423 @@
+//! Automated screenshots across resolutions and app states, e.g. store page or localization QA
+//! screenshots.
+//!
+//! A [`TakeScreenshotMatrix`] event lists shots, each with a name, a resolution and a setup
+//! callback, e.g. switching the language or loading a level. The [`ScreenshotMatrixPlugin`] runs
+//! the shots one after another: it calls the setup callback, resizes the target of the camera,
+//! waits a few frames for the scene to settle and saves a single frame as `<name>.png`.
+//!
+//! With the `golden` feature, the shots can additionally be compared against golden images within
+//! a tolerance, see [`TakeScreenshotMatrix::with_golden`].
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::screenshot_matrix::*;
+//! #
+//! const RESOLUTIONS: [(u32, u32); 2] = [(1920, 1080), (1280, 720)];
+//!
+//! fn take_screenshots(camera: Query<Entity, With<Camera>>, mut matrices: EventWriter<TakeScreenshotMatrix>) {
+//!     matrices.send(
+//!         TakeScreenshotMatrix::new(camera.single(), "screenshots")
+//!             .with_variant("en", RESOLUTIONS, |world| world.insert_resource(Locale::En))
+//!             .with_variant("de", RESOLUTIONS, |world| world.insert_resource(Locale::De)),
+//!     );
+//! }
+//! ```
+
+#[cfg(feature = "golden")]
+use crate::golden::{self, DiffTolerance};
+use crate::{encoder, Capture, Encoder};
+use bevy::{
+    prelude::*,
+    render::{camera::RenderTarget, render_resource::Extent3d},
+};
+use std::{
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// The default number of frames rendered after the setup of a shot before it is taken.
+pub const DEFAULT_SETTLE_FRAMES: u32 = 2;
+
+/// A Bevy plugin for screenshot matrices.
+pub struct ScreenshotMatrixPlugin;
+
+impl Plugin for ScreenshotMatrixPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScreenshotMatrices>()
+            .add_event::<TakeScreenshotMatrix>()
+            .add_event::<ScreenshotMatrixFinished>()
+            .add_systems(Update, update_matrices);
+    }
+}
+
+type SetupFn = Arc<dyn Fn(&mut World) + Send + Sync>;
+
+/// An event to take a list of screenshots with the given camera. Matrices are taken one after
+/// another, in the order of the events.
+///
+/// The camera must render to a headless image and must have a [`Capture`] component which is not
+/// capturing already. The target is restored to its original size afterwards.
+#[derive(Event)]
+pub struct TakeScreenshotMatrix {
+    camera: Entity,
+    directory: PathBuf,
+    settle_frames: u32,
+    shots: Vec<Shot>,
+    #[cfg(feature = "golden")]
+    golden: Option<(PathBuf, DiffTolerance)>,
+}
+
+struct Shot {
+    name: String,
+    size: UVec2,
+    setup: SetupFn,
+}
+
+impl TakeScreenshotMatrix {
+    /// Creates a new, empty screenshot matrix that saves the screenshots to the given directory.
+    pub fn new(camera: Entity, directory: impl Into<PathBuf>) -> Self {
+        Self {
+            camera,
+            directory: directory.into(),
+            settle_frames: DEFAULT_SETTLE_FRAMES,
+            shots: Vec::new(),
+            #[cfg(feature = "golden")]
+            golden: None,
+        }
+    }
+
+    /// Adds a shot that is saved as `<name>.png`. The setup callback is called with exclusive
+    /// access to the world before the target is resized.
+    pub fn with_shot(
+        mut self,
+        name: impl Into<String>,
+        width: u32,
+        height: u32,
+        setup: impl Fn(&mut World) + Send + Sync + 'static,
+    ) -> Self {
+        self.shots.push(Shot {
+            name: name.into(),
+            size: UVec2::new(width, height),
+            setup: Arc::new(setup),
+        });
+        self
+    }
+
+    /// Adds a shot of the variant, e.g. a language, at every resolution. The shots are saved as
+    /// `<variant>_<width>x<height>.png`.
+    pub fn with_variant(
+        mut self,
+        variant: impl Into<String>,
+        resolutions: impl IntoIterator<Item = (u32, u32)>,
+        setup: impl Fn(&mut World) + Send + Sync + 'static,
+    ) -> Self {
+        let variant = variant.into();
+        let setup: SetupFn = Arc::new(setup);
+        for (width, height) in resolutions {
+            self.shots.push(Shot {
+                name: format!("{variant}_{width}x{height}"),
+                size: UVec2::new(width, height),
+                setup: Arc::clone(&setup),
+            });
+        }
+        self
+    }
+
+    /// Sets the number of frames rendered after the setup of a shot before it is taken. Defaults
+    /// to [`DEFAULT_SETTLE_FRAMES`].
+    pub fn with_settle_frames(mut self, settle_frames: u32) -> Self {
+        self.settle_frames = settle_frames;
+        self
+    }
+
+    /// Compares every shot with the golden image `<name>.png` in the given directory. Shots that
+    /// don't match are reported as [mismatched](ScreenshotMatrixFinished::mismatched) and their
+    /// diff image is saved as `<name>.diff.png` next to the shot.
+    ///
+    /// Missing golden images are mismatches, unless the
+    /// [`UPDATE_GOLDEN_ENV`](crate::golden::UPDATE_GOLDEN_ENV) environment variable is set.
+    #[cfg(feature = "golden")]
+    pub fn with_golden(mut self, directory: impl Into<PathBuf>, tolerance: DiffTolerance) -> Self {
+        self.golden = Some((directory.into(), tolerance));
+        self
+    }
+}
+
+/// An event sent when all shots of a screenshot matrix were taken.
+#[derive(Debug, Clone, Event)]
+pub struct ScreenshotMatrixFinished {
+    /// The camera the screenshots were taken with.
+    pub camera: Entity,
+    /// The paths of the saved screenshots, in the order of the shots.
+    pub paths: Vec<PathBuf>,
+    /// The names of the shots that could not be saved. Failures are logged.
+    pub failed: Vec<String>,
+    /// The names of the shots that don't match their golden image.
+    #[cfg(feature = "golden")]
+    pub mismatched: Vec<String>,
+}
+
+#[derive(Default, Resource)]
+struct ScreenshotMatrices {
+    queue: VecDeque<TakeScreenshotMatrix>,
+    active: Option<ActiveMatrix>,
+}
+
+struct ActiveMatrix {
+    matrix: TakeScreenshotMatrix,
+    target: Handle<Image>,
+    original_size: Extent3d,
+    shot: usize,
+    phase: Phase,
+    paths: Vec<PathBuf>,
+    failed: Vec<String>,
+    #[cfg(feature = "golden")]
+    mismatched: Vec<String>,
+}
+
+enum Phase {
+    Setup,
+    Settling(u32),
+    Capturing(Arc<Mutex<Option<ShotOutcome>>>),
+}
+
+#[derive(Clone, Copy)]
+enum ShotOutcome {
+    Saved,
+    Failed,
+    #[cfg(feature = "golden")]
+    Mismatched,
+}
+
+fn update_matrices(world: &mut World) {
+    let events = world
+        .resource_mut::<Events<TakeScreenshotMatrix>>()
+        .drain()
+        .collect::<Vec<_>>();
+    world.resource_scope(|world, mut matrices: Mut<ScreenshotMatrices>| {
+        matrices.queue.extend(events);
+
+        loop {
+            if matrices.active.is_none() {
+                let Some(matrix) = matrices.queue.pop_front() else {
+                    return;
+                };
+                matrices.active = start_matrix(world, matrix);
+                continue;
+            }
+
+            let active = matrices.active.as_mut().unwrap();
+            if world.get::<Capture>(active.matrix.camera).is_none() {
+                warn!(
+                    "The screenshot camera {:?} was despawned",
+                    active.matrix.camera
+                );
+                matrices.active = None;
+                continue;
+            }
+            if active.update(world) {
+                let active = matrices.active.take().unwrap();
+                active.finish(world);
+                continue;
+            }
+            return;
+        }
+    });
+}
+
+fn start_matrix(world: &mut World, matrix: TakeScreenshotMatrix) -> Option<ActiveMatrix> {
+    let camera = matrix.camera;
+    if matrix.shots.is_empty() {
+        return None;
+    }
+    let Some(capture) = world.get::<Capture>(camera) else {
+        warn!("The screenshot camera {:?} has no capture", camera);
+        return None;
+    };
+    if capture.is_capturing() {
+        warn!("The screenshot camera {:?} is already capturing", camera);
+        return None;
+    }
+    let Some(RenderTarget::Image(target)) = world.get::<Camera>(camera).map(|c| &c.target) else {
+        warn!("The screenshot camera {:?} must render to an image", camera);
+        return None;
+    };
+    let target = target.clone();
+    let original_size = world
+        .resource::<Assets<Image>>()
+        .get(&target)?
+        .texture_descriptor
+        .size;
+
+    Some(ActiveMatrix {
+        matrix,
+        target,
+        original_size,
+        shot: 0,
+        phase: Phase::Setup,
+        paths: Vec::new(),
+        failed: Vec::new(),
+        #[cfg(feature = "golden")]
+        mismatched: Vec::new(),
+    })
+}
+
+impl ActiveMatrix {
+    /// Advances the matrix by one frame. Returns `true` once all shots were taken.
+    fn update(&mut self, world: &mut World) -> bool {
+        loop {
+            let shot = &self.matrix.shots[self.shot];
+            match &mut self.phase {
+                Phase::Setup => {
+                    (shot.setup)(world);
+                    self.resize(world, shot.size);
+                    self.phase = Phase::Settling(self.matrix.settle_frames);
+                    return false;
+                }
+                Phase::Settling(0) => {
+                    let saved = Arc::<Mutex<Option<ShotOutcome>>>::default();
+                    let encoder = ShotEncoder {
+                        path: self.path(shot),
+                        size: shot.size,
+                        saved: Arc::clone(&saved),
+                        #[cfg(feature = "golden")]
+                        golden: self.matrix.golden.as_ref().map(|(directory, tolerance)| {
+                            (directory.join(format!("{}.png", shot.name)), *tolerance)
+                        }),
+                    };
+                    let mut capture = world.get_mut::<Capture>(self.matrix.camera).unwrap();
+                    capture.start(encoder);
+                    self.phase = Phase::Capturing(saved);
+                    return false;
+                }
+                Phase::Settling(frames) => {
+                    *frames -= 1;
+                    return false;
+                }
+                Phase::Capturing(saved) => {
+                    let Some(outcome) = *saved.lock().unwrap() else {
+                        return false;
+                    };
+                    let mut capture = world.get_mut::<Capture>(self.matrix.camera).unwrap();
+                    capture.stop();
+                    match outcome {
+                        ShotOutcome::Saved => self.paths.push(self.path(shot)),
+                        ShotOutcome::Failed => self.failed.push(shot.name.clone()),
+                        #[cfg(feature = "golden")]
+                        ShotOutcome::Mismatched => {
+                            self.paths.push(self.path(shot));
+                            self.mismatched.push(shot.name.clone());
+                        }
+                    }
+
+                    self.shot += 1;
+                    if self.shot == self.matrix.shots.len() {
+                        return true;
+                    }
+                    // The next shot is set up in the same frame.
+                    self.phase = Phase::Setup;
+                }
+            }
+        }
+    }
+
+    fn finish(self, world: &mut World) {
+        self.resize(
+            world,
+            UVec2::new(self.original_size.width, self.original_size.height),
+        );
+        world.send_event(ScreenshotMatrixFinished {
+            camera: self.matrix.camera,
+            paths: self.paths,
+            failed: self.failed,
+            #[cfg(feature = "golden")]
+            mismatched: self.mismatched,
+        });
+    }
+
+    fn path(&self, shot: &Shot) -> PathBuf {
+        self.matrix.directory.join(format!("{}.png", shot.name))
+    }
+
+    fn resize(&self, world: &mut World, size: UVec2) {
+        let mut images = world.resource_mut::<Assets<Image>>();
+        if let Some(image) = images.get_mut(&self.target) {
+            if image.size() != size {
+                image.resize(Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                });
+            }
+        }
+    }
+}
+
+/// Saves the first frame with the expected size and compares it with the golden image, if any.
+struct ShotEncoder {
+    path: PathBuf,
+    size: UVec2,
+    saved: Arc<Mutex<Option<ShotOutcome>>>,
+    #[cfg(feature = "golden")]
+    golden: Option<(PathBuf, DiffTolerance)>,
+}
+
+impl Encoder for ShotEncoder {
+    fn encode(&mut self, image: &Image) -> encoder::Result<()> {
+        let mut saved = self.saved.lock().unwrap();
+        if saved.is_some() || image.size() != self.size {
+            return Ok(());
+        }
+
+        let result = encoder::to_dynamic_image(image).and_then(|dynamic_image| {
+            if let Some(directory) = self.path.parent() {
+                fs::create_dir_all(directory)?;
+            }
+            Ok(dynamic_image.save(&self.path)?)
+        });
+
+        // The shot is done even if saving failed, the error is logged by the capture.
+        *saved = Some(match result {
+            Ok(()) => self.compare(image),
+            Err(_) => ShotOutcome::Failed,
+        });
+
+        result
+    }
+
+    fn name(&self) -> &str {
+        "screenshot_matrix"
+    }
+}
+
+impl ShotEncoder {
+    #[cfg(not(feature = "golden"))]
+    fn compare(&self, _image: &Image) -> ShotOutcome {
+        ShotOutcome::Saved
+    }
+
+    #[cfg(feature = "golden")]
+    fn compare(&self, image: &Image) -> ShotOutcome {
+        let Some((golden, tolerance)) = &self.golden else {
+            return ShotOutcome::Saved;
+        };
+        match golden::diff_golden(image, golden, tolerance) {
+            Ok(diff) if diff.is_match() => ShotOutcome::Saved,
+            Ok(diff) => {
+                let diff_path = golden::diff_path(&self.path);
+                if let Err(err) = diff.diff_image.save(&diff_path) {
+                    bevy::log::error!("Failed to save {:?}: {:?}", diff_path, err);
+                }
+                ShotOutcome::Mismatched
+            }
+            Err(err) => {
+                bevy::log::error!("Failed to compare with {:?}: {:?}", golden, err);
+                ShotOutcome::Mismatched
+            }
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Control captures from scripts, e.g. to let designers trigger recordings from level scripts
+//! (feature `scripting`).
+//!
+//! Add the [`CaptureScriptingPlugin`] and call [`CaptureScriptQueue::start`],
+//! [`stop`](CaptureScriptQueue::stop) or [`screenshot`](CaptureScriptQueue::screenshot) from the
+//! functions registered with a scripting crate. The queue is `Send + Sync + 'static`, so it can be
+//! cloned into the closures of any scripting crate. The commands are applied in `PreUpdate`, the
+//! encoders of [`start`](CaptureScriptQueue::start) are created by name with the
+//! [`EncoderRegistry`].
+//!
+//! With the `lua` feature, [`CaptureScriptQueue::register_lua`] registers a global `capture` table
+//! with an [`mlua`] 0.9 Lua state, built with Lua 5.4 (the `lua54`, `vendored` and `send` features
+//! of mlua):
+//!
+//! ```lua
+//! capture.start("mp4_ffmpeg_cli", "boss_fight.mp4", '{ "framerate": 30 }')
+//! capture.screenshot("arena.png", "overview_camera")
+//! capture.stop()
+//! ```
+//!
+//! The state of another crate can only be passed if it uses the same mlua version with Lua 5.4,
+//! so cargo unifies both into one `mlua` crate. This is only tested with states created by the
+//! re-exported [`mlua`], not with the state of a scripting crate like `bevy_mod_scripting`. For
+//! crates that bundle another mlua version or Lua implementation, register functions that call the
+//! [`CaptureScriptQueue`] with their own API instead.
+//!
+//! Commands target the captures whose entity has the given [`Name`], or all captures if no name
+//! is given.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::scripting::{CaptureScriptQueue, CaptureScriptingPlugin};
+//! #
+//! app.add_plugins(CaptureScriptingPlugin);
+//!
+//! let queue = app.world().resource::<CaptureScriptQueue>().clone();
+//! queue.register_lua(&lua)?;
+//! ```
+
+use crate::{
+    encoder::{frames::FramesEncoder, registry::EncoderRegistry},
+    Capture,
+};
+use bevy::prelude::*;
+use std::{
+    fs::File,
+    mem,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "lua")]
+pub use mlua;
+
+/// The arguments of the Lua `capture.start` function: encoder, path, config and capture.
+#[cfg(feature = "lua")]
+type LuaStartArgs = (String, String, Option<String>, Option<String>);
+
+/// A Bevy plugin for controlling captures from scripts, see the [module docs](self).
+pub struct CaptureScriptingPlugin;
+
+impl Plugin for CaptureScriptingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CaptureScriptQueue>()
+            .init_resource::<EncoderRegistry>()
+            .add_systems(PreUpdate, apply_script_commands);
+    }
+}
+
+/// A command issued by a script, see [`CaptureScriptQueue`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptCommand {
+    /// Starts capturing with the encoder registered under the given name in the
+    /// [`EncoderRegistry`].
+    Start {
+        /// The name of the capture entity, or `None` for all captures.
+        capture: Option<String>,
+        /// The name of the encoder.
+        encoder: String,
+        /// The output path.
+        path: PathBuf,
+        /// The config of the encoder in JSON.
+        config: String,
+    },
+    /// Stops capturing.
+    Stop {
+        /// The name of the capture entity, or `None` for all captures.
+        capture: Option<String>,
+    },
+    /// Captures a single frame into a png.
+    Screenshot {
+        /// The name of the capture entity, or `None` for all captures.
+        capture: Option<String>,
+        /// The output path.
+        path: PathBuf,
+    },
+}
+
+impl ScriptCommand {
+    fn capture(&self) -> Option<&str> {
+        match self {
+            Self::Start { capture, .. }
+            | Self::Stop { capture }
+            | Self::Screenshot { capture, .. } => capture.as_deref(),
+        }
+    }
+}
+
+/// A queue of [`ScriptCommand`]s that are applied to the captures in `PreUpdate`. Clones share the
+/// same queue.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct CaptureScriptQueue {
+    commands: Arc<Mutex<Vec<ScriptCommand>>>,
+}
+
+impl CaptureScriptQueue {
+    /// Queues the given command.
+    pub fn push(&self, command: ScriptCommand) {
+        self.commands.lock().unwrap().push(command);
+    }
+
+    /// Starts capturing with the encoder registered under the given name, writing to the given
+    /// path and configured by the given config in JSON, e.g. `"{}"` for the defaults.
+    pub fn start(
+        &self,
+        capture: Option<&str>,
+        encoder: impl Into<String>,
+        path: impl Into<PathBuf>,
+        config: impl Into<String>,
+    ) {
+        self.push(ScriptCommand::Start {
+            capture: capture.map(str::to_string),
+            encoder: encoder.into(),
+            path: path.into(),
+            config: config.into(),
+        });
+    }
+
+    /// Stops capturing.
+    pub fn stop(&self, capture: Option<&str>) {
+        self.push(ScriptCommand::Stop {
+            capture: capture.map(str::to_string),
+        });
+    }
+
+    /// Captures the next frame into a png at the given path.
+    pub fn screenshot(&self, capture: Option<&str>, path: impl Into<PathBuf>) {
+        self.push(ScriptCommand::Screenshot {
+            capture: capture.map(str::to_string),
+            path: path.into(),
+        });
+    }
+
+    /// Registers a global `capture` table with `start(encoder, path, config?, capture?)`,
+    /// `stop(capture?)` and `screenshot(path, capture?)` functions with the given Lua state
+    /// (feature `lua`).
+    #[cfg(feature = "lua")]
+    pub fn register_lua(&self, lua: &mlua::Lua) -> mlua::Result<()> {
+        let table = lua.create_table()?;
+
+        let queue = self.clone();
+        table.set(
+            "start",
+            lua.create_function(move |_, (encoder, path, config, capture): LuaStartArgs| {
+                let config = config.unwrap_or_else(|| "{}".to_string());
+                queue.start(capture.as_deref(), encoder, path, config);
+                Ok(())
+            })?,
+        )?;
+
+        let queue = self.clone();
+        table.set(
+            "stop",
+            lua.create_function(move |_, capture: Option<String>| {
+                queue.stop(capture.as_deref());
+                Ok(())
+            })?,
+        )?;
+
+        let queue = self.clone();
+        table.set(
+            "screenshot",
+            lua.create_function(move |_, (path, capture): (String, Option<String>)| {
+                queue.screenshot(capture.as_deref(), path);
+                Ok(())
+            })?,
+        )?;
+
+        lua.globals().set("capture", table)
+    }
+
+    fn take(&self) -> Vec<ScriptCommand> {
+        mem::take(&mut *self.commands.lock().unwrap())
+    }
+}
+
+/// Marks a capture that takes a screenshot, which is stopped after the first frame.
+#[derive(Component)]
+struct ScriptScreenshot;
+
+fn apply_script_commands(
+    mut commands: Commands,
+    queue: Res<CaptureScriptQueue>,
+    registry: Res<EncoderRegistry>,
+    mut captures: Query<(Entity, &mut Capture, Option<&Name>, Has<ScriptScreenshot>)>,
+) {
+    for (entity, mut capture, _, screenshot) in &mut captures {
+        if screenshot && (capture.has_captured_frame() || !capture.is_capturing()) {
+            capture.stop();
+            commands.entity(entity).remove::<ScriptScreenshot>();
+        }
+    }
+
+    for command in queue.take() {
+        let targets = captures
+            .iter_mut()
+            .filter(|(_, _, name, _)| match command.capture() {
+                Some(capture) => name.is_some_and(|name| name.as_str() == capture),
+                None => true,
+            });
+        for (entity, mut capture, _, _) in targets {
+            if capture.is_capturing() && !matches!(command, ScriptCommand::Stop { .. }) {
+                warn!("Ignoring script command for capture {entity}, which is already capturing");
+                continue;
+            }
+            match &command {
+                ScriptCommand::Start {
+                    encoder,
+                    path,
+                    config,
+                    ..
+                } => match registry.create_from_str(encoder, path, config) {
+                    Ok(encoders) => {
+                        capture.start(encoders);
+                    }
+                    Err(err) => error!("Failed to start capture {entity} from script: {err}"),
+                },
+                ScriptCommand::Stop { .. } => capture.stop(),
+                ScriptCommand::Screenshot { path, .. } => {
+                    let path = path.clone();
+                    capture.start(FramesEncoder::new_with_writer(move |_| File::create(&path)));
+                    commands.entity(entity).insert(ScriptScreenshot);
+                }
+            }
+        }
+    }
+}
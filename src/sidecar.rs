@@ -0,0 +1,279 @@
+//! Write checksum and signature sidecars next to finished outputs, so automated pipelines can verify
+//! the integrity of artifacts after they were transferred from the render node.
+//!
+//! Every output file `<name>` gets a `<name>.sha256` in the format of `sha256sum`, and optionally a
+//! `<name>.minisig` signature that can be verified with [minisign](https://jedisct1.github.io/minisign/):
+//!
+//! ```sh
+//! sha256sum -c capture.mp4.sha256
+//! minisign -Vm capture.mp4 -P <public key>
+//! ```
+//!
+//! Wrap a file based encoder in a [`SidecarEncoder`] to write the sidecars when it finishes, or call
+//! [`write_checksum`] and [`write_signature`] for files written in other ways.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::{encoder::mp4_ffmpeg_cli::Mp4FfmpegCliEncoder, sidecar::*};
+//! #
+//! let key = SigningKey::from_hex(&std::env::var("CAPTURE_SIGNING_KEY")?)?;
+//! let encoder = SidecarEncoder::new(Mp4FfmpegCliEncoder::new("capture.mp4")?, "capture.mp4")
+//!     .with_signing_key(key);
+//! ```
+
+use crate::{
+    encoder::{capabilities::EncoderCapabilities, Encoder, Error, Result},
+    metadata::FrameMetadata,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bevy::prelude::*;
+use ring::{
+    rand::{SecureRandom, SystemRandom},
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt, fs,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// The extension of checksum sidecars.
+pub const CHECKSUM_EXTENSION: &str = "sha256";
+
+/// The extension of signature sidecars.
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// The signature algorithm of minisign, a plain Ed25519 signature of the file.
+const SIGNATURE_ALGORITHM: [u8; 2] = *b"Ed";
+
+/// An Ed25519 key to sign outputs in the minisign format.
+///
+/// The key id is derived from the public key, so the 32 byte seed is all that needs to be stored.
+pub struct SigningKey {
+    seed: [u8; 32],
+    key_pair: Ed25519KeyPair,
+    key_id: [u8; 8],
+}
+
+impl SigningKey {
+    /// Creates a key from a 32 byte seed.
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(&seed).unwrap();
+        let hash = Sha256::digest(key_pair.public_key().as_ref());
+        Self {
+            seed,
+            key_pair,
+            key_id: hash[..8].try_into().unwrap(),
+        }
+    }
+
+    /// Parses a key from a seed of 64 hexadecimal characters.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let hex = hex.trim();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err("a signing key must be 64 hexadecimal characters".into());
+        }
+        let mut seed = [0; 32];
+        for (byte, digits) in seed.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(digits).unwrap(), 16)
+                .map_err(|_| "a signing key must be 64 hexadecimal characters")?;
+        }
+        Ok(Self::from_seed(seed))
+    }
+
+    /// Generates a random key with the system random number generator.
+    pub fn generate() -> Result<Self> {
+        let mut seed = [0; 32];
+        SystemRandom::new()
+            .fill(&mut seed)
+            .map_err(|_| "failed to generate a random key")?;
+        Ok(Self::from_seed(seed))
+    }
+
+    /// Returns the seed as 64 hexadecimal characters, e.g. to store it in a secret manager.
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.seed)
+    }
+
+    /// Returns the public key in the minisign format, e.g. for `minisign -V -P <public key>`.
+    pub fn public_key(&self) -> String {
+        let mut public_key = Vec::with_capacity(42);
+        public_key.extend(SIGNATURE_ALGORITHM);
+        public_key.extend(self.key_id);
+        public_key.extend(self.key_pair.public_key().as_ref());
+        STANDARD.encode(public_key)
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never leak the key into logs.
+        f.debug_struct("SigningKey")
+            .field("public_key", &self.public_key())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Writes the SHA-256 checksum of the file to `<name>.sha256` and returns the path of the
+/// sidecar. The file is read in a streaming fashion.
+pub fn write_checksum(path: impl AsRef<Path>) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(fs::File::open(path)?), &mut hasher)?;
+
+    let sidecar = sidecar_path(path, CHECKSUM_EXTENSION);
+    fs::write(
+        &sidecar,
+        format!("{}  {}\n", to_hex(&hasher.finalize()), file_name(path)?),
+    )?;
+    Ok(sidecar)
+}
+
+/// Signs the file with the key, writes the signature to `<name>.minisig` and returns the path of the
+/// sidecar. The file is read into memory, as minisign signs the whole file at once.
+pub fn write_signature(path: impl AsRef<Path>, key: &SigningKey) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+
+    let mut signature = Vec::with_capacity(74);
+    signature.extend(SIGNATURE_ALGORITHM);
+    signature.extend(key.key_id);
+    signature.extend(key.key_pair.sign(&data).as_ref());
+
+    // The trusted comment is signed together with the signature, so it can't be swapped.
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let trusted_comment = format!("timestamp:{timestamp}\tfile:{}", file_name(path)?);
+    let mut global = signature[10..].to_vec();
+    global.extend(trusted_comment.as_bytes());
+    let global_signature = key.key_pair.sign(&global);
+
+    let sidecar = sidecar_path(path, SIGNATURE_EXTENSION);
+    fs::write(
+        &sidecar,
+        format!(
+            "untrusted comment: signature from bevy_capture key {}\n{}\ntrusted comment: {}\n{}\n",
+            to_hex(&key.key_id),
+            STANDARD.encode(signature),
+            trusted_comment,
+            STANDARD.encode(global_signature),
+        ),
+    )?;
+    Ok(sidecar)
+}
+
+/// Returns the path of a sidecar, `<name>.<extension>` in the same directory.
+pub fn sidecar_path(path: impl AsRef<Path>, extension: &str) -> PathBuf {
+    let mut sidecar = path.as_ref().as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(extension);
+    sidecar.into()
+}
+
+/// An encoder that writes checksum and signature sidecars when the inner encoder finishes.
+///
+/// The path is the output of the inner encoder. If it is a directory, e.g. of a
+/// [`FramesEncoder`](crate::encoder::frames::FramesEncoder), every file in it gets sidecars.
+pub struct SidecarEncoder<E> {
+    inner: E,
+    path: PathBuf,
+    signing_key: Option<SigningKey>,
+}
+
+impl<E: Encoder> SidecarEncoder<E> {
+    /// Creates a new sidecar encoder for the output of the inner encoder at the given path.
+    pub fn new(inner: E, path: impl Into<PathBuf>) -> Self {
+        Self {
+            inner,
+            path: path.into(),
+            signing_key: None,
+        }
+    }
+
+    /// Signs the outputs with the key in addition to the checksums.
+    pub fn with_signing_key(mut self, key: SigningKey) -> Self {
+        self.signing_key = Some(key);
+        self
+    }
+}
+
+impl<E: Encoder> Encoder for SidecarEncoder<E> {
+    fn encode(&mut self, image: &Image) -> Result<()> {
+        self.inner.encode(image)
+    }
+
+    fn encode_with_metadata(&mut self, image: &Image, metadata: &FrameMetadata) -> Result<()> {
+        self.inner.encode_with_metadata(image, metadata)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn capabilities(&self) -> EncoderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn describe_output(&self) -> String {
+        self.inner.describe_output()
+    }
+
+    fn finish(self: Box<Self>) {
+        let Self {
+            inner,
+            path,
+            signing_key,
+        } = *self;
+        // The output is complete once the inner encoder is finished and dropped.
+        Box::new(inner).finish();
+
+        if let Err(err) = write_sidecars(&path, signing_key.as_ref()) {
+            error!("Failed to write sidecars for {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn write_sidecars(path: &Path, signing_key: Option<&SigningKey>) -> Result<()> {
+    let files = if path.is_dir() {
+        let mut files = fs::read_dir(path)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        files.retain(|file| file.is_file() && !is_sidecar(file));
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    for file in files {
+        write_checksum(&file)?;
+        if let Some(key) = signing_key {
+            write_signature(&file, key)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_sidecar(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        extension == CHECKSUM_EXTENSION || extension == SIGNATURE_EXTENSION
+    })
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| Error::format(format!("{} is not a file", path.display())))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
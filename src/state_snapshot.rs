@@ -0,0 +1,305 @@
+//! Record snapshots of the game state next to a capture, so footage can be correlated with the
+//! exact state of the game when reviewing bugs.
+//!
+//! Attach a [`StateRecorder`] next to the [`Capture`] and select the components to record. The
+//! components need to implement [`Reflect`]. While the capture is running, the
+//! [`StateSnapshotPlugin`] serializes the selected components of all entities every `interval`
+//! captured frames into a sidecar file in the [JSON Lines](https://jsonlines.org/) format, one
+//! snapshot per line:
+//!
+//! ```json
+//! {"frame":0,"entities":[{"entity":"4v1","name":"Player","components":{"bevy_transform::components::transform::Transform":{...}}}]}
+//! ```
+//!
+//! The frame is the index of the captured frame, e.g. the index of the image of a
+//! [`FramesEncoder`](crate::encoder::frames::FramesEncoder). Paused frames and frames outside of a
+//! [`CaptureRange`] are not counted. Use [`read_snapshot`] to look up the state of a frame.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{state_snapshot::*, CaptureBundle};
+//! #
+//! app.add_plugins(StateSnapshotPlugin);
+//!
+//! commands.spawn((
+//!     Camera2dBundle::default(),
+//!     CaptureBundle::default(),
+//!     StateRecorder::new("captures/state.jsonl")
+//!         .with_component::<Transform>()
+//!         .with_interval(10),
+//! ));
+//! ```
+
+use crate::{
+    encoder::{Error, Result},
+    range::CaptureRange,
+    Capture,
+};
+use bevy::{
+    ecs::world::EntityRef,
+    prelude::*,
+    reflect::{serde::TypedReflectSerializer, GetTypeRegistration, TypeRegistry},
+    transform::TransformSystem,
+    utils::Instant,
+};
+use serde_json::{json, Map, Value};
+use std::{
+    any::TypeId,
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// A Bevy plugin that records the snapshots of [`StateRecorder`]s.
+pub struct StateSnapshotPlugin;
+
+impl Plugin for StateSnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            record_snapshots
+                .after(crate::range::update_capture_ranges)
+                .after(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// Records the selected components every `interval` captured frames, see the
+/// [module docs](self). This is optional and can be attached next to the [`Capture`].
+#[derive(Component)]
+pub struct StateRecorder {
+    path: PathBuf,
+    interval: u64,
+    components: Vec<RecordedComponent>,
+    position: Option<RecorderPosition>,
+    writer: Option<BufWriter<File>>,
+}
+
+/// A component selected with [`StateRecorder::with_component`].
+struct RecordedComponent {
+    type_id: TypeId,
+    type_path: &'static str,
+    register: fn(&mut TypeRegistry),
+    reflect: for<'w> fn(EntityRef<'w>) -> Option<&'w dyn Reflect>,
+}
+
+/// The position of the running capture.
+#[derive(Debug, Clone, Copy)]
+struct RecorderPosition {
+    started_at: Instant,
+    frames: u64,
+}
+
+impl StateRecorder {
+    /// Creates a new recorder that writes the snapshots to the file at the given path. The file
+    /// is overwritten every time the capture is started.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            interval: 1,
+            components: Vec::new(),
+            position: None,
+            writer: None,
+        }
+    }
+
+    /// Sets the number of captured frames between snapshots. Defaults to `1`, a snapshot of every
+    /// frame.
+    pub fn with_interval(mut self, interval: u64) -> Self {
+        self.interval = interval.max(1);
+        self
+    }
+
+    /// Adds a component to the snapshots. The component is registered in the type registry if it
+    /// isn't already.
+    pub fn with_component<T: Component + Reflect + TypePath + GetTypeRegistration>(
+        mut self,
+    ) -> Self {
+        self.components.push(RecordedComponent {
+            type_id: TypeId::of::<T>(),
+            type_path: T::type_path(),
+            register: |registry| registry.register::<T>(),
+            reflect: |entity| entity.get::<T>().map(|component| component as &dyn Reflect),
+        });
+        self
+    }
+
+    /// Returns the path of the file the snapshots are written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of captured frames between snapshots.
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+}
+
+fn record_snapshots(world: &mut World) {
+    let recorders = world
+        .query_filtered::<Entity, With<StateRecorder>>()
+        .iter(world)
+        .collect::<Vec<_>>();
+
+    for entity in recorders {
+        let Some(frame) = advance(world, entity) else {
+            continue;
+        };
+        let recorder = world.get::<StateRecorder>(entity).unwrap();
+        if frame % recorder.interval != 0 {
+            continue;
+        }
+        let snapshot = snapshot(world, frame, &recorder.components);
+
+        let mut recorder = world.get_mut::<StateRecorder>(entity).unwrap();
+        let Some(writer) = &mut recorder.writer else {
+            continue;
+        };
+        // Every snapshot is flushed, so it is available even if the app crashes.
+        let result = serde_json::to_writer(&mut *writer, &snapshot)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(writer))
+            .and_then(|()| writer.flush());
+        if let Err(err) = result {
+            error!(
+                "Failed to write the state snapshot to {}: {}",
+                recorder.path.display(),
+                err
+            );
+            recorder.writer = None;
+        }
+    }
+}
+
+/// Advances the recorder by a frame. Returns the index of the captured frame, or `None` if no
+/// frame is captured or the recorder failed.
+fn advance(world: &mut World, entity: Entity) -> Option<u64> {
+    let (started_at, paused) = world
+        .get::<Capture>(entity)
+        .map(|capture| (capture.started_at(), capture.is_paused()))
+        .unwrap_or_default();
+    let in_range = world
+        .get::<CaptureRange>(entity)
+        .is_none_or(|range| range.is_in_range());
+
+    let Some(started_at) = started_at else {
+        let mut recorder = world.get_mut::<StateRecorder>(entity).unwrap();
+        if recorder.position.is_some() {
+            // Dropping the writer flushes the file.
+            recorder.position = None;
+            recorder.writer = None;
+        }
+        return None;
+    };
+
+    let restarted = world
+        .get::<StateRecorder>(entity)
+        .unwrap()
+        .position
+        .is_none_or(|position| position.started_at != started_at);
+    if restarted {
+        // The components can only be serialized if they are registered.
+        let registry = world.resource::<AppTypeRegistry>().clone();
+        let mut registry = registry.write();
+        let mut recorder = world.get_mut::<StateRecorder>(entity).unwrap();
+        for component in &recorder.components {
+            (component.register)(&mut registry);
+        }
+        recorder.writer = match File::create(&recorder.path) {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(err) => {
+                error!(
+                    "Failed to create the state snapshot file {}: {}",
+                    recorder.path.display(),
+                    err
+                );
+                None
+            }
+        };
+        recorder.position = Some(RecorderPosition {
+            started_at,
+            frames: 0,
+        });
+    }
+
+    if paused || !in_range {
+        return None;
+    }
+    let mut recorder = world.get_mut::<StateRecorder>(entity).unwrap();
+    let position = recorder.position.as_mut().unwrap();
+    let frame = position.frames;
+    position.frames += 1;
+    recorder.writer.is_some().then_some(frame)
+}
+
+/// Serializes the components of all entities that have at least one of them.
+fn snapshot(world: &World, frame: u64, components: &[RecordedComponent]) -> Value {
+    let registry = world.resource::<AppTypeRegistry>().read();
+    let mut entities = BTreeMap::<Entity, Map<String, Value>>::new();
+
+    for component in components {
+        let Some(component_id) = world.components().get_id(component.type_id) else {
+            // No entity ever had the component.
+            continue;
+        };
+        for archetype in world
+            .archetypes()
+            .iter()
+            .filter(|archetype| archetype.contains(component_id))
+        {
+            for archetype_entity in archetype.entities() {
+                let entity = archetype_entity.id();
+                let Some(value) = (component.reflect)(world.entity(entity)) else {
+                    continue;
+                };
+                match serde_json::to_value(TypedReflectSerializer::new(value, &registry)) {
+                    Ok(value) => {
+                        entities
+                            .entry(entity)
+                            .or_default()
+                            .insert(component.type_path.to_owned(), value);
+                    }
+                    Err(err) => {
+                        warn_once!("Failed to serialize {}: {}", component.type_path, err);
+                    }
+                }
+            }
+        }
+    }
+
+    let entities = entities
+        .into_iter()
+        .map(|(entity, components)| {
+            json!({
+                "entity": entity.to_string(),
+                "name": world.get::<Name>(entity).map(Name::as_str),
+                "components": components,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "frame": frame, "entities": entities })
+}
+
+/// Reads the snapshot of the given frame from a file written by a [`StateRecorder`]. If there is
+/// no snapshot of the frame, the latest snapshot before it is returned, as it is the most recent
+/// known state.
+pub fn read_snapshot(path: impl AsRef<Path>, frame: u64) -> Result<Option<Value>> {
+    let mut latest = None;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let snapshot = serde_json::from_str::<Value>(&line).map_err(Error::custom)?;
+        let snapshot_frame = snapshot["frame"]
+            .as_u64()
+            .ok_or_else(|| Error::format("a state snapshot has no frame index"))?;
+        if snapshot_frame > frame {
+            break;
+        }
+        latest = Some(snapshot);
+    }
+    Ok(latest)
+}
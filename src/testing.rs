@@ -0,0 +1,180 @@
+//! A harness for running tiny headless captures, e.g. in integration tests.
+//!
+//! The harness prefers a software adapter (lavapipe, SwiftShader, WARP) if one is available,
+//! so results are reproducible across machines, and falls back to any other adapter otherwise.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::testing::HeadlessHarness;
+//! #
+//! // Fail instead of silently passing on machines without an adapter.
+//! let mut harness = HeadlessHarness::new(64, 64).expect("no wgpu adapter");
+//! harness.capture(10, my_encoder);
+//! ```
+
+use crate::{CameraTargetHeadless, Capture, CaptureBundle, CapturePlugin, IntoEncoders};
+use bevy::{
+    app::{Plugins, PluginsState},
+    core::{FrameCountPlugin, TaskPoolPlugin, TypeRegistrationPlugin},
+    core_pipeline::CorePipelinePlugin,
+    prelude::*,
+    render::{
+        renderer::{initialize_renderer, RenderInstance, WgpuWrapper},
+        settings::{RenderCreation, WgpuSettings},
+        RenderPlugin,
+    },
+    tasks::{block_on, tick_global_task_pools_on_main_thread},
+    time::TimePlugin,
+    window::ExitCondition,
+};
+use std::{fmt, sync::Arc};
+
+/// The error returned if no adapter is available. Tests should fail in this case rather than
+/// pass without rendering anything, install a software adapter like lavapipe on CI.
+#[derive(Debug)]
+pub struct NoAdapterError;
+
+impl fmt::Display for NoAdapterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no wgpu adapter available")
+    }
+}
+
+impl std::error::Error for NoAdapterError {}
+
+/// A headless app with a single capturing camera.
+pub struct HeadlessHarness {
+    app: App,
+    camera: Entity,
+}
+
+impl HeadlessHarness {
+    /// Creates a new harness with a camera rendering to a headless image of the given dimensions.
+    pub fn new(width: u32, height: u32) -> Result<Self, NoAdapterError> {
+        Self::new_with_plugins(width, height, ())
+    }
+
+    /// Creates a new harness with additional plugins, e.g. `SpritePlugin` to render something
+    /// more interesting than the clear color.
+    pub fn new_with_plugins<M>(
+        width: u32,
+        height: u32,
+        plugins: impl Plugins<M>,
+    ) -> Result<Self, NoAdapterError> {
+        Self::new_with_capture_plugin(width, height, CapturePlugin::default(), plugins)
+    }
+
+    /// Creates a new harness with a configured [`CapturePlugin`] and additional plugins.
+    pub fn new_with_capture_plugin<M>(
+        width: u32,
+        height: u32,
+        capture_plugin: CapturePlugin,
+        plugins: impl Plugins<M>,
+    ) -> Result<Self, NoAdapterError> {
+        let render_creation = create_renderer()?;
+
+        let mut app = App::new();
+        app.add_plugins((
+            TaskPoolPlugin::default(),
+            TypeRegistrationPlugin,
+            FrameCountPlugin,
+            TimePlugin,
+            TransformPlugin,
+            HierarchyPlugin,
+            WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            },
+            AssetPlugin::default(),
+            RenderPlugin {
+                render_creation,
+                synchronous_pipeline_compilation: true,
+            },
+            ImagePlugin::default(),
+            CorePipelinePlugin,
+            capture_plugin,
+        ))
+        .add_plugins(plugins);
+
+        while app.plugins_state() == PluginsState::Adding {
+            tick_global_task_pools_on_main_thread();
+        }
+        app.finish();
+        app.cleanup();
+
+        let mut images = app.world_mut().resource_mut::<Assets<Image>>();
+        let camera = Camera2dBundle::default().target_headless(width, height, &mut images);
+        let camera = app
+            .world_mut()
+            .spawn((camera, CaptureBundle::default()))
+            .id();
+
+        Ok(Self { app, camera })
+    }
+
+    /// Returns the app.
+    pub fn app(&self) -> &App {
+        &self.app
+    }
+
+    /// Returns the app mutably, e.g. to spawn entities or insert resources.
+    pub fn app_mut(&mut self) -> &mut App {
+        &mut self.app
+    }
+
+    /// Returns the capturing camera.
+    pub fn camera(&self) -> Entity {
+        self.camera
+    }
+
+    /// Captures the given number of frames with the given encoders. The encoders are finished
+    /// before this method returns.
+    pub fn capture(&mut self, frames: u32, encoders: impl IntoEncoders) {
+        self.capture_mut().start(encoders);
+        for _ in 0..frames {
+            self.app.update();
+        }
+
+        self.capture_mut().stop();
+        self.app.update();
+    }
+
+    fn capture_mut(&mut self) -> Mut<'_, Capture> {
+        self.app
+            .world_mut()
+            .get_mut::<Capture>(self.camera)
+            .expect("the camera has no capture component")
+    }
+}
+
+fn create_renderer() -> Result<RenderCreation, NoAdapterError> {
+    let settings = WgpuSettings::default();
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: settings.backends.unwrap_or(wgpu::Backends::all()),
+        dx12_shader_compiler: settings.dx12_shader_compiler.clone(),
+        flags: settings.instance_flags,
+        gles_minor_version: settings.gles3_minor_version,
+    });
+
+    let options = |force_fallback_adapter| wgpu::RequestAdapterOptions {
+        power_preference: settings.power_preference,
+        force_fallback_adapter,
+        compatible_surface: None,
+    };
+    let options = [options(true), options(false)]
+        .into_iter()
+        .find(|options| block_on(instance.request_adapter(options)).is_some())
+        .ok_or(NoAdapterError)?;
+
+    let (device, queue, adapter_info, adapter) =
+        block_on(initialize_renderer(&instance, &settings, &options));
+
+    Ok(RenderCreation::manual(
+        device,
+        queue,
+        adapter_info,
+        adapter,
+        RenderInstance(Arc::new(WgpuWrapper::new(instance))),
+    ))
+}
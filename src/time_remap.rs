@@ -0,0 +1,113 @@
+//! Advance the game time by a multiple of the frame duration per captured frame, e.g. to render a
+//! 10 minute simulation as a 1 minute video.
+//!
+//! While a capture is running, the [`TimeRemapPlugin`] advances `Time<Real>` by exactly one frame
+//! duration of the video per frame and `Time<Virtual>` by `speed` frame durations, independent of
+//! how long the frame took to render. A speed of `10.0` records the simulation at 10x speed, a
+//! speed of `0.25` in slow motion. Fixed timestep systems run as often as the virtual time
+//! requires. The previous `TimeUpdateStrategy` and relative speed are restored when no capture is
+//! running anymore.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy_capture::time_remap::*;
+//! #
+//! app.add_plugins(TimeRemapPlugin)
+//!     .insert_resource(TimeRemap::new(60).with_speed(10.0));
+//! ```
+
+use crate::Capture;
+use bevy::{prelude::*, time::TimeUpdateStrategy, utils::Duration};
+use std::mem;
+
+/// A Bevy plugin that remaps the time while capturing, according to the [`TimeRemap`] resource.
+pub struct TimeRemapPlugin;
+
+impl Plugin for TimeRemapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimeUpdateStrategy>()
+            .add_systems(Last, remap_time.run_if(resource_exists::<TimeRemap>));
+    }
+}
+
+/// The framerate of the captured video and the speed the game time advances at while capturing.
+#[derive(Resource)]
+pub struct TimeRemap {
+    frame_duration: Duration,
+    speed: f64,
+    previous: Option<PreviousTime>,
+}
+
+/// The time settings before the first capture started.
+struct PreviousTime {
+    strategy: TimeUpdateStrategy,
+    speed: f64,
+}
+
+impl TimeRemap {
+    /// Creates a new time remap for a video with the given framerate, at normal speed.
+    pub fn new(framerate: u32) -> Self {
+        Self {
+            frame_duration: Duration::from_secs_f64(1.0 / framerate.max(1) as f64),
+            speed: 1.0,
+            previous: None,
+        }
+    }
+
+    /// Sets how many frame durations the game time advances per captured frame. Defaults to `1.0`.
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.set_speed(speed);
+        self
+    }
+
+    /// Changes the speed, e.g. to slow down for an interesting part of the simulation. The speed
+    /// applies from the next frame on.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.max(0.0);
+    }
+
+    /// Returns the duration of a frame of the video.
+    pub fn frame_duration(&self) -> Duration {
+        self.frame_duration
+    }
+
+    /// Returns how many frame durations the game time advances per captured frame.
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Returns `true` if the time is currently remapped.
+    pub fn is_active(&self) -> bool {
+        self.previous.is_some()
+    }
+}
+
+fn remap_time(
+    mut remap: ResMut<TimeRemap>,
+    mut strategy: ResMut<TimeUpdateStrategy>,
+    mut time: ResMut<Time<Virtual>>,
+    captures: Query<&Capture>,
+) {
+    // The time is updated at the start of the frame, so this applies to the next frame.
+    let capturing = captures
+        .iter()
+        .any(|capture| capture.is_capturing() && !capture.is_paused());
+
+    if capturing {
+        let frame_duration = remap.frame_duration;
+        let previous = mem::replace(
+            &mut *strategy,
+            TimeUpdateStrategy::ManualDuration(frame_duration),
+        );
+        if remap.previous.is_none() {
+            remap.previous = Some(PreviousTime {
+                strategy: previous,
+                speed: time.relative_speed_f64(),
+            });
+        }
+        time.set_relative_speed_f64(remap.speed);
+    } else if let Some(previous) = remap.previous.take() {
+        *strategy = previous.strategy;
+        time.set_relative_speed_f64(previous.speed);
+    }
+}
@@ -0,0 +1,189 @@
+//! Capture a single UI hierarchy, e.g. to generate screenshots of UI screens in every language.
+//!
+//! Attach a [`UiCapture`] to a root UI node and the [`UiCapturePlugin`] spawns a dedicated camera
+//! rendering only that hierarchy to a headless image. The node is laid out at the size of the
+//! capture, independent of the window. The camera and the nodes of the hierarchy are moved to the
+//! [`UI_CAPTURE_LAYER`], so sprites and meshes of the scene are not part of the frames.
+//!
+//! # Example
+//! ```ignore
+//! # use bevy::prelude::*;
+//! # use bevy_capture::{encoder::frames::FramesEncoder, ui::*};
+//! #
+//! app.add_plugins(UiCapturePlugin);
+//!
+//! fn setup(mut commands: Commands) {
+//!     commands.spawn((
+//!         NodeBundle {
+//!             style: Style {
+//!                 width: Val::Percent(100.0),
+//!                 height: Val::Percent(100.0),
+//!                 ..default()
+//!             },
+//!             ..default()
+//!         },
+//!         UiCapture::new(1280, 720).with_encoders(FramesEncoder::new("captures/settings_menu")),
+//!     ));
+//! }
+//! ```
+
+use crate::{BoxedEncoder, CameraTargetHeadless, CaptureBundle, IntoEncoders};
+use bevy::{
+    prelude::*,
+    render::{
+        camera::{CameraUpdateSystem, RenderTarget},
+        view::{Layer, RenderLayers},
+    },
+    ui::update::update_target_camera_system,
+};
+
+/// The render layer of the cameras and nodes of all UI captures. Entities on this layer are
+/// rendered by every UI capture.
+pub const UI_CAPTURE_LAYER: Layer = 31;
+
+/// A Bevy plugin that spawns the cameras of [`UiCapture`] nodes.
+pub struct UiCapturePlugin;
+
+impl Plugin for UiCapturePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (despawn_ui_cameras, spawn_ui_cameras, assign_render_layers)
+                .chain()
+                .before(update_target_camera_system)
+                .before(CameraUpdateSystem),
+        );
+    }
+}
+
+/// Captures the UI hierarchy below the node this is attached to. The node must be a root node,
+/// i.e. it must not have a parent.
+///
+/// The camera has a [`Capture`](crate::Capture), which can be started with
+/// [`with_encoders`](Self::with_encoders) or later through [`camera`](Self::camera). Removing this
+/// component or despawning the node despawns the camera.
+#[derive(Component)]
+pub struct UiCapture {
+    width: u32,
+    height: u32,
+    clear_color: Color,
+    encoders: Option<Vec<BoxedEncoder>>,
+    camera: Option<Entity>,
+}
+
+impl UiCapture {
+    /// Creates a new UI capture with the given dimensions and a transparent background.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            clear_color: Color::NONE,
+            encoders: None,
+            camera: None,
+        }
+    }
+
+    /// Sets the background color of the frames.
+    pub fn with_clear_color(mut self, clear_color: impl Into<Color>) -> Self {
+        self.clear_color = clear_color.into();
+        self
+    }
+
+    /// Starts capturing with the given encoders as soon as the camera is spawned.
+    pub fn with_encoders(mut self, encoders: impl IntoEncoders) -> Self {
+        self.encoders = Some(encoders.into_encoders());
+        self
+    }
+
+    /// Returns the camera rendering the node, or `None` if it has not been spawned yet.
+    pub fn camera(&self) -> Option<Entity> {
+        self.camera
+    }
+}
+
+/// Marks the camera of a [`UiCapture`].
+#[derive(Component)]
+struct UiCaptureCamera {
+    node: Entity,
+}
+
+fn spawn_ui_cameras(
+    mut commands: Commands,
+    mut nodes: Query<(Entity, &mut UiCapture, Has<Parent>)>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for (node, mut ui_capture, has_parent) in &mut nodes {
+        if ui_capture.camera.is_some() {
+            continue;
+        }
+        if has_parent {
+            warn_once!("UiCapture must be attached to a root node, {node} has a parent");
+        }
+
+        let mut capture = CaptureBundle::default();
+        if let Some(encoders) = ui_capture.encoders.take() {
+            capture.capture.start(encoders);
+        }
+        let camera = commands
+            .spawn((
+                Camera2dBundle {
+                    camera: Camera {
+                        clear_color: ClearColorConfig::Custom(ui_capture.clear_color),
+                        ..default()
+                    },
+                    ..default()
+                }
+                .target_headless(ui_capture.width, ui_capture.height, &mut images),
+                RenderLayers::layer(UI_CAPTURE_LAYER),
+                capture,
+                UiCaptureCamera { node },
+            ))
+            .id();
+        commands.entity(node).insert(TargetCamera(camera));
+        ui_capture.camera = Some(camera);
+    }
+}
+
+fn despawn_ui_cameras(
+    mut commands: Commands,
+    cameras: Query<(Entity, &UiCaptureCamera, &Camera)>,
+    nodes: Query<&UiCapture>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for (camera, ui_camera, camera_settings) in &cameras {
+        if nodes
+            .get(ui_camera.node)
+            .is_ok_and(|ui_capture| ui_capture.camera == Some(camera))
+        {
+            continue;
+        }
+
+        commands.entity(camera).despawn_recursive();
+        if let RenderTarget::Image(target) = &camera_settings.target {
+            images.remove(target);
+        }
+        if let Some(mut node) = commands.get_entity(ui_camera.node) {
+            node.remove::<TargetCamera>();
+        }
+    }
+}
+
+/// Moves all nodes of the captured hierarchies to the [`UI_CAPTURE_LAYER`], so they are visible to
+/// the cameras. Runs every frame to include nodes spawned later.
+fn assign_render_layers(
+    mut commands: Commands,
+    roots: Query<Entity, With<UiCapture>>,
+    children: Query<&Children>,
+    nodes: Query<Option<&RenderLayers>, With<Node>>,
+) {
+    let layers = RenderLayers::layer(UI_CAPTURE_LAYER);
+    for root in &roots {
+        for node in std::iter::once(root).chain(children.iter_descendants(root)) {
+            if let Ok(node_layers) = nodes.get(node) {
+                if node_layers != Some(&layers) {
+                    commands.entity(node).insert(layers.clone());
+                }
+            }
+        }
+    }
+}
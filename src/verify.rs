@@ -0,0 +1,309 @@
+//! Inspect encoded outputs, e.g. to validate captures in tests without shelling out to ffprobe.
+
+use crate::encoder::Result;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+/// Basic properties of an encoded video or animation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaInfo {
+    /// The codec of the (first) video stream, e.g. `"avc1"` or `"gif"`.
+    pub codec: String,
+    /// The width in pixels.
+    pub width: u32,
+    /// The height in pixels.
+    pub height: u32,
+    /// The number of frames.
+    pub frame_count: u32,
+    /// The total duration.
+    pub duration: Duration,
+}
+
+/// Inspects the file at the given path. The format is detected by the file extension.
+pub fn inspect_file(path: impl AsRef<Path>) -> Result<MediaInfo> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    let reader = BufReader::new(File::open(path)?);
+
+    match extension.as_deref() {
+        Some("mp4" | "m4v" | "mov") => inspect_mp4(reader),
+        #[cfg(feature = "gif")]
+        Some("gif") => inspect_gif(reader),
+        _ => Err(format!("unsupported file format: {}", path.display()).into()),
+    }
+}
+
+/// Inspects an MP4 file. Only the first video track is taken into account.
+pub fn inspect_mp4(mut reader: impl Read + Seek) -> Result<MediaInfo> {
+    // The size of the moov box is checked against the length of the file before it is read into
+    // memory, so a corrupt size can't allocate more than the file.
+    let start = reader.stream_position()?;
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(start))?;
+
+    let moov = loop {
+        let (kind, size) = match read_box_header(&mut reader)? {
+            Some(header) => header,
+            None => return Err("mp4 has no moov box".into()),
+        };
+        if &kind == b"moov" {
+            let size = size.ok_or("moov box has no size")?;
+            let remaining = len.saturating_sub(reader.stream_position()?);
+            if size > remaining {
+                return Err(format!(
+                    "moov box of {size} bytes exceeds the remaining {remaining} bytes of the file"
+                )
+                .into());
+            }
+            let mut moov = vec![0; size as usize];
+            reader.read_exact(&mut moov)?;
+            break moov;
+        }
+        match size {
+            Some(size) => reader.seek(SeekFrom::Current(size as i64))?,
+            None => return Err("mp4 has no moov box".into()),
+        };
+    };
+
+    for (_, trak) in children(&moov)?
+        .into_iter()
+        .filter(|(kind, _)| kind == b"trak")
+    {
+        let mdia = child(trak, b"mdia")?;
+        let hdlr = child(mdia, b"hdlr")?;
+        if hdlr.get(8..12) != Some(b"vide") {
+            continue;
+        }
+
+        let tkhd = child(trak, b"tkhd")?;
+        let dimensions = tkhd.len().checked_sub(8).ok_or("tkhd box is too short")?;
+        let width = read_u32(tkhd, dimensions)? >> 16;
+        let height = read_u32(tkhd, dimensions + 4)? >> 16;
+
+        let mdhd = child(mdia, b"mdhd")?;
+        let (timescale, duration) = match mdhd.first() {
+            Some(1) => (read_u32(mdhd, 20)?, read_u64(mdhd, 24)?),
+            _ => (read_u32(mdhd, 12)?, read_u32(mdhd, 16)? as u64),
+        };
+        if timescale == 0 {
+            return Err("mdhd box has a timescale of zero".into());
+        }
+
+        let stbl = child(child(mdia, b"minf")?, b"stbl")?;
+        let stsd = child(stbl, b"stsd")?;
+        let codec = stsd.get(12..16).ok_or("stsd box is too short")?;
+        let frame_count = read_u32(child(stbl, b"stsz")?, 8)?;
+
+        return Ok(MediaInfo {
+            codec: String::from_utf8_lossy(codec).into_owned(),
+            width,
+            height,
+            frame_count,
+            duration: Duration::from_secs_f64(duration as f64 / timescale as f64),
+        });
+    }
+
+    Err("mp4 has no video track".into())
+}
+
+/// Inspects a GIF file.
+#[cfg(feature = "gif")]
+pub fn inspect_gif(reader: impl std::io::BufRead + Seek) -> Result<MediaInfo> {
+    use image::{codecs::gif::GifDecoder, AnimationDecoder, ImageDecoder};
+
+    let decoder = GifDecoder::new(reader)?;
+    let (width, height) = decoder.dimensions();
+
+    let mut frame_count = 0;
+    let mut duration = Duration::ZERO;
+    for frame in decoder.into_frames() {
+        let frame = frame?;
+        frame_count += 1;
+        duration += Duration::from(frame.delay());
+    }
+
+    Ok(MediaInfo {
+        codec: "gif".to_string(),
+        width,
+        height,
+        frame_count,
+        duration,
+    })
+}
+
+/// Reads a box header and returns the box type and the size of its payload, if known.
+fn read_box_header(reader: &mut impl Read) -> Result<Option<([u8; 4], Option<u64>)>> {
+    let mut header = [0; 8];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let size = read_u32(&header, 0)? as u64;
+    let kind = [header[4], header[5], header[6], header[7]];
+    let size = match size {
+        0 => None,
+        1 => {
+            let mut large_size = [0; 8];
+            reader.read_exact(&mut large_size)?;
+            Some(
+                u64::from_be_bytes(large_size)
+                    .checked_sub(16)
+                    .ok_or("invalid box size")?,
+            )
+        }
+        size => Some(size.checked_sub(8).ok_or("invalid box size")?),
+    };
+
+    Ok(Some((kind, size)))
+}
+
+fn children(mut data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut children = Vec::new();
+    while !data.is_empty() {
+        let (kind, size) = read_box_header(&mut data)?.ok_or("truncated box")?;
+        let size = size.map_or(data.len(), |size| size as usize);
+        let payload = data.get(..size).ok_or("truncated box")?;
+        children.push((kind, payload));
+        data = &data[size..];
+    }
+    Ok(children)
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Result<&'a [u8]> {
+    children(data)?
+        .into_iter()
+        .find(|(k, _)| k == kind)
+        .map(|(_, payload)| payload)
+        .ok_or_else(|| format!("missing {} box", String::from_utf8_lossy(kind)).into())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .ok_or("unexpected end of box")?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    let bytes = data
+        .get(offset..offset + 8)
+        .ok_or("unexpected end of box")?;
+    Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io};
+
+    #[test]
+    fn inspects_mp4() {
+        fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+            let mut data = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+            data.extend_from_slice(kind);
+            data.extend_from_slice(payload);
+            data
+        }
+
+        let mut tkhd = vec![0; 76];
+        tkhd.extend_from_slice(&(64u32 << 16).to_be_bytes());
+        tkhd.extend_from_slice(&(32u32 << 16).to_be_bytes());
+        let mut mdhd = vec![0; 12];
+        mdhd.extend_from_slice(&1000u32.to_be_bytes());
+        mdhd.extend_from_slice(&1500u32.to_be_bytes());
+        mdhd.extend_from_slice(&[0; 4]);
+        let hdlr = [&[0; 8][..], b"vide", &[0; 13]].concat();
+        let stsd = [&[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 8][..], b"avc1"].concat();
+        let stsz = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3];
+        let stbl = mp4_box(
+            b"stbl",
+            &[mp4_box(b"stsd", &stsd), mp4_box(b"stsz", &stsz)].concat(),
+        );
+        let mdia = [
+            mp4_box(b"mdhd", &mdhd),
+            mp4_box(b"hdlr", &hdlr),
+            mp4_box(b"minf", &stbl),
+        ]
+        .concat();
+        let trak = mp4_box(
+            b"trak",
+            &[mp4_box(b"tkhd", &tkhd), mp4_box(b"mdia", &mdia)].concat(),
+        );
+        let mp4 = [
+            mp4_box(b"ftyp", b"isom\0\0\0\0isom"),
+            mp4_box(b"mdat", &[0; 16]),
+            mp4_box(b"moov", &trak),
+        ]
+        .concat();
+
+        let path = std::env::temp_dir().join("bevy_capture_test_inspect.mp4");
+        fs::write(&path, &mp4).unwrap();
+        assert_eq!(
+            inspect_file(&path).unwrap(),
+            MediaInfo {
+                codec: "avc1".to_string(),
+                width: 64,
+                height: 32,
+                frame_count: 3,
+                duration: Duration::from_millis(1500),
+            }
+        );
+        fs::remove_file(&path).unwrap();
+
+        // A truncated file and a moov box claiming more bytes than the file has are rejected before
+        // the box is read into memory.
+        assert!(inspect_mp4(io::Cursor::new(&mp4[..mp4.len() - 10])).is_err());
+        let mut huge = mp4.clone();
+        let moov = huge.len() - trak.len() - 8;
+        huge[moov..moov + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let err = inspect_mp4(io::Cursor::new(huge)).unwrap_err();
+        assert!(err.to_string().contains("exceeds"), "{err}");
+    }
+
+    #[cfg(feature = "gif")]
+    #[test]
+    fn inspects_gif() {
+        use crate::encoder::{gif::GifEncoder, Encoder};
+        use bevy::{
+            prelude::*,
+            render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+        };
+
+        let image = Image::new_fill(
+            Extent3d {
+                width: 16,
+                height: 8,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[255, 0, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+            default(),
+        );
+        let mut gif = Vec::new();
+        let mut encoder = GifEncoder::new(&mut gif);
+        for _ in 0..3 {
+            encoder.encode(&image).unwrap();
+        }
+        drop(encoder);
+
+        let path = std::env::temp_dir().join("bevy_capture_test_inspect.gif");
+        fs::write(&path, &gif).unwrap();
+        let info = inspect_file(&path).unwrap();
+        assert_eq!(info.codec, "gif");
+        assert_eq!((info.width, info.height), (16, 8));
+        assert_eq!(info.frame_count, 3);
+        fs::remove_file(&path).unwrap();
+
+        assert!(inspect_gif(io::Cursor::new(&gif[..gif.len() / 2])).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+//! Runs the encoders of a capture on background threads, see [`CaptureWorkerSettings`].
+
+use crate::{
+    encoder,
+    memory::{Admission, CaptureMemoryBudget, Reservation, SpilledImage},
+    metadata::FrameMetadata,
+    AttachedEncoder, CaptureWorkerSettings, EncodeError, WorkerBackpressure, WorkerPriority,
+};
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+struct Frame {
+    image: FrameImage,
+    metadata: FrameMetadata,
+    // Released once all workers encoded the frame.
+    _reservation: Option<Reservation>,
+}
+
+/// A job for the workers.
+enum Job {
+    Frame(Arc<Frame>),
+    Flush,
+}
+
+enum FrameImage {
+    Memory(Image),
+    Spilled(SpilledImage),
+}
+
+/// The worker threads of a capture. Dropping the workers finishes their encoders and waits until
+/// they are done.
+pub(crate) struct Workers {
+    senders: Vec<Sender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+    backpressure: WorkerBackpressure,
+    dropped: AtomicU64,
+}
+
+impl Workers {
+    /// Distributes the encoders over the worker threads. Errors are sent to `errors`.
+    pub fn spawn(
+        encoders: Vec<AttachedEncoder>,
+        settings: &CaptureWorkerSettings,
+        errors: Sender<EncodeError>,
+    ) -> Self {
+        let count = settings.threads().min(encoders.len()).max(1);
+        let mut assigned = (0..count).map(|_| Vec::new()).collect::<Vec<_>>();
+        for (i, encoder) in encoders.into_iter().enumerate() {
+            assigned[i % count].push(encoder);
+        }
+
+        let (senders, threads) = assigned
+            .into_iter()
+            .enumerate()
+            .map(|(i, encoders)| {
+                let (sender, receiver) = crossbeam_channel::bounded(settings.queue_capacity());
+                let priority = settings.priority();
+                let core_affinity = settings.core_affinity().map(<[usize]>::to_vec);
+                let errors = errors.clone();
+                let thread = thread::Builder::new()
+                    .name(format!("capture worker {i}"))
+                    .spawn(move || {
+                        configure_current_thread(priority, core_affinity.as_deref());
+                        run(encoders, receiver, errors);
+                    })
+                    .expect("Failed to spawn capture worker");
+                (sender, thread)
+            })
+            .unzip();
+
+        Self {
+            senders,
+            threads,
+            backpressure: settings.backpressure(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues a frame for all workers. If a queue is full, this waits or drops the frame for the
+    /// worker, depending on the backpressure. If there is a memory budget, the frame might be
+    /// spilled or dropped.
+    pub fn send(
+        &self,
+        image: &Image,
+        metadata: &FrameMetadata,
+        budget: Option<&CaptureMemoryBudget>,
+    ) -> encoder::Result<()> {
+        let (image, reservation) = match budget {
+            None => (FrameImage::Memory(image.clone()), None),
+            Some(budget) => match budget.admit(image.data.len() as u64) {
+                Admission::Memory(reservation) => {
+                    (FrameImage::Memory(image.clone()), Some(reservation))
+                }
+                Admission::Spill => (FrameImage::Spilled(budget.spill(image)?), None),
+                Admission::Drop => return Ok(()),
+            },
+        };
+        let frame = Arc::new(Frame {
+            image,
+            metadata: metadata.clone(),
+            _reservation: reservation,
+        });
+        for sender in &self.senders {
+            let job = Job::Frame(Arc::clone(&frame));
+            // Only fails otherwise if the worker panicked, which is reported when it is joined.
+            match self.backpressure {
+                WorkerBackpressure::Block => {
+                    let _ = sender.send(job);
+                }
+                WorkerBackpressure::DropFrames => {
+                    if let Err(TrySendError::Full(_)) = sender.try_send(job) {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Lets the workers flush their encoders once they encoded the queued frames.
+    pub fn flush(&self) {
+        for sender in &self.senders {
+            let _ = sender.send(Job::Flush);
+        }
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("Dropped {dropped} frames for capture workers that fell behind");
+        }
+
+        // Closing the queues lets the workers finish their encoders.
+        self.senders.clear();
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                bevy::log::error!("A capture worker panicked");
+            }
+        }
+    }
+}
+
+fn run(mut encoders: Vec<AttachedEncoder>, jobs: Receiver<Job>, errors: Sender<EncodeError>) {
+    for job in jobs {
+        let frame = match job {
+            Job::Frame(frame) => frame,
+            Job::Flush => {
+                for encoder in &mut encoders {
+                    if let Err(err) = encoder.flush() {
+                        let _ = errors.send(err);
+                    }
+                }
+                continue;
+            }
+        };
+
+        let loaded;
+        let image = match &frame.image {
+            FrameImage::Memory(image) => image,
+            FrameImage::Spilled(spilled) => match spilled.load() {
+                Ok(image) => {
+                    loaded = image;
+                    &loaded
+                }
+                Err(err) => {
+                    let _ = errors.send(encoder::Error::from(err).into());
+                    continue;
+                }
+            },
+        };
+
+        for encoder in &mut encoders {
+            #[cfg(feature = "trace")]
+            let _span = info_span!("capture_encoder", encoder = encoder.encoder.name()).entered();
+
+            if let Err(err) = encoder.encode(image, &frame.metadata) {
+                let _ = errors.send(err);
+            }
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    let _span = info_span!("capture_finish").entered();
+
+    for encoder in encoders {
+        encoder.finish();
+    }
+}
+
+/// Sets the priority and the core affinity of the current thread. Failures are only logged, since
+/// encoding works without them.
+fn configure_current_thread(priority: WorkerPriority, core_affinity: Option<&[usize]>) {
+    if let Err(err) = platform::set_priority(priority) {
+        bevy::log::warn!("Failed to set the priority of a capture worker: {err}");
+    }
+    if let Some(cores) = core_affinity {
+        if let Err(err) = platform::set_core_affinity(cores) {
+            bevy::log::warn!("Failed to set the core affinity of a capture worker: {err}");
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use crate::WorkerPriority;
+    use std::{io, mem};
+
+    pub fn set_priority(priority: WorkerPriority) -> io::Result<()> {
+        let nice = match priority {
+            WorkerPriority::Normal => return Ok(()),
+            WorkerPriority::Low => 10,
+            WorkerPriority::Lowest => 19,
+        };
+
+        // On Linux, the nice value is an attribute of the thread, not of the process.
+        // SAFETY: The call has no memory safety preconditions.
+        let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, libc::gettid() as _, nice) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn set_core_affinity(cores: &[usize]) -> io::Result<()> {
+        // SAFETY: `cpu_set_t` is a plain bit set, all zeros is the empty set.
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        for &core in cores {
+            if core < libc::CPU_SETSIZE as usize {
+                // SAFETY: The core is within the bounds of the set.
+                unsafe { libc::CPU_SET(core, &mut set) };
+            }
+        }
+
+        // SAFETY: The set is valid and its size is passed along.
+        let result = unsafe { libc::sched_setaffinity(0, mem::size_of_val(&set), &set) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use crate::WorkerPriority;
+    use std::{ffi::c_void, io};
+
+    const THREAD_PRIORITY_BELOW_NORMAL: i32 = -1;
+    const THREAD_PRIORITY_LOWEST: i32 = -2;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> *mut c_void;
+        fn SetThreadPriority(thread: *mut c_void, priority: i32) -> i32;
+        fn SetThreadAffinityMask(thread: *mut c_void, mask: usize) -> usize;
+    }
+
+    pub fn set_priority(priority: WorkerPriority) -> io::Result<()> {
+        let priority = match priority {
+            WorkerPriority::Normal => return Ok(()),
+            WorkerPriority::Low => THREAD_PRIORITY_BELOW_NORMAL,
+            WorkerPriority::Lowest => THREAD_PRIORITY_LOWEST,
+        };
+
+        // SAFETY: The pseudo handle of the current thread is always valid.
+        if unsafe { SetThreadPriority(GetCurrentThread(), priority) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn set_core_affinity(cores: &[usize]) -> io::Result<()> {
+        let mask = cores
+            .iter()
+            .filter(|&&core| core < usize::BITS as usize)
+            .fold(0, |mask, &core| mask | 1 << core);
+
+        // SAFETY: The pseudo handle of the current thread is always valid.
+        if unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use crate::WorkerPriority;
+    use std::io;
+
+    pub fn set_priority(priority: WorkerPriority) -> io::Result<()> {
+        match priority {
+            WorkerPriority::Normal => Ok(()),
+            _ => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    pub fn set_core_affinity(_cores: &[usize]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
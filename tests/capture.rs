@@ -0,0 +1,980 @@
+mod common;
+
+use bevy::{
+    prelude::*,
+    render::{render_graph::RenderLabel, render_resource::TextureFormat},
+};
+use bevy_capture::{
+    auto_pause::{AutoPause, AutoPausePlugin},
+    defaults::DefaultCaptureSettings,
+    encoder::{
+        self,
+        file_output::FileOutput,
+        frames::FramesEncoder,
+        test::{RecordedFrame, TestEncoder},
+    },
+    live_settings::LiveEncoderSettings,
+    metadata::{FrameMetadata, MetadataValue, TIMESTAMP_KEY},
+    multi_pass::{CapturePass, CapturePasses, MultiPassPlugin},
+    preview::CapturePreview,
+    range::CaptureRange,
+    testing::HeadlessHarness,
+    time_remap::{TimeRemap, TimeRemapPlugin},
+    Capture, CaptureBufferSettings, CaptureBundle, CaptureClock, CapturePlugin,
+    CaptureWorkerSettings, Encoder, ReadbackMode,
+};
+use common::{harness, NO_ADAPTER};
+use std::{
+    fs,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[test]
+fn captures_clear_color() {
+    let mut harness = harness(64, 32);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(1.0, 0.0, 0.0)));
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    harness.capture(3, encoder);
+
+    assert!(handle.is_finished());
+    assert_eq!(
+        handle.frames(),
+        [RecordedFrame {
+            width: 64,
+            height: 32,
+            format: TextureFormat::Rgba8UnormSrgb,
+        }; 3]
+    );
+    for image in handle.images() {
+        assert_eq!(image.data.len(), 64 * 32 * 4);
+        for pixel in image.data.chunks_exact(4) {
+            assert_eq!(pixel, [255, 0, 0, 255]);
+        }
+    }
+}
+
+#[test]
+fn captures_unaligned_width() {
+    let mut harness = harness(33, 7);
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    harness.capture(1, encoder);
+
+    let images = handle.images();
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].data.len(), 33 * 7 * 4);
+}
+
+#[test]
+fn captures_in_chunks() {
+    let mut harness = harness(33, 7);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(0.0, 0.0, 1.0)));
+    let camera = harness.camera();
+    harness.app_mut().world_mut().entity_mut(camera).insert(
+        CaptureBufferSettings::default()
+            .with_row_alignment(512)
+            .with_max_rows_per_copy(3),
+    );
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    harness.capture(2, encoder);
+
+    let images = handle.images();
+    assert_eq!(images.len(), 2);
+    for image in images {
+        assert_eq!(image.data.len(), 33 * 7 * 4);
+        for pixel in image.data.chunks_exact(4) {
+            assert_eq!(pixel, [0, 0, 255, 255]);
+        }
+    }
+}
+
+#[test]
+fn captures_in_tiles() {
+    let mut harness = harness(200, 3);
+    let camera = harness.camera();
+    let world = harness.app_mut().world_mut();
+
+    // Capture the uploaded contents of the target without rendering, so every pixel is distinct.
+    let mut camera_settings = world.get_mut::<Camera>(camera).unwrap();
+    camera_settings.is_active = false;
+    let bevy::render::camera::RenderTarget::Image(target) = camera_settings.target.clone() else {
+        unreachable!()
+    };
+    let pattern = (0..200 * 3)
+        .flat_map(|i: u32| [i as u8, (i >> 8) as u8, 0, 255])
+        .collect::<Vec<_>>();
+    world
+        .resource_mut::<Assets<Image>>()
+        .get_mut(&target)
+        .unwrap()
+        .data
+        .clone_from(&pattern);
+
+    // A row doesn't fit into a buffer, so every row is split into 64 pixel wide tiles.
+    world
+        .entity_mut(camera)
+        .insert(CaptureBufferSettings::default().with_max_buffer_size(256));
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    harness.capture(2, encoder);
+
+    let images = handle.images();
+    assert_eq!(images.len(), 2);
+    for image in images {
+        assert_eq!(image.data, pattern);
+    }
+}
+
+#[test]
+fn writes_frames() {
+    let mut harness = harness(16, 16);
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_writes_frames");
+    let _ = fs::remove_dir_all(&dir);
+    harness.capture(2, FramesEncoder::new(&dir));
+
+    let mut files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files, ["frame_000000.png", "frame_000001.png"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn starts_default_capture() {
+    let mut harness = harness(16, 16);
+    let camera = harness.camera();
+
+    // Without settings, the capture is stopped again.
+    let handle = harness
+        .app_mut()
+        .world_mut()
+        .get_mut::<Capture>(camera)
+        .unwrap()
+        .start_default();
+    harness.app_mut().update();
+    assert!(handle.is_finished());
+    let capture = harness.app().world().get::<Capture>(camera).unwrap();
+    assert!(!capture.is_capturing());
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_default_capture");
+    let _ = fs::remove_dir_all(&dir);
+    let handles = Arc::new(Mutex::new(Vec::new()));
+    let settings = {
+        let handles = Arc::clone(&handles);
+        DefaultCaptureSettings::new(move |capture| {
+            assert_eq!(capture.entity, camera);
+            assert_eq!(capture.framerate, 30);
+            let encoder = TestEncoder::new();
+            handles
+                .lock()
+                .unwrap()
+                .push((encoder.handle(), capture.path("y4m")));
+            Ok(encoder)
+        })
+        .with_output_dir(&dir)
+        .with_framerate(30)
+    };
+    harness.app_mut().insert_resource(settings);
+
+    for _ in 0..2 {
+        let world = harness.app_mut().world_mut();
+        let handle = world.get_mut::<Capture>(camera).unwrap().start_default();
+        assert!(world.get::<Capture>(camera).unwrap().is_capturing());
+        for _ in 0..2 {
+            harness.app_mut().update();
+        }
+        let world = harness.app_mut().world_mut();
+        world.get_mut::<Capture>(camera).unwrap().stop();
+        harness.app_mut().update();
+        assert!(handle.is_finished());
+    }
+
+    let handles = handles.lock().unwrap();
+    assert_eq!(handles.len(), 2);
+    assert_eq!(handles[0].0.encode_count(), 2);
+    assert!(handles[0].0.is_finished());
+    assert_eq!(handles[0].1, dir.join("capture_0000.y4m"));
+    assert_eq!(handles[1].1, dir.join("capture_0001.y4m"));
+    assert!(dir.is_dir());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn writes_frames_to_writer() {
+    let mut harness = harness(16, 16);
+
+    struct SharedWriter(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0
+                .lock()
+                .unwrap()
+                .last_mut()
+                .unwrap()
+                .extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let files = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&files);
+    harness.capture(
+        2,
+        FramesEncoder::new_with_writer(move |_| {
+            sink.lock().unwrap().push(Vec::new());
+            Ok(SharedWriter(Arc::clone(&sink)))
+        }),
+    );
+
+    let files = files.lock().unwrap();
+    assert_eq!(files.len(), 2);
+    for file in files.iter() {
+        assert!(file.starts_with(b"\x89PNG"));
+    }
+}
+
+#[test]
+fn tracks_capture_stats() {
+    let mut harness = harness(16, 16);
+    fn capture(harness: &mut HeadlessHarness) -> Mut<'_, Capture> {
+        let camera = harness.camera();
+        harness
+            .app_mut()
+            .world_mut()
+            .get_mut::<Capture>(camera)
+            .unwrap()
+    }
+
+    let first = TestEncoder::new();
+    let first_handle = first.handle();
+    let first_capture = capture(&mut harness).start((first, TestEncoder::new()));
+    harness.app_mut().update();
+    harness.app_mut().update();
+
+    assert_eq!(capture(&mut harness).encoder_count(), 2);
+    assert_eq!(capture(&mut harness).frames_captured(), 2);
+    assert!(capture(&mut harness).elapsed().is_some());
+
+    // Restarting replaces the encoders and resets the stats.
+    let second = TestEncoder::new();
+    let second_handle = second.handle();
+    capture(&mut harness).start(second);
+    assert!(!capture(&mut harness).has_captured_frame());
+    harness.app_mut().update();
+
+    assert!(first_handle.is_finished());
+    assert!(first_capture.is_finished());
+    bevy::tasks::block_on(first_capture);
+    assert_eq!(first_handle.encode_count(), 2);
+    assert_eq!(second_handle.encode_count(), 1);
+    assert_eq!(capture(&mut harness).frames_captured(), 1);
+
+    let second_capture = capture(&mut harness).handle().unwrap();
+    assert!(!second_capture.is_finished());
+    capture(&mut harness).stop();
+    harness.app_mut().update();
+    assert!(second_capture.is_finished());
+}
+
+#[test]
+fn flushes_without_stopping() {
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+    let dir = std::env::temp_dir().join("bevy_capture_test_flush");
+    let _ = fs::remove_dir_all(&dir);
+
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    let frames = FramesEncoder::new(&dir).with_output(FileOutput::new().with_background_writes(4));
+    let world = harness.app_mut().world_mut();
+    world
+        .get_mut::<Capture>(camera)
+        .unwrap()
+        .start((encoder, frames));
+    harness.app_mut().update();
+    harness.app_mut().update();
+
+    let world = harness.app_mut().world_mut();
+    world.get_mut::<Capture>(camera).unwrap().flush();
+    harness.app_mut().update();
+
+    // The frames before the flush are written, and the capture continues.
+    assert_eq!(handle.flushes(), [2]);
+    assert!(fs::read_dir(&dir).unwrap().count() >= 2);
+    assert_eq!(handle.encode_count(), 3);
+    assert!(!handle.is_finished());
+    let world = harness.app_mut().world_mut();
+    assert!(world.get::<Capture>(camera).unwrap().is_capturing());
+
+    world.get_mut::<Capture>(camera).unwrap().stop();
+    harness.app_mut().update();
+    assert!(handle.is_finished());
+    assert_eq!(handle.flushes(), [2]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn detaches_failing_encoder() {
+    use bevy_capture::isolation::{EncoderDetached, EncoderIsolation};
+
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+
+    // Fails from the second frame on, e.g. once the disk is full.
+    #[derive(Default)]
+    struct FailingEncoder {
+        calls: Arc<Mutex<u32>>,
+        finished: Arc<Mutex<bool>>,
+    }
+
+    impl Encoder for FailingEncoder {
+        fn encode(&mut self, _image: &Image) -> encoder::Result<()> {
+            let mut calls = self.calls.lock().unwrap();
+            *calls += 1;
+            match *calls {
+                1 => Ok(()),
+                _ => Err(io::Error::other("disk full").into()),
+            }
+        }
+
+        fn finish(self: Box<Self>) {
+            *self.finished.lock().unwrap() = true;
+        }
+
+        fn name(&self) -> &str {
+            "disk"
+        }
+    }
+
+    let failing = FailingEncoder::default();
+    let (calls, finished) = (Arc::clone(&failing.calls), Arc::clone(&failing.finished));
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    let world = harness.app_mut().world_mut();
+    world.entity_mut(camera).insert(EncoderIsolation::new(3));
+    let ids = world
+        .get_mut::<Capture>(camera)
+        .unwrap()
+        .start((encoder, failing))
+        .encoders();
+
+    let mut reader = world.resource::<Events<EncoderDetached>>().get_reader();
+    let mut detached = Vec::new();
+    for _ in 0..6 {
+        harness.app_mut().update();
+        let events = harness.app().world().resource::<Events<EncoderDetached>>();
+        detached.extend(reader.read(events).cloned());
+    }
+
+    // The failing encoder is detached after three errors in a row, the other one keeps going.
+    assert_eq!(*calls.lock().unwrap(), 4);
+    assert!(*finished.lock().unwrap());
+    assert_eq!(handle.encode_count(), 6);
+    assert!(!handle.is_finished());
+    assert_eq!(
+        detached,
+        [EncoderDetached {
+            capture: camera,
+            encoder: ids[1],
+            name: "disk".to_owned(),
+            error: "io error: disk full".to_owned(),
+        }]
+    );
+}
+
+#[test]
+fn updates_preview() {
+    let mut harness = harness(8, 4);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(0.0, 0.0, 1.0)))
+        .init_resource::<CapturePreview>();
+
+    harness.capture(2, TestEncoder::new());
+
+    let world = harness.app().world();
+    let preview = world.resource::<CapturePreview>();
+    let image = world
+        .resource::<Assets<Image>>()
+        .get(preview.image())
+        .unwrap();
+    assert_eq!(image.size(), UVec2::new(8, 4));
+    for pixel in image.data.chunks_exact(4) {
+        assert_eq!(pixel, [0, 0, 255, 255]);
+    }
+}
+
+#[test]
+fn passes_frame_metadata() {
+    let mut harness = harness(16, 8);
+    fn insert_frame_count(mut metadata: ResMut<FrameMetadata>, mut frame: Local<u32>) {
+        if frame.is_multiple_of(2) {
+            metadata.insert("frame", *frame);
+        }
+        *frame += 1;
+    }
+    harness.app_mut().add_systems(Update, insert_frame_count);
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_frame_metadata");
+    let _ = fs::remove_dir_all(&dir);
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    harness.capture(2, (encoder, FramesEncoder::new(&dir)));
+
+    let metadata = handle.metadata();
+    assert_eq!(metadata.len(), 2);
+    assert_eq!(metadata[0].to_json(), r#"{"frame":0,"frame_index":0}"#);
+    assert_eq!(metadata[1].to_json(), r#"{"frame_index":1}"#);
+
+    assert!(dir.join("frame_000000.json").exists());
+    assert!(!dir.join("frame_000001.json").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn forces_keyframes() {
+    let mut harness = harness(16, 8);
+    fn force_second_keyframe(mut metadata: ResMut<FrameMetadata>, mut frame: Local<u32>) {
+        if *frame == 1 {
+            metadata.force_keyframe();
+        }
+        *frame += 1;
+    }
+    harness.app_mut().add_systems(Update, force_second_keyframe);
+
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    harness.capture(3, encoder);
+
+    let forced = handle
+        .metadata()
+        .iter()
+        .map(FrameMetadata::is_keyframe_forced)
+        .collect::<Vec<_>>();
+    assert_eq!(forced, [false, true, false]);
+}
+
+#[test]
+fn passes_live_encoder_settings() {
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+    harness
+        .app_mut()
+        .world_mut()
+        .entity_mut(camera)
+        .insert(LiveEncoderSettings::new().with_crf(28));
+    fn lower_quality(mut settings: Query<&mut LiveEncoderSettings>, mut frame: Local<u32>) {
+        if *frame == 1 {
+            for mut settings in &mut settings {
+                settings.set_crf(Some(35));
+                settings.set_frame_delay(Some(std::time::Duration::from_millis(50)));
+            }
+        }
+        *frame += 1;
+    }
+    harness.app_mut().add_systems(Update, lower_quality);
+
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    harness.capture(2, encoder);
+
+    let settings = handle
+        .metadata()
+        .iter()
+        .map(LiveEncoderSettings::from_metadata)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        settings,
+        [
+            LiveEncoderSettings::new().with_crf(28),
+            LiveEncoderSettings::new()
+                .with_crf(35)
+                .with_frame_delay(std::time::Duration::from_millis(50)),
+        ]
+    );
+}
+
+#[test]
+fn pauses_while_window_is_inactive() {
+    let mut harness = HeadlessHarness::new_with_plugins(16, 8, AutoPausePlugin).expect(NO_ADAPTER);
+    let window = harness
+        .app_mut()
+        .world_mut()
+        .spawn(Window {
+            focused: true,
+            ..default()
+        })
+        .id();
+    let camera = harness.camera();
+    let world = harness.app_mut().world_mut();
+    world.entity_mut(camera).insert(
+        AutoPause::default()
+            .with_window(window)
+            .with_pause_on_focus_lost(true),
+    );
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    world.get_mut::<Capture>(camera).unwrap().start(encoder);
+
+    let set_focused = |harness: &mut HeadlessHarness, focused: bool| {
+        let world = harness.app_mut().world_mut();
+        world.get_mut::<Window>(window).unwrap().focused = focused;
+        for _ in 0..2 {
+            harness.app_mut().update();
+        }
+        let world = harness.app_mut().world_mut();
+        world.get::<Capture>(camera).unwrap().is_paused()
+    };
+    assert!(!set_focused(&mut harness, true));
+    assert!(set_focused(&mut harness, false));
+    assert_eq!(handle.encode_count(), 2);
+    assert!(!set_focused(&mut harness, true));
+    assert_eq!(handle.encode_count(), 4);
+
+    let world = harness.app_mut().world_mut();
+    world.get_mut::<Capture>(camera).unwrap().stop();
+    harness.app_mut().update();
+}
+
+#[test]
+fn remaps_time_while_capturing() {
+    let mut harness = HeadlessHarness::new_with_capture_plugin(
+        16,
+        8,
+        CapturePlugin::default().with_clock(CaptureClock::Virtual),
+        TimeRemapPlugin,
+    )
+    .expect(NO_ADAPTER);
+    harness
+        .app_mut()
+        .insert_resource(TimeRemap::new(10).with_speed(2.0));
+
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    harness.capture(4, encoder);
+
+    // The time is remapped from the frame after the capture started.
+    let timestamps = handle
+        .metadata()
+        .iter()
+        .map(|metadata| match metadata.get(TIMESTAMP_KEY) {
+            Some(MetadataValue::Float(timestamp)) => *timestamp,
+            _ => panic!("missing timestamp"),
+        })
+        .collect::<Vec<_>>();
+    for delta in timestamps[1..].windows(2).map(|pair| pair[1] - pair[0]) {
+        assert!((delta - 0.2).abs() < 1e-6, "unexpected delta {delta}");
+    }
+
+    // The time is restored once the capture stopped.
+    let world = harness.app_mut().world_mut();
+    assert!(!world.resource::<TimeRemap>().is_active());
+    assert_eq!(world.resource::<Time<Virtual>>().relative_speed_f64(), 1.0);
+}
+
+#[test]
+fn captures_only_frames_in_range() {
+    let mut harness = harness(16, 8);
+    fn insert_frame(mut metadata: ResMut<FrameMetadata>, mut frame: Local<u32>) {
+        metadata.insert("frame", *frame);
+        *frame += 1;
+    }
+    harness.app_mut().add_systems(Update, insert_frame);
+    let camera = harness.camera();
+    let world = harness.app_mut().world_mut();
+    world.entity_mut(camera).insert(CaptureRange::frames(2..4));
+
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    world.get_mut::<Capture>(camera).unwrap().start(encoder);
+    for _ in 0..6 {
+        harness.app_mut().update();
+    }
+
+    let frames = handle
+        .metadata()
+        .iter()
+        .map(|metadata| metadata.get("frame").cloned())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        frames,
+        [Some(MetadataValue::Int(2)), Some(MetadataValue::Int(3))]
+    );
+    // The capture stopped after the range.
+    let world = harness.app_mut().world_mut();
+    assert!(!world.get::<Capture>(camera).unwrap().is_capturing());
+    assert!(handle.is_finished());
+}
+
+#[test]
+fn captures_multiple_passes() {
+    let mut harness = HeadlessHarness::new_with_plugins(16, 8, MultiPassPlugin).expect(NO_ADAPTER);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(0.0, 0.0, 1.0)));
+    let pass_encoder = TestEncoder::new().with_images();
+    let pass_handle = pass_encoder.handle();
+    let pass_encoder = Mutex::new(Some(pass_encoder));
+    let camera = harness.camera();
+    harness.app_mut().world_mut().entity_mut(camera).insert(
+        CapturePasses::default().with_pass(
+            CapturePass::new("red", move |name| {
+                assert_eq!(name, "red");
+                Ok(pass_encoder.lock().unwrap().take().unwrap())
+            })
+            .with_setup(|pass| {
+                pass.get_mut::<Camera>().unwrap().clear_color =
+                    ClearColorConfig::Custom(Color::srgb(1.0, 0.0, 0.0));
+            }),
+        ),
+    );
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    harness.capture(3, encoder);
+
+    assert_eq!(handle.encode_count(), 3);
+    assert_eq!(pass_handle.encode_count(), 3);
+    assert_eq!(handle.images()[0].data[..4], [0, 0, 255, 255]);
+    assert_eq!(pass_handle.images()[0].data[..4], [255, 0, 0, 255]);
+    // The pass is stopped together with the camera.
+    assert!(pass_handle.is_finished());
+}
+
+#[test]
+fn runs_without_render_app() {
+    // E.g. a dedicated server build.
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, CapturePlugin::default()));
+    let camera = app.world_mut().spawn(CaptureBundle::default()).id();
+
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    let capture_handle = app
+        .world_mut()
+        .get_mut::<Capture>(camera)
+        .unwrap()
+        .start(encoder);
+    for _ in 0..3 {
+        app.update();
+    }
+    app.world_mut().get_mut::<Capture>(camera).unwrap().stop();
+    app.update();
+
+    assert!(capture_handle.is_finished());
+    assert!(handle.is_finished());
+    assert_eq!(handle.encode_count(), 0);
+}
+
+#[test]
+fn configures_capture_plugin() {
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, RenderLabel)]
+    struct MissingNode;
+
+    let plugin = CapturePlugin::default()
+        .with_readback(ReadbackMode::Async)
+        .with_worker_settings(CaptureWorkerSettings::default())
+        .with_clock(CaptureClock::Virtual)
+        // Falls back to the camera driver.
+        .with_graph_node(MissingNode);
+    let mut harness = HeadlessHarness::new_with_capture_plugin(4, 4, plugin, ()).expect(NO_ADAPTER);
+    let camera = harness.camera();
+
+    struct ThreadNameEncoder(Arc<Mutex<Vec<String>>>);
+
+    impl Encoder for ThreadNameEncoder {
+        fn encode(&mut self, _image: &Image) -> encoder::Result<()> {
+            let name = thread::current().name().unwrap_or_default().to_owned();
+            self.0.lock().unwrap().push(name);
+            Ok(())
+        }
+    }
+
+    let names = Arc::new(Mutex::new(Vec::new()));
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    let world = harness.app_mut().world_mut();
+    let mut capture = world.get_mut::<Capture>(camera).unwrap();
+    capture.start((encoder, ThreadNameEncoder(Arc::clone(&names))));
+
+    // Every frame has a different color, so frames in the wrong order or of the wrong buffer
+    // would be noticed.
+    for i in 0..4 {
+        let red = 0.25 * (i + 1) as f32;
+        harness
+            .app_mut()
+            .insert_resource(ClearColor(Color::srgb(red, 0.0, 0.0)));
+        harness.app_mut().update();
+    }
+    let world = harness.app_mut().world_mut();
+    world.get_mut::<Capture>(camera).unwrap().stop();
+    harness.app_mut().update();
+
+    assert!(handle.is_finished());
+    let reds = handle
+        .images()
+        .iter()
+        .map(|image| image.data[0])
+        .collect::<Vec<_>>();
+    assert_eq!(reds, [64, 127, 191, 255]);
+    assert_eq!(*names.lock().unwrap(), ["capture worker 0"; 4]);
+
+    let timestamps = handle
+        .metadata()
+        .iter()
+        .map(|metadata| match metadata.get(TIMESTAMP_KEY) {
+            Some(MetadataValue::Float(timestamp)) => *timestamp,
+            other => panic!("expected a timestamp, got {other:?}"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(timestamps.len(), 4);
+    assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]));
+}
+
+#[test]
+fn lists_active_captures() {
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy_capture::debug::{log_active_captures, CaptureRegistry};
+
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+    let dir = std::env::temp_dir().join("bevy_capture_test_registry");
+
+    let world = harness.app_mut().world_mut();
+    let handle = world
+        .get_mut::<Capture>(camera)
+        .unwrap()
+        .start((TestEncoder::new(), FramesEncoder::new(&dir)));
+    harness.app_mut().update();
+    harness.app_mut().update();
+
+    let registry = harness.app_mut().world().resource::<CaptureRegistry>();
+    assert_eq!(registry.len(), 1);
+    let capture = registry.get(camera).unwrap();
+    assert_eq!(capture.camera, camera);
+    assert!(capture.frames_captured >= 1);
+    assert!(!capture.paused);
+    let encoders = &capture.encoders;
+    assert_eq!(encoders.len(), 2);
+    assert_eq!(encoders[0].id, handle.encoders()[0]);
+    assert_eq!(encoders[0].name, "test");
+    assert!(encoders[0].output.is_empty());
+    assert_eq!(encoders[1].name, "frames");
+    assert_eq!(encoders[1].output, dir.display().to_string());
+    harness
+        .app_mut()
+        .world_mut()
+        .run_system_once(log_active_captures);
+
+    let world = harness.app_mut().world_mut();
+    world.get_mut::<Capture>(camera).unwrap().stop();
+    harness.app_mut().update();
+    assert!(harness
+        .app_mut()
+        .world()
+        .resource::<CaptureRegistry>()
+        .is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn adds_encoder_to_running_capture() {
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+
+    let first = TestEncoder::new();
+    let first_handle = first.handle();
+    let world = harness.app_mut().world_mut();
+    let handle = world.get_mut::<Capture>(camera).unwrap().start(first);
+    harness.app_mut().update();
+    harness.app_mut().update();
+
+    let second = TestEncoder::new();
+    let second_handle = second.handle();
+    let world = harness.app_mut().world_mut();
+    let id = world
+        .get_mut::<Capture>(camera)
+        .unwrap()
+        .add_encoder(second)
+        .unwrap();
+    harness.app_mut().update();
+    harness.app_mut().update();
+
+    // The added encoder gets the frames from the next frame on and has its own id.
+    assert_eq!(first_handle.encode_count(), 4);
+    assert_eq!(second_handle.encode_count(), 2);
+    let ids = handle.encoders();
+    assert_eq!(ids.len(), 2);
+    assert_eq!(ids[1], id);
+    assert_ne!(ids[0], id);
+    let world = harness.app_mut().world_mut();
+    let encoders = world.get::<Capture>(camera).unwrap().encoders();
+    assert_eq!(
+        encoders
+            .iter()
+            .map(|encoder| (encoder.id, encoder.frames_encoded, encoder.errors))
+            .collect::<Vec<_>>(),
+        [(ids[0], 4, 0), (id, 2, 0)]
+    );
+
+    world.get_mut::<Capture>(camera).unwrap().stop();
+    harness.app_mut().update();
+    assert!(first_handle.is_finished());
+    assert!(second_handle.is_finished());
+    assert!(handle.is_finished());
+
+    // Encoders can't be added to a stopped capture.
+    let world = harness.app_mut().world_mut();
+    let mut capture = world.get_mut::<Capture>(camera).unwrap();
+    assert_eq!(capture.add_encoder(TestEncoder::new()), None);
+}
+
+#[test]
+fn stops_capture_over_limits() {
+    use bevy_capture::limits::{CaptureLimit, CaptureLimitExceeded, CaptureLimits};
+
+    let mut harness = harness(4, 2);
+    let camera = harness.camera();
+    let dir = std::env::temp_dir().join("bevy_capture_test_limits");
+    let _ = fs::remove_dir_all(&dir);
+
+    // The frames limit stops the capture after the frames passed to the encoders.
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    let world = harness.app_mut().world_mut();
+    world
+        .entity_mut(camera)
+        .insert(CaptureLimits::new().with_max_frames(2));
+    world.get_mut::<Capture>(camera).unwrap().start(encoder);
+    let mut reader = world
+        .resource::<Events<CaptureLimitExceeded>>()
+        .get_reader();
+    let mut exceeded = Vec::new();
+    for _ in 0..8 {
+        harness.app_mut().update();
+        let events = harness
+            .app()
+            .world()
+            .resource::<Events<CaptureLimitExceeded>>();
+        exceeded.extend(reader.read(events).cloned());
+    }
+    let capture = harness.app().world().get::<Capture>(camera).unwrap();
+    assert!(!capture.is_capturing());
+    assert!((2..=3).contains(&handle.encode_count()));
+    assert_eq!(
+        exceeded,
+        [CaptureLimitExceeded {
+            capture: camera,
+            limit: CaptureLimit::Frames(2),
+        }]
+    );
+
+    // The output size limit stops the capture once the files exceed it.
+    let world = harness.app_mut().world_mut();
+    world.entity_mut(camera).insert(
+        CaptureLimits::new()
+            .with_max_output_bytes(1)
+            .with_check_interval(std::time::Duration::ZERO),
+    );
+    world
+        .get_mut::<Capture>(camera)
+        .unwrap()
+        .start(FramesEncoder::new(&dir));
+    for _ in 0..8 {
+        harness.app_mut().update();
+        let events = harness
+            .app()
+            .world()
+            .resource::<Events<CaptureLimitExceeded>>();
+        exceeded.extend(reader.read(events).cloned());
+    }
+    let capture = harness.app().world().get::<Capture>(camera).unwrap();
+    assert!(!capture.is_capturing());
+    assert!(fs::read_dir(&dir).unwrap().count() < 8);
+    assert_eq!(exceeded[1].limit, CaptureLimit::OutputBytes(1));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn captures_single_channel() {
+    use bevy_capture::channel::{CaptureChannel, Channel, ChannelFormat};
+
+    let capture = |color: Color, channel: CaptureChannel| {
+        let mut harness = harness(32, 16);
+        harness.app_mut().insert_resource(ClearColor(color));
+        let camera = harness.camera();
+        harness
+            .app_mut()
+            .world_mut()
+            .entity_mut(camera)
+            .insert(channel);
+        let encoder = TestEncoder::new().with_images();
+        let handle = encoder.handle();
+        harness.capture(2, encoder);
+        handle.images().pop().unwrap()
+    };
+
+    let image = capture(Color::WHITE, CaptureChannel::luminance());
+    assert_eq!(image.texture_descriptor.format, TextureFormat::R8Unorm);
+    assert_eq!(image.data, vec![255; 32 * 16]);
+
+    // The channels have the same values as in the full frame.
+    let color = Color::srgb(1.0, 0.5, 0.0);
+    let image = capture(color, CaptureChannel::new(Channel::Red));
+    assert!(image.data.iter().all(|&value| value == 255));
+    let channel = CaptureChannel::new(Channel::Green).with_format(ChannelFormat::R16);
+    let image = capture(color, channel);
+    assert_eq!(image.texture_descriptor.format, TextureFormat::R16Uint);
+    assert_eq!(image.data.len(), 32 * 16 * 2);
+    for value in image.data.chunks_exact(2) {
+        let value = u16::from_le_bytes([value[0], value[1]]);
+        assert!(value.abs_diff(128 * 257) < 257, "{value}");
+    }
+}
+
+#[test]
+fn passes_frame_index_and_times() {
+    let plugin = CapturePlugin::default().with_clock(CaptureClock::Real);
+    let mut harness =
+        HeadlessHarness::new_with_capture_plugin(16, 8, plugin, ()).expect(NO_ADAPTER);
+
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    harness.capture(3, encoder);
+
+    let metadata = handle.metadata();
+    let indices = metadata
+        .iter()
+        .map(|metadata| metadata.frame_index())
+        .collect::<Vec<_>>();
+    assert_eq!(indices, [Some(0), Some(1), Some(2)]);
+    for pair in metadata.windows(2) {
+        let (previous, current) = (pair[0].timestamp().unwrap(), pair[1].timestamp().unwrap());
+        let delta_time = current - previous;
+        assert!(delta_time >= 0.0);
+        assert!((pair[1].delta_time().unwrap() - delta_time).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,13 @@
+//! Helpers shared by the integration tests.
+
+// Not every test uses every helper.
+#![allow(dead_code)]
+
+use bevy_capture::testing::HeadlessHarness;
+
+/// The tests render, so they need a wgpu adapter. CI uses the lavapipe software adapter.
+pub const NO_ADAPTER: &str = "no wgpu adapter, install a software adapter like lavapipe";
+
+pub fn harness(width: u32, height: u32) -> HeadlessHarness {
+    HeadlessHarness::new(width, height).expect(NO_ADAPTER)
+}
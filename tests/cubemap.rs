@@ -0,0 +1,195 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_capture::{
+    cubemap::{CaptureCubemap, CubemapCapturePlugin, CubemapCaptured},
+    testing::HeadlessHarness,
+};
+use common::NO_ADAPTER;
+use std::fs;
+
+#[test]
+fn captures_cubemap() {
+    let mut harness =
+        HeadlessHarness::new_with_plugins(16, 8, CubemapCapturePlugin).expect(NO_ADAPTER);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(1.0, 0.0, 0.0)));
+
+    let path = std::env::temp_dir().join("bevy_capture_test_cubemap.ktx2");
+    let _ = fs::remove_file(&path);
+    harness
+        .app_mut()
+        .world_mut()
+        .send_event(CaptureCubemap::new(Vec3::ZERO, &path).with_size(4));
+
+    // The faces are read back asynchronously.
+    let captured = (0..20).find_map(|_| {
+        harness.app_mut().update();
+        let events = harness.app().world().resource::<Events<CubemapCaptured>>();
+        events.iter_current_update_events().next().cloned()
+    });
+    assert!(captured.is_some());
+
+    let ktx2 = fs::read(&path).unwrap();
+    let header = |i: usize| u32::from_le_bytes(ktx2[12 + i * 4..16 + i * 4].try_into().unwrap());
+    assert_eq!(&ktx2[1..4], b"KTX");
+    assert_eq!((header(2), header(3), header(6), header(7)), (4, 4, 6, 1));
+
+    // All faces are cleared to red (1.0, 0.0, 0.0, 1.0 as half floats).
+    let level_offset = u64::from_le_bytes(ktx2[80..88].try_into().unwrap()) as usize;
+    let level = &ktx2[level_offset..];
+    assert_eq!(level.len(), 6 * 4 * 4 * 8);
+    for texel in level.chunks_exact(8) {
+        assert_eq!(texel, [0x00, 0x3c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3c]);
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+/// The directions of the markers of [`capture_cubemap_markers`], in the order of the cubemap
+/// layers (+X, -X, +Y, -Y, +Z, -Z), with their colors. Every marker lies in the top right corner of
+/// its face, following the cube map face selection of the Vulkan spec with the flipped z axis Bevy
+/// samples cubemaps with.
+const CUBEMAP_MARKERS: [(Vec3, [f32; 3]); 6] = [
+    (Vec3::new(2.0, 1.0, 1.0), [1.0, 0.0, 0.0]),
+    (Vec3::new(-2.0, 1.0, -1.0), [0.0, 1.0, 0.0]),
+    (Vec3::new(1.0, 2.0, 1.0), [0.0, 0.0, 1.0]),
+    (Vec3::new(1.0, -2.0, -1.0), [1.0, 1.0, 0.0]),
+    (Vec3::new(1.0, 1.0, -2.0), [0.0, 1.0, 1.0]),
+    (Vec3::new(-1.0, 1.0, 2.0), [1.0, 0.0, 1.0]),
+];
+
+/// Captures a cubemap with faces of the given size, surrounded by unlit [`CUBEMAP_MARKERS`].
+fn capture_cubemap_markers(path: &std::path::Path, size: u32) {
+    use bevy::pbr::{PbrPlugin, StandardMaterial};
+
+    let mut harness =
+        HeadlessHarness::new_with_plugins(16, 8, (PbrPlugin::default(), CubemapCapturePlugin))
+            .expect(NO_ADAPTER);
+    let world = harness.app_mut().world_mut();
+    world.insert_resource(ClearColor(Color::BLACK));
+    let mesh = world
+        .resource_mut::<Assets<Mesh>>()
+        .add(Cuboid::from_length(0.8));
+    for (position, [r, g, b]) in CUBEMAP_MARKERS {
+        let material = world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(StandardMaterial {
+                base_color: Color::linear_rgb(r, g, b),
+                unlit: true,
+                ..default()
+            });
+        world.spawn(PbrBundle {
+            mesh: mesh.clone(),
+            material,
+            transform: Transform::from_translation(position),
+            ..default()
+        });
+    }
+
+    let _ = fs::remove_file(path);
+    world.send_event(CaptureCubemap::new(Vec3::ZERO, path).with_size(size));
+
+    // The faces are read back asynchronously.
+    let captured = (0..20).find_map(|_| {
+        harness.app_mut().update();
+        let events = harness.app().world().resource::<Events<CubemapCaptured>>();
+        events.iter_current_update_events().next().cloned()
+    });
+    assert!(captured.is_some_and(|event| event.success));
+}
+
+#[test]
+fn orients_cubemap_faces() {
+    let path = std::env::temp_dir().join("bevy_capture_test_cubemap_faces.ktx2");
+    capture_cubemap_markers(&path, 32);
+
+    let ktx2 = fs::read(&path).unwrap();
+    let level_offset = u64::from_le_bytes(ktx2[80..88].try_into().unwrap()) as usize;
+    let faces = ktx2[level_offset..]
+        .chunks_exact(32 * 32 * 8)
+        .collect::<Vec<_>>();
+    assert_eq!(faces.len(), 6);
+
+    // Whether the channels of a texel are lit. Positive half floats order like their bits.
+    let lit = |face: &[u8], x: usize, y: usize| {
+        let offset = (y * 32 + x) * 8;
+        [0, 1, 2]
+            .map(|c| u16::from_le_bytes([face[offset + c * 2], face[offset + c * 2 + 1]]) >= 0x3800)
+    };
+    for (face, (_, color)) in faces.iter().zip(CUBEMAP_MARKERS) {
+        assert_eq!(lit(face, 24, 8), color.map(|channel| channel > 0.5));
+        assert_eq!(lit(face, 8, 8), [false; 3]);
+        assert_eq!(lit(face, 24, 24), [false; 3]);
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "hdr")]
+#[test]
+fn projects_cubemap_to_equirect() {
+    use std::f32::consts::{PI, TAU};
+
+    let path = std::env::temp_dir().join("bevy_capture_test_cubemap_equirect.hdr");
+    capture_cubemap_markers(&path, 32);
+
+    let equirect = image::open(&path).unwrap().into_rgb32f();
+    assert_eq!(equirect.dimensions(), (128, 64));
+
+    // The center looks along -Z, longitude increases towards +X and latitude towards +Y.
+    for (direction, color) in CUBEMAP_MARKERS {
+        let direction = direction.normalize();
+        let longitude = direction.x.atan2(-direction.z);
+        let latitude = direction.y.asin();
+        let x = ((longitude / TAU + 0.5) * 128.0) as u32;
+        let y = ((0.5 - latitude / PI) * 64.0) as u32;
+        let pixel = equirect.get_pixel(x, y).0.map(|channel| channel > 0.5);
+        assert_eq!(pixel, color.map(|channel| channel > 0.5), "{direction}");
+    }
+    assert_eq!(equirect.get_pixel(64, 32).0, [0.0; 3]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "probe_grid")]
+#[test]
+fn bakes_probe_grid() {
+    use bevy_capture::probe_grid::{BakeProbeGrid, ProbeGridBaked, ProbeGridPlugin};
+
+    let mut harness = HeadlessHarness::new_with_plugins(16, 8, ProbeGridPlugin).expect(NO_ADAPTER);
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_probe_grid");
+    let _ = fs::remove_dir_all(&dir);
+    harness.app_mut().world_mut().send_event(
+        BakeProbeGrid::new(Vec3::ZERO, Vec3::X, UVec3::new(2, 1, 1), &dir)
+            .with_size(4)
+            .with_batch_size(1),
+    );
+
+    let baked = (0..20)
+        .find_map(|_| {
+            harness.app_mut().update();
+            let events = harness.app().world().resource::<Events<ProbeGridBaked>>();
+            events.iter_current_update_events().next().cloned()
+        })
+        .unwrap();
+    assert_eq!(baked.placement, Some(dir.join("probes.json")));
+
+    let placement: serde_json::Value =
+        serde_json::from_slice(&fs::read(dir.join("probes.json")).unwrap()).unwrap();
+    assert_eq!(placement["counts"], serde_json::json!([2, 1, 1]));
+    assert_eq!(
+        placement["probes"][1],
+        serde_json::json!({
+            "index": [1, 0, 0],
+            "position": [1.0, 0.0, 0.0],
+            "file": "probe_1_0_0.ktx2",
+        })
+    );
+    assert!(dir.join("probe_0_0_0.ktx2").exists());
+    assert!(dir.join("probe_1_0_0.ktx2").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
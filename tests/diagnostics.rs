@@ -0,0 +1,261 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_capture::{
+    benchmark::{BenchmarkEncoder, BenchmarkPlugin},
+    encoder::{self, test::TestEncoder},
+    gpu_timing::{GpuTimingEncoder, GpuTimingPlugin},
+    metadata::FrameMetadata,
+    testing::HeadlessHarness,
+    CaptureWorkerSettings, Encoder,
+};
+use common::{harness, NO_ADAPTER};
+use std::{
+    fs, io,
+    sync::{Arc, Mutex},
+};
+
+/// The spans and log events (info and above) of all tests, recorded by a global subscriber.
+#[derive(Clone, Default)]
+struct Recorded {
+    spans: Arc<Mutex<Vec<String>>>,
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+impl Recorded {
+    fn get() -> &'static Self {
+        use bevy::log::tracing_subscriber::{layer::SubscriberExt, Registry};
+        use std::sync::OnceLock;
+
+        static RECORDED: OnceLock<Recorded> = OnceLock::new();
+        RECORDED.get_or_init(|| {
+            let recorded = Recorded::default();
+            let subscriber = Registry::default().with(recorded.clone());
+            bevy::utils::tracing::subscriber::set_global_default(subscriber).unwrap();
+            recorded
+        })
+    }
+
+    /// Returns the number of recorded spans with the given name and fields.
+    #[cfg(feature = "trace")]
+    fn spans(&self, span: &str) -> usize {
+        let spans = self.spans.lock().unwrap();
+        spans.iter().filter(|recorded| *recorded == span).count()
+    }
+
+    /// Returns the recorded log events containing the given string.
+    fn events(&self, pattern: &str) -> Vec<String> {
+        let events = self.events.lock().unwrap();
+        events
+            .iter()
+            .filter(|event| event.contains(pattern))
+            .cloned()
+            .collect()
+    }
+}
+
+impl<S: bevy::utils::tracing::Subscriber> bevy::log::tracing_subscriber::Layer<S> for Recorded {
+    fn on_new_span(
+        &self,
+        attrs: &bevy::utils::tracing::span::Attributes<'_>,
+        _id: &bevy::utils::tracing::span::Id,
+        _ctx: bevy::log::tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut fields = Fields(attrs.metadata().name().to_string());
+        attrs.record(&mut fields);
+        self.spans.lock().unwrap().push(fields.0);
+    }
+
+    fn on_event(
+        &self,
+        event: &bevy::utils::tracing::Event<'_>,
+        _ctx: bevy::log::tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let level = *event.metadata().level();
+        if level > bevy::utils::tracing::Level::INFO {
+            return;
+        }
+        let mut fields = Fields(level.to_string());
+        event.record(&mut fields);
+        self.events.lock().unwrap().push(fields.0);
+    }
+}
+
+struct Fields(String);
+
+/// An encoder with a unique name, so its spans and log events can be told apart from the ones of
+/// other tests. Fails to encode if it has an error.
+struct NamedEncoder {
+    name: &'static str,
+    error: Option<&'static str>,
+}
+
+impl Encoder for NamedEncoder {
+    fn encode(&mut self, _image: &Image) -> encoder::Result<()> {
+        match self.error {
+            Some(error) => Err(io::Error::other(error).into()),
+            None => Ok(()),
+        }
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+}
+
+impl bevy::utils::tracing::field::Visit for Fields {
+    fn record_debug(
+        &mut self,
+        field: &bevy::utils::tracing::field::Field,
+        value: &dyn std::fmt::Debug,
+    ) {
+        use std::fmt::Write as _;
+        write!(self.0, " {}={:?}", field.name(), value).unwrap();
+    }
+
+    fn record_str(&mut self, field: &bevy::utils::tracing::field::Field, value: &str) {
+        use std::fmt::Write as _;
+        write!(self.0, " {}={}", field.name(), value).unwrap();
+    }
+}
+
+#[test]
+fn logs_encode_errors_by_policy() {
+    use bevy::utils::Duration;
+    use bevy_capture::CaptureLogPolicy;
+
+    let log = Recorded::get();
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+
+    let capture = |harness: &mut HeadlessHarness, name, policy: CaptureLogPolicy| {
+        let world = harness.app_mut().world_mut();
+        world.entity_mut(camera).insert(policy);
+        harness.capture(
+            3,
+            NamedEncoder {
+                name,
+                error: Some("disk full"),
+            },
+        );
+        log.events(name)
+    };
+
+    // Every error is logged by default.
+    let events = capture(&mut harness, "log_errors", CaptureLogPolicy::default());
+    assert_eq!(events.len(), 3, "{events:?}");
+    assert!(events.iter().all(|event| event.starts_with("ERROR")
+        && event.contains("Failed to encode with log_errors")
+        && event.contains("disk full")));
+
+    // Nothing is logged when quiet.
+    let events = capture(&mut harness, "log_quiet", CaptureLogPolicy::quiet());
+    assert!(events.is_empty(), "{events:?}");
+
+    // Verbose captures also log when they start, with the names of their encoders.
+    let events = capture(&mut harness, "log_verbose", CaptureLogPolicy::verbose());
+    assert_eq!(events.len(), 4, "{events:?}");
+    assert!(events[0].starts_with("INFO") && events[0].contains("started with 1 encoders"));
+    assert!(!log
+        .events(&format!("Capture of {camera:?} finished after 3 frames"))
+        .is_empty());
+
+    // Aggregated errors are logged once per interval, the rest when the capture stops.
+    let events = capture(
+        &mut harness,
+        "log_aggregated",
+        CaptureLogPolicy::default().with_aggregated_errors(Duration::from_secs(3600)),
+    );
+    assert_eq!(events.len(), 2, "{events:?}");
+    assert!(events[0].contains("Failed to encode 1 times"));
+    assert!(events[1].contains("Failed to encode 2 times"));
+    assert!(events[1].contains("last error (of log_aggregated"));
+
+    // So are the errors of encoders on worker threads.
+    harness
+        .app_mut()
+        .world_mut()
+        .entity_mut(camera)
+        .insert(CaptureWorkerSettings::default());
+    let events = capture(&mut harness, "log_worker", CaptureLogPolicy::default());
+    assert_eq!(events.len(), 3, "{events:?}");
+}
+
+#[test]
+fn records_benchmark() {
+    let mut harness = HeadlessHarness::new_with_plugins(64, 32, BenchmarkPlugin).expect(NO_ADAPTER);
+
+    let results = std::env::temp_dir().join("bevy_capture_test_benchmark.json");
+    let _ = fs::remove_file(&results);
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    harness.capture(4, BenchmarkEncoder::new(encoder, &results));
+
+    let json = fs::read_to_string(&results).unwrap();
+    assert!(json.starts_with(r#"{ "frames": "#));
+    assert!(!json.starts_with(r#"{ "frames": 0,"#));
+    assert!(json.contains(r#""p99_ms": "#));
+
+    let last = handle.images().pop().unwrap();
+    assert_eq!(last.size(), UVec2::new(64, 32));
+
+    fs::remove_file(&results).unwrap();
+}
+
+#[test]
+fn records_gpu_timings() {
+    let mut harness = HeadlessHarness::new_with_plugins(64, 32, GpuTimingPlugin).expect(NO_ADAPTER);
+
+    let csv = std::env::temp_dir().join("bevy_capture_test_gpu_timing.csv");
+    let _ = fs::remove_file(&csv);
+    harness.capture(8, GpuTimingEncoder::new(&csv).unwrap());
+
+    let csv_content = fs::read_to_string(&csv).unwrap();
+    let mut lines = csv_content.lines();
+    assert_eq!(lines.next(), Some("frame,pass,measurement,ms"));
+    assert!(lines.any(|line| line.contains(",capture_copy,elapsed_cpu,")));
+
+    // The rows are keyed by the index of the captured frame, not by the number of encoded frames.
+    let mut encoder = GpuTimingEncoder::new(&csv).unwrap();
+    let mut metadata = FrameMetadata::default();
+    metadata.insert(bevy_capture::metadata::FRAME_INDEX_KEY, 5u64);
+    metadata.insert("render/main_pass/elapsed_gpu", 1.5);
+    encoder
+        .encode_with_metadata(&Image::default(), &metadata)
+        .unwrap();
+    Box::new(encoder).finish();
+    assert_eq!(
+        fs::read_to_string(&csv).unwrap(),
+        "frame,pass,measurement,ms\n5,main_pass,elapsed_gpu,1.500\n"
+    );
+
+    fs::remove_file(&csv).unwrap();
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn traces_capture_spans() {
+    let spans = Recorded::get();
+
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+    let encoder = || NamedEncoder {
+        name: "traced",
+        error: None,
+    };
+    harness.capture(2, encoder());
+
+    // The spans of a capture name its entity and encoders.
+    assert!(spans.spans(&format!("capture_copy entity={camera:?}")) >= 2);
+    assert!(spans.spans(&format!("capture_encode entity={camera:?}")) >= 2);
+    assert_eq!(spans.spans("capture_encoder encoder=traced"), 2);
+
+    // So do the spans of encoders on worker threads.
+    harness
+        .app_mut()
+        .world_mut()
+        .entity_mut(camera)
+        .insert(CaptureWorkerSettings::default());
+    harness.capture(2, encoder());
+    assert_eq!(spans.spans("capture_encoder encoder=traced"), 4);
+}
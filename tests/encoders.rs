@@ -0,0 +1,518 @@
+mod common;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureFormat},
+};
+use bevy_capture::{
+    encoder::{
+        self,
+        capabilities::EncoderCapabilities,
+        fallback::FallbackEncoder,
+        file_output::FileOutput,
+        frames::FramesEncoder,
+        ladder::LadderEncoder,
+        test::TestEncoder,
+        uncompressed::{UncompressedFormat, UncompressedFramesEncoder},
+    },
+    Capture, Encoder,
+};
+use common::harness;
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "mp4_openh264_libloading")]
+#[test]
+fn rejects_invalid_openh264_library() {
+    use bevy_capture::encoder::mp4_openh264::{openh264, Mp4Openh264Encoder, Openh264Backend};
+
+    // Only the official binaries are loaded, anything else fails before encoding.
+    let path = std::env::temp_dir().join("bevy_capture_libopenh264.so");
+    fs::write(&path, b"not a library").unwrap();
+    let result = Mp4Openh264Encoder::new_with_backend(
+        std::io::Cursor::new(Vec::new()),
+        16,
+        8,
+        Openh264Backend::Library(path.clone()),
+        openh264::encoder::EncoderConfig::new(),
+    );
+    assert!(matches!(result, Err(encoder::Error::Codec(_))));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn encodes_ladder() {
+    let mut harness = harness(64, 32);
+    let full = TestEncoder::new();
+    let half = TestEncoder::new().with_images();
+    let (full_handle, half_handle) = (full.handle(), half.handle());
+    harness.capture(
+        2,
+        LadderEncoder::new()
+            .with_rung(1080, full)
+            .with_rung(16, half),
+    );
+
+    let full_frames = full_handle.frames();
+    assert_eq!((full_frames[0].width, full_frames[0].height), (64, 32));
+    let half_frames = half_handle.frames();
+    assert_eq!(half_frames.len(), 2);
+    assert_eq!((half_frames[0].width, half_frames[0].height), (32, 16));
+    assert_eq!(half_handle.images()[0].data.len(), 32 * 16 * 4);
+    assert!(full_handle.is_finished() && half_handle.is_finished());
+}
+
+#[test]
+fn writes_uncompressed_frames() {
+    let mut harness = harness(4, 2);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(1.0, 0.0, 0.0)));
+    let dir = std::env::temp_dir().join("bevy_capture_test_uncompressed");
+    let _ = fs::remove_dir_all(&dir);
+    harness.capture(
+        1,
+        (
+            UncompressedFramesEncoder::new(dir.join("ppm"), UncompressedFormat::Ppm),
+            UncompressedFramesEncoder::new(dir.join("tga"), UncompressedFormat::Tga),
+            UncompressedFramesEncoder::new(dir.join("bmp"), UncompressedFormat::Bmp),
+        ),
+    );
+
+    let ppm = fs::read(dir.join("ppm/frame_000000.ppm")).unwrap();
+    assert!(ppm.starts_with(b"P6\n4 2\n255\n"));
+    assert_eq!(ppm.len(), 11 + 4 * 2 * 3);
+    assert_eq!(ppm[11..14], [255, 0, 0]);
+
+    let tga = fs::read(dir.join("tga/frame_000000.tga")).unwrap();
+    assert_eq!(tga.len(), 18 + 4 * 2 * 4);
+    assert_eq!(tga[12..17], [4, 0, 2, 0, 32]);
+    assert_eq!(tga[18..22], [0, 0, 255, 255]);
+
+    let bmp = fs::read(dir.join("bmp/frame_000000.bmp")).unwrap();
+    assert_eq!(bmp.len(), 54 + 4 * 2 * 4);
+    assert_eq!(bmp[..2], *b"BM");
+    assert_eq!(bmp[2..6], (bmp.len() as u32).to_le_bytes());
+    assert_eq!(bmp[22..26], (-2i32).to_le_bytes());
+    assert_eq!(bmp[54..58], [0, 0, 255, 255]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn writes_frames_with_file_output() {
+    let mut harness = harness(40, 30);
+    // The target directory, since direct I/O is not supported by tmpfs.
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("file_output");
+    let _ = fs::remove_dir_all(&dir);
+    let output = FileOutput::new()
+        .with_buffer_size(1000)
+        .with_direct_io(true)
+        .with_background_writes(2);
+    harness.capture(
+        3,
+        (
+            UncompressedFramesEncoder::new(dir.join("tga"), UncompressedFormat::Tga)
+                .with_output(output.clone()),
+            FramesEncoder::new(dir.join("png")).with_output(output),
+        ),
+    );
+
+    // The frames are larger than the buffer and not a multiple of the block size.
+    for frame in 0..3 {
+        let tga = fs::read(dir.join(format!("tga/frame_{frame:06}.tga"))).unwrap();
+        assert_eq!(tga.len(), 18 + 40 * 30 * 4);
+        assert!(dir.join(format!("png/frame_{frame:06}.png")).is_file());
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn converts_frames_to_encoder_capabilities() {
+    let mut harness = harness(63, 31);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(1.0, 0.0, 0.0)));
+
+    struct CapableEncoder(TestEncoder, EncoderCapabilities);
+
+    impl Encoder for CapableEncoder {
+        fn encode(&mut self, image: &Image) -> encoder::Result<()> {
+            self.0.encode(image)
+        }
+
+        fn finish(self: Box<Self>) {
+            Box::new(self.0).finish();
+        }
+
+        fn capabilities(&self) -> EncoderCapabilities {
+            self.1.clone()
+        }
+    }
+
+    let bgra = TestEncoder::new().with_images();
+    let bgra_handle = bgra.handle();
+    let small = TestEncoder::new();
+    let small_handle = small.handle();
+    harness.capture(
+        2,
+        (
+            CapableEncoder(
+                bgra,
+                EncoderCapabilities::new()
+                    .with_formats([TextureFormat::Bgra8UnormSrgb])
+                    .with_even_dimensions(true),
+            ),
+            CapableEncoder(
+                small,
+                EncoderCapabilities::new().with_max_dimensions(32, 32),
+            ),
+        ),
+    );
+
+    // The frames are converted to BGRA8 and padded to even dimensions.
+    let image = &bgra_handle.images()[0];
+    assert_eq!(
+        image.texture_descriptor.format,
+        TextureFormat::Bgra8UnormSrgb
+    );
+    assert_eq!((image.width(), image.height()), (64, 32));
+    assert_eq!(image.data[(31 * 64 + 63) * 4..][..4], [0, 0, 255, 255]);
+    // Frames that exceed the maximum dimensions are rejected before reaching the encoder.
+    assert_eq!(small_handle.encode_count(), 0);
+    assert!(small_handle.is_finished());
+}
+
+#[test]
+fn falls_back_to_next_encoder() {
+    let mut harness = harness(64, 32);
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    let dimensions = Arc::new(Mutex::new(None));
+    let dimensions_clone = Arc::clone(&dimensions);
+    let fallback = FallbackEncoder::new()
+        .with_candidate("missing", |_, _| -> encoder::Result<TestEncoder> {
+            Err("not installed".into())
+        })
+        .with_candidate("test", move |width, height| {
+            *dimensions_clone.lock().unwrap() = Some((width, height));
+            Ok(encoder)
+        })
+        .with_candidate("unused", |_, _| -> encoder::Result<TestEncoder> {
+            panic!("only the first candidate that can be created is used")
+        });
+    harness.capture(2, fallback);
+
+    // The candidates are created with the dimensions of the first frame.
+    assert_eq!(*dimensions.lock().unwrap(), Some((64, 32)));
+    assert_eq!(handle.encode_count(), 2);
+    assert!(handle.is_finished());
+}
+
+#[cfg(feature = "mp4_ffmpeg_cli")]
+#[test]
+fn probes_ffmpeg() {
+    use bevy_capture::encoder::mp4_ffmpeg_cli::{Mp4FfmpegCliEncoder, VideoCodec};
+
+    let encoder = Mp4FfmpegCliEncoder::new(std::env::temp_dir().join("bevy_capture_probe.mp4"))
+        .unwrap()
+        .with_codec(VideoCodec::H264);
+    match Mp4FfmpegCliEncoder::probe() {
+        Ok(info) => {
+            assert!(Mp4FfmpegCliEncoder::is_available());
+            assert!(!info.version().is_empty());
+            assert_eq!(
+                encoder.checked().is_ok(),
+                info.supports_codec(VideoCodec::H264)
+            );
+        }
+        Err(_) => {
+            // Without ffmpeg, the encoder fails before the capture instead of when it finishes.
+            assert!(!Mp4FfmpegCliEncoder::is_available());
+            assert!(encoder.checked().is_err());
+        }
+    }
+}
+
+#[cfg(feature = "state_snapshot")]
+#[test]
+fn records_state_snapshots() {
+    use bevy_capture::{
+        state_snapshot::{read_snapshot, StateRecorder, StateSnapshotPlugin},
+        testing::HeadlessHarness,
+    };
+    use common::NO_ADAPTER;
+
+    let mut harness =
+        HeadlessHarness::new_with_plugins(16, 16, StateSnapshotPlugin).expect(NO_ADAPTER);
+    let path = std::env::temp_dir().join("bevy_capture_test_state.jsonl");
+    let camera = harness.camera();
+    let world = harness.app_mut().world_mut();
+    world.entity_mut(camera).insert(
+        StateRecorder::new(&path)
+            .with_component::<Transform>()
+            .with_interval(2),
+    );
+    world.spawn((
+        Name::new("Player"),
+        SpatialBundle::from_transform(Transform::from_xyz(1.0, 2.0, 3.0)),
+    ));
+
+    harness.capture(5, TestEncoder::new());
+
+    let snapshots = fs::read_to_string(&path).unwrap();
+    let frames = snapshots
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["frame"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(frames, [0, 2, 4]);
+
+    // The latest snapshot before a frame without one is returned.
+    let snapshot = read_snapshot(&path, 3).unwrap().unwrap();
+    assert_eq!(snapshot["frame"], 2);
+    let player = snapshot["entities"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entity| entity["name"] == "Player")
+        .unwrap();
+    assert_eq!(
+        player["components"]["bevy_transform::components::transform::Transform"]["translation"],
+        serde_json::json!({ "x": 1.0, "y": 2.0, "z": 3.0 })
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "sidecar")]
+#[test]
+fn writes_checksum_sidecars() {
+    use bevy::render::render_resource::TextureDimension;
+    use bevy_capture::{encoder::y4m::Y4mEncoder, sidecar::*};
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_sidecar");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join("abc.txt");
+    fs::write(&path, "abc").unwrap();
+    let checksum = write_checksum(&path).unwrap();
+    assert_eq!(checksum, dir.join("abc.txt.sha256"));
+    assert_eq!(
+        fs::read_to_string(&checksum).unwrap(),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  abc.txt\n"
+    );
+
+    // The sidecars are written when the encoder finishes.
+    let key = SigningKey::generate().unwrap();
+    assert_eq!(
+        SigningKey::from_hex(&key.to_hex()).unwrap().public_key(),
+        key.public_key()
+    );
+    let path = dir.join("capture.y4m");
+    let mut encoder = SidecarEncoder::new(Y4mEncoder::new(fs::File::create(&path).unwrap()), &path)
+        .with_signing_key(key);
+    let image = Image::new_fill(
+        Extent3d {
+            width: 4,
+            height: 4,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[255, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    encoder.encode(&image).unwrap();
+    Box::new(encoder).finish();
+
+    let checksum = fs::read_to_string(dir.join("capture.y4m.sha256")).unwrap();
+    assert!(checksum.ends_with("  capture.y4m\n"));
+    let signature = fs::read_to_string(dir.join("capture.y4m.minisig")).unwrap();
+    let lines = signature.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("untrusted comment: "));
+    assert!(lines[2].starts_with("trusted comment: timestamp:"));
+    assert!(lines[2].ends_with("\tfile:capture.y4m"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn creates_encoders_from_configs() {
+    use bevy_capture::encoder::{
+        frames::FramesConfig,
+        y4m::{Y4mConfig, Y4mEncoder},
+    };
+
+    let mut harness = harness(4, 2);
+    let dir = std::env::temp_dir().join("bevy_capture_test_configs");
+    let _ = fs::remove_dir_all(&dir);
+
+    // The same configs start multiple captures.
+    let frames = FramesConfig::new().with_output(FileOutput::new().with_background_writes(2));
+    let y4m = Y4mConfig::new().with_framerate(30);
+    fs::create_dir_all(&dir).unwrap();
+    for capture in ["a", "b"] {
+        let stream = fs::File::create(dir.join(capture).with_extension("y4m")).unwrap();
+        harness.capture(
+            2,
+            (
+                FramesEncoder::from_config(dir.join(capture), &frames),
+                Y4mEncoder::from_config(stream, &y4m),
+            ),
+        );
+        assert_eq!(fs::read_dir(dir.join(capture)).unwrap().count(), 2);
+        let stream = fs::read(dir.join(capture).with_extension("y4m")).unwrap();
+        assert!(stream.starts_with(b"YUV4MPEG2 W4 H2 F30:1"));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn deserializes_configs_from_settings() {
+    use bevy_capture::encoder::{
+        frames::FramesConfig, uncompressed::UncompressedFramesConfig, y4m::Y4mConfig,
+    };
+
+    // Configs roundtrip through a settings file.
+    let uncompressed = UncompressedFramesConfig::new(UncompressedFormat::Ppm)
+        .with_output(FileOutput::new().with_background_writes(4));
+    let json = serde_json::to_string(&uncompressed).unwrap();
+    assert_eq!(
+        serde_json::from_str::<UncompressedFramesConfig>(&json).unwrap(),
+        uncompressed
+    );
+
+    // Missing fields fall back to the defaults.
+    let y4m: Y4mConfig = serde_json::from_str(r#"{ "framerate": 30 }"#).unwrap();
+    assert_eq!(y4m, Y4mConfig::new().with_framerate(30));
+    let frames: FramesConfig = serde_json::from_str("{}").unwrap();
+    assert_eq!(frames, FramesConfig::new());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn creates_encoders_by_name() {
+    use bevy_capture::encoder::registry::EncoderRegistry;
+
+    let mut harness = harness(4, 2);
+    let dir = std::env::temp_dir().join("bevy_capture_test_registry");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let registry = harness.app_mut().world().resource::<EncoderRegistry>();
+    assert!(registry.names().contains(&"y4m"));
+    let mut encoders = registry
+        .create_from_str("frames", dir.join("frames"), "{}")
+        .unwrap();
+    encoders.extend(
+        registry
+            .create(
+                "y4m",
+                dir.join("out.y4m"),
+                serde_json::json!({ "framerate": 30 }),
+            )
+            .unwrap(),
+    );
+    assert!(registry.create_from_str("unknown", &dir, "{}").is_err());
+    assert!(registry
+        .create_from_str("y4m", &dir, r#"{ "framerate": "fast" }"#)
+        .is_err());
+
+    harness.capture(2, encoders);
+    assert_eq!(fs::read_dir(dir.join("frames")).unwrap().count(), 2);
+    let stream = fs::read(dir.join("out.y4m")).unwrap();
+    assert!(stream.starts_with(b"YUV4MPEG2 W4 H2 F30:1"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn forwards_capabilities_in_wrappers() {
+    use bevy_capture::encoder::{retry::Retry, watermark::WatermarkEncoder};
+
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+
+    struct CapableEncoder(TestEncoder, EncoderCapabilities);
+
+    impl Encoder for CapableEncoder {
+        fn encode(&mut self, image: &Image) -> encoder::Result<()> {
+            self.0.encode(image)
+        }
+
+        fn finish(self: Box<Self>) {
+            Box::new(self.0).finish();
+        }
+
+        fn capabilities(&self) -> EncoderCapabilities {
+            self.1.clone()
+        }
+    }
+
+    let fixed = TestEncoder::new().with_images();
+    let fixed_handle = fixed.handle();
+    let fixed = Retry::new(CapableEncoder(
+        fixed,
+        EncoderCapabilities::new().with_fixed_dimensions(true),
+    ));
+    assert!(fixed.capabilities().fixed_dimensions());
+    let bgra = TestEncoder::new().with_images();
+    let bgra_handle = bgra.handle();
+    let bgra = WatermarkEncoder::new(
+        CapableEncoder(
+            bgra,
+            EncoderCapabilities::new().with_formats([TextureFormat::Bgra8UnormSrgb]),
+        ),
+        "bevy",
+    );
+    assert_eq!(
+        bgra.capabilities().formats(),
+        Some(&[TextureFormat::Bgra8UnormSrgb][..])
+    );
+
+    harness
+        .app_mut()
+        .world_mut()
+        .get_mut::<Capture>(camera)
+        .unwrap()
+        .start((fixed, bgra));
+    for _ in 0..3 {
+        harness.app_mut().update();
+    }
+    let frames = fixed_handle.encode_count();
+    assert!(frames > 0);
+
+    // Frames of a different size are rejected before they reach the wrapped encoder.
+    let world = harness.app_mut().world_mut();
+    let bevy::render::camera::RenderTarget::Image(target) =
+        world.get::<Camera>(camera).unwrap().target.clone()
+    else {
+        unreachable!()
+    };
+    world
+        .resource_mut::<Assets<Image>>()
+        .get_mut(&target)
+        .unwrap()
+        .resize(Extent3d {
+            width: 32,
+            height: 16,
+            depth_or_array_layers: 1,
+        });
+    for _ in 0..3 {
+        harness.app_mut().update();
+    }
+    assert!(bgra_handle.images().iter().any(|image| image.width() == 32));
+    assert!(fixed_handle
+        .images()
+        .iter()
+        .all(|image| (image.width(), image.height()) == (16, 8)));
+    assert!(fixed_handle.encode_count() < bgra_handle.encode_count());
+
+    // The frames modified by the wrapper are converted to the format of the wrapped encoder.
+    assert!(bgra_handle
+        .images()
+        .iter()
+        .all(|image| image.texture_descriptor.format == TextureFormat::Bgra8UnormSrgb));
+}
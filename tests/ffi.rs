@@ -0,0 +1,61 @@
+mod common;
+
+use bevy_capture::{testing::HeadlessHarness, Capture};
+use common::NO_ADAPTER;
+use std::fs;
+
+#[test]
+fn controls_capture_over_ffi() {
+    use bevy_capture::ffi::*;
+    use std::{ffi::CString, ptr};
+
+    let mut harness = HeadlessHarness::new_with_plugins(4, 2, CaptureFfiPlugin).expect(NO_ADAPTER);
+    let dir = std::env::temp_dir().join("bevy_capture_test_ffi");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let c_string = |path: &str| CString::new(path).unwrap();
+
+    let output = c_string(dir.join("out.y4m").to_str().unwrap());
+    let encoder = c_string("y4m");
+    let config = c_string(r#"{ "framerate": 30 }"#);
+    unsafe {
+        assert_eq!(
+            bevy_capture_start(ptr::null(), encoder.as_ptr(), ptr::null()),
+            BEVY_CAPTURE_NO_OUTPUT_PATH
+        );
+        assert_eq!(
+            bevy_capture_set_output_path(ptr::null()),
+            BEVY_CAPTURE_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            bevy_capture_set_output_path(output.as_ptr()),
+            BEVY_CAPTURE_OK
+        );
+        assert_eq!(
+            bevy_capture_start(ptr::null(), encoder.as_ptr(), config.as_ptr()),
+            BEVY_CAPTURE_OK
+        );
+    }
+    for _ in 0..2 {
+        harness.app_mut().update();
+    }
+    assert_eq!(unsafe { bevy_capture_stop(ptr::null()) }, BEVY_CAPTURE_OK);
+    harness.app_mut().update();
+    let stream = fs::read(dir.join("out.y4m")).unwrap();
+    assert!(stream.starts_with(b"YUV4MPEG2 W4 H2 F30:1"));
+
+    let shot = c_string(dir.join("shot.png").to_str().unwrap());
+    assert_eq!(
+        unsafe { bevy_capture_screenshot(ptr::null(), shot.as_ptr()) },
+        BEVY_CAPTURE_OK
+    );
+    for _ in 0..4 {
+        harness.app_mut().update();
+    }
+    let capture = harness.app().world().get::<Capture>(harness.camera());
+    assert!(!capture.unwrap().is_capturing());
+    assert!(fs::read(dir.join("shot.png"))
+        .unwrap()
+        .starts_with(b"\x89PNG"));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_capture::{testing::HeadlessHarness, Capture};
+use common::NO_ADAPTER;
+use std::fs;
+
+#[test]
+fn controls_capture_from_lua() {
+    use bevy_capture::scripting::{mlua::Lua, CaptureScriptQueue, CaptureScriptingPlugin};
+
+    let mut harness =
+        HeadlessHarness::new_with_plugins(4, 2, CaptureScriptingPlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+    harness
+        .app_mut()
+        .world_mut()
+        .entity_mut(camera)
+        .insert(Name::new("main"));
+    let dir = std::env::temp_dir().join("bevy_capture_test_lua");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let lua = Lua::new();
+    let queue = harness.app().world().resource::<CaptureScriptQueue>();
+    queue.register_lua(&lua).unwrap();
+    lua.globals().set("dir", dir.to_str().unwrap()).unwrap();
+
+    // A screenshot captures a single frame and stops again.
+    lua.load(r#"capture.screenshot(dir .. "/shot.png", "main")"#)
+        .exec()
+        .unwrap();
+    for _ in 0..4 {
+        harness.app_mut().update();
+    }
+    let capture = harness.app().world().get::<Capture>(camera).unwrap();
+    assert!(!capture.is_capturing());
+    assert!(fs::read(dir.join("shot.png"))
+        .unwrap()
+        .starts_with(b"\x89PNG"));
+
+    // Recordings use the encoders of the registry. Unknown cameras are ignored.
+    lua.load(
+        r#"
+        capture.start("y4m", dir .. "/out.y4m", '{ "framerate": 30 }')
+        capture.stop("unknown")
+        "#,
+    )
+    .exec()
+    .unwrap();
+    for _ in 0..2 {
+        harness.app_mut().update();
+    }
+    lua.load("capture.stop()").exec().unwrap();
+    harness.app_mut().update();
+    let capture = harness.app().world().get::<Capture>(camera).unwrap();
+    assert!(!capture.is_capturing());
+    let stream = fs::read(dir.join("out.y4m")).unwrap();
+    assert!(stream.starts_with(b"YUV4MPEG2 W4 H2 F30:1"));
+    fs::remove_dir_all(&dir).unwrap();
+}
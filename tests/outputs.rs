@@ -0,0 +1,728 @@
+mod common;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_capture::{
+    encoder::{self, test::TestEncoder},
+    screen::{ScreenCapture, ScreenSource},
+    Encoder,
+};
+use common::harness;
+use std::{fs, io, thread};
+
+#[cfg(unix)]
+#[test]
+fn publishes_frames_over_ipc() {
+    use bevy_capture::encoder::ipc::{IpcClient, IpcEncoder};
+
+    let mut harness = harness(16, 8);
+
+    let path = std::env::temp_dir().join("bevy_capture_test_ipc.sock");
+    let encoder = IpcEncoder::bind(&path).unwrap();
+    let client = IpcClient::connect(&path).unwrap();
+    harness.capture(2, encoder);
+
+    let frames = client.collect::<io::Result<Vec<_>>>().unwrap();
+    assert_eq!(frames.len(), 2);
+    for (i, frame) in frames.iter().enumerate() {
+        assert_eq!(frame.frame, i as u64);
+        assert_eq!((frame.width, frame.height), (16, 8));
+        assert_eq!(frame.data.len(), 16 * 8 * 4);
+    }
+    assert!(!path.exists());
+
+    // Stale sockets are replaced, but other files are left alone.
+    let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    drop(stale);
+    drop(IpcEncoder::bind(&path).unwrap());
+    fs::write(&path, "not a socket").unwrap();
+    assert!(IpcEncoder::bind(&path).is_err());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "not a socket");
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "zmq")]
+#[test]
+fn publishes_frames_over_zmq() {
+    use bevy_capture::encoder::zmq::{zmq, FrameEncoding, ZmqEncoder};
+    use std::time::{Duration, Instant};
+
+    let image = Image::new_fill(
+        Extent3d {
+            width: 4,
+            height: 2,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 255, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+
+    let context = zmq::Context::new();
+    let publisher = context.socket(zmq::PUB).unwrap();
+    publisher.bind("inproc://bevy_capture_zmq").unwrap();
+    let subscriber = context.socket(zmq::SUB).unwrap();
+    subscriber.connect("inproc://bevy_capture_zmq").unwrap();
+    subscriber.set_subscribe(b"camera").unwrap();
+    subscriber.set_rcvtimeo(10).unwrap();
+
+    // Subscriptions reach the publisher asynchronously, so frames are published until one
+    // arrives. Returns the index of the frame it was published as and the message.
+    fn publish_until_received(
+        encoder: &mut ZmqEncoder,
+        subscriber: &zmq::Socket,
+        image: &Image,
+        frame: &mut u64,
+    ) -> (u64, Vec<Vec<u8>>) {
+        let start = Instant::now();
+        loop {
+            encoder.encode(image).unwrap();
+            *frame += 1;
+            if let Ok(message) = subscriber.recv_multipart(0) {
+                return (*frame - 1, message);
+            }
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+        }
+    }
+
+    let mut frame = 0;
+    let mut encoder = ZmqEncoder::new(publisher, "camera");
+    let (index, message) = publish_until_received(&mut encoder, &subscriber, &image, &mut frame);
+    assert_eq!(message.len(), 3);
+    assert_eq!(message[0], b"camera");
+    let header = &message[1];
+    assert_eq!(u64::from_le_bytes(header[0..8].try_into().unwrap()), index);
+    assert_eq!(u32::from_le_bytes(header[8..12].try_into().unwrap()), 4);
+    assert_eq!(u32::from_le_bytes(header[12..16].try_into().unwrap()), 2);
+    assert_eq!(header[16], FrameEncoding::Rgba8 as u8);
+    assert_eq!(message[2], [0, 255, 0, 255].repeat(8));
+
+    let mut encoder = encoder.with_jpeg(90);
+    encoder.encode(&image).unwrap();
+    let message = subscriber.recv_multipart(0).unwrap();
+    assert_eq!(message[1][16], FrameEncoding::Jpeg as u8);
+    assert_eq!(&message[2][..2], [0xff, 0xd8]);
+}
+
+#[cfg(all(feature = "v4l2", target_os = "linux"))]
+#[test]
+fn rejects_invalid_v4l2_output() {
+    use bevy_capture::encoder::v4l2::V4l2Encoder;
+
+    let image = |width: u32| {
+        Image::new_fill(
+            Extent3d {
+                width,
+                height: 2,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 255, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+            default(),
+        )
+    };
+    let path = std::env::temp_dir().join("bevy_capture_v4l2");
+    fs::write(&path, []).unwrap();
+
+    // YUYV packs two pixels into four bytes, so the width must be even.
+    let err = V4l2Encoder::new(&path).encode(&image(3)).unwrap_err();
+    assert!(err.to_string().contains("even width"), "{err}");
+
+    // The format can only be set on a v4l2loopback device.
+    let err = V4l2Encoder::new(&path).encode(&image(4)).unwrap_err();
+    assert!(
+        err.to_string().contains("failed to set the format"),
+        "{err}"
+    );
+    assert!(fs::read(&path).unwrap().is_empty());
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(all(unix, feature = "camera_extension"))]
+#[test]
+fn publishes_frames_to_camera_extension() {
+    use bevy_capture::encoder::{
+        ipc::IpcClient,
+        virtual_camera::{CameraExtensionBackend, VirtualCameraEncoder},
+    };
+
+    let image = Image::new_fill(
+        Extent3d {
+            width: 2,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[1, 2, 3, 4],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+
+    // The socket is created with the camera, so the extension connects after the first frame.
+    let path = std::env::temp_dir().join("bevy_capture_test_camera_extension.sock");
+    let mut encoder = VirtualCameraEncoder::new(CameraExtensionBackend::new(&path));
+    encoder.encode(&image).unwrap();
+    let client = IpcClient::connect(&path).unwrap();
+    encoder.encode(&image).unwrap();
+    encoder.encode(&image).unwrap();
+    Box::new(encoder).finish();
+
+    let frames = client.collect::<io::Result<Vec<_>>>().unwrap();
+    assert_eq!(frames.len(), 2);
+    for (i, frame) in frames.iter().enumerate() {
+        assert_eq!(frame.frame, i as u64 + 1);
+        assert_eq!((frame.width, frame.height), (2, 1));
+        assert_eq!(frame.data, [1, 2, 3, 4, 1, 2, 3, 4]);
+    }
+    assert!(!path.exists());
+}
+
+#[cfg(feature = "obs")]
+#[test]
+fn syncs_obs_recording_in_background() {
+    use bevy_capture::{
+        obs::{ObsClient, ObsPlugin, SyncObsRecording},
+        testing::HeadlessHarness,
+    };
+    use common::NO_ADAPTER;
+    use serde_json::{json, Value};
+    use std::{net::TcpListener, sync::mpsc, time::Duration};
+    use tungstenite::{Message, WebSocket};
+
+    // A minimal obs-websocket server that holds back its responses until it is released.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let (release, released) = mpsc::channel();
+    let server = thread::spawn(move || {
+        let send = |socket: &mut WebSocket<_>, op: u8, d: Value| {
+            let message = json!({ "op": op, "d": d }).to_string();
+            socket.send(Message::Text(message)).unwrap();
+        };
+
+        let mut socket = tungstenite::accept(listener.accept().unwrap().0).unwrap();
+        send(&mut socket, 0, json!({ "rpcVersion": 1 }));
+        socket.read().unwrap();
+        send(&mut socket, 2, json!({ "negotiatedRpcVersion": 1 }));
+
+        let released = released.recv_timeout(Duration::from_secs(10)).is_ok();
+        let mut requests = Vec::new();
+        while let Ok(Message::Text(text)) = socket.read() {
+            let request = serde_json::from_str::<Value>(&text).unwrap()["d"].clone();
+            requests.push(request["requestType"].as_str().unwrap().to_owned());
+            let status = json!({ "result": true, "code": 100 });
+            send(
+                &mut socket,
+                7,
+                json!({
+                    "requestType": request["requestType"],
+                    "requestId": request["requestId"],
+                    "requestStatus": status,
+                }),
+            );
+        }
+        (released, requests)
+    });
+
+    let mut harness = HeadlessHarness::new_with_plugins(16, 8, ObsPlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+    let world = harness.app_mut().world_mut();
+    world.insert_resource(ObsClient::connect(&url, None).unwrap());
+    world.entity_mut(camera).insert(SyncObsRecording::default());
+
+    // The app keeps running while OBS hasn't responded yet.
+    harness.capture(2, TestEncoder::new());
+    release.send(()).unwrap();
+
+    // The queued requests are still sent once the client is dropped.
+    drop(harness);
+    let (released, requests) = server.join().unwrap();
+    assert!(released);
+    assert_eq!(requests, ["StartRecord", "StopRecord"]);
+}
+
+#[cfg(feature = "webhook")]
+#[test]
+fn posts_webhook_messages() {
+    use bevy_capture::encoder::webhook::WebhookNotifier;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    // Answers a single request, returns its headers and body.
+    fn serve_once() -> (String, thread::JoinHandle<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut reader = BufReader::new(listener.accept().unwrap().0);
+            let mut headers = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                headers.push_str(&line.to_ascii_lowercase());
+            }
+            let len = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap();
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            (headers, body)
+        });
+        (url, server)
+    }
+
+    let image = Image::new_fill(
+        Extent3d {
+            width: 4,
+            height: 2,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 255, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+
+    // Slack gets a JSON message when the capture finishes.
+    let (url, server) = serve_once();
+    let mut notifier = WebhookNotifier::slack(url, TestEncoder::new())
+        .with_title("nightly")
+        .with_output("out.mp4");
+    notifier.encode(&image).unwrap();
+    notifier.encode(&image).unwrap();
+    Box::new(notifier).finish();
+    let (headers, body) = server.join().unwrap();
+    assert!(headers.starts_with("post /hook "), "{headers}");
+    assert!(
+        headers.contains("content-type: application/json"),
+        "{headers}"
+    );
+    let message: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let text = message["text"].as_str().unwrap();
+    assert!(
+        text.starts_with("Capture `nightly` finished: 2 frames in "),
+        "{text}"
+    );
+    assert!(text.ends_with("\nOutput: out.mp4"), "{text}");
+
+    // Discord gets the first error and a thumbnail of the first frame.
+    let (url, server) = serve_once();
+    struct FullDiskEncoder;
+
+    impl Encoder for FullDiskEncoder {
+        fn encode(&mut self, _image: &Image) -> encoder::Result<()> {
+            Err(io::Error::other("disk full").into())
+        }
+    }
+
+    let mut notifier = WebhookNotifier::discord(url, FullDiskEncoder).with_thumbnail();
+    assert!(notifier.encode(&image).is_err());
+    Box::new(notifier).finish();
+    let (headers, body) = server.join().unwrap();
+    assert!(
+        headers.contains("content-type: multipart/form-data; boundary=bevy-capture-boundary"),
+        "{headers}"
+    );
+    let text = String::from_utf8_lossy(&body);
+    assert!(
+        text.contains("{\"content\":\"Capture `capture` failed after 0 frames in "),
+        "{text}"
+    );
+    assert!(text.contains("disk full\"}"), "{text}");
+    assert!(text.contains("filename=\"thumbnail.png\""), "{text}");
+    assert!(body.windows(4).any(|window| window == b"\x89PNG"));
+}
+
+#[test]
+fn draws_terminal_preview() {
+    use bevy_capture::encoder::terminal::{TerminalEncoder, TerminalMode};
+
+    let image = Image::new(
+        Extent3d {
+            width: 2,
+            height: 2,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        vec![
+            255, 0, 0, 255, 0, 255, 0, 255, //
+            0, 0, 255, 255, 255, 255, 255, 255,
+        ],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    let red = Image::new_fill(
+        Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[255, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    let draw = |mode: TerminalMode, image: &Image| {
+        let mut out = Vec::new();
+        TerminalEncoder::new_with_writer(&mut out)
+            .with_mode(mode)
+            .encode(image)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    // Every cell shows the upper pixel in the foreground and the lower pixel in the background.
+    assert_eq!(
+        draw(TerminalMode::Blocks, &image),
+        "\x1b[H\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m\u{2580}\
+         \x1b[38;2;0;255;0m\x1b[48;2;255;255;255m\u{2580}\x1b[0m\n"
+    );
+
+    // Red is the last color of the first row of the 6x6x6 color cube.
+    let sixel = draw(TerminalMode::Sixel, &red);
+    assert!(
+        sixel.starts_with("\x1b[H\x1bPq#0;2;0;0;0#1;2;0;0;20"),
+        "{sixel:?}"
+    );
+    assert!(sixel.contains("#180;2;100;0;0"), "{sixel:?}");
+    assert!(sixel.ends_with("#180@$-\x1b\\\n"), "{sixel:?}");
+
+    assert_eq!(
+        draw(TerminalMode::Kitty, &red),
+        "\x1b[H\x1b_Ga=d\x1b\\\x1b_Ga=T,f=32,s=1,v=1,m=0;/wAA/w==\x1b\\\n"
+    );
+
+    // Only every second frame is drawn, downscaled to the width.
+    let mut out = Vec::new();
+    {
+        let mut encoder = TerminalEncoder::new_with_writer(&mut out)
+            .with_mode(TerminalMode::Kitty)
+            .with_width(1)
+            .with_every(2);
+        for _ in 0..3 {
+            encoder.encode(&image).unwrap();
+        }
+    }
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.matches("\x1b[H").count(), 2);
+    assert_eq!(out.matches("a=T,f=32,s=1,v=1,").count(), 2);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn draws_to_framebuffer() {
+    use bevy_capture::encoder::framebuffer::{FramebufferEncoder, FramebufferGeometry};
+
+    let image = Image::new(
+        Extent3d {
+            width: 2,
+            height: 2,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        vec![
+            255, 0, 0, 255, 0, 255, 0, 255, //
+            0, 0, 255, 255, 255, 255, 255, 255,
+        ],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    // A regular file stands in for the framebuffer device.
+    let path = std::env::temp_dir().join("bevy_capture_framebuffer");
+
+    // 32-bit framebuffers are BGRX, frames larger than the framebuffer are clipped.
+    fs::write(&path, [0; 24]).unwrap();
+    let mut encoder = FramebufferEncoder::new(&path).with_geometry(FramebufferGeometry {
+        width: 1,
+        height: 2,
+        bits_per_pixel: 32,
+        stride: 8,
+    });
+    encoder.encode(&image).unwrap();
+    assert_eq!(
+        fs::read(&path).unwrap(),
+        [
+            [0, 0, 255, 255, 0, 0, 0, 0],
+            [255, 0, 0, 255, 0, 0, 0, 0],
+            [0; 8]
+        ]
+        .concat()
+    );
+
+    // 16-bit framebuffers are RGB565.
+    fs::write(&path, [0; 4]).unwrap();
+    let mut encoder = FramebufferEncoder::new(&path).with_geometry(FramebufferGeometry {
+        width: 2,
+        height: 1,
+        bits_per_pixel: 16,
+        stride: 4,
+    });
+    encoder.encode(&image).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), [0x00, 0xf8, 0xe0, 0x07]);
+
+    let mut encoder = FramebufferEncoder::new(&path).with_geometry(FramebufferGeometry {
+        width: 2,
+        height: 1,
+        bits_per_pixel: 24,
+        stride: 6,
+    });
+    let err = encoder.encode(&image).unwrap_err();
+    assert!(
+        err.to_string().contains("unsupported framebuffer depth"),
+        "{err}"
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn captures_screen_source() {
+    struct CountingSource(u32);
+
+    impl ScreenSource for CountingSource {
+        fn next_frame(&mut self) -> encoder::Result<Option<Image>> {
+            if self.0 == 0 {
+                return Ok(None);
+            }
+            self.0 -= 1;
+            Ok(Some(Image::new_fill(
+                Extent3d {
+                    width: 4,
+                    height: 2,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                &[0, 0, 255, 255],
+                TextureFormat::Rgba8UnormSrgb,
+                default(),
+            )))
+        }
+    }
+
+    let encoder = TestEncoder::new();
+    let test_handle = encoder.handle();
+    let mut capture = ScreenCapture::default();
+    let handle = capture.start(CountingSource(3), encoder);
+    assert!(capture.is_capturing());
+
+    // The source ends after three frames, which finishes the encoders.
+    while !handle.is_finished() {
+        thread::yield_now();
+    }
+    assert_eq!(capture.frames_captured(), 3);
+    assert_eq!(test_handle.frames().len(), 3);
+    assert!(test_handle.is_finished());
+
+    // The frames are indexed and timestamped like captured frames.
+    let metadata = test_handle.metadata();
+    let indices: Vec<_> = metadata.iter().map(|m| m.frame_index()).collect();
+    assert_eq!(indices, [Some(0), Some(1), Some(2)]);
+    let mut previous = 0.0;
+    for metadata in &metadata {
+        let timestamp = metadata.timestamp().unwrap();
+        assert!(timestamp >= previous);
+        assert!((metadata.delta_time().unwrap() - (timestamp - previous)).abs() < 1e-9);
+        previous = timestamp;
+    }
+
+    capture.stop();
+    assert!(!capture.is_capturing());
+}
+
+#[test]
+fn streams_frames_over_tcp() {
+    use bevy_capture::encoder::{ipc::IpcClient, tcp::TcpEncoder};
+    use std::{net::TcpListener, time::Duration};
+
+    let image = Image::new_fill(
+        Extent3d {
+            width: 4,
+            height: 2,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 255, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    // Encodes frames until the condition holds.
+    fn encode_until(encoder: &mut TcpEncoder, image: &Image, condition: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if condition() {
+                return;
+            }
+            encoder.encode(image).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("timed out");
+    }
+
+    // Frames are dropped until the server is reachable.
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut encoder = TcpEncoder::new(address.to_string())
+        .with_backoff(Duration::from_millis(10), Duration::from_millis(40));
+    let stats = encoder.stats();
+    encode_until(&mut encoder, &image, || stats.dropped_frames() >= 3);
+    assert!(!stats.is_connected());
+    assert_eq!(stats.sent_frames(), 0);
+
+    let listener = TcpListener::bind(address).unwrap();
+    encode_until(&mut encoder, &image, || stats.sent_frames() >= 1);
+    let (stream, _) = listener.accept().unwrap();
+    let frame = IpcClient::new(stream).read_frame().unwrap().unwrap();
+    assert!(frame.frame >= 3);
+    assert_eq!((frame.width, frame.height), (4, 2));
+    assert_eq!(&frame.data[..4], &[0, 255, 0, 255]);
+
+    // The encoder reconnects once the connection is lost.
+    encode_until(&mut encoder, &image, || stats.connections() >= 2);
+    let (stream, _) = listener.accept().unwrap();
+    encode_until(&mut encoder, &image, || stats.is_connected());
+    assert!(IpcClient::new(stream).read_frame().unwrap().is_some());
+    Box::new(encoder).finish();
+
+    // Without frame dropping, all frames are sent.
+    let mut encoder = TcpEncoder::new(address.to_string()).with_drop_frames(false);
+    for _ in 0..5 {
+        encoder.encode(&image).unwrap();
+    }
+    Box::new(encoder).finish();
+    let (stream, _) = listener.accept().unwrap();
+    let frames = IpcClient::new(stream)
+        .map(|frame| frame.unwrap().frame)
+        .collect::<Vec<_>>();
+    assert_eq!(frames, [0, 1, 2, 3, 4]);
+}
+
+#[cfg(feature = "quic")]
+#[test]
+fn streams_frames_over_quic() {
+    use bevy_capture::encoder::quic::{QuicClient, QuicEncoder};
+    use std::time::Duration;
+
+    let image = Image::new_fill(
+        Extent3d {
+            width: 16,
+            height: 8,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 255, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    let mut encoder = QuicEncoder::bind("127.0.0.1:0")
+        .unwrap()
+        .with_quality(20, 60);
+    let stats = encoder.stats();
+    assert_eq!(stats.quality(), 60);
+
+    // Frames are skipped while nobody is watching.
+    encoder.encode(&image).unwrap();
+
+    let address = encoder.local_addr();
+    let certificate = encoder.certificate().certificate_der().to_vec();
+    let viewer = thread::spawn(move || {
+        let mut client = QuicClient::connect(address, "localhost", &certificate).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = client.read_frame().unwrap() {
+            frames.push(frame);
+        }
+        frames
+    });
+    while stats.viewers() == 0 {
+        thread::sleep(Duration::from_millis(5));
+    }
+    for _ in 0..5 {
+        encoder.encode(&image).unwrap();
+        thread::sleep(Duration::from_millis(20));
+    }
+    Box::new(encoder).finish();
+
+    // The latest frame is always delivered.
+    let frames = viewer.join().unwrap();
+    let last = frames.last().unwrap();
+    assert_eq!(last.frame, 5);
+    assert_eq!((last.width, last.height), (16, 8));
+    assert!(frames.windows(2).all(|pair| pair[0].frame < pair[1].frame));
+    assert_eq!(stats.sent_frames(), frames.len() as u64);
+    let pixel = last.decode().unwrap().get_pixel(8, 4).0;
+    assert!(pixel[0] < 40 && pixel[1] > 200 && pixel[2] < 40);
+
+    // A viewer that doesn't read the frames lowers the quality.
+    let mut encoder = QuicEncoder::bind("127.0.0.1:0")
+        .unwrap()
+        .with_quality(20, 60);
+    let stats = encoder.stats();
+    let client = QuicClient::connect(
+        encoder.local_addr(),
+        "localhost",
+        encoder.certificate().certificate_der(),
+    )
+    .unwrap();
+    while stats.viewers() == 0 {
+        thread::sleep(Duration::from_millis(5));
+    }
+    for _ in 0..10 {
+        encoder.encode(&image).unwrap();
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(stats.quality(), 20);
+    drop(client);
+    Box::new(encoder).finish();
+}
+
+#[cfg(all(feature = "gstreamer", unix))]
+#[test]
+fn reports_gstreamer_errors() {
+    use bevy_capture::encoder::gstreamer::GstreamerEncoder;
+
+    let encoder = GstreamerEncoder::new("videoconvert ! autovideosink").with_framerate(30);
+    assert_eq!(
+        encoder.launch_line(32, 16),
+        "fdsrc fd=0 ! rawvideoparse width=32 height=16 format=rgba framerate=30/1 ! \
+         videoconvert ! autovideosink"
+    );
+
+    // The shell fails to open the pipeline as a script, like gst-launch-1.0 with a broken
+    // pipeline, and its stderr ends up in the error.
+    let mut encoder = encoder.with_program("sh");
+    let image = Image::new_fill(
+        Extent3d {
+            width: 32,
+            height: 16,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    let started = std::time::Instant::now();
+    let err = loop {
+        if let Err(err) = encoder.encode(&image) {
+            break err;
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        thread::sleep(std::time::Duration::from_millis(10));
+    };
+    assert!(matches!(err, encoder::Error::External { .. }));
+    assert!(err.to_string().contains("rawvideoparse"), "{err}");
+}
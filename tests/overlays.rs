@@ -0,0 +1,417 @@
+mod common;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_capture::{
+    cursor::{CursorOverlayEncoder, CURSOR_X_KEY, CURSOR_Y_KEY},
+    encoder::{
+        chroma_key::{ChromaBackground, ChromaKey, ChromaKeyEncoder},
+        test::TestEncoder,
+        watermark::{decode_watermark, WatermarkEncoder},
+    },
+    input_overlay::{InputOverlayEncoder, InputOverlayPlugin},
+    labels::{CaptureLabels, EntityLabelPlugin, Labeled},
+    metadata::FrameMetadata,
+    privacy::{CaptureMask, MaskRegion, PrivacyMaskPlugin, PrivacyMasked},
+    testing::HeadlessHarness,
+    Encoder,
+};
+use common::NO_ADAPTER;
+
+#[cfg(feature = "debug_view")]
+#[test]
+fn renders_debug_view_on_debug_layer() {
+    use bevy::render::view::RenderLayers;
+    use bevy_capture::debug_view::{DebugView, DebugViewPlugin, WireframeProxy, DEBUG_LAYER};
+
+    let mut harness = HeadlessHarness::new_with_plugins(16, 8, DebugViewPlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+    let world = harness.app_mut().world_mut();
+    world
+        .entity_mut(camera)
+        .insert(DebugView::default().with_wireframe(true));
+    let mesh = world
+        .spawn((SpatialBundle::default(), Handle::<Mesh>::default()))
+        .id();
+    harness.app_mut().update();
+    harness.app_mut().update();
+
+    let world = harness.app_mut().world_mut();
+    let layers = world.get::<RenderLayers>(camera).unwrap();
+    assert_eq!(*layers, RenderLayers::layer(0).with(DEBUG_LAYER));
+    let children = world.get::<Children>(mesh).unwrap().to_vec();
+    assert_eq!(children.len(), 1);
+    assert!(world.get::<WireframeProxy>(children[0]).is_some());
+    assert_eq!(
+        world.get::<RenderLayers>(children[0]),
+        Some(&RenderLayers::layer(DEBUG_LAYER))
+    );
+
+    // The proxies are removed once no camera shows wireframes anymore.
+    world.entity_mut(camera).insert(DebugView::default());
+    harness.app_mut().update();
+    let world = harness.app_mut().world_mut();
+    assert!(world
+        .get::<Children>(mesh)
+        .is_none_or(|children| children.is_empty()));
+}
+
+#[cfg(feature = "gizmos")]
+#[test]
+fn excludes_gizmos_from_capture() {
+    use bevy::{
+        gizmos::config::{DefaultGizmoConfigGroup, GizmoConfig, GizmoConfigStore},
+        render::view::RenderLayers,
+    };
+    use bevy_capture::gizmos::{CaptureGizmos, CaptureGizmosPlugin, GIZMO_LAYER};
+
+    let mut harness =
+        HeadlessHarness::new_with_plugins(16, 8, CaptureGizmosPlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+    let world = harness.app_mut().world_mut();
+    let mut config_store = GizmoConfigStore::default();
+    config_store.insert(GizmoConfig::default(), DefaultGizmoConfigGroup);
+    world.insert_resource(config_store);
+    world.entity_mut(camera).insert(CaptureGizmos::Exclude);
+    let player = world.spawn(Camera2dBundle::default()).id();
+    harness.app_mut().update();
+
+    let world = harness.app_mut().world_mut();
+    let (config, _) = world
+        .resource::<GizmoConfigStore>()
+        .config::<DefaultGizmoConfigGroup>();
+    assert_eq!(config.render_layers, RenderLayers::layer(GIZMO_LAYER));
+    assert_eq!(
+        world.get::<RenderLayers>(player),
+        Some(&RenderLayers::layer(0).with(GIZMO_LAYER))
+    );
+    assert!(world
+        .get::<RenderLayers>(camera)
+        .is_none_or(|layers| !layers.intersects(&RenderLayers::layer(GIZMO_LAYER))));
+
+    world.entity_mut(camera).insert(CaptureGizmos::Include);
+    harness.app_mut().update();
+    let world = harness.app_mut().world_mut();
+    assert_eq!(
+        world.get::<RenderLayers>(camera),
+        Some(&RenderLayers::layer(0).with(GIZMO_LAYER))
+    );
+}
+
+#[test]
+fn draws_input_overlay() {
+    let mut harness =
+        HeadlessHarness::new_with_plugins(64, 32, InputOverlayPlugin).expect(NO_ADAPTER);
+    harness.app_mut().insert_resource(ClearColor(Color::BLACK));
+    let mut keys = ButtonInput::<KeyCode>::default();
+    keys.press(KeyCode::KeyW);
+    keys.press(KeyCode::ShiftLeft);
+    harness.app_mut().insert_resource(keys);
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    harness.capture(1, InputOverlayEncoder::new(encoder).with_scale(1));
+
+    assert_eq!(
+        handle.metadata()[0].to_json(),
+        r#"{"frame_index":0,"input":"SHIFT W"}"#
+    );
+    let image = &handle.images()[0];
+    let white = image
+        .data
+        .chunks_exact(4)
+        .filter(|pixel| pixel[..3] == [255, 255, 255])
+        .count();
+    assert!(white > 0);
+    // The key caps are drawn in the bottom left corner.
+    assert!(image.data[..64 * 16 * 4]
+        .chunks_exact(4)
+        .all(|pixel| pixel == [0, 0, 0, 255]));
+}
+
+#[test]
+fn draws_cursor_overlay() {
+    let image = Image::new_fill(
+        Extent3d {
+            width: 40,
+            height: 20,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[255, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    let mut metadata = FrameMetadata::default();
+    metadata.insert(CURSOR_X_KEY, 0.5);
+    metadata.insert(CURSOR_Y_KEY, 0.25);
+
+    let test_encoder = TestEncoder::new().with_images();
+    let handle = test_encoder.handle();
+    let mut encoder = CursorOverlayEncoder::new(test_encoder).with_scale(1);
+    encoder.encode_with_metadata(&image, &metadata).unwrap();
+    encoder.encode(&image).unwrap();
+
+    let images = handle.images();
+    let pixel = |image: &Image, x: usize, y: usize| image.data[(y * 40 + x) * 4..][..4].to_vec();
+    // The hot spot is the tip of the arrow, the fill is white.
+    assert_eq!(pixel(&images[0], 20, 5), [0, 0, 0, 255]);
+    assert_eq!(pixel(&images[0], 21, 7), [255, 255, 255, 255]);
+    assert_eq!(pixel(&images[0], 19, 5), [255, 0, 0, 255]);
+    // Frames without a cursor position are unchanged.
+    assert_eq!(images[1].data, image.data);
+}
+
+#[test]
+fn embeds_watermark() {
+    let image = Image::new_fill(
+        Extent3d {
+            width: 16,
+            height: 8,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[200, 100, 50, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+
+    let test_encoder = TestEncoder::new().with_images();
+    let handle = test_encoder.handle();
+    let mut encoder = WatermarkEncoder::new(test_encoder, "build 1234");
+    encoder.encode(&image).unwrap();
+
+    let mut watermarked = handle.images().remove(0);
+    // The pixels change by at most one.
+    assert!(watermarked
+        .data
+        .iter()
+        .zip(&image.data)
+        .all(|(a, b)| a.abs_diff(*b) <= 1));
+    assert_eq!(
+        decode_watermark(&watermarked).unwrap().as_deref(),
+        Some(&b"build 1234"[..])
+    );
+
+    // The first copy is destroyed, a later copy is still intact.
+    watermarked.data[..64].fill(0);
+    assert_eq!(
+        decode_watermark(&watermarked).unwrap().as_deref(),
+        Some(&b"build 1234"[..])
+    );
+    assert_eq!(decode_watermark(&image).unwrap(), None);
+
+    // Frames that are too small for the watermark fail.
+    let tiny = Image::new_fill(
+        Extent3d {
+            width: 2,
+            height: 2,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    assert!(encoder.encode(&tiny).is_err());
+}
+
+#[test]
+fn replaces_chroma_key_background() {
+    let size = Extent3d {
+        width: 4,
+        height: 2,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image::new_fill(
+        size,
+        TextureDimension::D2,
+        &[0, 255, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    // The foreground is a red pixel in the top left corner.
+    image.data[..4].copy_from_slice(&[255, 0, 0, 255]);
+    let background = Image::new_fill(
+        Extent3d {
+            width: 2,
+            height: 1,
+            ..size
+        },
+        TextureDimension::D2,
+        &[0, 0, 255, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+
+    let test_encoder = TestEncoder::new().with_images();
+    let handle = test_encoder.handle();
+    let mut encoder = ChromaKeyEncoder::new(test_encoder, ChromaKey::GREEN)
+        .with_background(ChromaBackground::Image(background));
+    encoder.encode(&image).unwrap();
+
+    // Transparent pixels are replaced by the alpha key before the green key is applied.
+    let mut encoder = ChromaKeyEncoder::new(encoder, ChromaKey::Alpha)
+        .with_background(ChromaBackground::Color([255, 255, 255, 255]));
+    image.data[4..8].copy_from_slice(&[0, 0, 0, 0]);
+    encoder.encode(&image).unwrap();
+
+    let images = handle.images();
+    let pixel = |image: &Image, x: usize, y: usize| image.data[(y * 4 + x) * 4..][..4].to_vec();
+    assert_eq!(pixel(&images[0], 0, 0), [255, 0, 0, 255]);
+    // The background image is scaled to the size of the frame.
+    assert_eq!(pixel(&images[0], 3, 1), [0, 0, 255, 255]);
+    assert_eq!(pixel(&images[1], 0, 0), [255, 0, 0, 255]);
+    assert_eq!(pixel(&images[1], 1, 0), [255, 255, 255, 255]);
+    assert_eq!(pixel(&images[1], 3, 1), [0, 0, 255, 255]);
+}
+
+#[cfg(feature = "ui")]
+#[test]
+fn captures_ui_node() {
+    use bevy_capture::ui::{UiCapture, UiCapturePlugin};
+
+    let mut harness = HeadlessHarness::new_with_plugins(
+        16,
+        16,
+        (
+            bevy::input::InputPlugin,
+            bevy::sprite::SpritePlugin,
+            bevy::text::TextPlugin,
+            bevy::ui::UiPlugin,
+            UiCapturePlugin,
+        ),
+    )
+    .expect(NO_ADAPTER);
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    // A red node in the left half of a transparent screen.
+    let node = harness
+        .app_mut()
+        .world_mut()
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                ..default()
+            },
+            UiCapture::new(32, 16).with_encoders(encoder),
+        ))
+        .with_children(|parent| {
+            parent.spawn(NodeBundle {
+                style: Style {
+                    width: Val::Percent(50.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                background_color: Color::srgb(1.0, 0.0, 0.0).into(),
+                ..default()
+            });
+        })
+        .id();
+    for _ in 0..3 {
+        harness.app_mut().update();
+    }
+    harness
+        .app_mut()
+        .world_mut()
+        .entity_mut(node)
+        .despawn_recursive();
+    harness.app_mut().update();
+    harness.app_mut().update();
+
+    let images = handle.images();
+    let image = images.last().unwrap();
+    assert_eq!((image.width(), image.height()), (32, 16));
+    let pixel = |x: usize, y: usize| image.data[(y * 32 + x) * 4..][..4].to_vec();
+    assert_eq!(pixel(4, 8), [255, 0, 0, 255]);
+    assert_eq!(pixel(28, 8), [0, 0, 0, 0]);
+    // The camera is despawned with the node.
+    let world = harness.app_mut().world_mut();
+    assert_eq!(world.query::<&Camera>().iter(world).count(), 1);
+}
+
+#[test]
+fn masks_private_regions() {
+    let mut harness =
+        HeadlessHarness::new_with_plugins(64, 32, PrivacyMaskPlugin).expect(NO_ADAPTER);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(1.0, 0.0, 0.0)));
+    let camera = harness.camera();
+    let world = harness.app_mut().world_mut();
+    world.entity_mut(camera).insert(
+        CaptureMask::default()
+            .with_region(MaskRegion::blackout(Rect::new(0.0, 0.0, 16.0, 8.0)))
+            .with_region(MaskRegion::blur(Rect::new(40.0, 0.0, 64.0, 32.0), 4)),
+    );
+    // An entity in the center of the camera, 8x8 pixels large.
+    world.spawn((
+        SpatialBundle::default(),
+        bevy::render::primitives::Aabb::from_min_max(Vec3::splat(-4.0), Vec3::splat(4.0)),
+        PrivacyMasked::blackout(),
+    ));
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    harness.capture(2, encoder);
+
+    // The bounds of the entity are tracked after the first frame.
+    let image = &handle.images()[1];
+    let pixel = |x: usize, y: usize| image.data[(y * 64 + x) * 4..][..4].to_vec();
+    assert_eq!(pixel(0, 0), [0, 0, 0, 255]);
+    assert_eq!(pixel(15, 7), [0, 0, 0, 255]);
+    assert_eq!(pixel(16, 8), [255, 0, 0, 255]);
+    assert_eq!(pixel(32, 16), [0, 0, 0, 255]);
+    assert_eq!(pixel(27, 16), [255, 0, 0, 255]);
+    // Blurring a single color doesn't change it.
+    assert_eq!(pixel(50, 20), [255, 0, 0, 255]);
+}
+
+#[test]
+fn draws_entity_labels() {
+    let mut harness =
+        HeadlessHarness::new_with_plugins(64, 32, EntityLabelPlugin).expect(NO_ADAPTER);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(1.0, 0.0, 0.0)));
+    let camera = harness.camera();
+    let world = harness.app_mut().world_mut();
+    world
+        .entity_mut(camera)
+        .insert(CaptureLabels::default().with_scale(1));
+    // An entity in the center of the camera, and one far outside of it.
+    world.spawn((SpatialBundle::default(), Name::new("A"), Labeled::default()));
+    world.spawn((
+        SpatialBundle::from_transform(Transform::from_xyz(1000.0, 0.0, 0.0)),
+        Labeled::text("OUTSIDE"),
+    ));
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    harness.capture(2, encoder);
+
+    let world = harness.app_mut().world_mut();
+    let labels = world
+        .query::<&CaptureLabels>()
+        .single(world)
+        .labels()
+        .to_vec();
+    assert_eq!(labels.len(), 2);
+    assert_eq!(labels[0].text(), "A");
+    assert_eq!(labels[0].position(), Vec2::new(32.0, 16.0));
+
+    // The label is a 5x7 box with a 3x5 glyph, centered on the entity.
+    let image = &handle.images()[0];
+    let pixel = |x: usize, y: usize| image.data[(y * 64 + x) * 4..][..4].to_vec();
+    assert_eq!(pixel(30, 13), [77, 0, 0, 255]);
+    assert_eq!(pixel(32, 14), [255, 255, 255, 255]);
+    assert_eq!(pixel(31, 16), [255, 255, 255, 255]);
+    assert_eq!(pixel(32, 16), [255, 255, 255, 255]);
+    assert_eq!(pixel(28, 16), [255, 0, 0, 255]);
+}
@@ -0,0 +1,505 @@
+mod common;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_capture::{
+    adaptive_quality::{AdaptiveQuality, AdaptiveQualityPlugin, QUALITY_SCALE_KEY},
+    encoder::{self, test::TestEncoder},
+    memory::{CaptureMemoryBudget, MemoryBudgetPolicy},
+    metadata::MetadataValue,
+    testing::HeadlessHarness,
+    Capture, CaptureBundle, CaptureWorkerSettings, Encoder, WorkerBackpressure, WorkerPriority,
+};
+use common::{harness, NO_ADAPTER};
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[test]
+fn lowers_quality_over_budget() {
+    let mut harness =
+        HeadlessHarness::new_with_plugins(64, 32, AdaptiveQualityPlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+    harness.app_mut().world_mut().entity_mut(camera).insert(
+        AdaptiveQuality::new(bevy::utils::Duration::ZERO)
+            .with_cooldown(bevy::utils::Duration::ZERO),
+    );
+
+    // A second capture of the same camera without a governor.
+    let other = TestEncoder::new();
+    let other_handle = other.handle();
+    let mut other_capture = Capture::default();
+    other_capture.start(other);
+    harness.app_mut().world_mut().spawn(CaptureBundle {
+        capture: other_capture,
+        camera_source: bevy_capture::CaptureSource::Camera(camera),
+    });
+
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    harness.capture(10, encoder);
+
+    // Frames are captured at lower resolutions and marked in the metadata.
+    assert!(handle
+        .frames()
+        .iter()
+        .any(|frame| (frame.width, frame.height) == (32, 16)));
+    assert!(handle.metadata().iter().any(|metadata| matches!(
+        metadata.get(QUALITY_SCALE_KEY),
+        Some(MetadataValue::Float(scale)) if *scale < 1.0
+    )));
+    // The metadata of the governed capture doesn't leak into other captures.
+    assert!(other_handle.encode_count() > 0);
+    assert!(other_handle
+        .metadata()
+        .iter()
+        .all(|metadata| metadata.get(QUALITY_SCALE_KEY).is_none()));
+
+    // The original resolution is restored once the capture stops.
+    let world = harness.app().world();
+    let bevy::render::camera::RenderTarget::Image(target) =
+        &world.get::<Camera>(camera).unwrap().target
+    else {
+        unreachable!()
+    };
+    let target = world.resource::<Assets<Image>>().get(target).unwrap();
+    assert_eq!(target.size(), UVec2::new(64, 32));
+}
+
+#[test]
+fn converts_frames_on_secondary_gpu() {
+    use bevy_capture::encoder::secondary_gpu::{SecondaryAdapter, SecondaryGpuEncoder};
+
+    let mut harness = harness(16, 8);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(1.0, 0.5, 0.0)));
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    let encoder = SecondaryGpuEncoder::new(encoder, SecondaryAdapter::Index(0)).expect(NO_ADAPTER);
+    harness.capture(2, encoder);
+
+    let images = handle.images();
+    assert_eq!(images.len(), 2);
+    for image in images {
+        assert_eq!(
+            image.texture_descriptor.format,
+            TextureFormat::Rgba8UnormSrgb
+        );
+        for pixel in image.data.chunks_exact(4) {
+            assert_eq!(pixel[0], 255);
+            assert!(pixel[1].abs_diff(128) <= 1);
+            assert_eq!(pixel[2..], [0, 255]);
+        }
+    }
+
+    // Only sRGB and float frames are encoded, other normalized frames keep their values.
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    let mut encoder = SecondaryGpuEncoder::new(encoder, SecondaryAdapter::Index(0)).unwrap();
+    for (format, data, expected) in [
+        (
+            TextureFormat::Rgba8Unorm,
+            vec![128, 64, 0, 255],
+            TextureFormat::Rgba8Unorm,
+        ),
+        (
+            TextureFormat::Rgba8UnormSrgb,
+            vec![128, 64, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+        ),
+        (
+            TextureFormat::Bgra8UnormSrgb,
+            vec![0, 64, 128, 255],
+            TextureFormat::Rgba8UnormSrgb,
+        ),
+    ] {
+        let image = Image::new_fill(
+            Extent3d {
+                width: 4,
+                height: 2,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &data,
+            format,
+            default(),
+        );
+        encoder.encode(&image).unwrap();
+        let image = handle.images().pop().unwrap();
+        assert_eq!(image.texture_descriptor.format, expected);
+        for pixel in image.data.chunks_exact(4) {
+            assert!(pixel[0].abs_diff(128) <= 1, "{format:?}: {pixel:?}");
+            assert!(pixel[1].abs_diff(64) <= 1, "{format:?}: {pixel:?}");
+            assert_eq!(pixel[2..], [0, 255]);
+        }
+    }
+
+    // Frames that don't fit into a texture or storage buffer of the adapter are rejected.
+    let image = Image::new_fill(
+        Extent3d {
+            width: 1 << 17,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Rgba8UnormSrgb,
+        default(),
+    );
+    assert!(encoder.encode(&image).is_err());
+}
+
+#[test]
+fn encodes_on_worker_threads() {
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+    harness.app_mut().world_mut().entity_mut(camera).insert(
+        CaptureWorkerSettings::default()
+            .with_threads(2)
+            .with_priority(WorkerPriority::Lowest)
+            .with_core_affinity([0]),
+    );
+
+    struct ThreadNameEncoder(Arc<Mutex<Vec<String>>>);
+
+    impl Encoder for ThreadNameEncoder {
+        fn encode(&mut self, _image: &Image) -> encoder::Result<()> {
+            let name = thread::current().name().unwrap_or_default().to_owned();
+            self.0.lock().unwrap().push(name);
+            Ok(())
+        }
+    }
+
+    let names = Arc::new(Mutex::new(Vec::new()));
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    harness.capture(3, (encoder, ThreadNameEncoder(Arc::clone(&names))));
+
+    assert!(handle.is_finished());
+    assert_eq!(handle.frames().len(), 3);
+    assert_eq!(*names.lock().unwrap(), ["capture worker 1"; 3]);
+}
+
+#[test]
+fn drops_frames_for_slow_workers() {
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+    harness.app_mut().world_mut().entity_mut(camera).insert(
+        CaptureWorkerSettings::default()
+            .with_queue_capacity(1)
+            .with_backpressure(WorkerBackpressure::DropFrames),
+    );
+
+    struct SlowEncoder(Arc<Mutex<usize>>);
+
+    impl Encoder for SlowEncoder {
+        fn encode(&mut self, _image: &Image) -> encoder::Result<()> {
+            thread::sleep(std::time::Duration::from_millis(200));
+            *self.0.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    // The render thread doesn't wait for the worker, so most frames are dropped.
+    let encoded = Arc::new(Mutex::new(0));
+    harness.capture(8, SlowEncoder(Arc::clone(&encoded)));
+    let encoded = *encoded.lock().unwrap();
+    assert!((1..8).contains(&encoded), "encoded {encoded} frames");
+}
+
+#[test]
+fn spills_frames_over_memory_budget() {
+    let mut harness = harness(16, 8);
+    harness
+        .app_mut()
+        .insert_resource(ClearColor(Color::srgb(0.0, 1.0, 0.0)));
+    let camera = harness.camera();
+    harness
+        .app_mut()
+        .world_mut()
+        .entity_mut(camera)
+        .insert(CaptureWorkerSettings::default());
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_spill");
+    let _ = fs::remove_dir_all(&dir);
+    // Only a single frame fits into the budget, the others are spilled while it is queued.
+    let budget = CaptureMemoryBudget::new(1)
+        .with_policy(MemoryBudgetPolicy::Spill)
+        .with_spill_dir(&dir);
+    harness.app_mut().insert_resource(budget.clone());
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    harness.capture(5, encoder);
+
+    let images = handle.images();
+    assert_eq!(images.len(), 5);
+    for image in images {
+        for pixel in image.data.chunks_exact(4) {
+            assert_eq!(pixel, [0, 255, 0, 255]);
+        }
+    }
+
+    let stats = budget.stats();
+    assert_eq!(stats.in_flight_bytes, 0);
+    assert_eq!(stats.peak_bytes, 16 * 8 * 4);
+    assert_eq!(stats.frames_dropped, 0);
+    if dir.exists() {
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn skips_identical_frames() {
+    use bevy_capture::encoder::idle::IdleSkip;
+
+    let mut harness = harness(4, 2);
+
+    // The clear color doesn't change, so only the first frames up to the threshold are encoded.
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    harness.capture(6, IdleSkip::new(encoder, 2));
+    assert_eq!(handle.encode_count(), 3);
+    assert!(handle.is_finished());
+
+    // A changed frame is encoded again.
+    let mut encoder = IdleSkip::new(TestEncoder::new(), 0);
+    let image = |color: u8| {
+        Image::new(
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![color, 0, 0, 255],
+            TextureFormat::Rgba8Unorm,
+            default(),
+        )
+    };
+    for color in [0, 0, 0, 1, 1, 0] {
+        encoder.encode(&image(color)).unwrap();
+    }
+    assert_eq!(encoder.skipped(), 3);
+    assert!(!encoder.is_idle());
+}
+
+#[test]
+fn records_only_changed_frames() {
+    use bevy_capture::{
+        encoder::idle::{ChangeTrigger, GAP_FRAMES_KEY, GAP_START_KEY},
+        metadata::{FrameMetadata, MetadataValue, TIMESTAMP_KEY},
+    };
+
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    let mut encoder = ChangeTrigger::new(encoder, 0.1);
+    let image = |color: u8| {
+        Image::new(
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![color, color, color, 255],
+            TextureFormat::Rgba8Unorm,
+            default(),
+        )
+    };
+    for (i, color) in [0, 0, 0, 100, 101, 0].into_iter().enumerate() {
+        let mut metadata = FrameMetadata::default();
+        metadata.insert(TIMESTAMP_KEY, i as f64);
+        encoder
+            .encode_with_metadata(&image(color), &metadata)
+            .unwrap();
+    }
+
+    // Small changes don't trigger, the first frame after a gap has the gap in its metadata.
+    assert_eq!(encoder.skipped(), 3);
+    let metadata = handle.metadata();
+    assert_eq!(metadata.len(), 3);
+    assert_eq!(metadata[0].get(GAP_FRAMES_KEY), None);
+    assert_eq!(
+        metadata[1].get(GAP_FRAMES_KEY),
+        Some(&MetadataValue::Int(2))
+    );
+    assert_eq!(
+        metadata[1].get(GAP_START_KEY),
+        Some(&MetadataValue::Float(1.0))
+    );
+    assert_eq!(
+        metadata[2].get(GAP_FRAMES_KEY),
+        Some(&MetadataValue::Int(1))
+    );
+    assert_eq!(
+        metadata[2].get(GAP_START_KEY),
+        Some(&MetadataValue::Float(4.0))
+    );
+
+    // With a hold, the frames after a change are recorded as well.
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    let mut encoder = ChangeTrigger::new(encoder, 0.1).with_hold(1);
+    for color in [0, 0, 0, 100, 100, 100] {
+        encoder.encode(&image(color)).unwrap();
+    }
+    assert_eq!(handle.encode_count(), 4);
+}
+
+#[test]
+fn skips_unchanged_frames_on_gpu() {
+    use bevy_capture::{
+        encoder::idle::GAP_FRAMES_KEY, metadata::MetadataValue, scene_change::GpuChangeTrigger,
+    };
+
+    let mut harness = harness(32, 16);
+    harness.app_mut().insert_resource(ClearColor(Color::BLACK));
+    let camera = harness.camera();
+    harness
+        .app_mut()
+        .world_mut()
+        .entity_mut(camera)
+        .insert(GpuChangeTrigger::new(0.1).with_scene_cut_threshold(0.5));
+
+    let encoder = TestEncoder::new().with_images();
+    let handle = encoder.handle();
+    let world = harness.app_mut().world_mut();
+    world.get_mut::<Capture>(camera).unwrap().start(encoder);
+    for color in [
+        Color::BLACK,
+        Color::BLACK,
+        Color::BLACK,
+        Color::WHITE,
+        Color::WHITE,
+    ] {
+        harness.app_mut().insert_resource(ClearColor(color));
+        harness.app_mut().update();
+    }
+    let world = harness.app_mut().world_mut();
+    world.get_mut::<Capture>(camera).unwrap().stop();
+    harness.app_mut().update();
+
+    // Only the first frame and the cut to white are read back.
+    assert!(handle.is_finished());
+    let images = handle.images();
+    assert_eq!(images.len(), 2);
+    assert_eq!(images[0].data[..3], [0, 0, 0]);
+    assert_eq!(images[1].data[..3], [255, 255, 255]);
+    let metadata = handle.metadata();
+    assert!(!metadata[0].is_keyframe_forced());
+    assert!(metadata[1].is_keyframe_forced());
+    assert_eq!(
+        metadata[1].get(GAP_FRAMES_KEY),
+        Some(&MetadataValue::Int(2))
+    );
+}
+
+#[test]
+fn encodes_dirty_rects() {
+    use bevy::math::URect;
+    use bevy_capture::encoder::dirty::{self, DirtyRects};
+
+    let image = |changed: &[(u32, u32)]| {
+        let mut image = Image::new_fill(
+            Extent3d {
+                width: 64,
+                height: 32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+            default(),
+        );
+        for &(x, y) in changed {
+            let offset = (y as usize * 64 + x as usize) * 4;
+            image.data[offset..offset + 4].copy_from_slice(&[255, 255, 255, 255]);
+        }
+        image
+    };
+
+    let encoder = TestEncoder::new();
+    let handle = encoder.handle();
+    let mut encoder = DirtyRects::new(encoder).with_tile_size(16);
+    for changed in [&[][..], &[], &[(20, 5), (40, 5), (20, 20)]] {
+        encoder.encode(&image(changed)).unwrap();
+    }
+
+    // The first frame is dirty as a whole, unchanged frames have no dirty rects, and adjacent
+    // tiles in a row are merged.
+    let rects = handle
+        .metadata()
+        .iter()
+        .map(|metadata| dirty::from_metadata(metadata).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(rects[0], [URect::new(0, 0, 64, 32)]);
+    assert_eq!(rects[1], []);
+    assert_eq!(
+        rects[2],
+        [URect::new(16, 0, 48, 16), URect::new(16, 16, 32, 32)]
+    );
+}
+
+#[cfg(feature = "gif")]
+#[test]
+fn encodes_gif_sub_frames() {
+    use bevy_capture::encoder::{dirty::DirtyRects, gif::GifEncoder};
+    use image::{codecs::gif::GifDecoder, AnimationDecoder};
+    use std::io;
+
+    let image = |x: u32| {
+        let mut image = Image::new_fill(
+            Extent3d {
+                width: 128,
+                height: 64,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 255, 255],
+            TextureFormat::Rgba8UnormSrgb,
+            default(),
+        );
+        let offset = (10 * 128 + x as usize) * 4;
+        image.data[offset..offset + 4].copy_from_slice(&[255, 255, 255, 255]);
+        image
+    };
+    let encode = |dirty_rects: bool| {
+        let mut gif = Vec::new();
+        let mut encoder: Box<dyn Encoder> = match dirty_rects {
+            true => Box::new(DirtyRects::new(GifEncoder::new(&mut gif))),
+            false => Box::new(GifEncoder::new(&mut gif)),
+        };
+        for x in [5, 5, 100, 100] {
+            encoder.encode(&image(x)).unwrap();
+        }
+        drop(encoder);
+        gif
+    };
+
+    let full = encode(false);
+    let gif = encode(true);
+    assert!(gif.len() < full.len());
+
+    // The sub-frames are drawn over the previous frames.
+    let frames = GifDecoder::new(io::Cursor::new(gif))
+        .unwrap()
+        .into_frames()
+        .collect_frames()
+        .unwrap();
+    assert_eq!(frames.len(), 4);
+    for (frame, x) in frames.iter().zip([5, 5, 100, 100]) {
+        let buffer = frame.buffer();
+        assert_eq!(buffer.dimensions(), (128, 64));
+        assert_eq!(buffer.get_pixel(x, 10).0, [255, 255, 255, 255]);
+        assert_eq!(buffer.get_pixel(105 - x, 10).0, [0, 0, 255, 255]);
+        assert_eq!(buffer.get_pixel(64, 60).0, [0, 0, 255, 255]);
+    }
+}
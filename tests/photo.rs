@@ -0,0 +1,277 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_capture::{
+    burst::{BurstFinished, BurstPlugin, TakeBurst},
+    photo_mode::{PhotoCamera, PhotoMode, PhotoModePlugin, TakePhoto},
+    screenshot_matrix::{ScreenshotMatrixFinished, ScreenshotMatrixPlugin, TakeScreenshotMatrix},
+    testing::HeadlessHarness,
+    Capture,
+};
+use common::NO_ADAPTER;
+use std::fs;
+
+#[test]
+fn takes_high_res_photo() {
+    let mut harness = HeadlessHarness::new_with_plugins(16, 8, PhotoModePlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+    harness
+        .app_mut()
+        .world_mut()
+        .entity_mut(camera)
+        .insert(PhotoCamera::default());
+
+    let path = std::env::temp_dir().join("bevy_capture_test_photo.png");
+    let _ = fs::remove_file(&path);
+    harness
+        .app_mut()
+        .world_mut()
+        .send_event(TakePhoto::new(&path).with_scale(2));
+    for _ in 0..5 {
+        harness.app_mut().update();
+    }
+
+    let photo = image::open(&path).unwrap();
+    assert_eq!((photo.width(), photo.height()), (32, 16));
+    assert!(!harness
+        .app()
+        .world()
+        .get::<Capture>(camera)
+        .unwrap()
+        .is_capturing());
+
+    // The target is restored to its original size.
+    let world = harness.app().world();
+    let bevy::render::camera::RenderTarget::Image(target) =
+        &world.get::<Camera>(camera).unwrap().target
+    else {
+        unreachable!()
+    };
+    let target = world.resource::<Assets<Image>>().get(target).unwrap();
+    assert_eq!(target.size(), UVec2::new(16, 8));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rejects_oversized_photo() {
+    let mut harness = HeadlessHarness::new_with_plugins(16, 8, PhotoModePlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+    harness
+        .app_mut()
+        .world_mut()
+        .entity_mut(camera)
+        .insert(PhotoCamera::default());
+
+    // Larger than the maximum texture dimension of any GPU, and overflowing u32.
+    let path = std::env::temp_dir().join("bevy_capture_test_oversized_photo.png");
+    let _ = fs::remove_file(&path);
+    for scale in [1 << 16, u32::MAX] {
+        harness
+            .app_mut()
+            .world_mut()
+            .send_event(TakePhoto::new(&path).with_scale(scale));
+        for _ in 0..3 {
+            harness.app_mut().update();
+        }
+    }
+
+    assert!(!path.exists());
+    let world = harness.app().world();
+    assert!(!world.resource::<PhotoMode>().is_taking_photo());
+    assert!(!world.get::<Capture>(camera).unwrap().is_capturing());
+    let bevy::render::camera::RenderTarget::Image(target) =
+        &world.get::<Camera>(camera).unwrap().target
+    else {
+        unreachable!()
+    };
+    let target = world.resource::<Assets<Image>>().get(target).unwrap();
+    assert_eq!(target.size(), UVec2::new(16, 8));
+}
+
+#[test]
+fn takes_burst() {
+    let mut harness = HeadlessHarness::new_with_plugins(16, 8, BurstPlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_burst");
+    let _ = fs::remove_dir_all(&dir);
+    harness
+        .app_mut()
+        .world_mut()
+        .send_event(TakeBurst::consecutive(camera, &dir, 3));
+    for _ in 0..5 {
+        harness.app_mut().update();
+    }
+
+    let events = harness.app().world().resource::<Events<BurstFinished>>();
+    let finished = events.iter_current_update_events().next().unwrap();
+    assert_eq!(finished.camera, camera);
+    assert_eq!(
+        finished.paths,
+        ["burst_000.png", "burst_001.png", "burst_002.png"].map(|file| dir.join(file))
+    );
+    assert!(finished.paths.iter().all(|path| path.exists()));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn takes_screenshot_matrix() {
+    let mut harness =
+        HeadlessHarness::new_with_plugins(16, 8, ScreenshotMatrixPlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_screenshot_matrix");
+    let _ = fs::remove_dir_all(&dir);
+    harness.app_mut().world_mut().send_event(
+        TakeScreenshotMatrix::new(camera, &dir)
+            .with_variant("red", [(8, 8), (12, 4)], |world| {
+                world.insert_resource(ClearColor(Color::srgb(1.0, 0.0, 0.0)))
+            })
+            .with_variant("blue", [(8, 8), (12, 4)], |world| {
+                world.insert_resource(ClearColor(Color::srgb(0.0, 0.0, 1.0)))
+            })
+            .with_settle_frames(1),
+    );
+
+    let mut finished = None;
+    for _ in 0..40 {
+        harness.app_mut().update();
+        let events = harness
+            .app()
+            .world()
+            .resource::<Events<ScreenshotMatrixFinished>>();
+        if let Some(event) = events.iter_current_update_events().next() {
+            finished = Some(event.clone());
+            break;
+        }
+    }
+
+    let finished = finished.unwrap();
+    assert!(finished.failed.is_empty());
+    assert_eq!(
+        finished.paths,
+        [
+            "red_8x8.png",
+            "red_12x4.png",
+            "blue_8x8.png",
+            "blue_12x4.png"
+        ]
+        .map(|file| dir.join(file))
+    );
+    let shot = image::open(dir.join("blue_12x4.png")).unwrap().into_rgba8();
+    assert_eq!(shot.dimensions(), (12, 4));
+    assert_eq!(shot.get_pixel(0, 0).0, [0, 0, 255, 255]);
+    let shot = image::open(dir.join("red_8x8.png")).unwrap().into_rgba8();
+    assert_eq!(shot.get_pixel(7, 7).0, [255, 0, 0, 255]);
+    // The target is restored.
+    let world = harness.app().world();
+    let Camera {
+        target: bevy::render::camera::RenderTarget::Image(target),
+        ..
+    } = world.get::<Camera>(camera).unwrap()
+    else {
+        unreachable!();
+    };
+    let target = world.resource::<Assets<Image>>().get(target).unwrap();
+    assert_eq!(target.size(), UVec2::new(16, 8));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "golden")]
+#[test]
+fn compares_with_golden_images() {
+    use bevy_capture::golden::{self, DiffTolerance};
+
+    let mut harness =
+        HeadlessHarness::new_with_plugins(16, 8, ScreenshotMatrixPlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+    let dir = std::env::temp_dir().join("bevy_capture_test_golden");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("golden")).unwrap();
+
+    // The golden image is slightly brighter than the render, except for one pixel.
+    let mut golden = image::RgbaImage::from_pixel(16, 8, image::Rgba([130, 0, 0, 255]));
+    golden.put_pixel(3, 2, image::Rgba([0, 255, 0, 255]));
+    golden.save(dir.join("golden/red.png")).unwrap();
+
+    harness.app_mut().world_mut().send_event(
+        TakeScreenshotMatrix::new(camera, dir.join("shots"))
+            .with_shot("red", 16, 8, |world| {
+                world.insert_resource(ClearColor(Color::srgb_u8(128, 0, 0)))
+            })
+            .with_golden(
+                dir.join("golden"),
+                DiffTolerance::new(0.05).with_max_diff_ratio(0.005),
+            )
+            .with_settle_frames(1),
+    );
+    let mut finished = None;
+    for _ in 0..20 {
+        harness.app_mut().update();
+        let events = harness
+            .app()
+            .world()
+            .resource::<Events<ScreenshotMatrixFinished>>();
+        if let Some(event) = events.iter_current_update_events().next() {
+            finished = Some(event.clone());
+            break;
+        }
+    }
+
+    // One of 128 pixels differs, which exceeds the ratio of 0.5%.
+    let finished = finished.unwrap();
+    assert_eq!(finished.mismatched, ["red"]);
+    let diff = image::open(dir.join("shots/red.diff.png"))
+        .unwrap()
+        .into_rgba8();
+    assert_eq!(diff.get_pixel(3, 2).0, [255, 0, 0, 255]);
+    assert_ne!(diff.get_pixel(4, 2).0, [255, 0, 0, 255]);
+
+    let shot = Image::from_dynamic(
+        image::open(dir.join("shots/red.png")).unwrap(),
+        true,
+        default(),
+    );
+    let tolerance = DiffTolerance::new(0.05).with_max_diff_ratio(0.1);
+    let diff = golden::diff_golden(&shot, dir.join("golden/red.png"), &tolerance).unwrap();
+    assert_eq!(diff.diff_pixels, 1);
+    assert!(diff.is_match());
+    golden::assert_golden(&shot, dir.join("golden/red.png"), &tolerance);
+    assert!(
+        !golden::diff_golden(&shot, dir.join("golden/red.png"), &DiffTolerance::exact())
+            .unwrap()
+            .is_match()
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "hdr")]
+#[test]
+fn merges_exposure_bracket() {
+    let mut harness = HeadlessHarness::new_with_plugins(16, 8, BurstPlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_hdr_merge");
+    let _ = fs::remove_dir_all(&dir);
+    harness.app_mut().world_mut().send_event(
+        TakeBurst::exposure_bracket(camera, &dir, [-1.0, 0.0, 1.0])
+            .with_hdr_merge(dir.join("merged.hdr")),
+    );
+    for _ in 0..5 {
+        harness.app_mut().update();
+    }
+
+    let events = harness.app().world().resource::<Events<BurstFinished>>();
+    let finished = events.iter_current_update_events().next().unwrap();
+    assert_eq!(finished.paths.len(), 3);
+    assert_eq!(finished.merged, Some(dir.join("merged.hdr")));
+
+    let merged = image::open(dir.join("merged.hdr")).unwrap();
+    assert_eq!((merged.width(), merged.height()), (16, 8));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
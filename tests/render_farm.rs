@@ -0,0 +1,134 @@
+use bevy_capture::distributed::{RenderJob, RenderSlice, FRAMES_ENV, SLICE_ENV};
+use std::fs;
+
+#[test]
+fn splits_and_merges_distributed_render() {
+    let job = RenderJob::new(10..20, 3);
+    let slices = job.slices();
+    assert_eq!(
+        slices.iter().map(RenderSlice::frames).collect::<Vec<_>>(),
+        [10..13, 13..16, 16..20]
+    );
+    assert_eq!(RenderJob::new(0..2, 8).slices().len(), 2);
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_distributed");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    // Every process gets its slice in the environment.
+    #[cfg(unix)]
+    {
+        let dir = dir.clone();
+        job.run_local(2, |slice| {
+            let mut command = std::process::Command::new("sh");
+            command.arg("-c").arg(format!(
+                "echo \"${SLICE_ENV} ${FRAMES_ENV}\" > {}",
+                dir.join(format!("env_{}", slice.index())).display()
+            ));
+            command
+        })
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("env_2")).unwrap(),
+            "2/3 16..20\n"
+        );
+        assert!(job
+            .run_local(1, |_| std::process::Command::new("false"))
+            .is_err());
+    }
+
+    // Every slice wrote its frames starting at 0.
+    let frames = dir.join("frames");
+    for slice in &slices {
+        let slice_dir = slice.output_path(&frames);
+        fs::create_dir_all(&slice_dir).unwrap();
+        for index in 0..slice.frames().end - slice.frames().start {
+            let frame = slice.frames().start + index;
+            fs::write(
+                slice_dir.join(format!("frame_{index:06}.png")),
+                frame.to_string(),
+            )
+            .unwrap();
+        }
+    }
+    assert_eq!(slices[1].output_path(&frames), dir.join("frames.slice_001"));
+    job.merge_frames(&frames).unwrap();
+    for index in 0..10 {
+        let frame = fs::read_to_string(frames.join(format!("frame_{index:06}.png"))).unwrap();
+        assert_eq!(frame, (10 + index).to_string());
+    }
+    assert!(!slices[0].output_path(&frames).exists());
+
+    // Only the y4m header of the first slice is kept.
+    let stream = dir.join("capture.y4m");
+    for slice in &slices {
+        let content = format!("YUV4MPEG2 W4 H4\nFRAME {}\n", slice.index());
+        fs::write(slice.output_path(&stream), content).unwrap();
+    }
+    job.merge_streams(&stream).unwrap();
+    assert_eq!(
+        fs::read_to_string(&stream).unwrap(),
+        "YUV4MPEG2 W4 H4\nFRAME 0\nFRAME 1\nFRAME 2\n"
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "render_farm")]
+#[test]
+fn writes_render_farm_manifest() {
+    use bevy_capture::render_farm::{parse_frame_range, slice_from_args, JobManifest};
+
+    assert_eq!(parse_frame_range("10-19").unwrap(), 10..20);
+    assert_eq!(parse_frame_range("10:19").unwrap(), 10..20);
+    assert_eq!(parse_frame_range("10..20").unwrap(), 10..20);
+    assert_eq!(parse_frame_range("7").unwrap(), 7..8);
+    assert!(parse_frame_range("19-10").is_err());
+    assert!(parse_frame_range("a-b").is_err());
+
+    let manifest = JobManifest::new("intro", RenderJob::new(0..10, 3))
+        .with_program("./game")
+        .with_args(["--render"])
+        .with_output("captures/intro.mp4");
+    let json = manifest.to_json().unwrap();
+    assert_eq!(json["frames"]["last"], 9);
+    assert_eq!(json["outputs"][0], "captures/intro.mp4");
+    let task = &json["tasks"][2];
+    assert_eq!(task["frames"]["first"], 6);
+    assert_eq!(task["frames"]["last"], 9);
+    assert_eq!(task["outputs"][0], "captures/intro.slice_002.mp4");
+
+    // The app reads its slice from the command line of the task.
+    let command = task["command"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|arg| arg.as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        command,
+        [
+            "./game",
+            "--render",
+            "--frame-range",
+            "6-9",
+            "--slice",
+            "2/3"
+        ]
+    );
+    let slice = slice_from_args(command).unwrap().unwrap();
+    assert_eq!(slice, RenderJob::new(0..10, 3).slices()[2]);
+
+    let slice = slice_from_args(["game", "--frame-range=5-5"])
+        .unwrap()
+        .unwrap();
+    assert_eq!((slice.index(), slice.count(), slice.frames()), (0, 1, 5..6));
+    assert!(slice_from_args(["game"]).unwrap().is_none());
+    assert!(slice_from_args(["game", "--frame-range"]).is_err());
+    assert!(slice_from_args(["game", "--frame-range", "0-9", "--slice", "3/3"]).is_err());
+
+    let path = std::env::temp_dir().join("bevy_capture_test_manifest.json");
+    manifest.write(&path).unwrap();
+    assert!(fs::read_to_string(&path).unwrap().contains("\"tasks\""));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_capture::{
+    clip::{ClipPlugin, ClipRecorder, RecordClip},
+    encoder::test::TestEncoder,
+    testing::HeadlessHarness,
+    Capture,
+};
+use common::{harness, NO_ADAPTER};
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[test]
+fn records_clip() {
+    let mut harness = HeadlessHarness::new_with_plugins(16, 8, ClipPlugin).expect(NO_ADAPTER);
+    let camera = harness.camera();
+
+    let clips = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&clips);
+    harness.app_mut().world_mut().entity_mut(camera).insert(
+        ClipRecorder::new(move |name| {
+            let encoder = TestEncoder::new();
+            recorded
+                .lock()
+                .unwrap()
+                .push((name.to_string(), encoder.handle()));
+            encoder
+        })
+        .with_duration(std::time::Duration::ZERO),
+    );
+
+    harness
+        .app_mut()
+        .world_mut()
+        .send_event(RecordClip::new("highlight"));
+    for _ in 0..3 {
+        harness.app_mut().update();
+    }
+
+    let clips = clips.lock().unwrap();
+    assert_eq!(clips.len(), 1);
+    assert_eq!(clips[0].0, "highlight");
+    assert!(clips[0].1.is_finished());
+    assert_eq!(clips[0].1.encode_count(), 1);
+}
+
+#[test]
+fn keeps_last_frames_in_replay_buffer() {
+    use bevy_capture::encoder::replay::ReplayBufferEncoder;
+
+    let mut harness = harness(16, 8);
+
+    let encoder = ReplayBufferEncoder::new(2);
+    let buffer = encoder.buffer();
+    harness.capture(3, encoder);
+    assert_eq!(buffer.len(), 2);
+
+    let dir = std::env::temp_dir().join("bevy_capture_test_replay_buffer");
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(buffer.save_frames(&dir).unwrap(), 2);
+    assert!(dir.join("frame_000001.png").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn saves_replay_buffer_on_panic() {
+    use bevy_capture::{crash, encoder::replay::ReplayBufferEncoder};
+
+    let mut harness = harness(16, 8);
+
+    let encoder = ReplayBufferEncoder::new(2);
+    let buffer = encoder.buffer();
+    harness.capture(3, encoder);
+
+    // Installing the hook again replaces the directory instead of adding a second hook.
+    let replaced = std::env::temp_dir().join("bevy_capture_test_crash_replaced");
+    let dir = std::env::temp_dir().join("bevy_capture_test_crash");
+    let _ = fs::remove_dir_all(&replaced);
+    let _ = fs::remove_dir_all(&dir);
+    crash::install_panic_hook(buffer.clone(), &replaced);
+    crash::install_panic_hook(buffer.clone(), &dir);
+    thread::spawn(|| panic!("crash report test"))
+        .join()
+        .unwrap_err();
+
+    assert!(!replaced.exists());
+    let dumps = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(dumps.len(), 1);
+    assert!(dumps[0]
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .starts_with("crash_"));
+    assert_eq!(fs::read_dir(&dumps[0]).unwrap().count(), 2);
+    assert!(dumps[0].join("frame_000001.png").exists());
+
+    // Panics of other tests in this process only create empty dumps.
+    buffer.clear();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dumps_replay_buffer() {
+    use bevy_capture::encoder::replay::ReplayLength;
+
+    let mut harness = harness(16, 8);
+    let camera = harness.camera();
+    let world = harness.app_mut().world_mut();
+    world
+        .get_mut::<Capture>(camera)
+        .unwrap()
+        .start_replay(ReplayLength::Frames(2));
+    for red in [0.25, 0.5, 1.0] {
+        harness
+            .app_mut()
+            .insert_resource(ClearColor(Color::srgb(red, 0.0, 0.0)));
+        harness.app_mut().update();
+    }
+
+    // The last two frames are encoded, the capture keeps buffering.
+    let encoder = TestEncoder::new().with_images();
+    let encoded = encoder.handle();
+    let world = harness.app_mut().world_mut();
+    let capture = world.get::<Capture>(camera).unwrap();
+    let handle = capture.dump_replay(encoder).unwrap();
+    let started = std::time::Instant::now();
+    while !handle.is_finished() {
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        thread::yield_now();
+    }
+    assert!(encoded.is_finished());
+    let reds = encoded
+        .images()
+        .iter()
+        .map(|image| image.data[0])
+        .collect::<Vec<_>>();
+    assert_eq!(reds, [127, 255]);
+    assert!(capture.is_capturing());
+    assert_eq!(capture.replay_buffer().unwrap().len(), 2);
+
+    let mut capture = world.get_mut::<Capture>(camera).unwrap();
+    capture.stop();
+    assert!(capture.dump_replay(TestEncoder::new()).is_none());
+}